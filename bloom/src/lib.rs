@@ -0,0 +1,1479 @@
+//! Bloom Filter Implementation
+//!
+//! A Bloom filter is a space-efficient probabilistic data structure that tells you
+//! whether an element is POSSIBLY in a set, or DEFINITELY NOT in the set.
+//!
+//! Key properties:
+//! - False positives are possible (says "maybe yes" when actually "no")
+//! - False negatives are IMPOSSIBLE (never says "no" when actually "yes")
+//! - Space efficient: uses ~1.44 bits per element for 10% false positive rate
+//! - Fast: O(k) operations where k is number of hash functions
+//!
+//! For LSM trees, Bloom filters are perfect because:
+//! - We can skip reading entire SSTable files for keys that definitely don't exist
+//! - False positives only cost an extra file read (acceptable trade-off)
+//! - False negatives would cause data loss (but they never happen!)
+//!
+//! Example:
+//! ```ignore
+//! let mut bf = BloomFilter::new(1000, 0.01)?; // 1000 items, 1% false positive rate
+//! bf.insert(b"user:123");
+//! assert!(bf.might_contain(b"user:123"));   // true (definitely or possibly)
+//! assert!(!bf.might_contain(b"user:999"));  // false (definitely not)
+//! ```
+//!
+//! Hashes are seeded: every filter draws a random seed when it is built
+//! and stores it with its bits. Keys from untrusted users could otherwise
+//! be chosen to land on the same bits (FNV is easy to steer), filling a
+//! filter with a handful of positions and turning every lookup for another
+//! such key into a "maybe", i.e. a read of the table. Without the seed an
+//! attacker can't tell which keys collide. Seed 0 hashes exactly like
+//! filters from before seeds existed, which is what those load with.
+//!
+//! With the `siphash` feature new filters hash with keyed SipHash-2-4
+//! instead of seeded FNV: slower, but collisions can't be found even with
+//! many observations of the filter's behaviour. Every build reads both.
+//!
+//! The crate is `#![no_std]` and needs only `alloc` without its default
+//! `std` feature: the bit array, hashing and `to_bytes` / `from_bytes`
+//! work the same either way, and a filter serialized by one build reads
+//! back in the other. What needs `std` is what needs floating point math
+//! or an operating system: the sized constructors (`new`, `new_floored`),
+//! `DeferredBloomFilter`, `stats`, random seeds and the `io::Read` /
+//! `io::Write` adapters. Without `std`, filters come from `try_with_params`
+//! with seed 0 (unseeded) unless the caller passes its own to `with_seed`,
+//! and errors are `FilterError`s instead of `io::Error`s.
+#![no_std]
+
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, RandomState};
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+
+/// Fewest bits a filter has
+pub const MIN_BITS: usize = 8;
+
+/// Most hash functions a filter uses
+pub const MAX_HASHES: usize = 16;
+
+/// Default `FilterFloor::min_bits_per_key`
+pub const DEFAULT_MIN_BITS_PER_KEY: usize = 10;
+
+/// Default `FilterFloor::min_bytes`
+pub const DEFAULT_MIN_FILTER_BYTES: usize = 64;
+
+/// Smallest size a new filter gets, whatever its target rate works out to
+///
+/// The sizing formula only holds for large filters. With a few dozen keys,
+/// rounding the bit and hash counts up and the probes of different keys
+/// crowding a small array push the real false positive rate past the
+/// target: about 1.15 times it for 20 keys at 1%, five times it for a
+/// single key. Filters that small cost next to nothing to enlarge, so
+/// tables flushed from a small memtable get at least this much.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterFloor {
+    /// Fewest bits per expected key
+    pub min_bits_per_key: usize,
+
+    /// Fewest bytes of bits, however few keys there are
+    pub min_bytes: usize,
+}
+
+impl FilterFloor {
+    /// No floor: filters are sized by the formula alone
+    pub const NONE: FilterFloor = FilterFloor {
+        min_bits_per_key: 0,
+        min_bytes: 0,
+    };
+}
+
+impl Default for FilterFloor {
+    fn default() -> Self {
+        Self {
+            min_bits_per_key: DEFAULT_MIN_BITS_PER_KEY,
+            min_bytes: DEFAULT_MIN_FILTER_BYTES,
+        }
+    }
+}
+
+/// Hash function a filter derives its bit positions from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterHasher {
+    /// Two FNV-1a hashes, mixed with the seed (unless it is 0)
+    Fnv,
+
+    /// SipHash-2-4 keyed with the seed
+    SipHash,
+}
+
+impl FilterHasher {
+    /// Hasher of new filters: SipHash with the `siphash` feature
+    pub const DEFAULT: FilterHasher = if cfg!(feature = "siphash") {
+        FilterHasher::SipHash
+    } else {
+        FilterHasher::Fnv
+    };
+
+    fn code(self) -> u32 {
+        match self {
+            FilterHasher::Fnv => 0,
+            FilterHasher::SipHash => 1,
+        }
+    }
+
+    fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(FilterHasher::Fnv),
+            1 => Some(FilterHasher::SipHash),
+            _ => None,
+        }
+    }
+}
+
+/// Why a filter couldn't be built or read back
+///
+/// The first three come from parameters a caller chose; with `std` they
+/// become `InvalidInput` `io::Error`s, the others `InvalidData` ones, with
+/// the same message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterError {
+    /// A false positive rate that isn't a probability
+    InvalidRate(f64),
+
+    /// Fewer bits than `MIN_BITS`
+    TooFewBits(usize),
+
+    /// A number of hash functions outside 1..=`MAX_HASHES`
+    InvalidHashCount(usize),
+
+    /// A serialized filter holding more items than bits
+    TooManyItems { items: usize, bits: usize },
+
+    /// Serialized bytes that end before the header or bit array does
+    Truncated { len: usize, needed: usize },
+
+    /// Bytes after the bit array that aren't one of the trailers
+    BadTrailer(usize),
+
+    /// A hash function code this build doesn't know
+    UnknownHasher(u32),
+}
+
+impl FilterError {
+    /// Whether the error comes from a caller's parameters rather than
+    /// serialized bytes
+    pub fn is_invalid_input(&self) -> bool {
+        matches!(
+            self,
+            FilterError::InvalidRate(_)
+                | FilterError::TooFewBits(_)
+                | FilterError::InvalidHashCount(_)
+        )
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::InvalidRate(rate) => {
+                write!(f, "false positive rate {} is not between 0 and 1", rate)
+            }
+            FilterError::TooFewBits(bits) => {
+                write!(f, "{} bits is fewer than {}", bits, MIN_BITS)
+            }
+            FilterError::InvalidHashCount(hashes) => {
+                write!(f, "{} hash functions is outside 1..={}", hashes, MAX_HASHES)
+            }
+            FilterError::TooManyItems { items, bits } => {
+                write!(f, "{} items in only {} bits", items, bits)
+            }
+            FilterError::Truncated { len, needed } => {
+                write!(f, "{} bytes where at least {} are needed", len, needed)
+            }
+            FilterError::BadTrailer(len) => write!(f, "{} bytes after the bit array", len),
+            FilterError::UnknownHasher(code) => write!(f, "unknown hash function {}", code),
+        }
+    }
+}
+
+impl core::error::Error for FilterError {}
+
+#[cfg(feature = "std")]
+impl From<FilterError> for std::io::Error {
+    fn from(error: FilterError) -> Self {
+        let kind = if error.is_invalid_input() {
+            std::io::ErrorKind::InvalidInput
+        } else {
+            std::io::ErrorKind::InvalidData
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+/// A Bloom filter for efficient set membership testing
+///
+/// Uses multiple hash functions to map keys to positions in a bit array.
+/// When inserting, all positions are set to 1.
+/// When querying, if ALL positions are 1, the key MIGHT exist.
+/// If ANY position is 0, the key DEFINITELY doesn't exist.
+#[derive(Clone)]
+pub struct BloomFilter {
+    /// Bit array stored as bytes (8 bits per byte)
+    /// We use a `Vec<u8>` instead of a proper bit vector for simplicity
+    bits: Vec<u8>,
+
+    /// Number of bits in the filter (bits.len() * 8)
+    num_bits: usize,
+
+    /// Number of hash functions to use
+    /// More hashes = lower false positive rate, but slower operations
+    num_hashes: usize,
+
+    /// Number of items inserted (for statistics)
+    ///
+    /// `insert` can't tell a repeated key from a new one, so owners that know
+    /// the exact number of distinct keys (e.g. a flush) set it explicitly
+    /// with `set_num_items`.
+    num_items: usize,
+
+    /// Number of this filter's keys that also exist in older SSTables
+    ///
+    /// The filter itself never computes this; the LSM tree records it when
+    /// the SSTable is written so that statistics can avoid counting keys
+    /// that were overwritten in newer tables more than once.
+    overlapping_items: usize,
+
+    /// Mixed into every hash; 0 for filters written before seeds existed
+    seed: u64,
+
+    hasher: FilterHasher,
+}
+
+impl BloomFilter {
+    /// Creates a new Bloom filter optimized for the expected number of items
+    /// and desired false positive probability.
+    ///
+    /// # Arguments
+    /// * `expected_items` - How many items you expect to insert
+    /// * `false_positive_rate` - Desired probability of false positives (e.g., 0.01 for 1%)
+    ///
+    /// # Optimal Parameters
+    /// The optimal number of bits (m) and hash functions (k) are:
+    /// - m = -n * ln(p) / (ln(2)^2)  where n=items, p=false_positive_rate
+    /// - k = (m/n) * ln(2)
+    ///
+    /// Rates are kept between 0.0001 and 0.5; a rate that isn't a
+    /// probability at all (NaN, 0 or less, 1 or more) is rejected with
+    /// `InvalidInput`.
+    ///
+    /// # Example
+    /// ```ignore
+    /// // For 1000 items with 1% false positive rate
+    /// let bf = BloomFilter::new(1000, 0.01)?;
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> std::io::Result<Self> {
+        Self::new_floored(expected_items, false_positive_rate, FilterFloor::NONE)
+    }
+
+    /// Creates a Bloom filter like `new`, but with at least the bits
+    /// `floor` asks for; the hash functions are chosen for the final size
+    #[cfg(feature = "std")]
+    pub fn new_floored(
+        expected_items: usize,
+        false_positive_rate: f64,
+        floor: FilterFloor,
+    ) -> std::io::Result<Self> {
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err(FilterError::InvalidRate(false_positive_rate).into());
+        }
+
+        // Ensure reasonable parameters
+        let expected_items = expected_items.max(1);
+        let false_positive_rate = false_positive_rate.clamp(0.0001, 0.5);
+
+        // Calculate optimal number of bits using formula:
+        // m = -n * ln(p) / (ln(2)^2)
+        let ln2_squared = std::f64::consts::LN_2 * std::f64::consts::LN_2;
+        let num_bits_f64 = -(expected_items as f64) * false_positive_rate.ln() / ln2_squared;
+        let num_bits = (num_bits_f64.ceil() as usize)
+            .max(MIN_BITS)
+            .max(expected_items.saturating_mul(floor.min_bits_per_key))
+            .max(floor.min_bytes.saturating_mul(8));
+
+        // Calculate optimal number of hash functions:
+        // k = (m/n) * ln(2)
+        let num_hashes_f64 = (num_bits as f64 / expected_items as f64) * std::f64::consts::LN_2;
+        let num_hashes = (num_hashes_f64.ceil() as usize).clamp(1, MAX_HASHES);
+
+        Ok(Self::empty(num_bits, num_hashes))
+    }
+
+    /// Creates a Bloom filter with explicit parameters
+    ///
+    /// Use this when you need precise control over the filter size
+    /// (e.g., when deserializing from disk).
+    ///
+    /// # Arguments
+    /// * `num_bits` - Total number of bits in the filter (at least `MIN_BITS`)
+    /// * `num_hashes` - Number of hash functions to use (1 to `MAX_HASHES`)
+    ///
+    /// Values outside those ranges are rejected with `InvalidInput`.
+    #[cfg(feature = "std")]
+    pub fn with_params(num_bits: usize, num_hashes: usize) -> std::io::Result<Self> {
+        Ok(Self::try_with_params(num_bits, num_hashes)?)
+    }
+
+    /// `with_params` for builds without `std`: the same checks, reported as
+    /// a `FilterError`
+    ///
+    /// Without `std` there is nothing to draw a random seed from, so the
+    /// filter is unseeded (seed 0); pass one to `with_seed` to seed it.
+    pub fn try_with_params(num_bits: usize, num_hashes: usize) -> Result<Self, FilterError> {
+        check_params(num_bits, num_hashes)?;
+        Ok(Self::empty(num_bits, num_hashes))
+    }
+
+    /// An empty filter of the given shape, with a fresh seed
+    fn empty(num_bits: usize, num_hashes: usize) -> Self {
+        // Allocate bit array (round up to nearest byte)
+        let num_bytes = num_bits.div_ceil(8);
+        Self {
+            bits: vec![0u8; num_bytes],
+            num_bits,
+            num_hashes,
+            num_items: 0,
+            overlapping_items: 0,
+            seed: random_seed(),
+            hasher: FilterHasher::DEFAULT,
+        }
+    }
+
+    /// Replaces the random seed of an empty filter
+    ///
+    /// For reproducible filters in tests and tools; an FNV filter with
+    /// seed 0 hashes like filters from before seeds existed. Keys inserted before the change
+    /// would no longer be found, so only call it on a new filter.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Replaces the hash function of an empty filter (see `with_seed`)
+    pub fn with_hasher(mut self, hasher: FilterHasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Returns the seed mixed into the hashes
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the hash function of the filter
+    pub fn hasher(&self) -> FilterHasher {
+        self.hasher
+    }
+
+    /// Inserts a key into the Bloom filter
+    ///
+    /// This sets k bits in the bit array, where k is the number of hash functions.
+    /// After insertion, `might_contain(key)` will always return true.
+    ///
+    /// # Arguments
+    /// * `key` - The key to insert (as bytes)
+    ///
+    /// # Time Complexity
+    /// O(k) where k is the number of hash functions
+    pub fn insert(&mut self, key: &[u8]) {
+        self.insert_hashed(self.base_hashes(key));
+    }
+
+    /// Inserts a key given its base hashes (see `DeferredBloomFilter`)
+    fn insert_hashed(&mut self, base: (usize, usize)) {
+        // Generate k hash values and set corresponding bits
+        for i in 0..self.num_hashes {
+            let bit_index = self.hash(base, i);
+            self.set_bit(bit_index);
+        }
+        self.num_items += 1;
+    }
+
+    /// Checks if a key might be in the set
+    ///
+    /// Returns:
+    /// - `true` if the key MIGHT be in the set (could be a false positive)
+    /// - `false` if the key is DEFINITELY NOT in the set (never wrong)
+    ///
+    /// # Arguments
+    /// * `key` - The key to check (as bytes)
+    ///
+    /// # Time Complexity
+    /// O(k) where k is the number of hash functions
+    ///
+    /// # Example
+    /// ```ignore
+    /// if !bf.might_contain(b"user:123") {
+    ///     // Key definitely not in this SSTable - skip reading file!
+    ///     return None;
+    /// }
+    /// // Key might be here, need to actually read the file
+    /// ```
+    pub fn might_contain(&self, key: &[u8]) -> bool {
+        // Check all k hash positions - ALL must be set
+        let base = self.base_hashes(key);
+        for i in 0..self.num_hashes {
+            let bit_index = self.hash(base, i);
+            if !self.get_bit(bit_index) {
+                return false; // Definitely not in set
+            }
+        }
+        true // Possibly in set (might be false positive)
+    }
+
+    /// Computes the i-th hash value for a key
+    ///
+    /// Uses double hashing: h(key, i) = (h1(key) + i * h2(key)) mod m
+    /// This technique generates k hash values from just 2 base hashes,
+    /// which is faster than computing k independent hashes.
+    ///
+    /// h1 and h2 come from `base_hashes`.
+    fn hash(&self, (h1, h2): (usize, usize), index: usize) -> usize {
+        // Combine hashes with index to get the i-th hash value
+        let combined = h1.wrapping_add(index.wrapping_mul(h2));
+
+        // Map to bit array position
+        combined % self.num_bits
+    }
+
+    /// The two base hashes of double hashing, h1 and h2
+    ///
+    /// Seed 0 uses FNV-1a and a variant with another offset basis as they
+    /// are, like filters always did. Otherwise the seed goes into both
+    /// offset bases and the results are run through a finalizer, so every
+    /// bit of the hash depends on the seed (FNV's low bits otherwise only
+    /// depend on the low bits of its state). SipHash derives h2 from h1,
+    /// which is fine for a hash whose collisions can't be predicted.
+    fn base_hashes(&self, key: &[u8]) -> (usize, usize) {
+        base_hashes(self.hasher, self.seed, key)
+    }
+
+    /// Sets a bit at the given index
+    fn set_bit(&mut self, index: usize) {
+        let byte_index = index / 8;
+        let bit_offset = index % 8;
+        if byte_index < self.bits.len() {
+            self.bits[byte_index] |= 1 << bit_offset;
+        }
+    }
+
+    /// Gets a bit at the given index
+    fn get_bit(&self, index: usize) -> bool {
+        let byte_index = index / 8;
+        let bit_offset = index % 8;
+        if byte_index < self.bits.len() {
+            (self.bits[byte_index] & (1 << bit_offset)) != 0
+        } else {
+            false
+        }
+    }
+
+    /// Returns the number of items inserted
+    ///
+    /// This counts every `insert` call, so inserting the same key twice
+    /// counts it twice unless the count was corrected with `set_num_items`.
+    pub fn len(&self) -> usize {
+        self.num_items
+    }
+
+    /// Sets the exact number of distinct items in the filter
+    ///
+    /// Use this when the caller knows the true count (a flush knows the
+    /// memtable length, a merge knows how many keys survived).
+    pub fn set_num_items(&mut self, num_items: usize) {
+        self.num_items = num_items;
+    }
+
+    /// Returns how many items also exist in older SSTables
+    pub fn overlapping_items(&self) -> usize {
+        self.overlapping_items
+    }
+
+    /// Records how many items also exist in older SSTables
+    pub fn set_overlapping_items(&mut self, overlapping_items: usize) {
+        self.overlapping_items = overlapping_items;
+    }
+
+    /// Returns true if no items have been inserted
+    pub fn is_empty(&self) -> bool {
+        self.num_items == 0
+    }
+
+    /// Returns the size of the filter in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// Returns the number of bits in the filter
+    pub fn num_bits(&self) -> usize {
+        self.num_bits
+    }
+
+    /// Returns the number of hash functions used
+    pub fn num_hashes(&self) -> usize {
+        self.num_hashes
+    }
+
+    /// Estimates the current false positive probability
+    ///
+    /// Formula: (1 - e^(-kn/m))^k
+    /// where k=num_hashes, n=num_items, m=num_bits
+    #[cfg(feature = "std")]
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        if self.num_items == 0 {
+            return 0.0;
+        }
+
+        let k = self.num_hashes as f64;
+        let n = self.num_items as f64;
+        let m = self.num_bits as f64;
+
+        // Probability that a bit is still 0 after n insertions
+        let prob_bit_zero = (-k * n / m).exp();
+
+        // Probability of false positive
+        (1.0 - prob_bit_zero).powf(k)
+    }
+
+    /// Serializes the Bloom filter to bytes
+    ///
+    /// Format:
+    /// [num_bits: u32][num_hashes: u32][num_items: u32][bits: bytes]
+    /// [overlapping_items: u32][seed: u64][hasher: u32]
+    ///
+    /// This allows storing the Bloom filter alongside SSTable data. The
+    /// trailer fields are optional when reading, so files written before
+    /// they existed still load: without the trailer the overlap count is 0,
+    /// and without the seed the filter is an unseeded FNV one.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12 + self.bits.len() + TRAILER_LEN);
+
+        // Write header
+        bytes.extend_from_slice(&(self.num_bits as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.num_hashes as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.num_items as u32).to_le_bytes());
+
+        // Write bit array
+        bytes.extend_from_slice(&self.bits);
+
+        // Write trailer
+        bytes.extend_from_slice(&(self.overlapping_items as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.hasher.code().to_le_bytes());
+
+        bytes
+    }
+
+    /// Deserializes a Bloom filter from bytes
+    ///
+    /// Returns None if the data is invalid or corrupted: a header outside
+    /// what `with_params` accepts, more items than bits, a payload that
+    /// isn't exactly the bit array plus one of the trailers, or an unknown
+    /// hash function.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        Self::try_from_bytes(data).ok()
+    }
+
+    /// `from_bytes` with the reason the bytes were rejected
+    pub fn try_from_bytes(data: &[u8]) -> Result<Self, FilterError> {
+        if data.len() < 12 {
+            return Err(FilterError::Truncated {
+                len: data.len(),
+                needed: 12,
+            });
+        }
+
+        // Read header
+        let num_bits = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let num_hashes = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        let num_items = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize;
+        check_header(num_bits, num_hashes, num_items)?;
+
+        // The bit array, then one of the trailers
+        let expected_bytes = num_bits.div_ceil(8);
+        let (bits, trailer) =
+            data[12..]
+                .split_at_checked(expected_bytes)
+                .ok_or(FilterError::Truncated {
+                    len: data.len(),
+                    needed: 12 + expected_bytes,
+                })?;
+        let (overlapping_items, seed, hasher) = parse_trailer(trailer)?;
+
+        Ok(Self {
+            bits: bits.to_vec(),
+            num_bits,
+            num_hashes,
+            num_items,
+            overlapping_items,
+            seed,
+            hasher,
+        })
+    }
+
+    /// Writes the Bloom filter to a writer (file)
+    #[cfg(feature = "std")]
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let bytes = self.to_bytes();
+        writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Reads a Bloom filter from a reader (file)
+    #[cfg(feature = "std")]
+    pub fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        // Read header first
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+
+        let num_bits = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let num_hashes = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let num_items = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        check_header(num_bits, num_hashes, num_items).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                std::format!("invalid Bloom filter header: {}", e),
+            )
+        })?;
+
+        // Read bit array. A damaged header can claim far more bits than the
+        // file holds, so grow the buffer with the bytes actually read rather
+        // than allocating the claimed size up front.
+        let num_bytes = num_bits.div_ceil(8);
+        let mut bits = Vec::new();
+        reader.take(num_bytes as u64).read_to_end(&mut bits)?;
+        if bits.len() < num_bytes {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                std::format!(
+                    "Bloom filter claims {} bits but the file ends after {} bytes",
+                    num_bits,
+                    bits.len()
+                ),
+            ));
+        }
+
+        // Read the trailer (shorter or absent in files from older versions)
+        let mut trailer = Vec::new();
+        reader
+            .take(TRAILER_LEN as u64 + 1)
+            .read_to_end(&mut trailer)?;
+        let (overlapping_items, seed, hasher) = parse_trailer(&trailer).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                std::format!("invalid Bloom filter trailer: {}", e),
+            )
+        })?;
+
+        Ok(Self {
+            bits,
+            num_bits,
+            num_hashes,
+            num_items,
+            overlapping_items,
+            seed,
+            hasher,
+        })
+    }
+
+    /// Returns statistics about the Bloom filter
+    #[cfg(feature = "std")]
+    pub fn stats(&self) -> BloomFilterStats {
+        let bits_set = self.bits.iter().map(|b| b.count_ones() as usize).sum();
+        let fill_ratio = bits_set as f64 / self.num_bits as f64;
+
+        BloomFilterStats {
+            num_bits: self.num_bits,
+            num_hashes: self.num_hashes,
+            num_items: self.num_items,
+            overlapping_items: self.overlapping_items,
+            size_bytes: self.bits.len(),
+            bits_per_key: match self.num_items {
+                0 => 0.0,
+                n => self.num_bits as f64 / n as f64,
+            },
+            bits_set,
+            fill_ratio,
+            estimated_fpp: self.estimated_false_positive_rate(),
+        }
+    }
+}
+
+/// A Bloom filter built once its keys are all known
+///
+/// A filter has to be sized before the first key goes in, but a writer
+/// streaming merged input only learns how many distinct keys survived at
+/// the end. This keeps the base hashes of each key (16 bytes a key, the
+/// seed being drawn up front) and builds the filter in `build`, sized for
+/// exactly the keys added, so the false positive rate comes out at the
+/// target however many duplicates the input had.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct DeferredBloomFilter {
+    false_positive_rate: f64,
+    floor: FilterFloor,
+    seed: u64,
+    hasher: FilterHasher,
+    hashes: Vec<(usize, usize)>,
+}
+
+#[cfg(feature = "std")]
+impl DeferredBloomFilter {
+    /// Starts a filter for `false_positive_rate`, checked like
+    /// `BloomFilter::new` does
+    pub fn new(false_positive_rate: f64) -> std::io::Result<Self> {
+        BloomFilter::new(1, false_positive_rate)?;
+        Ok(Self {
+            false_positive_rate,
+            floor: FilterFloor::NONE,
+            seed: random_seed(),
+            hasher: FilterHasher::DEFAULT,
+            hashes: Vec::new(),
+        })
+    }
+
+    /// Gives the built filter at least the bits `floor` asks for
+    pub fn with_floor(mut self, floor: FilterFloor) -> Self {
+        self.floor = floor;
+        self
+    }
+
+    /// Adds a key; the caller adds each distinct key once
+    pub fn insert(&mut self, key: &[u8]) {
+        self.hashes.push(base_hashes(self.hasher, self.seed, key));
+    }
+
+    /// Number of keys added
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Builds the filter, sized for the keys added
+    pub fn build(self) -> BloomFilter {
+        let mut filter =
+            BloomFilter::new_floored(self.hashes.len(), self.false_positive_rate, self.floor)
+                .expect("the rate was checked in DeferredBloomFilter::new")
+                .with_seed(self.seed)
+                .with_hasher(self.hasher);
+        for base in self.hashes {
+            filter.insert_hashed(base);
+        }
+        filter
+    }
+}
+
+/// Statistics about a Bloom filter
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilterStats {
+    pub num_bits: usize,
+    pub num_hashes: usize,
+    pub num_items: usize,
+    pub overlapping_items: usize,
+    pub size_bytes: usize,
+
+    /// Bits per key the filter holds (0 when empty); above what the
+    /// target rate needs for filters raised to a `FilterFloor`
+    pub bits_per_key: f64,
+    pub bits_set: usize,
+    pub fill_ratio: f64,
+    pub estimated_fpp: f64,
+}
+
+impl fmt::Display for BloomFilterStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "BloomFilter {{ bits: {}, hashes: {}, items: {}, size: {} bytes, bits/key: {:.1}, fill: {:.1}%, fpp: {:.4}% }}",
+            self.num_bits,
+            self.num_hashes,
+            self.num_items,
+            self.size_bytes,
+            self.bits_per_key,
+            self.fill_ratio * 100.0,
+            self.estimated_fpp * 100.0
+        )
+    }
+}
+
+/// Size of the full trailer: overlap count, seed and hasher
+const TRAILER_LEN: usize = 16;
+
+/// Reads a trailer: empty (oldest files), the overlap count alone, or the
+/// full trailer
+fn parse_trailer(trailer: &[u8]) -> Result<(usize, u64, FilterHasher), FilterError> {
+    let u32_at = |at: usize| u32::from_le_bytes(trailer[at..at + 4].try_into().unwrap());
+    match trailer.len() {
+        0 => Ok((0, 0, FilterHasher::Fnv)),
+        4 => Ok((u32_at(0) as usize, 0, FilterHasher::Fnv)),
+        TRAILER_LEN => {
+            let seed = u64::from_le_bytes(trailer[4..12].try_into().unwrap());
+            let hasher = FilterHasher::from_code(u32_at(12))
+                .ok_or(FilterError::UnknownHasher(u32_at(12)))?;
+            Ok((u32_at(0) as usize, seed, hasher))
+        }
+        len => Err(FilterError::BadTrailer(len)),
+    }
+}
+
+/// FNV-1a parameters for 64-bit
+pub const FNV_OFFSET_BASIS: u64 = 14695981039346656037;
+const FNV_PRIME: u64 = 1099511628211;
+
+/// Different offset basis of the secondary hash, for independence
+const FNV_OFFSET_BASIS_ALT: u64 = 12345678901234567890;
+
+/// FNV-1a hash function
+///
+/// FNV-1a is a fast, non-cryptographic hash function with good distribution.
+/// It's ideal for Bloom filters because:
+/// - Fast to compute
+/// - Good avalanche effect (small input changes -> large output changes)
+/// - Works well with arbitrary byte sequences
+pub fn fnv1a(offset_basis: u64, key: &[u8]) -> u64 {
+    let mut hash = offset_basis;
+    for byte in key {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Finalizer of SplitMix64: every output bit depends on every input bit
+pub fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// SipHash-2-4 of `data` under the key (k0, k1)
+fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes(chunk.try_into().unwrap());
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    }
+    // The last block holds the remaining bytes and the length
+    let mut last = [0u8; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    let m = u64::from_le_bytes(last) | ((data.len() as u64) << 56);
+    v[3] ^= m;
+    round(&mut v);
+    round(&mut v);
+    v[0] ^= m;
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// The two base hashes of double hashing for `key` (see
+/// `BloomFilter::base_hashes`)
+fn base_hashes(hasher: FilterHasher, seed: u64, key: &[u8]) -> (usize, usize) {
+    let (h1, h2) = match hasher {
+        FilterHasher::Fnv if seed == 0 => (
+            fnv1a(FNV_OFFSET_BASIS, key),
+            fnv1a(FNV_OFFSET_BASIS_ALT, key),
+        ),
+        FilterHasher::Fnv => (
+            mix64(fnv1a(FNV_OFFSET_BASIS ^ seed, key)),
+            mix64(fnv1a(FNV_OFFSET_BASIS_ALT ^ seed, key)),
+        ),
+        FilterHasher::SipHash => {
+            let h1 = siphash24(seed, mix64(seed), key);
+            (h1, mix64(h1))
+        }
+    };
+    // Ensure h2 is never 0 (would make all hashes the same)
+    (h1 as usize, (h2 as usize) | 1)
+}
+
+/// A fresh, never zero seed from the process's random hasher keys
+#[cfg(feature = "std")]
+fn random_seed() -> u64 {
+    RandomState::new().hash_one(0u64).max(1)
+}
+
+/// Seed 0 without `std`: nothing to draw a random one from
+#[cfg(not(feature = "std"))]
+fn random_seed() -> u64 {
+    0
+}
+
+/// Checks the shape of a filter: enough bits and a sane number of hashes
+fn check_params(num_bits: usize, num_hashes: usize) -> Result<(), FilterError> {
+    if num_bits < MIN_BITS {
+        return Err(FilterError::TooFewBits(num_bits));
+    }
+    if !(1..=MAX_HASHES).contains(&num_hashes) {
+        return Err(FilterError::InvalidHashCount(num_hashes));
+    }
+    Ok(())
+}
+
+/// Checks a serialized header
+///
+/// On top of `check_params`, a filter can't hold more items than it has
+/// bits: every lookup would come back "maybe", so such a count only comes
+/// from a damaged header.
+fn check_header(num_bits: usize, num_hashes: usize, num_items: usize) -> Result<(), FilterError> {
+    check_params(num_bits, num_hashes)?;
+    if num_items > num_bits {
+        return Err(FilterError::TooManyItems {
+            items: num_items,
+            bits: num_bits,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::{format, vec};
+
+    #[test]
+    fn test_basic_insert_and_query() {
+        let mut bf = BloomFilter::new(100, 0.01).unwrap();
+
+        // Insert some keys
+        bf.insert(b"hello");
+        bf.insert(b"world");
+        bf.insert(b"rust");
+
+        // Should definitely find inserted keys
+        assert!(bf.might_contain(b"hello"), "Should find 'hello'");
+        assert!(bf.might_contain(b"world"), "Should find 'world'");
+        assert!(bf.might_contain(b"rust"), "Should find 'rust'");
+
+        // Should probably not find non-inserted keys
+        // (could be false positive, but unlikely with 1% rate)
+        // We don't assert on this because false positives are valid
+    }
+
+    #[test]
+    fn test_no_false_negatives() {
+        let mut bf = BloomFilter::new(1000, 0.01).unwrap();
+
+        // Insert many keys
+        let keys: Vec<String> = (0..1000).map(|i| format!("key_{}", i)).collect();
+        for key in &keys {
+            bf.insert(key.as_bytes());
+        }
+
+        // MUST find all inserted keys (no false negatives ever)
+        for key in &keys {
+            assert!(
+                bf.might_contain(key.as_bytes()),
+                "Must find inserted key: {}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        let mut bf = BloomFilter::new(1000, 0.01).unwrap();
+
+        // Insert 1000 keys
+        for i in 0..1000 {
+            let key = format!("inserted_{}", i);
+            bf.insert(key.as_bytes());
+        }
+
+        // Test 10000 non-inserted keys and count false positives
+        let mut false_positives = 0;
+        for i in 0..10000 {
+            let key = format!("not_inserted_{}", i);
+            if bf.might_contain(key.as_bytes()) {
+                false_positives += 1;
+            }
+        }
+
+        // False positive rate should be roughly around 1%
+        // Allow for some variance (0.1% to 5%)
+        let fpp = false_positives as f64 / 10000.0;
+        assert!(
+            fpp < 0.05,
+            "False positive rate {} is too high (expected < 5%)",
+            fpp
+        );
+    }
+
+    #[test]
+    fn test_empty_filter() {
+        let bf = BloomFilter::new(100, 0.01).unwrap();
+
+        assert!(bf.is_empty());
+        assert_eq!(bf.len(), 0);
+
+        // Empty filter should report nothing contained
+        assert!(!bf.might_contain(b"any_key"));
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut bf = BloomFilter::new(100, 0.01).unwrap();
+        bf.insert(b"key1");
+        bf.insert(b"key2");
+        bf.insert(b"key3");
+
+        // Serialize
+        let bytes = bf.to_bytes();
+
+        // Deserialize
+        let bf2 = BloomFilter::from_bytes(&bytes).expect("Should deserialize");
+
+        // Should find same keys
+        assert!(bf2.might_contain(b"key1"));
+        assert!(bf2.might_contain(b"key2"));
+        assert!(bf2.might_contain(b"key3"));
+
+        // Should have same stats
+        assert_eq!(bf.num_bits(), bf2.num_bits());
+        assert_eq!(bf.num_hashes(), bf2.num_hashes());
+        assert_eq!(bf.len(), bf2.len());
+    }
+
+    #[test]
+    fn test_with_params() {
+        let bf = BloomFilter::with_params(1024, 7).unwrap();
+
+        assert_eq!(bf.num_bits(), 1024);
+        assert_eq!(bf.num_hashes(), 7);
+        assert!(bf.is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut bf = BloomFilter::new(100, 0.01).unwrap();
+        bf.insert(b"test");
+
+        let stats = bf.stats();
+        assert_eq!(stats.num_items, 1);
+        assert!(stats.fill_ratio > 0.0);
+        assert!(stats.estimated_fpp >= 0.0);
+    }
+
+    #[test]
+    fn test_large_keys() {
+        let mut bf = BloomFilter::new(100, 0.01).unwrap();
+
+        // Test with large keys
+        let large_key = vec![0u8; 10000];
+        bf.insert(&large_key);
+        assert!(bf.might_contain(&large_key));
+
+        // Different large key should probably not match
+        let _different_key = vec![1u8; 10000];
+        // Note: might_contain could return true (false positive) - that's valid
+    }
+
+    #[test]
+    fn test_binary_keys() {
+        let mut bf = BloomFilter::new(100, 0.01).unwrap();
+
+        // Test with binary data (including null bytes)
+        let binary_key = vec![0, 1, 2, 0, 255, 128, 64, 0];
+        bf.insert(&binary_key);
+        assert!(bf.might_contain(&binary_key));
+    }
+
+    #[test]
+    fn test_clone() {
+        let mut bf1 = BloomFilter::new(100, 0.01).unwrap();
+        bf1.insert(b"key1");
+        bf1.insert(b"key2");
+
+        let bf2 = bf1.clone();
+
+        // Both should contain the same keys
+        assert!(bf1.might_contain(b"key1"));
+        assert!(bf2.might_contain(b"key1"));
+        assert!(bf1.might_contain(b"key2"));
+        assert!(bf2.might_contain(b"key2"));
+
+        // They should be independent
+        assert_eq!(bf1.len(), bf2.len());
+    }
+
+    #[test]
+    fn test_edge_case_small_filter() {
+        // Test with minimum size
+        let mut bf = BloomFilter::new(1, 0.5).unwrap();
+        bf.insert(b"key");
+        assert!(bf.might_contain(b"key"));
+    }
+
+    #[test]
+    fn test_many_insertions() {
+        let mut bf = BloomFilter::new(10000, 0.01).unwrap();
+
+        // Insert 10000 keys
+        for i in 0..10000 {
+            let key = format!("batch_key_{}", i);
+            bf.insert(key.as_bytes());
+        }
+
+        // All should be found
+        for i in 0..10000 {
+            let key = format!("batch_key_{}", i);
+            assert!(bf.might_contain(key.as_bytes()));
+        }
+
+        assert_eq!(bf.len(), 10000);
+    }
+
+    #[test]
+    fn test_set_num_items_overrides_insert_count() {
+        let mut bf = BloomFilter::new(100, 0.01).unwrap();
+        bf.insert(b"same");
+        bf.insert(b"same");
+        assert_eq!(bf.len(), 2);
+
+        bf.set_num_items(1);
+        assert_eq!(bf.len(), 1);
+        assert_eq!(bf.stats().num_items, 1);
+    }
+
+    #[test]
+    fn test_overlapping_items_round_trip() {
+        // Unseeded, so the filter still answers once the seed is cut off
+        let mut bf = BloomFilter::new(100, 0.01)
+            .unwrap()
+            .with_hasher(FilterHasher::Fnv)
+            .with_seed(0);
+        bf.insert(b"key1");
+        bf.set_overlapping_items(7);
+
+        let bf2 = BloomFilter::from_bytes(&bf.to_bytes()).expect("Should deserialize");
+        assert_eq!(bf2.overlapping_items(), 7);
+
+        let mut buf = Vec::new();
+        bf.write_to(&mut buf).unwrap();
+        let bf3 = BloomFilter::read_from(&mut buf.as_slice()).unwrap();
+        assert_eq!(bf3.overlapping_items(), 7);
+
+        // Files written before seeds, and before the trailer, still load
+        let unseeded = &buf[..buf.len() - 12];
+        let bf4 = BloomFilter::read_from(&mut &unseeded[..]).unwrap();
+        assert_eq!(bf4.overlapping_items(), 7);
+        assert_eq!(bf4.seed(), 0);
+        assert!(bf4.might_contain(b"key1"));
+        let legacy = &buf[..buf.len() - 16];
+        let bf5 = BloomFilter::from_bytes(legacy).unwrap();
+        assert_eq!(bf5.overlapping_items(), 0);
+        assert!(bf5.might_contain(b"key1"));
+    }
+
+    /// Keys whose unseeded FNV hashes land on the same bits of a 1024-bit
+    /// filter, and so set and probe the same positions
+    ///
+    /// h mod 1024 only depends on the low 10 bits of FNV's state. The last
+    /// byte is xored into those bits before a multiplication by the prime,
+    /// which is invertible, so for each prefix there is one candidate last
+    /// byte; about one prefix in 4096 makes both hashes hit their targets.
+    /// (The two states always differ in their lowest bit, since the offset
+    /// bases do, so the targets do too; h2 is made odd afterwards anyway.)
+    fn colliding_keys(count: usize) -> Vec<Vec<u8>> {
+        let mut inverse = FNV_PRIME;
+        for _ in 0..6 {
+            inverse = inverse.wrapping_mul(2u64.wrapping_sub(FNV_PRIME.wrapping_mul(inverse)));
+        }
+        let (want1, want2) = (
+            0x155u64.wrapping_mul(inverse),
+            0x2aau64.wrapping_mul(inverse),
+        );
+        let mut keys = Vec::new();
+        for n in 0u64.. {
+            let prefix = format!("user:{}:", n);
+            let s1 = fnv1a(FNV_OFFSET_BASIS, prefix.as_bytes());
+            let s2 = fnv1a(FNV_OFFSET_BASIS_ALT, prefix.as_bytes());
+            let last = (s1 ^ want1) & 0xff;
+            if (s1 ^ last) & 1023 == want1 & 1023 && (s2 ^ last) & 1023 == want2 & 1023 {
+                keys.push([prefix.as_bytes(), &[last as u8]].concat());
+                if keys.len() == count {
+                    break;
+                }
+            }
+        }
+        keys
+    }
+
+    #[test]
+    fn test_seeds_defeat_engineered_collisions() {
+        let keys = colliding_keys(64);
+        let (inserted, probes) = keys.split_at(32);
+        let false_positives =
+            |bf: &BloomFilter| probes.iter().filter(|k| bf.might_contain(k)).count();
+
+        // Unseeded, 32 keys fill four bits and every other crafted key is a
+        // false positive
+        let mut unseeded = BloomFilter::with_params(1024, 4)
+            .unwrap()
+            .with_hasher(FilterHasher::Fnv)
+            .with_seed(0);
+        for key in inserted {
+            unseeded.insert(key);
+        }
+        assert!(unseeded.stats().bits_set <= 4);
+        assert_eq!(false_positives(&unseeded), probes.len());
+
+        for hasher in [FilterHasher::Fnv, FilterHasher::SipHash] {
+            let mut seeded = BloomFilter::with_params(1024, 4)
+                .unwrap()
+                .with_seed(0x5eed)
+                .with_hasher(hasher);
+            for key in inserted {
+                seeded.insert(key);
+            }
+            assert!(seeded.stats().bits_set > 100, "{:?}", hasher);
+            assert!(false_positives(&seeded) <= 2, "{:?}", hasher);
+            assert!(inserted.iter().all(|k| seeded.might_contain(k)));
+
+            // The seed and hasher survive a round trip
+            let copy = BloomFilter::from_bytes(&seeded.to_bytes()).unwrap();
+            assert_eq!((copy.seed(), copy.hasher()), (0x5eed, hasher));
+            assert!(inserted.iter().all(|k| copy.might_contain(k)));
+        }
+
+        // Fresh filters draw different seeds
+        let a = BloomFilter::new(100, 0.01).unwrap();
+        let b = BloomFilter::new(100, 0.01).unwrap();
+        assert_ne!(a.seed(), b.seed());
+        assert_ne!(a.seed(), 0);
+
+        // Reference vector of SipHash-2-4: key 00..0f, message 00..0e
+        let message: Vec<u8> = (0..15).collect();
+        assert_eq!(
+            siphash24(0x0706050403020100, 0x0f0e0d0c0b0a0908, &message),
+            0xa129ca6149be45e5
+        );
+    }
+
+    #[test]
+    fn test_read_from_rejects_more_bits_than_the_file_holds() {
+        let mut bytes = BloomFilter::new(100, 0.01).unwrap().to_bytes();
+        bytes[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let Err(e) = BloomFilter::read_from(&mut bytes.as_slice()) else {
+            panic!("filter with a damaged bit count was read");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_inconsistent_headers() {
+        let mut bf = BloomFilter::new(100, 0.01).unwrap();
+        bf.insert(b"key1");
+        let good = bf.to_bytes();
+        assert!(BloomFilter::from_bytes(&good).is_some());
+
+        let with_field = |offset: usize, value: u32| {
+            let mut bytes = good.clone();
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            bytes
+        };
+        let bad = [
+            // num_hashes of 0 and far above the maximum
+            ("no hashes", with_field(4, 0)),
+            ("too many hashes", with_field(4, 10_000)),
+            // num_bits that no longer matches the bit array
+            ("bits too few", with_field(0, bf.num_bits() as u32 - 8)),
+            ("bits too many", with_field(0, bf.num_bits() as u32 + 8)),
+            ("bits below minimum", with_field(0, 0)),
+            // More items than the filter has bits
+            ("absurd items", with_field(8, bf.num_bits() as u32 + 1)),
+            // Payload that is neither bits nor bits + trailer
+            ("trailing garbage", [good.as_slice(), &[0; 3]].concat()),
+            ("torn trailer", good[..good.len() - 2].to_vec()),
+        ];
+        for (what, bytes) in bad {
+            assert!(BloomFilter::from_bytes(&bytes).is_none(), "{}", what);
+        }
+
+        // read_from applies the same header checks
+        for bytes in [with_field(4, 0), with_field(4, 17), with_field(8, u32::MAX)] {
+            let Err(e) = BloomFilter::read_from(&mut bytes.as_slice()) else {
+                panic!("damaged header was read");
+            };
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        }
+
+        // Within 7 bits of the array still matches it
+        let within = with_field(0, bf.num_bits().div_ceil(8) as u32 * 8 - 7);
+        assert!(BloomFilter::from_bytes(&within).is_some());
+    }
+
+    #[test]
+    fn test_floor_enlarges_small_filters_only() {
+        let floor = FilterFloor::default();
+        let tiny = BloomFilter::new_floored(1, 0.01, floor).unwrap();
+        assert_eq!(tiny.num_bits(), DEFAULT_MIN_FILTER_BYTES * 8);
+        assert_eq!(tiny.num_hashes(), MAX_HASHES);
+
+        let small = BloomFilter::new_floored(200, 0.01, floor).unwrap();
+        assert_eq!(small.num_bits(), 200 * DEFAULT_MIN_BITS_PER_KEY);
+
+        // Past the floor, the target rate decides
+        let tight = BloomFilter::new_floored(200, 0.0001, floor).unwrap();
+        let unfloored = BloomFilter::new(200, 0.0001).unwrap();
+        assert_eq!(tight.num_bits(), unfloored.num_bits());
+        assert_eq!(tight.num_hashes(), unfloored.num_hashes());
+    }
+
+    #[test]
+    fn test_constructors_reject_invalid_parameters() {
+        for fpp in [f64::NAN, 0.0, -0.1, 1.0, f64::INFINITY] {
+            let Err(e) = BloomFilter::new(100, fpp) else {
+                panic!("false positive rate {} was accepted", fpp);
+            };
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        for (bits, hashes) in [(0, 3), (7, 3), (64, 0), (64, 17)] {
+            assert!(BloomFilter::with_params(bits, hashes).is_err());
+        }
+        assert!(BloomFilter::with_params(MIN_BITS, MAX_HASHES).is_ok());
+    }
+
+    #[test]
+    fn test_deferred_filter_is_sized_for_the_keys_added() {
+        assert!(DeferredBloomFilter::new(0.0).is_err());
+
+        let mut deferred = DeferredBloomFilter::new(0.01).unwrap();
+        for i in 0..500 {
+            deferred.insert(format!("key{}", i).as_bytes());
+        }
+        assert_eq!(deferred.len(), 500);
+        let bf = deferred.build();
+
+        let sized = BloomFilter::new(500, 0.01).unwrap();
+        assert_eq!(bf.len(), 500);
+        assert_eq!(bf.num_bits(), sized.num_bits());
+        assert_eq!(bf.num_hashes(), sized.num_hashes());
+        assert!((0..500).all(|i| bf.might_contain(format!("key{}", i).as_bytes())));
+
+        // Round-trips with its seed like any other filter
+        let loaded = BloomFilter::from_bytes(&bf.to_bytes()).unwrap();
+        assert!(loaded.might_contain(b"key42"));
+    }
+}
+
+/// Runs with and without `std`: bytes serialized by a `std` build parse
+/// the same in a `no_std` one, and both builds serialize them identically
+#[cfg(test)]
+mod portable_tests {
+    use super::*;
+
+    const KEYS: [&[u8]; 3] = [b"alpha", b"beta", b"gamma"];
+
+    /// `filter(FilterHasher::Fnv)` and `filter(FilterHasher::SipHash)` as
+    /// serialized by a `std` build
+    const FNV_BYTES: [u8; 36] = [
+        64, 0, 0, 0, 3, 0, 0, 0, 3, 0, 0, 0, 144, 7, 0, 8, 0, 64, 0, 0, 1, 0, 0, 0, 237, 94, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    const SIPHASH_BYTES: [u8; 36] = [
+        64, 0, 0, 0, 3, 0, 0, 0, 3, 0, 0, 0, 8, 4, 129, 48, 24, 4, 0, 0, 1, 0, 0, 0, 237, 94, 0, 0,
+        0, 0, 0, 0, 1, 0, 0, 0,
+    ];
+
+    fn filter(hasher: FilterHasher) -> BloomFilter {
+        let mut bf = BloomFilter::try_with_params(64, 3)
+            .unwrap()
+            .with_seed(0x5eed)
+            .with_hasher(hasher);
+        for key in KEYS {
+            bf.insert(key);
+        }
+        bf.set_overlapping_items(1);
+        bf
+    }
+
+    #[test]
+    fn test_serialized_filters_read_back_in_every_build() {
+        for (hasher, bytes) in [
+            (FilterHasher::Fnv, FNV_BYTES),
+            (FilterHasher::SipHash, SIPHASH_BYTES),
+        ] {
+            assert_eq!(filter(hasher).to_bytes(), bytes, "{:?}", hasher);
+
+            let bf = BloomFilter::try_from_bytes(&bytes).unwrap();
+            assert_eq!((bf.seed(), bf.hasher()), (0x5eed, hasher));
+            assert_eq!((bf.len(), bf.overlapping_items()), (3, 1));
+            assert!(KEYS.iter().all(|key| bf.might_contain(key)));
+            assert_eq!(bf.to_bytes(), bytes);
+
+            #[cfg(feature = "std")]
+            {
+                let read = BloomFilter::read_from(&mut &bytes[..]).unwrap();
+                assert_eq!(read.to_bytes(), bytes);
+            }
+        }
+
+        // The core errors say why bytes were rejected
+        assert_eq!(
+            BloomFilter::try_from_bytes(&FNV_BYTES[..8]).err(),
+            Some(FilterError::Truncated { len: 8, needed: 12 })
+        );
+        assert_eq!(
+            BloomFilter::try_from_bytes(&FNV_BYTES[..33]).err(),
+            Some(FilterError::BadTrailer(13))
+        );
+        let mut unknown = FNV_BYTES;
+        unknown[32] = 9;
+        assert_eq!(
+            BloomFilter::try_from_bytes(&unknown).err(),
+            Some(FilterError::UnknownHasher(9))
+        );
+        assert_eq!(
+            BloomFilter::try_with_params(64, 0).err(),
+            Some(FilterError::InvalidHashCount(0))
+        );
+    }
+}
@@ -0,0 +1,9 @@
+//! Runs operations decoded from the fuzzer's input against the tree and a
+//! `BTreeMap`, failing on the first difference (see `testing::Model`)
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lsm_tree::testing::Model;
+
+fuzz_target!(|data: &[u8]| Model::fuzz(data));
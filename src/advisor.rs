@@ -0,0 +1,629 @@
+/// Configuration advice from workload statistics (see `LSMTree::advisor`)
+///
+/// Picking a memtable threshold or a Bloom filter false positive rate needs
+/// numbers about the workload that nobody has at hand. The tree collects
+/// them as it runs (`WorkloadStats`: key and value size histograms, a byte
+/// histogram of sampled values, reads, writes and reads of absent keys),
+/// and `advise` turns them, together with the current settings and the
+/// shape of the tree (`AdvisorInput`), into concrete suggestions:
+///
+/// - memtable threshold: each flush should write at least
+///   `MIN_ENTRIES_PER_FLUSH` entries; write-heavy trees with many tables
+///   get a larger memtable, for fewer and larger flushes
+/// - Bloom filter false positive rate: every read of an absent key probes
+///   each table whose filter lets it through, so the rate is tightened
+///   when such reads are common, and loosened (smaller filters) when they
+///   are rare
+/// - compression: values whose sampled bytes carry little information
+///   (low entropy) compress well; high-entropy values don't
+/// - compaction: overdue when reads probe too many tables, tables pile up,
+///   or tables are mostly expired
+///
+/// Every suggestion carries the numbers that led to it. `advise` is a pure
+/// function, so it can be tried on synthetic statistics.
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Entries a flush should write at the least
+pub const MIN_ENTRIES_PER_FLUSH: usize = 1024;
+
+/// Smallest memtable threshold suggested (64 KiB)
+pub const MIN_SUGGESTED_MEMTABLE: usize = 64 * 1024;
+
+/// Largest memtable threshold suggested (256 MiB)
+pub const MAX_SUGGESTED_MEMTABLE: usize = 256 * 1024 * 1024;
+
+/// Share of writes among operations from which a workload is write-heavy
+const WRITE_HEAVY_SHARE: f64 = 0.7;
+
+/// Table count from which a tree has many tables
+const MANY_TABLES: usize = 16;
+
+/// Share of reads for absent keys from which filters are worth tightening
+const MANY_ABSENT_READS: f64 = 0.25;
+
+/// Share of reads for absent keys below which filters barely matter
+const FEW_ABSENT_READS: f64 = 0.05;
+
+/// Table probes per read wasted on false positives that are acceptable
+const TARGET_WASTED_PROBES: f64 = 0.05;
+
+/// Rate suggested when filters barely matter
+const LOOSE_FPP: f64 = 0.05;
+
+/// Tightest rate suggested
+const MIN_FPP: f64 = 0.0001;
+
+/// Bits per byte of sampled values below which compression pays off
+const COMPRESSIBLE_ENTROPY: f64 = 6.0;
+
+/// Bits per byte of sampled values above which compression doesn't
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.5;
+
+/// Average value size below which compression isn't suggested
+const MIN_COMPRESSIBLE_VALUE: f64 = 64.0;
+
+/// Sampled value bytes needed before judging compression
+const MIN_SAMPLED_BYTES: u64 = 4096;
+
+/// Leading bytes of each value added to the byte histogram
+const VALUE_SAMPLE_PREFIX: usize = 64;
+
+/// Read amplification treated as too high when the tree has no threshold
+const DEFAULT_READ_AMP_LIMIT: f64 = 4.0;
+
+/// Histogram of sizes in power-of-two buckets
+///
+/// Bucket `i` counts sizes of bit length `i` (0, 1, 2-3, 4-7, ...); the
+/// last bucket also takes everything larger.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SizeHistogram {
+    buckets: [u64; 32],
+    count: u64,
+    sum: u64,
+}
+
+impl SizeHistogram {
+    pub fn record(&mut self, size: usize) {
+        let bucket = (usize::BITS - size.leading_zeros()).min(31) as usize;
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += size as u64;
+    }
+
+    /// Number of recorded sizes
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Average size (0.0 when empty)
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// Upper bound of the bucket holding the `p`th percentile (0.0 to 1.0)
+    pub fn percentile(&self, p: f64) -> u64 {
+        let rank = (p.clamp(0.0, 1.0) * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank.max(1) {
+                return (1u64 << bucket) - 1;
+            }
+        }
+        0
+    }
+}
+
+/// How often each byte value occurs in sampled values
+#[derive(Debug, Clone, PartialEq)]
+pub struct ByteHistogram {
+    counts: [u64; 256],
+    total: u64,
+}
+
+impl Default for ByteHistogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; 256],
+            total: 0,
+        }
+    }
+}
+
+impl ByteHistogram {
+    pub fn record(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.counts[byte as usize] += 1;
+        }
+        self.total += bytes.len() as u64;
+    }
+
+    /// Number of sampled bytes
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Shannon entropy of the sampled bytes in bits per byte (0.0 to 8.0),
+    /// or `None` without samples
+    pub fn entropy(&self) -> Option<f64> {
+        if self.total == 0 {
+            return None;
+        }
+        let total = self.total as f64;
+        Some(
+            self.counts
+                .iter()
+                .filter(|&&count| count > 0)
+                .map(|&count| {
+                    let p = count as f64 / total;
+                    -p * p.log2()
+                })
+                .sum(),
+        )
+    }
+}
+
+/// What the tree has seen of its workload since it was opened
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkloadStats {
+    /// Sizes of the keys written
+    pub key_sizes: SizeHistogram,
+
+    /// Sizes of the values written (deletes have none)
+    pub value_sizes: SizeHistogram,
+
+    /// Leading bytes of the values written
+    pub value_bytes: ByteHistogram,
+
+    /// Puts and deletes
+    pub writes: u64,
+
+    /// Point lookups (`get`, `get_opt`, `get_immut`, `multi_get`)
+    pub reads: u64,
+
+    /// Lookups that found nothing
+    pub absent_reads: u64,
+}
+
+impl WorkloadStats {
+    /// Adds a key and value (`None` for a delete) to the size and byte
+    /// histograms, without counting a write
+    ///
+    /// Also useful to describe existing data, by feeding it a scan.
+    pub fn record_entry(&mut self, key: &[u8], value: Option<&[u8]>) {
+        self.key_sizes.record(key.len());
+        if let Some(value) = value {
+            self.value_sizes.record(value.len());
+            self.value_bytes
+                .record(&value[..value.len().min(VALUE_SAMPLE_PREFIX)]);
+        }
+    }
+
+    /// Share of writes among reads and writes (`None` without either)
+    pub fn write_share(&self) -> Option<f64> {
+        let total = self.reads + self.writes;
+        (total > 0).then(|| self.writes as f64 / total as f64)
+    }
+
+    /// Share of reads that found nothing (`None` without reads)
+    pub fn absent_read_ratio(&self) -> Option<f64> {
+        (self.reads > 0).then(|| self.absent_reads as f64 / self.reads as f64)
+    }
+}
+
+/// Read counters, updated through `&self`
+#[derive(Debug, Default)]
+pub(crate) struct ReadCounts {
+    reads: AtomicU64,
+    absent: AtomicU64,
+}
+
+impl ReadCounts {
+    pub(crate) fn record(&self, found: bool) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        if !found {
+            self.absent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns (reads, absent reads)
+    pub(crate) fn get(&self) -> (u64, u64) {
+        (
+            self.reads.load(Ordering::Relaxed),
+            self.absent.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Everything `advise` looks at
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AdvisorInput {
+    pub workload: WorkloadStats,
+
+    /// Current `LSMConfig::memtable_size_threshold`
+    pub memtable_size_threshold: usize,
+
+    /// Current `LSMConfig::bloom_filter_fpp`
+    pub bloom_filter_fpp: f64,
+
+    /// Whether `LSMConfig::compression` compresses values
+    pub compression_enabled: bool,
+
+    /// Live SSTables
+    pub sstable_count: usize,
+
+    /// Moving average of SSTables probed per `get`
+    pub read_amplification: f64,
+
+    /// `LSMConfig::read_amp_warn_threshold`
+    pub read_amp_warn_threshold: Option<f64>,
+
+    /// SSTables with most of their writes past the TTL
+    pub mostly_expired_tables: usize,
+
+    /// SSTables whose estimated garbage reaches
+    /// `garbage::HIGH_GARBAGE_RATIO`
+    pub high_garbage_tables: usize,
+}
+
+/// A suggested value for one setting and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion<T> {
+    pub current: T,
+    pub suggested: T,
+
+    /// The numbers that led to the suggestion
+    pub reasons: Vec<String>,
+}
+
+impl<T: PartialEq> Suggestion<T> {
+    fn keep(current: T, reasons: Vec<String>) -> Self
+    where
+        T: Clone,
+    {
+        Self {
+            suggested: current.clone(),
+            current,
+            reasons,
+        }
+    }
+
+    /// Returns true if the suggested value differs from the current one
+    pub fn is_change(&self) -> bool {
+        self.current != self.suggested
+    }
+}
+
+/// Whether the tree should be compacted now, and why
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompactionAdvice {
+    pub overdue: bool,
+    pub reasons: Vec<String>,
+}
+
+/// Suggestions for a tree's settings (see `advise`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Advice {
+    pub memtable_size_threshold: Suggestion<usize>,
+    pub bloom_filter_fpp: Suggestion<f64>,
+
+    /// Whether values are, and should be, compressed
+    pub compress_values: Suggestion<bool>,
+
+    pub compaction: CompactionAdvice,
+}
+
+/// Computes suggestions from statistics alone
+pub fn advise(input: &AdvisorInput) -> Advice {
+    Advice {
+        memtable_size_threshold: advise_memtable(input),
+        bloom_filter_fpp: advise_fpp(input),
+        compress_values: advise_compression(input),
+        compaction: advise_compaction(input),
+    }
+}
+
+fn advise_memtable(input: &AdvisorInput) -> Suggestion<usize> {
+    let current = input.memtable_size_threshold;
+    let workload = &input.workload;
+    if workload.key_sizes.count() == 0 {
+        return Suggestion::keep(current, vec!["no writes recorded yet".to_string()]);
+    }
+    let entry_size = (workload.key_sizes.mean() + workload.value_sizes.mean()).max(1.0);
+    let per_flush = current as f64 / entry_size;
+    let mut reasons = vec![format!(
+        "average entry {:.0} bytes: about {:.0} entries per flush",
+        entry_size, per_flush
+    )];
+
+    let mut suggested = current;
+    if per_flush < MIN_ENTRIES_PER_FLUSH as f64 {
+        suggested = ((MIN_ENTRIES_PER_FLUSH as f64 * entry_size) as usize).next_power_of_two();
+        reasons.push(format!(
+            "fewer than {} entries per flush makes many small SSTables",
+            MIN_ENTRIES_PER_FLUSH
+        ));
+    } else if let Some(share) = workload.write_share()
+        && share >= WRITE_HEAVY_SHARE
+        && input.sstable_count >= MANY_TABLES
+    {
+        suggested = current.saturating_mul(2);
+        reasons.push(format!(
+            "{:.0}% of operations are writes and there are {} SSTables: \
+             larger flushes mean fewer tables",
+            share * 100.0,
+            input.sstable_count
+        ));
+    }
+    let suggested = suggested.clamp(MIN_SUGGESTED_MEMTABLE, MAX_SUGGESTED_MEMTABLE);
+    if suggested == current {
+        return Suggestion::keep(current, reasons);
+    }
+    Suggestion {
+        current,
+        suggested,
+        reasons,
+    }
+}
+
+fn advise_fpp(input: &AdvisorInput) -> Suggestion<f64> {
+    let current = input.bloom_filter_fpp;
+    let Some(absent) = input.workload.absent_read_ratio() else {
+        return Suggestion::keep(current, vec!["no reads recorded yet".to_string()]);
+    };
+    let tables = input.sstable_count.max(1) as f64;
+    let wasted = absent * current * tables;
+    let reasons = vec![
+        format!(
+            "{:.1}% of {} reads were for absent keys",
+            absent * 100.0,
+            input.workload.reads
+        ),
+        format!(
+            "{} SSTables at rate {}: {:.3} wasted table probes per read",
+            input.sstable_count, current, wasted
+        ),
+    ];
+
+    let suggested = if absent >= MANY_ABSENT_READS && wasted > TARGET_WASTED_PROBES {
+        round_down(TARGET_WASTED_PROBES / (absent * tables)).max(MIN_FPP)
+    } else if absent < FEW_ABSENT_READS && current < LOOSE_FPP {
+        LOOSE_FPP
+    } else {
+        current
+    };
+    Suggestion {
+        current,
+        suggested,
+        reasons,
+    }
+}
+
+/// Rounds down to one significant digit (0.0372 -> 0.03)
+fn round_down(value: f64) -> f64 {
+    let scale = 10f64.powf(value.log10().floor());
+    (value / scale).floor() * scale
+}
+
+fn advise_compression(input: &AdvisorInput) -> Suggestion<bool> {
+    let current = input.compression_enabled;
+    let sample = &input.workload.value_bytes;
+    let (Some(entropy), true) = (sample.entropy(), sample.total() >= MIN_SAMPLED_BYTES) else {
+        return Suggestion::keep(
+            current,
+            vec![format!(
+                "{} value bytes sampled, {} needed",
+                sample.total(),
+                MIN_SAMPLED_BYTES
+            )],
+        );
+    };
+    let value_size = input.workload.value_sizes.mean();
+    let reasons = vec![
+        format!(
+            "sampled values carry {:.2} bits per byte ({} bytes sampled)",
+            entropy,
+            sample.total()
+        ),
+        format!("average value {:.0} bytes", value_size),
+    ];
+    let suggested = if entropy < COMPRESSIBLE_ENTROPY && value_size >= MIN_COMPRESSIBLE_VALUE {
+        true
+    } else if entropy > INCOMPRESSIBLE_ENTROPY {
+        false
+    } else {
+        current
+    };
+    Suggestion {
+        current,
+        suggested,
+        reasons,
+    }
+}
+
+fn advise_compaction(input: &AdvisorInput) -> CompactionAdvice {
+    let mut advice = CompactionAdvice::default();
+    let limit = input
+        .read_amp_warn_threshold
+        .unwrap_or(DEFAULT_READ_AMP_LIMIT);
+    if input.read_amplification > limit {
+        advice.reasons.push(format!(
+            "gets probe {:.1} SSTables on average (limit {})",
+            input.read_amplification, limit
+        ));
+    }
+    if input.sstable_count >= MANY_TABLES {
+        advice
+            .reasons
+            .push(format!("{} SSTables", input.sstable_count));
+    }
+    if input.mostly_expired_tables > 0 {
+        advice.reasons.push(format!(
+            "{} SSTables are mostly expired",
+            input.mostly_expired_tables
+        ));
+    }
+    if input.high_garbage_tables > 0 {
+        advice.reasons.push(format!(
+            "{} SSTables are mostly overwritten or deleted data",
+            input.high_garbage_tables
+        ));
+    }
+    advice.overdue = !advice.reasons.is_empty();
+    if !advice.overdue {
+        advice.reasons.push(format!(
+            "{} SSTables, {:.1} probed per get",
+            input.sstable_count, input.read_amplification
+        ));
+    }
+    advice
+}
+
+impl Advice {
+    /// Returns true if any setting should change or compaction is overdue
+    pub fn has_changes(&self) -> bool {
+        self.memtable_size_threshold.is_change()
+            || self.bloom_filter_fpp.is_change()
+            || self.compress_values.is_change()
+            || self.compaction.overdue
+    }
+}
+
+fn write_suggestion<T: PartialEq + fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    suggestion: &Suggestion<T>,
+) -> fmt::Result {
+    if suggestion.is_change() {
+        writeln!(
+            f,
+            "{}: {} -> {}",
+            name, suggestion.current, suggestion.suggested
+        )?;
+    } else {
+        writeln!(f, "{}: {} (keep)", name, suggestion.current)?;
+    }
+    for reason in &suggestion.reasons {
+        writeln!(f, "  - {}", reason)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Advice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_suggestion(f, "memtable_size_threshold", &self.memtable_size_threshold)?;
+        write_suggestion(f, "bloom_filter_fpp", &self.bloom_filter_fpp)?;
+        write_suggestion(f, "compress_values", &self.compress_values)?;
+        let verdict = if self.compaction.overdue {
+            "overdue"
+        } else {
+            "not needed"
+        };
+        writeln!(f, "compaction: {}", verdict)?;
+        for reason in &self.compaction.reasons {
+            writeln!(f, "  - {}", reason)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input() -> AdvisorInput {
+        AdvisorInput {
+            memtable_size_threshold: 4 * 1024 * 1024,
+            bloom_filter_fpp: 0.01,
+            sstable_count: 4,
+            ..AdvisorInput::default()
+        }
+    }
+
+    #[test]
+    fn test_histograms() {
+        let mut sizes = SizeHistogram::default();
+        for size in [0, 1, 3, 100, 100, 5000] {
+            sizes.record(size);
+        }
+        assert_eq!(sizes.count(), 6);
+        assert_eq!(sizes.mean(), 5204.0 / 6.0);
+        assert_eq!(sizes.percentile(0.5), 3);
+        assert_eq!(sizes.percentile(0.8), 127);
+        assert_eq!(sizes.percentile(1.0), 8191);
+
+        let mut bytes = ByteHistogram::default();
+        assert_eq!(bytes.entropy(), None);
+        bytes.record(&[7; 100]);
+        assert_eq!(bytes.entropy(), Some(0.0));
+        let all: Vec<u8> = (0..=255).cycle().take(256 * 16).collect();
+        let mut uniform = ByteHistogram::default();
+        uniform.record(&all);
+        assert!((uniform.entropy().unwrap() - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_advice_from_synthetic_stats() {
+        // Nothing recorded: keep everything
+        let advice = advise(&input());
+        assert!(!advice.has_changes());
+
+        // Tiny memtable for 2 KiB entries, mostly absent reads over many
+        // tables, repetitive values
+        let mut input = input();
+        input.memtable_size_threshold = 256 * 1024;
+        input.sstable_count = 20;
+        input.read_amplification = 6.0;
+        for i in 0..100u32 {
+            let value = format!("{{\"id\":{},\"name\":\"user\"}}", i).repeat(60);
+            input
+                .workload
+                .record_entry(&i.to_be_bytes(), Some(value.as_bytes()));
+        }
+        input.workload.writes = 100;
+        input.workload.reads = 1000;
+        input.workload.absent_reads = 800;
+
+        let advice = advise(&input);
+        assert_eq!(advice.memtable_size_threshold.suggested, 2 * 1024 * 1024);
+        // 0.8 absent * 20 tables: 0.05 / 16 rounds down to 0.003
+        assert!((advice.bloom_filter_fpp.suggested - 0.003).abs() < 1e-12);
+        assert!(advice.compress_values.suggested);
+        assert!(advice.compaction.overdue);
+        assert_eq!(advice.compaction.reasons.len(), 2);
+        assert!(
+            advice
+                .to_string()
+                .contains("memtable_size_threshold: 262144 -> 2097152")
+        );
+
+        // Random-looking values don't compress; rare absent reads loosen
+        // the filters
+        let mut input = self::input();
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..200 {
+            let value: Vec<u8> = (0..64)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            input.workload.record_entry(b"key", Some(&value));
+        }
+        input.compression_enabled = true;
+        input.workload.reads = 1000;
+        input.workload.absent_reads = 10;
+        let advice = advise(&input);
+        assert!(!advice.compress_values.suggested);
+        assert_eq!(advice.bloom_filter_fpp.suggested, LOOSE_FPP);
+        assert!(!advice.memtable_size_threshold.is_change());
+        assert!(!advice.compaction.overdue);
+    }
+}
@@ -0,0 +1,348 @@
+/// An async handle to a tree, for services running on tokio (`tokio`
+/// feature)
+///
+/// The tree's calls block: a write may fsync the WAL, a flush writes an
+/// SSTable, a compaction rewrites every table. Called from an async task
+/// they would stall a runtime worker. `AsyncLSMTree` keeps the blocking
+/// off the runtime:
+///
+/// - writes, flushes and compactions go through a bounded queue to one
+///   dedicated writer thread, which applies them one at a time in the order
+///   they were queued; each call resolves once its operation is done. When
+///   the queue is full (the writer is stalled on a slow fsync or a long
+///   compaction) a new write waits asynchronously for room instead of
+///   blocking
+/// - reads run on tokio's blocking pool, in parallel with each other
+///
+/// A flush or compaction would hold the tree's write lock for as long as
+/// it runs, so before starting one the writer publishes a `TreeState` of
+/// the tree. Reads issued meanwhile are answered from that state: they see
+/// every write that finished before the flush or compaction began, and
+/// don't wait for it to end.
+///
+/// Writes from one handle are applied in the order their calls are
+/// awaited; awaiting each write before issuing the next orders them the
+/// same way a sync caller would.
+///
+/// Example:
+/// ```ignore
+/// let tree = AsyncLSMTree::open(LSMConfig::new(dir)).await?;
+/// tree.put(b"key".to_vec(), b"value".to_vec()).await?;
+/// assert_eq!(tree.get(b"key".to_vec()).await, Some(b"value".to_vec()));
+/// ```
+use std::ops::RangeBounds;
+use std::sync::{Arc, Mutex, RwLock, TryLockError};
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{CompactionResult, LSMConfig, LSMTree, TreeState};
+
+/// Operations the writer thread can have queued before writers wait
+pub const WRITE_QUEUE_DEPTH: usize = 64;
+
+/// An operation for the writer thread
+type Job = Box<dyn FnOnce(&Shared) + Send>;
+
+/// What the handles and the writer thread share
+struct Shared {
+    tree: RwLock<LSMTree>,
+
+    /// The tree as of the start of the running flush or compaction, if
+    /// one is running
+    frozen: Mutex<Option<Arc<TreeState>>>,
+}
+
+impl Shared {
+    /// Runs a short write under the write lock
+    fn write<R>(&self, f: impl FnOnce(&mut LSMTree) -> R) -> R {
+        f(&mut self.tree.write().expect("LSM tree lock poisoned"))
+    }
+
+    /// Runs a long write, answering reads from a state taken before it
+    fn long_write<R>(&self, f: impl FnOnce(&mut LSMTree) -> R) -> R {
+        let state = self
+            .tree
+            .read()
+            .expect("LSM tree lock poisoned")
+            .read_state();
+        *self.frozen.lock().expect("frozen state lock poisoned") = Some(Arc::new(state));
+        let result = self.write(f);
+        *self.frozen.lock().expect("frozen state lock poisoned") = None;
+        result
+    }
+
+    /// Runs a read on the tree, or on the frozen state while a long write
+    /// holds the tree
+    ///
+    /// Never blocks behind a long write: it only sleeps briefly while a
+    /// short write finishes.
+    fn read<R>(
+        &self,
+        on_tree: impl FnOnce(&LSMTree) -> R,
+        on_state: impl FnOnce(&Arc<TreeState>) -> R,
+    ) -> R {
+        let mut waits = 0u32;
+        loop {
+            // The state goes up before the write lock is taken, so a long
+            // write holding the lock always has one
+            let frozen = self
+                .frozen
+                .lock()
+                .expect("frozen state lock poisoned")
+                .clone();
+            if let Some(state) = frozen {
+                return on_state(&state);
+            }
+            match self.tree.try_read() {
+                Ok(tree) => return on_tree(&tree),
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Poisoned(_)) => panic!("LSM tree lock poisoned"),
+            }
+            waits += 1;
+            if waits < 64 {
+                std::thread::yield_now();
+            } else {
+                std::thread::sleep(Duration::from_micros(50));
+            }
+        }
+    }
+}
+
+/// Cloneable async handle to an `LSMTree` (see the module docs)
+#[derive(Clone)]
+pub struct AsyncLSMTree {
+    shared: Arc<Shared>,
+    jobs: mpsc::Sender<Job>,
+}
+
+impl AsyncLSMTree {
+    /// Wraps an open tree and starts its writer thread
+    ///
+    /// The thread stops, and the tree is dropped, once every handle is.
+    pub fn new(tree: LSMTree) -> Self {
+        let shared = Arc::new(Shared {
+            tree: RwLock::new(tree),
+            frozen: Mutex::new(None),
+        });
+        let (jobs, mut queue) = mpsc::channel::<Job>(WRITE_QUEUE_DEPTH);
+        let writer = shared.clone();
+        std::thread::Builder::new()
+            .name("lsm-writer".to_string())
+            .spawn(move || {
+                while let Some(job) = queue.blocking_recv() {
+                    job(&writer);
+                }
+            })
+            .expect("failed to start the LSM writer thread");
+        Self { shared, jobs }
+    }
+
+    /// Opens (or creates) a tree on the blocking pool and wraps it
+    pub async fn open(config: LSMConfig) -> std::io::Result<Self> {
+        let tree = tokio::task::spawn_blocking(move || LSMTree::open(config))
+            .await
+            .map_err(join_error)??;
+        Ok(Self::new(tree))
+    }
+
+    /// Inserts or updates a key-value pair
+    pub async fn put(&self, key: Vec<u8>, value: Vec<u8>) -> std::io::Result<()> {
+        self.submit(move |shared| shared.write(|tree| tree.put(key, value)))
+            .await?
+    }
+
+    /// Deletes a key
+    pub async fn delete(&self, key: Vec<u8>) -> std::io::Result<()> {
+        self.submit(move |shared| shared.write(|tree| tree.delete(key)))
+            .await?
+    }
+
+    /// Flushes the memtable to a new SSTable
+    pub async fn flush(&self) -> std::io::Result<()> {
+        self.submit(|shared| shared.long_write(LSMTree::flush))
+            .await?
+    }
+
+    /// Compacts every SSTable into one (`LSMTree::purge`)
+    pub async fn compact(&self) -> std::io::Result<CompactionResult> {
+        self.submit(|shared| shared.long_write(LSMTree::purge))
+            .await?
+    }
+
+    /// Looks up a key (without updating Bloom filter statistics)
+    pub async fn get(&self, key: Vec<u8>) -> Option<Vec<u8>> {
+        let shared = self.shared.clone();
+        tokio::task::spawn_blocking(move || {
+            shared.read(|tree| tree.get_immut(&key), |state| state.get(&key))
+        })
+        .await
+        .expect("LSM tree read panicked")
+    }
+
+    /// Collects the live key-value pairs in `range`, in key order, as of
+    /// one moment
+    pub async fn range_collect<R>(&self, range: R) -> Vec<(Vec<u8>, Vec<u8>)>
+    where
+        R: RangeBounds<Vec<u8>> + Send + 'static,
+    {
+        let shared = self.shared.clone();
+        tokio::task::spawn_blocking(move || {
+            let state = shared.read(|tree| Arc::new(tree.read_state()), Arc::clone);
+            state.range(range).collect()
+        })
+        .await
+        .expect("LSM tree read panicked")
+    }
+
+    /// Queues `job` for the writer thread and waits for its result
+    async fn submit<R: Send + 'static>(
+        &self,
+        job: impl FnOnce(&Shared) -> R + Send + 'static,
+    ) -> std::io::Result<R> {
+        let (reply, result) = oneshot::channel();
+        let job: Job = Box::new(move |shared| {
+            // The caller may have stopped waiting; the job still ran
+            let _ = reply.send(job(shared));
+        });
+        self.jobs.send(job).await.map_err(|_| writer_stopped())?;
+        result.await.map_err(|_| writer_stopped())
+    }
+}
+
+/// Error for a job the writer thread couldn't run (it panicked earlier)
+fn writer_stopped() -> std::io::Error {
+    std::io::Error::other("the LSM writer thread stopped")
+}
+
+fn join_error(e: tokio::task::JoinError) -> std::io::Error {
+    std::io::Error::other(format!("opening the tree failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompactionFilter, FilterDecision};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_puts_and_gets() {
+        let dir = PathBuf::from("./test_async_concurrent");
+        fs::remove_dir_all(&dir).ok();
+
+        let config = LSMConfig {
+            memtable_size_threshold: 2048,
+            sync_on_flush: false,
+            ..LSMConfig::new(dir.clone())
+        };
+        let tree = AsyncLSMTree::open(config).await.unwrap();
+        let key = |task: u32, i: u32| format!("t{}:{:04}", task, i).into_bytes();
+
+        let tasks: Vec<_> = (0..8u32)
+            .map(|task| {
+                let tree = tree.clone();
+                tokio::spawn(async move {
+                    for i in 0..200u32 {
+                        tree.put(key(task, i), i.to_le_bytes().to_vec())
+                            .await
+                            .unwrap();
+                        // A handle reads its own writes
+                        assert_eq!(tree.get(key(task, i)).await, Some(i.to_le_bytes().to_vec()));
+                        if i % 50 == 0 {
+                            tree.delete(key(task, i)).await.unwrap();
+                            assert_eq!(tree.get(key(task, i)).await, None);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        tree.flush().await.unwrap();
+
+        let all = tree.range_collect(..).await;
+        assert_eq!(all.len(), 8 * (200 - 4));
+        assert!(all.windows(2).all(|w| w[0].0 < w[1].0));
+        let t3 = tree.range_collect(b"t3:".to_vec()..b"t4:".to_vec()).await;
+        assert_eq!(t3.len(), 200 - 4);
+        assert_eq!(
+            tree.get(key(5, 199)).await,
+            Some(199u32.to_le_bytes().to_vec())
+        );
+        assert!(tree.shared.tree.read().unwrap().sstable_count() > 1);
+        drop(tree);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    /// Keeps every entry, slowly
+    #[derive(Debug)]
+    struct SlowFilter;
+
+    impl CompactionFilter for SlowFilter {
+        fn filter(&self, _key: &[u8], _value: &[u8]) -> FilterDecision {
+            std::thread::sleep(Duration::from_millis(2));
+            FilterDecision::Keep
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_gets_progress_during_long_compaction() {
+        let dir = PathBuf::from("./test_async_long_compaction");
+        fs::remove_dir_all(&dir).ok();
+
+        let config = LSMConfig {
+            memtable_size_threshold: 1024 * 1024,
+            sync_on_flush: false,
+            compaction_filter: Some(Arc::new(SlowFilter)),
+            ..LSMConfig::new(dir.clone())
+        };
+        let tree = AsyncLSMTree::open(config).await.unwrap();
+        for table in 0..4u32 {
+            for i in 0..50u32 {
+                let key = format!("{:04}", table * 50 + i).into_bytes();
+                tree.put(key, table.to_le_bytes().to_vec()).await.unwrap();
+            }
+            tree.flush().await.unwrap();
+        }
+
+        // 200 entries at 2 ms each: the compaction runs for 400 ms
+        let done = Arc::new(AtomicBool::new(false));
+        let compaction = {
+            let tree = tree.clone();
+            let done = done.clone();
+            tokio::spawn(async move {
+                let result = tree.compact().await.unwrap();
+                done.store(true, Ordering::SeqCst);
+                result
+            })
+        };
+        while tree.shared.frozen.lock().unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        for i in (0..200u32).step_by(10) {
+            let key = format!("{:04}", i).into_bytes();
+            assert_eq!(tree.get(key).await, Some((i / 50).to_le_bytes().to_vec()));
+        }
+        assert_eq!(tree.range_collect(..).await.len(), 200);
+        assert!(
+            !done.load(Ordering::SeqCst),
+            "reads waited for the compaction to finish"
+        );
+
+        let result = compaction.await.unwrap();
+        assert_eq!(result.entries_written, 200);
+        assert_eq!(tree.shared.tree.read().unwrap().sstable_count(), 1);
+        assert_eq!(
+            tree.get(b"0199".to_vec()).await,
+            Some(3u32.to_le_bytes().to_vec())
+        );
+        drop(tree);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
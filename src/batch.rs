@@ -0,0 +1,59 @@
+//! A group of writes applied together
+//!
+//! `LSMTree::write` logs every operation of a batch to the WAL, syncs the
+//! log at most once for all of them, and only then applies them to the
+//! memtable. That is cheaper than the same writes one by one, and a flush
+//! can't start halfway through the batch.
+//!
+//! A batch is not atomic across a crash: WAL entries are replayed one by
+//! one, so after a crash in the middle of logging a batch, a prefix of it
+//! may be recovered. `LSMTree::load_from_reader` builds on this: it
+//! writes its input in synced batches and reports the rows a crash can't
+//! lose.
+//!
+//! Example:
+//! ```ignore
+//! let mut batch = WriteBatch::new();
+//! batch.put(b"user:1".to_vec(), b"alice".to_vec());
+//! batch.delete(b"user:2".to_vec());
+//! lsm.write(batch)?;
+//! ```
+
+/// Ordered list of puts and deletes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WriteBatch {
+    /// Keys with their new value, `None` for a delete
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl WriteBatch {
+    /// Creates an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a put
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.ops.push((key, Some(value)));
+    }
+
+    /// Adds a delete
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.ops.push((key, None));
+    }
+
+    /// Number of operations
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns true if the batch holds no operations
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Takes the operations out, in the order they were added
+    pub(crate) fn into_ops(self) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        self.ops
+    }
+}
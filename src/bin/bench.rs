@@ -0,0 +1,264 @@
+//! Micro-benchmarks for the SSTable read paths
+//!
+//! Run with: `cargo run --release --bin lsm-bench [entries]`
+//!
+//! Builds a single SSTable and times point lookups that have to scan the
+//! whole file (the last key) and the Bloom filter rebuild done on open when
+//! a `.bloom` file is missing. Then compares the memtable backends under
+//! concurrent readers and writers (build with `--features skiplist` to
+//! include the skiplist), one write shard against several under many
+//! writer threads, and flushes and full scans with small and large I/O
+//! buffers. Each run ends with its write and space amplification.
+
+use lsm_tree::config::{DEFAULT_READ_BUFFER_BYTES, DEFAULT_WRITE_BUFFER_BYTES};
+use lsm_tree::{
+    AmplificationStats, LSMConfig, LSMTree, MemtableBackend, ShardedLSMTree, SharedLSMTree,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DATA_DIR: &str = "./lsm_bench_data";
+const LOOKUPS: u32 = 200;
+const REBUILDS: u32 = 10;
+const CONCURRENT_WRITERS: usize = 2;
+const CONCURRENT_READERS: usize = 4;
+const SHARDED_WRITERS: usize = 8;
+const SHARDED_MEMTABLE_BYTES: usize = 4 * 1024 * 1024;
+const SCANS: u32 = 5;
+
+fn main() {
+    let entries: usize = std::env::args()
+        .nth(1)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(100_000);
+
+    let _ = std::fs::remove_dir_all(DATA_DIR);
+    let data_dir = PathBuf::from(DATA_DIR);
+
+    let mut lsm = LSMTree::new(data_dir.clone(), usize::MAX).expect("Failed to create LSM tree");
+    for i in 0..entries {
+        lsm.put(
+            format!("key_{:08}", i).into_bytes(),
+            format!("value_{:08}_{}", i, "x".repeat(64)).into_bytes(),
+        )
+        .expect("Failed to put");
+    }
+    lsm.flush().expect("Failed to flush");
+    println!("=== LSM Tree Bench ({} entries, 1 SSTable) ===\n", entries);
+
+    let last_key = format!("key_{:08}", entries - 1).into_bytes();
+    let start = Instant::now();
+    for _ in 0..LOOKUPS {
+        assert!(lsm.get(&last_key).is_some());
+    }
+    report("get (last key)", start.elapsed(), LOOKUPS);
+    report_amplification(&lsm.amplification_stats());
+    drop(lsm);
+
+    let mut total = Duration::ZERO;
+    for _ in 0..REBUILDS {
+        remove_bloom_files(&data_dir);
+        let start = Instant::now();
+        let lsm = LSMTree::new(data_dir.clone(), usize::MAX).expect("Failed to reopen");
+        total += start.elapsed();
+        assert_eq!(lsm.filterless_sstables().len(), 0);
+    }
+    report("open + filter rebuild", total, REBUILDS);
+    let _ = std::fs::remove_dir_all(DATA_DIR);
+
+    println!(
+        "\n=== Memtable backends ({} writers, {} readers) ===\n",
+        CONCURRENT_WRITERS, CONCURRENT_READERS
+    );
+    for backend in MemtableBackend::available() {
+        bench_concurrent(backend, entries);
+        let _ = std::fs::remove_dir_all(DATA_DIR);
+    }
+
+    println!(
+        "\n=== Write shards ({} writers, synced puts) ===\n",
+        SHARDED_WRITERS
+    );
+    for shards in [1, SHARDED_WRITERS] {
+        // Every put waits for an fsync: a tenth of the entries will do
+        bench_sharded(shards, entries / 10);
+        let _ = std::fs::remove_dir_all(DATA_DIR);
+    }
+
+    println!("\n=== I/O buffers (read / write bytes) ===\n");
+    // std's 8 KiB default, the tree's defaults, and large buffers
+    for (read, write) in [
+        (8 * 1024, 8 * 1024),
+        (DEFAULT_READ_BUFFER_BYTES, DEFAULT_WRITE_BUFFER_BYTES),
+        (1024 * 1024, 1024 * 1024),
+    ] {
+        bench_buffers(read, write, entries);
+        let _ = std::fs::remove_dir_all(DATA_DIR);
+    }
+}
+
+/// Times one large flush, then full scans of the resulting table, with the
+/// given `LSMConfig::read_buffer_bytes` and `write_buffer_bytes`
+fn bench_buffers(read_buffer_bytes: usize, write_buffer_bytes: usize, entries: usize) {
+    let config = LSMConfig {
+        memtable_size_threshold: usize::MAX,
+        // The fsync would drown out the writes themselves
+        sync_on_flush: false,
+        read_buffer_bytes,
+        write_buffer_bytes,
+        ..LSMConfig::new(PathBuf::from(DATA_DIR))
+    };
+    let mut lsm = LSMTree::open(config).expect("Failed to create LSM tree");
+    for i in 0..entries {
+        lsm.put(
+            format!("key_{:08}", i).into_bytes(),
+            format!("value_{:08}_{}", i, "x".repeat(64)).into_bytes(),
+        )
+        .expect("Failed to put");
+    }
+    let label = format!(
+        "{}K / {}K",
+        read_buffer_bytes / 1024,
+        write_buffer_bytes / 1024
+    );
+    let start = Instant::now();
+    lsm.flush().expect("Failed to flush");
+    report(&format!("flush {}", label), start.elapsed(), 1);
+
+    let start = Instant::now();
+    for _ in 0..SCANS {
+        assert_eq!(lsm.range::<&[u8], _>(..).count(), entries);
+    }
+    report(&format!("scan {}", label), start.elapsed(), SCANS);
+}
+
+/// `SHARDED_WRITERS` threads insert `entries` keys between them into a
+/// tree with `shards` write shards, each put synced to its WAL
+///
+/// Synced writes are where one shard hurts most: a writer holds the write
+/// lock through its fsync, so every other writer waits for it.
+fn bench_sharded(shards: usize, entries: usize) {
+    let config = LSMConfig {
+        memtable_size_threshold: SHARDED_MEMTABLE_BYTES,
+        sync_writes: true,
+        write_shards: shards,
+        ..LSMConfig::new(PathBuf::from(DATA_DIR))
+    };
+    let tree = ShardedLSMTree::open(config).expect("Failed to create LSM tree");
+
+    let start = Instant::now();
+    let writers: Vec<_> = (0..SHARDED_WRITERS)
+        .map(|w| {
+            let tree = tree.clone();
+            thread::spawn(move || {
+                for i in (w..entries).step_by(SHARDED_WRITERS) {
+                    tree.put(format!("key_{:08}", i).into_bytes(), vec![b'x'; 64])
+                        .expect("Failed to put");
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().expect("Writer panicked");
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<10} {:>12.0} puts/s  ({} SSTables)",
+        format!("{} shards", shards),
+        entries as f64 / elapsed.as_secs_f64(),
+        tree.sstable_count()
+    );
+}
+
+/// Writers insert `entries` keys between them while readers look up keys
+/// until the writers are done; reports the throughput of both
+fn bench_concurrent(backend: MemtableBackend, entries: usize) {
+    let config = LSMConfig {
+        memtable_size_threshold: usize::MAX,
+        memtable_backend: backend,
+        ..LSMConfig::new(PathBuf::from(DATA_DIR))
+    };
+    let tree = SharedLSMTree::new(LSMTree::open(config).expect("Failed to create LSM tree"));
+    let writing = Arc::new(AtomicBool::new(true));
+    let reads = Arc::new(AtomicU64::new(0));
+
+    let readers: Vec<_> = (0..CONCURRENT_READERS)
+        .map(|r| {
+            let tree = tree.clone();
+            let writing = writing.clone();
+            let reads = reads.clone();
+            thread::spawn(move || {
+                let mut i = r;
+                while writing.load(Ordering::Relaxed) {
+                    tree.get(format!("key_{:08}", i % entries).as_bytes());
+                    reads.fetch_add(1, Ordering::Relaxed);
+                    i += CONCURRENT_READERS;
+                }
+            })
+        })
+        .collect();
+
+    let start = Instant::now();
+    let writers: Vec<_> = (0..CONCURRENT_WRITERS)
+        .map(|w| {
+            let tree = tree.clone();
+            thread::spawn(move || {
+                for i in (w..entries).step_by(CONCURRENT_WRITERS) {
+                    tree.put(format!("key_{:08}", i).into_bytes(), vec![b'x'; 64])
+                        .expect("Failed to put");
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().expect("Writer panicked");
+    }
+    let elapsed = start.elapsed();
+    writing.store(false, Ordering::Relaxed);
+    for reader in readers {
+        reader.join().expect("Reader panicked");
+    }
+
+    let reads = reads.load(Ordering::Relaxed);
+    println!(
+        "{:<10} {:>12.0} puts/s  {:>12.0} gets/s",
+        backend.name(),
+        entries as f64 / elapsed.as_secs_f64(),
+        reads as f64 / elapsed.as_secs_f64()
+    );
+    tree.flush().expect("Failed to flush");
+    report_amplification(&tree.read().amplification_stats());
+}
+
+fn remove_bloom_files(data_dir: &PathBuf) {
+    for entry in std::fs::read_dir(data_dir).expect("Failed to read data dir") {
+        let path = entry.expect("Failed to read entry").path();
+        if path.extension().is_some_and(|ext| ext == "bloom") {
+            std::fs::remove_file(path).expect("Failed to remove filter");
+        }
+    }
+}
+
+fn report_amplification(stats: &AmplificationStats) {
+    println!(
+        "{:<24} write {:.2}x  space {:.2}x  ({} user bytes, {} SSTable bytes)",
+        "amplification",
+        stats.write_amplification(),
+        stats.space_amplification(),
+        stats.user_bytes_written,
+        stats.sstable_bytes_written
+    );
+}
+
+fn report(name: &str, total: Duration, iterations: u32) {
+    println!(
+        "{:<24} {:>10.3} ms/op  ({} ops)",
+        name,
+        total.as_secs_f64() * 1000.0 / iterations as f64,
+        iterations
+    );
+}
@@ -3,24 +3,54 @@
 //! A beautiful terminal user interface to explore and interact with the LSM Tree.
 //!
 //! Run with: cargo run --bin lsm-cli
+//!
+//! Maintenance subcommands run without the TUI:
+//!
+//! ```text
+//! lsm-cli migrate --dir <path>   Rewrite legacy SSTables into the current format
+//! lsm-cli repair-ordering --dir <path>
+//!                                Reorder legacy SSTables by modification time
+//! lsm-cli repair --dir <path>    Rebuild a lost or damaged manifest
+//! lsm-cli import-legacy --from <path> --dir <path>
+//!                                Convert an old demo directory into a new tree
+//! lsm-cli apply-wal --file <path> --dir <path>
+//!                                Replay a salvaged WAL file into a tree
+//! lsm-cli stats --dir <path> --bloom [--json]
+//!                                Print Bloom filter statistics
+//! lsm-cli stats --dir <path> --advise
+//!                                Suggest settings for the stored data
+//! lsm-cli stats --dir <path> --disk
+//!                                Show bytes on disk per kind of file
+//! ```
+//!
+//! Commands that open the tree accept `--verbose` to print what opening it
+//! recovered (see `OpenReport`) to stderr.
 
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
-use lsm_tree::LSMTree;
+use lsm_tree::{
+    CancellationToken, Cancelled, Clock, ColdPolicy, IoRates, LSMConfig, LSMTree, LatencyOp,
+    OpenPhase, OpenProgress, RepairOptions, SSTableId, SSTableReader, SSTableReaderOptions,
+    SystemClock, advisor, prefix_stats,
+};
 use ratatui::{
     Frame, Terminal,
     backend::CrosstermBackend,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style, Stylize},
     text::{Line, Span},
-    widgets::{Block, Borders, Clear, Gauge, List, ListItem, Paragraph, Tabs},
+    widgets::{Block, Borders, Clear, Gauge, List, ListItem, Paragraph, Sparkline, Tabs, Wrap},
 };
 use std::{
-    io,
-    path::PathBuf,
+    io::{self, Stdout},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{Duration, Instant},
 };
 
@@ -42,8 +72,9 @@ struct App {
     search_result: Option<SearchResult>,
     /// Message log
     messages: Vec<(Instant, String, MessageType)>,
-    /// Selected SSTable index for viewing
-    selected_sstable: usize,
+    /// SSTable selected for viewing, by id so flushes and purges between
+    /// renders can't shift the selection onto another file
+    selected_sstable: Option<SSTableId>,
     /// Scroll offset for SSTable view
     sstable_scroll: usize,
     /// Scroll offset for memtable view
@@ -54,26 +85,65 @@ struct App {
     should_quit: bool,
     /// Show help popup
     show_help: bool,
+    /// How keys and values are shown
+    display_mode: DisplayMode,
+    /// Entry shown in full in a popup: key, value
+    entry_popup: Option<(Vec<u8>, Vec<u8>)>,
     /// Auto-demo mode
     auto_demo: bool,
     /// Demo step counter
     demo_step: usize,
     /// Last demo time
     last_demo_time: Instant,
+    /// The tree's clock, which `t` and the TTL demo move forward
+    clock: DemoClock,
+    /// The TTL demo, while it runs
+    ttl_demo: Option<TtlDemo>,
+    /// Disk throughput sampled every tick, oldest first
+    io_history: Vec<IoRates>,
 }
 
 #[derive(Clone)]
 enum Operation {
-    Put(String, String),
-    Get(String, bool), // key, found
+    Put(Vec<u8>, Vec<u8>),
+    Get(Vec<u8>, bool), // key, found
     Flush,
 }
 
 enum SearchResult {
-    Found(String),
+    Found(Vec<u8>),
     NotFound,
 }
 
+/// How keys and values are shown; `b` cycles through the modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DisplayMode {
+    /// Text, with bytes that aren't valid UTF-8 escaped as `\xNN`
+    Utf8,
+    /// `0x` and two digits per byte, the form the input popups accept
+    Hex,
+    /// Standard Base64 with padding
+    Base64,
+}
+
+impl DisplayMode {
+    fn next(self) -> Self {
+        match self {
+            DisplayMode::Utf8 => DisplayMode::Hex,
+            DisplayMode::Hex => DisplayMode::Base64,
+            DisplayMode::Base64 => DisplayMode::Utf8,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            DisplayMode::Utf8 => "UTF-8",
+            DisplayMode::Hex => "HEX",
+            DisplayMode::Base64 => "BASE64",
+        }
+    }
+}
+
 #[derive(PartialEq)]
 enum InputMode {
     Normal,
@@ -90,14 +160,42 @@ enum MessageType {
     Error,
 }
 
-impl App {
-    fn new() -> io::Result<Self> {
-        // Clean up for fresh start
-        let _ = std::fs::remove_dir_all("./lsm_cli_data");
+/// Directory the TUI keeps its tree in (wiped on start and exit)
+const TUI_DATA_DIR: &str = "./lsm_cli_data";
+
+/// SSTables probed per get (on average) at which the dashboard asks for
+/// compaction
+const TUI_READ_AMP_WARN_THRESHOLD: f64 = 8.0;
+
+/// Ticks of disk throughput the dashboard keeps (a tick is 100 ms)
+const IO_HISTORY_LEN: usize = 120;
+
+/// Demo steps that each put `DEMO_BURST_PUTS` keys at once, so the disk
+/// rates spike as the memtable flushes over and over
+const DEMO_BURST_STEPS: usize = 3;
+
+/// Puts per burst step of the demo
+const DEMO_BURST_PUTS: usize = 100;
+
+/// Age after which the TUI's entries expire
+const TUI_TTL: Duration = Duration::from_secs(60 * 60);
 
-        let lsm = LSMTree::new(PathBuf::from("./lsm_cli_data"), 200)?;
+/// How far `t` moves the clock forward
+const TUI_TIME_STEP: Duration = Duration::from_secs(10 * 60);
 
-        Ok(Self {
+/// How far past the TTL the TTL demo moves the clock, so the writes it
+/// means to expire are clearly older than it
+const TTL_DEMO_MARGIN: Duration = Duration::from_secs(60);
+
+/// Time between two steps of the TTL demo, to read the narration
+const TTL_DEMO_STEP_INTERVAL: Duration = Duration::from_millis(1500);
+
+/// Minimum time between two redraws of the loading screen
+const LOADING_REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+impl App {
+    fn new(lsm: LSMTree, clock: DemoClock) -> Self {
+        Self {
             lsm,
             current_tab: 0,
             input_mode: InputMode::Normal,
@@ -106,16 +204,29 @@ impl App {
             search_input: String::new(),
             search_result: None,
             messages: Vec::new(),
-            selected_sstable: 0,
+            selected_sstable: None,
             sstable_scroll: 0,
             memtable_scroll: 0,
             operation_history: Vec::new(),
             should_quit: false,
             show_help: false,
+            display_mode: DisplayMode::Utf8,
+            entry_popup: None,
             auto_demo: false,
             demo_step: 0,
             last_demo_time: Instant::now(),
-        })
+            clock,
+            ttl_demo: None,
+            io_history: Vec::new(),
+        }
+    }
+
+    /// Adds the tree's current disk throughput to the dashboard history
+    fn sample_io_rates(&mut self) {
+        self.io_history.push(self.lsm.io_rates());
+        if self.io_history.len() > IO_HISTORY_LEN {
+            self.io_history.remove(0);
+        }
     }
 
     fn add_message(&mut self, msg: String, msg_type: MessageType) {
@@ -126,13 +237,76 @@ impl App {
         }
     }
 
-    fn put(&mut self, key: String, value: String) {
-        match self
-            .lsm
-            .put(key.as_bytes().to_vec(), value.as_bytes().to_vec())
+    /// Moves the SSTable selection `step` places in read order (newest
+    /// first), starting from the newest table if nothing live is selected
+    fn move_sstable_selection(&mut self, step: isize) {
+        let ids: Vec<SSTableId> = self.lsm.sstables().map(|t| t.id()).collect();
+        let next = match self
+            .selected_sstable
+            .and_then(|id| ids.iter().position(|&i| i == id))
+        {
+            Some(pos) => pos
+                .saturating_add_signed(step)
+                .min(ids.len().saturating_sub(1)),
+            None => 0,
+        };
+        if let Some(&id) = ids.get(next)
+            && self.selected_sstable != Some(id)
         {
+            self.selected_sstable = Some(id);
+            self.sstable_scroll = 0;
+        }
+    }
+
+    /// Points the selection at a live SSTable, saying so if the one that
+    /// was selected has been compacted away
+    fn resolve_sstable_selection(&mut self) {
+        if let Some(id) = self.selected_sstable
+            && self.lsm.sstable(id).is_none()
+        {
+            self.add_message(
+                format!("SSTable {} is gone; showing the newest table", id),
+                MessageType::Warning,
+            );
+            self.selected_sstable = None;
+        }
+        if self.selected_sstable.is_none() {
+            self.move_sstable_selection(0);
+        }
+    }
+
+    /// Renders bytes for a one-line row in the current display mode
+    fn short(&self, bytes: &[u8]) -> String {
+        format_bytes_short(bytes, self.display_mode)
+    }
+
+    /// Opens the popup for the entry at the top of the MemTable or
+    /// SSTable view
+    fn open_entry_popup(&mut self) {
+        let entry = match self.current_tab {
+            1 => self
+                .lsm
+                .memtable_page(self.memtable_scroll, 1)
+                .into_iter()
+                .next(),
+            2 => {
+                self.resolve_sstable_selection();
+                self.selected_sstable
+                    .and_then(|id| self.lsm.sstable(id)?.live_entries())
+                    .and_then(|entries| entries.into_iter().nth(self.sstable_scroll))
+            }
+            _ => None,
+        };
+        self.entry_popup = entry;
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        match self.lsm.put(key.clone(), value.clone()) {
             Ok(_) => {
-                self.add_message(format!("PUT {} = {}", key, value), MessageType::Success);
+                self.add_message(
+                    format!("PUT {} = {}", self.short(&key), self.short(&value)),
+                    MessageType::Success,
+                );
                 self.operation_history.push(Operation::Put(key, value));
             }
             Err(e) => {
@@ -141,13 +315,12 @@ impl App {
         }
     }
 
-    fn get(&mut self, key: &str) -> Option<String> {
-        let result = self.lsm.get(key.as_bytes());
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let result = self.lsm.get(key);
         let found = result.is_some();
         self.operation_history
-            .push(Operation::Get(key.to_string(), found));
-
-        result.map(|v| String::from_utf8_lossy(&v).to_string())
+            .push(Operation::Get(key.to_vec(), found));
+        result
     }
 
     fn run_demo_step(&mut self) {
@@ -166,7 +339,7 @@ impl App {
 
         if self.demo_step < demo_keys.len() {
             let (key, value) = demo_keys[self.demo_step];
-            self.put(key.to_string(), value.to_string());
+            self.put(key.as_bytes().to_vec(), value.as_bytes().to_vec());
             self.demo_step += 1;
         } else if self.demo_step < demo_keys.len() + 5 {
             // Search for some keys
@@ -179,20 +352,658 @@ impl App {
             ];
             let idx = self.demo_step - demo_keys.len();
             let key = search_keys[idx];
-            let result = self.get(key);
+            let result = self.get(key.as_bytes());
             match result {
-                Some(v) => self.add_message(format!("GET {} = {}", key, v), MessageType::Info),
+                Some(v) => self.add_message(
+                    format!("GET {} = {}", key, self.short(&v)),
+                    MessageType::Info,
+                ),
                 None => self.add_message(format!("GET {} = NOT FOUND", key), MessageType::Warning),
             }
             self.demo_step += 1;
+        } else if self.demo_step < demo_keys.len() + 5 + DEMO_BURST_STEPS {
+            // A burst of writes: watch the WAL and SSTable write rates
+            let burst = self.demo_step - demo_keys.len() - 5;
+            let before = self.lsm.sstable_count();
+            for i in 0..DEMO_BURST_PUTS {
+                let key = format!("burst:{:04}", burst * DEMO_BURST_PUTS + i);
+                if let Err(e) = self
+                    .lsm
+                    .put(key.into_bytes(), format!("value {}", i).into_bytes())
+                {
+                    self.add_message(format!("Error: {}", e), MessageType::Error);
+                    break;
+                }
+            }
+            let flushed = self.lsm.sstable_count().saturating_sub(before);
+            self.add_message(
+                format!(
+                    "BURST {} puts, {} flushes - see Disk I/O",
+                    DEMO_BURST_PUTS, flushed
+                ),
+                MessageType::Info,
+            );
+            self.demo_step += 1;
+        } else if self.demo_step == demo_keys.len() + 5 + DEMO_BURST_STEPS {
+            // Compacting the burst's tables reads them all back
+            match self.lsm.purge() {
+                Ok(result) => self.add_message(
+                    format!(
+                        "COMPACT {} SSTables -> 1 ({} bytes read)",
+                        result.input_tables, result.bytes_read
+                    ),
+                    MessageType::Success,
+                ),
+                Err(e) => self.add_message(format!("Error: {}", e), MessageType::Error),
+            }
+            self.demo_step += 1;
         } else {
             self.auto_demo = false;
             self.add_message("Demo complete!".to_string(), MessageType::Success);
         }
     }
+
+    fn run_ttl_demo_step(&mut self) {
+        let Some(op) = self.ttl_demo.as_mut().and_then(TtlDemo::next_op) else {
+            self.ttl_demo = None;
+            self.add_message("TTL demo complete!".to_string(), MessageType::Success);
+            return;
+        };
+        let (msg, msg_type) = apply_demo_op(&mut self.lsm, &self.clock, &op);
+        self.add_message(msg, msg_type);
+    }
+
+    /// Moves the clock forward a step and runs maintenance, which deletes
+    /// SSTables that have expired entirely
+    fn advance_clock(&mut self) {
+        for op in [
+            DemoOp::AdvanceClock(TUI_TIME_STEP),
+            DemoOp::ReportExpiry,
+            DemoOp::MaintenanceTick,
+        ] {
+            let (msg, msg_type) = apply_demo_op(&mut self.lsm, &self.clock, &op);
+            self.add_message(msg, msg_type);
+        }
+    }
+}
+
+/// Clock of the TUI's tree: the system time, moved forward by `t` and by
+/// the TTL demo so expiry can be watched without waiting for it
+#[derive(Debug, Clone, Default)]
+struct DemoClock {
+    offset_millis: Arc<AtomicU64>,
+}
+
+impl DemoClock {
+    fn advance(&self, by: Duration) {
+        self.offset_millis
+            .fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// How far the clock is ahead of the system time
+    fn offset(&self) -> Duration {
+        Duration::from_millis(self.offset_millis.load(Ordering::Relaxed))
+    }
+}
+
+impl Clock for DemoClock {
+    fn now_millis(&self) -> u64 {
+        SystemClock.now_millis() + self.offset_millis.load(Ordering::Relaxed)
+    }
+}
+
+/// `+1h 05m`, how far `DemoClock` is ahead
+fn format_clock_offset(offset: Duration) -> String {
+    let minutes = offset.as_secs() / 60;
+    match minutes / 60 {
+        0 => format!("+{}m", minutes),
+        hours => format!("+{}h {:02}m", hours, minutes % 60),
+    }
+}
+
+/// One thing the TTL demo does to the tree or its clock
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DemoOp {
+    /// Only a line in the message log
+    Narrate(&'static str),
+    Put(&'static str, &'static str),
+    Get(&'static str),
+    Flush,
+    AdvanceClock(Duration),
+    /// How many writes have expired, and which tables entirely
+    ReportExpiry,
+    MaintenanceTick,
+    Compact,
+    ReportDiskUsage,
+}
+
+/// The TTL demo (`D`): writes, moves the clock past the TTL, and shows
+/// maintenance deleting a fully expired SSTable and a compaction dropping
+/// the expired entries of another, with the disk usage after each
+struct TtlDemo {
+    ops: Vec<DemoOp>,
+    next: usize,
+}
+
+impl TtlDemo {
+    /// The script for a tree whose entries expire after `ttl`
+    fn new(ttl: Duration) -> Self {
+        let half = ttl / 2;
+        let ops = vec![
+            DemoOp::Narrate("TTL demo: every write expires once it is older than the TTL"),
+            DemoOp::Put("session:alice", "token-a1"),
+            DemoOp::Put("session:bob", "token-b2"),
+            DemoOp::Put("session:carol", "token-c3"),
+            DemoOp::Flush,
+            DemoOp::AdvanceClock(half),
+            DemoOp::Put("cache:home", "<html>home</html>"),
+            DemoOp::Put("cache:search", "<html>results</html>"),
+            DemoOp::ReportDiskUsage,
+            DemoOp::AdvanceClock(half + TTL_DEMO_MARGIN),
+            DemoOp::Narrate("The sessions are now older than the TTL"),
+            DemoOp::Put("user:dave", "Dave Jones"),
+            DemoOp::Flush,
+            DemoOp::ReportExpiry,
+            DemoOp::Narrate("Reads don't check the TTL: expired entries stay until reclaimed"),
+            DemoOp::Get("session:alice"),
+            DemoOp::MaintenanceTick,
+            DemoOp::ReportDiskUsage,
+            DemoOp::AdvanceClock(half),
+            DemoOp::ReportExpiry,
+            DemoOp::Narrate(
+                "The cache entries share a table with a live key: only a compaction drops them",
+            ),
+            DemoOp::MaintenanceTick,
+            DemoOp::Compact,
+            DemoOp::ReportDiskUsage,
+            DemoOp::Get("cache:home"),
+            DemoOp::Get("user:dave"),
+        ];
+        Self { ops, next: 0 }
+    }
+
+    /// The next operation, or `None` once the script is done
+    fn next_op(&mut self) -> Option<DemoOp> {
+        let op = self.ops.get(self.next)?.clone();
+        self.next += 1;
+        Some(op)
+    }
+}
+
+/// Applies one demo operation and describes what happened
+fn apply_demo_op(lsm: &mut LSMTree, clock: &DemoClock, op: &DemoOp) -> (String, MessageType) {
+    let result = match op {
+        DemoOp::Narrate(text) => Ok((text.to_string(), MessageType::Info)),
+        DemoOp::Put(key, value) => lsm
+            .put(key.as_bytes().to_vec(), value.as_bytes().to_vec())
+            .map(|()| (format!("PUT {} = {}", key, value), MessageType::Success)),
+        DemoOp::Get(key) => Ok(match lsm.get(key.as_bytes()) {
+            Some(value) => (
+                format!("GET {} = {}", key, String::from_utf8_lossy(&value)),
+                MessageType::Info,
+            ),
+            None => (format!("GET {} = NOT FOUND", key), MessageType::Warning),
+        }),
+        DemoOp::Flush => lsm.flush().map(|()| {
+            (
+                format!("FLUSH -> {} SSTables", lsm.sstable_count()),
+                MessageType::Success,
+            )
+        }),
+        DemoOp::AdvanceClock(by) => {
+            clock.advance(*by);
+            Ok((
+                format!(
+                    "CLOCK {} (now {})",
+                    format_clock_offset(*by),
+                    format_clock_offset(clock.offset())
+                ),
+                MessageType::Info,
+            ))
+        }
+        DemoOp::ReportExpiry => {
+            let estimate = lsm.expired_estimate();
+            Ok((
+                format!(
+                    "EXPIRED {} of {} writes, {} SSTables entirely",
+                    estimate.expired_writes,
+                    estimate.timed_writes,
+                    estimate.fully_expired.len()
+                ),
+                MessageType::Warning,
+            ))
+        }
+        DemoOp::MaintenanceTick => {
+            let before = lsm.sstable_count();
+            lsm.maintenance_tick().map(|_| {
+                let deleted = before.saturating_sub(lsm.sstable_count());
+                match deleted {
+                    0 => (
+                        "MAINTENANCE nothing to delete".to_string(),
+                        MessageType::Info,
+                    ),
+                    n => (
+                        format!("MAINTENANCE deleted {} expired SSTables", n),
+                        MessageType::Success,
+                    ),
+                }
+            })
+        }
+        DemoOp::Compact => lsm.purge().map(|result| {
+            (
+                format!(
+                    "COMPACT {} SSTables -> {} live entries ({} -> {} bytes)",
+                    result.input_tables,
+                    result.entries_written,
+                    result.bytes_read,
+                    result.bytes_written
+                ),
+                MessageType::Success,
+            )
+        }),
+        DemoOp::ReportDiskUsage => lsm.disk_usage().map(|usage| {
+            (
+                format!(
+                    "DISK {} bytes ({} in SSTables)",
+                    usage.total, usage.sstable_bytes
+                ),
+                MessageType::Info,
+            )
+        }),
+    };
+    result.unwrap_or_else(|e| (format!("Error: {}", e), MessageType::Error))
+}
+
+/// Default memtable threshold used when a subcommand opens a tree
+const COMMAND_MEMTABLE_THRESHOLD: usize = 4 * 1024 * 1024;
+
+/// Entries `stats --advise` samples from the stored data
+const ADVISE_SAMPLE_ENTRIES: usize = 10_000;
+
+/// Returns the value following `flag` in the argument list
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|a| a == flag)
+}
+
+/// Config for a subcommand that changes the tree
+fn writable_config(dir: &str) -> LSMConfig {
+    LSMConfig {
+        memtable_size_threshold: COMMAND_MEMTABLE_THRESHOLD,
+        ..LSMConfig::new(PathBuf::from(dir))
+    }
+}
+
+/// Opens the tree for a subcommand, printing what recovery did to stderr
+/// with `--verbose`
+fn open_tree(config: LSMConfig, args: &[String]) -> io::Result<LSMTree> {
+    let (lsm, report) = LSMTree::open_with_report(config)?;
+    if has_flag(args, "--verbose") {
+        eprint!("{}", report);
+    }
+    Ok(lsm)
+}
+
+/// A token Ctrl-C cancels, for commands that can stop part way and leave
+/// the tree as it was; a second Ctrl-C exits at once
+fn ctrl_c_token() -> io::Result<CancellationToken> {
+    let token = CancellationToken::new();
+    #[cfg(unix)]
+    {
+        use signal_hook::{consts::SIGINT, flag};
+        flag::register_conditional_shutdown(SIGINT, 130, token.as_flag())?;
+        flag::register(SIGINT, token.as_flag())?;
+    }
+    Ok(token)
+}
+
+fn print_usage() {
+    eprintln!("Usage:");
+    eprintln!("  lsm-cli                        Start the interactive TUI");
+    eprintln!("  lsm-cli migrate --dir <path>   Rewrite legacy SSTables into the current format");
+    eprintln!("  lsm-cli repair-ordering --dir <path>");
+    eprintln!("                                 Reorder legacy SSTables by modification time");
+    eprintln!(
+        "  lsm-cli repair --dir <path>    Rebuild a lost or damaged manifest (Ctrl-C stops it)"
+    );
+    eprintln!("  lsm-cli compact --dir <path>   Merge every SSTable into one (Ctrl-C stops it)");
+    eprintln!("  lsm-cli migrate-cold --dir <path> --cold-dir <path> [--older-than <secs>]");
+    eprintln!(
+        "                                 Move compacted (or old) SSTables to the cold directory"
+    );
+    eprintln!("  lsm-cli import-legacy --from <path> --dir <path>");
+    eprintln!("                                 Convert an old demo directory into a new tree");
+    eprintln!("  lsm-cli apply-wal --file <path> --dir <path>");
+    eprintln!("                                 Replay a salvaged WAL file into a tree");
+    eprintln!("  lsm-cli snapshot export --dir <path> --file <path>");
+    eprintln!(
+        "                                 Write the live data to a portable snapshot (Ctrl-C stops it)"
+    );
+    eprintln!("  lsm-cli snapshot import --file <path> --dir <path>");
+    eprintln!("                                 Load a snapshot into a tree");
+    eprintln!("  lsm-cli dump --file <path> [--verify]");
+    eprintln!("                                 Print the metadata and records of one SSTable");
+    eprintln!("  lsm-cli metrics --dir <path>   Print the tree's metrics in Prometheus format");
+    eprintln!("  lsm-cli stats --dir <path> --bloom [--json]");
+    eprintln!("                                 Print Bloom filter statistics");
+    eprintln!("  lsm-cli stats --dir <path> --advise");
+    eprintln!("                                 Suggest settings for the stored data");
+    eprintln!("  lsm-cli stats --dir <path> --prefixes");
+    eprintln!("        [--delimiter <c>] [--depth <n>] [--sample <rate>]");
+    eprintln!("                                 Count keys per key prefix (default ':', depth 1)");
+    eprintln!("  lsm-cli stats --dir <path> --disk");
+    eprintln!("                                 Show bytes on disk per kind of file");
+    eprintln!();
+    eprintln!("Commands that open the tree print what recovery did with --verbose.");
+}
+
+/// Runs a non-interactive subcommand
+fn run_command(args: &[String]) -> io::Result<()> {
+    let invalid = |msg: &str| {
+        print_usage();
+        io::Error::new(io::ErrorKind::InvalidInput, msg.to_string())
+    };
+
+    match args[0].as_str() {
+        "migrate" => {
+            let dir = flag_value(args, "--dir").ok_or_else(|| invalid("missing --dir"))?;
+            let mut lsm = open_tree(writable_config(dir), args)?;
+            let legacy = lsm.legacy_sstable_count();
+            let migrated = lsm.migrate_format()?;
+            println!(
+                "Migrated {} of {} legacy SSTables in {} ({} SSTables total)",
+                migrated,
+                legacy,
+                dir,
+                lsm.sstable_count()
+            );
+            Ok(())
+        }
+        "repair-ordering" => {
+            let dir = flag_value(args, "--dir").ok_or_else(|| invalid("missing --dir"))?;
+            let mut lsm = open_tree(writable_config(dir), args)?;
+            for anomaly in lsm.ordering_anomalies() {
+                println!(
+                    "{} is read before {}, but was modified earlier",
+                    anomaly.newer.display(),
+                    anomaly.older.display()
+                );
+            }
+            let changes = lsm.repair_ordering()?;
+            if changes.is_empty() {
+                println!("SSTable order in {} is consistent; nothing to do", dir);
+            }
+            for change in &changes {
+                println!(
+                    "{} -> {} (generation {} -> {})",
+                    change.old_path.display(),
+                    change.new_path.display(),
+                    change.old_generation,
+                    change.new_generation
+                );
+            }
+            Ok(())
+        }
+        "compact" => {
+            let dir = flag_value(args, "--dir").ok_or_else(|| invalid("missing --dir"))?;
+            let mut lsm = open_tree(writable_config(dir), args)?;
+            let cancel = ctrl_c_token()?;
+            match lsm.purge_with(&cancel) {
+                Ok(result) => println!(
+                    "Merged {} SSTables ({} bytes) into {} entries ({} bytes)",
+                    result.input_tables,
+                    result.bytes_read,
+                    result.entries_written,
+                    result.bytes_written
+                ),
+                Err(e) => {
+                    let Some(cancelled) = Cancelled::from_io(&e) else {
+                        return Err(e);
+                    };
+                    println!(
+                        "Cancelled after {} entries; the SSTables in {} are as they were",
+                        cancelled.entries, dir
+                    );
+                }
+            }
+            Ok(())
+        }
+        "repair" => {
+            let dir = flag_value(args, "--dir").ok_or_else(|| invalid("missing --dir"))?;
+            let cancel = ctrl_c_token()?;
+            let report =
+                match LSMTree::repair_with(Path::new(dir), &RepairOptions::default(), &cancel) {
+                    Ok(report) => report,
+                    Err(e) => {
+                        let Some(cancelled) = Cancelled::from_io(&e) else {
+                            return Err(e);
+                        };
+                        println!(
+                            "Cancelled after {} records; no manifest was written in {}",
+                            cancelled.entries, dir
+                        );
+                        return Ok(());
+                    }
+                };
+            for damaged in &report.quarantined {
+                println!(
+                    "Quarantined {}: {}",
+                    damaged.original_path.display(),
+                    damaged.reason
+                );
+            }
+            for id in &report.rebuilt_filters {
+                println!("Rebuilt Bloom filter of sstable_{}.db", id);
+            }
+            if !report.ordered_by_mtime.is_empty() {
+                println!(
+                    "{} SSTables without sequence numbers ordered by modification time",
+                    report.ordered_by_mtime.len()
+                );
+            }
+            if let Some(previous) = &report.previous_manifest {
+                println!("Previous manifest kept as {}", previous.display());
+            }
+            println!(
+                "Wrote a manifest listing {} SSTables in {}",
+                report.tables.len(),
+                dir
+            );
+            Ok(())
+        }
+        "migrate-cold" => {
+            let dir = flag_value(args, "--dir").ok_or_else(|| invalid("missing --dir"))?;
+            let cold_dir =
+                flag_value(args, "--cold-dir").ok_or_else(|| invalid("missing --cold-dir"))?;
+            let cold_policy = match flag_value(args, "--older-than") {
+                Some(secs) => ColdPolicy::OlderThan(Duration::from_secs(
+                    secs.parse().map_err(|_| invalid("invalid --older-than"))?,
+                )),
+                None => ColdPolicy::Compacted,
+            };
+            let config = LSMConfig {
+                cold_dir: Some(PathBuf::from(cold_dir)),
+                cold_policy,
+                ..writable_config(dir)
+            };
+            let mut lsm = open_tree(config, args)?;
+            let moved = lsm.migrate_cold()?;
+            for id in &moved {
+                println!("Moved sstable_{}.db to {}", id, cold_dir);
+            }
+            println!("Moved {} SSTables", moved.len());
+            Ok(())
+        }
+        "import-legacy" => {
+            let src = flag_value(args, "--from").ok_or_else(|| invalid("missing --from"))?;
+            let dir = flag_value(args, "--dir").ok_or_else(|| invalid("missing --dir"))?;
+            let report = LSMTree::import_legacy_dir(Path::new(src), Path::new(dir))?;
+            print!("{}", report);
+            println!("Wrote the new tree to {}", dir);
+            Ok(())
+        }
+        "apply-wal" => {
+            let file = flag_value(args, "--file").ok_or_else(|| invalid("missing --file"))?;
+            let dir = flag_value(args, "--dir").ok_or_else(|| invalid("missing --dir"))?;
+            let mut lsm = open_tree(writable_config(dir), args)?;
+            print!("{}", lsm.apply_wal_file(Path::new(file))?);
+            Ok(())
+        }
+        "snapshot" => {
+            let file = flag_value(args, "--file").ok_or_else(|| invalid("missing --file"))?;
+            let dir = flag_value(args, "--dir").ok_or_else(|| invalid("missing --dir"))?;
+            let info = match args.get(1).map(String::as_str) {
+                Some("export") => {
+                    let config = LSMConfig {
+                        read_only: true,
+                        ..LSMConfig::new(PathBuf::from(dir))
+                    };
+                    let lsm = open_tree(config, args)?;
+                    let writer = io::BufWriter::new(std::fs::File::create(file)?);
+                    let cancel = ctrl_c_token()?;
+                    match lsm.export_snapshot_with(writer, &cancel) {
+                        Ok(info) => info,
+                        Err(e) => {
+                            let Some(cancelled) = Cancelled::from_io(&e) else {
+                                return Err(e);
+                            };
+                            std::fs::remove_file(file)?;
+                            println!(
+                                "Cancelled after {} entries; {} was removed",
+                                cancelled.entries, file
+                            );
+                            return Ok(());
+                        }
+                    }
+                }
+                Some("import") => {
+                    let mut lsm = open_tree(writable_config(dir), args)?;
+                    let reader = io::BufReader::new(std::fs::File::open(file)?);
+                    lsm.import_snapshot(reader)?
+                }
+                _ => return Err(invalid("snapshot takes export or import")),
+            };
+            println!("Snapshot {}: {}", file, info);
+            Ok(())
+        }
+        "dump" => {
+            let file = flag_value(args, "--file").ok_or_else(|| invalid("missing --file"))?;
+            let options = SSTableReaderOptions {
+                verify_checksums: has_flag(args, "--verify"),
+                ..SSTableReaderOptions::default()
+            };
+            let mut reader = SSTableReader::open_with_options(Path::new(file), &options)?;
+            let metadata = reader.metadata()?;
+            println!(
+                "format version {}, {} bytes, {} records ({} tombstones, {} older versions)",
+                metadata.format_version,
+                metadata.file_bytes,
+                metadata.summary.entries,
+                metadata.summary.tombstones,
+                metadata.summary.shadowed
+            );
+            if let Some(tree_id) = metadata.tree_id {
+                println!("written by tree {}", tree_id);
+            }
+            for record in reader.iter() {
+                let (key, seq, value) = record?;
+                match value {
+                    Some(value) => {
+                        println!("{}\t@{}\t{}", key.escape_ascii(), seq, value.escape_ascii())
+                    }
+                    None => println!("{}\t@{}\t<deleted>", key.escape_ascii(), seq),
+                }
+            }
+            Ok(())
+        }
+        "metrics" => {
+            let dir = flag_value(args, "--dir").ok_or_else(|| invalid("missing --dir"))?;
+            let config = LSMConfig {
+                read_only: true,
+                ..LSMConfig::new(PathBuf::from(dir))
+            };
+            print!("{}", open_tree(config, args)?.prometheus_metrics());
+            Ok(())
+        }
+        "stats" => {
+            let dir = flag_value(args, "--dir").ok_or_else(|| invalid("missing --dir"))?;
+            let advise = has_flag(args, "--advise");
+            let prefixes = has_flag(args, "--prefixes");
+            let disk = has_flag(args, "--disk");
+            if !has_flag(args, "--bloom") && !advise && !prefixes && !disk {
+                return Err(invalid("missing --bloom, --advise, --prefixes or --disk"));
+            }
+            let config = LSMConfig {
+                read_only: true,
+                ..LSMConfig::new(PathBuf::from(dir))
+            };
+            let lsm = open_tree(config, args)?;
+            if disk {
+                print!("{}", lsm.disk_usage()?);
+                return Ok(());
+            }
+            if advise {
+                // A fresh process has seen no workload; describe the stored
+                // data instead (reads stay unknown)
+                let mut input = lsm.advisor_input();
+                for (key, value) in lsm.range::<&[u8], _>(..).take(ADVISE_SAMPLE_ENTRIES) {
+                    input.workload.record_entry(&key, Some(&value));
+                }
+                print!("{}", advisor::advise(&input));
+                return Ok(());
+            }
+            if prefixes {
+                let delimiter = match flag_value(args, "--delimiter") {
+                    Some(d) if d.len() == 1 => d.as_bytes()[0],
+                    Some(_) => return Err(invalid("--delimiter takes a single byte")),
+                    None => b':',
+                };
+                let depth = match flag_value(args, "--depth") {
+                    Some(n) => n.parse().map_err(|_| invalid("invalid --depth"))?,
+                    None => 1,
+                };
+                let rate = match flag_value(args, "--sample") {
+                    Some(r) => r.parse().map_err(|_| invalid("invalid --sample"))?,
+                    None => 1.0,
+                };
+                print!("{}", lsm.prefix_stats_sampled(delimiter, depth, rate)?);
+                return Ok(());
+            }
+            let stats = lsm.bloom_filter_stats();
+            if has_flag(args, "--json") {
+                println!("{}", stats.to_json());
+            } else {
+                print!("{}", stats);
+                for (id, stat) in stats.sstable_ids.iter().zip(&stats.individual_stats) {
+                    println!("  sstable_{}.db: {}", id, stat);
+                }
+            }
+            Ok(())
+        }
+        "help" | "--help" | "-h" => {
+            print_usage();
+            Ok(())
+        }
+        other => Err(invalid(&format!("unknown command: {}", other))),
+    }
 }
 
 fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = run_command(&args) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -200,8 +1011,19 @@ fn main() -> io::Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
+    // Clean up for fresh start
+    let _ = std::fs::remove_dir_all(TUI_DATA_DIR);
+
     // Create app
-    let mut app = App::new()?;
+    let clock = DemoClock::default();
+    let config = LSMConfig {
+        memtable_size_threshold: 200,
+        read_amp_warn_threshold: Some(TUI_READ_AMP_WARN_THRESHOLD),
+        ttl: Some(TUI_TTL),
+        clock: Arc::new(clock.clone()),
+        ..LSMConfig::new(PathBuf::from(TUI_DATA_DIR))
+    };
+    let mut app = App::new(open_with_loading_screen(&mut terminal, config)?, clock);
 
     // Initial welcome message
     app.add_message(
@@ -232,6 +1054,12 @@ fn main() -> io::Result<()> {
                 app.run_demo_step();
                 app.last_demo_time = Instant::now();
             }
+            if app.ttl_demo.is_some() && app.last_demo_time.elapsed() >= TTL_DEMO_STEP_INTERVAL {
+                app.run_ttl_demo_step();
+                app.last_demo_time = Instant::now();
+            }
+
+            app.sample_io_rates();
 
             // Clean old messages (older than 10 seconds)
             let now = Instant::now();
@@ -256,11 +1084,97 @@ fn main() -> io::Result<()> {
     terminal.show_cursor()?;
 
     // Cleanup
-    let _ = std::fs::remove_dir_all("./lsm_cli_data");
+    let _ = std::fs::remove_dir_all(TUI_DATA_DIR);
 
     Ok(())
 }
 
+/// Opens the tree while drawing a loading screen with its progress
+fn open_with_loading_screen(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    config: LSMConfig,
+) -> io::Result<LSMTree> {
+    let mut last_draw: Option<(OpenPhase, Instant)> = None;
+    LSMTree::open_with_progress(config, |progress| {
+        // Redrawing on every event would slow the open down
+        let due = match last_draw {
+            Some((phase, at)) => {
+                phase != progress.phase
+                    || progress.done == progress.total
+                    || at.elapsed() >= LOADING_REDRAW_INTERVAL
+            }
+            None => true,
+        };
+        if due {
+            // A failed redraw only loses a frame; the open goes on
+            let _ = terminal.draw(|f| render_loading_screen(f, &progress));
+            last_draw = Some((progress.phase, Instant::now()));
+        }
+    })
+}
+
+fn render_loading_screen(f: &mut Frame, progress: &OpenProgress) {
+    let area = centered_rect(60, 30, f.area());
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan))
+        .title(" Opening LSM Tree ")
+        .title_style(Style::default().fg(Color::Cyan).bold());
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(0),
+        ])
+        .split(inner);
+
+    let step = match progress.phase {
+        OpenPhase::ScanningDirectory => 1,
+        OpenPhase::LoadingFilters => 2,
+        OpenPhase::ReplayingWal => 3,
+    };
+    let title = Paragraph::new(Line::from(vec![
+        Span::styled(
+            format!("Step {}/3: ", step),
+            Style::default().fg(Color::DarkGray),
+        ),
+        Span::styled(
+            progress.phase.label(),
+            Style::default().fg(Color::Yellow).bold(),
+        ),
+    ]));
+    f.render_widget(title, chunks[0]);
+
+    let ratio = progress.ratio().clamp(0.0, 1.0);
+    let gauge = Gauge::default()
+        .gauge_style(Style::default().fg(Color::Green).bg(Color::DarkGray))
+        .ratio(ratio)
+        .label(format!("{:.0}%", ratio * 100.0));
+    f.render_widget(gauge, chunks[1]);
+
+    let unit = match progress.phase {
+        OpenPhase::ReplayingWal => "entries",
+        _ => "SSTables",
+    };
+    let detail = Paragraph::new(format!(
+        "{} / {} {}, {} / {} KB",
+        progress.done,
+        progress.total,
+        unit,
+        progress.bytes_done / 1024,
+        progress.bytes_total / 1024
+    ))
+    .style(Style::default().fg(Color::White))
+    .alignment(Alignment::Center);
+    f.render_widget(detail, chunks[2]);
+}
+
 fn handle_input(app: &mut App, key: KeyCode, modifiers: KeyModifiers) {
     // Handle help popup
     if app.show_help {
@@ -270,6 +1184,16 @@ fn handle_input(app: &mut App, key: KeyCode, modifiers: KeyModifiers) {
         return;
     }
 
+    // Handle the full entry popup
+    if app.entry_popup.is_some() {
+        match key {
+            KeyCode::Char('b') => app.display_mode = app.display_mode.next(),
+            KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => app.entry_popup = None,
+            _ => {}
+        }
+        return;
+    }
+
     match app.input_mode {
         InputMode::Normal => match key {
             KeyCode::Char('q') => app.should_quit = true,
@@ -278,8 +1202,9 @@ fn handle_input(app: &mut App, key: KeyCode, modifiers: KeyModifiers) {
             KeyCode::Char('2') => app.current_tab = 1,
             KeyCode::Char('3') => app.current_tab = 2,
             KeyCode::Char('4') => app.current_tab = 3,
-            KeyCode::Tab => app.current_tab = (app.current_tab + 1) % 4,
-            KeyCode::BackTab => app.current_tab = (app.current_tab + 3) % 4,
+            KeyCode::Char('5') => app.current_tab = 4,
+            KeyCode::Tab => app.current_tab = (app.current_tab + 1) % 5,
+            KeyCode::BackTab => app.current_tab = (app.current_tab + 4) % 5,
             KeyCode::Char('p') | KeyCode::Char('i') => {
                 app.input_mode = InputMode::EnteringKey;
                 app.key_input.clear();
@@ -301,9 +1226,17 @@ fn handle_input(app: &mut App, key: KeyCode, modifiers: KeyModifiers) {
                     app.operation_history.push(Operation::Flush);
                 }
             }
+            KeyCode::Char('b') => {
+                app.display_mode = app.display_mode.next();
+                app.add_message(
+                    format!("Showing keys and values as {}", app.display_mode.label()),
+                    MessageType::Info,
+                );
+            }
+            KeyCode::Enter => app.open_entry_popup(),
             KeyCode::Char('r') => {
-                app.lsm.reset_bloom_filter_stats();
-                app.add_message("Reset Bloom filter stats".to_string(), MessageType::Info);
+                app.lsm.reset_read_stats();
+                app.add_message("Reset read stats".to_string(), MessageType::Info);
             }
             KeyCode::Char('d') => {
                 app.auto_demo = !app.auto_demo;
@@ -314,15 +1247,21 @@ fn handle_input(app: &mut App, key: KeyCode, modifiers: KeyModifiers) {
                     app.add_message("Demo paused".to_string(), MessageType::Info);
                 }
             }
+            KeyCode::Char('D') => {
+                if app.ttl_demo.take().is_some() {
+                    app.add_message("TTL demo stopped".to_string(), MessageType::Info);
+                } else {
+                    app.ttl_demo = Some(TtlDemo::new(TUI_TTL));
+                    app.add_message("Starting TTL demo...".to_string(), MessageType::Info);
+                }
+            }
+            KeyCode::Char('t') => app.advance_clock(),
             KeyCode::Up | KeyCode::Char('k') => {
                 if app.current_tab == 1 && app.memtable_scroll > 0 {
                     app.memtable_scroll -= 1;
                 } else if app.current_tab == 2 {
                     if modifiers.contains(KeyModifiers::SHIFT) {
-                        if app.selected_sstable > 0 {
-                            app.selected_sstable -= 1;
-                            app.sstable_scroll = 0;
-                        }
+                        app.move_sstable_selection(-1);
                     } else if app.sstable_scroll > 0 {
                         app.sstable_scroll -= 1;
                     }
@@ -330,38 +1269,27 @@ fn handle_input(app: &mut App, key: KeyCode, modifiers: KeyModifiers) {
             }
             KeyCode::Down | KeyCode::Char('j') => {
                 if app.current_tab == 1 {
-                    app.memtable_scroll += 1;
+                    // Stop at the last entry rather than scroll into blank rows
+                    if app.memtable_scroll + 1 < app.lsm.memtable_live_len() {
+                        app.memtable_scroll += 1;
+                    }
                 } else if app.current_tab == 2 {
                     if modifiers.contains(KeyModifiers::SHIFT) {
-                        if app.selected_sstable < app.lsm.sstable_count().saturating_sub(1) {
-                            app.selected_sstable += 1;
-                            app.sstable_scroll = 0;
-                        }
+                        app.move_sstable_selection(1);
                     } else {
                         app.sstable_scroll += 1;
                     }
                 }
             }
-            KeyCode::Left => {
-                if app.selected_sstable > 0 {
-                    app.selected_sstable -= 1;
-                    app.sstable_scroll = 0;
-                }
-            }
-            KeyCode::Right => {
-                if app.selected_sstable < app.lsm.sstable_count().saturating_sub(1) {
-                    app.selected_sstable += 1;
-                    app.sstable_scroll = 0;
-                }
-            }
+            KeyCode::Left => app.move_sstable_selection(-1),
+            KeyCode::Right => app.move_sstable_selection(1),
             _ => {}
         },
         InputMode::EnteringKey => match key {
-            KeyCode::Enter => {
-                if !app.key_input.is_empty() {
-                    app.input_mode = InputMode::EnteringValue;
-                }
-            }
+            KeyCode::Enter if !app.key_input.is_empty() => match parse_input(&app.key_input) {
+                Ok(_) => app.input_mode = InputMode::EnteringValue,
+                Err(e) => app.add_message(format!("Invalid key: {}", e), MessageType::Error),
+            },
             KeyCode::Char(c) => {
                 app.key_input.push(c);
             }
@@ -375,14 +1303,17 @@ fn handle_input(app: &mut App, key: KeyCode, modifiers: KeyModifiers) {
             _ => {}
         },
         InputMode::EnteringValue => match key {
-            KeyCode::Enter => {
-                if !app.value_input.is_empty() {
-                    let key = app.key_input.clone();
-                    let value = app.value_input.clone();
-                    app.put(key, value);
-                    app.input_mode = InputMode::Normal;
-                    app.key_input.clear();
-                    app.value_input.clear();
+            KeyCode::Enter if !app.value_input.is_empty() => {
+                let parsed = parse_input(&app.key_input)
+                    .and_then(|key| Ok((key, parse_input(&app.value_input)?)));
+                match parsed {
+                    Ok((key, value)) => {
+                        app.put(key, value);
+                        app.input_mode = InputMode::Normal;
+                        app.key_input.clear();
+                        app.value_input.clear();
+                    }
+                    Err(e) => app.add_message(format!("Invalid value: {}", e), MessageType::Error),
                 }
             }
             KeyCode::Char(c) => {
@@ -400,15 +1331,27 @@ fn handle_input(app: &mut App, key: KeyCode, modifiers: KeyModifiers) {
         },
         InputMode::Searching => match key {
             KeyCode::Enter => {
-                let key = app.search_input.clone();
+                let key = match parse_input(&app.search_input) {
+                    Ok(key) => key,
+                    Err(e) => {
+                        app.add_message(format!("Invalid key: {}", e), MessageType::Error);
+                        return;
+                    }
+                };
                 let result = app.get(&key);
                 app.search_result = Some(match result {
                     Some(v) => {
-                        app.add_message(format!("Found: {} = {}", key, v), MessageType::Success);
+                        app.add_message(
+                            format!("Found: {} = {}", app.short(&key), app.short(&v)),
+                            MessageType::Success,
+                        );
                         SearchResult::Found(v)
                     }
                     None => {
-                        app.add_message(format!("Not found: {}", key), MessageType::Warning);
+                        app.add_message(
+                            format!("Not found: {}", app.short(&key)),
+                            MessageType::Warning,
+                        );
                         SearchResult::NotFound
                     }
                 });
@@ -464,6 +1407,7 @@ fn ui(f: &mut Frame, app: &mut App) {
         "[2] MemTable",
         "[3] SSTables",
         "[4] Bloom Filters",
+        "[5] Advisor",
     ];
     let tabs = Tabs::new(tab_titles)
         .block(Block::default().borders(Borders::ALL).title(" Navigation "))
@@ -482,6 +1426,7 @@ fn ui(f: &mut Frame, app: &mut App) {
         1 => render_memtable(f, app, chunks[2]),
         2 => render_sstables(f, app, chunks[2]),
         3 => render_bloom_filters(f, app, chunks[2]),
+        4 => render_advisor(f, app, chunks[2]),
         _ => {}
     }
 
@@ -496,6 +1441,11 @@ fn ui(f: &mut Frame, app: &mut App) {
         render_input_popup(f, app);
     }
 
+    // Full entry popup
+    if let Some((key, value)) = &app.entry_popup {
+        render_entry_popup(f, key, value, app.display_mode);
+    }
+
     // Help popup
     if app.show_help {
         render_help_popup(f);
@@ -515,11 +1465,17 @@ fn render_dashboard(f: &mut Frame, app: &mut App, area: Rect) {
 
     let right_chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .constraints([
+            Constraint::Percentage(35),
+            Constraint::Percentage(35),
+            Constraint::Percentage(30),
+        ])
         .split(chunks[1]);
 
     // Stats overview
     let stats = app.lsm.bloom_filter_stats();
+    let wal_stats = app.lsm.wal_stats();
+    let recovery = app.lsm.recovery_estimate();
     let memtable_pct = if app.lsm.memtable_threshold() > 0 {
         (app.lsm.memtable_size() as f64 / app.lsm.memtable_threshold() as f64 * 100.0) as u16
     } else {
@@ -528,45 +1484,138 @@ fn render_dashboard(f: &mut Frame, app: &mut App, area: Rect) {
 
     let overview_text = vec![
         Line::from(vec![
-            Span::styled("  MemTable Entries: ", Style::default().fg(Color::Gray)),
+            Span::styled("  MemTable Entries: ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                format!("{}", app.lsm.len()),
+                Style::default().fg(Color::Cyan).bold(),
+            ),
+        ]),
+        Line::from(vec![
+            Span::styled("  MemTable Size:    ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                format!(
+                    "{} / {} bytes",
+                    app.lsm.memtable_size(),
+                    app.lsm.memtable_threshold()
+                ),
+                Style::default().fg(Color::Yellow),
+            ),
+        ]),
+        Line::from(vec![
+            Span::styled("  SSTable Count:    ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                format!("{}", app.lsm.sstable_count()),
+                Style::default().fg(Color::Green).bold(),
+            ),
+        ]),
+        Line::from(vec![
+            Span::styled("  Read Amp (avg):   ", Style::default().fg(Color::Gray)),
+            if app.lsm.needs_compaction() {
+                Span::styled(
+                    format!(
+                        "{:.1} SSTables/get - compaction needed",
+                        app.lsm.read_amplification()
+                    ),
+                    Style::default().fg(Color::Red).bold(),
+                )
+            } else {
+                Span::styled(
+                    format!("{:.1} SSTables/get", app.lsm.read_amplification()),
+                    Style::default().fg(Color::Green),
+                )
+            },
+        ]),
+        Line::from(vec![
+            Span::styled("  Bloom Filters:    ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                format!("{}", stats.num_filters),
+                Style::default().fg(Color::Magenta).bold(),
+            ),
+        ]),
+        Line::from(vec![
+            Span::styled("  WAL Size:         ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                format!(
+                    "{} bytes ({} entries)",
+                    wal_stats.file_size, wal_stats.entries_since_clear
+                ),
+                Style::default().fg(Color::Blue),
+            ),
+        ]),
+        Line::from(vec![
+            Span::styled("  WAL Syncs:        ", Style::default().fg(Color::Gray)),
             Span::styled(
-                format!("{}", app.lsm.len()),
-                Style::default().fg(Color::Cyan).bold(),
+                format!("{}", wal_stats.syncs),
+                Style::default().fg(Color::Blue),
             ),
         ]),
         Line::from(vec![
-            Span::styled("  MemTable Size:    ", Style::default().fg(Color::Gray)),
+            Span::styled("  Crash Replay:     ", Style::default().fg(Color::Gray)),
             Span::styled(
                 format!(
-                    "{} / {} bytes",
-                    app.lsm.memtable_size(),
-                    app.lsm.memtable_threshold()
+                    "~{:.1} ms ({} rate)",
+                    recovery.replay_time.as_secs_f64() * 1000.0,
+                    if recovery.rate_measured {
+                        "measured"
+                    } else {
+                        "assumed"
+                    }
                 ),
-                Style::default().fg(Color::Yellow),
+                Style::default().fg(Color::Blue),
             ),
         ]),
         Line::from(vec![
-            Span::styled("  SSTable Count:    ", Style::default().fg(Color::Gray)),
+            Span::styled("  Disk Usage:       ", Style::default().fg(Color::Gray)),
+            match app.lsm.disk_usage() {
+                Ok(usage) if usage.tmp_or_orphan_bytes > 0 => Span::styled(
+                    format!(
+                        "{} bytes, {} orphaned - run repair/cleanup",
+                        usage.total, usage.tmp_or_orphan_bytes
+                    ),
+                    Style::default().fg(Color::Red).bold(),
+                ),
+                Ok(usage) => Span::styled(
+                    format!(
+                        "{} bytes ({} sst, {} bloom, {} wal)",
+                        usage.total, usage.sstable_bytes, usage.bloom_bytes, usage.wal_bytes
+                    ),
+                    Style::default().fg(Color::Blue),
+                ),
+                Err(e) => Span::styled(
+                    format!("unavailable: {}", e),
+                    Style::default().fg(Color::Red),
+                ),
+            },
+        ]),
+        Line::from(vec![
+            Span::styled("  Get p50 / p99:    ", Style::default().fg(Color::Gray)),
             Span::styled(
-                format!("{}", app.lsm.sstable_count()),
-                Style::default().fg(Color::Green).bold(),
+                format_latency_pair(&app.lsm, LatencyOp::Get),
+                Style::default().fg(Color::Cyan),
             ),
         ]),
         Line::from(vec![
-            Span::styled("  Bloom Filters:    ", Style::default().fg(Color::Gray)),
+            Span::styled("  Put p50 / p99:    ", Style::default().fg(Color::Gray)),
             Span::styled(
-                format!("{}", stats.num_filters),
-                Style::default().fg(Color::Magenta).bold(),
+                format_latency_pair(&app.lsm, LatencyOp::Put),
+                Style::default().fg(Color::Cyan),
             ),
         ]),
         Line::from(""),
         Line::from(vec![
-            Span::styled("  Total Items:      ", Style::default().fg(Color::Gray)),
+            Span::styled("  Keys in Filters:  ", Style::default().fg(Color::Gray)),
             Span::styled(
                 format!("{}", stats.total_items),
                 Style::default().fg(Color::White),
             ),
         ]),
+        Line::from(vec![
+            Span::styled("  Distinct (est.):  ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                format!("{}", stats.distinct_estimate),
+                Style::default().fg(Color::White),
+            ),
+        ]),
     ];
 
     let overview = Paragraph::new(overview_text).block(
@@ -678,6 +1727,8 @@ fn render_dashboard(f: &mut Frame, app: &mut App, area: Rect) {
     );
     f.render_widget(bloom_overview, right_chunks[0]);
 
+    render_io_rates(f, app, right_chunks[1]);
+
     // Operation history display
     let history_items: Vec<ListItem> = app
         .operation_history
@@ -687,13 +1738,19 @@ fn render_dashboard(f: &mut Frame, app: &mut App, area: Rect) {
         .map(|op| match op {
             Operation::Put(key, value) => ListItem::new(Line::from(vec![
                 Span::styled(" PUT ", Style::default().fg(Color::Black).bg(Color::Green)),
-                Span::styled(format!(" {} ", key), Style::default().fg(Color::Cyan)),
+                Span::styled(
+                    format!(" {} ", app.short(key)),
+                    Style::default().fg(Color::Cyan),
+                ),
                 Span::styled("= ", Style::default().fg(Color::Gray)),
-                Span::styled(value.clone(), Style::default().fg(Color::White)),
+                Span::styled(app.short(value), Style::default().fg(Color::White)),
             ])),
             Operation::Get(key, found) => ListItem::new(Line::from(vec![
                 Span::styled(" GET ", Style::default().fg(Color::Black).bg(Color::Cyan)),
-                Span::styled(format!(" {} ", key), Style::default().fg(Color::Cyan)),
+                Span::styled(
+                    format!(" {} ", app.short(key)),
+                    Style::default().fg(Color::Cyan),
+                ),
                 if *found {
                     Span::styled("[found]", Style::default().fg(Color::Green))
                 } else {
@@ -716,37 +1773,90 @@ fn render_dashboard(f: &mut Frame, app: &mut App, area: Rect) {
             .title(" Recent Operations ")
             .title_style(Style::default().fg(Color::Green).bold()),
     );
-    f.render_widget(history_list, right_chunks[1]);
+    f.render_widget(history_list, right_chunks[2]);
+}
+
+/// Disk throughput over the last few seconds: a rate and a sparkline each
+/// for WAL writes, SSTable writes and SSTable reads
+fn render_io_rates(f: &mut Frame, app: &App, area: Rect) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(" Disk I/O (per second) ")
+        .title_style(Style::default().fg(Color::Blue).bold());
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Ratio(1, 3); 3])
+        .split(inner);
+    let series: [(&str, RateOf, Color); 3] = [
+        ("WAL write    ", |r| r.wal_write, Color::Blue),
+        ("SSTable write", |r| r.sstable_write, Color::Green),
+        ("SSTable read ", |r| r.sstable_read, Color::Yellow),
+    ];
+    for ((label, rate, color), row) in series.into_iter().zip(rows.iter()) {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(26), Constraint::Min(1)])
+            .split(*row);
+        let current = app.io_history.last().map_or(0.0, rate);
+        let text = Paragraph::new(Line::from(vec![
+            Span::styled(format!(" {} ", label), Style::default().fg(Color::Gray)),
+            Span::styled(format_rate(current), Style::default().fg(color).bold()),
+        ]));
+        f.render_widget(text, columns[0]);
+
+        // The newest samples that fit, right-aligned like a scrolling chart
+        let width = columns[1].width as usize;
+        let data: Vec<u64> = app
+            .io_history
+            .iter()
+            .skip(app.io_history.len().saturating_sub(width))
+            .map(|r| rate(r) as u64)
+            .collect();
+        let sparkline = Sparkline::default()
+            .data(&data)
+            .style(Style::default().fg(color));
+        f.render_widget(sparkline, columns[1]);
+    }
+}
+
+/// Picks one of the rates out of a sample
+type RateOf = fn(&IoRates) -> f64;
+
+/// Formats bytes per second with a unit that keeps it readable
+fn format_rate(bytes_per_sec: f64) -> String {
+    if bytes_per_sec < 1024.0 {
+        format!("{:.0} B", bytes_per_sec)
+    } else if bytes_per_sec < 1024.0 * 1024.0 {
+        format!("{:.1} KB", bytes_per_sec / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes_per_sec / (1024.0 * 1024.0))
+    }
 }
 
 fn render_memtable(f: &mut Frame, app: &mut App, area: Rect) {
-    let entries = app.lsm.memtable_entries();
+    // Only the rows that fit are copied out of the memtable
+    let count = app.lsm.memtable_live_len();
+    app.memtable_scroll = app.memtable_scroll.min(count.saturating_sub(1));
+    let rows = area.height.saturating_sub(2) as usize;
+    let entries = app.lsm.memtable_page(app.memtable_scroll, rows);
 
     let items: Vec<ListItem> = entries
         .iter()
         .enumerate()
         .map(|(i, (k, v))| {
-            let key_str = String::from_utf8_lossy(k);
-            let value_str = String::from_utf8_lossy(v);
-            ListItem::new(Line::from(vec![
-                Span::styled(
-                    format!("{:4} ", i + 1),
-                    Style::default().fg(Color::DarkGray),
-                ),
-                Span::styled(
-                    format!("{}", key_str),
-                    Style::default().fg(Color::Cyan).bold(),
-                ),
-                Span::styled(" = ", Style::default().fg(Color::Gray)),
-                Span::styled(format!("{}", value_str), Style::default().fg(Color::White)),
-            ]))
+            let index = app.memtable_scroll + i;
+            entry_row(index, k, v, i == 0, app.display_mode)
         })
         .collect();
 
     let title = format!(
-        " MemTable ({} entries, {} bytes) ",
-        entries.len(),
-        app.lsm.memtable_size()
+        " MemTable ({} entries, {} bytes) [{}] ",
+        count,
+        app.lsm.memtable_size(),
+        app.display_mode.label()
     );
 
     let list = List::new(items)
@@ -760,7 +1870,7 @@ fn render_memtable(f: &mut Frame, app: &mut App, area: Rect) {
 
     f.render_widget(list, area);
 
-    if entries.is_empty() {
+    if count == 0 {
         let empty_msg = Paragraph::new(vec![
             Line::from(""),
             Line::from(Span::styled(
@@ -811,21 +1921,38 @@ fn render_sstables(f: &mut Frame, app: &mut App, area: Rect) {
 
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Length(25), Constraint::Min(30)])
+        .constraints([Constraint::Length(46), Constraint::Min(30)])
         .split(area);
 
-    // SSTable list
-    let sstable_items: Vec<ListItem> = (0..sstable_count)
-        .map(|i| {
-            let marker = if i == app.selected_sstable { ">" } else { " " };
-            let style = if i == app.selected_sstable {
+    app.resolve_sstable_selection();
+    let tables: Vec<_> = app.lsm.sstables().collect();
+    let selected = tables.iter().find(|t| Some(t.id()) == app.selected_sstable);
+
+    // SSTable list, newest first
+    let sstable_items: Vec<ListItem> = tables
+        .iter()
+        .map(|table| {
+            let is_selected = Some(table.id()) == app.selected_sstable;
+            let marker = if is_selected { ">" } else { " " };
+            let style = if is_selected {
                 Style::default().fg(Color::Yellow).bold()
             } else {
                 Style::default().fg(Color::White)
             };
             ListItem::new(Line::from(vec![
                 Span::styled(format!("{} ", marker), Style::default().fg(Color::Yellow)),
-                Span::styled(format!("SSTable {}", i), style),
+                Span::styled(format!("SSTable {}", table.id()), style),
+                Span::styled(
+                    format!(
+                        " {:>3.0}% garbage",
+                        table.info().stats.garbage_ratio() * 100.0
+                    ),
+                    Style::default().fg(Color::DarkGray),
+                ),
+                Span::styled(
+                    format!(" {:>6} probes", table.info().read_stats().probes),
+                    Style::default().fg(Color::DarkGray),
+                ),
             ]))
         })
         .collect();
@@ -839,47 +1966,37 @@ fn render_sstables(f: &mut Frame, app: &mut App, area: Rect) {
     f.render_widget(sstable_list, chunks[0]);
 
     // SSTable content
-    if let Some(entries) = app.lsm.read_sstable_entries(app.selected_sstable) {
+    if let Some((table, entries)) = selected.and_then(|table| Some((table, table.live_entries()?)))
+    {
+        app.sstable_scroll = app.sstable_scroll.min(entries.len().saturating_sub(1));
         let items: Vec<ListItem> = entries
             .iter()
+            .enumerate()
             .skip(app.sstable_scroll)
             .take(area.height.saturating_sub(4) as usize)
-            .enumerate()
-            .map(|(i, (k, v))| {
-                let key_str = String::from_utf8_lossy(k);
-                let value_str = String::from_utf8_lossy(v);
-                ListItem::new(Line::from(vec![
-                    Span::styled(
-                        format!("{:4} ", i + 1 + app.sstable_scroll),
-                        Style::default().fg(Color::DarkGray),
-                    ),
-                    Span::styled(format!("{}", key_str), Style::default().fg(Color::Cyan)),
-                    Span::styled(" = ", Style::default().fg(Color::Gray)),
-                    Span::styled(format!("{}", value_str), Style::default().fg(Color::White)),
-                ]))
-            })
+            .map(|(i, (k, v))| entry_row(i, k, v, i == app.sstable_scroll, app.display_mode))
             .collect();
 
-        let bloom_stats = app.lsm.bloom_filter_stats();
-        let bf_info = if app.selected_sstable < bloom_stats.individual_stats.len() {
-            let stat = &bloom_stats.individual_stats[app.selected_sstable];
-            format!(
+        let bf_info = match table.filter_stats() {
+            Some(stat) => format!(
                 " [BF: {} items, {:.1}% FPP] ",
                 stat.num_items,
                 stat.estimated_fpp * 100.0
-            )
-        } else {
-            String::new()
+            ),
+            None => String::new(),
         };
 
         let content = List::new(items).block(
             Block::default()
                 .borders(Borders::ALL)
                 .title(format!(
-                    " SSTable {} ({} entries){} ",
-                    app.selected_sstable,
+                    " SSTable {} ({} entries, {} tombstones, ~{:.0}% garbage){}[{}] ",
+                    table.id(),
                     entries.len(),
-                    bf_info
+                    table.info().stats.tombstones,
+                    table.info().stats.garbage_ratio() * 100.0,
+                    bf_info,
+                    app.display_mode.label()
                 ))
                 .title_style(Style::default().fg(Color::Cyan).bold()),
         );
@@ -935,11 +2052,17 @@ fn render_bloom_filters(f: &mut Frame, app: &mut App, area: Rect) {
                 Style::default().fg(Color::Cyan),
             ),
             Span::raw("    "),
-            Span::styled("Total Items: ", Style::default().fg(Color::Gray)),
+            Span::styled("Keys: ", Style::default().fg(Color::Gray)),
             Span::styled(
                 format!("{}", stats.total_items),
                 Style::default().fg(Color::White),
             ),
+            Span::styled(" (~", Style::default().fg(Color::Gray)),
+            Span::styled(
+                format!("{}", stats.distinct_estimate),
+                Style::default().fg(Color::White),
+            ),
+            Span::styled(" distinct)", Style::default().fg(Color::Gray)),
         ]),
         Line::from(""),
         Line::from(vec![
@@ -1032,12 +2155,315 @@ fn render_bloom_filters(f: &mut Frame, app: &mut App, area: Rect) {
     f.render_widget(details, chunks[1]);
 }
 
+fn render_advisor(f: &mut Frame, app: &mut App, area: Rect) {
+    let advice = app.lsm.advisor();
+    let mut lines = Vec::new();
+    let mut section = |name: &str, verdict: String, change: bool, reasons: &[String]| {
+        lines.push(Line::from(vec![
+            Span::styled(format!("  {:<26}", name), Style::default().fg(Color::Gray)),
+            Span::styled(
+                verdict,
+                Style::default()
+                    .fg(if change { Color::Yellow } else { Color::Green })
+                    .bold(),
+            ),
+        ]));
+        for reason in reasons {
+            lines.push(Line::from(Span::styled(
+                format!("      - {}", reason),
+                Style::default().fg(Color::DarkGray),
+            )));
+        }
+        lines.push(Line::from(""));
+    };
+
+    let memtable = &advice.memtable_size_threshold;
+    section(
+        "Memtable threshold:",
+        if memtable.is_change() {
+            format!("{} -> {} bytes", memtable.current, memtable.suggested)
+        } else {
+            format!("{} bytes (keep)", memtable.current)
+        },
+        memtable.is_change(),
+        &memtable.reasons,
+    );
+    let fpp = &advice.bloom_filter_fpp;
+    section(
+        "Bloom filter fpp:",
+        if fpp.is_change() {
+            format!("{} -> {}", fpp.current, fpp.suggested)
+        } else {
+            format!("{} (keep)", fpp.current)
+        },
+        fpp.is_change(),
+        &fpp.reasons,
+    );
+    let compress = &advice.compress_values;
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    section(
+        "Value compression:",
+        if compress.is_change() {
+            format!(
+                "{} -> {}",
+                on_off(compress.current),
+                on_off(compress.suggested)
+            )
+        } else {
+            format!("{} (keep)", on_off(compress.current))
+        },
+        compress.is_change(),
+        &compress.reasons,
+    );
+    section(
+        "Compaction:",
+        if advice.compaction.overdue {
+            "overdue".to_string()
+        } else {
+            "not needed".to_string()
+        },
+        advice.compaction.overdue,
+        &advice.compaction.reasons,
+    );
+
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+        .split(area);
+    let panel = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Advisor (from this session's workload) ")
+            .title_style(Style::default().fg(Color::Cyan).bold()),
+    );
+    f.render_widget(panel, chunks[0]);
+    render_prefixes(f, app, chunks[1]);
+}
+
+/// Key counts per `:`-separated prefix, largest first
+fn render_prefixes(f: &mut Frame, app: &App, area: Rect) {
+    let report = app.lsm.prefix_stats(b':', 1);
+    let total = report.total_keys().max(1) as f64;
+    let items: Vec<ListItem> = report
+        .largest()
+        .into_iter()
+        .map(|p| {
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    format!("  {:<16}", prefix_stats::display_prefix(&p.prefix)),
+                    Style::default().fg(Color::White),
+                ),
+                Span::styled(
+                    format!("{:>6} keys ", p.keys),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::styled(
+                    format!(
+                        "{:>5.1}%  ~{} B",
+                        p.keys as f64 * 100.0 / total,
+                        p.approx_bytes
+                    ),
+                    Style::default().fg(Color::Gray),
+                ),
+            ]))
+        })
+        .collect();
+    let list = List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" Key Prefixes ({} keys) ", report.total_keys()))
+            .title_style(Style::default().fg(Color::Cyan).bold()),
+    );
+    f.render_widget(list, area);
+}
+
+/// Formats the median and 99th percentile latency of an operation
+fn format_latency_pair(lsm: &LSMTree, op: LatencyOp) -> String {
+    let p = lsm.latency_percentiles(op);
+    if p.count == 0 {
+        return "-".to_string();
+    }
+    format!("{} / {}", format_duration(p.p50), format_duration(p.p99))
+}
+
+/// Formats a short duration with a unit that keeps it readable
+fn format_duration(d: Duration) -> String {
+    let nanos = d.as_nanos();
+    if nanos < 1_000 {
+        format!("{}ns", nanos)
+    } else if nanos < 1_000_000 {
+        format!("{:.1}µs", nanos as f64 / 1_000.0)
+    } else {
+        format!("{:.1}ms", nanos as f64 / 1_000_000.0)
+    }
+}
+
 fn create_fill_bar(ratio: f64, width: usize) -> String {
     let filled = (ratio * width as f64).round() as usize;
     let empty = width.saturating_sub(filled);
     format!("[{}{}]", "█".repeat(filled), "░".repeat(empty))
 }
 
+/// Longest key or value shown on a one-line row, in characters
+const MAX_SHORT_DISPLAY_CHARS: usize = 40;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Renders bytes in full in the given display mode
+///
+/// UTF-8 mode escapes invalid bytes, control characters and backslashes,
+/// so two different byte strings never look the same.
+fn format_bytes(bytes: &[u8], mode: DisplayMode) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    match mode {
+        DisplayMode::Utf8 => {
+            for chunk in bytes.utf8_chunks() {
+                for c in chunk.valid().chars() {
+                    if c.is_control() || c == '\\' {
+                        out.extend(c.escape_default());
+                    } else {
+                        out.push(c);
+                    }
+                }
+                for b in chunk.invalid() {
+                    let _ = write!(out, "\\x{:02x}", b);
+                }
+            }
+        }
+        DisplayMode::Hex => {
+            out.push_str("0x");
+            for b in bytes {
+                let _ = write!(out, "{:02x}", b);
+            }
+        }
+        DisplayMode::Base64 => {
+            for chunk in bytes.chunks(3) {
+                let n = (chunk[0] as u32) << 16
+                    | (chunk.get(1).copied().unwrap_or(0) as u32) << 8
+                    | chunk.get(2).copied().unwrap_or(0) as u32;
+                for i in 0..4 {
+                    if i <= chunk.len() {
+                        out.push(BASE64_ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+                    } else {
+                        out.push('=');
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Renders bytes for a one-line row, cut off with an ellipsis past
+/// `MAX_SHORT_DISPLAY_CHARS` (Enter shows an entry in full)
+fn format_bytes_short(bytes: &[u8], mode: DisplayMode) -> String {
+    truncate_display(&format_bytes(bytes, mode), MAX_SHORT_DISPLAY_CHARS)
+}
+
+/// Cuts `text` to at most `max_chars` characters, the last being `…`
+fn truncate_display(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut out: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    out.push('…');
+    out
+}
+
+/// Reads a key or value typed into a popup: input starting with `0x` is
+/// hex, anything else is taken as text
+fn parse_input(input: &str) -> Result<Vec<u8>, String> {
+    let Some(hex) = input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix("0X"))
+    else {
+        return Ok(input.as_bytes().to_vec());
+    };
+    if let Some((i, c)) = hex.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+        return Err(format!("{:?} at position {} is not a hex digit", c, i + 2));
+    }
+    if hex.len() % 2 != 0 {
+        return Err(format!("odd number of hex digits ({})", hex.len()));
+    }
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("checked hex digits"))
+        .collect())
+}
+
+/// One key-value row of the MemTable or SSTable view; the selected row is
+/// the one Enter opens
+fn entry_row(
+    index: usize,
+    key: &[u8],
+    value: &[u8],
+    selected: bool,
+    mode: DisplayMode,
+) -> ListItem<'static> {
+    let marker = if selected { ">" } else { " " };
+    ListItem::new(Line::from(vec![
+        Span::styled(
+            format!("{}{:4} ", marker, index + 1),
+            Style::default().fg(Color::DarkGray),
+        ),
+        Span::styled(
+            format_bytes_short(key, mode),
+            Style::default().fg(Color::Cyan).bold(),
+        ),
+        Span::styled(" = ", Style::default().fg(Color::Gray)),
+        Span::styled(
+            format_bytes_short(value, mode),
+            Style::default().fg(Color::White),
+        ),
+    ]))
+}
+
+fn render_entry_popup(f: &mut Frame, key: &[u8], value: &[u8], mode: DisplayMode) {
+    let area = centered_rect(80, 60, f.area());
+
+    f.render_widget(Clear, area);
+
+    let content = vec![
+        Line::from(""),
+        Line::from(vec![
+            Span::styled(
+                format!("  Key ({} bytes): ", key.len()),
+                Style::default().fg(Color::Gray),
+            ),
+            Span::styled(
+                format_bytes(key, mode),
+                Style::default().fg(Color::Cyan).bold(),
+            ),
+        ]),
+        Line::from(""),
+        Line::from(Span::styled(
+            format!("  Value ({} bytes):", value.len()),
+            Style::default().fg(Color::Gray),
+        )),
+        Line::from(Span::styled(
+            format!("  {}", format_bytes(value, mode)),
+            Style::default().fg(Color::White),
+        )),
+        Line::from(""),
+        Line::from(Span::styled(
+            "  Press b to change the display, Enter or Esc to close",
+            Style::default().fg(Color::DarkGray),
+        )),
+    ];
+
+    let popup = Paragraph::new(content).wrap(Wrap { trim: false }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Yellow))
+            .title(format!(" Entry [{}] ", mode.label()))
+            .title_style(Style::default().fg(Color::Yellow).bold()),
+    );
+    f.render_widget(popup, area);
+}
+
 fn render_status_bar(f: &mut Frame, app: &App, area: Rect) {
     let mode_text = match app.input_mode {
         InputMode::Normal => "NORMAL",
@@ -1057,9 +2483,22 @@ fn render_status_bar(f: &mut Frame, app: &App, area: Rect) {
             " [DEMO RUNNING] ",
             Style::default().fg(Color::Magenta).bold(),
         )
+    } else if app.ttl_demo.is_some() {
+        Span::styled(
+            " [TTL DEMO RUNNING] ",
+            Style::default().fg(Color::Magenta).bold(),
+        )
     } else {
         Span::raw("")
     };
+    let clock_status = if app.clock.offset().is_zero() {
+        Span::raw("")
+    } else {
+        Span::styled(
+            format!(" [CLOCK {}] ", format_clock_offset(app.clock.offset())),
+            Style::default().fg(Color::Cyan).bold(),
+        )
+    };
 
     let status = Paragraph::new(Line::from(vec![
         Span::styled(
@@ -1068,6 +2507,7 @@ fn render_status_bar(f: &mut Frame, app: &App, area: Rect) {
         ),
         Span::raw(" "),
         demo_status,
+        clock_status,
         Span::raw(" "),
         Span::styled("p", Style::default().fg(Color::Yellow).bold()),
         Span::styled(":put ", Style::default().fg(Color::Gray)),
@@ -1077,6 +2517,13 @@ fn render_status_bar(f: &mut Frame, app: &App, area: Rect) {
         Span::styled(":flush ", Style::default().fg(Color::Gray)),
         Span::styled("d", Style::default().fg(Color::Yellow).bold()),
         Span::styled(":demo ", Style::default().fg(Color::Gray)),
+        Span::styled("t", Style::default().fg(Color::Yellow).bold()),
+        Span::styled(":+10m ", Style::default().fg(Color::Gray)),
+        Span::styled("b", Style::default().fg(Color::Yellow).bold()),
+        Span::styled(
+            format!(":{} ", app.display_mode.label().to_lowercase()),
+            Style::default().fg(Color::Gray),
+        ),
         Span::styled("h", Style::default().fg(Color::Yellow).bold()),
         Span::styled(":help ", Style::default().fg(Color::Gray)),
         Span::styled("q", Style::default().fg(Color::Yellow).bold()),
@@ -1162,7 +2609,7 @@ fn render_input_popup(f: &mut Frame, app: &App) {
             let result_line = match &app.search_result {
                 Some(SearchResult::Found(v)) => Line::from(vec![
                     Span::styled("  Result: ", Style::default().fg(Color::Gray)),
-                    Span::styled(v, Style::default().fg(Color::Green).bold()),
+                    Span::styled(app.short(v), Style::default().fg(Color::Green).bold()),
                 ]),
                 Some(SearchResult::NotFound) => Line::from(Span::styled(
                     "  Result: NOT FOUND",
@@ -1217,9 +2664,11 @@ fn render_help_popup(f: &mut Frame) {
             "  Navigation:",
             Style::default().fg(Color::Yellow).bold(),
         )),
-        Line::from("    1-4, Tab    Switch between tabs"),
+        Line::from("    1-5, Tab    Switch between tabs"),
         Line::from("    j/k, ↑/↓    Scroll through entries"),
         Line::from("    ←/→         Switch SSTable (in SSTable view)"),
+        Line::from("    Enter       Show the top entry in full (MemTable/SSTable view)"),
+        Line::from("    b           Show keys and values as UTF-8, hex or base64"),
         Line::from(""),
         Line::from(Span::styled(
             "  Operations:",
@@ -1227,14 +2676,17 @@ fn render_help_popup(f: &mut Frame) {
         )),
         Line::from("    p, i        Put a new key-value pair"),
         Line::from("    g, /        Get/search for a key"),
+        Line::from("                (0x... keys and values are read as hex)"),
         Line::from("    f           Flush memtable to SSTable"),
-        Line::from("    r           Reset Bloom filter statistics"),
+        Line::from("    r           Reset read statistics (Bloom filters, per SSTable)"),
         Line::from(""),
         Line::from(Span::styled(
             "  Demo:",
             Style::default().fg(Color::Yellow).bold(),
         )),
         Line::from("    d           Toggle auto-demo mode"),
+        Line::from("    D           Start/stop the TTL demo (expiry, maintenance, compaction)"),
+        Line::from("    t           Move the clock 10 minutes forward (entries expire after 1h)"),
         Line::from(""),
         Line::from(Span::styled(
             "  General:",
@@ -1279,3 +2731,179 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         ])
         .split(popup_layout[1])[1]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes_keeps_binary_keys_apart() {
+        // Lossy decoding shows both as the same replacement character
+        assert_eq!(format_bytes(b"a\xff", DisplayMode::Utf8), "a\\xff");
+        assert_eq!(format_bytes(b"a\xfe", DisplayMode::Utf8), "a\\xfe");
+        assert_eq!(format_bytes(b"a\\xff", DisplayMode::Utf8), "a\\\\xff");
+        assert_eq!(format_bytes(b"tab\there", DisplayMode::Utf8), "tab\\there");
+        assert_eq!(format_bytes("über".as_bytes(), DisplayMode::Utf8), "über");
+
+        assert_eq!(format_bytes(b"\x00\xffA", DisplayMode::Hex), "0x00ff41");
+        assert_eq!(format_bytes(b"", DisplayMode::Hex), "0x");
+        for (input, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(format_bytes(input.as_bytes(), DisplayMode::Base64), encoded);
+        }
+        assert_eq!(DisplayMode::Utf8.next().next().next(), DisplayMode::Utf8);
+    }
+
+    #[test]
+    fn test_long_values_are_truncated() {
+        assert_eq!(truncate_display("short", 10), "short");
+        assert_eq!(truncate_display("0123456789", 10), "0123456789");
+        assert_eq!(truncate_display("0123456789abc", 10), "012345678…");
+        assert_eq!(truncate_display("ääääää", 4), "äää…");
+
+        let value = vec![0xab; 100];
+        let short = format_bytes_short(&value, DisplayMode::Hex);
+        assert_eq!(short.chars().count(), MAX_SHORT_DISPLAY_CHARS);
+        assert!(short.starts_with("0xabab") && short.ends_with('…'));
+    }
+
+    #[test]
+    fn test_parse_input_reads_hex() {
+        assert_eq!(parse_input("user:1"), Ok(b"user:1".to_vec()));
+        assert_eq!(parse_input("0x00ff41"), Ok(vec![0x00, 0xff, 0x41]));
+        assert_eq!(parse_input("0XAbCd"), Ok(vec![0xab, 0xcd]));
+        assert_eq!(parse_input("0x"), Ok(Vec::new()));
+        assert_eq!(
+            parse_input("0x0g"),
+            Err("'g' at position 3 is not a hex digit".to_string())
+        );
+        assert!(parse_input("0x0é").is_err());
+        assert_eq!(
+            parse_input("0xabc"),
+            Err("odd number of hex digits (3)".to_string())
+        );
+
+        // Whatever hex mode shows can be typed back in
+        let key = b"\x00binary\xff".to_vec();
+        assert_eq!(parse_input(&format_bytes(&key, DisplayMode::Hex)), Ok(key));
+    }
+
+    #[test]
+    fn test_ttl_demo_expires_before_reclaiming() {
+        let ttl = Duration::from_secs(60 * 60);
+        let mut demo = TtlDemo::new(ttl);
+        let ops: Vec<DemoOp> = std::iter::from_fn(|| demo.next_op()).collect();
+        assert_eq!(demo.next_op(), None);
+        let position = |wanted: &DemoOp| ops.iter().position(|op| op == wanted).unwrap();
+
+        // The clock moves past the TTL in all, and only after the writes
+        // it is meant to expire
+        let advanced: Duration = ops
+            .iter()
+            .filter_map(|op| match op {
+                DemoOp::AdvanceClock(by) => Some(*by),
+                _ => None,
+            })
+            .sum();
+        assert_eq!(advanced, ttl + ttl / 2 + TTL_DEMO_MARGIN);
+        assert!(position(&DemoOp::Put("session:alice", "token-a1")) < position(&DemoOp::Flush));
+        assert!(
+            position(&DemoOp::AdvanceClock(ttl / 2 + TTL_DEMO_MARGIN))
+                < position(&DemoOp::MaintenanceTick)
+        );
+
+        let expected_steps: Vec<&DemoOp> = ops
+            .iter()
+            .filter(|op| !matches!(op, DemoOp::Narrate(_) | DemoOp::Put(..)))
+            .collect();
+        assert_eq!(
+            expected_steps,
+            [
+                &DemoOp::Flush,
+                &DemoOp::AdvanceClock(ttl / 2),
+                &DemoOp::ReportDiskUsage,
+                &DemoOp::AdvanceClock(ttl / 2 + TTL_DEMO_MARGIN),
+                &DemoOp::Flush,
+                &DemoOp::ReportExpiry,
+                &DemoOp::Get("session:alice"),
+                &DemoOp::MaintenanceTick,
+                &DemoOp::ReportDiskUsage,
+                &DemoOp::AdvanceClock(ttl / 2),
+                &DemoOp::ReportExpiry,
+                &DemoOp::MaintenanceTick,
+                &DemoOp::Compact,
+                &DemoOp::ReportDiskUsage,
+                &DemoOp::Get("cache:home"),
+                &DemoOp::Get("user:dave"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ttl_demo_reclaims_what_expired() {
+        let dir = PathBuf::from("./test_cli_ttl_demo");
+        let _ = std::fs::remove_dir_all(&dir);
+        let clock = DemoClock::default();
+        let mut lsm = LSMTree::open(LSMConfig {
+            ttl: Some(TUI_TTL),
+            clock: Arc::new(clock.clone()),
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+
+        let mut demo = TtlDemo::new(TUI_TTL);
+        let mut messages = Vec::new();
+        while let Some(op) = demo.next_op() {
+            let (msg, msg_type) = apply_demo_op(&mut lsm, &clock, &op);
+            assert!(!matches!(msg_type, MessageType::Error), "{}", msg);
+            messages.push(msg);
+        }
+        let find = |prefix: &str| -> Vec<&String> {
+            messages.iter().filter(|m| m.starts_with(prefix)).collect()
+        };
+        assert_eq!(
+            find("MAINTENANCE"),
+            [
+                "MAINTENANCE deleted 1 expired SSTables",
+                "MAINTENANCE nothing to delete"
+            ]
+        );
+        assert_eq!(
+            find("EXPIRED"),
+            [
+                "EXPIRED 3 of 6 writes, 1 SSTables entirely",
+                "EXPIRED 2 of 3 writes, 0 SSTables entirely"
+            ]
+        );
+        assert!(find("COMPACT")[0].starts_with("COMPACT 1 SSTables -> 1 live entries"));
+        assert_eq!(
+            find("GET"),
+            [
+                "GET session:alice = token-a1",
+                "GET cache:home = NOT FOUND",
+                "GET user:dave = Dave Jones"
+            ]
+        );
+        assert_eq!(clock.offset(), TUI_TTL + TUI_TTL / 2 + TTL_DEMO_MARGIN);
+        assert_eq!(format_clock_offset(clock.offset()), "+1h 31m");
+        assert_eq!(format_clock_offset(Duration::from_secs(30 * 60)), "+30m");
+
+        let sstable_bytes: Vec<u64> = find("DISK")
+            .iter()
+            .map(|m| {
+                let (_, rest) = m.split_once('(').unwrap();
+                rest.split(' ').next().unwrap().parse().unwrap()
+            })
+            .collect();
+        assert_eq!(sstable_bytes.len(), 3);
+        assert!(sstable_bytes[2] < sstable_bytes[1], "{:?}", sstable_bytes);
+
+        drop(lsm);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
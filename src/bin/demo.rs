@@ -64,16 +64,13 @@ fn main() {
             .unwrap_or_else(|_| panic!("Failed to put product:{}", i));
     }
 
-    println!("Number of entries in memtable: {}", lsm.len());
-    println!("Number of SSTables on disk: {}", lsm.sstable_count());
-
     println!();
 
     // Example 4: Bloom Filter effectiveness
     println!("Example 4: Bloom Filter Effectiveness");
     println!("--------------------------------------");
 
-    lsm.reset_bloom_filter_stats();
+    lsm.reset_read_stats();
 
     println!("Searching for 100 non-existent keys...");
     for i in 1000..1100 {
@@ -87,8 +84,7 @@ fn main() {
         let _ = lsm.get(key.as_bytes());
     }
 
-    let stats = lsm.bloom_filter_stats();
-    println!("\n{}", stats);
+    println!("\n{}", lsm.tree_summary());
 
     println!("=== Demo Complete ===");
     println!("\nRun 'cargo run --bin lsm-cli' for interactive TUI!");
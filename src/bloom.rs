@@ -0,0 +1,7 @@
+/// Bloom filters, re-exported from the `lsm_bloom` crate (see its docs)
+pub use lsm_bloom::{
+    BloomFilter, BloomFilterStats, DEFAULT_MIN_BITS_PER_KEY, DEFAULT_MIN_FILTER_BYTES,
+    DeferredBloomFilter, FilterError, FilterFloor, FilterHasher, MAX_HASHES, MIN_BITS,
+};
+
+pub(crate) use lsm_bloom::{FNV_OFFSET_BASIS, fnv1a, mix64};
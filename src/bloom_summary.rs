@@ -0,0 +1,372 @@
+/// Bloom filter statistics of a whole tree, and comparing two snapshots
+///
+/// `LSMTree::bloom_filter_stats` returns a `BloomFilterSummary`. When
+/// tuning `LSMConfig::bloom_filter_fpp`, capture one before and one after
+/// the change (`to_json` turns it into text that can be kept in a file,
+/// `from_json` reads it back) and compare them with `diff`. Per-filter
+/// statistics carry the file number of their SSTable, so a diff lines up
+/// the same tables even when others were added or removed in between.
+///
+/// Example:
+/// ```ignore
+/// let before = BloomFilterSummary::from_json(&std::fs::read_to_string("before.json")?)?;
+/// let diff = before.diff(&lsm.bloom_filter_stats());
+/// println!("skip rate {:+.1} points", diff.skip_rate * 100.0);
+/// ```
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::bloom::BloomFilterStats;
+use crate::json::{self, Value};
+
+/// Summary of Bloom filter effectiveness
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilterSummary {
+    pub num_filters: usize,
+    pub total_size_bytes: usize,
+    /// Keys stored across all filters; a key present in several SSTables
+    /// is counted once per table
+    pub total_items: usize,
+    /// Estimated number of distinct keys across all filters, counting keys
+    /// that newer tables overwrote only once (approximate: it relies on the
+    /// overlap recorded by Bloom filter checks at flush time)
+    pub distinct_estimate: usize,
+    pub checks_negative: usize,
+    pub checks_positive: usize,
+    pub individual_stats: Vec<BloomFilterStats>,
+    /// File number of the SSTable each entry of `individual_stats` belongs
+    /// to (`sstable_<id>.db`)
+    pub sstable_ids: Vec<usize>,
+}
+
+impl BloomFilterSummary {
+    pub fn skip_rate(&self) -> f64 {
+        let total = self.checks_negative + self.checks_positive;
+        if total == 0 {
+            0.0
+        } else {
+            self.checks_negative as f64 / total as f64
+        }
+    }
+
+    pub fn total_checks(&self) -> usize {
+        self.checks_negative + self.checks_positive
+    }
+
+    /// Average false positive rate the filters show given how full they
+    /// are (0.0 without filters)
+    pub fn mean_estimated_fpp(&self) -> f64 {
+        if self.individual_stats.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.individual_stats.iter().map(|s| s.estimated_fpp).sum();
+        sum / self.individual_stats.len() as f64
+    }
+
+    /// The summary on one line, for status output
+    pub fn one_line(&self) -> String {
+        format!(
+            "{} filters, {} bytes, {} keys, {:.1}% of {} checks skipped",
+            self.num_filters,
+            self.total_size_bytes,
+            self.total_items,
+            self.skip_rate() * 100.0,
+            self.total_checks()
+        )
+    }
+
+    /// Serializes the summary as a JSON object
+    ///
+    /// `skip_rate` and `mean_estimated_fpp` are included for readers of the
+    /// file; `from_json` ignores them and derives them again.
+    pub fn to_json(&self) -> String {
+        let count = |n: usize| Value::from_u64(n as u64);
+        let filters = self
+            .sstable_ids
+            .iter()
+            .zip(&self.individual_stats)
+            .map(|(&id, stats)| {
+                Value::Object(BTreeMap::from([
+                    ("sstable_id".to_string(), count(id)),
+                    ("num_bits".to_string(), count(stats.num_bits)),
+                    ("num_hashes".to_string(), count(stats.num_hashes)),
+                    ("num_items".to_string(), count(stats.num_items)),
+                    (
+                        "overlapping_items".to_string(),
+                        count(stats.overlapping_items),
+                    ),
+                    ("size_bytes".to_string(), count(stats.size_bytes)),
+                    ("bits_set".to_string(), count(stats.bits_set)),
+                    ("fill_ratio".to_string(), Value::from_f64(stats.fill_ratio)),
+                    (
+                        "estimated_fpp".to_string(),
+                        Value::from_f64(stats.estimated_fpp),
+                    ),
+                ]))
+            })
+            .collect();
+
+        Value::Object(BTreeMap::from([
+            ("num_filters".to_string(), count(self.num_filters)),
+            ("total_size_bytes".to_string(), count(self.total_size_bytes)),
+            ("total_items".to_string(), count(self.total_items)),
+            (
+                "distinct_estimate".to_string(),
+                count(self.distinct_estimate),
+            ),
+            ("checks_negative".to_string(), count(self.checks_negative)),
+            ("checks_positive".to_string(), count(self.checks_positive)),
+            ("skip_rate".to_string(), Value::from_f64(self.skip_rate())),
+            (
+                "mean_estimated_fpp".to_string(),
+                Value::from_f64(self.mean_estimated_fpp()),
+            ),
+            ("filters".to_string(), Value::Array(filters)),
+        ]))
+        .to_pretty_string()
+    }
+
+    /// Reads a summary written by `to_json`
+    ///
+    /// Fails with `InvalidData` on malformed JSON or a missing field.
+    pub fn from_json(text: &str) -> std::io::Result<Self> {
+        let root = json::parse(text)?;
+        let Value::Array(filters) = root.field("filters")? else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "\"filters\" is not an array",
+            ));
+        };
+
+        let mut sstable_ids = Vec::with_capacity(filters.len());
+        let mut individual_stats = Vec::with_capacity(filters.len());
+        for filter in filters {
+            sstable_ids.push(filter.field("sstable_id")?.as_usize()?);
+            let num_bits = filter.field("num_bits")?.as_usize()?;
+            let num_items = filter.field("num_items")?.as_usize()?;
+            individual_stats.push(BloomFilterStats {
+                num_bits,
+                num_hashes: filter.field("num_hashes")?.as_usize()?,
+                num_items,
+                overlapping_items: filter.field("overlapping_items")?.as_usize()?,
+                size_bytes: filter.field("size_bytes")?.as_usize()?,
+                // Derived, so it isn't exported
+                bits_per_key: match num_items {
+                    0 => 0.0,
+                    n => num_bits as f64 / n as f64,
+                },
+                bits_set: filter.field("bits_set")?.as_usize()?,
+                fill_ratio: filter.field("fill_ratio")?.as_f64()?,
+                estimated_fpp: filter.field("estimated_fpp")?.as_f64()?,
+            });
+        }
+
+        Ok(Self {
+            num_filters: root.field("num_filters")?.as_usize()?,
+            total_size_bytes: root.field("total_size_bytes")?.as_usize()?,
+            total_items: root.field("total_items")?.as_usize()?,
+            distinct_estimate: root.field("distinct_estimate")?.as_usize()?,
+            checks_negative: root.field("checks_negative")?.as_usize()?,
+            checks_positive: root.field("checks_positive")?.as_usize()?,
+            individual_stats,
+            sstable_ids,
+        })
+    }
+
+    /// Returns how `after` differs from `self` (every delta is `after`
+    /// minus `self`)
+    pub fn diff(&self, after: &BloomFilterSummary) -> SummaryDiff {
+        let by_id = |summary: &BloomFilterSummary| -> BTreeMap<usize, BloomFilterStats> {
+            summary
+                .sstable_ids
+                .iter()
+                .copied()
+                .zip(summary.individual_stats.iter().cloned())
+                .collect()
+        };
+        let mut before_filters = by_id(self);
+        let mut after_filters = by_id(after);
+        let ids: BTreeSet<usize> = before_filters
+            .keys()
+            .chain(after_filters.keys())
+            .copied()
+            .collect();
+
+        let filters = ids
+            .into_iter()
+            .map(|sstable_id| FilterDiff {
+                sstable_id,
+                before: before_filters.remove(&sstable_id),
+                after: after_filters.remove(&sstable_id),
+            })
+            .filter(|f| f.before != f.after)
+            .collect();
+
+        SummaryDiff {
+            num_filters: delta(self.num_filters, after.num_filters),
+            total_size_bytes: delta(self.total_size_bytes, after.total_size_bytes),
+            total_items: delta(self.total_items, after.total_items),
+            skip_rate: after.skip_rate() - self.skip_rate(),
+            mean_estimated_fpp: after.mean_estimated_fpp() - self.mean_estimated_fpp(),
+            filters,
+        }
+    }
+}
+
+impl std::fmt::Display for BloomFilterSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Bloom Filter Summary:")?;
+        writeln!(f, "  Filters: {}", self.num_filters)?;
+        writeln!(f, "  Total Size: {} bytes", self.total_size_bytes)?;
+        writeln!(f, "  Total Items: {}", self.total_items)?;
+        writeln!(f, "  Distinct Keys (est.): {}", self.distinct_estimate)?;
+        writeln!(
+            f,
+            "  Checks (skipped/proceeded): {}/{}",
+            self.checks_negative, self.checks_positive
+        )?;
+        writeln!(f, "  Skip Rate: {:.1}%", self.skip_rate() * 100.0)?;
+        Ok(())
+    }
+}
+
+/// Difference between two Bloom filter summaries
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryDiff {
+    pub num_filters: i64,
+    pub total_size_bytes: i64,
+    pub total_items: i64,
+    /// Change of the skip rate, as a fraction (0.05 = five points)
+    pub skip_rate: f64,
+    pub mean_estimated_fpp: f64,
+    /// Filters whose statistics changed, appeared or disappeared, by
+    /// SSTable file number
+    pub filters: Vec<FilterDiff>,
+}
+
+/// One SSTable's filter in a `SummaryDiff`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterDiff {
+    pub sstable_id: usize,
+    /// `None` if the table (or its resident filter) is new
+    pub before: Option<BloomFilterStats>,
+    /// `None` if the table (or its resident filter) is gone
+    pub after: Option<BloomFilterStats>,
+}
+
+impl FilterDiff {
+    /// Change of the filter's size; a missing side counts as zero
+    pub fn size_bytes(&self) -> i64 {
+        let size = |s: &Option<BloomFilterStats>| s.as_ref().map_or(0, |s| s.size_bytes);
+        delta(size(&self.before), size(&self.after))
+    }
+
+    /// Change of the filter's estimated false positive rate; a missing
+    /// side counts as zero
+    pub fn estimated_fpp(&self) -> f64 {
+        let fpp = |s: &Option<BloomFilterStats>| s.as_ref().map_or(0.0, |s| s.estimated_fpp);
+        fpp(&self.after) - fpp(&self.before)
+    }
+}
+
+fn delta(before: usize, after: usize) -> i64 {
+    after as i64 - before as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(num_items: usize, size_bytes: usize, estimated_fpp: f64) -> BloomFilterStats {
+        BloomFilterStats {
+            num_bits: size_bytes * 8,
+            num_hashes: 7,
+            num_items,
+            overlapping_items: 0,
+            size_bytes,
+            bits_per_key: (size_bytes * 8) as f64 / num_items.max(1) as f64,
+            bits_set: num_items * 7,
+            fill_ratio: (num_items * 7) as f64 / (size_bytes * 8) as f64,
+            estimated_fpp,
+        }
+    }
+
+    fn summary(
+        ids: &[usize],
+        stats: Vec<BloomFilterStats>,
+        checks: (usize, usize),
+    ) -> BloomFilterSummary {
+        BloomFilterSummary {
+            num_filters: stats.len(),
+            total_size_bytes: stats.iter().map(|s| s.size_bytes).sum(),
+            total_items: stats.iter().map(|s| s.num_items).sum(),
+            distinct_estimate: stats.iter().map(|s| s.num_items).sum(),
+            checks_negative: checks.0,
+            checks_positive: checks.1,
+            individual_stats: stats,
+            sstable_ids: ids.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let original = summary(
+            &[7, 3],
+            vec![filter(100, 120, 0.01), filter(3, 8, 1.0 / 3.0)],
+            (40, 10),
+        );
+        let text = original.to_json();
+        assert!(text.contains("\"skip_rate\": 0.8"));
+        assert_eq!(BloomFilterSummary::from_json(&text).unwrap(), original);
+
+        let empty = summary(&[], Vec::new(), (0, 0));
+        assert_eq!(
+            BloomFilterSummary::from_json(&empty.to_json()).unwrap(),
+            empty
+        );
+
+        let err = BloomFilterSummary::from_json("{\"filters\": []}").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_diff_lines_up_filters_by_sstable() {
+        // Table 1 was compacted away, table 2 kept its filter, table 3 got
+        // a bigger one and table 4 is new
+        let before = summary(
+            &[3, 2, 1],
+            vec![
+                filter(10, 16, 0.02),
+                filter(10, 16, 0.02),
+                filter(10, 16, 0.05),
+            ],
+            (30, 10),
+        );
+        let after = summary(
+            &[4, 3, 2],
+            vec![
+                filter(10, 32, 0.01),
+                filter(10, 32, 0.01),
+                filter(10, 16, 0.02),
+            ],
+            (90, 10),
+        );
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.num_filters, 0);
+        assert_eq!(diff.total_size_bytes, 32);
+        assert_eq!(diff.total_items, 0);
+        assert!((diff.skip_rate - 0.15).abs() < 1e-12);
+        assert!((diff.mean_estimated_fpp - (0.04 / 3.0 - 0.09 / 3.0)).abs() < 1e-12);
+
+        let ids: Vec<usize> = diff.filters.iter().map(|f| f.sstable_id).collect();
+        assert_eq!(ids, [1, 3, 4]);
+        assert_eq!(diff.filters[0].after, None);
+        assert_eq!(diff.filters[0].size_bytes(), -16);
+        assert_eq!(diff.filters[1].size_bytes(), 16);
+        assert!((diff.filters[1].estimated_fpp() + 0.01).abs() < 1e-12);
+        assert_eq!(diff.filters[2].before, None);
+        assert_eq!(diff.filters[2].size_bytes(), 32);
+
+        assert!(before.diff(&before).filters.is_empty());
+    }
+}
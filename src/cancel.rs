@@ -0,0 +1,55 @@
+/// Stopping long operations from outside
+///
+/// A purge rewrites every SSTable, and an export or a scan of a big range
+/// reads them all; any of them can run for minutes. A `CancellationToken`
+/// passed to `LSMTree::purge_with`, `compact_for_with`,
+/// `export_snapshot_with` or `repair_with`, or `SSTableReader::verify_with`,
+/// or attached to a range or keys iterator with `with_cancellation`, is
+/// checked once per entry. Once it is cancelled,
+/// a compaction stops and fails with a `Cancelled` error, leaving the tree
+/// as it was: the half-written output table is deleted and the input
+/// tables stay live. An export fails the same way, its stream unfinished.
+/// An iterator simply ends, and says it was cancelled.
+///
+/// The token is a shared flag: clones cancel each other, and
+/// `as_flag` hands the flag to whatever sets it, such as a Ctrl-C handler.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::Cancelled;
+
+/// Shared flag asking an operation to stop (see `cancel`)
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// A token that isn't cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks every operation holding the token, or a clone of it, to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether `cancel` has been called (or the flag set)
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// The flag itself; setting it to `true` cancels the token
+    pub fn as_flag(&self) -> Arc<AtomicBool> {
+        self.0.clone()
+    }
+
+    /// Fails with `Cancelled` once the token is cancelled; `entries` is
+    /// how far the operation got
+    pub(crate) fn check(&self, entries: u64) -> std::io::Result<()> {
+        if self.is_cancelled() {
+            Err(Cancelled { entries }.into())
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,53 @@
+/// Application logic applied while SSTables are compacted
+///
+/// A `CompactionFilter` in `LSMConfig::compaction_filter` sees every entry
+/// that survives a compaction (`LSMTree::purge`) and decides whether it is
+/// kept as it is, dropped, or kept with a new value. That is where
+/// soft-deleted records can be cleaned out, or values migrated to a new
+/// schema, without a separate pass over the whole tree.
+///
+/// The filter only runs during compaction, never on flush, so until the
+/// next compaction reads still return the unfiltered values. A removed
+/// entry is gone for good, exactly as if it had been deleted; no older
+/// version of its key shows up again.
+///
+/// Filters must be deterministic: the same key and value always get the
+/// same decision. A compaction may ask more than once about the same entry
+/// (for instance when it trains a compression dictionary on the filtered
+/// values), and the tree relies on the answers agreeing.
+///
+/// Example:
+/// ```ignore
+/// #[derive(Debug)]
+/// struct DropSoftDeleted;
+///
+/// impl CompactionFilter for DropSoftDeleted {
+///     fn filter(&self, _key: &[u8], value: &[u8]) -> FilterDecision {
+///         if value.starts_with(b"deleted:") {
+///             FilterDecision::Remove
+///         } else {
+///             FilterDecision::Keep
+///         }
+///     }
+/// }
+/// ```
+use std::fmt::Debug;
+
+/// What a compaction does with an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// Write the entry unchanged
+    Keep,
+
+    /// Drop the entry, as if the key had been deleted
+    Remove,
+
+    /// Write the entry with this value instead
+    Replace(Vec<u8>),
+}
+
+/// Decides the fate of every entry that survives a compaction
+pub trait CompactionFilter: Send + Sync + Debug {
+    /// Returns the decision for a live entry (tombstones are never passed)
+    fn filter(&self, key: &[u8], value: &[u8]) -> FilterDecision;
+}
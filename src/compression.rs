@@ -0,0 +1,211 @@
+/// Value compression for SSTables
+///
+/// With the `zstd` feature, values can be stored Zstandard-compressed.
+/// Values in a key-value store tend to be small and similar to each other
+/// (JSON documents with the same fields, for instance), which leaves little
+/// for a compressor to find inside any single value. A dictionary trained
+/// on a sample of the table's values fixes that: it holds the common
+/// fragments once, in the file header, and every value is compressed
+/// against it. The dictionary is trained again for every new table (each
+/// flush and each purge), so it follows the data as it changes.
+///
+/// Keys are never compressed, so lookups and scans compare them without
+/// decompressing anything; only the values that are actually returned get
+/// decompressed.
+use std::borrow::Cow;
+use std::io::Read;
+
+/// How SSTable values are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Values are stored as they are (the default)
+    #[default]
+    None,
+
+    /// Every value is compressed with Zstandard at `level`
+    ///
+    /// With `dictionary`, a dictionary trained on a sample of the values is
+    /// stored in each table's header. A table with too few values to train
+    /// on (see `MIN_DICTIONARY_SAMPLES`) is compressed without one.
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32, dictionary: bool },
+}
+
+impl Compression {
+    /// Returns true if new tables should get a trained dictionary
+    pub fn wants_dictionary(&self) -> bool {
+        match self {
+            Compression::None => false,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { dictionary, .. } => *dictionary,
+        }
+    }
+}
+
+/// Fewest sampled values worth training a dictionary on
+pub const MIN_DICTIONARY_SAMPLES: usize = 64;
+
+/// Most values sampled for training
+#[cfg(feature = "zstd")]
+const MAX_DICTIONARY_SAMPLES: usize = 4096;
+
+/// Largest dictionary trained (16 KiB)
+#[cfg(feature = "zstd")]
+const MAX_DICTIONARY_SIZE: usize = 16 * 1024;
+
+/// Codec id stored in the header of compressed tables
+#[cfg(feature = "zstd")]
+const ZSTD_CODEC: u8 = 1;
+
+/// Trains a dictionary on an evenly spaced sample of `count` values
+///
+/// Returns `None` when there are fewer than `MIN_DICTIONARY_SAMPLES`
+/// non-empty values, or when training fails (typically because the values
+/// have too little in common); the table is then compressed without a
+/// dictionary.
+#[cfg(feature = "zstd")]
+pub(crate) fn train_dictionary<V: AsRef<[u8]>>(
+    values: impl IntoIterator<Item = V>,
+    count: usize,
+) -> Option<Vec<u8>> {
+    let stride = count.div_ceil(MAX_DICTIONARY_SAMPLES).max(1);
+    let samples: Vec<Vec<u8>> = values
+        .into_iter()
+        .step_by(stride)
+        .filter(|v| !v.as_ref().is_empty())
+        .map(|v| v.as_ref().to_vec())
+        .collect();
+    if samples.len() < MIN_DICTIONARY_SAMPLES {
+        return None;
+    }
+    let total: usize = samples.iter().map(Vec::len).sum();
+    // Training wants far more sample data than dictionary
+    let size = (total / 10).min(MAX_DICTIONARY_SIZE);
+    zstd::dict::from_samples(&samples, size).ok()
+}
+
+/// Without the `zstd` feature there is nothing to train
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn train_dictionary<V: AsRef<[u8]>>(
+    _values: impl IntoIterator<Item = V>,
+    _count: usize,
+) -> Option<Vec<u8>> {
+    None
+}
+
+/// Compresses the values of one table as it is written
+pub(crate) enum ValueEncoder {
+    Plain,
+    #[cfg(feature = "zstd")]
+    Zstd(Box<zstd::bulk::Compressor<'static>>),
+}
+
+impl ValueEncoder {
+    /// Returns the encoder for `compression` and the header section that
+    /// describes it (`None` for uncompressed tables, which keep the
+    /// version 2 header)
+    pub(crate) fn new(
+        compression: Compression,
+        dictionary: Option<&[u8]>,
+    ) -> std::io::Result<(Self, Option<Vec<u8>>)> {
+        match compression {
+            Compression::None => {
+                let _ = dictionary;
+                Ok((ValueEncoder::Plain, None))
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level, .. } => {
+                let dictionary = dictionary.unwrap_or_default();
+                let mut compressor = zstd::bulk::Compressor::with_dictionary(level, dictionary)?;
+                // The header already says which dictionary to use
+                compressor.include_dictid(false)?;
+
+                // [codec: u8][dict_len: u32][dict]
+                let mut header = vec![ZSTD_CODEC];
+                header.extend_from_slice(&(dictionary.len() as u32).to_le_bytes());
+                header.extend_from_slice(dictionary);
+                Ok((ValueEncoder::Zstd(Box::new(compressor)), Some(header)))
+            }
+        }
+    }
+
+    /// Returns the bytes to store for a value
+    pub(crate) fn encode<'a>(&mut self, value: &'a [u8]) -> std::io::Result<Cow<'a, [u8]>> {
+        match self {
+            ValueEncoder::Plain => Ok(Cow::Borrowed(value)),
+            #[cfg(feature = "zstd")]
+            ValueEncoder::Zstd(compressor) => Ok(Cow::Owned(compressor.compress(value)?)),
+        }
+    }
+}
+
+/// Decompresses the values of one table
+///
+/// The dictionary is prepared once when the table is opened and shared by
+/// every value read from it.
+pub(crate) enum ValueDecoder {
+    Plain,
+    #[cfg(feature = "zstd")]
+    Zstd(Option<Box<zstd::dict::DecoderDictionary<'static>>>),
+}
+
+impl ValueDecoder {
+    /// Reads the compression section of a version 3 or 4 header
+    pub(crate) fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut codec = [0u8; 1];
+        reader.read_exact(&mut codec)?;
+        let mut len_buf = [0u8; 4];
+        reader.read_exact(&mut len_buf)?;
+        let dict_len = u32::from_le_bytes(len_buf) as usize;
+        let mut dictionary = Vec::new();
+        crate::sstable::read_field(reader, dict_len, &mut dictionary)?;
+
+        match codec[0] {
+            // Version 4 tables always have the section
+            0 => Ok(ValueDecoder::Plain),
+            #[cfg(feature = "zstd")]
+            ZSTD_CODEC => {
+                Ok(ValueDecoder::Zstd((!dictionary.is_empty()).then(|| {
+                    Box::new(zstd::dict::DecoderDictionary::copy(&dictionary))
+                })))
+            }
+            #[cfg(not(feature = "zstd"))]
+            1 => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "SSTable is Zstandard-compressed, but this build lacks the `zstd` feature",
+            )),
+            other => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("SSTable uses unknown compression codec {}", other),
+            )),
+        }
+    }
+
+    /// Returns a value as it was written
+    ///
+    /// Output grows with what the data actually decompresses to rather
+    /// than trusting a size in the frame header.
+    pub(crate) fn decode(&self, stored: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        match self {
+            ValueDecoder::Plain => Ok(stored),
+            #[cfg(feature = "zstd")]
+            ValueDecoder::Zstd(dictionary) => {
+                let mut value = Vec::new();
+                match dictionary {
+                    Some(dictionary) => {
+                        zstd::stream::read::Decoder::with_prepared_dictionary(
+                            stored.as_slice(),
+                            dictionary,
+                        )?
+                        .read_to_end(&mut value)?;
+                    }
+                    None => {
+                        zstd::stream::read::Decoder::with_buffer(stored.as_slice())?
+                            .read_to_end(&mut value)?;
+                    }
+                }
+                Ok(value)
+            }
+        }
+    }
+}
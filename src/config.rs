@@ -0,0 +1,588 @@
+/// Configuration for opening an LSM tree
+///
+/// `LSMTree::new` and `LSMTree::with_bloom_filter_fpp` cover the common
+/// cases; `LSMConfig` exposes every knob. Start from `LSMConfig::new` and
+/// override the fields you care about:
+///
+/// ```ignore
+/// let config = LSMConfig {
+///     memtable_size_threshold: 64 * 1024,
+///     sync_on_flush: false,
+///     ..LSMConfig::new(PathBuf::from("./data"))
+/// };
+/// let lsm = LSMTree::open(config)?;
+/// ```
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::bloom::FilterFloor;
+use crate::clock::{Clock, SystemClock};
+use crate::compaction_filter::CompactionFilter;
+use crate::compression::Compression;
+use crate::events::{EventListener, NoopListener};
+use crate::flush_pause::DEFAULT_MAX_PAUSED_MEMTABLE_BYTES;
+use crate::flush_policy::FlushPolicy;
+use crate::memtable::MemtableBackend;
+use crate::storage::{FsStorage, Storage};
+
+/// Default memtable size before a flush (4 MiB)
+pub const DEFAULT_MEMTABLE_SIZE_THRESHOLD: usize = 4 * 1024 * 1024;
+
+/// Default false positive probability for Bloom filters (1%)
+pub const DEFAULT_BLOOM_FILTER_FPP: f64 = 0.01;
+
+/// Default size up to which an SSTable counts as small (16 KiB)
+pub const DEFAULT_SMALL_SSTABLE_MAX_BYTES: u64 = 16 * 1024;
+
+/// Default largest key accepted (1 MiB)
+pub const DEFAULT_MAX_KEY_SIZE: usize = 1024 * 1024;
+
+/// Default largest value accepted (256 MiB)
+pub const DEFAULT_MAX_VALUE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Default buffer for reading SSTables and the WAL (32 KiB)
+pub const DEFAULT_READ_BUFFER_BYTES: usize = 32 * 1024;
+
+/// Default buffer for writing SSTables (64 KiB)
+pub const DEFAULT_WRITE_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Largest read or write buffer accepted (64 MiB)
+pub const MAX_IO_BUFFER_BYTES: usize = 64 * 1024 * 1024;
+
+/// Whether SSTables get Bloom filters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterPolicy {
+    /// A Bloom filter per SSTable, kept in a `.bloom` file next to it and
+    /// sized for `LSMConfig::bloom_filter_fpp`
+    #[default]
+    Bloom,
+
+    /// No filters: new tables get no `.bloom` file, none are loaded or
+    /// rebuilt on open, and every lookup probes the tables themselves
+    ///
+    /// For tiny trees and scan-heavy workloads, where filters only cost
+    /// files, memory and startup time. Tables written with filters keep
+    /// their `.bloom` files but they go unused; switching back to `Bloom`
+    /// rebuilds the filters of tables written without one on open.
+    None,
+}
+
+/// How the Bloom filter of a table written from merged input is sized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterSizing {
+    /// Up front, from `SSTableWriterOptions::expected_items`; a compaction
+    /// passes the number of entries in its input tables, an upper bound
+    /// that over-provisions the filter for every duplicate or dropped key
+    Expected,
+
+    /// When the table is finished, from the exact number of distinct keys
+    /// written (see `DeferredBloomFilter`); costs 16 bytes of memory per
+    /// key while the table is written
+    #[default]
+    Exact,
+}
+
+/// What a read with `ReadOptions::verify_checksums` does when the record
+/// it is about to read turns out damaged
+///
+/// Either way the record is listed in `LSMTree::corruption_report` and
+/// reported to `EventListener::on_corrupt_read`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CorruptionPolicy {
+    /// Fail the read with `InvalidData`
+    #[default]
+    Error,
+
+    /// Skip the table and keep looking in older ones, which may hold an
+    /// older but intact version of the key
+    ///
+    /// A stale value beats no value for some callers, never for others;
+    /// only choose this if yours is the first kind.
+    FallbackToOlder,
+}
+
+/// What writing pairs from outside the tree does with a key given more
+/// than once in a row (see `LSMConfig::ingest_dedup`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DedupPolicy {
+    /// Fail with `InvalidData`, naming the key
+    #[default]
+    Error,
+
+    /// Keep the value given last
+    LastWins,
+
+    /// Keep the value given first
+    FirstWins,
+}
+
+/// Which SSTables belong in `LSMConfig::cold_dir`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColdPolicy {
+    /// Tables written by a compaction; flushed and ingested tables stay
+    /// in `data_dir`
+    #[default]
+    Compacted,
+
+    /// Tables whose newest write is older than this (measured with
+    /// `LSMConfig::clock`); tables without write times never qualify
+    OlderThan(Duration),
+}
+
+/// Largest key and value sizes the tree accepts
+///
+/// Writes over a limit are rejected. On recovery, a logged length over a
+/// limit is taken as corruption rather than trusted with an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeLimits {
+    /// Largest key, in bytes
+    pub max_key_size: usize,
+
+    /// Largest value, in bytes
+    pub max_value_size: usize,
+}
+
+impl SizeLimits {
+    /// Rejects a key or value larger than the limits
+    ///
+    /// Recovery treats such lengths as corruption, so accepting the write
+    /// would lose it (and everything logged after it) on the next open.
+    pub(crate) fn check(&self, key: &[u8], value: Option<&[u8]>) -> std::io::Result<()> {
+        let too_large = |what: &str, len: usize, max: usize| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} of {} bytes exceeds the limit of {} bytes",
+                    what, len, max
+                ),
+            )
+        };
+        if key.len() > self.max_key_size {
+            return Err(too_large("key", key.len(), self.max_key_size));
+        }
+        if let Some(value) = value
+            && value.len() > self.max_value_size
+        {
+            return Err(too_large("value", value.len(), self.max_value_size));
+        }
+        Ok(())
+    }
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        Self {
+            max_key_size: DEFAULT_MAX_KEY_SIZE,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+        }
+    }
+}
+
+/// A setting that can be changed while the tree is open
+/// (`LSMTree::set_option`)
+///
+/// A change applies to what the tree does from then on: a new threshold
+/// is checked after the next write, a new false positive rate sizes the
+/// filters of tables written later, and so on. Nothing already on disk is
+/// rewritten.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TreeOption {
+    /// `LSMConfig::memtable_size_threshold` (at least 1; unused while a
+    /// `flush_policy` is set)
+    MemtableSizeThreshold(usize),
+
+    /// `LSMConfig::bloom_filter_fpp` (between 0 and 1)
+    BloomFilterFpp(f64),
+
+    /// `LSMConfig::sync_writes`
+    SyncWrites(bool),
+
+    /// `LSMConfig::sync_on_flush`
+    SyncOnFlush(bool),
+
+    /// `LSMConfig::read_amp_warn_threshold` (positive when set)
+    ReadAmpWarnThreshold(Option<f64>),
+
+    /// `LSMConfig::flush_after_idle`
+    FlushAfterIdle(Option<Duration>),
+}
+
+impl TreeOption {
+    /// Checks the new value, returning what is wrong with it
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match *self {
+            TreeOption::MemtableSizeThreshold(0) => {
+                Err("memtable size threshold must be at least 1 byte".to_string())
+            }
+            TreeOption::BloomFilterFpp(fpp) if !(fpp > 0.0 && fpp < 1.0) => Err(format!(
+                "false positive rate {} is not between 0 and 1",
+                fpp
+            )),
+            TreeOption::ReadAmpWarnThreshold(Some(threshold))
+                if threshold.is_nan() || threshold <= 0.0 =>
+            {
+                Err(format!(
+                    "read amplification threshold {} is not positive",
+                    threshold
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Stores the value in `config`, returning the option as it was
+    pub(crate) fn apply(self, config: &mut LSMConfig) -> TreeOption {
+        match self {
+            TreeOption::MemtableSizeThreshold(v) => TreeOption::MemtableSizeThreshold(
+                std::mem::replace(&mut config.memtable_size_threshold, v),
+            ),
+            TreeOption::BloomFilterFpp(v) => {
+                TreeOption::BloomFilterFpp(std::mem::replace(&mut config.bloom_filter_fpp, v))
+            }
+            TreeOption::SyncWrites(v) => {
+                TreeOption::SyncWrites(std::mem::replace(&mut config.sync_writes, v))
+            }
+            TreeOption::SyncOnFlush(v) => {
+                TreeOption::SyncOnFlush(std::mem::replace(&mut config.sync_on_flush, v))
+            }
+            TreeOption::ReadAmpWarnThreshold(v) => TreeOption::ReadAmpWarnThreshold(
+                std::mem::replace(&mut config.read_amp_warn_threshold, v),
+            ),
+            TreeOption::FlushAfterIdle(v) => {
+                TreeOption::FlushAfterIdle(std::mem::replace(&mut config.flush_after_idle, v))
+            }
+        }
+    }
+}
+
+/// Settings used when opening an LSM tree
+#[derive(Debug, Clone)]
+pub struct LSMConfig {
+    /// Directory holding the SSTables, Bloom filters, manifest and WAL
+    pub data_dir: PathBuf,
+
+    /// Directory for the write-ahead log, if it should live somewhere other
+    /// than `data_dir` (for example on a faster device)
+    ///
+    /// The manifest remembers this setting. Reopening a tree with a
+    /// different WAL directory fails instead of starting with an empty log,
+    /// which would silently lose unflushed writes.
+    pub wal_dir: Option<PathBuf>,
+
+    /// Directory for cold SSTables (`None` to keep every table in
+    /// `data_dir`), for example on a larger, slower volume
+    ///
+    /// Compactions write their output here when `cold_policy` says it is
+    /// cold, and `LSMTree::migrate_cold` moves tables that have become cold
+    /// since. Flushes always write to `data_dir`. The manifest records
+    /// which tables are cold; like `wal_dir`, reopening a tree that has
+    /// cold tables with a different directory fails.
+    pub cold_dir: Option<PathBuf>,
+
+    /// Which SSTables go to `cold_dir`
+    pub cold_policy: ColdPolicy,
+
+    /// Maximum size in bytes before the memtable flushes to disk
+    ///
+    /// Ignored for flush decisions when `flush_policy` is set; still the
+    /// size the TUI measures the memtable against.
+    pub memtable_size_threshold: usize,
+
+    /// Decides after each write whether to flush (`None` to flush at
+    /// `memtable_size_threshold`)
+    pub flush_policy: Option<Arc<dyn FlushPolicy>>,
+
+    /// Data structure the memtable keeps its entries in
+    pub memtable_backend: MemtableBackend,
+
+    /// Number of write shards (1, the default, for a plain tree)
+    ///
+    /// More than one is opened with `ShardedLSMTree::open`: keys are split
+    /// by hash across that many memtables, each with its own WAL and
+    /// flushed on its own, so writers to different shards don't wait for
+    /// each other. `LSMTree::open` refuses such a config.
+    pub write_shards: usize,
+
+    /// Whether SSTables get Bloom filters
+    pub filter_policy: FilterPolicy,
+
+    /// Target false positive rate for Bloom filters
+    pub bloom_filter_fpp: f64,
+
+    /// Smallest Bloom filter a new SSTable gets (10 bits per key and 64
+    /// bytes by default)
+    ///
+    /// Small tables would otherwise miss `bloom_filter_fpp` (see
+    /// `FilterFloor`). Tables of many keys only reach the floor with a
+    /// rate of about 1% or looser; `FilterFloor::NONE` sizes every filter
+    /// by the rate alone.
+    pub bloom_filter_floor: FilterFloor,
+
+    /// How compactions size the Bloom filter of the table they write
+    pub compaction_filter_sizing: FilterSizing,
+
+    /// Maximum bytes of Bloom filters kept in memory (`None` for no limit)
+    ///
+    /// Over the budget, the least recently used filters are dropped and
+    /// read back from their `.bloom` files when a lookup needs them again.
+    /// Pinned filters (`LSMTree::pin_filter`) are never dropped.
+    pub bloom_filter_memory_budget: Option<usize>,
+
+    /// Bytes of small SSTables to hold entirely in memory (`None` to read
+    /// every table from disk)
+    ///
+    /// Tables no larger than `small_sstable_max_bytes` are loaded, newest
+    /// first while they fit, and lookups that reach them never open the
+    /// file. See `LSMTree::pinned_sstable_stats`.
+    pub pin_small_sstables_bytes: Option<usize>,
+
+    /// Largest SSTable file `pin_small_sstables_bytes` applies to
+    pub small_sstable_max_bytes: u64,
+
+    /// Merge small SSTables when the tree is opened, once more than this
+    /// many are no larger than `small_table_bytes` (`None` to leave them)
+    ///
+    /// Bursts of tiny flushes leave dozens of tables of a few entries,
+    /// each probed by every lookup that gets past the memtable. Every run
+    /// of adjacent small tables (in read order) becomes one table, before
+    /// the tree serves anything; `OpenReport` says how many went. A
+    /// read-only tree never merges.
+    pub small_table_merge_threshold: Option<usize>,
+
+    /// Largest SSTable file `small_table_merge_threshold` counts as small
+    pub small_table_bytes: u64,
+
+    /// Fsync new SSTables, Bloom filters and the data directory before the
+    /// WAL is cleared on flush
+    ///
+    /// Without it a power failure right after a flush can lose the flushed
+    /// data, because the WAL that would have recovered it is already gone.
+    /// Turning it off trades that guarantee for faster flushes.
+    pub sync_on_flush: bool,
+
+    /// Fsync the WAL after every write (or batch) before it returns
+    ///
+    /// Off by default: a write then reaches the operating system, which
+    /// survives the process crashing but not a power failure. Single
+    /// writes can override this with `WriteOptions::sync`.
+    pub sync_writes: bool,
+
+    /// Flush a non-empty memtable once no write has arrived for this long
+    /// (`None` to only flush at the size threshold)
+    ///
+    /// Checked by `LSMTree::maintenance_tick`, which has to be called
+    /// periodically. Without it, a quiet tree keeps its last writes only in
+    /// the WAL until the next flush or a clean drop, and a restart after a
+    /// kill has to replay them.
+    pub flush_after_idle: Option<Duration>,
+
+    /// Memtable size past which a flush happens even while automatic
+    /// flushes are paused (see `flush_pause`; 64 MiB by default)
+    pub max_paused_memtable_bytes: usize,
+
+    /// Age after which entries expire (`None` to keep them forever)
+    ///
+    /// Expired entries are dropped by compaction, and SSTables holding
+    /// nothing else are deleted by `LSMTree::maintenance_tick`; until then
+    /// reads still return them. Ages are measured with `clock`. See
+    /// `expiry`.
+    pub ttl: Option<Duration>,
+
+    /// Turn a put into a no-op when the memtable already holds exactly
+    /// that value for the key
+    ///
+    /// Saves the WAL record and memtable churn of rewriting unchanged
+    /// values. Only the memtable is compared, so a put of a value that is
+    /// only on disk is still written. A skipped put takes no sequence
+    /// number: the entry keeps the sequence and write time of the write
+    /// that stored it, and under `ttl` it ages from that write. Counted in
+    /// `LSMTree::skipped_identical_writes`.
+    pub skip_identical_writes: bool,
+
+    /// With `skip_identical_writes` and a `ttl`, write identical puts
+    /// anyway so they restart the entry's age
+    ///
+    /// Refreshing an age takes a new write, so with this set nothing is
+    /// skipped while a TTL is configured.
+    pub identical_writes_refresh_ttl: bool,
+
+    /// Largest keys and values accepted by writes
+    ///
+    /// WAL replay stops at the first entry whose lengths exceed these, so
+    /// lowering them below sizes already in an unflushed log drops those
+    /// entries and everything after them.
+    pub size_limits: SizeLimits,
+
+    /// Bytes buffered per file when reading SSTables (lookups, scans,
+    /// compaction inputs, integrity checks and Bloom filter rebuilds) and
+    /// replaying the WAL
+    ///
+    /// Each open table gets its own buffer, so a scan or compaction over
+    /// many tables holds that many. Larger buffers mean fewer reads for
+    /// long sequential passes; a point lookup in a small table reads a
+    /// whole buffer's worth for nothing. Between 1 byte and
+    /// `MAX_IO_BUFFER_BYTES`.
+    pub read_buffer_bytes: usize,
+
+    /// Bytes buffered when writing SSTables (flushes, compactions,
+    /// migrations and ingests)
+    ///
+    /// WAL appends aren't buffered: each entry is handed to the operating
+    /// system in one write as it happens, whatever this says. Between 1
+    /// byte and `MAX_IO_BUFFER_BYTES`.
+    pub write_buffer_bytes: usize,
+
+    /// How values are stored in new SSTables
+    ///
+    /// Applies to tables written from now on (flushes, purges and
+    /// migrations); existing tables keep their format and stay readable.
+    /// Compressed tables need a build with the `zstd` feature to be read.
+    pub compression: Compression,
+
+    /// Application logic run on every entry a compaction keeps (`None` to
+    /// keep them all unchanged)
+    ///
+    /// Not applied on flush. See `compaction_filter` for what a filter may
+    /// and must not do.
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+
+    /// What to do with a damaged SSTable found when opening the tree
+    ///
+    /// Every table is checked on open (non-empty, no truncated record, keys
+    /// in order). With `paranoid_checks` the open fails; without it the
+    /// table is renamed to `<name>.db.corrupt`, left out of the tree and
+    /// listed in `LSMTree::quarantined_sstables`.
+    ///
+    /// The same applies to a Bloom filter that has to be rebuilt but can't
+    /// be: the open fails, or the table is kept without a filter and is
+    /// always probed (see `LSMTree::filterless_sstables`).
+    pub paranoid_checks: bool,
+
+    /// What a verified read does with a damaged record (see
+    /// `CorruptionPolicy`)
+    pub on_corruption: CorruptionPolicy,
+
+    /// What `LSMTree::import_snapshot` does with a key that comes twice
+    ///
+    /// Pairs written straight into SSTables from outside the tree must
+    /// come in strictly ascending key order. A key lower than the one
+    /// before it always fails the import with `InvalidData`; a repeated
+    /// key fails it too unless this says which value to keep.
+    pub ingest_dedup: DedupPolicy,
+
+    /// Open the tree without modifying anything on disk
+    ///
+    /// No directories, WAL or manifest are created or updated, missing
+    /// Bloom filters are not rebuilt (those tables are simply always
+    /// probed), and damaged tables are skipped rather than renamed. Writes,
+    /// flushes and migrations fail with `PermissionDenied`.
+    pub read_only: bool,
+
+    /// Average number of SSTables probed per get above which the tree asks
+    /// for compaction (`None` to never ask)
+    ///
+    /// A table counts as probed when a get has to look at it at all, even
+    /// if its Bloom filter rules the key out. The average is a moving one
+    /// over recent `get`s; above the threshold `LSMTree::needs_compaction`
+    /// returns true and `EventListener::on_read_amp_high` is called.
+    pub read_amp_warn_threshold: Option<f64>,
+
+    /// Projected WAL replay time after a crash above which
+    /// `EventListener::on_recovery_estimate_high` is called (`None` to
+    /// never call it)
+    ///
+    /// The projection is `LSMTree::recovery_estimate`, checked after every
+    /// write. A flush clears the WAL and brings it back to zero.
+    pub recovery_warn_threshold: Option<Duration>,
+
+    /// Receives events such as high read amplification
+    pub event_listener: Arc<dyn EventListener>,
+
+    /// Where durability calls (fsync) are sent
+    pub storage: Arc<dyn Storage>,
+
+    /// Where the current time comes from
+    pub clock: Arc<dyn Clock>,
+}
+
+impl LSMConfig {
+    /// Returns the default configuration for a data directory
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            data_dir,
+            wal_dir: None,
+            cold_dir: None,
+            cold_policy: ColdPolicy::default(),
+            memtable_size_threshold: DEFAULT_MEMTABLE_SIZE_THRESHOLD,
+            flush_policy: None,
+            memtable_backend: MemtableBackend::default(),
+            write_shards: 1,
+            filter_policy: FilterPolicy::default(),
+            bloom_filter_fpp: DEFAULT_BLOOM_FILTER_FPP,
+            bloom_filter_floor: FilterFloor::default(),
+            compaction_filter_sizing: FilterSizing::default(),
+            bloom_filter_memory_budget: None,
+            pin_small_sstables_bytes: None,
+            small_sstable_max_bytes: DEFAULT_SMALL_SSTABLE_MAX_BYTES,
+            small_table_merge_threshold: None,
+            small_table_bytes: DEFAULT_SMALL_SSTABLE_MAX_BYTES,
+            sync_on_flush: true,
+            sync_writes: false,
+            flush_after_idle: None,
+            max_paused_memtable_bytes: DEFAULT_MAX_PAUSED_MEMTABLE_BYTES,
+            ttl: None,
+            skip_identical_writes: false,
+            identical_writes_refresh_ttl: false,
+            size_limits: SizeLimits::default(),
+            read_buffer_bytes: test_buffer_bytes().unwrap_or(DEFAULT_READ_BUFFER_BYTES),
+            write_buffer_bytes: test_buffer_bytes().unwrap_or(DEFAULT_WRITE_BUFFER_BYTES),
+            compression: Compression::None,
+            compaction_filter: None,
+            paranoid_checks: true,
+            on_corruption: CorruptionPolicy::default(),
+            ingest_dedup: DedupPolicy::default(),
+            read_only: false,
+            read_amp_warn_threshold: None,
+            recovery_warn_threshold: None,
+            event_listener: Arc::new(NoopListener),
+            storage: Arc::new(FsStorage),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Returns the directory the WAL lives in (`wal_dir`, or `data_dir`)
+    pub fn resolved_wal_dir(&self) -> &Path {
+        self.wal_dir.as_deref().unwrap_or(&self.data_dir)
+    }
+
+    /// Checks `read_buffer_bytes` and `write_buffer_bytes`
+    pub(crate) fn check_buffer_sizes(&self) -> Result<(), String> {
+        for (name, bytes) in [
+            ("read_buffer_bytes", self.read_buffer_bytes),
+            ("write_buffer_bytes", self.write_buffer_bytes),
+        ] {
+            if !(1..=MAX_IO_BUFFER_BYTES).contains(&bytes) {
+                return Err(format!(
+                    "{} is {}; it must be between 1 and {}",
+                    name, bytes, MAX_IO_BUFFER_BYTES
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Buffer size for both read and write buffers in test builds, from the
+/// `LSM_TEST_IO_BUFFER_BYTES` environment variable, so the whole test suite
+/// can run with tiny buffers
+fn test_buffer_bytes() -> Option<usize> {
+    #[cfg(test)]
+    if let Some(bytes) = std::env::var("LSM_TEST_IO_BUFFER_BYTES")
+        .ok()
+        .and_then(|bytes| bytes.parse().ok())
+    {
+        return Some(bytes);
+    }
+    None
+}
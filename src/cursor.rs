@@ -0,0 +1,699 @@
+/// Cursor: seekable, bidirectional navigation over the merged view
+///
+/// `LSMTree::range` only walks forward from a start key. A `Cursor` can be
+/// positioned anywhere (`seek` for the first live key at or after a key,
+/// `seek_for_prev` for the last one at or before it) and then moved in
+/// either direction with `next` and `prev`, the way a database cursor or a
+/// LevelDB iterator is used.
+///
+/// Like the range iterator it merges every layer (memtable, then SSTables
+/// newest to oldest), lets the newest version of a key shadow the older
+/// ones and never stops on a deleted key. Going forward is the usual k-way
+/// merge on the smallest key; going backward merges on the largest key
+/// instead. All layers sit on the same side of the current key, so when
+/// the direction changes each of them is sought to the other side of it
+/// first.
+///
+/// An SSTable is only stored as a forward sequence of records, so a table
+/// is read a block of its footer's index at a time: a seek reads the block
+/// the key falls in, found through the index, and stepping past either end
+/// of a block reads the one beside it. Only one block of each table is
+/// held, and nothing is read before the first seek. Blocks start at a
+/// restart point, so each is read from its start without the keys before
+/// it; a table without a footer is a single block.
+///
+/// Example:
+/// ```ignore
+/// let mut cursor = lsm.cursor();
+/// // Newest ten users, from the last one down
+/// cursor.seek_for_prev(b"user;");
+/// for _ in 0..10 {
+///     let Some(key) = cursor.key() else { break };
+///     println!("{:?}", key);
+///     cursor.prev();
+/// }
+/// ```
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::path::PathBuf;
+
+use crate::memtable::Memtable;
+use crate::sstable::SSTableReader;
+use crate::storage::Storage;
+
+/// A key and its newest value in one source (`None` = tombstone)
+type Entry = (Vec<u8>, Option<Vec<u8>>);
+
+/// The memtable, stepped through by key
+struct MemtableSource<'a> {
+    memtable: &'a Memtable,
+    entry: Option<Entry>,
+}
+
+impl MemtableSource<'_> {
+    fn seek_forward(&mut self, bound: Bound<&[u8]>) {
+        let start = bound.map(<[u8]>::to_vec);
+        self.entry = self.memtable.range((start, Bound::Unbounded)).next();
+    }
+
+    fn seek_backward(&mut self, bound: Bound<&[u8]>) {
+        let end = bound.map(<[u8]>::to_vec);
+        self.entry = self.memtable.range((Bound::Unbounded, end)).next_back();
+    }
+
+    fn step_forward(&mut self) {
+        if let Some((key, _)) = self.entry.take() {
+            self.seek_forward(Bound::Excluded(&key));
+        }
+    }
+
+    fn step_backward(&mut self) {
+        if let Some((key, _)) = self.entry.take() {
+            self.seek_backward(Bound::Excluded(&key));
+        }
+    }
+}
+
+/// One SSTable, read a block of its footer's index at a time
+struct TableSource {
+    /// None when the table could not be opened (treated as empty, like `get`)
+    reader: Option<SSTableReader>,
+    /// Where every block starts, first to last
+    blocks: Vec<u64>,
+    /// The block `entries` holds, once one is read
+    block: Option<usize>,
+    /// The newest version of every key of that block, in key order
+    entries: Vec<Entry>,
+    /// Position in `entries` of the current entry
+    index: Option<usize>,
+    entry: Option<Entry>,
+}
+
+impl TableSource {
+    /// Opens a table and reads its block index; no record is read yet
+    fn open(path: &std::path::Path, storage: &dyn Storage, buffer_bytes: usize) -> Self {
+        let mut reader = SSTableReader::open_with(path, storage, buffer_bytes).ok();
+        let blocks = reader
+            .as_mut()
+            .and_then(|reader| Some(reader.blocks_through(None).ok()?.0))
+            .unwrap_or_default();
+        Self {
+            reader,
+            blocks,
+            block: None,
+            entries: Vec::new(),
+            index: None,
+            entry: None,
+        }
+    }
+
+    /// Reads block `block` into `entries`, unless it is there already
+    ///
+    /// A damaged record ends its block, as it ends a range scan; the
+    /// blocks after it are still read.
+    fn load_block(&mut self, block: usize) {
+        if self.block == Some(block) {
+            return;
+        }
+        let end = self.blocks.get(block + 1).copied();
+        let start = self.blocks[block];
+        self.entries = self
+            .reader
+            .as_mut()
+            .and_then(|reader| reader.read_block(start, end).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, _, value)| (key, value))
+            .collect();
+        self.block = Some(block);
+    }
+
+    /// The block to start looking for `key` in (the first one for a key
+    /// before every block)
+    fn block_for(&mut self, key: &[u8]) -> usize {
+        let offset = self
+            .reader
+            .as_mut()
+            .and_then(|reader| reader.block_for(key).ok().flatten());
+        offset.map_or(0, |offset| {
+            self.blocks
+                .partition_point(|&start| start <= offset)
+                .saturating_sub(1)
+        })
+    }
+
+    /// Makes the entry at `index` of block `block` the current one, or the
+    /// first entry of a later block if that block has no more
+    fn land_forward(&mut self, mut block: usize, mut index: usize) {
+        while block < self.blocks.len() {
+            self.load_block(block);
+            if index < self.entries.len() {
+                self.set(Some(index));
+                return;
+            }
+            block += 1;
+            index = 0;
+        }
+        self.set(None);
+    }
+
+    /// Makes the last of the first `count` entries of block `block` the
+    /// current one, or the last entry of an earlier block if there are none
+    fn land_backward(&mut self, mut block: usize, mut count: usize) {
+        loop {
+            if count > 0 {
+                self.set(Some(count - 1));
+                return;
+            }
+            let Some(earlier) = block.checked_sub(1) else {
+                self.set(None);
+                return;
+            };
+            block = earlier;
+            self.load_block(block);
+            count = self.entries.len();
+        }
+    }
+
+    fn set(&mut self, index: Option<usize>) {
+        self.index = index;
+        self.entry = index.map(|i| self.entries[i].clone());
+    }
+
+    /// Number of entries of the loaded block ordered before `target` (or
+    /// at it, with `or_equal`)
+    fn partition(&self, target: &[u8], or_equal: bool) -> usize {
+        self.entries.partition_point(|(key, _)| {
+            key.as_slice() < target || (or_equal && key.as_slice() == target)
+        })
+    }
+
+    fn seek_forward(&mut self, bound: Bound<&[u8]>) {
+        let (key, or_equal) = match bound {
+            Bound::Included(key) => (key, false),
+            Bound::Excluded(key) => (key, true),
+            Bound::Unbounded => return self.land_forward(0, 0),
+        };
+        let block = self.block_for(key);
+        if block >= self.blocks.len() {
+            return self.set(None);
+        }
+        self.load_block(block);
+        let index = self.partition(key, or_equal);
+        self.land_forward(block, index);
+    }
+
+    fn seek_backward(&mut self, bound: Bound<&[u8]>) {
+        let (key, or_equal) = match bound {
+            Bound::Included(key) => (key, true),
+            Bound::Excluded(key) => (key, false),
+            Bound::Unbounded => {
+                let Some(last) = self.blocks.len().checked_sub(1) else {
+                    return self.set(None);
+                };
+                self.load_block(last);
+                return self.land_backward(last, self.entries.len());
+            }
+        };
+        let block = self.block_for(key);
+        if block >= self.blocks.len() {
+            return self.set(None);
+        }
+        self.load_block(block);
+        let count = self.partition(key, or_equal);
+        self.land_backward(block, count);
+    }
+
+    fn step_forward(&mut self) {
+        if let (Some(block), Some(index)) = (self.block, self.index) {
+            self.land_forward(block, index + 1);
+        }
+    }
+
+    fn step_backward(&mut self) {
+        if let (Some(block), Some(index)) = (self.block, self.index) {
+            self.land_backward(block, index);
+        }
+    }
+}
+
+/// A single sorted input to the cursor
+enum Source<'a> {
+    Memtable(MemtableSource<'a>),
+    // Boxed: a table's reader is much bigger than the memtable's view
+    SSTable(Box<TableSource>),
+}
+
+impl Source<'_> {
+    fn entry(&self) -> Option<&Entry> {
+        match self {
+            Source::Memtable(source) => source.entry.as_ref(),
+            Source::SSTable(source) => source.entry.as_ref(),
+        }
+    }
+
+    fn is_at(&self, key: &[u8]) -> bool {
+        self.entry().is_some_and(|(k, _)| k == key)
+    }
+
+    /// Moves to the first key matching `bound` from below (`Unbounded` =
+    /// the first key)
+    fn seek_forward(&mut self, bound: Bound<&[u8]>) {
+        match self {
+            Source::Memtable(source) => source.seek_forward(bound),
+            Source::SSTable(source) => source.seek_forward(bound),
+        }
+    }
+
+    /// Moves to the last key matching `bound` from above (`Unbounded` =
+    /// the last key)
+    fn seek_backward(&mut self, bound: Bound<&[u8]>) {
+        match self {
+            Source::Memtable(source) => source.seek_backward(bound),
+            Source::SSTable(source) => source.seek_backward(bound),
+        }
+    }
+
+    fn step_forward(&mut self) {
+        match self {
+            Source::Memtable(source) => source.step_forward(),
+            Source::SSTable(source) => source.step_forward(),
+        }
+    }
+
+    fn step_backward(&mut self) {
+        match self {
+            Source::Memtable(source) => source.step_backward(),
+            Source::SSTable(source) => source.step_backward(),
+        }
+    }
+}
+
+/// A position in the merged view of a tree (see `LSMTree::cursor`)
+///
+/// A new cursor is not positioned; call one of the seek methods first.
+/// Every move returns whether the cursor ended up on a live key. Once it
+/// runs off either end it stays invalid until the next seek.
+///
+/// The cursor borrows the tree, so it sees the tree exactly as it was when
+/// the cursor was created.
+pub struct Cursor<'a> {
+    /// Memtable first, then SSTables newest first; on equal keys the lower
+    /// index is the newer version
+    sources: Vec<Source<'a>>,
+    /// Whether the sources sit at or after the current key (moving
+    /// forward) or at or before it (moving backward)
+    forward: bool,
+    current: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(
+        memtable: &'a Memtable,
+        sstables: &[PathBuf],
+        storage: &dyn Storage,
+        buffer_bytes: usize,
+    ) -> Self {
+        let mut sources = Vec::with_capacity(sstables.len() + 1);
+        sources.push(Source::Memtable(MemtableSource {
+            memtable,
+            entry: None,
+        }));
+        for path in sstables {
+            sources.push(Source::SSTable(Box::new(TableSource::open(
+                path,
+                storage,
+                buffer_bytes,
+            ))));
+        }
+        Self {
+            sources,
+            forward: true,
+            current: None,
+        }
+    }
+
+    /// Returns true if the cursor is on a live key
+    pub fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Key the cursor is on
+    pub fn key(&self) -> Option<&[u8]> {
+        self.current.as_ref().map(|(key, _)| key.as_slice())
+    }
+
+    /// Value of the key the cursor is on
+    pub fn value(&self) -> Option<&[u8]> {
+        self.current.as_ref().map(|(_, value)| value.as_slice())
+    }
+
+    /// Moves to the first live key
+    pub fn seek_to_first(&mut self) -> bool {
+        self.seek_forward(Bound::Unbounded)
+    }
+
+    /// Moves to the last live key
+    pub fn seek_to_last(&mut self) -> bool {
+        self.seek_backward(Bound::Unbounded)
+    }
+
+    /// Moves to the first live key at or after `key`
+    pub fn seek(&mut self, key: &[u8]) -> bool {
+        self.seek_forward(Bound::Included(key))
+    }
+
+    /// Moves to the last live key at or before `key`
+    pub fn seek_for_prev(&mut self, key: &[u8]) -> bool {
+        self.seek_backward(Bound::Included(key))
+    }
+
+    /// Moves to the next live key
+    ///
+    /// Named like `Iterator::next`, but it only moves; the entry is read
+    /// through `key` and `value`, as with `prev`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> bool {
+        let Some((key, _)) = self.current.take() else {
+            return false;
+        };
+        if self.forward {
+            self.step_forward_from(&key);
+        } else {
+            // The sources are at or before the key; bring them all past it
+            for source in &mut self.sources {
+                source.seek_forward(Bound::Excluded(&key));
+            }
+            self.forward = true;
+        }
+        self.settle_forward()
+    }
+
+    /// Moves to the previous live key
+    pub fn prev(&mut self) -> bool {
+        let Some((key, _)) = self.current.take() else {
+            return false;
+        };
+        if self.forward {
+            // The sources are at or after the key; bring them all before it
+            for source in &mut self.sources {
+                source.seek_backward(Bound::Excluded(&key));
+            }
+            self.forward = false;
+        } else {
+            self.step_backward_from(&key);
+        }
+        self.settle_backward()
+    }
+
+    fn seek_forward(&mut self, bound: Bound<&[u8]>) -> bool {
+        for source in &mut self.sources {
+            source.seek_forward(bound);
+        }
+        self.forward = true;
+        self.settle_forward()
+    }
+
+    fn seek_backward(&mut self, bound: Bound<&[u8]>) -> bool {
+        for source in &mut self.sources {
+            source.seek_backward(bound);
+        }
+        self.forward = false;
+        self.settle_backward()
+    }
+
+    fn step_forward_from(&mut self, key: &[u8]) {
+        for source in &mut self.sources {
+            if source.is_at(key) {
+                source.step_forward();
+            }
+        }
+    }
+
+    fn step_backward_from(&mut self, key: &[u8]) {
+        for source in &mut self.sources {
+            if source.is_at(key) {
+                source.step_backward();
+            }
+        }
+    }
+
+    /// Returns the entry of the newest source holding the smallest key
+    /// (`Ordering::Less`) or the largest one (`Ordering::Greater`)
+    fn newest_at_edge(&self, edge: Ordering) -> Option<Entry> {
+        let mut best: Option<&Entry> = None;
+        for entry in self.sources.iter().filter_map(Source::entry) {
+            // Only a strictly better key replaces an earlier (newer) source
+            if best.is_none_or(|(key, _)| entry.0.cmp(key) == edge) {
+                best = Some(entry);
+            }
+        }
+        best.cloned()
+    }
+
+    /// Lands on the smallest live key the sources are at, skipping keys
+    /// whose newest version is a tombstone
+    fn settle_forward(&mut self) -> bool {
+        self.current = None;
+        while let Some((key, value)) = self.newest_at_edge(Ordering::Less) {
+            if let Some(value) = value {
+                self.current = Some((key, value));
+                return true;
+            }
+            self.step_forward_from(&key);
+        }
+        false
+    }
+
+    /// Like `settle_forward`, on the largest key
+    fn settle_backward(&mut self) -> bool {
+        self.current = None;
+        while let Some((key, value)) = self.newest_at_edge(Ordering::Greater) {
+            if let Some(value) = value {
+                self.current = Some((key, value));
+                return true;
+            }
+            self.step_backward_from(&key);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::LSMTree;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::ops::Bound;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_cursor_matches_reference_map() {
+        let dir = PathBuf::from("./test_cursor_reference");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        let mut reference = BTreeMap::new();
+
+        // Deterministic pseudo-random puts and deletes over a small key
+        // space, flushed now and then so every key is spread over several
+        // tables and the memtable. A pinned sequence keeps older versions
+        // in the tables for the cursor to skip over.
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let key = |n: u64| format!("key{:02}", n % 60).into_bytes();
+        lsm.pin_sequence();
+        for round in 0..1600 {
+            let n = next();
+            if n.is_multiple_of(3) {
+                lsm.delete(key(n)).unwrap();
+                reference.remove(&key(n));
+            } else {
+                let value = format!("v{}", round).into_bytes();
+                lsm.put(key(n), value.clone()).unwrap();
+                reference.insert(key(n), value);
+            }
+            if round % 250 == 249 {
+                lsm.flush().unwrap();
+            }
+        }
+        assert!(lsm.sstable_count() >= 5);
+        assert!(lsm.memtable_size() > 0);
+
+        let mut cursor = lsm.cursor();
+        assert!(!cursor.valid());
+        assert!(!cursor.next());
+        let mut expected: Option<Vec<u8>> = None;
+        for _ in 0..5000 {
+            let n = next();
+            // Targets include keys outside the key space on both ends
+            let target = format!("key{:02}", n % 64).into_bytes();
+            let (moved, found) = match n % 6 {
+                0 => (
+                    cursor.seek(&target),
+                    reference.range(target.clone()..).next(),
+                ),
+                1 => (
+                    cursor.seek_for_prev(&target),
+                    reference.range(..=target.clone()).next_back(),
+                ),
+                2 => (cursor.seek_to_first(), reference.iter().next()),
+                3 => (cursor.seek_to_last(), reference.iter().next_back()),
+                4 => (
+                    cursor.next(),
+                    expected.as_ref().and_then(|key| {
+                        reference
+                            .range((Bound::Excluded(key.clone()), Bound::Unbounded))
+                            .next()
+                    }),
+                ),
+                _ => (
+                    cursor.prev(),
+                    expected.as_ref().and_then(|key| {
+                        reference
+                            .range((Bound::Unbounded, Bound::Excluded(key.clone())))
+                            .next_back()
+                    }),
+                ),
+            };
+            assert_eq!(moved, found.is_some());
+            assert_eq!(cursor.key(), found.map(|(key, _)| key.as_slice()));
+            assert_eq!(cursor.value(), found.map(|(_, value)| value.as_slice()));
+            expected = found.map(|(key, _)| key.clone());
+        }
+
+        // A full walk in each direction sees exactly the live keys
+        let mut forward = Vec::new();
+        let mut more = cursor.seek_to_first();
+        while more {
+            forward.push(cursor.key().unwrap().to_vec());
+            more = cursor.next();
+        }
+        assert_eq!(forward, reference.keys().cloned().collect::<Vec<_>>());
+        let mut backward = Vec::new();
+        let mut more = cursor.seek_to_last();
+        while more {
+            backward.push(cursor.key().unwrap().to_vec());
+            more = cursor.prev();
+        }
+        backward.reverse();
+        assert_eq!(backward, forward);
+
+        drop(cursor);
+        drop(lsm);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_cursor_reads_tables_a_block_at_a_time() {
+        let dir = PathBuf::from("./test_cursor_blocks");
+        fs::remove_dir_all(&dir).ok();
+
+        // Tables of many blocks, with overwrites and deletes across them
+        let mut lsm = LSMTree::new(dir.clone(), 1 << 20).unwrap();
+        let mut reference = BTreeMap::new();
+        let key = |n: u32| format!("key{:03}", n).into_bytes();
+        for round in 0..4u32 {
+            for i in 0..150u32 {
+                let n = (i * 7 + round * 31) % 400;
+                if (i + round) % 6 == 0 {
+                    lsm.delete(key(n)).unwrap();
+                    reference.remove(&key(n));
+                } else {
+                    let value = vec![b'a' + round as u8; 100 + n as usize];
+                    lsm.put(key(n), value.clone()).unwrap();
+                    reference.insert(key(n), value);
+                }
+            }
+            if round < 3 {
+                lsm.flush().unwrap();
+            }
+        }
+
+        let mut cursor = lsm.cursor();
+        let held = |cursor: &super::Cursor| {
+            cursor
+                .sources
+                .iter()
+                .filter_map(|source| match source {
+                    super::Source::SSTable(table) => {
+                        Some((table.blocks.len(), table.entries.len()))
+                    }
+                    super::Source::Memtable(_) => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        // Nothing is read before the first seek
+        assert!(
+            held(&cursor)
+                .iter()
+                .all(|&(blocks, entries)| blocks > 3 && entries == 0)
+        );
+
+        let targets = [
+            b"key000".to_vec(),
+            b"key123x".to_vec(),
+            b"key250".to_vec(),
+            b"key999".to_vec(),
+        ];
+        for target in &targets {
+            let mut walked = Vec::new();
+            let mut more = cursor.seek(target);
+            while more && walked.len() < 40 {
+                walked.push((
+                    cursor.key().unwrap().to_vec(),
+                    cursor.value().unwrap().to_vec(),
+                ));
+                more = cursor.next();
+            }
+            let expected: Vec<_> = reference
+                .range(target.clone()..)
+                .take(40)
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            assert_eq!(walked, expected, "from {:?}", target);
+
+            let mut walked = Vec::new();
+            let mut more = cursor.seek_for_prev(target);
+            while more && walked.len() < 40 {
+                walked.push(cursor.key().unwrap().to_vec());
+                more = cursor.prev();
+            }
+            let expected: Vec<_> = reference
+                .range(..=target.clone())
+                .rev()
+                .take(40)
+                .map(|(key, _)| key.clone())
+                .collect();
+            assert_eq!(walked, expected, "back from {:?}", target);
+            // No table holds more than a block's worth of its keys
+            assert!(held(&cursor).iter().all(|&(_, entries)| entries < 40));
+        }
+
+        // Full walks cross every block boundary in both directions
+        let mut forward = Vec::new();
+        let mut more = cursor.seek_to_first();
+        while more {
+            forward.push(cursor.key().unwrap().to_vec());
+            more = cursor.next();
+        }
+        assert_eq!(forward, reference.keys().cloned().collect::<Vec<_>>());
+        let mut backward = Vec::new();
+        let mut more = cursor.seek_to_last();
+        while more {
+            backward.push(cursor.key().unwrap().to_vec());
+            more = cursor.prev();
+        }
+        forward.reverse();
+        assert_eq!(backward, forward);
+        drop(cursor);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
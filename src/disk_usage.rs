@@ -0,0 +1,166 @@
+/// Where the bytes of a tree's directory go (see `LSMTree::disk_usage`)
+///
+/// Every file under the data directory is statted and put in one class:
+///
+/// - the live SSTables (`sstable_<n>.db`) and their Bloom filters
+/// - the WAL (`wal.log`, which may live in `LSMConfig::wal_dir`)
+/// - the metadata files: `MANIFEST`, `FORMAT` and `LOCK`
+/// - everything else: `*.tmp` files left by a crash mid-write, SSTables
+///   the tree no longer lists (including quarantined `*.corrupt` ones, and
+///   tables whose deletion waits for a `TreeState`), and files the tree
+///   never wrote
+///
+/// Only `wal.log` is counted in a separate WAL directory, and only the live
+/// tables and their filters in `LSMConfig::cold_dir`; whatever else is in
+/// there isn't the tree's.
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::format::FORMAT_FILE;
+use crate::lock::LOCK_FILE;
+use crate::manifest::MANIFEST_FILE;
+
+/// Bytes on disk per kind of file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiskUsage {
+    /// Live SSTables
+    pub sstable_bytes: u64,
+
+    /// Bloom filters of live SSTables
+    pub bloom_bytes: u64,
+
+    /// The write-ahead log
+    pub wal_bytes: u64,
+
+    /// `MANIFEST`, `FORMAT` and `LOCK`
+    pub manifest_bytes: u64,
+
+    /// Temporary files and files the tree doesn't use
+    pub tmp_or_orphan_bytes: u64,
+
+    /// Sum of the above
+    pub total: u64,
+
+    /// The files counted in `tmp_or_orphan_bytes`
+    pub orphan_files: Vec<PathBuf>,
+}
+
+impl DiskUsage {
+    /// What to do about orphaned files, if there are any
+    pub fn suggestion(&self) -> Option<String> {
+        if self.tmp_or_orphan_bytes == 0 {
+            return None;
+        }
+        Some(format!(
+            "{} bytes in {} files the tree doesn't use: inspect and remove them, \
+             or run `lsm-cli repair` if SSTables are missing from the manifest",
+            self.tmp_or_orphan_bytes,
+            self.orphan_files.len()
+        ))
+    }
+
+    /// Appends the byte counts as one Prometheus gauge labelled by kind
+    pub(crate) fn write_prometheus(&self, out: &mut String) {
+        use std::fmt::Write as _;
+
+        let name = "lsm_disk_bytes";
+        let _ = writeln!(out, "# HELP {} Bytes on disk per kind of file", name);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for (kind, bytes) in [
+            ("sstable", self.sstable_bytes),
+            ("bloom", self.bloom_bytes),
+            ("wal", self.wal_bytes),
+            ("manifest", self.manifest_bytes),
+            ("orphan", self.tmp_or_orphan_bytes),
+        ] {
+            let _ = writeln!(out, "{}{{kind=\"{}\"}} {}", name, kind, bytes);
+        }
+    }
+}
+
+impl fmt::Display for DiskUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = [
+            ("sstables", self.sstable_bytes),
+            ("bloom filters", self.bloom_bytes),
+            ("wal", self.wal_bytes),
+            ("manifest", self.manifest_bytes),
+            ("tmp/orphan", self.tmp_or_orphan_bytes),
+        ];
+        let total = self.total.max(1) as f64;
+        for (name, bytes) in rows {
+            writeln!(
+                f,
+                "{:<14} {:>12} bytes {:>6.1}%",
+                name,
+                bytes,
+                bytes as f64 * 100.0 / total
+            )?;
+        }
+        writeln!(f, "{:<14} {:>12} bytes", "total", self.total)?;
+        if let Some(suggestion) = self.suggestion() {
+            writeln!(f, "suggestion: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stats every file under `data_dir` (and the WAL, wherever it is)
+///
+/// `sstables` are the paths of the live tables.
+pub(crate) fn measure(
+    data_dir: &Path,
+    wal_path: &Path,
+    sstables: &[PathBuf],
+) -> std::io::Result<DiskUsage> {
+    let tables: HashSet<&Path> = sstables.iter().map(PathBuf::as_path).collect();
+    let filters: HashSet<PathBuf> = sstables.iter().map(|p| p.with_extension("bloom")).collect();
+    let metadata: Vec<PathBuf> = [MANIFEST_FILE, FORMAT_FILE, LOCK_FILE]
+        .iter()
+        .map(|name| data_dir.join(name))
+        .collect();
+
+    let mut usage = DiskUsage::default();
+    let mut pending = vec![data_dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let bytes = meta.len();
+            if tables.contains(path.as_path()) {
+                usage.sstable_bytes += bytes;
+            } else if filters.contains(&path) {
+                usage.bloom_bytes += bytes;
+            } else if path == wal_path {
+                usage.wal_bytes += bytes;
+            } else if metadata.contains(&path) {
+                usage.manifest_bytes += bytes;
+            } else {
+                usage.tmp_or_orphan_bytes += bytes;
+                usage.orphan_files.push(path);
+            }
+        }
+    }
+    let file_size = |path: &Path| std::fs::metadata(path).map_or(0, |m| m.len());
+    // A WAL or cold tables outside the data directory weren't walked
+    if !wal_path.starts_with(data_dir) {
+        usage.wal_bytes = file_size(wal_path);
+    }
+    for table in sstables.iter().filter(|path| !path.starts_with(data_dir)) {
+        usage.sstable_bytes += file_size(table);
+        usage.bloom_bytes += file_size(&table.with_extension("bloom"));
+    }
+    usage.orphan_files.sort();
+    usage.total = usage.sstable_bytes
+        + usage.bloom_bytes
+        + usage.wal_bytes
+        + usage.manifest_bytes
+        + usage.tmp_or_orphan_bytes;
+    Ok(usage)
+}
@@ -0,0 +1,272 @@
+/// Which operation failed, on which path (`LsmError`)
+///
+/// The tree returns `std::io::Result` throughout, and a bare `io::Error`
+/// from deep inside an open ("Permission denied (os error 13)") doesn't say
+/// what the tree was doing or where. Opening, flushing and compacting wrap
+/// their failures in an `LsmError` naming the operation and the path. The
+/// wrapper becomes the inner error of the `io::Error` and keeps its kind, so
+/// code matching on `kind()` sees no difference; code that wants the
+/// details gets them back with `LsmError::from_io`.
+///
+/// Writes refused because repeated WAL failures poisoned the tree fail with
+/// a `Poisoned` error instead (kind `Other`, see `LSMTree::is_poisoned`),
+/// lookups over their `ReadOptions::max_tables_probed` budget with a
+/// `ReadBudgetExceeded` one (also `Other`), and every call on a tree whose
+/// directory was deleted or replaced while it was open with a
+/// `TreeDeleted` one (also `Other`, see `LSMTree::is_poisoned`). A purge
+/// stopped by its `CancellationToken` fails with `Cancelled` (kind
+/// `Interrupted`), which no `LsmError` wraps.
+///
+/// Example:
+/// ```ignore
+/// let Err(e) = LSMTree::new(dir, 4096) else { /* opened */ };
+/// if let Some(err) = LsmError::from_io(&e) {
+///     eprintln!("{} failed on {}", err.operation, err.path.display());
+/// }
+/// ```
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// What the tree was doing when an I/O error happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Creating the data or WAL directory
+    CreateDir,
+
+    /// Taking the directory lock
+    LockDir,
+
+    /// Reading the data directory: its manifest and SSTables
+    ReadDir,
+
+    /// Replaying or opening the WAL
+    OpenWal,
+
+    /// Writing the memtable into a new SSTable
+    Flush,
+
+    /// Merging SSTables
+    Compact,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::CreateDir => "create_dir",
+            Operation::LockDir => "lock_dir",
+            Operation::ReadDir => "read_dir",
+            Operation::OpenWal => "open_wal",
+            Operation::Flush => "flush",
+            Operation::Compact => "compact",
+        })
+    }
+}
+
+/// An I/O error with the operation and path it happened on
+#[derive(Debug)]
+pub struct LsmError {
+    pub operation: Operation,
+
+    /// The file or directory the operation worked on
+    pub path: PathBuf,
+
+    /// The underlying error
+    pub source: std::io::Error,
+}
+
+impl LsmError {
+    /// The `LsmError` inside an `io::Error` returned by the tree, if any
+    pub fn from_io(error: &std::io::Error) -> Option<&LsmError> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for LsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.operation,
+            self.path.display(),
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for LsmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<LsmError> for std::io::Error {
+    fn from(error: LsmError) -> Self {
+        std::io::Error::new(error.source.kind(), error)
+    }
+}
+
+/// A write refused because WAL failures poisoned the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Poisoned {
+    /// WAL failures in a row when the tree was poisoned
+    pub failures: u32,
+
+    /// The last of them
+    pub cause: String,
+}
+
+impl Poisoned {
+    /// The `Poisoned` inside an `io::Error` returned by a write, if any
+    pub fn from_io(error: &std::io::Error) -> Option<&Poisoned> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Poisoned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tree poisoned after {} failed WAL writes (last: {}); call clear_poison to resume",
+            self.failures, self.cause
+        )
+    }
+}
+
+impl std::error::Error for Poisoned {}
+
+impl From<Poisoned> for std::io::Error {
+    fn from(poisoned: Poisoned) -> Self {
+        std::io::Error::other(poisoned)
+    }
+}
+
+/// An operation stopped by its `CancellationToken` (see `cancel`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled {
+    /// Entries the operation had processed when it stopped
+    pub entries: u64,
+}
+
+impl Cancelled {
+    /// The `Cancelled` inside an `io::Error` returned by the tree, if any
+    pub fn from_io(error: &std::io::Error) -> Option<&Cancelled> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cancelled after {} entries", self.entries)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl From<Cancelled> for std::io::Error {
+    fn from(cancelled: Cancelled) -> Self {
+        std::io::Error::new(std::io::ErrorKind::Interrupted, cancelled)
+    }
+}
+
+/// An operation on a tree whose directory was deleted, or replaced by
+/// another tree, while it was open
+///
+/// Found when an operation fails with an I/O error and the directory's
+/// FORMAT file no longer carries the tree's id. From then on the tree is
+/// poisoned and every call that can fail returns this error; only opening
+/// the directory again (or the new tree in it) gets anywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeDeleted {
+    /// The tree's data directory
+    pub data_dir: PathBuf,
+
+    /// The I/O error that gave it away
+    pub cause: String,
+}
+
+impl TreeDeleted {
+    /// The `TreeDeleted` inside an `io::Error` returned by the tree, if
+    /// any
+    pub fn from_io(error: &std::io::Error) -> Option<&TreeDeleted> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for TreeDeleted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tree at {} was deleted or replaced while open (first failure: {}); reopen it",
+            self.data_dir.display(),
+            self.cause
+        )
+    }
+}
+
+impl std::error::Error for TreeDeleted {}
+
+impl From<TreeDeleted> for std::io::Error {
+    fn from(deleted: TreeDeleted) -> Self {
+        std::io::Error::other(deleted)
+    }
+}
+
+/// A lookup given up because it would have consulted more SSTables than
+/// `ReadOptions::max_tables_probed` allows
+///
+/// The key may or may not be in the tables left; many of them usually
+/// means compaction is behind (see `LSMTree::read_budget_exceeded`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadBudgetExceeded {
+    /// SSTables consulted before giving up
+    pub probed: usize,
+}
+
+impl ReadBudgetExceeded {
+    /// The `ReadBudgetExceeded` inside an `io::Error` returned by a
+    /// lookup, if any
+    pub fn from_io(error: &std::io::Error) -> Option<&ReadBudgetExceeded> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for ReadBudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "read budget exceeded: key not found in {} SSTables",
+            self.probed
+        )
+    }
+}
+
+impl std::error::Error for ReadBudgetExceeded {}
+
+impl From<ReadBudgetExceeded> for std::io::Error {
+    fn from(exceeded: ReadBudgetExceeded) -> Self {
+        std::io::Error::other(exceeded)
+    }
+}
+
+/// Adds operation and path context to the error of a result
+pub(crate) trait Context<T> {
+    fn context(self, operation: Operation, path: &Path) -> std::io::Result<T>;
+}
+
+impl<T> Context<T> for std::io::Result<T> {
+    fn context(self, operation: Operation, path: &Path) -> std::io::Result<T> {
+        self.map_err(|source| {
+            // The innermost context is the most precise; keep it. A
+            // cancellation didn't fail on any path
+            if LsmError::from_io(&source).is_some() || Cancelled::from_io(&source).is_some() {
+                return source;
+            }
+            LsmError {
+                operation,
+                path: path.to_path_buf(),
+                source,
+            }
+            .into()
+        })
+    }
+}
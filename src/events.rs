@@ -0,0 +1,92 @@
+/// Notifications about conditions an operator may want to act on
+///
+/// The tree calls an `EventListener` (set in `LSMConfig::event_listener`)
+/// synchronously from the operation that noticed the condition, so a
+/// listener should only record or forward the event and return; it must
+/// not call back into the tree. Every method has an empty default, so a
+/// listener implements only the events it cares about.
+use std::fmt::Debug;
+use std::time::Duration;
+
+use crate::config::TreeOption;
+use crate::recovery::RecoveryEstimate;
+use crate::verify::CorruptRead;
+
+/// Receiver of tree events
+pub trait EventListener: Send + Sync + Debug {
+    /// The moving average of SSTables probed per `get` rose above
+    /// `LSMConfig::read_amp_warn_threshold`
+    ///
+    /// Called once each time the average crosses the threshold, not on
+    /// every read while it stays above (`LSMTree::needs_compaction` tells
+    /// whether it still is).
+    fn on_read_amp_high(&self, _event: &ReadAmpEvent) {}
+
+    /// A setting was changed with `LSMTree::set_option`
+    fn on_option_changed(&self, _event: &OptionChangeEvent) {}
+
+    /// The memtable outgrew `LSMConfig::max_paused_memtable_bytes` while
+    /// automatic flushes were paused, and is being flushed anyway
+    fn on_flush_pause_overridden(&self, _event: &FlushPauseEvent) {}
+
+    /// A read with `ReadOptions::verify_checksums` found a damaged record
+    /// (once per table and key; see `LSMTree::corruption_report`)
+    fn on_corrupt_read(&self, _event: &CorruptRead) {}
+
+    /// The projected WAL replay time after a crash rose above
+    /// `LSMConfig::recovery_warn_threshold`
+    ///
+    /// Called once each time the projection crosses the threshold. A flush
+    /// brings it back down; the listener can't call one itself, but can
+    /// tell whoever owns the tree that one is due.
+    fn on_recovery_estimate_high(&self, _event: &RecoveryEstimateEvent) {}
+}
+
+/// Listener that ignores every event (the default)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopListener;
+
+impl EventListener for NoopListener {}
+
+/// Details of `EventListener::on_read_amp_high`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadAmpEvent {
+    /// Moving average of SSTables probed per get
+    pub average: f64,
+
+    /// The configured threshold it exceeded
+    pub threshold: f64,
+
+    /// Number of live SSTables at the time
+    pub sstable_count: usize,
+}
+
+/// Details of `EventListener::on_flush_pause_overridden`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushPauseEvent {
+    /// Memtable size that forced the flush
+    pub memtable_bytes: usize,
+
+    /// The configured cap it passed
+    pub max_paused_memtable_bytes: usize,
+}
+
+/// Details of `EventListener::on_recovery_estimate_high`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecoveryEstimateEvent {
+    /// The estimate that crossed the threshold
+    pub estimate: RecoveryEstimate,
+
+    /// The configured threshold it exceeded
+    pub threshold: Duration,
+}
+
+/// Details of `EventListener::on_option_changed`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptionChangeEvent {
+    /// The setting before the change
+    pub previous: TreeOption,
+
+    /// The setting now in effect
+    pub current: TreeOption,
+}
@@ -0,0 +1,60 @@
+/// Expiry of old data under `LSMConfig::ttl`
+///
+/// With a TTL, entries written longer ago than the TTL are expired. They
+/// are removed in two ways, neither of which touches the read path, so
+/// reads may keep returning an expired entry until one of them gets to it:
+///
+/// - compaction (`LSMTree::purge`) drops expired entries like deleted ones
+/// - `LSMTree::maintenance_tick` deletes SSTables whose entries have all
+///   expired outright, without reading or merging them
+///
+/// Whether a table has expired comes from the write times in its header
+/// (see `value_meta`): its earliest and latest write bound when its entries
+/// expire. Tables written before write times existed, and tables holding
+/// writes recovered from the WAL, never count as fully expired.
+///
+/// Only the oldest tables are deleted whole, and only as long as every
+/// table older than them goes too. Sequence numbers grow with time, so an
+/// older table can't hold an entry written after a fully expired one; the
+/// rule just makes sure a deleted table never uncovers an older version of
+/// one of its keys.
+///
+/// Tables with at least half their writes expired are worth compacting
+/// before they expire entirely; `LSMTree::needs_compaction` asks for it.
+use crate::handle::SSTableId;
+
+/// Share of its writes past which a table counts as mostly expired
+pub const MOSTLY_EXPIRED_FRACTION: f64 = 0.5;
+
+/// How much of the tree has expired (see `LSMTree::expired_estimate`)
+///
+/// Counts are of write sequence numbers recorded in the tables' write
+/// times, which include versions since overwritten, so they are an
+/// estimate of entries rather than an exact count.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExpiryEstimate {
+    /// Writes recorded in the SSTables' write times
+    pub timed_writes: u64,
+
+    /// Of those, the writes older than the TTL
+    pub expired_writes: u64,
+
+    /// Tables the next maintenance tick deletes, oldest first: the oldest
+    /// tables whose entries have all expired
+    pub fully_expired: Vec<SSTableId>,
+
+    /// The other tables with at least `MOSTLY_EXPIRED_FRACTION` of their
+    /// writes expired, oldest first
+    pub mostly_expired: Vec<SSTableId>,
+}
+
+impl ExpiryEstimate {
+    /// Share of the timed writes that have expired (0.0 without any)
+    pub fn expired_fraction(&self) -> f64 {
+        if self.timed_writes == 0 {
+            0.0
+        } else {
+            self.expired_writes as f64 / self.timed_writes as f64
+        }
+    }
+}
@@ -0,0 +1,365 @@
+/// In-memory Bloom filters of the live SSTables, under an optional budget
+///
+/// Every SSTable has a `.bloom` file next to it (unless
+/// `LSMConfig::filter_policy` turns filters off). Keeping all filters in
+/// memory makes lookups cheapest, but the filters of a large tree can take
+/// a lot of memory. With a budget (`LSMConfig::bloom_filter_memory_budget`)
+/// only the most recently used filters stay resident; the others are
+/// dropped and loaded again from disk the next time a lookup needs them.
+///
+/// Filters can also be pinned, which loads them and exempts them from
+/// budget eviction (useful for the hottest tables), or evicted by hand.
+///
+/// Slots are indexed like `LSMTree::sstables`, newest table first.
+/// Filters are shared, so a read snapshot (`TreeState`) keeps using the
+/// ones resident when it was taken even after they are evicted here.
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::bloom::BloomFilter;
+
+/// Where an SSTable's Bloom filter currently lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterResidency {
+    /// Loaded and subject to budget eviction
+    Resident,
+
+    /// Loaded and exempt from budget eviction
+    Pinned,
+
+    /// Only in the `.bloom` file; loaded again on the next lookup
+    OnDisk,
+
+    /// No usable filter at all; the table is probed on every lookup
+    Missing,
+}
+
+/// One SSTable's filter slot
+struct Slot {
+    /// The filter, when it is in memory
+    filter: Option<Arc<BloomFilter>>,
+
+    /// Whether a readable `.bloom` file backs the slot, so the filter can
+    /// be dropped and loaded again
+    on_disk: bool,
+
+    pinned: bool,
+
+    /// Tick of the most recent use, for least-recently-used eviction
+    last_used: u64,
+}
+
+/// Where a slot comes from when the table list is rearranged (see
+/// `FilterCache::relayout`)
+pub(crate) enum SlotSource {
+    /// The slot at this index of the current cache, kept with its filter
+    /// and pin
+    Existing(usize),
+
+    /// A table new to the cache, with the filter loaded for it (`None`
+    /// when it has no usable filter)
+    New(Option<BloomFilter>),
+}
+
+/// Bloom filters of all live SSTables plus their memory budget
+#[derive(Default)]
+pub(crate) struct FilterCache {
+    slots: Vec<Slot>,
+
+    /// Maximum bytes of unpinned and pinned filters in memory (`None` for
+    /// no limit). Pinned filters count towards it but are never evicted.
+    budget: Option<usize>,
+
+    /// Use counter driving `Slot::last_used`
+    tick: u64,
+}
+
+impl FilterCache {
+    /// Builds the cache from the filters loaded on open, newest table first
+    ///
+    /// `None` marks a table without a usable filter. If the filters exceed
+    /// the budget, the oldest tables lose theirs first.
+    pub(crate) fn new(filters: Vec<Option<BloomFilter>>, budget: Option<usize>) -> Self {
+        let count = filters.len() as u64;
+        let slots = filters
+            .into_iter()
+            .enumerate()
+            .map(|(i, filter)| Slot {
+                on_disk: filter.is_some(),
+                filter: filter.map(Arc::new),
+                pinned: false,
+                // Newer tables count as more recently used
+                last_used: count - i as u64,
+            })
+            .collect();
+        let mut cache = Self {
+            slots,
+            budget,
+            tick: count,
+        };
+        cache.enforce_budget(None);
+        cache
+    }
+
+    /// Adds the filter of a newly flushed table in front of the others
+    /// (`None` for a table written without one)
+    pub(crate) fn insert_front(&mut self, filter: Option<BloomFilter>) {
+        let last_used = self.next_tick();
+        self.slots.insert(
+            0,
+            Slot {
+                on_disk: filter.is_some(),
+                filter: filter.map(Arc::new),
+                pinned: false,
+                last_used,
+            },
+        );
+        self.enforce_budget(Some(0));
+    }
+
+    /// Replaces the filter of a rewritten table, keeping its pin
+    pub(crate) fn replace(&mut self, index: usize, filter: Option<BloomFilter>) {
+        let last_used = self.next_tick();
+        let slot = &mut self.slots[index];
+        slot.on_disk = filter.is_some();
+        slot.filter = filter.map(Arc::new);
+        slot.last_used = last_used;
+        self.enforce_budget(Some(index));
+    }
+
+    /// Rearranges the slots for a new table list, newest table first
+    ///
+    /// Slots of tables that are not mentioned are dropped.
+    pub(crate) fn relayout(&mut self, sources: Vec<SlotSource>) {
+        let mut old: Vec<Option<Slot>> = std::mem::take(&mut self.slots)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut slots = Vec::with_capacity(sources.len());
+        for source in sources {
+            slots.push(match source {
+                SlotSource::Existing(index) => old[index].take().expect("slot reused twice"),
+                SlotSource::New(filter) => Slot {
+                    on_disk: filter.is_some(),
+                    filter: filter.map(Arc::new),
+                    pinned: false,
+                    last_used: self.next_tick(),
+                },
+            });
+        }
+        self.slots = slots;
+        self.enforce_budget(None);
+    }
+
+    /// Returns the filter if it is in memory, without loading it
+    pub(crate) fn resident(&self, index: usize) -> Option<&BloomFilter> {
+        self.slots.get(index)?.filter.as_deref()
+    }
+
+    /// Returns a shared handle to the filter if it is in memory
+    pub(crate) fn resident_shared(&self, index: usize) -> Option<Arc<BloomFilter>> {
+        self.slots.get(index)?.filter.clone()
+    }
+
+    /// Iterates over the filters currently in memory
+    pub(crate) fn resident_filters(&self) -> impl Iterator<Item = (usize, &BloomFilter)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| Some((i, slot.filter.as_deref()?)))
+    }
+
+    /// Returns the filter for a lookup, loading it from disk if needed
+    ///
+    /// Returns `None` when the table has no usable filter. A `.bloom` file
+    /// that can no longer be read leaves the table filterless rather than
+    /// failing the lookup.
+    pub(crate) fn get_or_load(
+        &mut self,
+        index: usize,
+        sstable_path: &Path,
+    ) -> Option<&BloomFilter> {
+        let tick = self.next_tick();
+        let slot = self.slots.get_mut(index)?;
+        slot.last_used = tick;
+        if slot.filter.is_none() && slot.on_disk {
+            match load(sstable_path) {
+                Some(filter) => {
+                    slot.filter = Some(Arc::new(filter));
+                    self.enforce_budget(Some(index));
+                }
+                None => self.slots[index].on_disk = false,
+            }
+        }
+        self.slots[index].filter.as_deref()
+    }
+
+    /// Checks a key against a table's filter without changing the cache
+    ///
+    /// A resident filter is used as is, without counting as a use; an
+    /// evicted one is read from disk for this check only. Returns `None`
+    /// when the table has no usable filter.
+    pub(crate) fn might_contain_without_filling(
+        &self,
+        index: usize,
+        sstable_path: &Path,
+        key: &[u8],
+    ) -> Option<bool> {
+        let slot = self.slots.get(index)?;
+        match &slot.filter {
+            Some(filter) => Some(filter.might_contain(key)),
+            None if slot.on_disk => load(sstable_path).map(|filter| filter.might_contain(key)),
+            None => None,
+        }
+    }
+
+    /// Loads a filter and exempts it from budget eviction
+    pub(crate) fn pin(&mut self, index: usize, sstable_path: &Path) -> std::io::Result<()> {
+        if self.get_or_load(index, sstable_path).is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("SSTable {} has no Bloom filter", sstable_path.display()),
+            ));
+        }
+        self.slots[index].pinned = true;
+        // Pinning may push the other filters over the budget
+        self.enforce_budget(Some(index));
+        Ok(())
+    }
+
+    /// Makes a pinned filter subject to budget eviction again
+    pub(crate) fn unpin(&mut self, index: usize) {
+        self.slots[index].pinned = false;
+        self.enforce_budget(None);
+    }
+
+    /// Drops a filter from memory and unpins it; the `.bloom` file stays
+    /// and the filter is loaded again by the next lookup that needs it
+    pub(crate) fn evict(&mut self, index: usize) {
+        let slot = &mut self.slots[index];
+        if slot.on_disk {
+            slot.filter = None;
+        }
+        slot.pinned = false;
+    }
+
+    /// Reports where a table's filter currently lives
+    pub(crate) fn residency(&self, index: usize) -> FilterResidency {
+        let slot = &self.slots[index];
+        match (&slot.filter, slot.pinned) {
+            (Some(_), true) => FilterResidency::Pinned,
+            (Some(_), false) => FilterResidency::Resident,
+            (None, _) if slot.on_disk => FilterResidency::OnDisk,
+            (None, _) => FilterResidency::Missing,
+        }
+    }
+
+    /// Returns true if the table has no usable filter at all
+    pub(crate) fn is_missing(&self, index: usize) -> bool {
+        self.residency(index) == FilterResidency::Missing
+    }
+
+    /// Total size of the filters in memory
+    pub(crate) fn resident_bytes(&self) -> usize {
+        self.resident_filters().map(|(_, bf)| bf.size_bytes()).sum()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn reverse(&mut self) {
+        self.slots.reverse();
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Evicts least recently used filters until the budget is met
+    ///
+    /// Pinned filters, filters without a `.bloom` file to reload them from,
+    /// and the slot at `keep` (the one just used) are never evicted, so the
+    /// budget can be exceeded when those alone are larger.
+    fn enforce_budget(&mut self, keep: Option<usize>) {
+        let Some(budget) = self.budget else {
+            return;
+        };
+        let mut resident = self.resident_bytes();
+        while resident > budget {
+            let victim = self
+                .slots
+                .iter()
+                .enumerate()
+                .filter(|&(i, slot)| {
+                    slot.filter.is_some() && slot.on_disk && !slot.pinned && Some(i) != keep
+                })
+                .min_by_key(|(_, slot)| slot.last_used)
+                .map(|(i, _)| i);
+            let Some(victim) = victim else {
+                break;
+            };
+            let filter = self.slots[victim].filter.take();
+            resident -= filter.map_or(0, |bf| bf.size_bytes());
+        }
+    }
+}
+
+/// Loads the `.bloom` file that belongs to an SSTable
+pub(crate) fn load(sstable_path: &Path) -> Option<BloomFilter> {
+    let file = File::open(sstable_path.with_extension("bloom")).ok()?;
+    BloomFilter::read_from(&mut BufReader::new(file)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(size_bytes: usize) -> BloomFilter {
+        BloomFilter::with_params(size_bytes * 8, 3).unwrap()
+    }
+
+    #[test]
+    fn test_budget_evicts_least_recently_used() {
+        let path = Path::new("./test_filter_cache_never_exists.db");
+        let filters = (0..4).map(|_| Some(filter(100))).collect();
+        let mut cache = FilterCache::new(filters, Some(250));
+
+        // Only the two newest tables fit
+        let states: Vec<_> = (0..4).map(|i| cache.residency(i)).collect();
+        assert_eq!(
+            states,
+            [
+                FilterResidency::Resident,
+                FilterResidency::Resident,
+                FilterResidency::OnDisk,
+                FilterResidency::OnDisk,
+            ]
+        );
+
+        // A failed reload leaves the table filterless instead of failing
+        assert!(cache.get_or_load(3, path).is_none());
+        assert_eq!(cache.residency(3), FilterResidency::Missing);
+
+        cache.insert_front(Some(filter(100)));
+        assert_eq!(cache.slots.len(), 5);
+        assert_eq!(cache.residency(0), FilterResidency::Resident);
+        assert_eq!(cache.resident_bytes(), 200);
+        assert_eq!(cache.residency(2), FilterResidency::OnDisk);
+    }
+
+    #[test]
+    fn test_unbudgeted_cache_keeps_everything() {
+        let mut cache = FilterCache::new(vec![Some(filter(100)), None], None);
+        cache.insert_front(Some(filter(1000)));
+
+        assert_eq!(cache.resident_bytes(), 1100);
+        assert!(cache.is_missing(2));
+
+        cache.evict(0);
+        assert_eq!(cache.residency(0), FilterResidency::OnDisk);
+        assert!(cache.resident(0).is_none());
+        cache.evict(2);
+        assert!(cache.is_missing(2));
+    }
+}
@@ -0,0 +1,76 @@
+/// Holding off automatic flushes during bulk writes
+///
+/// A tree flushes its memtable whenever the flush policy says it is full,
+/// so a bulk load running alongside other writes ends up cut into tables
+/// at arbitrary points. `LSMTree::pause_auto_flush` defers those flushes
+/// (and the idle flush of `maintenance_tick`): writes still go to the WAL
+/// and the memtable, which keeps growing until `resume_auto_flush`
+/// flushes it, if it is over the threshold by then. Pauses nest; flushes
+/// resume once every pause has been resumed.
+///
+/// Memory isn't unbounded while paused: once the memtable passes
+/// `LSMConfig::max_paused_memtable_bytes` it is flushed anyway, and
+/// `EventListener::on_flush_pause_overridden` reports it.
+///
+/// `FlushPauseGuard` resumes when dropped, and gives access to the tree
+/// meanwhile:
+///
+/// ```ignore
+/// let mut paused = lsm.pause_flushes();
+/// for (key, value) in bulk {
+///     paused.put(key, value)?;
+/// }
+/// paused.resume()?; // or let it drop, ignoring a failed flush
+/// ```
+use std::ops::{Deref, DerefMut};
+
+use crate::LSMTree;
+
+/// Default `LSMConfig::max_paused_memtable_bytes` (64 MiB)
+pub const DEFAULT_MAX_PAUSED_MEMTABLE_BYTES: usize = 64 * 1024 * 1024;
+
+/// A pause of automatic flushes, resumed when dropped (see the module
+/// docs)
+pub struct FlushPauseGuard<'a> {
+    tree: &'a mut LSMTree,
+    resumed: bool,
+}
+
+impl<'a> FlushPauseGuard<'a> {
+    pub(crate) fn new(tree: &'a mut LSMTree) -> Self {
+        tree.pause_auto_flush();
+        Self {
+            tree,
+            resumed: false,
+        }
+    }
+
+    /// Ends the pause, returning the error of the flush that may follow
+    pub fn resume(mut self) -> std::io::Result<()> {
+        self.resumed = true;
+        self.tree.resume_auto_flush()
+    }
+}
+
+impl Deref for FlushPauseGuard<'_> {
+    type Target = LSMTree;
+
+    fn deref(&self) -> &LSMTree {
+        self.tree
+    }
+}
+
+impl DerefMut for FlushPauseGuard<'_> {
+    fn deref_mut(&mut self) -> &mut LSMTree {
+        self.tree
+    }
+}
+
+impl Drop for FlushPauseGuard<'_> {
+    fn drop(&mut self) {
+        if !self.resumed {
+            // The data is in the WAL either way; the next write retries
+            let _ = self.tree.resume_auto_flush();
+        }
+    }
+}
@@ -0,0 +1,158 @@
+/// Deciding when the memtable is flushed
+///
+/// After every write (or batch) the tree asks its `FlushPolicy` whether to
+/// flush now, passing a `FlushContext` that describes the memtable and WAL
+/// at that moment. Without a policy in `LSMConfig::flush_policy` the tree
+/// flushes once the memtable reaches `LSMConfig::memtable_size_threshold`,
+/// exactly as `SizeThresholdPolicy` does.
+///
+/// Example: flush at 10,000 entries or a 64 MiB WAL, whichever comes first
+/// ```ignore
+/// let policy = AnyOf::new(vec![
+///     Box::new(EntryCountPolicy::new(10_000)),
+///     Box::new(WalSizePolicy::new(64 * 1024 * 1024)),
+/// ]);
+/// let config = LSMConfig {
+///     flush_policy: Some(Arc::new(policy)),
+///     ..LSMConfig::new(PathBuf::from("./data"))
+/// };
+/// ```
+use std::fmt::Debug;
+use std::time::Duration;
+
+/// State of the tree a flush decision is based on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushContext {
+    /// Approximate size of the memtable in bytes
+    pub memtable_bytes: usize,
+
+    /// Number of keys in the memtable
+    pub memtable_entries: usize,
+
+    /// Size of the WAL file in bytes
+    pub wal_bytes: u64,
+
+    /// Time since the last flush (or since the tree was opened)
+    pub since_last_flush: Duration,
+}
+
+/// Decides after each write whether the memtable should be flushed
+pub trait FlushPolicy: Send + Sync + Debug {
+    /// Returns true to flush the memtable now
+    ///
+    /// Only called after a write, with a non-empty memtable.
+    fn should_flush(&self, context: &FlushContext) -> bool;
+}
+
+/// Flushes once the memtable reaches a size in bytes (the default)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeThresholdPolicy {
+    bytes: usize,
+}
+
+impl SizeThresholdPolicy {
+    /// Flushes at `bytes` of memtable
+    pub fn new(bytes: usize) -> Self {
+        Self { bytes }
+    }
+}
+
+impl FlushPolicy for SizeThresholdPolicy {
+    fn should_flush(&self, context: &FlushContext) -> bool {
+        context.memtable_bytes >= self.bytes
+    }
+}
+
+/// Flushes once the memtable holds a number of keys, whatever their size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryCountPolicy {
+    entries: usize,
+}
+
+impl EntryCountPolicy {
+    /// Flushes at `entries` keys
+    pub fn new(entries: usize) -> Self {
+        Self { entries }
+    }
+}
+
+impl FlushPolicy for EntryCountPolicy {
+    fn should_flush(&self, context: &FlushContext) -> bool {
+        context.memtable_entries >= self.entries
+    }
+}
+
+/// Flushes once the WAL reaches a size in bytes
+///
+/// Bounds recovery time: the WAL is what has to be replayed on open. It
+/// also catches workloads that overwrite a few keys over and over, which
+/// keep the memtable small while the log keeps growing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalSizePolicy {
+    bytes: u64,
+}
+
+impl WalSizePolicy {
+    /// Flushes at `bytes` of WAL
+    pub fn new(bytes: u64) -> Self {
+        Self { bytes }
+    }
+}
+
+impl FlushPolicy for WalSizePolicy {
+    fn should_flush(&self, context: &FlushContext) -> bool {
+        context.wal_bytes >= self.bytes
+    }
+}
+
+/// Flushes as soon as any of its policies would
+#[derive(Debug)]
+pub struct AnyOf {
+    policies: Vec<Box<dyn FlushPolicy>>,
+}
+
+impl AnyOf {
+    /// Combines `policies`; with none it never flushes
+    pub fn new(policies: Vec<Box<dyn FlushPolicy>>) -> Self {
+        Self { policies }
+    }
+}
+
+impl FlushPolicy for AnyOf {
+    fn should_flush(&self, context: &FlushContext) -> bool {
+        self.policies.iter().any(|p| p.should_flush(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_built_in_policies() {
+        let context = FlushContext {
+            memtable_bytes: 1000,
+            memtable_entries: 10,
+            wal_bytes: 5000,
+            since_last_flush: Duration::from_secs(1),
+        };
+        assert!(SizeThresholdPolicy::new(1000).should_flush(&context));
+        assert!(!SizeThresholdPolicy::new(1001).should_flush(&context));
+        assert!(EntryCountPolicy::new(10).should_flush(&context));
+        assert!(!EntryCountPolicy::new(11).should_flush(&context));
+        assert!(WalSizePolicy::new(5000).should_flush(&context));
+        assert!(!WalSizePolicy::new(5001).should_flush(&context));
+
+        let none = AnyOf::new(vec![
+            Box::new(EntryCountPolicy::new(11)),
+            Box::new(WalSizePolicy::new(5001)),
+        ]);
+        assert!(!none.should_flush(&context));
+        let one = AnyOf::new(vec![
+            Box::new(EntryCountPolicy::new(11)),
+            Box::new(WalSizePolicy::new(5000)),
+        ]);
+        assert!(one.should_flush(&context));
+        assert!(!AnyOf::new(Vec::new()).should_flush(&context));
+    }
+}
@@ -0,0 +1,453 @@
+/// SSTable footer: a block index and summary statistics at the end of a
+/// table, so its metadata is known without scanning it
+///
+/// Tables of format version 6 (`sstable::FOOTER_FORMAT_VERSION`) and later end with
+/// three parts after their records:
+///
+/// ```text
+/// +---------------------+
+/// | records ...         |
+/// +---------------------+  ← index_offset (also in the table header)
+/// | index block         |  ← [count: u32][(key_len: u32, key, offset: u64) ...]
+/// +---------------------+  ← stats_offset
+/// | stats block         |  ← see below
+/// +---------------------+
+/// | trailer (36 bytes)  |  ← [index_offset: u64][stats_offset: u64]
+/// |                     |    [block_count: u32][footer_version: u32]
+/// |                     |    [checksum: u64][magic: b"LSSF"]
+/// +---------------------+
+/// ```
+///
+/// The index block lists the first key and offset of every block of
+/// records. A block is at least `BLOCK_BYTES` of records and only ever
+/// starts at a new key, so all versions of a key are in the same block and
+/// a lookup can start at the last block whose first key isn't greater than
+/// the key it wants.
+///
+/// The stats block holds, as `u64`s, the record count, tombstones, shadowed
+/// records, smallest and largest sequence, oldest and newest write time
+/// (Unix milliseconds, `u64::MAX` if unknown), value bytes before and after
+/// compression and the table's creation time (Unix milliseconds), then the
+/// first and last key, each as `[len: u32][key]` (`len` `u32::MAX` for an
+/// empty table). Expiry times aren't stored: they depend on the tree's
+/// `ttl`, so `TableSummary::expiry_span` derives them from the write times.
+///
+/// The checksum is FNV-1a over both blocks and the first 24 bytes of the
+/// trailer. Reading the footer takes two reads, the trailer and then both
+/// blocks, and a footer that fails any check is ignored: readers fall back
+/// to scanning the records, which the header still locates.
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::bloom::{FNV_OFFSET_BASIS, fnv1a};
+use crate::sstable::TableFile;
+
+/// Magic bytes at the very end of a table with a footer
+pub const FOOTER_MAGIC: [u8; 4] = *b"LSSF";
+
+/// Version of the footer layout written by this build
+pub const FOOTER_VERSION: u32 = 1;
+
+/// Size of the trailer in bytes
+pub(crate) const TRAILER_LEN: u64 = 36;
+
+/// Bytes of records a block holds before the next one may start
+pub(crate) const BLOCK_BYTES: u64 = 4096;
+
+/// Stored for a time that isn't known
+const UNKNOWN_MILLIS: u64 = u64::MAX;
+
+/// Stored as the length of a key an empty table doesn't have
+const NO_KEY: u32 = u32::MAX;
+
+/// What a table holds, from its footer or a full scan (see
+/// `sstable::read_summary`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableSummary {
+    /// Records in the table, tombstones and older versions included
+    pub entries: u64,
+
+    /// Tombstones among them
+    pub tombstones: u64,
+
+    /// Records that are an older version of the record before them
+    pub shadowed: u64,
+
+    /// Smallest key (`None` for an empty table)
+    pub first_key: Option<Vec<u8>>,
+
+    /// Largest key (`None` for an empty table)
+    pub last_key: Option<Vec<u8>>,
+
+    /// Smallest sequence number of a record (0 for an empty table)
+    pub min_sequence: u64,
+
+    /// Largest sequence number of a record (0 for an empty table)
+    pub max_sequence: u64,
+
+    /// Earliest and latest write time, if every write's time is known
+    pub write_span: Option<(SystemTime, SystemTime)>,
+
+    /// Value bytes as they were written
+    pub raw_value_bytes: u64,
+
+    /// Value bytes as stored, after compression
+    pub stored_value_bytes: u64,
+
+    /// When the table was written (the file's modification time for tables
+    /// without a footer)
+    pub created_at: SystemTime,
+
+    /// Blocks in the table's index (0 without a footer)
+    pub blocks: u32,
+
+    /// Whether the summary was read from a footer rather than a scan
+    pub from_footer: bool,
+}
+
+impl TableSummary {
+    /// Summary of an empty table created at `created_at`
+    pub(crate) fn empty(created_at: SystemTime) -> Self {
+        Self {
+            entries: 0,
+            tombstones: 0,
+            shadowed: 0,
+            first_key: None,
+            last_key: None,
+            min_sequence: 0,
+            max_sequence: 0,
+            write_span: None,
+            raw_value_bytes: 0,
+            stored_value_bytes: 0,
+            created_at,
+            blocks: 0,
+            from_footer: false,
+        }
+    }
+
+    /// Counts one record in, in table order
+    ///
+    /// `value_bytes` are its raw and stored value lengths (`None` for a
+    /// tombstone).
+    pub(crate) fn add(&mut self, key: &[u8], seq: u64, value_bytes: Option<(u64, u64)>) {
+        if self.entries == 0 {
+            self.min_sequence = seq;
+            self.max_sequence = seq;
+            self.first_key = Some(key.to_vec());
+        }
+        self.entries += 1;
+        self.min_sequence = self.min_sequence.min(seq);
+        self.max_sequence = self.max_sequence.max(seq);
+        match value_bytes {
+            Some((raw, stored)) => {
+                self.raw_value_bytes += raw;
+                self.stored_value_bytes += stored;
+            }
+            None => self.tombstones += 1,
+        }
+        if self.last_key.as_deref() == Some(key) {
+            self.shadowed += 1;
+        } else {
+            self.last_key = Some(key.to_vec());
+        }
+    }
+
+    /// When the table's values expire under `ttl`: the earliest and latest
+    /// expiry, if every write's time is known
+    pub fn expiry_span(&self, ttl: Duration) -> Option<(SystemTime, SystemTime)> {
+        let (oldest, newest) = self.write_span?;
+        Some((oldest + ttl, newest + ttl))
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        let (oldest, newest) = match self.write_span {
+            Some((oldest, newest)) => (to_millis(oldest), to_millis(newest)),
+            None => (UNKNOWN_MILLIS, UNKNOWN_MILLIS),
+        };
+        for n in [
+            self.entries,
+            self.tombstones,
+            self.shadowed,
+            self.min_sequence,
+            self.max_sequence,
+            oldest,
+            newest,
+            self.raw_value_bytes,
+            self.stored_value_bytes,
+            to_millis(self.created_at),
+        ] {
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        for key in [&self.first_key, &self.last_key] {
+            match key {
+                Some(key) => {
+                    out.extend_from_slice(&(key.len() as u32).to_le_bytes());
+                    out.extend_from_slice(key);
+                }
+                None => out.extend_from_slice(&NO_KEY.to_le_bytes()),
+            }
+        }
+    }
+
+    fn read_from(fields: &mut Fields<'_>, blocks: u32) -> Option<Self> {
+        let mut n = [0u64; 10];
+        for n in &mut n {
+            *n = fields.u64()?;
+        }
+        let [
+            entries,
+            tombstones,
+            shadowed,
+            min_sequence,
+            max_sequence,
+            oldest,
+            newest,
+            raw_value_bytes,
+            stored_value_bytes,
+            created,
+        ] = n;
+        let mut key = || match fields.u32()? {
+            NO_KEY => Some(None),
+            len => Some(Some(fields.bytes(len as usize)?.to_vec())),
+        };
+        let first_key = key()?;
+        let last_key = key()?;
+        let write_span = (oldest != UNKNOWN_MILLIS && newest != UNKNOWN_MILLIS)
+            .then(|| (from_millis(oldest), from_millis(newest)));
+        Some(Self {
+            entries,
+            tombstones,
+            shadowed,
+            first_key,
+            last_key,
+            min_sequence,
+            max_sequence,
+            write_span,
+            raw_value_bytes,
+            stored_value_bytes,
+            created_at: from_millis(created),
+            blocks,
+            from_footer: true,
+        })
+    }
+}
+
+/// First key and offset of every block of a table's records
+///
+/// The entries stay encoded as in the index block, so reading an index
+/// takes the same two allocations however many blocks it has.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct BlockIndex {
+    /// `[key_len: u32][key][offset: u64]` of every block
+    entries: Vec<u8>,
+
+    /// Where each block's key starts in `entries`, its length and the
+    /// block's offset in the table
+    blocks: Vec<(usize, usize, u64)>,
+}
+
+impl BlockIndex {
+    /// Notes the first version of a key, about to be written at `offset`,
+    /// and starts a block there if the current one is full; returns whether
+    /// it did
+    ///
+    /// Older versions of the key aren't passed in, so a key's versions
+    /// never straddle two blocks.
+    pub(crate) fn add_key(&mut self, key: &[u8], offset: u64) -> bool {
+        let full = self
+            .blocks
+            .last()
+            .is_none_or(|&(_, _, start)| offset - start >= BLOCK_BYTES);
+        if full {
+            self.entries
+                .extend_from_slice(&(key.len() as u32).to_le_bytes());
+            self.blocks.push((self.entries.len(), key.len(), offset));
+            self.entries.extend_from_slice(key);
+            self.entries.extend_from_slice(&offset.to_le_bytes());
+        }
+        full
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Offsets of the blocks, first to last
+    pub(crate) fn offsets(&self) -> impl Iterator<Item = u64> + '_ {
+        self.blocks.iter().map(|&(_, _, offset)| offset)
+    }
+
+    /// Offset of the block to start looking for `key` in: the last one
+    /// whose first key isn't greater than it (`None` if `key` is smaller
+    /// than every key in the table)
+    pub(crate) fn block_for(&self, key: &[u8]) -> Option<u64> {
+        let after = self
+            .blocks
+            .partition_point(|&(at, len, _)| &self.entries[at..at + len] <= key);
+        after.checked_sub(1).map(|i| self.blocks[i].2)
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.blocks.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.entries);
+    }
+
+    /// Parses an index block, taking its bytes (`None` if they don't hold
+    /// exactly the blocks they count)
+    fn read_from(mut block: Vec<u8>) -> Option<Self> {
+        let mut fields = Fields(&block);
+        let count = fields.u32()? as usize;
+        // A damaged count can't reserve more than the block could hold
+        let mut blocks = Vec::with_capacity(count.min(block.len() / 12));
+        // Where the next entry starts once the count is dropped
+        let mut entry_at = 0;
+        for _ in 0..count {
+            let len = fields.u32()? as usize;
+            fields.bytes(len)?;
+            let offset = fields.u64()?;
+            blocks.push((entry_at + 4, len, offset));
+            entry_at += 4 + len + 8;
+        }
+        if !fields.0.is_empty() {
+            return None;
+        }
+        block.drain(..4);
+        Some(Self {
+            entries: block,
+            blocks,
+        })
+    }
+}
+
+/// A table's footer, read and checksummed but not parsed yet
+pub(crate) struct Footer {
+    /// The index and stats blocks
+    blocks: Vec<u8>,
+
+    /// Where the stats block starts in `blocks`
+    stats_at: usize,
+
+    block_count: u32,
+}
+
+impl Footer {
+    /// The block index (`None` if it can't be parsed)
+    pub(crate) fn into_index(mut self) -> Option<BlockIndex> {
+        self.blocks.truncate(self.stats_at);
+        BlockIndex::read_from(self.blocks).filter(|index| index.len() == self.block_count as usize)
+    }
+
+    /// The summary statistics (`None` if they can't be parsed)
+    pub(crate) fn summary(&self) -> Option<TableSummary> {
+        let mut fields = Fields(&self.blocks[self.stats_at..]);
+        TableSummary::read_from(&mut fields, self.block_count).filter(|_| fields.0.is_empty())
+    }
+}
+
+/// Appends the footer of a table whose records end at `index_offset`
+pub(crate) fn write_footer<W: Write>(
+    writer: &mut W,
+    index_offset: u64,
+    index: &BlockIndex,
+    summary: &TableSummary,
+) -> std::io::Result<()> {
+    let mut blocks = Vec::new();
+    index.write_to(&mut blocks);
+    let stats_offset = index_offset + blocks.len() as u64;
+    summary.write_to(&mut blocks);
+
+    let mut trailer = Vec::with_capacity(TRAILER_LEN as usize);
+    trailer.extend_from_slice(&index_offset.to_le_bytes());
+    trailer.extend_from_slice(&stats_offset.to_le_bytes());
+    trailer.extend_from_slice(&(index.len() as u32).to_le_bytes());
+    trailer.extend_from_slice(&FOOTER_VERSION.to_le_bytes());
+    let checksum = fnv1a(fnv1a(FNV_OFFSET_BASIS, &blocks), &trailer);
+    trailer.extend_from_slice(&checksum.to_le_bytes());
+    trailer.extend_from_slice(&FOOTER_MAGIC);
+
+    writer.write_all(&blocks)?;
+    writer.write_all(&trailer)
+}
+
+/// Reads the footer of a table whose records end at `records_end`
+///
+/// Returns `Ok(None)` if the trailer is damaged or from a newer build, or
+/// the checksum doesn't match; `Err` only for I/O errors.
+pub(crate) fn read_footer(
+    file: &mut TableFile,
+    records_end: u64,
+) -> std::io::Result<Option<Footer>> {
+    let file_len = file.len();
+    if file_len < records_end + TRAILER_LEN {
+        return Ok(None);
+    }
+    let mut trailer = [0u8; TRAILER_LEN as usize];
+    file.read_exact_at(file_len - TRAILER_LEN, &mut trailer)?;
+    let Some((stats_offset, block_count)) = check_trailer(&trailer, records_end, file_len) else {
+        return Ok(None);
+    };
+
+    let mut blocks = vec![0u8; (file_len - TRAILER_LEN - records_end) as usize];
+    file.read_exact_at(records_end, &mut blocks)?;
+    let checksum = fnv1a(fnv1a(FNV_OFFSET_BASIS, &blocks), &trailer[..24]);
+    if checksum.to_le_bytes() != trailer[24..32] {
+        return Ok(None);
+    }
+    Ok(Some(Footer {
+        blocks,
+        stats_at: (stats_offset - records_end) as usize,
+        block_count,
+    }))
+}
+
+/// Checks the trailer's magic, version and offsets, and returns the stats
+/// offset and block count
+fn check_trailer(
+    trailer: &[u8; TRAILER_LEN as usize],
+    records_end: u64,
+    file_len: u64,
+) -> Option<(u64, u32)> {
+    let mut fields = Fields(trailer);
+    let index_offset = fields.u64()?;
+    let stats_offset = fields.u64()?;
+    let block_count = fields.u32()?;
+    let version = fields.u32()?;
+    fields.u64()?;
+    let valid = fields.bytes(4)? == FOOTER_MAGIC
+        && version == FOOTER_VERSION
+        && index_offset == records_end
+        && stats_offset >= index_offset
+        && stats_offset <= file_len - TRAILER_LEN;
+    valid.then_some((stats_offset, block_count))
+}
+
+/// Reads fields off the front of a byte slice
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (field, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(field)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
@@ -0,0 +1,353 @@
+/// FORMAT: what a data directory needs from the build that opens it
+///
+/// Every integer in the tree's files is little-endian and keys are ordered
+/// bytewise, but none of the files said so. The FORMAT file records it,
+/// together with the newest SSTable format version and the optional
+/// features the directory's files rely on. It is written when a tree is
+/// created and checked on every open, so a directory written by a newer or
+/// differently built version of the crate is refused with a list of what
+/// doesn't match, instead of failing somewhere in the middle of reading a
+/// table. New format changes should add a field or feature here.
+///
+/// Like the manifest it is plain text:
+///
+/// ```text
+/// lsm-format 1
+/// sstable_version 8
+/// endianness little
+/// comparator bytewise
+/// features zstd
+/// tree_id 0d5e3a6c-27f1-4b8e-9a41-6f0c2d7e8b93
+/// ```
+///
+/// `tree_id` is the id stamped into every SSTable the tree writes (see
+/// `tree_id`). A directory without one gets one the next time it is opened
+/// for writing.
+///
+/// Features only ever get added: once a directory holds compressed tables,
+/// turning compression off doesn't make them readable by a build without
+/// the `zstd` feature.
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::compression::Compression;
+use crate::sstable::{CHECKSUM_FORMAT_VERSION, NEWEST_FORMAT_VERSION};
+use crate::tree_id::TreeId;
+
+/// File name of the format file inside the data directory
+pub const FORMAT_FILE: &str = "FORMAT";
+
+/// Version of the FORMAT file written by this build
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Byte order of every integer in the tree's files
+pub const ENDIANNESS: &str = "little";
+
+/// Key order of the tree
+pub const COMPARATOR: &str = "bytewise";
+
+/// Feature flag of directories holding Zstandard-compressed tables
+pub const ZSTD_FEATURE: &str = "zstd";
+
+/// Contents of a FORMAT file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryFormat {
+    /// Version of the FORMAT file itself
+    pub version: u32,
+
+    /// Newest SSTable format version the directory may hold
+    pub sstable_version: u32,
+
+    /// Byte order of integers in the files
+    pub endianness: String,
+
+    /// Name of the key order
+    pub comparator: String,
+
+    /// Optional features the files rely on
+    pub features: BTreeSet<String>,
+
+    /// Id of the tree (`None` in directories created before trees had one)
+    pub tree_id: Option<TreeId>,
+
+    /// Fields this build doesn't know, as `name value` lines
+    pub unknown_fields: Vec<String>,
+}
+
+impl DirectoryFormat {
+    /// Returns the format a tree opened with `compression` writes
+    pub fn for_compression(compression: Compression) -> Self {
+        let mut features = BTreeSet::new();
+        if compression != Compression::None {
+            features.insert(ZSTD_FEATURE.to_string());
+        }
+        Self {
+            version: FORMAT_VERSION,
+            // Flushes stamp the tree id, record write times, add a footer,
+            // prefix-compress keys and checksum records
+            sstable_version: CHECKSUM_FORMAT_VERSION,
+            endianness: ENDIANNESS.to_string(),
+            comparator: COMPARATOR.to_string(),
+            features,
+            tree_id: None,
+            unknown_fields: Vec::new(),
+        }
+    }
+
+    /// Feature flags this build can read
+    pub fn supported_features() -> &'static [&'static str] {
+        if cfg!(feature = "zstd") {
+            &[ZSTD_FEATURE]
+        } else {
+            &[]
+        }
+    }
+
+    /// Returns the FORMAT path for a data directory
+    pub fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(FORMAT_FILE)
+    }
+
+    /// Loads the FORMAT file of a data directory
+    ///
+    /// Returns `Ok(None)` if there is none (a new tree, or one created
+    /// before FORMAT files existed). Fields are not checked here; see
+    /// `check`.
+    pub fn load(data_dir: &Path) -> std::io::Result<Option<Self>> {
+        let path = Self::path(data_dir);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let invalid = |line: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid format line in {}: {:?}", path.display(), line),
+            )
+        };
+
+        let mut lines = BufReader::new(file).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let version = header
+            .strip_prefix("lsm-format ")
+            .and_then(|v| v.trim().parse().ok())
+            .ok_or_else(|| invalid(&header))?;
+
+        // Missing fields read as empty, which `check` reports
+        let mut format = Self {
+            version,
+            sstable_version: 0,
+            endianness: String::new(),
+            comparator: String::new(),
+            features: BTreeSet::new(),
+            tree_id: None,
+            unknown_fields: Vec::new(),
+        };
+        for line in lines {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (field, value) = line.split_once(' ').unwrap_or((line, ""));
+            match field {
+                "sstable_version" => {
+                    format.sstable_version = value.parse().map_err(|_| invalid(line))?;
+                }
+                "endianness" => format.endianness = value.to_string(),
+                "comparator" => format.comparator = value.to_string(),
+                "features" => {
+                    format.features = value.split_whitespace().map(str::to_string).collect();
+                }
+                "tree_id" => {
+                    format.tree_id = Some(TreeId::parse(value).ok_or_else(|| invalid(line))?);
+                }
+                _ => format.unknown_fields.push(line.to_string()),
+            }
+        }
+        Ok(Some(format))
+    }
+
+    /// Atomically replaces the FORMAT file of a data directory
+    pub fn store(&self, data_dir: &Path) -> std::io::Result<()> {
+        let tmp_path = data_dir.join(format!("{}.tmp", FORMAT_FILE));
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&tmp_path)?;
+        let mut writer = BufWriter::new(file);
+
+        writeln!(writer, "lsm-format {}", self.version)?;
+        writeln!(writer, "sstable_version {}", self.sstable_version)?;
+        writeln!(writer, "endianness {}", self.endianness)?;
+        writeln!(writer, "comparator {}", self.comparator)?;
+        let features: Vec<&str> = self.features.iter().map(String::as_str).collect();
+        writeln!(writer, "features {}", features.join(" "))?;
+        if let Some(tree_id) = self.tree_id {
+            writeln!(writer, "tree_id {}", tree_id)?;
+        }
+
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+
+        std::fs::rename(&tmp_path, Self::path(data_dir))
+    }
+
+    /// Fails with `InvalidData` listing every field this build can't work
+    /// with
+    pub fn check(&self, data_dir: &Path) -> std::io::Result<()> {
+        let mut mismatches = Vec::new();
+        if self.version > FORMAT_VERSION {
+            mismatches.push(format!(
+                "format version {} (this build supports up to {})",
+                self.version, FORMAT_VERSION
+            ));
+        }
+        if self.sstable_version > NEWEST_FORMAT_VERSION {
+            mismatches.push(format!(
+                "SSTable version {} (this build supports up to {})",
+                self.sstable_version, NEWEST_FORMAT_VERSION
+            ));
+        }
+        if self.endianness != ENDIANNESS {
+            mismatches.push(format!(
+                "endianness {:?} (expected {:?})",
+                self.endianness, ENDIANNESS
+            ));
+        }
+        if self.comparator != COMPARATOR {
+            mismatches.push(format!(
+                "comparator {:?} (expected {:?})",
+                self.comparator, COMPARATOR
+            ));
+        }
+        for feature in &self.features {
+            if !Self::supported_features().contains(&feature.as_str()) {
+                mismatches.push(format!("unsupported feature {:?}", feature));
+            }
+        }
+        for line in &self.unknown_fields {
+            mismatches.push(format!("unknown field {:?}", line));
+        }
+
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "Data directory {} was written by an incompatible build: {}",
+                data_dir.display(),
+                mismatches.join("; ")
+            ),
+        ))
+    }
+
+    /// Returns this format extended with what `other` needs
+    pub fn merged_with(&self, other: &Self) -> Self {
+        let mut merged = self.clone();
+        merged.sstable_version = merged.sstable_version.max(other.sstable_version);
+        merged.features.extend(other.features.iter().cloned());
+        merged.tree_id = merged.tree_id.or(other.tree_id);
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LSMConfig, LSMTree};
+    use std::fs;
+
+    #[test]
+    fn test_format_written_on_create_and_round_trips() {
+        let dir = PathBuf::from("./test_format_round_trip");
+        fs::remove_dir_all(&dir).ok();
+
+        let lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+        drop(lsm);
+        let format = DirectoryFormat::load(&dir).unwrap().unwrap();
+        assert!(format.tree_id.is_some());
+        assert_eq!(
+            format,
+            DirectoryFormat {
+                tree_id: format.tree_id,
+                ..DirectoryFormat::for_compression(Compression::None)
+            }
+        );
+        assert!(format.check(&dir).is_ok());
+
+        // The id stays the tree's across opens
+        drop(LSMTree::new(dir.clone(), 1024).unwrap());
+        assert_eq!(
+            DirectoryFormat::load(&dir).unwrap().unwrap().tree_id,
+            format.tree_id
+        );
+
+        let mut extended = format.clone();
+        extended.features.insert("zstd".to_string());
+        extended.store(&dir).unwrap();
+        assert_eq!(DirectoryFormat::load(&dir).unwrap().unwrap(), extended);
+        assert!(!dir.join("FORMAT.tmp").exists());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_open_refuses_incompatible_directory() {
+        let dir = PathBuf::from("./test_format_incompatible");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+        lsm.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        drop(lsm);
+
+        let path = DirectoryFormat::path(&dir);
+        let original = fs::read_to_string(&path).unwrap();
+        fs::write(&path, original.replace("features ", "features encryption")).unwrap();
+        let Err(e) = LSMTree::open(LSMConfig::new(dir.clone())) else {
+            panic!("directory with an unknown feature was opened");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("unsupported feature \"encryption\""));
+
+        // Every mismatching field is listed
+        fs::write(
+            &path,
+            "lsm-format 2\nsstable_version 9\nendianness big\ncomparator reverse\nfeatures \nchecksum crc32\n",
+        )
+        .unwrap();
+        let Err(e) = LSMTree::open(LSMConfig::new(dir.clone())) else {
+            panic!("incompatible directory was opened");
+        };
+        let message = e.to_string();
+        for field in [
+            "format version 2",
+            "SSTable version 9",
+            "endianness \"big\"",
+            "comparator \"reverse\"",
+            "unknown field \"checksum crc32\"",
+        ] {
+            assert!(
+                message.contains(field),
+                "{} missing from {}",
+                field,
+                message
+            );
+        }
+
+        // Restored, the tree opens again with its data
+        fs::write(&path, original).unwrap();
+        let mut lsm = LSMTree::open(LSMConfig::new(dir.clone())).unwrap();
+        assert_eq!(lsm.get(b"key"), Some(b"value".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
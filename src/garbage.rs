@@ -0,0 +1,99 @@
+/// Estimated garbage per SSTable, to decide what compaction should take
+/// first (see `LSMTree::compaction_candidates`)
+///
+/// Every table records what it held when it was written: its records, the
+/// tombstones among them, and the records that were older versions of a
+/// key already in the same table (kept for a pinned read state; a flush
+/// without pins writes none). That part is exact and comes from the writer.
+///
+/// Whatever gets overwritten or deleted later is estimated as it happens.
+/// Each flush probes the older tables' Bloom filters with the flushed keys
+/// and charges every key to the newest older table that might hold it: the
+/// version there is now shadowed. A false positive charges a table that
+/// never had the key, and a table whose filter isn't in memory isn't
+/// charged at all, so the count is an estimate, never more than the table's
+/// records.
+///
+/// A table's garbage ratio is the share of its records that a compaction
+/// would drop. The counts live in the manifest, so they survive a reopen.
+use crate::handle::SSTableId;
+
+/// Garbage ratio past which `LSMTree::needs_compaction` asks for a
+/// compaction
+pub const HIGH_GARBAGE_RATIO: f64 = 0.5;
+
+/// What one SSTable holds and how much of it is garbage
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TableStats {
+    /// Records in the table, tombstones included (0 if unknown)
+    pub entries: u64,
+
+    /// Tombstones among them
+    pub tombstones: u64,
+
+    /// Records that were already an older version of a key in the same
+    /// table when it was written
+    pub shadowed: u64,
+
+    /// Records estimated to be overwritten or deleted by newer tables
+    pub obsolete: u64,
+}
+
+impl TableStats {
+    /// Records a compaction would drop
+    pub fn garbage(&self) -> u64 {
+        self.shadowed + self.obsolete
+    }
+
+    /// Share of the records that are garbage (0.0 for an empty or unknown
+    /// table)
+    pub fn garbage_ratio(&self) -> f64 {
+        if self.entries == 0 {
+            0.0
+        } else {
+            self.garbage() as f64 / self.entries as f64
+        }
+    }
+
+    /// Counts `records` more as overwritten by a newer table
+    pub(crate) fn add_obsolete(&mut self, records: u64) {
+        let limit = self.entries.saturating_sub(self.shadowed);
+        self.obsolete = (self.obsolete + records).min(limit);
+    }
+}
+
+/// One SSTable ranked by its garbage (see `LSMTree::compaction_candidates`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionCandidate {
+    pub sstable: SSTableId,
+
+    /// `TableStats::garbage_ratio` of the table
+    pub garbage_ratio: f64,
+
+    /// Records a compaction would drop
+    pub garbage: u64,
+
+    /// Lookups that searched the table (`TableReadStats::probes`)
+    pub probes: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_obsolete_never_exceeds_records() {
+        let mut stats = TableStats {
+            entries: 10,
+            shadowed: 2,
+            ..TableStats::default()
+        };
+        assert_eq!(stats.garbage_ratio(), 0.2);
+        stats.add_obsolete(3);
+        assert_eq!(stats.garbage(), 5);
+        stats.add_obsolete(100);
+        assert_eq!(stats.obsolete, 8);
+        assert_eq!(stats.garbage_ratio(), 1.0);
+        assert_eq!(TableStats::default().garbage_ratio(), 0.0);
+    }
+}
@@ -0,0 +1,125 @@
+/// Borrowed views of the live SSTables
+///
+/// Positions in the tree's table list shift whenever a flush adds a table
+/// or a purge replaces them, so anything that has to refer to a table
+/// across operations (a TUI selection, a report) should hold its
+/// `SSTableId` instead. The id is the table's file number: it never changes
+/// while the table is live and is never reused for another table, so
+/// `LSMTree::sstable(id)` either finds the same file again or reports that
+/// it is gone.
+///
+/// Example:
+/// ```ignore
+/// let selected = lsm.sstables().next().map(|t| t.id());
+/// lsm.flush()?;
+/// match selected.and_then(|id| lsm.sstable(id)) {
+///     Some(table) => println!("{} is still live", table.path().display()),
+///     None => println!("the table was compacted away"),
+/// }
+/// ```
+use std::fmt;
+use std::path::Path;
+
+use crate::bloom::BloomFilterStats;
+use crate::filter_cache::FilterResidency;
+use crate::footer::TableSummary;
+use crate::sstable::{self, SSTableReader};
+use crate::{LSMTree, SSTableInfo};
+
+/// Stable identifier of an SSTable: its file number (`sstable_<id>.db`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SSTableId(pub usize);
+
+impl fmt::Display for SSTableId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A live SSTable, borrowed from the tree
+///
+/// Holding a handle borrows the tree, so the table can't disappear while
+/// it is in use; keep `id()` to find it again later.
+#[derive(Clone, Copy)]
+pub struct SSTableHandle<'a> {
+    tree: &'a LSMTree,
+    /// Position in `LSMTree::sstables`, only valid for this borrow
+    index: usize,
+    id: SSTableId,
+}
+
+impl<'a> SSTableHandle<'a> {
+    pub(crate) fn new(tree: &'a LSMTree, index: usize, id: SSTableId) -> Self {
+        Self { tree, index, id }
+    }
+
+    /// The table's stable id
+    pub fn id(&self) -> SSTableId {
+        self.id
+    }
+
+    /// Path of the table file
+    pub fn path(&self) -> &'a Path {
+        &self.info().path
+    }
+
+    /// Generation of the table; higher holds newer data
+    pub fn generation(&self) -> u64 {
+        self.info().generation
+    }
+
+    /// Highest write sequence number flushed into the table
+    pub fn max_sequence(&self) -> u64 {
+        self.info().max_sequence
+    }
+
+    /// The table's place in the tree's history
+    pub fn info(&self) -> &'a SSTableInfo {
+        &self.tree.sstables[self.index]
+    }
+
+    /// Where the table's Bloom filter currently lives
+    pub fn filter_residency(&self) -> FilterResidency {
+        self.tree.bloom_filters.residency(self.index)
+    }
+
+    /// Statistics of the table's Bloom filter, if it is in memory
+    pub fn filter_stats(&self) -> Option<BloomFilterStats> {
+        self.tree
+            .bloom_filters
+            .resident(self.index)
+            .map(|bf| bf.stats())
+    }
+
+    /// Reads the table's summary statistics from its footer, or by
+    /// scanning it if it has none (see `sstable::read_summary`)
+    pub fn summary(&self) -> std::io::Result<TableSummary> {
+        sstable::read_summary(self.path())
+    }
+
+    /// Reads the table's live entries (for display; tombstones and older
+    /// versions are skipped)
+    ///
+    /// `None` if the file can't be opened.
+    pub fn live_entries(&self) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+        read_live_entries(self.path())
+    }
+}
+
+impl fmt::Debug for SSTableHandle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SSTableHandle")
+            .field("id", &self.id)
+            .field("path", &self.path())
+            .field("generation", &self.generation())
+            .finish()
+    }
+}
+
+/// Reads the newest live version of every key in an SSTable file
+///
+/// A truncated trailing record ends the entries, as it ends iteration.
+pub(crate) fn read_live_entries(path: &Path) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut reader = SSTableReader::open(path).ok()?;
+    Some(reader.live_entries().map_while(Result::ok).collect())
+}
@@ -0,0 +1,546 @@
+/// Merged iteration across the memtable and all SSTables
+///
+/// Every layer of the tree (memtable, then SSTables newest to oldest) is
+/// already sorted by key. To walk the whole tree in order we run a k-way
+/// merge: each layer is a "source", a min-heap tracks the smallest pending
+/// key of every source, and when several sources hold the same key the
+/// newest one wins while the older copies are skipped.
+///
+/// Tombstones take part in the merge like any other entry (so they shadow
+/// older values) but are never handed out to the caller.
+///
+/// `VersionIter` runs the same merge without dropping the older copies: it
+/// hands out every version the tree still stores, tombstones included.
+///
+/// Example:
+/// ```ignore
+/// for (key, value) in lsm.range("user:".."user;") {
+///     println!("{:?} = {:?}", key, value);
+/// }
+/// ```
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::BufReader;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+use crate::cancel::CancellationToken;
+use crate::compression::ValueDecoder;
+use crate::memtable::{self, Memtable};
+use crate::sstable::{self, TOMBSTONE_MARKER, TableFile};
+use crate::value_meta::ValueSource;
+
+/// A key range with owned bounds
+pub(crate) type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// Converts any range over byte-like keys into owned bounds
+///
+/// This lets callers write `lsm.range("a".."c")`, `lsm.range(b"a".as_slice()..)`
+/// or `lsm.range(start_vec..=end_vec)` interchangeably.
+pub(crate) fn to_key_range<K: AsRef<[u8]>, R: RangeBounds<K>>(range: &R) -> KeyRange {
+    let start = match range.start_bound() {
+        Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+        Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+        Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (start, end)
+}
+
+/// Returns true if `key` is at or after the start bound
+pub(crate) fn after_start(range: &KeyRange, key: &[u8]) -> bool {
+    match &range.0 {
+        Bound::Included(start) => key >= start.as_slice(),
+        Bound::Excluded(start) => key > start.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// Returns true if `key` is at or before the end bound
+pub(crate) fn before_end(range: &KeyRange, key: &[u8]) -> bool {
+    match &range.1 {
+        Bound::Included(end) => key <= end.as_slice(),
+        Bound::Excluded(end) => key < end.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// Returns true if no key can possibly fall inside the range
+///
+/// `BTreeMap::range` panics on inverted bounds, so those are filtered out
+/// before any source is built.
+fn is_inverted(range: &KeyRange) -> bool {
+    match (&range.0, &range.1) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// One entry coming out of a source: the key, the sequence number of the
+/// write and its value (None = tombstone)
+pub(crate) type SourceEntry = (Vec<u8>, u64, Option<Vec<u8>>);
+
+/// The versions of one key, newest first: sequence number and value (None
+/// = tombstone)
+pub(crate) type Versions = Vec<(u64, Option<Vec<u8>>)>;
+
+/// Sequential scanner over one SSTable file restricted to a key range
+///
+/// The scan starts at the block of the footer's index holding the start
+/// bound, and the entries of that block before the bound are skipped
+/// without reading their values (this is how a source seeks to a cursor);
+/// the scan stops as soon as a key passes the end bound, since the file is
+/// sorted. Only the newest
+/// version of each key is returned, unless `all_versions` is set; older
+/// versions kept for pinned sequences are skipped like seeked-over records.
+struct SSTableScan {
+    /// None when the file could not be opened (treated as empty, like `get`)
+    reader: Option<BufReader<TableFile>>,
+    version: u32,
+    values: ValueDecoder,
+    range: KeyRange,
+    keys_only: bool,
+    all_versions: bool,
+    /// Key of the previous record, to recognize older versions
+    previous_key: Option<Vec<u8>>,
+    /// Key of the last record read, which a prefix-compressed key builds on
+    key: Vec<u8>,
+}
+
+impl SSTableScan {
+    fn open(
+        path: &Path,
+        range: KeyRange,
+        keys_only: bool,
+        all_versions: bool,
+        buffer_bytes: usize,
+    ) -> Self {
+        let start = match &range.0 {
+            Bound::Included(start) | Bound::Excluded(start) => Some(start.as_slice()),
+            Bound::Unbounded => None,
+        };
+        let opened = sstable::open_reader(path, buffer_bytes).and_then(|(mut reader, format)| {
+            if let Some(start) = start {
+                sstable::seek_to_block(&mut reader, &format, start)?;
+            }
+            Ok((reader, format))
+        });
+        let (reader, version, values) = match opened {
+            Ok((reader, format)) => (Some(reader), format.version, format.values),
+            Err(_) => (None, 0, ValueDecoder::Plain),
+        };
+        Self {
+            reader,
+            version,
+            values,
+            range,
+            keys_only,
+            all_versions,
+            previous_key: None,
+            key: Vec::new(),
+        }
+    }
+
+    /// Reads the next record, or None at end of file
+    ///
+    /// Value bytes are only read when the caller actually needs them:
+    /// records before the start bound (while seeking), older versions and
+    /// all records in keys-only mode skip over their values instead of
+    /// allocating them.
+    fn read_next(&mut self) -> Option<SourceEntry> {
+        loop {
+            let reader = self.reader.as_mut()?;
+            let (seq, value_len) =
+                sstable::read_key_into(reader, self.version, &mut self.key).ok()??;
+            let key = self.key.clone();
+
+            let older_version = !self.all_versions && self.previous_key.as_ref() == Some(&key);
+            let seeking = older_version || !after_start(&self.range, &key);
+            if !seeking && !before_end(&self.range, &key) {
+                // The file is sorted, so nothing after this can match
+                return None;
+            }
+
+            if !older_version {
+                self.previous_key = Some(key.clone());
+            }
+
+            if value_len == TOMBSTONE_MARKER {
+                if seeking {
+                    continue;
+                }
+                return Some((key, seq, None));
+            }
+
+            if seeking || self.keys_only {
+                reader.seek_relative(value_len as i64).ok()?;
+                if seeking {
+                    continue;
+                }
+                // Only the fact that the entry is live matters, not its bytes
+                return Some((key, seq, Some(Vec::new())));
+            }
+
+            let mut value = Vec::new();
+            sstable::read_field(reader, value_len as usize, &mut value).ok()?;
+            return Some((key, seq, Some(self.values.decode(value).ok()?)));
+        }
+    }
+}
+
+impl Iterator for SSTableScan {
+    type Item = SourceEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.read_next();
+        if entry.is_none() {
+            // Release the file handle as soon as the scan is over
+            self.reader = None;
+        }
+        entry
+    }
+}
+
+/// A single sorted input to the merge
+enum Source<'a> {
+    Memtable(memtable::VersionsIter<'a>),
+    /// Every version in the memtable: the versions of the current key not
+    /// handed out yet, then the rest of the range
+    MemtableVersions(std::vec::IntoIter<SourceEntry>, memtable::VersionsIter<'a>),
+    SSTable(SSTableScan),
+}
+
+impl Iterator for Source<'_> {
+    type Item = SourceEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Source::Memtable(range) => {
+                let (key, mut versions) = range.next()?;
+                let newest = versions.swap_remove(0);
+                Some((key, newest.seq, newest.value))
+            }
+            Source::MemtableVersions(pending, range) => {
+                if let Some(entry) = pending.next() {
+                    return Some(entry);
+                }
+                let (key, versions) = range.next()?;
+                *pending = versions
+                    .into_iter()
+                    .map(|v| (key.clone(), v.seq, v.value))
+                    .collect::<Vec<_>>()
+                    .into_iter();
+                pending.next()
+            }
+            Source::SSTable(scan) => scan.next(),
+        }
+    }
+}
+
+/// K-way merge over all layers of the tree, newest version of each key wins
+///
+/// Sources are ordered newest first: index 0 is the memtable, followed by
+/// SSTables from newest to oldest. For equal keys the heap pops the lowest
+/// source index first, which is exactly the newest version.
+pub(crate) struct MergeIter<'a> {
+    sources: Vec<Source<'a>>,
+    /// Min-heap of (next key, source index) for every non-exhausted source
+    heap: BinaryHeap<Reverse<(Vec<u8>, usize)>>,
+    /// The pending sequence number and value for each source, matching
+    /// its entry in the heap
+    pending: Vec<Option<(u64, Option<Vec<u8>>)>>,
+    /// Checked before every entry; once cancelled the merge ends
+    cancel: Option<CancellationToken>,
+    /// Whether the merge ended because `cancel` was cancelled
+    cancelled: bool,
+}
+
+impl<'a> MergeIter<'a> {
+    /// A merge of `memtable` and `sstables` (newest first), each table read
+    /// through a buffer of `buffer_bytes`
+    pub(crate) fn new(
+        memtable: &'a Memtable,
+        sstables: &[std::path::PathBuf],
+        range: KeyRange,
+        keys_only: bool,
+        buffer_bytes: usize,
+    ) -> Self {
+        Self::with_sources(memtable, sstables, range, keys_only, false, buffer_bytes)
+    }
+
+    /// A merge that keeps every version of every key (see `next_version`)
+    pub(crate) fn all_versions(
+        memtable: &'a Memtable,
+        sstables: &[std::path::PathBuf],
+        range: KeyRange,
+        keys_only: bool,
+        buffer_bytes: usize,
+    ) -> Self {
+        Self::with_sources(memtable, sstables, range, keys_only, true, buffer_bytes)
+    }
+
+    fn with_sources(
+        memtable: &'a Memtable,
+        sstables: &[std::path::PathBuf],
+        range: KeyRange,
+        keys_only: bool,
+        all_versions: bool,
+        buffer_bytes: usize,
+    ) -> Self {
+        let mut sources = Vec::with_capacity(sstables.len() + 1);
+        if is_inverted(&range) {
+            return Self {
+                sources,
+                heap: BinaryHeap::new(),
+                pending: Vec::new(),
+                cancel: None,
+                cancelled: false,
+            };
+        }
+        let versions = memtable.range_versions((range.0.clone(), range.1.clone()));
+        sources.push(if all_versions {
+            Source::MemtableVersions(Vec::new().into_iter(), versions)
+        } else {
+            Source::Memtable(versions)
+        });
+        for path in sstables {
+            sources.push(Source::SSTable(SSTableScan::open(
+                path,
+                range.clone(),
+                keys_only,
+                all_versions,
+                buffer_bytes,
+            )));
+        }
+
+        let mut iter = Self {
+            pending: vec![None; sources.len()],
+            heap: BinaryHeap::with_capacity(sources.len()),
+            sources,
+            cancel: None,
+            cancelled: false,
+        };
+        for index in 0..iter.sources.len() {
+            iter.advance(index);
+        }
+        iter
+    }
+
+    /// Ends the merge early once `token` is cancelled (see `cancel`)
+    pub(crate) fn set_cancellation(&mut self, token: &CancellationToken) {
+        self.cancel = Some(token.clone());
+    }
+
+    /// Whether the merge ended because its token was cancelled
+    pub(crate) fn was_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Notes a cancellation, after which the merge hands out nothing more
+    fn stop_if_cancelled(&mut self) -> bool {
+        if !self.cancelled && self.cancel.as_ref().is_some_and(|t| t.is_cancelled()) {
+            self.cancelled = true;
+            self.heap.clear();
+        }
+        self.cancelled
+    }
+
+    /// Pulls the next entry from a source into the heap
+    fn advance(&mut self, index: usize) {
+        if let Some((key, seq, value)) = self.sources[index].next() {
+            self.pending[index] = Some((seq, value));
+            self.heap.push(Reverse((key, index)));
+        }
+    }
+
+    /// Returns the next visible version of a key, including tombstones
+    pub(crate) fn next_entry(&mut self) -> Option<SourceEntry> {
+        if self.stop_if_cancelled() {
+            return None;
+        }
+        let Reverse((key, index)) = self.heap.pop()?;
+        let (seq, value) = self.pending[index].take()?;
+        self.advance(index);
+
+        // Drop the older copies of the same key from every other source
+        while let Some(Reverse((next_key, _))) = self.heap.peek() {
+            if *next_key != key {
+                break;
+            }
+            let Reverse((_, older)) = self.heap.pop().expect("peeked entry");
+            self.pending[older] = None;
+            self.advance(older);
+        }
+
+        Some((key, seq, value))
+    }
+
+    /// Returns the next entry of any source, with the index of the source,
+    /// without dropping older copies of the key
+    ///
+    /// The heap orders equal keys by source, and each source hands out the
+    /// versions of a key newest first, so versions come out newest first.
+    pub(crate) fn next_version(&mut self) -> Option<(usize, SourceEntry)> {
+        if self.stop_if_cancelled() {
+            return None;
+        }
+        let Reverse((key, index)) = self.heap.pop()?;
+        let (seq, value) = self.pending[index].take()?;
+        self.advance(index);
+        Some((index, (key, seq, value)))
+    }
+
+    /// Returns the next key with every version of it, newest first (for a
+    /// merge made with `all_versions`)
+    pub(crate) fn next_versions(&mut self) -> Option<(Vec<u8>, Versions)> {
+        let (_, (key, seq, value)) = self.next_version()?;
+        let mut versions = vec![(seq, value)];
+        while self
+            .heap
+            .peek()
+            .is_some_and(|Reverse((next_key, _))| *next_key == key)
+        {
+            let Some((_, (_, seq, value))) = self.next_version() else {
+                break;
+            };
+            versions.push((seq, value));
+        }
+        Some((key, versions))
+    }
+}
+
+impl Iterator for MergeIter<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, _, value) = self.next_entry()?;
+            // Tombstones shadow older data but are not visible themselves
+            if let Some(value) = value {
+                return Some((key, value));
+            }
+        }
+    }
+}
+
+/// Iterator over live key-value pairs in a range, in ascending key order
+pub struct RangeIter<'a> {
+    inner: MergeIter<'a>,
+}
+
+impl<'a> RangeIter<'a> {
+    pub(crate) fn new(inner: MergeIter<'a>) -> Self {
+        Self { inner }
+    }
+
+    /// Ends the iteration early once `token` is cancelled (see `cancel`)
+    pub fn with_cancellation(mut self, token: &CancellationToken) -> Self {
+        self.inner.set_cancellation(token);
+        self
+    }
+
+    /// Whether the iteration ended because its token was cancelled, rather
+    /// than at the end of the range
+    pub fn was_cancelled(&self) -> bool {
+        self.inner.was_cancelled()
+    }
+}
+
+impl Iterator for RangeIter<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// Iterator over live keys in a range, in ascending order
+///
+/// Values are never read from disk, which makes counting and existence
+/// checks much cheaper than a full range scan.
+pub struct KeysIter<'a> {
+    inner: MergeIter<'a>,
+}
+
+impl<'a> KeysIter<'a> {
+    pub(crate) fn new(inner: MergeIter<'a>) -> Self {
+        Self { inner }
+    }
+
+    /// Ends the iteration early once `token` is cancelled (see `cancel`)
+    pub fn with_cancellation(mut self, token: &CancellationToken) -> Self {
+        self.inner.set_cancellation(token);
+        self
+    }
+
+    /// Whether the iteration ended because its token was cancelled, rather
+    /// than at the end of the range
+    pub fn was_cancelled(&self) -> bool {
+        self.inner.was_cancelled()
+    }
+}
+
+impl Iterator for KeysIter<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, _)| key)
+    }
+}
+
+/// One stored version of a key (see `LSMTree::iter_versions`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyVersion {
+    pub key: Vec<u8>,
+
+    /// Sequence number of the write
+    pub sequence: u64,
+
+    /// Where the version is stored
+    pub source: ValueSource,
+
+    /// The value, `None` for a tombstone
+    pub value: Option<Vec<u8>>,
+}
+
+impl KeyVersion {
+    /// Returns true if the version is a delete
+    pub fn is_tombstone(&self) -> bool {
+        self.value.is_none()
+    }
+}
+
+/// Iterator over every stored version in a range: in key order, and the
+/// versions of a key newest first
+pub struct VersionIter<'a> {
+    inner: MergeIter<'a>,
+    /// Where each merge source lives: the memtable, then the SSTables
+    sources: Vec<ValueSource>,
+}
+
+impl<'a> VersionIter<'a> {
+    pub(crate) fn new(inner: MergeIter<'a>, sources: Vec<ValueSource>) -> Self {
+        Self { inner, sources }
+    }
+}
+
+impl Iterator for VersionIter<'_> {
+    type Item = KeyVersion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, (key, sequence, value)) = self.inner.next_version()?;
+        Some(KeyVersion {
+            key,
+            sequence,
+            source: self.sources[index],
+            value,
+        })
+    }
+}
@@ -0,0 +1,359 @@
+/// Minimal JSON reading and writing
+///
+/// Just enough for the crate's own exports (statistics snapshots), so the
+/// library doesn't need a serialization dependency. Numbers keep their
+/// original text, which lets integers of any size round-trip exactly;
+/// non-finite floats, which JSON can't express, are written as `null`.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// A parsed JSON value
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    /// The number exactly as written
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub(crate) fn from_u64(n: u64) -> Self {
+        Value::Number(n.to_string())
+    }
+
+    pub(crate) fn from_f64(n: f64) -> Self {
+        if n.is_finite() {
+            Value::Number(n.to_string())
+        } else {
+            Value::Null
+        }
+    }
+
+    /// Returns the member of an object, failing if it is missing
+    pub(crate) fn field(&self, name: &str) -> std::io::Result<&Value> {
+        match self {
+            Value::Object(members) => members
+                .get(name)
+                .ok_or_else(|| invalid(format!("missing field \"{}\"", name))),
+            _ => Err(invalid(format!("expected an object holding \"{}\"", name))),
+        }
+    }
+
+    pub(crate) fn as_u64(&self) -> std::io::Result<u64> {
+        match self {
+            Value::Number(text) => text
+                .parse()
+                .map_err(|_| invalid(format!("{} is not an unsigned integer", text))),
+            _ => Err(invalid("expected a number".to_string())),
+        }
+    }
+
+    pub(crate) fn as_usize(&self) -> std::io::Result<usize> {
+        let n = self.as_u64()?;
+        usize::try_from(n).map_err(|_| invalid(format!("{} is too large", n)))
+    }
+
+    /// Reads a float; `null` (a non-finite value on export) reads as NaN
+    pub(crate) fn as_f64(&self) -> std::io::Result<f64> {
+        match self {
+            Value::Number(text) => text
+                .parse()
+                .map_err(|_| invalid(format!("{} is not a number", text))),
+            Value::Null => Ok(f64::NAN),
+            _ => Err(invalid("expected a number".to_string())),
+        }
+    }
+
+    /// Renders the value with two-space indentation
+    pub(crate) fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::Number(text) => out.push_str(text),
+            Value::String(s) => write_string(out, s),
+            Value::Array(items) if items.is_empty() => out.push_str("[]"),
+            Value::Array(items) => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    push_indent(out, indent + 1);
+                    item.write_pretty(out, indent + 1);
+                    out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                push_indent(out, indent);
+                out.push(']');
+            }
+            Value::Object(members) if members.is_empty() => out.push_str("{}"),
+            Value::Object(members) => {
+                out.push_str("{\n");
+                for (i, (name, value)) in members.iter().enumerate() {
+                    push_indent(out, indent + 1);
+                    write_string(out, name);
+                    out.push_str(": ");
+                    value.write_pretty(out, indent + 1);
+                    out.push_str(if i + 1 < members.len() { ",\n" } else { "\n" });
+                }
+                push_indent(out, indent);
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn push_indent(out: &mut String, indent: usize) {
+    for _ in 0..indent {
+        out.push_str("  ");
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Parses a complete JSON document
+pub(crate) fn parse(text: &str) -> std::io::Result<Value> {
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, what: &str) -> std::io::Error {
+        invalid(format!("invalid JSON at byte {}: {}", self.pos, what))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> std::io::Result<()> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> std::io::Result<Value> {
+        if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("unknown literal"))
+        }
+    }
+
+    fn value(&mut self) -> std::io::Result<Value> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self) -> std::io::Result<Value> {
+        self.expect(b'{')?;
+        let mut members = BTreeMap::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a member name"));
+            }
+            let name = self.string()?;
+            self.expect(b':')?;
+            members.insert(name, self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> std::io::Result<Value> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> std::io::Result<Value> {
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).expect("ASCII digits");
+        if text.parse::<f64>().is_err() {
+            return Err(self.error("malformed number"));
+        }
+        Ok(Value::Number(text.to_string()))
+    }
+
+    fn string(&mut self) -> std::io::Result<String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.pos) else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.bytes.get(self.pos) else {
+                        return Err(self.error("unterminated escape"));
+                    };
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("unknown escape")),
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                byte => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("string is not UTF-8"))
+    }
+
+    /// Reads the four hex digits after `\u` (surrogate pairs are rejected;
+    /// nothing this crate writes needs them)
+    fn unicode_escape(&mut self) -> std::io::Result<char> {
+        let digits = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| self.error("malformed \\u escape"))?;
+        self.pos += 4;
+        char::from_u32(digits).ok_or_else(|| self.error("unsupported \\u escape"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_errors() {
+        let mut members = BTreeMap::new();
+        members.insert("big".to_string(), Value::from_u64(u64::MAX));
+        members.insert("rate".to_string(), Value::from_f64(0.0125));
+        members.insert("nan".to_string(), Value::from_f64(f64::NAN));
+        members.insert("name".to_string(), Value::String("a \"b\"\n\u{1}é".into()));
+        members.insert(
+            "list".to_string(),
+            Value::Array(vec![Value::Bool(true), Value::Object(BTreeMap::new())]),
+        );
+        let value = Value::Object(members);
+
+        let text = value.to_pretty_string();
+        let parsed = parse(&text).unwrap();
+        assert_eq!(parsed, value);
+        assert_eq!(parsed.field("big").unwrap().as_u64().unwrap(), u64::MAX);
+        assert_eq!(parsed.field("rate").unwrap().as_f64().unwrap(), 0.0125);
+        assert!(parsed.field("nan").unwrap().as_f64().unwrap().is_nan());
+        assert!(parsed.field("missing").is_err());
+
+        assert_eq!(
+            parse(" [1, -2.5e3, \"\\u0041\"] ").unwrap(),
+            Value::Array(vec![
+                Value::Number("1".into()),
+                Value::Number("-2.5e3".into()),
+                Value::String("A".into()),
+            ])
+        );
+        for bad in ["", "{", "[1,]", "{\"a\" 1}", "tru", "1 2", "\"open", "--1"] {
+            let err = parse(bad).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+/// Key encodings whose byte order matches their natural order
+///
+/// The tree sorts keys as bytes, so a number stored as its native
+/// (little-endian) bytes or as decimal text scans in the wrong order: 256
+/// sorts before 1, and "10" before "9". These encodings keep scans
+/// numeric:
+///
+/// - `u64_be`: the 8 big-endian bytes of a `u64`
+/// - `i64_be`: the 8 big-endian bytes of an `i64` with its sign bit
+///   flipped (a bias of 2^63), so negative values sort before positive ones
+/// - `composite`: several byte strings in one key, ordered part by part;
+///   parts can be encoded numbers or any other bytes
+///
+/// `LSMTree::put_u64`, `get_u64`, `delete_u64` and `range_u64` (and their
+/// `i64` twins) use them so callers don't have to. A range over numeric
+/// keys only returns keys of exactly 8 bytes; other keys stored in the same
+/// tree that happen to fall in between are skipped.
+///
+/// Example:
+/// ```ignore
+/// lsm.put_i64(-5, b"minus five".to_vec())?;
+/// lsm.put_i64(3, b"three".to_vec())?;
+/// let ids: Vec<i64> = lsm.range_i64(-10..10).map(|(id, _)| id).collect();
+/// assert_eq!(ids, vec![-5, 3]);
+///
+/// let key = keys::composite::encode(&[&b"user"[..], &keys::u64_be::encode(42)]);
+/// ```
+use std::ops::{Bound, RangeBounds};
+
+use crate::iter::RangeIter;
+
+/// Unsigned 64-bit keys, big-endian
+pub mod u64_be {
+    /// The key for `value`
+    pub fn encode(value: u64) -> [u8; 8] {
+        value.to_be_bytes()
+    }
+
+    /// The value of `key`, or `None` if it isn't exactly 8 bytes
+    pub fn decode(key: &[u8]) -> Option<u64> {
+        Some(u64::from_be_bytes(key.try_into().ok()?))
+    }
+}
+
+/// Signed 64-bit keys, big-endian with the sign bit flipped
+pub mod i64_be {
+    /// Added to every value (mod 2^64) so `i64::MIN` encodes as all zeros
+    const BIAS: u64 = 1 << 63;
+
+    /// The key for `value`
+    pub fn encode(value: i64) -> [u8; 8] {
+        ((value as u64) ^ BIAS).to_be_bytes()
+    }
+
+    /// The value of `key`, or `None` if it isn't exactly 8 bytes
+    pub fn decode(key: &[u8]) -> Option<i64> {
+        Some((u64::from_be_bytes(key.try_into().ok()?) ^ BIAS) as i64)
+    }
+}
+
+/// Keys made of several parts, compared part by part
+///
+/// Each part is written with its `0x00` bytes escaped as `0x00 0xFF` and
+/// ends with `0x00 0x01`. The terminator sorts below every byte a part can
+/// continue with, so a part sorts before any longer part it is a prefix
+/// of, and a key's parts never run into each other: `["a", "b"]` and
+/// `["ab"]` are different keys, and every key with first part `"a"` sorts
+/// before every key with first part `"ab"`.
+pub mod composite {
+    /// Byte that starts an escape or a terminator
+    const ESCAPE: u8 = 0x00;
+
+    /// Follows `ESCAPE` for a `0x00` byte inside a part
+    const ESCAPED_ZERO: u8 = 0xFF;
+
+    /// Follows `ESCAPE` at the end of a part
+    const TERMINATOR: u8 = 0x01;
+
+    /// The key for `parts`
+    ///
+    /// The key for some leading parts is a prefix of the key for any
+    /// longer list of parts, so it works as the prefix of a `Subspace`.
+    pub fn encode<P: AsRef<[u8]>>(parts: &[P]) -> Vec<u8> {
+        let mut key = Vec::new();
+        for part in parts {
+            for &byte in part.as_ref() {
+                key.push(byte);
+                if byte == ESCAPE {
+                    key.push(ESCAPED_ZERO);
+                }
+            }
+            key.extend_from_slice(&[ESCAPE, TERMINATOR]);
+        }
+        key
+    }
+
+    /// The parts of `key`, or `None` if it wasn't made by `encode`
+    pub fn decode(key: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let mut parts = Vec::new();
+        let mut part = Vec::new();
+        let mut bytes = key.iter();
+        while let Some(&byte) = bytes.next() {
+            if byte != ESCAPE {
+                part.push(byte);
+                continue;
+            }
+            match *bytes.next()? {
+                ESCAPED_ZERO => part.push(ESCAPE),
+                TERMINATOR => parts.push(std::mem::take(&mut part)),
+                _ => return None,
+            }
+        }
+        // A trailing part without its terminator
+        part.is_empty().then_some(parts)
+    }
+}
+
+/// Maps a range of numbers to the range of their keys
+pub(crate) fn key_range<T: Copy, R: RangeBounds<T>>(
+    range: &R,
+    encode: fn(T) -> [u8; 8],
+) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let bound = |bound: Bound<&T>| match bound {
+        Bound::Included(&n) => Bound::Included(encode(n).to_vec()),
+        Bound::Excluded(&n) => Bound::Excluded(encode(n).to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (bound(range.start_bound()), bound(range.end_bound()))
+}
+
+/// Iterator over the live entries with numeric keys in a range, decoded
+///
+/// Returned by `LSMTree::range_u64` and `LSMTree::range_i64`.
+pub struct NumericRangeIter<'a, T> {
+    inner: RangeIter<'a>,
+    decode: fn(&[u8]) -> Option<T>,
+}
+
+impl<'a, T> NumericRangeIter<'a, T> {
+    pub(crate) fn new(inner: RangeIter<'a>, decode: fn(&[u8]) -> Option<T>) -> Self {
+        Self { inner, decode }
+    }
+}
+
+impl<T> Iterator for NumericRangeIter<'_, T> {
+    type Item = (T, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Keys of another length in between aren't numbers
+        self.inner
+            .by_ref()
+            .find_map(|(key, value)| Some(((self.decode)(&key)?, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LSMTree;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_encodings_preserve_order() {
+        let unsigned = [0, 1, 255, 256, 1 << 32, (1 << 63) - 1, 1 << 63, u64::MAX];
+        for pair in unsigned.windows(2) {
+            assert!(u64_be::encode(pair[0]) < u64_be::encode(pair[1]));
+        }
+        let signed = [i64::MIN, -256, -1, 0, 1, 255, i64::MAX];
+        for pair in signed.windows(2) {
+            assert!(i64_be::encode(pair[0]) < i64_be::encode(pair[1]));
+        }
+        for n in signed {
+            assert_eq!(i64_be::decode(&i64_be::encode(n)), Some(n));
+        }
+        assert_eq!(i64_be::encode(i64::MIN), [0; 8]);
+        assert_eq!(u64_be::decode(&u64_be::encode(u64::MAX)), Some(u64::MAX));
+        assert_eq!(u64_be::decode(b"short"), None);
+
+        // Parts compare one at a time, whatever bytes they hold
+        let keys = [
+            composite::encode(&[&b"a"[..]]),
+            composite::encode(&[&b"a"[..], &b""[..]]),
+            composite::encode(&[&b"a"[..], &b"z"[..]]),
+            composite::encode(&[&b"a\x00"[..]]),
+            composite::encode(&[&b"a\x00\x00"[..], &b"b"[..]]),
+            composite::encode(&[&b"a\x01"[..]]),
+            composite::encode(&[&b"ab"[..]]),
+        ];
+        for pair in keys.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} >= {:?}", pair[0], pair[1]);
+        }
+        let parts = vec![
+            b"user".to_vec(),
+            vec![0, 0xFF, 0],
+            u64_be::encode(7).to_vec(),
+        ];
+        assert_eq!(composite::decode(&composite::encode(&parts)), Some(parts));
+        assert_eq!(composite::decode(b"a\x00\x01b"), None);
+        assert_eq!(composite::decode(b"a\x00\x02"), None);
+    }
+
+    #[test]
+    fn test_numeric_ranges_across_sign_and_high_bit() {
+        let dir = PathBuf::from("./test_keys_numeric_ranges");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 512).unwrap();
+        let unsigned = [
+            0,
+            7,
+            255,
+            256,
+            (1 << 63) - 1,
+            1 << 63,
+            (1 << 63) + 1,
+            u64::MAX,
+        ];
+        // Inserted out of order, and flushed part way
+        for (i, &n) in unsigned.iter().rev().enumerate() {
+            lsm.put_u64(n, n.to_le_bytes().to_vec()).unwrap();
+            if i == 3 {
+                lsm.flush().unwrap();
+            }
+        }
+        // Keys that aren't numbers, in between numeric ones, are skipped
+        lsm.put(vec![0x80, 0, 0, 0, 0, 0, 0, 0, 9], b"x".to_vec())
+            .unwrap();
+        lsm.put(b"\x00\x01".to_vec(), b"x".to_vec()).unwrap();
+
+        let ids: Vec<u64> = lsm.range_u64(..).map(|(id, _)| id).collect();
+        assert_eq!(ids, unsigned);
+        let ids: Vec<u64> = lsm.range_u64(256..=(1 << 63)).map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![256, (1 << 63) - 1, 1 << 63]);
+        let ids: Vec<u64> = lsm
+            .range_u64((1 << 63)..u64::MAX)
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids, vec![1 << 63, (1 << 63) + 1]);
+        assert_eq!(
+            lsm.get_u64(1 << 63),
+            Some((1u64 << 63).to_le_bytes().to_vec())
+        );
+        lsm.delete_u64(7).unwrap();
+        assert_eq!(lsm.get_u64(7), None);
+        assert_eq!(lsm.range_u64(0..256).count(), 2);
+        drop(lsm);
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 512).unwrap();
+        let signed = [i64::MIN, -300, -2, -1, 0, 1, 2, 300, i64::MAX];
+        for &n in signed.iter().rev() {
+            lsm.put_i64(n, n.to_le_bytes().to_vec()).unwrap();
+        }
+        lsm.flush().unwrap();
+        let all: Vec<(i64, Vec<u8>)> = lsm.range_i64(..).collect();
+        assert_eq!(all.iter().map(|(n, _)| *n).collect::<Vec<_>>(), signed);
+        assert!(all.iter().all(|(n, v)| v == &n.to_le_bytes()));
+        let ids: Vec<i64> = lsm.range_i64(-2..2).map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![-2, -1, 0, 1]);
+        let ids: Vec<i64> = lsm.range_i64(..=-300).map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![i64::MIN, -300]);
+        let ids: Vec<i64> = lsm.range_i64(301..).map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![i64::MAX]);
+        assert_eq!(lsm.get_i64(-1), Some((-1i64).to_le_bytes().to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
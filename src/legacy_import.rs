@@ -0,0 +1,427 @@
+/// Converting a directory written by the old demo binary into a new tree
+/// (see `LSMTree::import_legacy_dir`)
+///
+/// Before manifests, FORMAT files and sequence numbers, the demo wrote
+/// `sstable_<n>.db` files without Bloom filters and restarted its file
+/// counter on every run, so file numbers don't tell which table is newer.
+/// Opening such a directory in place works, but relies on guesses the
+/// tree makes quietly. Importing makes those guesses explicit instead:
+///
+/// 1. every `sstable_<n>.db` is checked; damaged ones are skipped
+/// 2. tables are applied oldest first: those without sequence numbers by
+///    modification time (file number, then name, breaking ties), then the
+///    sequenced ones by sequence, and the WAL last, so the newest version
+///    of each key wins
+/// 3. the surviving values are written into a fresh directory with
+///    `LSMTree::from_iter`: sorted SSTables with filters, a manifest and a
+///    FORMAT file
+///
+/// The report lists whatever the order rested on a guess: file names that
+/// mean the same table number, tables modified at the same moment, and
+/// keys whose value differs between tables ordered only by modification
+/// time. The source directory is only read. The whole data set is held in
+/// memory while it is converted.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::{DEFAULT_READ_BUFFER_BYTES, SizeLimits};
+use crate::manifest::MANIFEST_FILE;
+use crate::sstable::{self, SSTableReader};
+use crate::wal::{WAL, WALOp};
+use crate::{LSMConfig, LSMTree};
+
+/// Conflicting keys listed one by one in a report; more are only counted
+pub const MAX_REPORTED_CONFLICTS: usize = 100;
+
+/// Something the import had to guess about
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ambiguity {
+    /// Files whose names parse to the same table number (`sstable_1.db`
+    /// and `sstable_01.db`), so the number can't order them
+    SameNumber { number: usize, files: Vec<PathBuf> },
+
+    /// Unsequenced tables modified at the same moment, ordered by name
+    SameModificationTime { files: Vec<PathBuf> },
+
+    /// A key with different values in unsequenced tables; the value of
+    /// the most recently modified one was kept
+    ConflictingVersions {
+        key: Vec<u8>,
+        kept: PathBuf,
+        overridden: PathBuf,
+    },
+}
+
+impl fmt::Display for Ambiguity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |files: &[PathBuf]| {
+            files
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Ambiguity::SameNumber { number, files } => {
+                write!(f, "table number {} used by {}", number, list(files))
+            }
+            Ambiguity::SameModificationTime { files } => {
+                write!(
+                    f,
+                    "same modification time, ordered by name: {}",
+                    list(files)
+                )
+            }
+            Ambiguity::ConflictingVersions {
+                key,
+                kept,
+                overridden,
+            } => write!(
+                f,
+                "key {} differs; kept {} over {}",
+                String::from_utf8_lossy(key),
+                kept.display(),
+                overridden.display()
+            ),
+        }
+    }
+}
+
+/// What `LSMTree::import_legacy_dir` read, wrote and had to guess
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    /// Source tables, in the order they were applied (oldest first)
+    pub tables: Vec<PathBuf>,
+
+    /// Damaged source tables that were left out, with the reason
+    pub damaged: Vec<(PathBuf, String)>,
+
+    /// WAL entries applied after the tables
+    pub wal_entries: usize,
+
+    /// Bytes at the end of the WAL that couldn't be read
+    pub wal_bytes_dropped: u64,
+
+    /// Live keys written to the new tree
+    pub keys_written: usize,
+
+    /// Everything the order had to guess (at most
+    /// `MAX_REPORTED_CONFLICTS` conflicting keys)
+    pub ambiguities: Vec<Ambiguity>,
+
+    /// Keys whose value differed between unsequenced tables, listed or not
+    pub conflicting_keys: usize,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Imported {} keys from {} tables and {} WAL entries",
+            self.keys_written,
+            self.tables.len(),
+            self.wal_entries
+        )?;
+        for (path, reason) in &self.damaged {
+            writeln!(f, "  skipped damaged {}: {}", path.display(), reason)?;
+        }
+        if self.wal_bytes_dropped > 0 {
+            writeln!(
+                f,
+                "  dropped {} unreadable bytes at the end of the WAL",
+                self.wal_bytes_dropped
+            )?;
+        }
+        for ambiguity in &self.ambiguities {
+            writeln!(f, "  {}", ambiguity)?;
+        }
+        let unlisted = self.conflicting_keys.saturating_sub(MAX_REPORTED_CONFLICTS);
+        if unlisted > 0 {
+            writeln!(f, "  ... and {} more conflicting keys", unlisted)?;
+        }
+        Ok(())
+    }
+}
+
+/// A source table and what orders it
+struct Source {
+    path: PathBuf,
+    number: usize,
+    max_sequence: u64,
+    modified: SystemTime,
+}
+
+/// The version of a key applied last, and where it came from
+struct Winner {
+    value: Option<Vec<u8>>,
+    /// Index into `ImportReport::tables`, or `None` for the WAL
+    table: Option<usize>,
+    sequenced: bool,
+}
+
+pub(crate) fn import(src: &Path, dst: &Path) -> std::io::Result<ImportReport> {
+    std::fs::metadata(src)?;
+    if src.join(MANIFEST_FILE).exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{} has a manifest; open it as a tree instead of importing it",
+                src.display()
+            ),
+        ));
+    }
+    let mut report = ImportReport::default();
+
+    let mut by_number: BTreeMap<usize, Vec<PathBuf>> = BTreeMap::new();
+    for entry in std::fs::read_dir(src)? {
+        let path = entry?.path();
+        if let Some(number) = LSMTree::sstable_number(&path) {
+            by_number.entry(number).or_default().push(path);
+        }
+    }
+
+    let mut sources = Vec::new();
+    for (number, mut files) in by_number {
+        files.sort();
+        if files.len() > 1 {
+            report.ambiguities.push(Ambiguity::SameNumber {
+                number,
+                files: files.clone(),
+            });
+        }
+        for path in files {
+            if let Some(corruption) = sstable::check_integrity(&path, DEFAULT_READ_BUFFER_BYTES)? {
+                report.damaged.push((path, corruption.to_string()));
+                continue;
+            }
+            let mut max_sequence = 0;
+            let mut reader = SSTableReader::open(&path)?;
+            while let Some((_, seq, _)) = reader.try_next()? {
+                max_sequence = max_sequence.max(seq);
+            }
+            let modified = std::fs::metadata(&path)?.modified()?;
+            sources.push(Source {
+                path,
+                number,
+                max_sequence,
+                modified,
+            });
+        }
+    }
+
+    // Oldest first, as `repair` orders tables
+    sources.sort_by(|a, b| {
+        let key = |s: &Source| {
+            let unsequenced = s.max_sequence == 0;
+            (
+                !unsequenced,
+                s.max_sequence,
+                unsequenced.then_some(s.modified),
+                s.number,
+            )
+        };
+        key(a).cmp(&key(b)).then_with(|| a.path.cmp(&b.path))
+    });
+    for group in sources
+        .chunk_by(|a, b| a.max_sequence == 0 && b.max_sequence == 0 && a.modified == b.modified)
+    {
+        if group.len() > 1 {
+            report.ambiguities.push(Ambiguity::SameModificationTime {
+                files: group.iter().map(|s| s.path.clone()).collect(),
+            });
+        }
+    }
+
+    let mut winners: BTreeMap<Vec<u8>, Winner> = BTreeMap::new();
+    for (index, source) in sources.iter().enumerate() {
+        let sequenced = source.max_sequence > 0;
+        let mut previous: Option<Vec<u8>> = None;
+        for (key, _, value) in SSTableReader::open(&source.path)? {
+            // Older versions of a key follow its newest one
+            if previous.as_deref() == Some(key.as_slice()) {
+                continue;
+            }
+            previous = Some(key.clone());
+            let replaced = winners.insert(
+                key.clone(),
+                Winner {
+                    value: value.clone(),
+                    table: Some(index),
+                    sequenced,
+                },
+            );
+            if let Some(replaced) = replaced
+                && !sequenced
+                && !replaced.sequenced
+                && replaced.value != value
+                && let Some(old) = replaced.table
+            {
+                report.conflicting_keys += 1;
+                if report.conflicting_keys <= MAX_REPORTED_CONFLICTS {
+                    report.ambiguities.push(Ambiguity::ConflictingVersions {
+                        key,
+                        kept: source.path.clone(),
+                        overridden: sources[old].path.clone(),
+                    });
+                }
+            }
+        }
+    }
+    report.tables = sources.into_iter().map(|s| s.path).collect();
+
+    // Whatever the WAL holds was written after the last table
+    let recovery = WAL::replay(&src.join("wal.log"), SizeLimits::default())?;
+    report.wal_bytes_dropped = recovery.dropped_bytes();
+    report.wal_entries = recovery.entries.len();
+    for entry in recovery.entries {
+        let value = match entry.op {
+            WALOp::Put => Some(entry.value),
+            WALOp::Delete => None,
+        };
+        winners.insert(
+            entry.key,
+            Winner {
+                value,
+                table: None,
+                sequenced: entry.seq > 0,
+            },
+        );
+    }
+
+    // Nothing older survives to be shadowed, so tombstones go
+    let live: Vec<(Vec<u8>, Vec<u8>)> = winners
+        .into_iter()
+        .filter_map(|(key, winner)| winner.value.map(|value| (key, value)))
+        .collect();
+    report.keys_written = live.len();
+    LSMTree::from_iter(LSMConfig::new(dst.to_path_buf()), live)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn write_legacy_sstable(path: &Path, records: &[(&[u8], &[u8])], modified_secs: u64) {
+        let mut buf = Vec::new();
+        for (key, value) in records {
+            sstable::write_unsequenced_record(&mut buf, key, Some(value));
+        }
+        fs::write(path, buf).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(modified_secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_import_orders_by_mtime_and_reports_guesses() {
+        let src = PathBuf::from("./test_legacy_import_src");
+        let dst = PathBuf::from("./test_legacy_import_dst");
+        fs::remove_dir_all(&src).ok();
+        fs::remove_dir_all(&dst).ok();
+        fs::create_dir_all(&src).unwrap();
+
+        // Three runs of the old demo: the counter restarted in between, and
+        // one file was saved under a zero-padded name
+        write_legacy_sstable(&src.join("sstable_01.db"), &[(b"e", b"e")], 500);
+        write_legacy_sstable(
+            &src.join("sstable_1.db"),
+            &[(b"a", b"stale"), (b"b", b"1"), (b"d", b"gone")],
+            1_000,
+        );
+        write_legacy_sstable(&src.join("sstable_2.db"), &[(b"a", b"stale-2")], 2_000);
+        write_legacy_sstable(&src.join("sstable_3.db"), &[(b"c", b"x")], 2_000);
+        write_legacy_sstable(&src.join("sstable_0.db"), &[(b"a", b"fresh")], 3_000);
+        fs::write(src.join("sstable_5.db"), b"").unwrap();
+        let mut wal = WAL::new(src.join("wal.log")).unwrap();
+        wal.append_delete(0, b"d").unwrap();
+        wal.append_put(0, b"f", b"wal").unwrap();
+        drop(wal);
+
+        let report = LSMTree::import_legacy_dir(&src, &dst).unwrap();
+        let names: Vec<&str> = report
+            .tables
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "sstable_01.db",
+                "sstable_1.db",
+                "sstable_2.db",
+                "sstable_3.db",
+                "sstable_0.db"
+            ]
+        );
+        assert_eq!(report.damaged.len(), 1);
+        assert_eq!(report.damaged[0].0, src.join("sstable_5.db"));
+        assert_eq!(report.wal_entries, 2);
+        assert_eq!(report.keys_written, 5);
+        assert_eq!(report.conflicting_keys, 2);
+        assert_eq!(
+            report.ambiguities,
+            [
+                Ambiguity::SameNumber {
+                    number: 1,
+                    files: vec![src.join("sstable_01.db"), src.join("sstable_1.db")],
+                },
+                Ambiguity::SameModificationTime {
+                    files: vec![src.join("sstable_2.db"), src.join("sstable_3.db")],
+                },
+                Ambiguity::ConflictingVersions {
+                    key: b"a".to_vec(),
+                    kept: src.join("sstable_2.db"),
+                    overridden: src.join("sstable_1.db"),
+                },
+                Ambiguity::ConflictingVersions {
+                    key: b"a".to_vec(),
+                    kept: src.join("sstable_0.db"),
+                    overridden: src.join("sstable_2.db"),
+                },
+            ]
+        );
+        assert!(report.to_string().contains("table number 1 used by"));
+
+        // The new tree is a modern one with every surviving value
+        assert!(dst.join(MANIFEST_FILE).exists());
+        let mut lsm = LSMTree::new(dst.clone(), 1024).unwrap();
+        for info in lsm.sstable_infos() {
+            assert_eq!(
+                sstable::format_version(&info.path).unwrap(),
+                sstable::NEWEST_FORMAT_VERSION
+            );
+            assert!(info.path.with_extension("bloom").exists());
+        }
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = lsm.range::<&[u8], _>(..).collect();
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = [
+            ("a", "fresh"),
+            ("b", "1"),
+            ("c", "x"),
+            ("e", "e"),
+            ("f", "wal"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+        .collect();
+        assert_eq!(pairs, expected);
+        assert_eq!(lsm.get(b"d"), None);
+        drop(lsm);
+
+        // The source is untouched, and a tree can't be imported again
+        assert!(!src.join(MANIFEST_FILE).exists());
+        assert!(!src.join("sstable_0.bloom").exists());
+        let e = LSMTree::import_legacy_dir(&dst, &src).expect_err("imported a modern tree");
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+
+        fs::remove_dir_all(&src).ok();
+        fs::remove_dir_all(&dst).ok();
+    }
+}
@@ -0,0 +1,240 @@
+/// Bulk loading rows from a reader (see `LSMTree::load_from_reader`)
+///
+/// Rows are lines of `key<TAB>value`: the key is everything before the
+/// first tab (so it can't hold a tab or a newline), the value everything
+/// after it up to the newline (a `\r` before it is dropped too). Each line
+/// is a row, numbered from 0.
+///
+/// Durability contract: rows go to the WAL in chunks of
+/// `LoadOptions::chunk_rows`, each written as one batch (see `batch`) and
+/// synced once, whatever `LSMConfig::sync_writes` says; a load never syncs
+/// per row. When a chunk's sync returns, every row up to the end of the
+/// chunk survives a crash. `LoadReport::durable_rows`, and
+/// `LoadInterrupted::durable_rows` for a load that failed, is the number of
+/// rows that are.
+///
+/// Resuming: a load started again with `LoadOptions::resume_from` set to
+/// that number skips the rows already loaded and picks up after them. With
+/// `LoadOptions::progress_key`, each chunk also writes the row count it
+/// brings the load to under that key, as the chunk's last entry, so the
+/// number survives a crash of the whole process: the WAL is replayed in
+/// order, so the key is only recovered when the rows before it are. A
+/// load with a progress key and no `resume_from` starts after the count
+/// stored there. Rows of a chunk that was cut short are loaded again on
+/// resume, which rewrites the same values; nothing is lost or applied out
+/// of order, as long as nothing else writes the loaded keys meanwhile.
+use std::fmt;
+use std::io::BufRead;
+
+use crate::batch::WriteBatch;
+use crate::options::WriteOptions;
+use crate::tree::LSMTree;
+
+/// Rows per chunk when `LoadOptions` isn't given one
+pub const DEFAULT_CHUNK_ROWS: usize = 1024;
+
+/// How `LSMTree::load_from_reader` loads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// Rows written to the WAL, and synced, together (at least 1)
+    pub chunk_rows: usize,
+
+    /// Rows at the start of the input already loaded by an earlier,
+    /// interrupted load, which are skipped (`None` to use the count under
+    /// `progress_key`, or start at row 0 without one)
+    pub resume_from: Option<u64>,
+
+    /// Key each chunk writes the load's durable row count under, as a
+    /// big-endian `u64` (see `load`)
+    pub progress_key: Option<Vec<u8>>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            chunk_rows: DEFAULT_CHUNK_ROWS,
+            resume_from: None,
+            progress_key: None,
+        }
+    }
+}
+
+/// What a finished `LSMTree::load_from_reader` did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    /// Rows in the input
+    pub rows_read: u64,
+
+    /// Rows skipped because an earlier load had them durable
+    pub rows_skipped: u64,
+
+    /// Rows written by this load
+    pub rows_loaded: u64,
+
+    /// Chunks written, each synced once
+    pub chunks: u64,
+
+    /// Rows from the start of the input that are durable: all of them,
+    /// once the load finished
+    pub durable_rows: u64,
+}
+
+/// A load that stopped partway (a bad row, or a failed read or write)
+///
+/// Carried inside the `io::Error` the load returns, with the kind of the
+/// error that stopped it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadInterrupted {
+    /// Rows from the start of the input that are durable; a load resumed
+    /// from here loses and repeats nothing
+    pub durable_rows: u64,
+
+    /// What stopped the load
+    pub cause: String,
+}
+
+impl LoadInterrupted {
+    /// The `LoadInterrupted` inside an `io::Error` returned by a load, if
+    /// any
+    pub fn from_io(error: &std::io::Error) -> Option<&LoadInterrupted> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for LoadInterrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "load interrupted after {} durable rows: {}",
+            self.durable_rows, self.cause
+        )
+    }
+}
+
+impl std::error::Error for LoadInterrupted {}
+
+/// Loads rows from `reader` into `tree` (see the module docs)
+pub(crate) fn load(
+    tree: &mut LSMTree,
+    reader: impl BufRead,
+    options: &LoadOptions,
+) -> std::io::Result<LoadReport> {
+    if options.chunk_rows == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "chunk_rows must be at least 1",
+        ));
+    }
+    let resume_from = match (options.resume_from, &options.progress_key) {
+        (Some(rows), _) => rows,
+        (None, Some(key)) => stored_progress(tree, key)?,
+        (None, None) => 0,
+    };
+    let mut loader = Loader {
+        tree,
+        options,
+        batch: WriteBatch::new(),
+        report: LoadReport {
+            durable_rows: resume_from,
+            ..LoadReport::default()
+        },
+    };
+    loader
+        .run(reader, resume_from)
+        .map_err(|error| loader.interrupted(error))?;
+    Ok(loader.report)
+}
+
+/// The row count stored under a progress key (0 if there is none)
+fn stored_progress(tree: &mut LSMTree, key: &[u8]) -> std::io::Result<u64> {
+    let Some(value) = tree.get(key) else {
+        return Ok(0);
+    };
+    let bytes: [u8; 8] = value.as_slice().try_into().map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "progress key holds {} bytes, not a big-endian u64",
+                value.len()
+            ),
+        )
+    })?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+struct Loader<'a> {
+    tree: &'a mut LSMTree,
+    options: &'a LoadOptions,
+    batch: WriteBatch,
+    report: LoadReport,
+}
+
+impl Loader<'_> {
+    fn run(&mut self, mut reader: impl BufRead, resume_from: u64) -> std::io::Result<()> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let row = self.report.rows_read;
+            self.report.rows_read += 1;
+            if row < resume_from {
+                self.report.rows_skipped += 1;
+                continue;
+            }
+            let (key, value) = parse_row(&line).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("row {} has no tab between key and value", row),
+                )
+            })?;
+            self.batch.put(key.to_vec(), value.to_vec());
+            if self.batch.len() == self.options.chunk_rows {
+                self.write_chunk()?;
+            }
+        }
+        self.write_chunk()
+    }
+
+    /// Writes and syncs the rows batched so far, with the progress key
+    fn write_chunk(&mut self) -> std::io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let rows = self.batch.len() as u64;
+        let durable_rows = self.report.durable_rows + rows;
+        let mut batch = std::mem::take(&mut self.batch);
+        if let Some(key) = &self.options.progress_key {
+            batch.put(key.clone(), durable_rows.to_be_bytes().to_vec());
+        }
+        let options = WriteOptions {
+            sync: Some(true),
+            disable_wal: false,
+        };
+        self.tree.write_opt(batch, &options)?;
+        self.report.rows_loaded += rows;
+        self.report.chunks += 1;
+        self.report.durable_rows = durable_rows;
+        Ok(())
+    }
+
+    /// Wraps the error that stopped the load with how far it got
+    fn interrupted(&self, error: std::io::Error) -> std::io::Error {
+        std::io::Error::new(
+            error.kind(),
+            LoadInterrupted {
+                durable_rows: self.report.durable_rows,
+                cause: error.to_string(),
+            },
+        )
+    }
+}
+
+/// Splits a line into key and value at the first tab, without the newline
+fn parse_row(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let tab = line.iter().position(|&b| b == b'\t')?;
+    Some((&line[..tab], &line[tab + 1..]))
+}
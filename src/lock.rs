@@ -0,0 +1,46 @@
+/// Exclusive lock on a data directory
+///
+/// A tree opened for writing holds an advisory lock on the `LOCK` file in
+/// its data directory until it is dropped, so a second writer (another
+/// process, or a second `LSMTree` in the same one) can't open the directory
+/// and interleave its flushes and manifest updates with the first.
+/// `LSMTree::repair` takes the same lock before it rewrites the manifest.
+/// Read-only trees don't take it.
+///
+/// The lock belongs to the open file, so the operating system releases it
+/// when a process dies; a stale `LOCK` file left behind by a crash never
+/// blocks the next open.
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+
+/// File name of the lock file inside the data directory
+pub const LOCK_FILE: &str = "LOCK";
+
+/// A held directory lock, released when dropped
+#[derive(Debug)]
+pub(crate) struct DirLock {
+    _file: File,
+}
+
+impl DirLock {
+    /// Takes the lock of `data_dir`, failing with `WouldBlock` if someone
+    /// else holds it
+    pub(crate) fn acquire(data_dir: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(data_dir.join(LOCK_FILE))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(std::io::Error::new(
+                std::io::ErrorKind::WouldBlock,
+                format!(
+                    "Data directory {} is locked by another writer",
+                    data_dir.display()
+                ),
+            )),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+}
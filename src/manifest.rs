@@ -0,0 +1,328 @@
+/// Manifest: the authoritative list of live SSTables
+///
+/// Scanning the directory for `sstable_*.db` files can't tell a finished
+/// SSTable from one that was half-written when the process crashed, and it
+/// can't express ordering that differs from file numbers. The manifest
+/// fixes both: an SSTable only becomes part of the tree once the manifest
+/// that lists it has been written.
+///
+/// The file is plain text so it's easy to inspect by hand:
+///
+/// ```text
+/// lsm-manifest 4
+/// next_file_number 7
+/// wal_dir /mnt/ssd/wal
+/// cold_dir /mnt/hdd/cold
+/// sstable 6 gen=5 max_seq=912 entries=300 tombstones=12 shadowed=0 obsolete=0 tier=hot compacted=0
+/// sstable 4 gen=3 max_seq=455 entries=455 tombstones=0 shadowed=0 obsolete=140 tier=cold compacted=1
+/// ```
+///
+/// `sstable` lines are ordered newest first. `gen` is the table's generation:
+/// a table with a higher generation holds newer data, no matter what its
+/// file number or position is. `max_seq` is the highest write
+/// sequence number flushed into that table; recovery uses it to skip WAL
+/// entries that already reached an SSTable. Version 1 manifests have neither
+/// attribute and load with 0 for both; the tree then derives generations
+/// from the line order.
+///
+/// `entries`, `tombstones`, `shadowed` and `obsolete` are the table's
+/// `TableStats` (see `garbage`). Manifests before version 3 don't have them;
+/// their tables load with all four at 0, which reads as no garbage.
+///
+/// `tier` says whether the table lives in the data directory (`hot`) or in
+/// the directory of the `cold_dir` line (`cold`), and `compacted` whether a
+/// compaction wrote it. Manifests before version 4 have neither; their
+/// tables are hot and count as flushed.
+///
+/// The optional `wal_dir` line records where the WAL lives when it is kept
+/// outside the data directory. Updates are atomic: the new
+/// contents are written to `MANIFEST.tmp`, synced, then renamed over
+/// `MANIFEST`, so a crash leaves either the old or the new version.
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::garbage::TableStats;
+
+/// File name of the manifest inside the data directory
+pub const MANIFEST_FILE: &str = "MANIFEST";
+
+/// Manifest format version written by this build
+const MANIFEST_VERSION: u32 = 4;
+
+/// One live SSTable as recorded in the manifest
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TableEntry {
+    /// File number (`sstable_<number>.db`)
+    pub number: usize,
+
+    /// Generation of the table; higher is newer (0 if unknown)
+    pub generation: u64,
+
+    /// Highest sequence number of any write in the table (0 if unknown)
+    pub max_sequence: u64,
+
+    /// Records and estimated garbage in the table (all 0 if unknown)
+    pub stats: TableStats,
+
+    /// Whether the table lives in `Manifest::cold_dir`
+    pub cold: bool,
+
+    /// Whether a compaction wrote the table
+    pub compacted: bool,
+}
+
+/// In-memory copy of the manifest
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    /// File number to use for the next SSTable
+    pub next_file_number: usize,
+
+    /// Directory of the WAL, or `None` when it lives in the data directory
+    pub wal_dir: Option<PathBuf>,
+
+    /// Directory of the cold tables, if the tree has a cold directory
+    pub cold_dir: Option<PathBuf>,
+
+    /// Live SSTables, newest first
+    pub sstables: Vec<TableEntry>,
+}
+
+impl Manifest {
+    /// Highest sequence number that has reached any SSTable
+    pub fn max_flushed_sequence(&self) -> u64 {
+        self.sstables
+            .iter()
+            .map(|t| t.max_sequence)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the manifest path for a data directory
+    pub fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(MANIFEST_FILE)
+    }
+
+    /// Loads the manifest from a data directory
+    ///
+    /// Returns `Ok(None)` if the directory has no manifest yet (a new tree,
+    /// or one created before manifests existed).
+    pub fn load(data_dir: &Path) -> std::io::Result<Option<Self>> {
+        let path = Self::path(data_dir);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let invalid = |line: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid manifest line in {}: {:?}", path.display(), line),
+            )
+        };
+
+        let mut manifest = Manifest::default();
+        let mut lines = BufReader::new(file).lines();
+
+        let header = lines.next().transpose()?.unwrap_or_default();
+        match header.strip_prefix("lsm-manifest ") {
+            Some(version) => {
+                let version: u32 = version.trim().parse().map_err(|_| invalid(&header))?;
+                if version > MANIFEST_VERSION {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "Manifest {} has version {}, but this build only supports versions up to {}",
+                            path.display(),
+                            version,
+                            MANIFEST_VERSION
+                        ),
+                    ));
+                }
+            }
+            None => return Err(invalid(&header)),
+        }
+
+        for line in lines {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (field, value) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+            match field {
+                "next_file_number" => {
+                    manifest.next_file_number = value.parse().map_err(|_| invalid(line))?;
+                }
+                "wal_dir" => manifest.wal_dir = Some(PathBuf::from(value)),
+                "cold_dir" => manifest.cold_dir = Some(PathBuf::from(value)),
+                "sstable" => {
+                    let mut parts = value.split_whitespace();
+                    let mut table = TableEntry {
+                        number: parts
+                            .next()
+                            .and_then(|n| n.parse().ok())
+                            .ok_or_else(|| invalid(line))?,
+                        ..TableEntry::default()
+                    };
+                    for attr in parts {
+                        match attr.split_once('=') {
+                            Some(("gen", generation)) => {
+                                table.generation = generation.parse().map_err(|_| invalid(line))?;
+                            }
+                            Some(("max_seq", seq)) => {
+                                table.max_sequence = seq.parse().map_err(|_| invalid(line))?;
+                            }
+                            Some(("tier", tier)) => {
+                                table.cold = match tier {
+                                    "hot" => false,
+                                    "cold" => true,
+                                    _ => return Err(invalid(line)),
+                                };
+                            }
+                            Some(("compacted", flag)) => {
+                                table.compacted = match flag {
+                                    "0" => false,
+                                    "1" => true,
+                                    _ => return Err(invalid(line)),
+                                };
+                            }
+                            Some((name, count)) => {
+                                let stat = match name {
+                                    "entries" => &mut table.stats.entries,
+                                    "tombstones" => &mut table.stats.tombstones,
+                                    "shadowed" => &mut table.stats.shadowed,
+                                    "obsolete" => &mut table.stats.obsolete,
+                                    _ => return Err(invalid(line)),
+                                };
+                                *stat = count.parse().map_err(|_| invalid(line))?;
+                            }
+                            _ => return Err(invalid(line)),
+                        }
+                    }
+                    manifest.sstables.push(table);
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+
+        Ok(Some(manifest))
+    }
+
+    /// Atomically replaces the manifest in a data directory
+    pub fn store(&self, data_dir: &Path) -> std::io::Result<()> {
+        let tmp_path = data_dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&tmp_path)?;
+        let mut writer = BufWriter::new(file);
+
+        writeln!(writer, "lsm-manifest {}", MANIFEST_VERSION)?;
+        writeln!(writer, "next_file_number {}", self.next_file_number)?;
+        if let Some(wal_dir) = &self.wal_dir {
+            writeln!(writer, "wal_dir {}", wal_dir.display())?;
+        }
+        if let Some(cold_dir) = &self.cold_dir {
+            writeln!(writer, "cold_dir {}", cold_dir.display())?;
+        }
+        for table in &self.sstables {
+            writeln!(
+                writer,
+                "sstable {} gen={} max_seq={} entries={} tombstones={} shadowed={} obsolete={} \
+                 tier={} compacted={}",
+                table.number,
+                table.generation,
+                table.max_sequence,
+                table.stats.entries,
+                table.stats.tombstones,
+                table.stats.shadowed,
+                table.stats.obsolete,
+                if table.cold { "cold" } else { "hot" },
+                table.compacted as u8
+            )?;
+        }
+
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+
+        std::fs::rename(&tmp_path, Self::path(data_dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let dir = PathBuf::from("./test_manifest_round_trip");
+        fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(Manifest::load(&dir).unwrap(), None);
+
+        let manifest = Manifest {
+            next_file_number: 7,
+            wal_dir: Some(PathBuf::from("/mnt/fast ssd/wal")),
+            cold_dir: Some(PathBuf::from("/mnt/slow disk/cold")),
+            sstables: [(6, 3, 30), (4, 2, 20), (1, 1, 0)]
+                .into_iter()
+                .map(|(number, generation, max_sequence)| TableEntry {
+                    number,
+                    generation,
+                    max_sequence,
+                    stats: TableStats {
+                        entries: 40,
+                        tombstones: 3,
+                        shadowed: 1,
+                        obsolete: number as u64,
+                    },
+                    cold: generation == 1,
+                    compacted: generation < 3,
+                })
+                .collect(),
+        };
+        manifest.store(&dir).unwrap();
+        let loaded = Manifest::load(&dir).unwrap().unwrap();
+        assert_eq!(loaded, manifest);
+        assert_eq!(loaded.max_flushed_sequence(), 30);
+        assert!(!dir.join("MANIFEST.tmp").exists());
+
+        // Version 1 manifests carry no sequence numbers
+        fs::write(
+            Manifest::path(&dir),
+            "lsm-manifest 1\nnext_file_number 3\nsstable 2\n",
+        )
+        .unwrap();
+        let old = Manifest::load(&dir).unwrap().unwrap();
+        assert_eq!(
+            old.sstables,
+            vec![TableEntry {
+                number: 2,
+                ..TableEntry::default()
+            }]
+        );
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_manifest_rejects_garbage() {
+        let dir = PathBuf::from("./test_manifest_garbage");
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(Manifest::path(&dir), "lsm-manifest 1\nbogus 3\n").unwrap();
+        let err = Manifest::load(&dir).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        fs::write(Manifest::path(&dir), "lsm-manifest 99\n").unwrap();
+        let err = Manifest::load(&dir).unwrap_err();
+        assert!(err.to_string().contains("version 99"));
+
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,695 @@
+/// In-memory write buffer of the LSM tree
+///
+/// The memtable holds the most recent writes, sorted by key, until it grows
+/// past the configured threshold and is flushed to an SSTable. A `None`
+/// value is a tombstone left by a delete: it has to be kept (and flushed)
+/// so it keeps shadowing older versions of the key in SSTables.
+///
+/// Every write carries its sequence number. An overwrite normally replaces
+/// the previous version, but when a pinned sequence can still see that
+/// version (`LSMTree::pin_sequence`) it is kept as well, so reads "as of"
+/// the pinned sequence keep working.
+///
+/// Besides the entries, the memtable tracks its approximate size in bytes,
+/// which drives the flush trigger. All changes go through `insert`,
+/// `insert_version` and `remove` so the size can't drift away from the
+/// entries it describes.
+///
+/// A write is a single step as far as any reader can tell: the key's
+/// version list is replaced whole and the size adjusted in the same call,
+/// and nothing can read the memtable while that call runs (it takes
+/// `&mut self`). A reader therefore gets the complete old value or the
+/// complete new value, never part of each, and the size always matches the
+/// entries. The tree keeps it that way when it is shared: readers hold the
+/// memtable through an `Arc` (`TreeState`), and a write copies it first
+/// (`Arc::make_mut`) instead of changing what they see.
+///
+/// The entries live in a `BTreeMap` by default. With the `skiplist`
+/// feature, `MemtableBackend::SkipList` keeps them in a lock-free skiplist
+/// instead, which can be read while it is written to. Both behave the same
+/// through this API; lookups and iterators hand out owned copies, since
+/// skiplist entries can't be borrowed past a single access.
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::ops::{Bound, RangeBounds};
+
+#[cfg(feature = "skiplist")]
+use crossbeam_skiplist::{SkipMap, map as skip_map};
+
+use crate::iter::KeyRange;
+
+/// One version of a key: the write's sequence number and value (`None`
+/// for a tombstone)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version {
+    pub seq: u64,
+    pub value: Option<Vec<u8>>,
+}
+
+/// Data structure holding the memtable's entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemtableBackend {
+    /// `BTreeMap`: compact and fast, but every write needs `&mut`
+    BTree,
+
+    /// Lock-free skiplist (`crossbeam-skiplist`)
+    #[cfg(feature = "skiplist")]
+    SkipList,
+}
+
+impl MemtableBackend {
+    /// Every backend compiled into this build
+    pub fn available() -> Vec<MemtableBackend> {
+        vec![
+            MemtableBackend::BTree,
+            #[cfg(feature = "skiplist")]
+            MemtableBackend::SkipList,
+        ]
+    }
+
+    /// Lowercase name, as accepted by `from_name`
+    pub fn name(self) -> &'static str {
+        match self {
+            MemtableBackend::BTree => "btree",
+            #[cfg(feature = "skiplist")]
+            MemtableBackend::SkipList => "skiplist",
+        }
+    }
+
+    /// Looks up a backend by name, if it is compiled in
+    pub fn from_name(name: &str) -> Option<MemtableBackend> {
+        Self::available()
+            .into_iter()
+            .find(|backend| backend.name() == name)
+    }
+}
+
+impl Default for MemtableBackend {
+    /// `BTree`
+    ///
+    /// Test builds can pick another backend with the
+    /// `LSM_TEST_MEMTABLE_BACKEND` environment variable, so the whole test
+    /// suite can run against each one.
+    fn default() -> Self {
+        #[cfg(test)]
+        if let Some(backend) = std::env::var("LSM_TEST_MEMTABLE_BACKEND")
+            .ok()
+            .and_then(|name| Self::from_name(&name))
+        {
+            return backend;
+        }
+        MemtableBackend::BTree
+    }
+}
+
+/// The versions of every key, newest first (never empty)
+#[derive(Debug)]
+enum Entries {
+    BTree(BTreeMap<Vec<u8>, Vec<Version>>),
+    #[cfg(feature = "skiplist")]
+    SkipList(Box<SkipMap<Vec<u8>, Vec<Version>>>),
+}
+
+impl Entries {
+    fn new(backend: MemtableBackend) -> Self {
+        match backend {
+            MemtableBackend::BTree => Entries::BTree(BTreeMap::new()),
+            #[cfg(feature = "skiplist")]
+            MemtableBackend::SkipList => Entries::SkipList(Box::default()),
+        }
+    }
+
+    fn backend(&self) -> MemtableBackend {
+        match self {
+            Entries::BTree(_) => MemtableBackend::BTree,
+            #[cfg(feature = "skiplist")]
+            Entries::SkipList(_) => MemtableBackend::SkipList,
+        }
+    }
+
+    /// Runs `f` on the versions of a key, if it has any
+    fn with_versions<R>(&self, key: &[u8], f: impl FnOnce(&[Version]) -> R) -> Option<R> {
+        match self {
+            Entries::BTree(map) => map.get(key).map(|versions| f(versions)),
+            #[cfg(feature = "skiplist")]
+            Entries::SkipList(map) => map.get(key).map(|entry| f(entry.value())),
+        }
+    }
+
+    /// Applies `update` to the versions of a key (empty if it has none)
+    fn update<R>(&mut self, key: Vec<u8>, update: impl FnOnce(&mut Vec<Version>) -> R) -> R {
+        match self {
+            Entries::BTree(map) => update(map.entry(key).or_default()),
+            #[cfg(feature = "skiplist")]
+            Entries::SkipList(map) => {
+                // Entries are immutable once inserted; swap in a new one
+                let mut versions = map
+                    .get(&key)
+                    .map(|entry| entry.value().clone())
+                    .unwrap_or_default();
+                let result = update(&mut versions);
+                map.insert(key, versions);
+                result
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<Version>> {
+        match self {
+            Entries::BTree(map) => map.remove(key),
+            #[cfg(feature = "skiplist")]
+            Entries::SkipList(map) => map.remove(key).map(|entry| entry.value().clone()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Entries::BTree(map) => map.len(),
+            #[cfg(feature = "skiplist")]
+            Entries::SkipList(map) => map.len(),
+        }
+    }
+
+    fn range(&self, range: KeyRange) -> RawIter<'_> {
+        match self {
+            Entries::BTree(map) => RawIter::BTree(map.range(range)),
+            #[cfg(feature = "skiplist")]
+            Entries::SkipList(map) => RawIter::SkipList(map.range(range)),
+        }
+    }
+}
+
+impl Clone for Entries {
+    fn clone(&self) -> Self {
+        match self {
+            Entries::BTree(map) => Entries::BTree(map.clone()),
+            #[cfg(feature = "skiplist")]
+            Entries::SkipList(map) => Entries::SkipList(Box::new(
+                map.iter()
+                    .map(|entry| (entry.key().clone(), entry.value().clone()))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+/// Sorted map of the latest writes plus their approximate size
+#[derive(Debug, Clone)]
+pub struct Memtable {
+    /// Versions of each key, newest first (never empty)
+    entries: Entries,
+
+    /// Sum of `entry_size` over all versions
+    size: usize,
+}
+
+impl Default for Memtable {
+    fn default() -> Self {
+        Self::with_backend(MemtableBackend::default())
+    }
+}
+
+impl Memtable {
+    /// Creates an empty memtable with the default backend
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty memtable keeping its entries in `backend`
+    pub fn with_backend(backend: MemtableBackend) -> Self {
+        Self {
+            entries: Entries::new(backend),
+            size: 0,
+        }
+    }
+
+    /// Returns the data structure holding the entries
+    pub fn backend(&self) -> MemtableBackend {
+        self.entries.backend()
+    }
+
+    /// Approximate footprint of one entry (tombstones count the key only)
+    pub fn entry_size(key: &[u8], value: Option<&[u8]>) -> usize {
+        key.len() + value.map_or(0, |v| v.len())
+    }
+
+    /// Inserts a value, or a tombstone for `None`, replacing any older entry
+    ///
+    /// The entry gets sequence number 0. Returns how much the memtable size
+    /// changed.
+    pub fn insert(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) -> isize {
+        self.insert_version(key, value, 0, None)
+    }
+
+    /// Inserts a version written at sequence `seq`
+    ///
+    /// The previous newest version is replaced unless `newest_pin` (the
+    /// highest pinned sequence) is at or above its sequence: that pin still
+    /// sees it, so it is kept behind the new version. Returns how much the
+    /// memtable size changed.
+    pub fn insert_version(
+        &mut self,
+        key: Vec<u8>,
+        value: Option<Vec<u8>>,
+        seq: u64,
+        newest_pin: Option<u64>,
+    ) -> isize {
+        let key_len = key.len();
+        let added = Self::entry_size(&key, value.as_deref());
+
+        let removed = self.entries.update(key, |versions| {
+            let mut removed = 0;
+            if let Some(previous) = versions.first()
+                && newest_pin.is_none_or(|pin| pin < previous.seq)
+            {
+                let previous = versions.remove(0);
+                removed = key_len + previous.value.map_or(0, |v| v.len());
+            }
+            versions.insert(0, Version { seq, value });
+            removed
+        });
+
+        self.resize(added, removed);
+        added as isize - removed as isize
+    }
+
+    /// Removes an entry entirely, all versions included (not a delete: no
+    /// tombstone is left)
+    ///
+    /// Returns how much the memtable size changed (0 if the key was absent).
+    pub fn remove(&mut self, key: &[u8]) -> isize {
+        match self.entries.remove(key) {
+            Some(versions) => {
+                let removed = versions
+                    .iter()
+                    .map(|v| Self::entry_size(key, v.value.as_deref()))
+                    .sum();
+                self.resize(0, removed);
+                -(removed as isize)
+            }
+            None => 0,
+        }
+    }
+
+    /// Applies one write's change to the size, without ever wrapping around
+    ///
+    /// A wrapped size would look enormous and trigger a flush on every
+    /// write from then on. The accounting should never disagree with the
+    /// entries, so debug builds treat that as a bug.
+    fn resize(&mut self, added: usize, removed: usize) {
+        debug_assert!(
+            removed <= self.size,
+            "memtable size accounting underflow: removing {} from {}",
+            removed,
+            self.size
+        );
+        self.size = self.size.saturating_sub(removed) + added;
+    }
+
+    /// Recomputes the size from the entries, to check the accounting
+    #[cfg(test)]
+    pub(crate) fn recount_size(&self) -> usize {
+        self.iter_versions()
+            .map(|(key, versions)| {
+                versions
+                    .iter()
+                    .map(|v| Self::entry_size(&key, v.value.as_deref()))
+                    .sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// Looks up a key: `Some(Some(value))`, `Some(None)` for a tombstone,
+    /// or `None` if the memtable has no entry for it
+    pub fn get(&self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        self.entries
+            .with_versions(key, |versions| versions[0].value.clone())
+    }
+
+    /// Looks up the newest version of a key written at or before `seq`
+    ///
+    /// Returns `None` if the memtable holds no such version, in which case
+    /// older data has to be consulted.
+    pub fn get_at(&self, key: &[u8], seq: u64) -> Option<Option<Vec<u8>>> {
+        self.entries
+            .with_versions(key, |versions| {
+                versions
+                    .iter()
+                    .find(|version| version.seq <= seq)
+                    .map(|version| version.value.clone())
+            })
+            .flatten()
+    }
+
+    /// Sequence number of the newest version of a key
+    pub fn newest_sequence(&self, key: &[u8]) -> Option<u64> {
+        self.entries.with_versions(key, |versions| versions[0].seq)
+    }
+
+    /// Returns every retained version of a key, newest first
+    pub fn versions(&self, key: &[u8]) -> Vec<Version> {
+        self.entries
+            .with_versions(key, |versions| versions.to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Approximate size of all entries in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of keys, tombstones included (older versions don't count)
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if there are no entries (not even tombstones)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every entry
+    pub fn clear(&mut self) {
+        self.entries = Entries::new(self.backend());
+        self.size = 0;
+    }
+
+    /// Iterates over the newest version of every key in key order,
+    /// tombstones included
+    pub fn iter(&self) -> Iter<'_> {
+        self.range(..)
+    }
+
+    /// Returns up to `limit` live entries in key order, starting after the
+    /// first `offset` live entries (tombstones don't count)
+    ///
+    /// Only the entries returned are copied; the ones skipped are not.
+    pub fn live_page(&self, offset: usize, limit: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut raw = self.entries.range((Bound::Unbounded, Bound::Unbounded));
+        let mut skipped = 0;
+        let mut page = Vec::new();
+        while page.len() < limit {
+            let next = raw.next_with(|key, versions| {
+                let value = versions[0].value.as_ref()?;
+                if skipped < offset {
+                    skipped += 1;
+                    return None;
+                }
+                Some((key.clone(), value.clone()))
+            });
+            match next {
+                Some(row) => page.extend(row),
+                None => break,
+            }
+        }
+        page
+    }
+
+    /// Number of keys whose newest version is a value, not a tombstone
+    pub fn live_len(&self) -> usize {
+        let mut raw = self.entries.range((Bound::Unbounded, Bound::Unbounded));
+        let mut count = 0;
+        while let Some(live) = raw.next_with(|_, versions| versions[0].value.is_some()) {
+            count += live as usize;
+        }
+        count
+    }
+
+    /// Iterates over all keys in order, tombstones included
+    pub fn keys(&self) -> Keys<'_> {
+        Keys(self.entries.range((Bound::Unbounded, Bound::Unbounded)))
+    }
+
+    /// Iterates over the newest version of the keys in a range, tombstones
+    /// included
+    pub fn range<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Iter<'_> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        Iter(self.entries.range(bounds))
+    }
+
+    /// Iterates over every key with all of its retained versions, newest
+    /// first
+    pub fn iter_versions(&self) -> VersionsIter<'_> {
+        self.range_versions(..)
+    }
+
+    /// Iterates over the keys in a range with all of their retained
+    /// versions, newest first
+    pub fn range_versions<R: RangeBounds<Vec<u8>>>(&self, range: R) -> VersionsIter<'_> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        VersionsIter(self.entries.range(bounds))
+    }
+}
+
+/// Range over the skiplist backend
+#[cfg(feature = "skiplist")]
+type SkipRange<'a> = skip_map::Range<'a, Vec<u8>, KeyRange, Vec<u8>, Vec<Version>>;
+
+/// Walks the entries of either backend in key order
+enum RawIter<'a> {
+    BTree(btree_map::Range<'a, Vec<u8>, Vec<Version>>),
+    #[cfg(feature = "skiplist")]
+    SkipList(SkipRange<'a>),
+}
+
+impl RawIter<'_> {
+    /// Moves to the next key and runs `f` on it and its versions
+    fn next_with<R>(&mut self, f: impl FnOnce(&Vec<u8>, &[Version]) -> R) -> Option<R> {
+        match self {
+            RawIter::BTree(range) => range.next().map(|(key, versions)| f(key, versions)),
+            #[cfg(feature = "skiplist")]
+            RawIter::SkipList(range) => range.next().map(|entry| f(entry.key(), entry.value())),
+        }
+    }
+
+    /// Moves to the previous key from the back and runs `f` on it
+    fn next_back_with<R>(&mut self, f: impl FnOnce(&Vec<u8>, &[Version]) -> R) -> Option<R> {
+        match self {
+            RawIter::BTree(range) => range.next_back().map(|(key, versions)| f(key, versions)),
+            #[cfg(feature = "skiplist")]
+            RawIter::SkipList(range) => {
+                range.next_back().map(|entry| f(entry.key(), entry.value()))
+            }
+        }
+    }
+}
+
+/// Iterator over the newest version of each key (see `Memtable::iter`)
+pub struct Iter<'a>(RawIter<'a>);
+
+impl Iterator for Iter<'_> {
+    type Item = (Vec<u8>, Option<Vec<u8>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .next_with(|key, versions| (key.clone(), versions[0].value.clone()))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0
+            .next_back_with(|key, versions| (key.clone(), versions[0].value.clone()))
+    }
+}
+
+/// Iterator over the keys of a memtable (see `Memtable::keys`)
+pub struct Keys<'a>(RawIter<'a>);
+
+impl Iterator for Keys<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_with(|key, _| key.clone())
+    }
+}
+
+/// Iterator over every key with all its versions (see
+/// `Memtable::iter_versions`)
+pub struct VersionsIter<'a>(RawIter<'a>);
+
+impl Iterator for VersionsIter<'_> {
+    type Item = (Vec<u8>, Vec<Version>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .next_with(|key, versions| (key.clone(), versions.to_vec()))
+    }
+}
+
+impl<'a> IntoIterator for &'a Memtable {
+    type Item = (Vec<u8>, Option<Vec<u8>>);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_overwrites_keep_size_exact() {
+        for backend in MemtableBackend::available() {
+            let mut memtable = Memtable::with_backend(backend);
+
+            assert_eq!(memtable.insert(b"key".to_vec(), Some(vec![0; 10])), 13);
+            assert_eq!(memtable.insert(b"key".to_vec(), Some(vec![0; 4])), -6);
+            for len in 0..100 {
+                memtable.insert(b"key".to_vec(), Some(vec![0; len]));
+            }
+            assert_eq!(memtable.size(), 3 + 99);
+            assert_eq!(memtable.len(), 1);
+
+            memtable.insert(b"other".to_vec(), Some(b"v".to_vec()));
+            assert_eq!(memtable.size(), 3 + 99 + 6);
+
+            memtable.clear();
+            assert_eq!(memtable.size(), 0);
+            assert!(memtable.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_tombstones_count_only_the_key() {
+        for backend in MemtableBackend::available() {
+            let mut memtable = Memtable::with_backend(backend);
+
+            // Deleting a key the memtable never saw still stores a tombstone
+            assert_eq!(memtable.insert(b"missing".to_vec(), None), 7);
+            assert_eq!(memtable.get(b"missing"), Some(None));
+
+            memtable.insert(b"key".to_vec(), Some(b"value".to_vec()));
+            assert_eq!(memtable.size(), 7 + 8);
+
+            // Replacing a value with a tombstone gives back the value bytes
+            assert_eq!(memtable.insert(b"key".to_vec(), None), -5);
+            assert_eq!(memtable.size(), 7 + 3);
+            assert_eq!(memtable.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_live_pages_are_stable_and_limited() {
+        for backend in MemtableBackend::available() {
+            let mut memtable = Memtable::with_backend(backend);
+            for i in 0..10u8 {
+                memtable.insert(vec![i], Some(vec![i; 3]));
+            }
+            memtable.insert(vec![3], None);
+            memtable.insert(vec![42], None);
+            assert_eq!(memtable.live_len(), 9);
+
+            let all = memtable.live_page(0, usize::MAX);
+            let live: Vec<_> = memtable.iter().filter_map(|(k, v)| Some((k, v?))).collect();
+            assert_eq!(all, live);
+            for offset in 0..=all.len() + 1 {
+                for limit in 0..4 {
+                    let page = memtable.live_page(offset, limit);
+                    let end = (offset + limit).min(all.len());
+                    assert_eq!(page, all[offset.min(end)..end]);
+                }
+            }
+            assert_eq!(
+                memtable.live_page(3, 2),
+                vec![(vec![4], vec![4; 3]), (vec![5], vec![5; 3])]
+            );
+        }
+    }
+
+    #[test]
+    fn test_remove_missing_key_changes_nothing() {
+        for backend in MemtableBackend::available() {
+            let mut memtable = Memtable::with_backend(backend);
+            memtable.insert(b"a".to_vec(), Some(b"1".to_vec()));
+
+            assert_eq!(memtable.remove(b"nope"), 0);
+            assert_eq!(memtable.size(), 2);
+
+            assert_eq!(memtable.remove(b"a"), -2);
+            assert_eq!(memtable.remove(b"a"), 0);
+            assert_eq!(memtable.size(), 0);
+            assert_eq!(memtable.get(b"a"), None);
+        }
+    }
+
+    #[test]
+    fn test_pinned_versions_are_kept() {
+        for backend in MemtableBackend::available() {
+            let mut memtable = Memtable::with_backend(backend);
+            memtable.insert_version(b"k".to_vec(), Some(b"v1".to_vec()), 1, None);
+            // Nothing pins sequence 1, so v1 is replaced
+            memtable.insert_version(b"k".to_vec(), Some(b"v2".to_vec()), 2, None);
+            assert_eq!(memtable.versions(b"k").len(), 1);
+
+            // A pin at 2 still sees v2
+            memtable.insert_version(b"k".to_vec(), None, 3, Some(2));
+            assert_eq!(memtable.size(), 1 + (1 + 2));
+            assert_eq!(memtable.get(b"k"), Some(None));
+            assert_eq!(memtable.get_at(b"k", 2), Some(Some(b"v2".to_vec())));
+            assert_eq!(memtable.get_at(b"k", 1), None);
+
+            // The tombstone at 3 isn't pinned, so only it is replaced
+            let delta = memtable.insert_version(b"k".to_vec(), Some(b"v4".to_vec()), 4, Some(2));
+            assert_eq!(delta, 2);
+            let seqs: Vec<u64> = memtable.versions(b"k").iter().map(|v| v.seq).collect();
+            assert_eq!(seqs, [4, 2]);
+            assert_eq!(memtable.len(), 1);
+
+            assert_eq!(memtable.remove(b"k"), -(3 + 3));
+            assert_eq!(memtable.size(), 0);
+        }
+    }
+
+    #[test]
+    fn test_backends_agree_on_mixed_operations() {
+        let mut memtables: Vec<Memtable> = MemtableBackend::available()
+            .into_iter()
+            .map(Memtable::with_backend)
+            .collect();
+
+        // Deterministic pseudo-random puts, deletes, removes and pinned
+        // overwrites over a small key space
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for seq in 1..2000u64 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let key = format!("key{:02}", state % 50).into_bytes();
+            let value = (!state.is_multiple_of(5)).then(|| vec![b'v'; (state % 17) as usize]);
+            let pin = state.is_multiple_of(3).then_some(seq - 1);
+            for memtable in &mut memtables {
+                if state.is_multiple_of(11) {
+                    memtable.remove(&key);
+                } else {
+                    memtable.insert_version(key.clone(), value.clone(), seq, pin);
+                }
+            }
+        }
+
+        let reference = &memtables[0];
+        for memtable in &memtables[1..] {
+            assert_eq!(memtable.size(), reference.size());
+            assert_eq!(memtable.len(), reference.len());
+            assert!(memtable.iter().eq(reference.iter()));
+            assert!(memtable.iter_versions().eq(reference.iter_versions()));
+            let range = b"key10".to_vec()..b"key20".to_vec();
+            assert!(memtable.range(range.clone()).eq(reference.range(range)));
+            assert_eq!(
+                memtable.get_at(b"key07", 1000),
+                reference.get_at(b"key07", 1000)
+            );
+        }
+        let expected_size: usize = reference
+            .iter_versions()
+            .flat_map(|(key, versions)| {
+                versions
+                    .into_iter()
+                    .map(move |v| Memtable::entry_size(&key, v.value.as_deref()))
+            })
+            .sum();
+        assert_eq!(reference.size(), expected_size);
+    }
+}
@@ -0,0 +1,719 @@
+/// Latency histograms and Prometheus text output
+///
+/// Averages hide tail latency: a tree that answers most reads in a few
+/// microseconds but stalls for 50 ms on every flush looks fine on average.
+/// Every get, put and flush duration is therefore recorded in a
+/// log-bucketed histogram (in the spirit of HDR histograms), from which
+/// percentiles are read.
+///
+/// Bucketing: durations are recorded in nanoseconds. Values below 16 get an
+/// exact bucket; above that, every power of two is split into 8 linear
+/// sub-buckets, so a reported percentile is at most 12.5% above the true
+/// value. That takes 496 counters per histogram and covers every `u64`.
+///
+/// Counters are atomics, so recording needs only `&self` and costs one
+/// relaxed increment plus a compare for the maximum.
+///
+/// Per-SSTable lookup counters (`TableReadStats`) are atomics too, one set
+/// per table, so the tables absorbing the probes can be told apart.
+///
+/// Disk throughput is tracked by `RateTracker`s: bytes with a timestamp,
+/// turned into bytes per second over a short sliding window.
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Number of linear sub-buckets per power of two
+const SUB_BUCKETS: u64 = 8;
+
+/// log2 of `SUB_BUCKETS`
+const SUB_BUCKET_BITS: u32 = 3;
+
+/// Total number of buckets needed for the full `u64` range
+const NUM_BUCKETS: usize = ((64 - SUB_BUCKET_BITS as usize) * SUB_BUCKETS as usize) + 8;
+
+/// Operations whose latency is tracked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyOp {
+    /// Point lookups (`get`, `get_immut`)
+    Get,
+
+    /// Writes (`put` and `delete`), including any flush they trigger
+    Put,
+
+    /// Memtable flushes
+    Flush,
+
+    /// SSTable compactions (nothing compacts yet, so this stays empty)
+    Compaction,
+}
+
+impl LatencyOp {
+    /// All tracked operations
+    pub const ALL: [LatencyOp; 4] = [
+        LatencyOp::Get,
+        LatencyOp::Put,
+        LatencyOp::Flush,
+        LatencyOp::Compaction,
+    ];
+
+    /// Lowercase name, as used in metric labels
+    pub fn name(self) -> &'static str {
+        match self {
+            LatencyOp::Get => "get",
+            LatencyOp::Put => "put",
+            LatencyOp::Flush => "flush",
+            LatencyOp::Compaction => "compaction",
+        }
+    }
+}
+
+/// Percentiles of one operation's recorded latencies
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LatencyPercentiles {
+    /// Number of recorded operations
+    pub count: u64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    /// Exact slowest recorded duration
+    pub max: Duration,
+}
+
+/// Log-bucketed histogram of durations
+pub struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl LatencyHistogram {
+    /// Creates an empty histogram
+    pub fn new() -> Self {
+        Self {
+            buckets: (0..NUM_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Records one duration
+    pub fn record(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[bucket_index(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Number of recorded durations
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Sum of all recorded durations
+    pub fn sum(&self) -> Duration {
+        Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Returns the duration below which a fraction `q` (0.0 to 1.0) of the
+    /// recorded durations fall, or zero if nothing was recorded
+    ///
+    /// The result is the upper end of the bucket holding that rank, capped
+    /// at the exact maximum.
+    pub fn percentile(&self, q: f64) -> Duration {
+        let total = self.count();
+        if total == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+        let max = self.max_nanos.load(Ordering::Relaxed);
+
+        let mut seen = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return Duration::from_nanos(bucket_upper_bound(index).min(max));
+            }
+        }
+        Duration::from_nanos(max)
+    }
+
+    /// Returns the standard set of percentiles
+    pub fn percentiles(&self) -> LatencyPercentiles {
+        LatencyPercentiles {
+            count: self.count(),
+            p50: self.percentile(0.50),
+            p90: self.percentile(0.90),
+            p99: self.percentile(0.99),
+            p999: self.percentile(0.999),
+            max: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Forgets everything recorded so far
+    pub fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.sum_nanos.store(0, Ordering::Relaxed);
+        self.max_nanos.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the bucket a value in nanoseconds falls into
+fn bucket_index(nanos: u64) -> usize {
+    if nanos < 2 * SUB_BUCKETS {
+        return nanos as usize;
+    }
+    let msb = 63 - nanos.leading_zeros();
+    let shift = msb - SUB_BUCKET_BITS;
+    let sub_bucket = (nanos >> shift) & (SUB_BUCKETS - 1);
+    ((msb - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS + sub_bucket) as usize
+}
+
+/// Returns the largest value that falls into a bucket
+fn bucket_upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < 2 * SUB_BUCKETS {
+        return index;
+    }
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    let sub_bucket = index % SUB_BUCKETS;
+    let lower = (SUB_BUCKETS + sub_bucket) << shift;
+    lower + ((1u64 << shift) - 1)
+}
+
+/// Latency histograms for every tracked operation
+#[derive(Default)]
+pub(crate) struct LatencyStats {
+    get: LatencyHistogram,
+    put: LatencyHistogram,
+    flush: LatencyHistogram,
+    compaction: LatencyHistogram,
+}
+
+impl LatencyStats {
+    /// Returns the histogram of one operation
+    pub(crate) fn histogram(&self, op: LatencyOp) -> &LatencyHistogram {
+        match op {
+            LatencyOp::Get => &self.get,
+            LatencyOp::Put => &self.put,
+            LatencyOp::Flush => &self.flush,
+            LatencyOp::Compaction => &self.compaction,
+        }
+    }
+
+    pub(crate) fn record(&self, op: LatencyOp, duration: Duration) {
+        self.histogram(op).record(duration);
+    }
+
+    pub(crate) fn reset(&self) {
+        for op in LatencyOp::ALL {
+            self.histogram(op).reset();
+        }
+    }
+
+    /// Appends the histograms as a Prometheus summary
+    pub(crate) fn write_prometheus(&self, out: &mut String) {
+        let name = "lsm_operation_latency_seconds";
+        let _ = writeln!(out, "# HELP {} Latency of tree operations", name);
+        let _ = writeln!(out, "# TYPE {} summary", name);
+        for op in LatencyOp::ALL {
+            let histogram = self.histogram(op);
+            for (quantile, value) in [
+                ("0.5", 0.50),
+                ("0.9", 0.90),
+                ("0.99", 0.99),
+                ("0.999", 0.999),
+            ] {
+                let _ = writeln!(
+                    out,
+                    "{}{{op=\"{}\",quantile=\"{}\"}} {}",
+                    name,
+                    op.name(),
+                    quantile,
+                    histogram.percentile(value).as_secs_f64()
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_sum{{op=\"{}\"}} {}",
+                name,
+                op.name(),
+                histogram.sum().as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "{}_count{{op=\"{}\"}} {}",
+                name,
+                op.name(),
+                histogram.count()
+            );
+        }
+    }
+}
+
+/// Weight of the newest sample in the read amplification average
+const READ_AMP_SMOOTHING: f64 = 0.1;
+
+/// Exponential moving average of SSTables probed per get
+///
+/// Each get moves the average a tenth of the way towards its own count,
+/// so it follows the last few dozen reads and a single unlucky lookup
+/// doesn't swing it.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ReadAmpTracker {
+    average: f64,
+    /// Whether the average was above the threshold after the last sample
+    high: bool,
+}
+
+impl ReadAmpTracker {
+    /// Records one get; returns true if the average just rose above
+    /// `threshold` (it wasn't above it before this sample)
+    pub(crate) fn record(&mut self, probed: usize, threshold: Option<f64>) -> bool {
+        self.average += (probed as f64 - self.average) * READ_AMP_SMOOTHING;
+        let was_high = self.high;
+        self.high = threshold.is_some_and(|t| self.average > t);
+        self.high && !was_high
+    }
+
+    pub(crate) fn average(&self) -> f64 {
+        self.average
+    }
+
+    /// Whether the average is above the threshold
+    pub(crate) fn is_high(&self) -> bool {
+        self.high
+    }
+}
+
+/// Bytes written by users and by the tree, for judging compaction
+///
+/// Counters cover the time since the tree was opened; they are not
+/// persisted. SSTable bytes are table files only (`.db`), not Bloom
+/// filters or the WAL.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AmplificationStats {
+    /// Key and value bytes passed to puts and deletes
+    pub user_bytes_written: u64,
+
+    /// Bytes of SSTables written by flushes and compactions
+    pub sstable_bytes_written: u64,
+
+    /// Current size of the live SSTables
+    pub sstable_bytes_on_disk: u64,
+
+    /// Estimated part of `sstable_bytes_on_disk` holding the newest version
+    /// of each key
+    ///
+    /// Scaled by the share of distinct keys in the resident Bloom filters
+    /// (see `BloomFilterSummary::distinct_estimate`), so it is only as good
+    /// as that estimate; tombstones count as live.
+    pub live_bytes_estimate: u64,
+}
+
+impl AmplificationStats {
+    /// SSTable bytes written per user byte written (0.0 before any write)
+    pub fn write_amplification(&self) -> f64 {
+        if self.user_bytes_written == 0 {
+            0.0
+        } else {
+            self.sstable_bytes_written as f64 / self.user_bytes_written as f64
+        }
+    }
+
+    /// SSTable bytes on disk per byte of live data (0.0 without tables)
+    ///
+    /// 1.0 means no space is spent on overwritten versions.
+    pub fn space_amplification(&self) -> f64 {
+        if self.live_bytes_estimate == 0 {
+            0.0
+        } else {
+            self.sstable_bytes_on_disk as f64 / self.live_bytes_estimate as f64
+        }
+    }
+}
+
+/// Window `LSMTree::io_rates` averages over
+pub const IO_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Samples a tracker of `LSMTree::io_rates` keeps
+pub const IO_RATE_SAMPLES: usize = 64;
+
+/// Bytes per second over a sliding window, from timestamped byte counts
+///
+/// Samples of `(time, bytes)` go into a ring buffer of at most `capacity`
+/// entries, dropping the oldest when it is full. A sample less than
+/// `window / capacity` after the previous one is added to it instead, so
+/// the buffer always spans the whole window however many small writes
+/// arrive. Times are milliseconds, as `Clock::now_millis` returns them.
+#[derive(Debug, Clone)]
+pub struct RateTracker {
+    window_millis: u64,
+    capacity: usize,
+    samples: VecDeque<(u64, u64)>,
+    total: u64,
+}
+
+impl RateTracker {
+    /// Creates a tracker averaging over `window` (at least a millisecond)
+    /// with room for `capacity` samples (at least one)
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            window_millis: (window.as_millis() as u64).max(1),
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+            total: 0,
+        }
+    }
+
+    /// Records `bytes` moved at `now_millis`
+    pub fn record(&mut self, now_millis: u64, bytes: u64) {
+        self.total += bytes;
+        let granularity = (self.window_millis / self.capacity as u64).max(1);
+        if let Some((at, sample)) = self.samples.back_mut()
+            && (*at..*at + granularity).contains(&now_millis)
+        {
+            *sample += bytes;
+            return;
+        }
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now_millis, bytes));
+    }
+
+    /// Bytes per second over the window ending at `now_millis`
+    ///
+    /// The window counts in full even right after the tracker was created,
+    /// so a rate ramps up over the first window rather than spiking.
+    pub fn rate(&self, now_millis: u64) -> f64 {
+        let start = now_millis.saturating_sub(self.window_millis);
+        let bytes: u64 = self
+            .samples
+            .iter()
+            .filter(|(at, _)| *at > start && *at <= now_millis)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        bytes as f64 * 1000.0 / self.window_millis as f64
+    }
+
+    /// Bytes recorded since the tracker was created
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+/// Bytes per second moved to and from disk over the last
+/// `IO_RATE_WINDOW` (see `LSMTree::io_rates`)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IoRates {
+    /// Entries appended to the WAL
+    pub wal_write: f64,
+
+    /// SSTables written by flushes, compactions and ingestion
+    pub sstable_write: f64,
+
+    /// SSTables read by lookups (up to the record each one stopped at)
+    /// and by compactions (their whole input)
+    pub sstable_read: f64,
+}
+
+/// The trackers behind `IoRates`, recorded through `&self`
+#[derive(Debug)]
+pub(crate) struct IoRateTrackers {
+    pub(crate) wal_write: Mutex<RateTracker>,
+    pub(crate) sstable_write: Mutex<RateTracker>,
+    pub(crate) sstable_read: Mutex<RateTracker>,
+}
+
+impl IoRateTrackers {
+    pub(crate) fn record(tracker: &Mutex<RateTracker>, now_millis: u64, bytes: u64) {
+        tracker
+            .lock()
+            .expect("rate tracker lock poisoned")
+            .record(now_millis, bytes);
+    }
+
+    pub(crate) fn rates(&self, now_millis: u64) -> IoRates {
+        let rate = |tracker: &Mutex<RateTracker>| {
+            tracker
+                .lock()
+                .expect("rate tracker lock poisoned")
+                .rate(now_millis)
+        };
+        IoRates {
+            wal_write: rate(&self.wal_write),
+            sstable_write: rate(&self.sstable_write),
+            sstable_read: rate(&self.sstable_read),
+        }
+    }
+}
+
+impl Default for IoRateTrackers {
+    fn default() -> Self {
+        let tracker = || Mutex::new(RateTracker::new(IO_RATE_WINDOW, IO_RATE_SAMPLES));
+        Self {
+            wal_write: tracker(),
+            sstable_write: tracker(),
+            sstable_read: tracker(),
+        }
+    }
+}
+
+/// Point lookups one SSTable took part in (see `SSTableInfo::read_stats`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableReadStats {
+    /// Lookups that consulted the table's Bloom filter
+    pub bloom_checks: u64,
+
+    /// Of those, the ones the filter ruled out
+    pub bloom_negatives: u64,
+
+    /// Lookups that searched the table itself
+    pub probes: u64,
+
+    /// Of those, the ones that found the key (a value or a tombstone)
+    pub hits: u64,
+
+    /// Bytes the probes read from disk (pinned tables read none)
+    pub bytes_read: u64,
+}
+
+impl TableReadStats {
+    /// Share of the probes that found nothing: the filter's false
+    /// positives, and lookups of tables without a filter in memory
+    pub fn wasted_probe_ratio(&self) -> f64 {
+        if self.probes == 0 {
+            0.0
+        } else {
+            (self.probes - self.hits) as f64 / self.probes as f64
+        }
+    }
+}
+
+/// The counters behind `TableReadStats`, recorded through `&self`
+#[derive(Debug, Default)]
+pub(crate) struct TableReads {
+    bloom_checks: AtomicU64,
+    bloom_negatives: AtomicU64,
+    probes: AtomicU64,
+    hits: AtomicU64,
+    bytes_read: AtomicU64,
+}
+
+impl TableReads {
+    pub(crate) fn record_bloom_check(&self, negative: bool) {
+        self.bloom_checks.fetch_add(1, Ordering::Relaxed);
+        if negative {
+            self.bloom_negatives.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_probe(&self, hit: bool, bytes: u64) {
+        self.probes.fetch_add(1, Ordering::Relaxed);
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> TableReadStats {
+        TableReadStats {
+            bloom_checks: self.bloom_checks.load(Ordering::Relaxed),
+            bloom_negatives: self.bloom_negatives.load(Ordering::Relaxed),
+            probes: self.probes.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+        }
+    }
+
+    /// A copy of the counters as they are now
+    pub(crate) fn snapshot(&self) -> Self {
+        let stats = self.stats();
+        Self {
+            bloom_checks: AtomicU64::new(stats.bloom_checks),
+            bloom_negatives: AtomicU64::new(stats.bloom_negatives),
+            probes: AtomicU64::new(stats.probes),
+            hits: AtomicU64::new(stats.hits),
+            bytes_read: AtomicU64::new(stats.bytes_read),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for counter in [
+            &self.bloom_checks,
+            &self.bloom_negatives,
+            &self.probes,
+            &self.hits,
+            &self.bytes_read,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Counters don't take part in comparisons: two `SSTableInfo`s of the same
+/// table are equal however often each copy was read
+impl PartialEq for TableReads {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// Appends one Prometheus gauge or counter
+pub(crate) fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl std::fmt::Display,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_are_contiguous_and_bounded() {
+        assert_eq!(bucket_index(0), 0);
+        assert_eq!(bucket_index(15), 15);
+        assert_eq!(bucket_index(16), 16);
+        assert_eq!(bucket_index(u64::MAX), NUM_BUCKETS - 1);
+        assert_eq!(bucket_upper_bound(NUM_BUCKETS - 1), u64::MAX);
+
+        for nanos in (0..100_000).chain([u64::MAX / 3, u64::MAX - 1]) {
+            let index = bucket_index(nanos);
+            let upper = bucket_upper_bound(index);
+            assert!(upper >= nanos, "{} above its bucket bound {}", nanos, upper);
+            // At most one eighth above the true value
+            assert!(upper - nanos <= nanos / 8, "{} -> {}", nanos, upper);
+            if index > 0 {
+                assert!(bucket_upper_bound(index - 1) < nanos);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_amp_tracker_reports_crossing_once() {
+        let mut tracker = ReadAmpTracker::default();
+        // Without a threshold the average is tracked but never high
+        assert!(!tracker.record(50, None));
+        assert!(!tracker.is_high());
+
+        let mut tracker = ReadAmpTracker::default();
+        let crossings: Vec<bool> = (0..30).map(|_| tracker.record(10, Some(5.0))).collect();
+        assert_eq!(crossings.iter().filter(|&&c| c).count(), 1);
+        assert!(tracker.is_high());
+        assert!(tracker.average() > 9.0 && tracker.average() < 10.0);
+
+        // Cheap reads pull it back down, and the next rise reports again
+        while tracker.is_high() {
+            assert!(!tracker.record(1, Some(5.0)));
+        }
+        assert!(tracker.average() <= 5.0);
+        assert!((0..30).any(|_| tracker.record(10, Some(5.0))));
+    }
+
+    #[test]
+    fn test_rate_tracker_windowed_rates() {
+        let mut tracker = RateTracker::new(Duration::from_secs(1), 10);
+        assert_eq!(tracker.rate(5_000), 0.0);
+
+        // 1000 bytes every 100 ms: 10 KB/s once a full window has passed
+        for i in 0..20 {
+            tracker.record(1_000 + i * 100, 1_000);
+        }
+        assert_eq!(tracker.rate(2_900), 10_000.0);
+        assert_eq!(tracker.total(), 20_000);
+
+        // The window slides: half of it is empty after 500 quiet ms, all
+        // of it after a second
+        assert_eq!(tracker.rate(3_400), 5_000.0);
+        assert_eq!(tracker.rate(3_900), 0.0);
+
+        // A burst within one sample's span merges into one sample, so the
+        // buffer still covers the window
+        for _ in 0..500 {
+            tracker.record(4_000, 100);
+        }
+        tracker.record(4_050, 100);
+        tracker.record(4_500, 1_000);
+        assert_eq!(tracker.rate(4_500), 51_100.0);
+        assert_eq!(tracker.rate(5_050), 1_000.0);
+    }
+
+    #[test]
+    fn test_rate_tracker_drops_oldest_samples_when_full() {
+        let mut tracker = RateTracker::new(Duration::from_millis(100), 4);
+        // Samples 25 ms apart or more don't merge; the fifth pushes out
+        // the first, even though it is still inside the window
+        for (i, at) in [10u64, 40, 70, 100, 130].iter().enumerate() {
+            tracker.record(*at, 1 << i);
+        }
+        assert_eq!(tracker.samples.len(), 4);
+        assert_eq!(tracker.rate(105), 140.0);
+        // One 5 ms later merges into the last
+        tracker.record(135, 32);
+        assert_eq!(tracker.samples.back(), Some(&(130, 48)));
+        assert_eq!(tracker.total(), 63);
+    }
+
+    #[test]
+    fn test_percentiles_of_synthetic_durations() {
+        let histogram = LatencyHistogram::new();
+        assert_eq!(histogram.percentiles(), LatencyPercentiles::default());
+
+        // 1..=1000 microseconds, one each
+        for micros in 1..=1000 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let within = |actual: Duration, expected_micros: u64| {
+            let expected = Duration::from_micros(expected_micros);
+            assert!(
+                actual >= expected && actual <= expected + expected / 8,
+                "{:?} is not within 12.5% above {:?}",
+                actual,
+                expected
+            );
+        };
+
+        let p = histogram.percentiles();
+        assert_eq!(p.count, 1000);
+        within(p.p50, 500);
+        within(p.p90, 900);
+        within(p.p99, 990);
+        within(p.p999, 999);
+        assert_eq!(p.max, Duration::from_micros(1000));
+        assert_eq!(histogram.sum(), Duration::from_micros(500_500));
+
+        // One outlier only shows up in the tail
+        histogram.record(Duration::from_secs(2));
+        let p = histogram.percentiles();
+        within(p.p99, 990);
+        assert_eq!(p.max, Duration::from_secs(2));
+        assert_eq!(histogram.percentile(1.0), Duration::from_secs(2));
+
+        histogram.reset();
+        assert_eq!(histogram.percentiles(), LatencyPercentiles::default());
+    }
+}
@@ -0,0 +1,158 @@
+/// What opening a tree did to get it back in shape (see
+/// `LSMTree::open_with_report`)
+///
+/// After a crash the open does real work: it replays the WAL (and cuts off
+/// a torn tail), checks every SSTable, loads or rebuilds the Bloom filters
+/// and removes temporary files a write left behind, and filter files whose
+/// table is gone. The report counts each of those, so an operator can tell
+/// a clean restart from a recovery.
+///
+/// With the `tracing` feature the same counts are logged as an `info`
+/// event when the open finishes, and a `warn` event when the WAL had to be
+/// cut.
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Everything recovery did while a tree was opened
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpenReport {
+    /// WAL entries written back into the memtable
+    pub wal_entries_replayed: u64,
+
+    /// WAL entries skipped because an SSTable already holds them (a crash
+    /// between a flush and clearing the log)
+    pub wal_entries_skipped: u64,
+
+    /// Bytes of well-formed WAL entries read, skipped ones included
+    pub wal_bytes_replayed: u64,
+
+    /// Bytes after the last good WAL entry that were dropped (a torn or
+    /// damaged tail)
+    pub wal_bytes_dropped: u64,
+
+    /// Where and why WAL replay stopped early, if it did
+    pub wal_corruption: Option<String>,
+
+    /// SSTables that joined the tree
+    pub sstables_loaded: usize,
+
+    /// SSTables found damaged and set aside
+    pub sstables_quarantined: usize,
+
+    /// Bloom filters read from their `.bloom` files
+    pub filters_loaded: usize,
+
+    /// Bloom filters rebuilt from their table because the file was missing
+    /// or damaged
+    pub filters_rebuilt: usize,
+
+    /// Tables left without a filter (a read-only tree can't rebuild one,
+    /// and a failed rebuild isn't guessed), so every read probes them
+    pub filters_missing: usize,
+
+    /// Temporary files from interrupted writes that were removed
+    pub tmp_files_removed: Vec<PathBuf>,
+
+    /// Bloom filter files whose SSTable no longer exists, which were
+    /// removed
+    pub orphan_sidecars_removed: Vec<PathBuf>,
+
+    /// Small SSTables merged away (see
+    /// `LSMConfig::small_table_merge_threshold`)
+    pub small_tables_merged: usize,
+
+    /// Tables those were merged into
+    pub merged_tables_written: usize,
+
+    /// How long the open took
+    pub duration: Duration,
+}
+
+impl OpenReport {
+    /// Whether the open found anything to recover from
+    pub fn recovered(&self) -> bool {
+        self.wal_bytes_dropped > 0
+            || self.wal_entries_skipped > 0
+            || self.sstables_quarantined > 0
+            || self.filters_rebuilt > 0
+            || self.filters_missing > 0
+            || !self.tmp_files_removed.is_empty()
+            || !self.orphan_sidecars_removed.is_empty()
+    }
+
+    /// Logs the report (does nothing without the `tracing` feature)
+    pub(crate) fn log(&self) {
+        #[cfg(feature = "tracing")]
+        {
+            if let Some(reason) = &self.wal_corruption {
+                tracing::warn!(
+                    dropped_bytes = self.wal_bytes_dropped,
+                    reason = reason.as_str(),
+                    "WAL replay stopped at a damaged entry"
+                );
+            }
+            tracing::info!(
+                wal_entries_replayed = self.wal_entries_replayed,
+                wal_entries_skipped = self.wal_entries_skipped,
+                wal_bytes_replayed = self.wal_bytes_replayed,
+                wal_bytes_dropped = self.wal_bytes_dropped,
+                sstables_loaded = self.sstables_loaded,
+                sstables_quarantined = self.sstables_quarantined,
+                filters_loaded = self.filters_loaded,
+                filters_rebuilt = self.filters_rebuilt,
+                filters_missing = self.filters_missing,
+                tmp_files_removed = self.tmp_files_removed.len(),
+                orphan_sidecars_removed = self.orphan_sidecars_removed.len(),
+                small_tables_merged = self.small_tables_merged,
+                duration_ms = self.duration.as_millis() as u64,
+                "opened LSM tree"
+            );
+        }
+    }
+}
+
+impl fmt::Display for OpenReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Opened in {:.1} ms",
+            self.duration.as_secs_f64() * 1000.0
+        )?;
+        writeln!(
+            f,
+            "  wal: {} entries replayed, {} already flushed, {} bytes read, {} bytes dropped",
+            self.wal_entries_replayed,
+            self.wal_entries_skipped,
+            self.wal_bytes_replayed,
+            self.wal_bytes_dropped
+        )?;
+        if let Some(reason) = &self.wal_corruption {
+            writeln!(f, "  wal stopped at {}", reason)?;
+        }
+        writeln!(
+            f,
+            "  sstables: {} loaded, {} quarantined",
+            self.sstables_loaded, self.sstables_quarantined
+        )?;
+        writeln!(
+            f,
+            "  bloom filters: {} loaded, {} rebuilt, {} missing",
+            self.filters_loaded, self.filters_rebuilt, self.filters_missing
+        )?;
+        if self.small_tables_merged > 0 {
+            writeln!(
+                f,
+                "  merged {} small sstables into {}",
+                self.small_tables_merged, self.merged_tables_written
+            )?;
+        }
+        for path in &self.tmp_files_removed {
+            writeln!(f, "  removed {}", path.display())?;
+        }
+        for path in &self.orphan_sidecars_removed {
+            writeln!(f, "  removed orphan {}", path.display())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+//! Per-operation options for reads and writes
+//!
+//! The tree-wide defaults live in `LSMConfig`; these let a single call
+//! deviate from them, for example to fsync one important write in a tree
+//! that normally doesn't, or to scan cold data without pushing the hot
+//! Bloom filters out of memory. `Default` always matches what the plain
+//! `put`, `delete`, `write` and `get` do.
+
+/// Options for `put_opt`, `delete_opt` and `write_opt`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Whether to fsync the WAL before the write returns
+    ///
+    /// `None` follows `LSMConfig::sync_writes`; `Some(true)` forces an
+    /// fsync for this write even when the tree doesn't sync by default,
+    /// and `Some(false)` skips it even when it does.
+    pub sync: Option<bool>,
+
+    /// Skip the WAL entirely
+    ///
+    /// The write only lives in the memtable until the next flush, so a
+    /// crash before then loses it. Useful for data that can be rebuilt,
+    /// such as a bulk load that is simply restarted after a failure.
+    pub disable_wal: bool,
+}
+
+/// Options for `get_opt`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOptions {
+    /// Load Bloom filters evicted under the memory budget back into the
+    /// cache (the default)
+    ///
+    /// With `false`, a filter that isn't resident is read from disk for
+    /// this lookup only, and no filter's place in the least-recently-used
+    /// order changes, so one-off reads of cold data don't evict the
+    /// filters of hot tables.
+    pub fill_cache: bool,
+
+    /// Check the records the lookup reads against their checksums before
+    /// trusting them
+    ///
+    /// Only the records of the key are checked, in each SSTable the lookup
+    /// gets to, not the rest of the table. A damaged record fails the
+    /// lookup with `InvalidData` instead of giving a possibly wrong
+    /// answer, or its table is skipped, as `LSMConfig::on_corruption`
+    /// says; it goes into `LSMTree::corruption_report` either way. Tables
+    /// older than `sstable::CHECKSUM_FORMAT_VERSION` have no checksums, so
+    /// only records too damaged to read are caught in them.
+    pub verify_checksums: bool,
+
+    /// Most SSTables the lookup may consult, Bloom filter checks included
+    ///
+    /// A lookup that would have to go past the budget without finding the
+    /// key fails with a `ReadBudgetExceeded` error instead, so a
+    /// latency-sensitive caller can fall back to another source. `None`
+    /// (the default) has no limit.
+    pub max_tables_probed: Option<usize>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            fill_cache: true,
+            verify_checksums: false,
+            max_tables_probed: None,
+        }
+    }
+}
@@ -0,0 +1,139 @@
+/// Small SSTables held entirely in memory
+///
+/// Trees with a small memtable threshold pile up tiny SSTables, and a
+/// lookup that gets past a table's Bloom filter pays for opening the file
+/// and parsing its header, however few records it holds. With
+/// `LSMConfig::pin_small_sstables_bytes` set, every table no larger than
+/// `LSMConfig::small_sstable_max_bytes` is read once into a `BTreeMap` and
+/// lookups are answered from there, newest tables first until the budget
+/// (counted in file bytes) is used up.
+///
+/// The set is chosen again whenever the table list changes (flush, purge,
+/// migration, refresh); tables that left the tree are dropped with it, and
+/// tables that stay keep their already loaded records.
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::memtable::Version;
+use crate::sstable::SSTableReader;
+
+/// Statistics of the pinned small tables (see `LSMTree::pinned_sstable_stats`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PinnedTableStats {
+    /// Tables currently held in memory
+    pub tables: usize,
+
+    /// File bytes of those tables, counted against the budget
+    pub bytes: u64,
+
+    /// Lookups answered from a pinned table since the tree was opened
+    pub hits: u64,
+}
+
+/// Records of one table: every version of a key, newest first
+#[derive(Debug)]
+pub(crate) struct PinnedTable {
+    records: BTreeMap<Vec<u8>, Vec<Version>>,
+    bytes: u64,
+}
+
+impl PinnedTable {
+    /// Reads a whole table, or returns `None` if it can't be read
+    fn load(path: &Path, bytes: u64) -> Option<Self> {
+        let mut reader = SSTableReader::open(path).ok()?;
+        let mut table = Self {
+            records: BTreeMap::new(),
+            bytes,
+        };
+        while let Some((key, seq, value)) = reader.try_next().ok()? {
+            table
+                .records
+                .entry(key)
+                .or_default()
+                .push(Version { seq, value });
+        }
+        Some(table)
+    }
+
+    /// Same answer as `SSTableReader::get`
+    pub(crate) fn get(&self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        self.records
+            .get(key)
+            .map(|versions| versions[0].value.clone())
+    }
+
+    /// Same answer as `SSTableReader::get_at`
+    pub(crate) fn get_at(&self, key: &[u8], seq: u64) -> Option<Option<Vec<u8>>> {
+        self.records
+            .get(key)?
+            .iter()
+            .find(|version| version.seq <= seq)
+            .map(|version| version.value.clone())
+    }
+}
+
+/// The pinned tables of a tree, by path
+#[derive(Debug)]
+pub(crate) struct PinnedTables {
+    budget: usize,
+    max_table_bytes: u64,
+    tables: HashMap<PathBuf, PinnedTable>,
+    hits: AtomicU64,
+}
+
+impl PinnedTables {
+    /// An empty set; with a budget of 0 nothing is ever pinned
+    pub(crate) fn new(budget: usize, max_table_bytes: u64) -> Self {
+        Self {
+            budget,
+            max_table_bytes,
+            tables: HashMap::new(),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Chooses the pinned tables again for the live tables, newest first
+    ///
+    /// Tables that can't be read are simply not pinned; lookups read them
+    /// from disk and report the problem as before.
+    pub(crate) fn refill(&mut self, paths_newest_first: &[PathBuf]) {
+        if self.budget == 0 {
+            return;
+        }
+        let mut old = std::mem::take(&mut self.tables);
+        let mut used = 0u64;
+        for path in paths_newest_first {
+            let Ok(bytes) = std::fs::metadata(path).map(|m| m.len()) else {
+                continue;
+            };
+            if bytes > self.max_table_bytes || used + bytes > self.budget as u64 {
+                continue;
+            }
+            let table = match old.remove(path) {
+                Some(table) => table,
+                None => match PinnedTable::load(path, bytes) {
+                    Some(table) => table,
+                    None => continue,
+                },
+            };
+            used += bytes;
+            self.tables.insert(path.clone(), table);
+        }
+    }
+
+    /// Returns the pinned copy of a table, counting the hit
+    pub(crate) fn get(&self, path: &Path) -> Option<&PinnedTable> {
+        let table = self.tables.get(path)?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(table)
+    }
+
+    pub(crate) fn stats(&self) -> PinnedTableStats {
+        PinnedTableStats {
+            tables: self.tables.len(),
+            bytes: self.tables.values().map(|t| t.bytes).sum(),
+            hits: self.hits.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+/// How the keyspace splits up by key prefix (see `LSMTree::prefix_stats`)
+///
+/// Keys are grouped by their first `depth` components, components being
+/// separated by a delimiter byte: with `:` and depth 1, `user:42:name` and
+/// `user:7` both count under `user:`, and `order:9` under `order:`. A key
+/// with fewer delimiters than `depth` counts under the part up to its last
+/// delimiter, so `user:42` lands in `user:` at depth 2 as well, and a key
+/// without any delimiter in the empty prefix.
+///
+/// The scan iterates keys only; values are never read. Bytes per prefix are
+/// therefore an estimate: the prefix's own key bytes plus, for every key,
+/// an equal share of the rest of the tree's live data.
+///
+/// On huge trees a sample rate below 1.0 counts only every n-th key (in key
+/// order, so every part of the keyspace is sampled alike) and scales the
+/// counts up. The scan still walks every key; sampling saves the grouping.
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Keys and bytes under one prefix
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefixStats {
+    /// The prefix, including its trailing delimiter (empty for keys
+    /// without a delimiter)
+    pub prefix: Vec<u8>,
+
+    /// Live keys under the prefix (scaled up when sampling)
+    pub keys: u64,
+
+    /// Total size of those keys (scaled up when sampling)
+    pub key_bytes: u64,
+
+    /// Estimated size of the keys and their values
+    pub approx_bytes: u64,
+}
+
+/// Keys and bytes per prefix, in prefix order
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefixReport {
+    pub delimiter: u8,
+    pub depth: usize,
+
+    /// Share of the keys that were counted (1.0 counts every key)
+    pub sample_rate: f64,
+
+    /// Keys the scan walked
+    pub scanned_keys: u64,
+
+    /// Keys counted into the prefixes
+    pub sampled_keys: u64,
+
+    /// One entry per prefix, sorted by prefix
+    pub prefixes: Vec<PrefixStats>,
+}
+
+impl PrefixReport {
+    /// Estimated live keys in the tree
+    pub fn total_keys(&self) -> u64 {
+        self.prefixes.iter().map(|p| p.keys).sum()
+    }
+
+    /// The prefixes ordered by key count, largest first
+    pub fn largest(&self) -> Vec<&PrefixStats> {
+        let mut prefixes: Vec<_> = self.prefixes.iter().collect();
+        prefixes.sort_by(|a, b| b.keys.cmp(&a.keys).then(a.prefix.cmp(&b.prefix)));
+        prefixes
+    }
+}
+
+impl fmt::Display for PrefixReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} prefixes (delimiter {:?}, depth {}), {} keys",
+            self.prefixes.len(),
+            self.delimiter as char,
+            self.depth,
+            self.total_keys()
+        )?;
+        if self.sample_rate < 1.0 {
+            writeln!(
+                f,
+                "sampled {} of {} keys (rate {})",
+                self.sampled_keys, self.scanned_keys, self.sample_rate
+            )?;
+        }
+        let total = self.total_keys().max(1) as f64;
+        for p in &self.prefixes {
+            writeln!(
+                f,
+                "  {:<24} {:>10} keys {:>6.1}%  ~{} bytes",
+                display_prefix(&p.prefix),
+                p.keys,
+                p.keys as f64 * 100.0 / total,
+                p.approx_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows a prefix as text, escaping bytes that aren't printable
+pub fn display_prefix(prefix: &[u8]) -> String {
+    if prefix.is_empty() {
+        return "(no prefix)".to_string();
+    }
+    prefix.escape_ascii().to_string()
+}
+
+/// Returns the first `depth` components of `key`, delimiters included
+pub(crate) fn prefix_of(key: &[u8], delimiter: u8, depth: usize) -> &[u8] {
+    let mut end = 0;
+    for (i, _) in key
+        .iter()
+        .enumerate()
+        .filter(|&(_, &b)| b == delimiter)
+        .take(depth)
+    {
+        end = i + 1;
+    }
+    &key[..end]
+}
+
+/// Groups the keys of a scan by prefix
+///
+/// `stored_bytes` is the tree's live data estimate, spread over the keys
+/// to estimate bytes per prefix.
+pub(crate) fn collect(
+    keys: impl Iterator<Item = Vec<u8>>,
+    delimiter: u8,
+    depth: usize,
+    sample_rate: f64,
+    stored_bytes: u64,
+) -> PrefixReport {
+    let stride = (1.0 / sample_rate).round().max(1.0) as u64;
+    let mut groups: BTreeMap<Vec<u8>, (u64, u64)> = BTreeMap::new();
+    let mut scanned = 0;
+    let mut sampled = 0;
+    for key in keys {
+        scanned += 1;
+        if (scanned - 1) % stride != 0 {
+            continue;
+        }
+        sampled += 1;
+        let prefix = prefix_of(&key, delimiter, depth);
+        // Keys arrive in order, so most keys join the last group
+        match groups.get_mut(prefix) {
+            Some((count, bytes)) => {
+                *count += 1;
+                *bytes += key.len() as u64;
+            }
+            None => {
+                groups.insert(prefix.to_vec(), (1, key.len() as u64));
+            }
+        }
+    }
+
+    let key_bytes: u64 = groups.values().map(|&(_, bytes)| bytes * stride).sum();
+    let other_per_key = if sampled == 0 {
+        0
+    } else {
+        stored_bytes.saturating_sub(key_bytes) / (sampled * stride)
+    };
+    PrefixReport {
+        delimiter,
+        depth,
+        sample_rate,
+        scanned_keys: scanned,
+        sampled_keys: sampled,
+        prefixes: groups
+            .into_iter()
+            .map(|(prefix, (count, bytes))| PrefixStats {
+                prefix,
+                keys: count * stride,
+                key_bytes: bytes * stride,
+                approx_bytes: (bytes + count * other_per_key) * stride,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_of_stops_at_depth() {
+        assert_eq!(prefix_of(b"user:42:name", b':', 1), b"user:");
+        assert_eq!(prefix_of(b"user:42:name", b':', 2), b"user:42:");
+        assert_eq!(prefix_of(b"user:42", b':', 2), b"user:");
+        assert_eq!(prefix_of(b"config", b':', 1), b"");
+        assert_eq!(prefix_of(b"user:42", b':', 0), b"");
+
+        let keys = ["a:1", "a:2", "a:3", "b:1"].map(|k| k.as_bytes().to_vec());
+        let report = collect(keys.into_iter(), b':', 1, 0.5, 0);
+        assert_eq!(report.scanned_keys, 4);
+        assert_eq!(report.sampled_keys, 2);
+        // a:1 and a:3 were counted, each standing for two keys
+        assert_eq!(report.prefixes.len(), 1);
+        assert_eq!(report.prefixes[0].keys, 4);
+    }
+}
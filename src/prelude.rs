@@ -0,0 +1,22 @@
+/// The types most programs using the tree need
+///
+/// `use lsm_tree::prelude::*;` brings in the tree, its configuration and
+/// per-call options, batches and transactions, the ways of reading (range
+/// iterators, cursors, subspaces, handles to SSTables) and the error type.
+/// Everything here is also exported from the crate root; the more
+/// specialised reports, statistics and extension points are only there.
+pub use crate::batch::WriteBatch;
+pub use crate::clock::{Clock, SystemClock};
+pub use crate::compression::Compression;
+pub use crate::config::{FilterPolicy, LSMConfig};
+pub use crate::cursor::Cursor;
+pub use crate::error::{LsmError, Operation};
+pub use crate::footer::TableSummary;
+pub use crate::handle::{SSTableHandle, SSTableId};
+pub use crate::iter::{KeysIter, RangeIter};
+pub use crate::options::{ReadOptions, WriteOptions};
+pub use crate::shared::SharedLSMTree;
+pub use crate::state::TreeState;
+pub use crate::subspace::Subspace;
+pub use crate::transaction::{Conflict, Transaction};
+pub use crate::tree::LSMTree;
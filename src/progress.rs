@@ -0,0 +1,70 @@
+//! Progress reporting while a tree is opened
+//!
+//! Opening a directory with thousands of SSTables and a large WAL can take
+//! a while: every table is checked, every Bloom filter is loaded (or
+//! rebuilt), and the whole log is replayed. `LSMTree::open_with_progress`
+//! reports how far each of those phases got, so a CLI can draw a progress
+//! bar instead of appearing to hang.
+//!
+//! Phases run in order (`ScanningDirectory`, `LoadingFilters`,
+//! `ReplayingWal`). Each starts with an event where `done` is 0 and ends
+//! with one where `done == total`; in between, `done` and `bytes_done`
+//! never decrease.
+
+/// A step of opening the tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenPhase {
+    /// Checking the SSTables listed in the manifest (or found in the
+    /// directory) for damage; items are tables, bytes their file sizes
+    ScanningDirectory,
+
+    /// Loading or rebuilding the Bloom filter of every intact table;
+    /// items are tables, bytes the sizes of their `.bloom` files
+    LoadingFilters,
+
+    /// Replaying the write-ahead log into the memtable; items are log
+    /// entries, bytes the part of the log replayed so far
+    ReplayingWal,
+}
+
+impl OpenPhase {
+    /// Short human-readable description
+    pub fn label(self) -> &'static str {
+        match self {
+            OpenPhase::ScanningDirectory => "Scanning SSTables",
+            OpenPhase::LoadingFilters => "Loading Bloom filters",
+            OpenPhase::ReplayingWal => "Replaying WAL",
+        }
+    }
+}
+
+/// How far the current phase of an open got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenProgress {
+    pub phase: OpenPhase,
+
+    /// Items finished so far in this phase
+    pub done: u64,
+
+    /// Items in this phase
+    pub total: u64,
+
+    /// Bytes finished so far in this phase
+    pub bytes_done: u64,
+
+    /// Bytes in this phase
+    pub bytes_total: u64,
+}
+
+impl OpenProgress {
+    /// Fraction of the phase that is finished (1.0 for an empty phase)
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_total > 0 {
+            self.bytes_done as f64 / self.bytes_total as f64
+        } else if self.total > 0 {
+            self.done as f64 / self.total as f64
+        } else {
+            1.0
+        }
+    }
+}
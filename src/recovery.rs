@@ -0,0 +1,110 @@
+/// How long a crash restart would take right now
+///
+/// Everything flushed is in SSTables, which an open only checks; what it
+/// has to redo is the WAL, replayed entry by entry into a new memtable.
+/// The replay time is projected from the log's size and a replay rate.
+/// The rate is measured while the tree is opened, when the log replayed
+/// is big enough for the timing to mean something
+/// (`MIN_MEASURED_REPLAY_BYTES`); until a restart has measured one,
+/// `DEFAULT_REPLAY_BYTES_PER_SEC` is assumed.
+///
+/// With `LSMConfig::recovery_warn_threshold` set, the tree calls
+/// `EventListener::on_recovery_estimate_high` when the projection rises
+/// above it. A flush clears the log, and with it the estimate.
+use std::fmt;
+use std::time::Duration;
+
+/// Replay rate assumed before one has been measured (64 MiB/s)
+pub const DEFAULT_REPLAY_BYTES_PER_SEC: f64 = 64.0 * 1024.0 * 1024.0;
+
+/// Smallest log whose replay on open is timed to measure the rate
+pub const MIN_MEASURED_REPLAY_BYTES: u64 = 64 * 1024;
+
+/// What replaying the WAL after a crash would cost (see
+/// `LSMTree::recovery_estimate`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecoveryEstimate {
+    /// Size of the log, which a restart reads whole
+    pub wal_bytes: u64,
+
+    /// Entries in the log: written since the last flush, and any the last
+    /// open replayed
+    pub wal_entries: u64,
+
+    /// Replay rate the projection uses
+    pub replay_bytes_per_sec: f64,
+
+    /// Whether that rate was measured by an open of this tree (rather than
+    /// `DEFAULT_REPLAY_BYTES_PER_SEC`)
+    pub rate_measured: bool,
+
+    /// Projected time to replay the log
+    pub replay_time: Duration,
+}
+
+impl RecoveryEstimate {
+    /// The estimate for a log of `wal_bytes` and `wal_entries`, at the
+    /// measured rate if there is one
+    pub(crate) fn new(wal_bytes: u64, wal_entries: u64, measured_rate: Option<f64>) -> Self {
+        let replay_bytes_per_sec = measured_rate.unwrap_or(DEFAULT_REPLAY_BYTES_PER_SEC);
+        Self {
+            wal_bytes,
+            wal_entries,
+            replay_bytes_per_sec,
+            rate_measured: measured_rate.is_some(),
+            replay_time: Duration::from_secs_f64(wal_bytes as f64 / replay_bytes_per_sec),
+        }
+    }
+}
+
+/// The replay rate an open achieved, if it replayed enough to tell
+pub(crate) fn measured_rate(bytes: u64, elapsed: Duration) -> Option<f64> {
+    let secs = elapsed.as_secs_f64();
+    (bytes >= MIN_MEASURED_REPLAY_BYTES && secs > 0.0).then(|| bytes as f64 / secs)
+}
+
+impl fmt::Display for RecoveryEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "~{:.1} ms to replay {} WAL entries ({} bytes) at {:.1} MiB/s ({})",
+            self.replay_time.as_secs_f64() * 1000.0,
+            self.wal_entries,
+            self.wal_bytes,
+            self.replay_bytes_per_sec / (1024.0 * 1024.0),
+            if self.rate_measured {
+                "measured"
+            } else {
+                "assumed"
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_projection_uses_the_measured_rate_when_there_is_one() {
+        let assumed = RecoveryEstimate::new(64 * 1024 * 1024, 10, None);
+        assert_eq!(assumed.replay_time, Duration::from_secs(1));
+        assert!(!assumed.rate_measured);
+        assert!(assumed.to_string().contains("assumed"));
+
+        let measured = RecoveryEstimate::new(1024, 1, Some(512.0));
+        assert_eq!(measured.replay_time, Duration::from_secs(2));
+        assert!(measured.rate_measured);
+
+        // Too small a replay, or one too fast to time, measures nothing
+        assert_eq!(measured_rate(1024, Duration::from_millis(1)), None);
+        assert_eq!(
+            measured_rate(MIN_MEASURED_REPLAY_BYTES, Duration::ZERO),
+            None
+        );
+        assert_eq!(
+            measured_rate(MIN_MEASURED_REPLAY_BYTES, Duration::from_secs(2)),
+            Some(MIN_MEASURED_REPLAY_BYTES as f64 / 2.0)
+        );
+    }
+}
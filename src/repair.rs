@@ -0,0 +1,219 @@
+/// Rebuilding the manifest from the SSTables in a data directory
+///
+/// The manifest is the only record of which SSTables are live and which is
+/// newest. If it is lost or damaged the tables themselves are usually fine,
+/// and `LSMTree::repair` rebuilds it from them:
+///
+/// 1. every `sstable_<n>.db` is checked as on open, each record against
+///    its checksum in tables that have them; damaged ones are moved aside
+///    as `<name>.db.corrupt`, exactly like quarantine on open, and one
+///    stamped by another tree fails the repair (see `tree_id`)
+/// 2. tables are ordered by the highest write sequence number they hold;
+///    tables written before sequence numbers existed have none and are
+///    ordered by modification time, older than every sequenced table
+/// 3. missing or unreadable Bloom filters are rebuilt, unless the tree
+///    runs without filters (`FilterPolicy::None`)
+/// 4. the previous manifest, if there is one, is kept as `MANIFEST.bak`
+///    and a fresh one is written
+///
+/// Nothing is deleted. Repair takes the directory lock, so it refuses to
+/// run while a tree has the directory open for writing. The WAL is left
+/// alone; the next open replays it as usual.
+///
+/// A table's highest record sequence can be lower than the `max_seq` the
+/// old manifest had for it (a purge drops tombstones, and with them the
+/// newest sequences). Should the WAL still hold those writes, the next open
+/// replays them again, which only rewrites the values they already wrote.
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::bloom::FilterFloor;
+use crate::cancel::CancellationToken;
+use crate::config::{DEFAULT_BLOOM_FILTER_FPP, DEFAULT_READ_BUFFER_BYTES, FilterPolicy};
+use crate::filter_cache;
+use crate::format::DirectoryFormat;
+use crate::handle::SSTableId;
+use crate::lock::DirLock;
+use crate::manifest::{MANIFEST_FILE, Manifest, TableEntry};
+use crate::sstable::SSTableReader;
+use crate::{LSMConfig, LSMTree, QuarantinedSSTable};
+
+/// Settings for `LSMTree::repair`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepairOptions {
+    /// Whether missing Bloom filters are rebuilt (`FilterPolicy::None`
+    /// leaves them missing, as the tree does)
+    pub filter_policy: FilterPolicy,
+
+    /// False positive rate of rebuilt Bloom filters
+    pub bloom_filter_fpp: f64,
+
+    /// WAL directory to record in the manifest (`None` keeps the one the
+    /// old manifest recorded, if it can still be read)
+    pub wal_dir: Option<PathBuf>,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self {
+            filter_policy: FilterPolicy::Bloom,
+            bloom_filter_fpp: DEFAULT_BLOOM_FILTER_FPP,
+            wal_dir: None,
+        }
+    }
+}
+
+/// What `LSMTree::repair` found and did
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RepairReport {
+    /// Tables in the new manifest, newest first
+    pub tables: Vec<SSTableId>,
+
+    /// Tables without sequence numbers, ordered by modification time
+    pub ordered_by_mtime: Vec<SSTableId>,
+
+    /// Tables whose Bloom filter was rebuilt
+    pub rebuilt_filters: Vec<SSTableId>,
+
+    /// Damaged tables moved aside
+    pub quarantined: Vec<QuarantinedSSTable>,
+
+    /// Where the previous manifest was kept, if there was one
+    pub previous_manifest: Option<PathBuf>,
+}
+
+/// A table that passed the checks, with what orders it
+struct Candidate {
+    number: usize,
+    max_sequence: u64,
+    modified: SystemTime,
+}
+
+pub(crate) fn repair(
+    data_dir: &Path,
+    options: &RepairOptions,
+    cancel: &CancellationToken,
+) -> std::io::Result<RepairReport> {
+    std::fs::metadata(data_dir)?;
+    let _lock = DirLock::acquire(data_dir)?;
+    let format = DirectoryFormat::load(data_dir)?;
+    if let Some(format) = &format {
+        format.check(data_dir)?;
+    }
+    let tree_id = format.and_then(|format| format.tree_id);
+
+    let config = LSMConfig {
+        bloom_filter_fpp: options.bloom_filter_fpp,
+        paranoid_checks: false,
+        ..LSMConfig::new(data_dir.to_path_buf())
+    };
+    let mut report = RepairReport::default();
+    // The old manifest only contributes its WAL directory, and only if it
+    // can still be read
+    let old_wal_dir = Manifest::load(data_dir)
+        .ok()
+        .flatten()
+        .and_then(|manifest| manifest.wal_dir);
+
+    let mut numbers = Vec::new();
+    let mut highest_number = None;
+    for entry in std::fs::read_dir(data_dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        // Quarantined files keep their number reserved too
+        let number = LSMTree::sstable_number(&path).or_else(|| {
+            name.strip_suffix(".corrupt")
+                .and_then(|name| LSMTree::sstable_number(Path::new(name)))
+        });
+        highest_number = highest_number.max(number);
+        if let Some(number) = LSMTree::sstable_number(&path) {
+            numbers.push(number);
+        }
+    }
+    numbers.sort_unstable();
+
+    let mut candidates = Vec::with_capacity(numbers.len());
+    for number in numbers {
+        let path = LSMTree::sstable_path(data_dir, number);
+        if let Some(damaged) = LSMTree::check_sstable_with(&config, tree_id, &path, cancel)? {
+            report.quarantined.push(damaged);
+            continue;
+        }
+
+        let (mut max_sequence, mut records) = (0, 0);
+        let mut reader = SSTableReader::open(&path)?;
+        while let Some((_, seq, _)) = reader.try_next()? {
+            cancel.check(records)?;
+            max_sequence = max_sequence.max(seq);
+            records += 1;
+        }
+        let modified = std::fs::metadata(&path)?.modified()?;
+
+        if options.filter_policy == FilterPolicy::Bloom && filter_cache::load(&path).is_none() {
+            LSMTree::rebuild_bloom_filter(
+                &path,
+                options.bloom_filter_fpp,
+                FilterFloor::default(),
+                DEFAULT_READ_BUFFER_BYTES,
+            )?;
+            report.rebuilt_filters.push(SSTableId(number));
+        }
+        candidates.push(Candidate {
+            number,
+            max_sequence,
+            modified,
+        });
+    }
+
+    // Oldest first: unsequenced tables by modification time, then the rest
+    // by sequence; file numbers only break ties
+    candidates.sort_by_key(|c| {
+        let unsequenced = c.max_sequence == 0;
+        (
+            !unsequenced,
+            c.max_sequence,
+            unsequenced.then_some(c.modified),
+            c.number,
+        )
+    });
+    report.ordered_by_mtime = candidates
+        .iter()
+        .filter(|c| c.max_sequence == 0)
+        .map(|c| SSTableId(c.number))
+        .collect();
+
+    let manifest = Manifest {
+        next_file_number: highest_number.map_or(0, |n| n + 1),
+        wal_dir: options.wal_dir.clone().or(old_wal_dir),
+        cold_dir: None,
+        sstables: candidates
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, c)| TableEntry {
+                number: c.number,
+                generation: i as u64 + 1,
+                max_sequence: c.max_sequence,
+                ..TableEntry::default()
+            })
+            .collect(),
+    };
+    report.tables = manifest
+        .sstables
+        .iter()
+        .map(|t| SSTableId(t.number))
+        .collect();
+
+    let manifest_path = data_dir.join(MANIFEST_FILE);
+    if manifest_path.exists() {
+        let backup = data_dir.join(format!("{}.bak", MANIFEST_FILE));
+        std::fs::copy(&manifest_path, &backup)?;
+        report.previous_manifest = Some(backup);
+    }
+    manifest.store(data_dir)?;
+    config.storage.sync_dir(data_dir)?;
+    Ok(report)
+}
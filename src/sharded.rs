@@ -0,0 +1,358 @@
+/// A tree split by key hash into independently written shards
+/// (`LSMConfig::write_shards`)
+///
+/// Behind a `SharedLSMTree` every write takes the one write lock, appends
+/// to the one WAL and may wait for the one flush, so writer threads queue
+/// up however fast the memtable is. `ShardedLSMTree` splits the key space
+/// into `write_shards` shards by a stable hash of the key. Each shard is a
+/// tree of its own in `shard_<i>/` under the data directory (its WAL and
+/// cold tables go under the same name in `wal_dir` and `cold_dir`, if they
+/// are set), with its own lock,
+/// memtable, WAL and SSTables, and flushes when its own memtable fills up.
+/// Writers to different shards never wait for each other.
+///
+/// A point read goes to the key's shard only. A range scan takes a
+/// `TreeState` of every shard and merges them; the shards hold disjoint
+/// keys, so the merge only interleaves. Opening replays every shard's WAL.
+///
+/// What a single tree guarantees across keys doesn't carry over:
+///
+/// - a `WriteBatch` is applied shard by shard, so a reader can see part of
+///   it, and a crash can keep a different prefix in each shard
+/// - a range scan sees each shard as of a slightly different moment
+///
+/// The shard count is recorded in a `SHARDS` file and can't change: keys
+/// would hash to other shards than the ones holding them. Each shard gets
+/// `memtable_size_threshold / write_shards` of memtable, so the whole tree
+/// buffers as much as an unsharded one.
+///
+/// Example:
+/// ```ignore
+/// let tree = ShardedLSMTree::open(LSMConfig {
+///     write_shards: 8,
+///     ..LSMConfig::new(dir)
+/// })?;
+/// let writer = tree.clone();
+/// std::thread::spawn(move || writer.put(b"a".to_vec(), b"1".to_vec()));
+/// ```
+use std::io::ErrorKind;
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::bloom::{FNV_OFFSET_BASIS, fnv1a, mix64};
+use crate::{CompactionResult, LSMConfig, LSMTree, TreeState, WriteBatch};
+
+/// File in the data directory recording the number of shards
+pub const SHARDS_FILE: &str = "SHARDS";
+
+/// Cloneable, thread-safe handle to a sharded tree (see the module docs)
+#[derive(Clone)]
+pub struct ShardedLSMTree {
+    shards: Arc<Vec<RwLock<LSMTree>>>,
+}
+
+impl ShardedLSMTree {
+    /// Opens (or creates) a sharded tree with `config.write_shards` shards
+    ///
+    /// Fails with `InvalidInput` for 0 shards, for a directory created with
+    /// another shard count, and for a directory holding an unsharded tree.
+    pub fn open(config: LSMConfig) -> std::io::Result<Self> {
+        let count = config.write_shards;
+        if count == 0 {
+            return Err(invalid_input("a sharded tree needs at least one shard"));
+        }
+        let recorded = Self::recorded_shards(&config.data_dir)?;
+        match recorded {
+            Some(recorded) if recorded != count => {
+                return Err(invalid_input(format!(
+                    "{} has {} shards, but was opened with write_shards {}",
+                    config.data_dir.display(),
+                    recorded,
+                    count
+                )));
+            }
+            Some(_) => {}
+            None if config.read_only => {
+                return Err(std::io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("{} holds no sharded tree", config.data_dir.display()),
+                ));
+            }
+            None => {
+                if config.data_dir.join("wal.log").exists()
+                    || config.data_dir.join("MANIFEST").exists()
+                {
+                    return Err(invalid_input(format!(
+                        "{} holds an unsharded tree",
+                        config.data_dir.display()
+                    )));
+                }
+                std::fs::create_dir_all(&config.data_dir)?;
+                std::fs::write(config.data_dir.join(SHARDS_FILE), format!("{}\n", count))?;
+            }
+        }
+
+        let shards = (0..count)
+            .map(|i| LSMTree::open(Self::shard_config(&config, i)).map(RwLock::new))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        Ok(Self {
+            shards: Arc::new(shards),
+        })
+    }
+
+    /// The shard count recorded in `data_dir`, if it holds a sharded tree
+    pub fn recorded_shards(data_dir: &Path) -> std::io::Result<Option<usize>> {
+        let path = data_dir.join(SHARDS_FILE);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match text.trim().parse() {
+            Ok(count) if count > 0 => Ok(Some(count)),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is damaged: {:?}", path.display(), text),
+            )),
+        }
+    }
+
+    /// Config of shard `index`: its own directories and share of the
+    /// memtable budget
+    fn shard_config(config: &LSMConfig, index: usize) -> LSMConfig {
+        let name = format!("shard_{}", index);
+        LSMConfig {
+            data_dir: config.data_dir.join(&name),
+            wal_dir: config.wal_dir.as_ref().map(|dir| dir.join(&name)),
+            cold_dir: config.cold_dir.as_ref().map(|dir| dir.join(&name)),
+            memtable_size_threshold: (config.memtable_size_threshold / config.write_shards).max(1),
+            write_shards: 1,
+            ..config.clone()
+        }
+    }
+
+    /// Number of shards
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The shard a key belongs to
+    pub fn shard_of(&self, key: &[u8]) -> usize {
+        (mix64(fnv1a(FNV_OFFSET_BASIS, key)) % self.shards.len() as u64) as usize
+    }
+
+    /// Inserts or updates a key-value pair
+    pub fn put(&self, key: Vec<u8>, value: Vec<u8>) -> std::io::Result<()> {
+        self.write(self.shard_of(&key)).put(key, value)
+    }
+
+    /// Deletes a key
+    pub fn delete(&self, key: Vec<u8>) -> std::io::Result<()> {
+        self.write(self.shard_of(&key)).delete(key)
+    }
+
+    /// Applies a batch, one shard at a time (not atomically; see the
+    /// module docs)
+    pub fn write_batch(&self, batch: WriteBatch) -> std::io::Result<()> {
+        let mut per_shard = vec![WriteBatch::new(); self.shards.len()];
+        for (key, value) in batch.into_ops() {
+            let shard = &mut per_shard[self.shard_of(&key)];
+            match value {
+                Some(value) => shard.put(key, value),
+                None => shard.delete(key),
+            }
+        }
+        for (i, batch) in per_shard.into_iter().enumerate() {
+            self.write(i).write(batch)?;
+        }
+        Ok(())
+    }
+
+    /// Looks up a key in its shard (without updating Bloom filter
+    /// statistics)
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.read(self.shard_of(key)).get_immut(key)
+    }
+
+    /// Collects the live key-value pairs in `range` from every shard, in
+    /// key order
+    pub fn range_collect<K: AsRef<[u8]>, R: RangeBounds<K> + Clone>(
+        &self,
+        range: R,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let states: Vec<TreeState> = (0..self.shards.len())
+            .map(|i| self.read(i).read_state())
+            .collect();
+        let mut iters: Vec<_> = states
+            .iter()
+            .map(|state| state.range(range.clone()).peekable())
+            .collect();
+
+        let mut entries = Vec::new();
+        // Shards hold disjoint keys: take the smallest head each time
+        while let Some(next) = iters
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, iter)| iter.peek().map(|(key, _)| (key.clone(), i)))
+            .min()
+            .map(|(_, i)| i)
+        {
+            entries.extend(iters[next].next());
+        }
+        entries
+    }
+
+    /// Flushes every shard's memtable
+    pub fn flush(&self) -> std::io::Result<()> {
+        (0..self.shards.len()).try_for_each(|i| self.write(i).flush())
+    }
+
+    /// Compacts each shard's SSTables into one (`LSMTree::purge`), adding
+    /// up the results
+    pub fn purge(&self) -> std::io::Result<CompactionResult> {
+        let mut total = CompactionResult {
+            completed: true,
+            ..CompactionResult::default()
+        };
+        for i in 0..self.shards.len() {
+            let result = self.write(i).purge()?;
+            total.input_tables += result.input_tables;
+            total.bytes_read += result.bytes_read;
+            total.bytes_written += result.bytes_written;
+            total.entries_written += result.entries_written;
+            total.orphans_removed += result.orphans_removed;
+        }
+        Ok(total)
+    }
+
+    /// SSTables across all shards
+    pub fn sstable_count(&self) -> usize {
+        (0..self.shards.len())
+            .map(|i| self.read(i).sstable_count())
+            .sum()
+    }
+
+    /// Directory of shard `index`
+    pub fn shard_dir(&self, index: usize) -> PathBuf {
+        self.read(index).data_dir().clone()
+    }
+
+    /// Locks shard `index` for reading
+    ///
+    /// # Panics
+    /// If `index` is out of range, or a thread panicked while holding the
+    /// shard's write lock.
+    pub fn read(&self, index: usize) -> RwLockReadGuard<'_, LSMTree> {
+        self.shards[index].read().expect("LSM shard lock poisoned")
+    }
+
+    /// Locks shard `index` for writing (see `read`)
+    pub fn write(&self, index: usize) -> RwLockWriteGuard<'_, LSMTree> {
+        self.shards[index].write().expect("LSM shard lock poisoned")
+    }
+
+    /// Abandons every shard the way a crash of the process would (see
+    /// `LSMTree::simulate_crash`)
+    ///
+    /// # Panics
+    /// If another handle to the tree is still alive.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn simulate_crash(self) {
+        let shards = Arc::into_inner(self.shards).expect("other handles to the tree are alive");
+        for shard in shards {
+            shard
+                .into_inner()
+                .expect("LSM shard lock poisoned")
+                .simulate_crash();
+        }
+    }
+}
+
+fn invalid_input(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidInput, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn config(dir: &Path, shards: usize) -> LSMConfig {
+        LSMConfig {
+            write_shards: shards,
+            memtable_size_threshold: 4096,
+            sync_on_flush: false,
+            ..LSMConfig::new(dir.to_path_buf())
+        }
+    }
+
+    #[test]
+    fn test_parallel_writers_across_shards() {
+        let dir = PathBuf::from("./test_sharded_parallel");
+        fs::remove_dir_all(&dir).ok();
+
+        let tree = ShardedLSMTree::open(config(&dir, 4)).unwrap();
+        let key = |t: u32, i: u32| format!("t{}:{:04}", t, i).into_bytes();
+        std::thread::scope(|scope| {
+            for t in 0..8u32 {
+                let tree = tree.clone();
+                scope.spawn(move || {
+                    for i in 0..300u32 {
+                        tree.put(key(t, i), i.to_le_bytes().to_vec()).unwrap();
+                        assert_eq!(tree.get(&key(t, i)), Some(i.to_le_bytes().to_vec()));
+                    }
+                    tree.delete(key(t, 0)).unwrap();
+                });
+            }
+        });
+
+        // Every shard got keys and flushed on its own
+        for i in 0..4 {
+            assert!(
+                tree.read(i).sstable_count() > 0,
+                "shard {} never flushed",
+                i
+            );
+            assert!(tree.shard_dir(i).ends_with(format!("shard_{}", i)));
+        }
+        let all = tree.range_collect::<&[u8], _>(..);
+        assert_eq!(all.len(), 8 * 299);
+        assert!(all.windows(2).all(|w| w[0].0 < w[1].0));
+        let t3 = tree.range_collect(b"t3:".as_slice()..b"t4:".as_slice());
+        assert_eq!(t3.len(), 299);
+
+        // Recovery replays every shard's WAL
+        tree.put(b"unflushed".to_vec(), b"v".to_vec()).unwrap();
+        tree.simulate_crash();
+        let tree = ShardedLSMTree::open(config(&dir, 4)).unwrap();
+        assert_eq!(tree.get(b"unflushed"), Some(b"v".to_vec()));
+        assert_eq!(tree.get(&key(5, 0)), None);
+        assert_eq!(tree.range_collect::<&[u8], _>(..).len(), 8 * 299 + 1);
+        drop(tree);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_shard_count_is_fixed() {
+        let dir = PathBuf::from("./test_sharded_count");
+        fs::remove_dir_all(&dir).ok();
+
+        let tree = ShardedLSMTree::open(config(&dir, 3)).unwrap();
+        drop(tree);
+        assert_eq!(ShardedLSMTree::recorded_shards(&dir).unwrap(), Some(3));
+
+        for bad in [config(&dir, 2), config(&dir, 0)] {
+            let e = ShardedLSMTree::open(bad).map(|_| ()).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        }
+        // A plain tree refuses the sharded config and the sharded directory
+        let e = LSMTree::open(config(&dir, 3)).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        let e = LSMTree::open(LSMConfig::new(dir.clone())).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,336 @@
+/// A tree that can be shared between threads
+///
+/// `LSMTree` itself takes `&mut self` for writes, so sharing it needs a
+/// lock. `SharedLSMTree` wraps it in an `RwLock`: any number of reads run
+/// at the same time, and a write (or flush) runs alone.
+///
+/// A flush changes what readers see in one step (see
+/// `LSMTree::install_flushed_table`): the new SSTable and its filter are
+/// registered and the memtable is cleared while the write lock is held,
+/// and the WAL is cleared only after that. A reader therefore finds every
+/// key either in the memtable or in the new table, never in neither.
+///
+/// A reader racing a write to the same key gets the whole old value or the
+/// whole new one, never a mix (see `Memtable`), and the memtable's size
+/// always matches its entries.
+///
+/// Long reads (scans, `multi_get`) shouldn't hold the read lock: writers
+/// would wait for them. They take a `TreeState` under the lock instead and
+/// run on that, unaffected by the flushes and compactions that follow.
+///
+/// Example:
+/// ```ignore
+/// let tree = SharedLSMTree::new(LSMTree::new(dir, 4 * 1024 * 1024)?);
+/// let reader = tree.clone();
+/// std::thread::spawn(move || reader.get(b"key"));
+/// tree.put(b"key".to_vec(), b"value".to_vec())?;
+/// ```
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{LSMTree, RefreshReport, TreeState};
+
+/// Cloneable, thread-safe handle to an `LSMTree`
+#[derive(Clone)]
+pub struct SharedLSMTree {
+    inner: Arc<RwLock<LSMTree>>,
+}
+
+impl SharedLSMTree {
+    /// Wraps an open tree
+    pub fn new(lsm: LSMTree) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(lsm)),
+        }
+    }
+
+    /// Looks up a key (without updating Bloom filter statistics)
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.read().get_immut(key)
+    }
+
+    /// Looks up several keys as of one moment, without holding the lock
+    /// while reading the SSTables
+    pub fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Option<Vec<u8>>> {
+        self.read_state().multi_get(keys)
+    }
+
+    /// Captures the tree's data for reads that run without the lock
+    ///
+    /// The read lock is held only while the state is taken. Iterate with
+    /// `state.range(..)` or `state.keys(..)`: the state keeps seeing the
+    /// tree as it was, and the files it reads stay until it is dropped.
+    pub fn read_state(&self) -> TreeState {
+        self.read().read_state()
+    }
+
+    /// Inserts or updates a key-value pair
+    pub fn put(&self, key: Vec<u8>, value: Vec<u8>) -> std::io::Result<()> {
+        self.write().put(key, value)
+    }
+
+    /// Deletes a key
+    pub fn delete(&self, key: Vec<u8>) -> std::io::Result<()> {
+        self.write().delete(key)
+    }
+
+    /// Flushes the memtable to a new SSTable
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.write().flush()
+    }
+
+    /// Runs `LSMTree::maintenance_tick` under the write lock
+    ///
+    /// Holds the lock only for the tick itself, so a maintenance thread can
+    /// call this on a timer alongside regular readers and writers.
+    pub fn maintenance_tick(&self) -> std::io::Result<bool> {
+        self.write().maintenance_tick()
+    }
+
+    /// Runs `LSMTree::refresh` under the write lock
+    ///
+    /// Readers wait for the refresh and then see the new table list; none
+    /// sees it half installed.
+    pub fn refresh(&self) -> std::io::Result<RefreshReport> {
+        self.write().refresh()
+    }
+
+    /// Locks the tree for reading, for anything else that takes `&self`
+    ///
+    /// # Panics
+    /// If a thread panicked while holding the write lock.
+    pub fn read(&self) -> RwLockReadGuard<'_, LSMTree> {
+        self.inner.read().expect("LSM tree lock poisoned")
+    }
+
+    /// Locks the tree for writing, for anything else that takes `&mut self`
+    ///
+    /// # Panics
+    /// If a thread panicked while holding the write lock.
+    pub fn write(&self) -> RwLockWriteGuard<'_, LSMTree> {
+        self.inner.write().expect("LSM tree lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LSMConfig, WriteBatch};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_key_stays_visible_while_being_flushed() {
+        let dir = PathBuf::from("./test_shared_flush_visibility");
+        fs::remove_dir_all(&dir).ok();
+
+        let config = LSMConfig {
+            memtable_size_threshold: 1024 * 1024,
+            sync_on_flush: false,
+            ..LSMConfig::new(dir.clone())
+        };
+        let tree = SharedLSMTree::new(LSMTree::open(config).unwrap());
+        tree.put(b"hot".to_vec(), b"0".to_vec()).unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reads = Arc::new(AtomicUsize::new(0));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let tree = tree.clone();
+                let done = done.clone();
+                let reads = reads.clone();
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        assert!(tree.get(b"hot").is_some(), "key observed as missing");
+                        reads.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+
+        for round in 0..100u32 {
+            tree.put(b"hot".to_vec(), round.to_le_bytes().to_vec())
+                .unwrap();
+            tree.put(round.to_be_bytes().to_vec(), b"filler".to_vec())
+                .unwrap();
+            tree.flush().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert!(reads.load(Ordering::Relaxed) > 0);
+        assert_eq!(tree.read().sstable_count(), 100);
+        assert_eq!(tree.get(b"hot"), Some(99u32.to_le_bytes().to_vec()));
+        drop(tree);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_reads_never_see_a_torn_value() {
+        let dir = PathBuf::from("./test_shared_torn_values");
+        fs::remove_dir_all(&dir).ok();
+
+        let config = LSMConfig {
+            memtable_size_threshold: 1024 * 1024,
+            sync_on_flush: false,
+            ..LSMConfig::new(dir.clone())
+        };
+        let tree = SharedLSMTree::new(LSMTree::open(config).unwrap());
+        let keys: Vec<Vec<u8>> = (0..4u8).map(|i| vec![b'k', i]).collect();
+        // A value is its round number repeated, a different number of times
+        // per round, so a mix of two writes shows up as differing chunks
+        let value = |round: u64| round.to_le_bytes().repeat(1 + (round % 13) as usize);
+        let check = |value: &[u8]| {
+            assert_eq!(value.len() % 8, 0, "value of torn length");
+            let round = u64::from_le_bytes(value[..8].try_into().unwrap());
+            assert_eq!(
+                value.len(),
+                8 * (1 + (round % 13) as usize),
+                "value of torn length"
+            );
+            assert!(value.chunks(8).all(|c| c == &value[..8]), "torn value");
+        };
+        for key in &keys {
+            tree.put(key.clone(), value(0)).unwrap();
+        }
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reads = Arc::new(AtomicUsize::new(0));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let tree = tree.clone();
+                let keys = keys.clone();
+                let done = done.clone();
+                let reads = reads.clone();
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        for key in &keys {
+                            check(&tree.get(key).expect("key observed as missing"));
+                        }
+                        let state = tree.read_state();
+                        check(&state.get(&keys[0]).unwrap());
+                        let lsm = tree.read();
+                        assert_eq!(
+                            lsm.memtable_size(),
+                            lsm.memtable.recount_size(),
+                            "memtable size out of step with its entries"
+                        );
+                        reads.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+
+        for round in 1..=2000u64 {
+            let key = &keys[round as usize % keys.len()];
+            if round % 5 == 0 {
+                // Remove and write back in one batch: never seen as missing
+                let mut batch = WriteBatch::new();
+                batch.delete(key.clone());
+                batch.put(key.clone(), value(round));
+                tree.write().write(batch).unwrap();
+            } else {
+                tree.put(key.clone(), value(round)).unwrap();
+            }
+            if round % 500 == 0 {
+                tree.flush().unwrap();
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert!(reads.load(Ordering::Relaxed) > 0);
+        assert_eq!(tree.get(&keys[0]), Some(value(2000)));
+        drop(tree);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_read_states_stay_consistent_under_compaction() {
+        let dir = PathBuf::from("./test_shared_read_states");
+        fs::remove_dir_all(&dir).ok();
+
+        let config = LSMConfig {
+            memtable_size_threshold: 1024 * 1024,
+            sync_on_flush: false,
+            ..LSMConfig::new(dir.clone())
+        };
+        let tree = SharedLSMTree::new(LSMTree::open(config).unwrap());
+        let keys: Vec<Vec<u8>> = (0..200u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        // Every round rewrites all keys in one batch, so a consistent view
+        // has every key with the same value
+        let write_round = |round: u32| {
+            let mut batch = WriteBatch::new();
+            for key in &keys {
+                batch.put(key.clone(), round.to_le_bytes().to_vec());
+            }
+            tree.write().write(batch).unwrap();
+        };
+        write_round(0);
+
+        let done = Arc::new(AtomicBool::new(false));
+        let scans = Arc::new(AtomicUsize::new(0));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let tree = tree.clone();
+                let keys = keys.clone();
+                let done = done.clone();
+                let scans = scans.clone();
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        let state = tree.read_state();
+                        let mut seen = Vec::new();
+                        for (key, value) in state.range::<&[u8], _>(..) {
+                            seen.push((key, value));
+                            // Give compactions a chance to run mid-scan
+                            if seen.len() % 50 == 0 {
+                                thread::yield_now();
+                            }
+                        }
+                        let seen_keys: Vec<_> = seen.iter().map(|(key, _)| key.clone()).collect();
+                        assert_eq!(seen_keys, keys, "scan missed or repeated keys");
+                        let value = &seen[0].1;
+                        assert!(seen.iter().all(|(_, v)| v == value), "scan mixed rounds");
+                        let values = state.multi_get(&keys);
+                        assert!(values.iter().all(|v| v.as_ref() == Some(value)));
+                        scans.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+
+        for round in 1..=60u32 {
+            write_round(round);
+            tree.flush().unwrap();
+            if round % 3 == 0 {
+                tree.write().purge().unwrap();
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert!(scans.load(Ordering::Relaxed) > 0);
+        // Every table the purges dropped is gone once the states are
+        let lsm = tree.read();
+        assert_eq!(lsm.pending_table_deletions(), 0);
+        let files = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("db".as_ref()))
+            .count();
+        assert_eq!(files, lsm.sstable_count());
+        assert_eq!(lsm.get_immut(&keys[0]), Some(60u32.to_le_bytes().to_vec()));
+        drop(lsm);
+        drop(tree);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,288 @@
+/// Snapshot streams: the live data of a tree in a format of its own (see
+/// `LSMTree::export_snapshot` and `LSMTree::import_snapshot`)
+///
+/// A backup made of SSTable files is only as readable as their format
+/// version. A snapshot holds nothing but the live key-value pairs, in key
+/// order, and a few fields of metadata, in a layout that stays the same
+/// whatever the tree's files look like:
+///
+/// ```text
+/// [magic: b"LSMSNAP1"][version: u32 = 1][entries: u64][created_unix_ms: u64]
+/// [key_len: u32][key][value_len: u32][value]   ← `entries` times
+/// [checksum: u64]
+/// ```
+///
+/// All integers are little-endian. The checksum is FNV-1a over every byte
+/// before it, so a stream cut short or damaged anywhere is rejected: a
+/// short one ends before its checksum, a damaged one fails it. Nothing
+/// may follow the checksum.
+///
+/// The stream is read and written front to back in one pass, so a
+/// snapshot can go through a pipe or a socket as well as a file.
+use std::fmt;
+use std::io::{Read, Write};
+
+use crate::bloom::{FNV_OFFSET_BASIS, fnv1a};
+use crate::sstable::read_field;
+
+/// Magic bytes at the start of every snapshot
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"LSMSNAP1";
+
+/// Version of the snapshot layout written by this build
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+/// What a snapshot held, from an export or an import
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    /// Key-value pairs in the snapshot
+    pub entries: u64,
+
+    /// Bytes of the stream, checksum included
+    pub bytes: u64,
+
+    /// When the snapshot was exported, in milliseconds since the Unix epoch
+    pub created_unix_ms: u64,
+}
+
+impl fmt::Display for SnapshotInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries, {} bytes, exported at {} ms",
+            self.entries, self.bytes, self.created_unix_ms
+        )
+    }
+}
+
+/// A snapshot that can't be read back
+fn invalid(reason: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, reason)
+}
+
+/// Writes a snapshot of `entries` pairs, checksumming as it goes
+pub(crate) struct SnapshotWriter<W: Write> {
+    writer: W,
+    checksum: u64,
+    info: SnapshotInfo,
+    written: u64,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    /// Writes the header of a snapshot of `entries` pairs
+    pub(crate) fn new(writer: W, entries: u64, created_unix_ms: u64) -> std::io::Result<Self> {
+        let mut snapshot = Self {
+            writer,
+            checksum: FNV_OFFSET_BASIS,
+            info: SnapshotInfo {
+                entries,
+                bytes: 0,
+                created_unix_ms,
+            },
+            written: 0,
+        };
+        snapshot.write(SNAPSHOT_MAGIC)?;
+        snapshot.write(&SNAPSHOT_FORMAT_VERSION.to_le_bytes())?;
+        snapshot.write(&entries.to_le_bytes())?;
+        snapshot.write(&created_unix_ms.to_le_bytes())?;
+        Ok(snapshot)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.checksum = fnv1a(self.checksum, bytes);
+        self.info.bytes += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Writes one pair; keys must come in ascending order
+    pub(crate) fn add(&mut self, key: &[u8], value: &[u8]) -> std::io::Result<()> {
+        self.write(&(key.len() as u32).to_le_bytes())?;
+        self.write(key)?;
+        self.write(&(value.len() as u32).to_le_bytes())?;
+        self.write(value)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Writes the checksum, once the header's count of pairs has been
+    /// written
+    pub(crate) fn finish(mut self) -> std::io::Result<SnapshotInfo> {
+        if self.written != self.info.entries {
+            return Err(std::io::Error::other(format!(
+                "snapshot declared {} entries but {} were written",
+                self.info.entries, self.written
+            )));
+        }
+        self.writer.write_all(&self.checksum.to_le_bytes())?;
+        self.writer.flush()?;
+        self.info.bytes += 8;
+        Ok(self.info)
+    }
+}
+
+/// Reads the pairs of a snapshot, in key order
+///
+/// Iterating yields each pair, then checks the checksum once the header's
+/// count of pairs has been read; a stream that fails it or ends early
+/// yields an `InvalidData` error, after which the iteration ends. Key
+/// order is left to the import (see `OrderedRecords`), which settles
+/// repeated keys by the tree's `DedupPolicy`.
+pub(crate) struct SnapshotReader<R: Read> {
+    reader: R,
+    checksum: u64,
+    info: SnapshotInfo,
+    read: u64,
+    done: bool,
+}
+
+impl<R: Read> SnapshotReader<R> {
+    /// Reads and checks the header
+    pub(crate) fn new(reader: R) -> std::io::Result<Self> {
+        let mut snapshot = Self {
+            reader,
+            checksum: FNV_OFFSET_BASIS,
+            info: SnapshotInfo {
+                entries: 0,
+                bytes: 0,
+                created_unix_ms: 0,
+            },
+            read: 0,
+            done: false,
+        };
+        let mut magic = [0u8; 8];
+        snapshot.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(invalid("not a snapshot (bad magic)".to_string()));
+        }
+        let version = u32::from_le_bytes(snapshot.read_array()?);
+        if version > SNAPSHOT_FORMAT_VERSION {
+            return Err(invalid(format!(
+                "snapshot format version {} is newer than this build supports (up to {})",
+                version, SNAPSHOT_FORMAT_VERSION
+            )));
+        }
+        snapshot.info.entries = u64::from_le_bytes(snapshot.read_array()?);
+        snapshot.info.created_unix_ms = u64::from_le_bytes(snapshot.read_array()?);
+        Ok(snapshot)
+    }
+
+    /// What the snapshot holds: its count and creation time from the
+    /// header, and the bytes read so far
+    pub(crate) fn info(&self) -> SnapshotInfo {
+        self.info
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        self.reader.read_exact(buf).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => {
+                invalid("snapshot ends before its checksum".to_string())
+            }
+            _ => e,
+        })?;
+        self.checksum = fnv1a(self.checksum, buf);
+        self.info.bytes += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_array<const N: usize>(&mut self) -> std::io::Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        self.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Reads a length-prefixed field
+    fn read_field(&mut self) -> std::io::Result<Vec<u8>> {
+        let len = u32::from_le_bytes(self.read_array()?) as usize;
+        let mut field = Vec::new();
+        read_field(&mut self.reader, len, &mut field)
+            .map_err(|_| invalid("snapshot ends before its checksum".to_string()))?;
+        self.checksum = fnv1a(self.checksum, &field);
+        self.info.bytes += len as u64;
+        Ok(field)
+    }
+
+    /// Reads the next pair, or checks the checksum after the last one
+    fn try_next(&mut self) -> std::io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if self.read == self.info.entries {
+            let expected = self.checksum;
+            let mut stored = [0u8; 8];
+            self.read_exact(&mut stored)?;
+            if u64::from_le_bytes(stored) != expected {
+                return Err(invalid("snapshot checksum mismatch".to_string()));
+            }
+            let mut rest = [0u8; 1];
+            if self.reader.read(&mut rest)? > 0 {
+                return Err(invalid("data after the snapshot checksum".to_string()));
+            }
+            return Ok(None);
+        }
+        let key = self.read_field()?;
+        let value = self.read_field()?;
+        self.read += 1;
+        Ok(Some((key, value)))
+    }
+}
+
+impl<R: Read> Iterator for SnapshotReader<R> {
+    type Item = std::io::Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.try_next().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(pairs: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut writer = SnapshotWriter::new(&mut buf, pairs.len() as u64, 42).unwrap();
+        for (key, value) in pairs {
+            writer.add(key, value).unwrap();
+        }
+        let info = writer.finish().unwrap();
+        assert_eq!(info.bytes, buf.len() as u64);
+        buf
+    }
+
+    fn read_all(bytes: &[u8]) -> std::io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        SnapshotReader::new(bytes)?.collect()
+    }
+
+    #[test]
+    fn test_snapshot_rejects_every_damaged_stream() {
+        let pairs: [(&[u8], &[u8]); 3] = [(b"a", b"1"), (b"b", b""), (b"c", b"three")];
+        let bytes = snapshot(&pairs);
+        let mut reader = SnapshotReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.info().entries, 3);
+        assert_eq!(reader.info().created_unix_ms, 42);
+        assert_eq!(reader.by_ref().count(), 3);
+        assert_eq!(reader.info().bytes, bytes.len() as u64);
+
+        // Cut anywhere, or with any byte changed, it reads back as damaged
+        for len in 0..bytes.len() {
+            let e = read_all(&bytes[..len]).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData, "cut at {}", len);
+        }
+        for at in 0..bytes.len() {
+            let mut damaged = bytes.clone();
+            damaged[at] ^= 0x40;
+            assert!(read_all(&damaged).is_err(), "flipped byte {}", at);
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(read_all(&longer).is_err());
+
+        // A writer that doesn't write what it declared fails instead of
+        // producing a stream that can't be read back
+        let mut buf = Vec::new();
+        let writer = SnapshotWriter::new(&mut buf, 1, 0).unwrap();
+        assert!(writer.finish().is_err());
+    }
+}
@@ -0,0 +1,2566 @@
+/// SSTable on-disk format
+///
+/// An SSTable is an immutable, sorted file of key-value records written by a
+/// memtable flush. Current files start with a small header so that readers
+/// can tell format versions apart:
+///
+/// ```text
+/// +---------------------+
+/// | magic (4 bytes)     |  ← b"LSST"
+/// +---------------------+
+/// | version (u32)       |  ← SSTABLE_FORMAT_VERSION
+/// +---------------------+
+/// | record              |  ← [key_len: u32][key][seq: u64][value_len: u32][value]
+/// | record              |
+/// | ...                 |
+/// +---------------------+
+/// ```
+///
+/// Records are sorted by key. `seq` is the sequence number of the write
+/// that produced the record; a key may appear several times when older
+/// versions had to be kept for a pinned sequence (`LSMTree::pin_sequence`),
+/// newest (highest `seq`) first. Version 1 files have no `seq` field and
+/// exactly one record per key; their records read back with sequence 0,
+/// which counts as older than every write.
+///
+/// Tables with compressed values (`LSMConfig::compression`) are version 3.
+/// Their header continues with a compression section, and every value is
+/// stored compressed (its `value_len` is the compressed length):
+///
+/// ```text
+/// [magic][version = 3][codec: u8][dict_len: u32][dictionary]
+/// ```
+///
+/// Tables that record when their writes happened (see
+/// `value_meta::WriteTimes`) are version 4. The compression section is
+/// always present (codec 0 for uncompressed values) and is followed by the
+/// write times; the records are laid out as in version 2:
+///
+/// ```text
+/// [magic][version = 4][codec: u8][dict_len: u32][dictionary]
+/// [range_count: u32][(first_seq: u64, last_seq: u64, unix_ms: u64) ...]
+/// ```
+///
+/// A `unix_ms` of `u64::MAX` stands for writes whose time isn't known.
+///
+/// Tables stamped with the id of the tree that wrote them (see `tree_id`)
+/// are version 5: the id follows the version, and the rest is laid out as
+/// in version 4. Every table a tree with an id writes is stamped.
+///
+/// ```text
+/// [magic][version = 5][tree_id: 16 bytes][codec: u8][dict_len: u32][dictionary]
+/// [range_count: u32][(first_seq: u64, last_seq: u64, unix_ms: u64) ...]
+/// ```
+///
+/// Tables with a footer (see `footer`) are version 6: the header of
+/// version 5 gains the offset where the records end, and the block index,
+/// summary statistics and trailer of the footer follow the records. A
+/// damaged footer doesn't hide the records, since the header says where
+/// they end. Tables stamped with a tree id get a footer, so this is the
+/// version flushes and purges write now.
+///
+/// ```text
+/// [magic][version = 6][tree_id: 16 bytes][records_end: u64]
+/// [codec: u8][dict_len: u32][dictionary]
+/// [range_count: u32][(first_seq: u64, last_seq: u64, unix_ms: u64) ...]
+/// [records ...][index block][stats block][trailer]
+/// ```
+///
+/// Tables with a footer now store their keys prefix-compressed, which
+/// makes them version 7; the header and footer are laid out as in version
+/// 6. Sorted neighbours share long prefixes (`user:000123`, `user:000124`),
+/// so a record only stores how many bytes its key shares with the key of
+/// the record before it, and the rest:
+///
+/// ```text
+/// [shared: u32][suffix_len: u32][suffix][seq: u64][value_len: u32][value]
+/// ```
+///
+/// Older versions of a key share all of it. Every block of the footer's
+/// index starts with a restart point, a record sharing nothing, and so does
+/// every `RESTART_INTERVAL`th key of a block: a lookup can start reading at
+/// any block, and a reader can get back to any record by reading forward
+/// from the restart point before it.
+///
+/// Tables with a footer now also checksum every record, which makes them
+/// version 8. The checksum follows the value length, so the value is
+/// still the last field and can be skipped as before:
+///
+/// ```text
+/// [shared: u32][suffix_len: u32][suffix][seq: u64][value_len: u32][checksum: u32][value]
+/// ```
+///
+/// It covers the whole key, the sequence number, the value length and the
+/// stored value bytes (see `record_checksum`). Checksums are checked for
+/// the records a verified read or `LSMTree::verify_key` reads, and for
+/// every record by the integrity check of a whole table; plain reads and
+/// scans don't check them.
+///
+/// A table is written in the oldest version that can hold it: version 2
+/// without a tree id, compression or write times, so it stays readable by
+/// builds that know none of them.
+///
+/// Files written before the header existed ("legacy", version 0) start
+/// directly with the first record. They are still readable: a legacy file
+/// would need a first key of roughly 1.4 GB for its first four bytes to look
+/// like the magic, so the two layouts can't be confused in practice.
+///
+/// A tombstone record stores `TOMBSTONE_MARKER` as its value length and has
+/// no value bytes.
+///
+/// `SSTableWriter` produces files in this format and `SSTableReader` parses
+/// them; code that creates or reads whole tables should go through those
+/// two rather than the record helpers. `SSTableReader` is also the way
+/// for tools outside the crate to read a table file (see its docs).
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use crate::bloom::{BloomFilter, DeferredBloomFilter, FNV_OFFSET_BASIS, FilterFloor, fnv1a, mix64};
+use crate::cancel::CancellationToken;
+use crate::clock::{Clock, SystemClock};
+use crate::compression::{Compression, ValueDecoder, ValueEncoder};
+use crate::config::{
+    DEFAULT_BLOOM_FILTER_FPP, DEFAULT_READ_BUFFER_BYTES, DEFAULT_WRITE_BUFFER_BYTES, DedupPolicy,
+    FilterPolicy, FilterSizing,
+};
+use crate::footer::{self, BlockIndex, TableSummary};
+use crate::storage::{FsStorage, Storage};
+use crate::tree_id::TreeId;
+use crate::value_meta::WriteTimes;
+
+/// Magic bytes at the start of every versioned SSTable
+pub const SSTABLE_MAGIC: [u8; 4] = *b"LSST";
+
+/// Format version of SSTables written by this build
+pub const SSTABLE_FORMAT_VERSION: u32 = 2;
+
+/// Format version of SSTables with compressed values
+pub const COMPRESSED_FORMAT_VERSION: u32 = 3;
+
+/// Format version of SSTables that record their write times
+pub const TIMED_FORMAT_VERSION: u32 = 4;
+
+/// Format version of SSTables stamped with the id of their tree
+pub const STAMPED_FORMAT_VERSION: u32 = 5;
+
+/// Format version of SSTables that end with a footer
+pub const FOOTER_FORMAT_VERSION: u32 = 6;
+
+/// Format version of SSTables with prefix-compressed keys
+pub const PREFIX_FORMAT_VERSION: u32 = 7;
+
+/// Format version of SSTables whose records carry a checksum
+pub const CHECKSUM_FORMAT_VERSION: u32 = 8;
+
+/// Newest format version this build can read
+pub const NEWEST_FORMAT_VERSION: u32 = CHECKSUM_FORMAT_VERSION;
+
+/// Keys between two restart points of a prefix-compressed table, unless a
+/// new block starts first
+pub(crate) const RESTART_INTERVAL: usize = 16;
+
+/// First format version whose records carry a sequence number
+const SEQUENCED_FORMAT_VERSION: u32 = 2;
+
+/// Version number reported for headerless files from older builds
+pub const LEGACY_FORMAT_VERSION: u32 = 0;
+
+/// Size of the header in bytes (magic + version)
+pub(crate) const HEADER_LEN: u64 = 8;
+
+/// Offset of the `records_end` field in tables with a footer, after the
+/// tree id
+const RECORDS_END_AT: u64 = HEADER_LEN + TreeId::LEN as u64;
+
+/// Value length written in place of a real length for deleted keys
+pub(crate) const TOMBSTONE_MARKER: u32 = u32::MAX;
+
+/// Size of a record's checksum in tables that have one
+const CHECKSUM_LEN: u64 = 4;
+
+/// One record: the key, its sequence number and its value (`None` for a
+/// tombstone)
+pub type Record = (Vec<u8>, u64, Option<Vec<u8>>);
+
+/// One record of a key found by `SSTableReader::locate`
+#[derive(Debug)]
+pub(crate) struct LocatedRecord {
+    /// Where the record starts in the file
+    pub(crate) offset: u64,
+    pub(crate) seq: u64,
+    /// The value (`None` for a tombstone, or when the record is damaged)
+    pub(crate) value: Option<Vec<u8>>,
+    pub(crate) damage: Option<Corruption>,
+}
+
+/// Writes the magic + version header at the start of a new SSTable
+pub(crate) fn write_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writer.write_all(&SSTABLE_MAGIC)?;
+    writer.write_all(&SSTABLE_FORMAT_VERSION.to_le_bytes())?;
+    Ok(())
+}
+
+/// Writes one record in the current format (a `None` value writes a
+/// tombstone)
+pub(crate) fn write_record<W: Write>(
+    writer: &mut W,
+    key: &[u8],
+    seq: u64,
+    value: Option<&[u8]>,
+) -> std::io::Result<()> {
+    writer.write_all(&(key.len() as u32).to_le_bytes())?;
+    writer.write_all(key)?;
+    writer.write_all(&seq.to_le_bytes())?;
+    write_value(writer, value)
+}
+
+/// Writes one prefix-compressed, checksummed record: only the bytes of
+/// `key` past the first `shared`, which the key before it has, are stored
+fn write_prefixed_record<W: Write>(
+    writer: &mut W,
+    key: &[u8],
+    shared: usize,
+    seq: u64,
+    value: Option<&[u8]>,
+) -> std::io::Result<()> {
+    let suffix = &key[shared..];
+    writer.write_all(&(shared as u32).to_le_bytes())?;
+    writer.write_all(&(suffix.len() as u32).to_le_bytes())?;
+    writer.write_all(suffix)?;
+    writer.write_all(&seq.to_le_bytes())?;
+    let value_len = value.map_or(TOMBSTONE_MARKER, |value| value.len() as u32);
+    let value = value.unwrap_or_default();
+    writer.write_all(&value_len.to_le_bytes())?;
+    writer.write_all(&record_checksum(key, seq, value_len, value).to_le_bytes())?;
+    writer.write_all(value)
+}
+
+/// Checksum of a record: its whole key, sequence number, value length and
+/// stored value bytes (none for a tombstone)
+pub(crate) fn record_checksum(key: &[u8], seq: u64, value_len: u32, value: &[u8]) -> u32 {
+    let hash = fnv1a(FNV_OFFSET_BASIS, &(key.len() as u32).to_le_bytes());
+    let hash = fnv1a(hash, key);
+    let hash = fnv1a(hash, &seq.to_le_bytes());
+    let hash = fnv1a(hash, &value_len.to_le_bytes());
+    mix64(fnv1a(hash, value)) as u32
+}
+
+/// Number of leading bytes `a` and `b` have in common
+fn shared_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// Writes a record without a sequence number, as formats before version 2
+/// did
+#[cfg(test)]
+pub(crate) fn write_unsequenced_record(buf: &mut Vec<u8>, key: &[u8], value: Option<&[u8]>) {
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key);
+    write_value(buf, value).unwrap();
+}
+
+/// Writes the value length and bytes that end a record
+fn write_value<W: Write>(writer: &mut W, value: Option<&[u8]>) -> std::io::Result<()> {
+    match value {
+        Some(value) => {
+            writer.write_all(&(value.len() as u32).to_le_bytes())?;
+            writer.write_all(value)?;
+        }
+        None => writer.write_all(&TOMBSTONE_MARKER.to_le_bytes())?,
+    }
+    Ok(())
+}
+
+/// Reads the header and leaves the reader positioned at the first record
+///
+/// Returns the file's format version (`LEGACY_FORMAT_VERSION` for files
+/// without a header). Files declaring a version newer than this build
+/// understands are rejected with `InvalidData` rather than misparsed.
+pub(crate) fn read_header<R: Read + Seek>(reader: &mut R, path: &Path) -> std::io::Result<u32> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+
+    if filled < header.len() || header[..4] != SSTABLE_MAGIC {
+        // No header: a legacy file, records start at offset 0
+        reader.seek(SeekFrom::Start(0))?;
+        return Ok(LEGACY_FORMAT_VERSION);
+    }
+
+    let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if version > NEWEST_FORMAT_VERSION {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "SSTable {} has format version {}, but this build only supports versions up to {}",
+                path.display(),
+                version,
+                NEWEST_FORMAT_VERSION
+            ),
+        ));
+    }
+
+    Ok(version)
+}
+
+/// What a table's header says about reading its records
+pub(crate) struct TableFormat {
+    pub(crate) version: u32,
+
+    /// Turns stored values back into the values that were written
+    pub(crate) values: ValueDecoder,
+
+    /// Offset of the write times section, in tables that have one
+    ///
+    /// The section is skipped on open and only read when asked for (see
+    /// `SSTableReader::write_times`).
+    pub(crate) write_times_at: Option<u64>,
+
+    /// Id of the tree that wrote the table, in stamped tables
+    pub(crate) tree_id: Option<TreeId>,
+
+    /// Where the records start, after the header
+    pub(crate) records_start: u64,
+
+    /// Where the records end: the start of the footer, or the end of the
+    /// file in tables without one
+    pub(crate) records_end: u64,
+}
+
+impl TableFormat {
+    /// Whether the table was written with a footer
+    pub(crate) fn has_footer(&self) -> bool {
+        self.version >= FOOTER_FORMAT_VERSION
+    }
+}
+
+/// An SSTable file that reads as if it ended with its records
+///
+/// Reads stop at `end`, so record loops that run until end of file don't
+/// run into a footer. The position is tracked here, which also saves
+/// buffered readers a system call each time they ask for it.
+pub(crate) struct TableFile {
+    file: File,
+    pos: u64,
+    end: u64,
+    len: u64,
+}
+
+impl TableFile {
+    fn new(file: File) -> std::io::Result<Self> {
+        let len = file.metadata()?.len();
+        Ok(Self {
+            file,
+            pos: 0,
+            end: len,
+            len,
+        })
+    }
+
+    /// Size of the whole file, footer included
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Reads `buf.len()` bytes at `offset`, past the end of the records
+    /// too, leaving the position where it was
+    pub(crate) fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        let read = self.file.read_exact(buf);
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        read
+    }
+}
+
+impl Read for TableFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.end.saturating_sub(self.pos);
+        let max = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        let n = self.file.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for TableFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+        };
+        let Some(target) = target else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek to a negative offset",
+            ));
+        };
+        self.pos = self.file.seek(SeekFrom::Start(target))?;
+        Ok(self.pos)
+    }
+
+    fn stream_position(&mut self) -> std::io::Result<u64> {
+        Ok(self.pos)
+    }
+}
+
+/// Opens an SSTable for sequential reading through a buffer of
+/// `buffer_bytes`, positioned at the first record
+pub(crate) fn open_reader(
+    path: &Path,
+    buffer_bytes: usize,
+) -> std::io::Result<(BufReader<TableFile>, TableFormat)> {
+    read_table_header(File::open(path)?, path, buffer_bytes)
+}
+
+/// Moves a reader at the first record to the block of the footer's index
+/// that `key` falls in, so the records of the blocks before it aren't read
+///
+/// Blocks start at a restart point and a new key, so reading carries on
+/// from there as from the first record. A table without a usable index is
+/// left where it is.
+pub(crate) fn seek_to_block(
+    reader: &mut BufReader<TableFile>,
+    format: &TableFormat,
+    key: &[u8],
+) -> std::io::Result<()> {
+    if !format.has_footer() {
+        return Ok(());
+    }
+    let index = footer::read_footer(reader.get_mut(), format.records_end)?
+        .and_then(|footer| footer.into_index());
+    if let Some(offset) = index.and_then(|index| index.block_for(key))
+        && offset > reader.stream_position()?
+    {
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+    Ok(())
+}
+
+/// Reads the header of an opened SSTable, leaving the reader at the first
+/// record
+fn read_table_header(
+    file: File,
+    path: &Path,
+    buffer_bytes: usize,
+) -> std::io::Result<(BufReader<TableFile>, TableFormat)> {
+    let file = TableFile::new(file)?;
+    let file_len = file.len();
+    let mut reader = BufReader::with_capacity(buffer_bytes, file);
+    let version = read_header(&mut reader, path)?;
+    let tree_id = if version >= STAMPED_FORMAT_VERSION {
+        let mut id = [0u8; TreeId::LEN];
+        reader.read_exact(&mut id).map_err(eof_as_truncated)?;
+        Some(TreeId::from_bytes(id))
+    } else {
+        None
+    };
+    let records_end = if version >= FOOTER_FORMAT_VERSION {
+        let mut end_buf = [0u8; 8];
+        reader.read_exact(&mut end_buf).map_err(eof_as_truncated)?;
+        Some(u64::from_le_bytes(end_buf))
+    } else {
+        None
+    };
+    let values = if version >= COMPRESSED_FORMAT_VERSION {
+        ValueDecoder::read_from(&mut reader)?
+    } else {
+        ValueDecoder::Plain
+    };
+    let write_times_at = if version >= TIMED_FORMAT_VERSION {
+        let at = reader.stream_position()?;
+        let mut count_buf = [0u8; 4];
+        reader
+            .read_exact(&mut count_buf)
+            .map_err(eof_as_truncated)?;
+        let section_len = 4 + u32::from_le_bytes(count_buf) as u64 * 24;
+        // A damaged count must not make the records look like they start
+        // past the end of the file (an empty, valid-looking table)
+        if at + section_len > file_len {
+            return Err(truncated_record());
+        }
+        reader.seek(SeekFrom::Start(at + section_len))?;
+        Some(at)
+    } else {
+        None
+    };
+
+    let records_start = reader.stream_position()?;
+    let records_end = match records_end {
+        Some(end) if end < records_start => return Err(truncated_record()),
+        Some(end) => end,
+        None => file_len,
+    };
+    // Nothing past the records is buffered yet: tables with a footer
+    // seeked past their write times just above. A file cut short ends
+    // where it ends, which `check_integrity` reports.
+    reader.get_mut().end = records_end.min(file_len);
+    Ok((
+        reader,
+        TableFormat {
+            version,
+            values,
+            write_times_at,
+            tree_id,
+            records_start,
+            records_end,
+        },
+    ))
+}
+
+/// Returns the format version of an SSTable file
+pub fn format_version(path: &Path) -> std::io::Result<u32> {
+    open_reader(path, DEFAULT_READ_BUFFER_BYTES).map(|(_, format)| format.version)
+}
+
+/// Returns where the records of an SSTable file end
+#[cfg(test)]
+pub(crate) fn records_end(path: &Path) -> u64 {
+    open_reader(path, DEFAULT_READ_BUFFER_BYTES)
+        .unwrap()
+        .1
+        .records_end
+}
+
+/// Returns the id of the tree that wrote an SSTable file (`None` for
+/// tables written before tables were stamped)
+pub fn tree_id(path: &Path) -> std::io::Result<Option<TreeId>> {
+    open_reader(path, DEFAULT_READ_BUFFER_BYTES).map(|(_, format)| format.tree_id)
+}
+
+/// Returns what an SSTable file holds: its counts, key and sequence range,
+/// write times and sizes
+///
+/// Tables with a footer are summarized from it in two small reads; older
+/// tables, and tables whose footer is damaged, are scanned in full, with
+/// the file's modification time as their creation time.
+pub fn read_summary(path: &Path) -> std::io::Result<TableSummary> {
+    let (mut reader, format) = open_reader(path, DEFAULT_READ_BUFFER_BYTES)?;
+    if format.has_footer()
+        && let Some(footer) = footer::read_footer(reader.get_mut(), format.records_end)?
+        && let Some(summary) = footer.summary()
+    {
+        return Ok(summary);
+    }
+
+    let mut table = SSTableReader::from_parts(reader, format, path);
+    let mut summary = TableSummary::empty(std::fs::metadata(path)?.modified()?);
+    summary.write_span = table.write_times()?.span();
+    let mut key = Vec::new();
+    let mut value = Vec::new();
+    while let Some((seq, value_len)) = read_key_into(&mut table.reader, table.version, &mut key)? {
+        let value_bytes = if value_len == TOMBSTONE_MARKER {
+            None
+        } else {
+            read_field(&mut table.reader, value_len as usize, &mut value)?;
+            let raw_len = table.values.decode(std::mem::take(&mut value))?.len();
+            Some((raw_len as u64, value_len as u64))
+        };
+        summary.add(&key, seq, value_bytes);
+    }
+    Ok(summary)
+}
+
+/// Why an SSTable failed the integrity check done when a tree is opened,
+/// or why one copy of a key failed `LSMTree::verify_key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Corruption {
+    /// The file has no bytes at all (e.g. a crash right after it was created)
+    Empty,
+
+    /// The record starting at `offset` runs past the end of the file
+    Truncated { offset: u64 },
+
+    /// The key of the record at `offset` is not greater than the key before it
+    OutOfOrder { offset: u64 },
+
+    /// The value of the record at `offset` can't be decompressed
+    Undecodable { offset: u64 },
+
+    /// The prefix-compressed key at `offset` shares more bytes than the key
+    /// before it has
+    BadKeyPrefix { offset: u64 },
+
+    /// The footer is damaged or doesn't match the records, which are intact
+    BadFooter,
+
+    /// The record at `offset` doesn't match its checksum
+    ChecksumMismatch { offset: u64 },
+}
+
+impl std::fmt::Display for Corruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Corruption::Empty => write!(f, "file is empty"),
+            Corruption::Truncated { offset } => {
+                write!(f, "record at offset {} is truncated", offset)
+            }
+            Corruption::OutOfOrder { offset } => {
+                write!(f, "key at offset {} is out of order", offset)
+            }
+            Corruption::Undecodable { offset } => {
+                write!(f, "value at offset {} can't be decoded", offset)
+            }
+            Corruption::BadKeyPrefix { offset } => {
+                write!(
+                    f,
+                    "key at offset {} shares more than the key before it",
+                    offset
+                )
+            }
+            Corruption::BadFooter => write!(f, "footer is damaged"),
+            Corruption::ChecksumMismatch { offset } => {
+                write!(f, "record at offset {} fails its checksum", offset)
+            }
+        }
+    }
+}
+
+/// Returns the size of a record's sequence field in a given format version
+fn seq_len(version: u32) -> u64 {
+    if version >= SEQUENCED_FORMAT_VERSION {
+        8
+    } else {
+        0
+    }
+}
+
+/// Returns the size of a record's checksum field in a given format version
+fn checksum_len(version: u32) -> u64 {
+    if version >= CHECKSUM_FORMAT_VERSION {
+        CHECKSUM_LEN
+    } else {
+        0
+    }
+}
+
+/// Walks every record of an SSTable and checks that it is well formed
+///
+/// A table is valid when it is non-empty, every record fits inside the file
+/// and records are ordered: keys ascending, and versions of the same key
+/// by strictly descending sequence. In tables with record checksums every
+/// record must also match its checksum, so values are read; older tables
+/// have their values skipped. A table with a footer must also have one that reads back and agrees
+/// with its records; a footer is only reported as `BadFooter` once the
+/// records are known to be fine. Returns `Ok(None)` for a valid table and
+/// `Ok(Some(_))` describing the first problem otherwise; `Err` is reserved
+/// for I/O errors and files from a newer format version. The file is read
+/// through a buffer of `buffer_bytes`.
+pub(crate) fn check_integrity(
+    path: &Path,
+    buffer_bytes: usize,
+) -> std::io::Result<Option<Corruption>> {
+    check_integrity_with(path, buffer_bytes, &CancellationToken::new())
+}
+
+/// `check_integrity`, failing with `Cancelled` once `cancel` is cancelled
+///
+/// The token is checked before every record; the error says how many
+/// records were checked.
+pub(crate) fn check_integrity_with(
+    path: &Path,
+    buffer_bytes: usize,
+    cancel: &CancellationToken,
+) -> std::io::Result<Option<Corruption>> {
+    let file_len = std::fs::metadata(path)?.len();
+    if file_len == 0 {
+        return Ok(Some(Corruption::Empty));
+    }
+
+    let (mut reader, format) = open_reader(path, buffer_bytes)?;
+    let version = format.version;
+    // Past the header, whose length depends on the version
+    let mut offset = reader.stream_position()?;
+    // A table cut short in its records has lost its footer as well
+    let records_end = format.records_end.min(file_len);
+    let seq_len = seq_len(version);
+    let checksum_len = checksum_len(version);
+    let prefixed = version >= PREFIX_FORMAT_VERSION;
+    let mut previous: Option<(Vec<u8>, u64)> = None;
+    let mut scanned = TableSummary::empty(UNIX_EPOCH);
+    let mut len_buf = [0u8; 4];
+    let mut seq_buf = [0u8; 8];
+    let mut checksum_buf = [0u8; 4];
+    let mut value = Vec::new();
+
+    while offset < records_end {
+        cancel.check(scanned.entries)?;
+        let record_start = offset;
+        let truncated = Corruption::Truncated {
+            offset: record_start,
+        };
+
+        // Check each length against the bytes left before reading
+        if records_end - offset < 4 {
+            return Ok(Some(truncated));
+        }
+        reader.read_exact(&mut len_buf)?;
+        offset += 4;
+        // Prefix-compressed keys are the shared bytes and a suffix
+        let (shared, key_len) = if prefixed {
+            let shared = u32::from_le_bytes(len_buf) as usize;
+            if records_end - offset < 4 {
+                return Ok(Some(truncated));
+            }
+            reader.read_exact(&mut len_buf)?;
+            offset += 4;
+            (shared, u32::from_le_bytes(len_buf) as u64)
+        } else {
+            (0, u32::from_le_bytes(len_buf) as u64)
+        };
+        if records_end - offset < key_len + seq_len + 4 + checksum_len {
+            return Ok(Some(truncated));
+        }
+        let previous_key = previous.as_ref().map_or(&[][..], |(key, _)| key.as_slice());
+        if shared > previous_key.len() {
+            return Ok(Some(Corruption::BadKeyPrefix {
+                offset: record_start,
+            }));
+        }
+
+        let mut key = previous_key[..shared].to_vec();
+        key.resize(shared + key_len as usize, 0);
+        reader.read_exact(&mut key[shared..])?;
+        reader.read_exact(&mut seq_buf[..seq_len as usize])?;
+        reader.read_exact(&mut len_buf)?;
+        reader.read_exact(&mut checksum_buf[..checksum_len as usize])?;
+        offset += key_len + seq_len + 4 + checksum_len;
+        let seq = u64::from_le_bytes(seq_buf);
+
+        let value_len = u32::from_le_bytes(len_buf);
+        let value_bytes = (value_len != TOMBSTONE_MARKER).then_some((0, value_len as u64));
+        let stored_len = value_bytes.map_or(0, |(_, stored)| stored);
+        if records_end - offset < stored_len {
+            return Ok(Some(truncated));
+        }
+        if checksum_len > 0 {
+            read_field(&mut reader, stored_len as usize, &mut value)?;
+            if u32::from_le_bytes(checksum_buf) != record_checksum(&key, seq, value_len, &value) {
+                return Ok(Some(Corruption::ChecksumMismatch {
+                    offset: record_start,
+                }));
+            }
+        } else {
+            reader.seek_relative(stored_len as i64)?;
+        }
+        offset += stored_len;
+
+        // Equal keys are older versions and must have smaller sequences
+        let in_order = previous.as_ref().is_none_or(|(prev_key, prev_seq)| {
+            key > *prev_key || (key == *prev_key && seq < *prev_seq)
+        });
+        if !in_order {
+            return Ok(Some(Corruption::OutOfOrder {
+                offset: record_start,
+            }));
+        }
+        scanned.add(&key, seq, value_bytes);
+        previous = Some((key, seq));
+    }
+
+    if format.records_end > file_len {
+        return Ok(Some(Corruption::Truncated { offset: file_len }));
+    }
+    if !format.has_footer() {
+        return Ok(None);
+    }
+    let Some(footer) = footer::read_footer(reader.get_mut(), records_end)? else {
+        return Ok(Some(Corruption::BadFooter));
+    };
+    let Some(summary) = footer.summary() else {
+        return Ok(Some(Corruption::BadFooter));
+    };
+    if footer.into_index().is_none() {
+        return Ok(Some(Corruption::BadFooter));
+    }
+    // Raw value bytes would take decoding every value; the rest is counted
+    let matches = summary.entries == scanned.entries
+        && summary.tombstones == scanned.tombstones
+        && summary.shadowed == scanned.shadowed
+        && summary.first_key == scanned.first_key
+        && summary.last_key == scanned.last_key
+        && summary.min_sequence == scanned.min_sequence
+        && summary.max_sequence == scanned.max_sequence
+        && summary.stored_value_bytes == scanned.stored_value_bytes;
+    Ok((!matches).then_some(Corruption::BadFooter))
+}
+
+/// Reads one record from an SSTable of the given format version
+///
+/// Returns `Ok(None)` at a clean end of file and an `InvalidData` error
+/// when the file ends in the middle of a record. Tombstones are returned
+/// with a `None` value. A prefix-compressed record can only be read this
+/// way at a restart point; `SSTableReader` reads any.
+#[cfg(test)]
+pub(crate) fn read_record<R: Read>(
+    reader: &mut R,
+    version: u32,
+) -> std::io::Result<Option<Record>> {
+    let mut key = Vec::new();
+    let Some((seq, value_len)) = read_key_into(reader, version, &mut key)? else {
+        return Ok(None);
+    };
+    if value_len == TOMBSTONE_MARKER {
+        return Ok(Some((key, seq, None)));
+    }
+
+    let mut value = Vec::new();
+    read_field(reader, value_len as usize, &mut value)?;
+
+    Ok(Some((key, seq, Some(value))))
+}
+
+/// Reads a record's key into `key` and returns its sequence number and raw
+/// value length
+///
+/// `key` is resized in place, so a buffer reused across records stops
+/// allocating once it has grown to the longest key. In a prefix-compressed
+/// table it must hold the key of the record before, which the key read
+/// builds on (or anything at a restart point). The reader is left at the
+/// value bytes (none for a tombstone, whose length is
+/// `TOMBSTONE_MARKER`), past the checksum of tables that have one, which
+/// isn't checked. Records from before sequence numbers existed get
+/// sequence 0. Returns `Ok(None)` at a clean end of file.
+pub(crate) fn read_key_into<R: Read>(
+    reader: &mut R,
+    version: u32,
+    key: &mut Vec<u8>,
+) -> std::io::Result<Option<(u64, u32)>> {
+    Ok(read_key(reader, version, key)?.map(|head| (head.seq, head.value_len)))
+}
+
+/// What `read_key` reads of a record besides its key
+struct RecordHead {
+    seq: u64,
+    value_len: u32,
+
+    /// Whether the record is a restart point: one that holds its whole key
+    restart: bool,
+
+    /// The stored checksum, in tables with record checksums
+    checksum: Option<u32>,
+}
+
+/// `read_key_into`, also returning the record's checksum and whether it is
+/// a restart point
+fn read_key<R: Read>(
+    reader: &mut R,
+    version: u32,
+    key: &mut Vec<u8>,
+) -> std::io::Result<Option<RecordHead>> {
+    let mut len_buf = [0u8; 4];
+    let mut filled = 0;
+    while filled < len_buf.len() {
+        match reader.read(&mut len_buf[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(truncated_record()),
+            n => filled += n,
+        }
+    }
+    let mut shared = 0;
+    if version >= PREFIX_FORMAT_VERSION {
+        shared = u32::from_le_bytes(len_buf) as usize;
+        if shared > key.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "SSTable key shares more than the key before it",
+            ));
+        }
+        reader.read_exact(&mut len_buf).map_err(eof_as_truncated)?;
+    }
+    let key_len = u32::from_le_bytes(len_buf) as usize;
+
+    key.truncate(shared);
+    append_field(reader, key_len, key)?;
+
+    let mut seq_buf = [0u8; 8];
+    reader
+        .read_exact(&mut seq_buf[..seq_len(version) as usize])
+        .map_err(eof_as_truncated)?;
+
+    reader.read_exact(&mut len_buf).map_err(eof_as_truncated)?;
+    let value_len = u32::from_le_bytes(len_buf);
+
+    let checksum = if version >= CHECKSUM_FORMAT_VERSION {
+        reader.read_exact(&mut len_buf).map_err(eof_as_truncated)?;
+        Some(u32::from_le_bytes(len_buf))
+    } else {
+        None
+    };
+    Ok(Some(RecordHead {
+        seq: u64::from_le_bytes(seq_buf),
+        value_len,
+        restart: shared == 0,
+        checksum,
+    }))
+}
+
+/// Reads a `len`-byte field into `buf`, replacing its contents
+///
+/// The length comes from the file and may be damaged, so the buffer grows
+/// with the bytes actually read instead of being sized up front: a length
+/// claiming gigabytes in a small file fails as a truncated record rather
+/// than attempting the allocation.
+pub(crate) fn read_field<R: Read>(
+    reader: &mut R,
+    len: usize,
+    buf: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    buf.clear();
+    append_field(reader, len, buf)
+}
+
+/// Like `read_field`, appending to what `buf` holds
+fn append_field<R: Read>(reader: &mut R, len: usize, buf: &mut Vec<u8>) -> std::io::Result<()> {
+    let start = buf.len();
+    reader.take(len as u64).read_to_end(buf)?;
+    if buf.len() - start < len {
+        return Err(truncated_record());
+    }
+    Ok(())
+}
+
+fn truncated_record() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "SSTable ends in the middle of a record",
+    )
+}
+
+fn eof_as_truncated(e: std::io::Error) -> std::io::Error {
+    if e.kind() == std::io::ErrorKind::UnexpectedEof {
+        truncated_record()
+    } else {
+        e
+    }
+}
+
+/// Settings for writing one SSTable
+#[derive(Debug, Clone)]
+pub struct SSTableWriterOptions {
+    /// Number of keys the table is expected to hold (sizes the Bloom filter
+    /// with `FilterSizing::Expected`)
+    pub expected_items: usize,
+
+    /// Whether a Bloom filter is built for the table
+    pub filter_policy: FilterPolicy,
+
+    /// When the Bloom filter is sized (`Expected` by default: the caller
+    /// knows the count)
+    pub filter_sizing: FilterSizing,
+
+    /// Target false positive rate for the table's Bloom filter
+    pub bloom_filter_fpp: f64,
+
+    /// Smallest Bloom filter the table gets
+    pub filter_floor: FilterFloor,
+
+    /// Fsync the file before it is renamed into place
+    pub sync: bool,
+
+    /// Where the fsync is sent
+    pub storage: Arc<dyn Storage>,
+
+    /// How values are stored
+    pub compression: Compression,
+
+    /// Dictionary to compress values with (see
+    /// `compression::Compression::Zstd`); ignored without compression
+    pub dictionary: Option<Vec<u8>>,
+
+    /// When the table's records were written (none recorded by default)
+    pub(crate) write_times: WriteTimes,
+
+    /// Id of the tree the table belongs to (unstamped by default)
+    pub tree_id: Option<TreeId>,
+
+    /// Bytes buffered before they are written to the file
+    pub buffer_bytes: usize,
+
+    /// Where the creation time in the footer comes from
+    pub clock: Arc<dyn Clock>,
+}
+
+impl SSTableWriterOptions {
+    /// Returns options for a table of roughly `expected_items` keys
+    pub fn new(expected_items: usize) -> Self {
+        Self {
+            expected_items,
+            filter_policy: FilterPolicy::Bloom,
+            filter_sizing: FilterSizing::Expected,
+            bloom_filter_fpp: DEFAULT_BLOOM_FILTER_FPP,
+            filter_floor: FilterFloor::default(),
+            sync: true,
+            storage: Arc::new(FsStorage),
+            compression: Compression::None,
+            dictionary: None,
+            write_times: WriteTimes::default(),
+            tree_id: None,
+            buffer_bytes: DEFAULT_WRITE_BUFFER_BYTES,
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+/// Summary of a finished SSTable
+#[derive(Clone)]
+pub struct SSTableMeta {
+    /// Final path of the table
+    pub path: PathBuf,
+
+    /// Number of records written, tombstones included
+    pub num_items: usize,
+
+    /// Tombstones among the records
+    pub tombstones: usize,
+
+    /// Records that are an older version of the record before them
+    pub shadowed: usize,
+
+    /// Smallest key in the table (`None` for an empty table)
+    pub first_key: Option<Vec<u8>>,
+
+    /// Largest key in the table (`None` for an empty table)
+    pub last_key: Option<Vec<u8>>,
+
+    /// Size of the table file in bytes
+    pub file_size: u64,
+
+    /// Bloom filter holding every key of the table (`None` with
+    /// `FilterPolicy::None`)
+    ///
+    /// It is not written to disk by the writer, so the caller can still
+    /// adjust it (for example `set_overlapping_items`) first.
+    pub bloom_filter: Option<BloomFilter>,
+}
+
+/// Streams sorted records into a new SSTable
+///
+/// Records go to a temporary `<path>.tmp` file, and `finish` renames it to
+/// the final path once everything is written (and synced), so a table never
+/// appears half-written under its real name. Dropping a writer without
+/// calling `finish` removes the temporary file.
+///
+/// ```ignore
+/// let mut writer = SSTableWriter::new(&path, SSTableWriterOptions::new(2))?;
+/// writer.add(b"apple", 7, Some(b"red"))?;
+/// writer.add(b"banana", 9, None)?; // tombstone
+/// let meta = writer.finish()?;
+/// ```
+pub struct SSTableWriter {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    /// `None` once the writer has finished
+    writer: Option<BufWriter<File>>,
+    options: SSTableWriterOptions,
+    values: ValueEncoder,
+    filter: TableFilter,
+    /// Counts and key range so far, written to the footer
+    summary: TableSummary,
+    /// Offset the next record is written at
+    position: u64,
+    /// Blocks of the footer's index (`None` for tables without a footer,
+    /// whose keys aren't prefix-compressed either)
+    index: Option<BlockIndex>,
+    /// Keys written since the last restart point
+    keys_since_restart: usize,
+}
+
+impl SSTableWriter {
+    /// Creates the temporary file and writes the format header
+    pub fn new(path: &Path, options: SSTableWriterOptions) -> std::io::Result<Self> {
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+        let filter = match (options.filter_policy, options.filter_sizing) {
+            (FilterPolicy::None, _) => TableFilter::None,
+            (FilterPolicy::Bloom, FilterSizing::Expected) => {
+                TableFilter::Sized(BloomFilter::new_floored(
+                    options.expected_items.max(1),
+                    options.bloom_filter_fpp,
+                    options.filter_floor,
+                )?)
+            }
+            (FilterPolicy::Bloom, FilterSizing::Exact) => TableFilter::Deferred(
+                DeferredBloomFilter::new(options.bloom_filter_fpp)?
+                    .with_floor(options.filter_floor),
+            ),
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&tmp_path)?;
+        let mut writer = BufWriter::with_capacity(options.buffer_bytes, file);
+        let (values, compression_header) =
+            ValueEncoder::new(options.compression, options.dictionary.as_deref())?;
+        if let Some(tree_id) = options.tree_id {
+            writer.write_all(&SSTABLE_MAGIC)?;
+            writer.write_all(&CHECKSUM_FORMAT_VERSION.to_le_bytes())?;
+            writer.write_all(tree_id.as_bytes())?;
+            // Where the records end, filled in by `finish`
+            writer.write_all(&0u64.to_le_bytes())?;
+            let plain = [0u8; 5];
+            writer.write_all(compression_header.as_deref().unwrap_or(&plain))?;
+            options.write_times.write_to(&mut writer)?;
+        } else if !options.write_times.is_empty() {
+            writer.write_all(&SSTABLE_MAGIC)?;
+            writer.write_all(&TIMED_FORMAT_VERSION.to_le_bytes())?;
+            // Codec 0 with an empty dictionary: values are stored as they are
+            let plain = [0u8; 5];
+            writer.write_all(compression_header.as_deref().unwrap_or(&plain))?;
+            options.write_times.write_to(&mut writer)?;
+        } else if let Some(section) = compression_header {
+            writer.write_all(&SSTABLE_MAGIC)?;
+            writer.write_all(&COMPRESSED_FORMAT_VERSION.to_le_bytes())?;
+            writer.write_all(&section)?;
+        } else {
+            write_header(&mut writer)?;
+        }
+
+        let position = writer.stream_position()?;
+        let index = options.tree_id.map(|_| BlockIndex::default());
+        Ok(Self {
+            path: path.to_path_buf(),
+            tmp_path,
+            writer: Some(writer),
+            values,
+            filter,
+            options,
+            summary: TableSummary::empty(UNIX_EPOCH),
+            position,
+            index,
+            keys_since_restart: 0,
+        })
+    }
+
+    /// Appends one record written at sequence `seq` (a `None` value writes
+    /// a tombstone)
+    ///
+    /// Keys must be added in ascending order, and several versions of one
+    /// key newest first; readers rely on it. Debug builds check the key
+    /// order; pairs from outside the tree go through `OrderedRecords`
+    /// first.
+    pub fn add(&mut self, key: &[u8], seq: u64, value: Option<&[u8]>) -> std::io::Result<()> {
+        let writer = self
+            .writer
+            .as_mut()
+            .expect("SSTableWriter used after finish");
+        let previous_key = self.summary.last_key.as_deref();
+        debug_assert!(
+            previous_key.is_none_or(|previous| previous <= key),
+            "SSTableWriter keys out of order: {} then {}",
+            hex_key(previous_key.unwrap_or_default()),
+            hex_key(key)
+        );
+        let new_key = previous_key != Some(key);
+        let raw_len = value.map(|v| v.len() as u64);
+        let value = value.map(|v| self.values.encode(v)).transpose()?;
+        // The key fields, and the checksum of tables that have one
+        let key_bytes = match &mut self.index {
+            Some(index) => {
+                let mut restart = false;
+                if new_key {
+                    restart = index.add_key(key, self.position)
+                        || self.keys_since_restart == RESTART_INTERVAL;
+                    self.keys_since_restart = if restart {
+                        1
+                    } else {
+                        self.keys_since_restart + 1
+                    };
+                }
+                let shared = match (restart, previous_key) {
+                    (false, Some(previous_key)) => shared_prefix_len(previous_key, key),
+                    _ => 0,
+                };
+                write_prefixed_record(writer, key, shared, seq, value.as_deref())?;
+                8 + (key.len() - shared) as u64 + CHECKSUM_LEN
+            }
+            None => {
+                write_record(writer, key, seq, value.as_deref())?;
+                4 + key.len() as u64
+            }
+        };
+        let stored_len = value.as_ref().map(|v| v.len() as u64);
+        self.position += key_bytes + 8 + 4 + stored_len.unwrap_or(0);
+
+        // Tombstones go into the filter too, so reads stop at them
+        match &mut self.filter {
+            TableFilter::None => {}
+            TableFilter::Sized(bloom_filter) => bloom_filter.insert(key),
+            // Older versions of a key follow it; the key is in already
+            TableFilter::Deferred(hashes) => {
+                if new_key {
+                    hashes.insert(key);
+                }
+            }
+        }
+        self.summary.add(key, seq, raw_len.zip(stored_len));
+        Ok(())
+    }
+
+    /// Flushes, syncs and renames the table into place
+    pub fn finish(mut self) -> std::io::Result<SSTableMeta> {
+        let mut writer = self.writer.take().expect("SSTableWriter finished twice");
+        if let Some(index) = &self.index {
+            self.summary.write_span = self.options.write_times.span();
+            self.summary.created_at = self.options.clock.now();
+            self.summary.blocks = index.len() as u32;
+            footer::write_footer(&mut writer, self.position, index, &self.summary)?;
+        }
+        writer.flush()?;
+        if self.index.is_some() {
+            let file = writer.get_mut();
+            file.seek(SeekFrom::Start(RECORDS_END_AT))?;
+            file.write_all(&self.position.to_le_bytes())?;
+        }
+        if self.options.sync {
+            self.options.storage.sync_file(writer.get_ref())?;
+        }
+        let file_size = writer.get_ref().metadata()?.len();
+        drop(writer);
+
+        std::fs::rename(&self.tmp_path, &self.path)?;
+
+        let bloom_filter = match std::mem::replace(&mut self.filter, TableFilter::None) {
+            TableFilter::None => None,
+            // Each added key is one item, so the count is exact
+            TableFilter::Sized(mut bloom_filter) => {
+                bloom_filter.set_num_items(self.summary.entries as usize);
+                Some(bloom_filter)
+            }
+            TableFilter::Deferred(hashes) => Some(hashes.build()),
+        };
+
+        Ok(SSTableMeta {
+            path: self.path.clone(),
+            num_items: self.summary.entries as usize,
+            tombstones: self.summary.tombstones as usize,
+            shadowed: self.summary.shadowed as usize,
+            first_key: self.summary.first_key.take(),
+            last_key: self.summary.last_key.take(),
+            file_size,
+            bloom_filter,
+        })
+    }
+}
+
+/// Pairs from outside the tree, checked to be in strictly ascending key
+/// order before they reach an `SSTableWriter`
+///
+/// Flushes and compactions write keys in order by construction (the
+/// memtable and the merge sort them); a snapshot or another caller's
+/// iterator might not. Keys compare bytewise, the tree's only key order
+/// (see `format::COMPARATOR`), so keys meant to sort as numbers have to
+/// be encoded for it (see `keys`). A key lower than the one before it
+/// yields an `InvalidData` error naming both; a key repeated is settled
+/// by the `DedupPolicy`, one pair behind. Iteration ends after an error.
+pub(crate) struct OrderedRecords<I, V> {
+    records: I,
+    policy: DedupPolicy,
+    /// The pair read last, held back until the next key is known to differ
+    pending: Option<(Vec<u8>, V)>,
+    failed: bool,
+}
+
+impl<I, V> OrderedRecords<I, V>
+where
+    I: Iterator<Item = std::io::Result<(Vec<u8>, V)>>,
+{
+    pub(crate) fn new(records: impl IntoIterator<IntoIter = I>, policy: DedupPolicy) -> Self {
+        Self {
+            records: records.into_iter(),
+            policy,
+            pending: None,
+            failed: false,
+        }
+    }
+}
+
+impl<I, V> Iterator for OrderedRecords<I, V>
+where
+    I: Iterator<Item = std::io::Result<(Vec<u8>, V)>>,
+{
+    type Item = std::io::Result<(Vec<u8>, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            let record = match self.records.next() {
+                None => return self.pending.take().map(Ok),
+                Some(Ok(record)) => record,
+                Some(Err(e)) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            };
+            let Some(pending) = self.pending.take() else {
+                self.pending = Some(record);
+                continue;
+            };
+            let problem = match (pending.0.cmp(&record.0), self.policy) {
+                (std::cmp::Ordering::Less, _) => {
+                    self.pending = Some(record);
+                    return Some(Ok(pending));
+                }
+                (std::cmp::Ordering::Equal, DedupPolicy::LastWins) => {
+                    self.pending = Some(record);
+                    continue;
+                }
+                (std::cmp::Ordering::Equal, DedupPolicy::FirstWins) => {
+                    self.pending = Some(pending);
+                    continue;
+                }
+                (std::cmp::Ordering::Equal, DedupPolicy::Error) => "duplicate key",
+                (std::cmp::Ordering::Greater, _) => "keys out of order",
+            };
+            self.failed = true;
+            return Some(Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{}: {} then {}",
+                    problem,
+                    hex_key(&pending.0),
+                    hex_key(&record.0)
+                ),
+            )));
+        }
+    }
+}
+
+/// A key for error messages: hex, cut after 32 bytes
+fn hex_key(key: &[u8]) -> String {
+    const SHOWN: usize = 32;
+    let mut hex: String = key
+        .iter()
+        .take(SHOWN)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if key.len() > SHOWN {
+        hex.push_str(&format!("... ({} bytes)", key.len()));
+    }
+    hex
+}
+
+/// The Bloom filter an `SSTableWriter` fills
+enum TableFilter {
+    None,
+
+    /// Sized up front (`FilterSizing::Expected`)
+    Sized(BloomFilter),
+
+    /// Built in `finish` (`FilterSizing::Exact`)
+    Deferred(DeferredBloomFilter),
+}
+
+impl Drop for SSTableWriter {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            // Abandoned before finish: don't leave the partial file behind
+            let _ = std::fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+/// Sequential reader over the records of one SSTable
+///
+/// Iterating yields every record in key order, tombstones and older
+/// versions included (a tombstone has a `None` value). Iteration simply
+/// ends at a truncated trailing record; use `try_next` where that has to be
+/// reported as an error.
+///
+/// This is the supported way for code outside the crate (dump tools,
+/// backup validators, format converters) to read a table file without
+/// opening a tree: `open`, `iter`, `get` and `metadata` read every format
+/// version this build knows, from headerless legacy files to
+/// prefix-compressed keys, and go on reading new ones the same way. The
+/// file is only read; a table that belongs to an open tree can be read
+/// alongside it, since tables never change once written.
+///
+/// ```no_run
+/// use lsm_tree::sstable::SSTableReader;
+///
+/// let mut reader = SSTableReader::open("data/sstable_3.db".as_ref())?;
+/// println!("{:?}", reader.metadata()?);
+/// for record in reader.iter() {
+///     let (key, seq, value) = record?;
+///     println!("{:?} @{} = {:?}", key, seq, value);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct SSTableReader {
+    reader: BufReader<TableFile>,
+    path: PathBuf,
+    version: u32,
+    tree_id: Option<TreeId>,
+    records_start: u64,
+    values: ValueDecoder,
+    write_times_at: Option<u64>,
+    index: LazyIndex,
+    /// Key of the last record read, which a prefix-compressed key builds on
+    key: Vec<u8>,
+    /// Checksum of the last record read, in tables with record checksums
+    checksum: Option<u32>,
+    /// Offset of the last restart point read, where reading can start
+    /// again to get back to the records after it
+    restart: u64,
+}
+
+/// How `SSTableReader::open_with_options` opens a table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SSTableReaderOptions {
+    /// Check the whole table before handing out a reader, failing with
+    /// `InvalidData` if it is damaged
+    ///
+    /// This is the check done when a tree is opened (see
+    /// `SSTableReader::verify`): every record is checked against its
+    /// checksum in tables that have them. Reads through the reader don't
+    /// check checksums themselves.
+    pub verify_checksums: bool,
+
+    /// Size of the read buffer
+    pub buffer_bytes: usize,
+}
+
+impl Default for SSTableReaderOptions {
+    fn default() -> Self {
+        Self {
+            verify_checksums: false,
+            buffer_bytes: DEFAULT_READ_BUFFER_BYTES,
+        }
+    }
+}
+
+/// What `SSTableReader::metadata` says about a table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableMetadata {
+    /// Format version of the file (`LEGACY_FORMAT_VERSION` for files
+    /// without a header)
+    pub format_version: u32,
+
+    /// Id of the tree that wrote the table, in stamped tables
+    pub tree_id: Option<TreeId>,
+
+    /// Size of the file in bytes, footer included
+    pub file_bytes: u64,
+
+    /// Counts, key and sequence range and write times (see
+    /// `read_summary`)
+    pub summary: TableSummary,
+}
+
+/// The footer's block index, read the first time a lookup wants it
+enum LazyIndex {
+    /// Not read yet; the records end where the footer starts
+    Unread { records_end: u64 },
+
+    /// Read, and worth seeking with
+    Loaded(BlockIndex),
+
+    /// No footer, a damaged one, or a table of a single block
+    None,
+}
+
+impl SSTableReader {
+    /// Opens a table and checks its header, positioned at the first record
+    ///
+    /// Files declaring a format version newer than this build are rejected
+    /// with `InvalidData`.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Self::open_buffered(path, DEFAULT_READ_BUFFER_BYTES)
+    }
+
+    /// Like `open`, with the buffer size and verification of `options`
+    pub fn open_with_options(path: &Path, options: &SSTableReaderOptions) -> std::io::Result<Self> {
+        let reader = Self::open_buffered(path, options.buffer_bytes)?;
+        if options.verify_checksums
+            && let Some(corruption) = reader.verify()?
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Corruption in SSTable {}: {}", path.display(), corruption),
+            ));
+        }
+        Ok(reader)
+    }
+
+    /// Like `open`, reading through a buffer of `buffer_bytes`
+    pub(crate) fn open_buffered(path: &Path, buffer_bytes: usize) -> std::io::Result<Self> {
+        Self::open_with(path, &FsStorage, buffer_bytes)
+    }
+
+    /// Like `open_buffered`, with the file opened through `storage`
+    pub(crate) fn open_with(
+        path: &Path,
+        storage: &dyn Storage,
+        buffer_bytes: usize,
+    ) -> std::io::Result<Self> {
+        let (reader, format) = read_table_header(storage.open_file(path)?, path, buffer_bytes)?;
+        Ok(Self::from_parts(reader, format, path))
+    }
+
+    fn from_parts(reader: BufReader<TableFile>, format: TableFormat, path: &Path) -> Self {
+        let index = if format.has_footer() {
+            LazyIndex::Unread {
+                records_end: format.records_end,
+            }
+        } else {
+            LazyIndex::None
+        };
+        Self {
+            reader,
+            path: path.to_path_buf(),
+            version: format.version,
+            tree_id: format.tree_id,
+            records_start: format.records_start,
+            values: format.values,
+            write_times_at: format.write_times_at,
+            index,
+            key: Vec::new(),
+            checksum: None,
+            restart: format.records_start,
+        }
+    }
+
+    /// Format version of the table (`LEGACY_FORMAT_VERSION` for old files)
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The table's format version, tree id, size and summary
+    ///
+    /// The summary comes from the footer, or a scan of the file for tables
+    /// without one (see `read_summary`); the reading position is left
+    /// where it was.
+    pub fn metadata(&self) -> std::io::Result<TableMetadata> {
+        Ok(TableMetadata {
+            format_version: self.version,
+            tree_id: self.tree_id,
+            file_bytes: self.reader.get_ref().len(),
+            summary: read_summary(&self.path)?,
+        })
+    }
+
+    /// Checks the whole table (see `check_integrity`): `Ok(None)` for a
+    /// sound table, the first problem found otherwise
+    pub fn verify(&self) -> std::io::Result<Option<Corruption>> {
+        check_integrity(&self.path, DEFAULT_READ_BUFFER_BYTES)
+    }
+
+    /// `verify`, failing with `Cancelled` once `cancel` is cancelled; the
+    /// token is checked before every record
+    pub fn verify_with(&self, cancel: &CancellationToken) -> std::io::Result<Option<Corruption>> {
+        check_integrity_with(&self.path, DEFAULT_READ_BUFFER_BYTES, cancel)
+    }
+
+    /// Every record of the table, from the first, in key order
+    ///
+    /// Tombstones and older versions are included, as when iterating the
+    /// reader itself, but a truncated or undecodable record is yielded as
+    /// an error, after which the iteration ends.
+    pub fn iter(&mut self) -> impl Iterator<Item = std::io::Result<Record>> + '_ {
+        let mut failed = self.rewind().err();
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            if let Some(error) = failed.take() {
+                done = true;
+                return Some(Err(error));
+            }
+            let next = self.try_next().transpose();
+            done = !matches!(next, Some(Ok(_)));
+            next
+        })
+    }
+
+    /// The newest version of every key that isn't deleted, from the first,
+    /// in key order: what a read through the tree would see of this table
+    /// alone (see `LSMTree::read_sstable_entries`)
+    pub fn live_entries(
+        &mut self,
+    ) -> impl Iterator<Item = std::io::Result<(Vec<u8>, Vec<u8>)>> + '_ {
+        let mut previous_key: Option<Vec<u8>> = None;
+        self.iter().filter_map(move |record| {
+            let (key, _, value) = match record {
+                Ok(record) => record,
+                Err(error) => return Some(Err(error)),
+            };
+            // Older versions follow the newest one; only keep the newest
+            if previous_key.as_ref() == Some(&key) {
+                return None;
+            }
+            previous_key = Some(key.clone());
+            value.map(|value| Ok((key, value)))
+        })
+    }
+
+    /// Goes back to the first record
+    fn rewind(&mut self) -> std::io::Result<()> {
+        self.reader.seek(SeekFrom::Start(self.records_start))?;
+        self.restart = self.records_start;
+        self.key.clear();
+        Ok(())
+    }
+
+    /// Reads the table's write times (empty for tables without them),
+    /// leaving the reading position where it was
+    pub(crate) fn write_times(&mut self) -> std::io::Result<WriteTimes> {
+        let Some(at) = self.write_times_at else {
+            return Ok(WriteTimes::default());
+        };
+        let position = self.reader.stream_position()?;
+        self.reader.seek(SeekFrom::Start(at))?;
+        let times = WriteTimes::read_from(&mut self.reader);
+        self.reader.seek(SeekFrom::Start(position))?;
+        times
+    }
+
+    /// Offset of the next record in the file
+    pub(crate) fn position(&mut self) -> std::io::Result<u64> {
+        self.reader.stream_position()
+    }
+
+    /// Reads the next record's key into `self.key` and its checksum into
+    /// `self.checksum`, and returns its sequence number and raw value length
+    /// (see `read_key_into`)
+    fn read_key(&mut self) -> std::io::Result<Option<(u64, u32)>> {
+        let offset = self.reader.stream_position()?;
+        let Some(head) = read_key(&mut self.reader, self.version, &mut self.key)? else {
+            return Ok(None);
+        };
+        if head.restart {
+            self.restart = offset;
+        }
+        self.checksum = head.checksum;
+        Ok(Some((head.seq, head.value_len)))
+    }
+
+    /// Whether the record just read matches its checksum, given its stored
+    /// value bytes (always true in tables without record checksums)
+    fn checksum_matches(&self, seq: u64, value_len: u32, value: &[u8]) -> bool {
+        self.checksum
+            .is_none_or(|stored| stored == record_checksum(&self.key, seq, value_len, value))
+    }
+
+    /// Moves forward to the block of `key`, if the table's block index
+    /// says it starts past the current position
+    ///
+    /// Tables without a footer, or with a damaged one, are scanned from
+    /// where the reader is, as before.
+    fn skip_to_block(&mut self, key: &[u8]) -> std::io::Result<()> {
+        self.load_index()?;
+        let LazyIndex::Loaded(index) = &self.index else {
+            return Ok(());
+        };
+        if let Some(offset) = index.block_for(key)
+            && offset > self.reader.stream_position()?
+        {
+            // Blocks start at a restart point
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.restart = offset;
+        }
+        Ok(())
+    }
+
+    /// Reads the footer's block index, if it hasn't been yet
+    fn load_index(&mut self) -> std::io::Result<()> {
+        if let LazyIndex::Unread { records_end } = self.index {
+            let index = footer::read_footer(self.reader.get_mut(), records_end)?
+                .and_then(|footer| footer.into_index());
+            self.index = match index {
+                Some(index) if index.len() > 1 => LazyIndex::Loaded(index),
+                _ => LazyIndex::None,
+            };
+        }
+        Ok(())
+    }
+
+    /// Where the blocks of the footer's index start, first to last, up to
+    /// the block of `last` (all of them for `None`), and where the records
+    /// after the last of those start (`None` for the end of the records)
+    ///
+    /// A table without a usable index is a single block starting at its
+    /// first record. Records of one key never straddle two blocks (see
+    /// `read_block`).
+    pub(crate) fn blocks_through(
+        &mut self,
+        last: Option<&[u8]>,
+    ) -> std::io::Result<(Vec<u64>, Option<u64>)> {
+        self.load_index()?;
+        let LazyIndex::Loaded(index) = &self.index else {
+            return Ok((vec![self.records_start], None));
+        };
+        let mut blocks: Vec<u64> = index.offsets().collect();
+        if let Some(last) = last {
+            let kept = index
+                .block_for(last)
+                .map_or(0, |offset| blocks.partition_point(|&start| start <= offset));
+            let end = blocks.get(kept).copied();
+            blocks.truncate(kept);
+            return Ok((blocks, end));
+        }
+        Ok((blocks, None))
+    }
+
+    /// Start of the block of the footer's index to look for `key` in: the
+    /// last one whose first key isn't greater than it (`None` for a key
+    /// before every block, or a table without a usable index)
+    pub(crate) fn block_for(&mut self, key: &[u8]) -> std::io::Result<Option<u64>> {
+        self.load_index()?;
+        Ok(match &self.index {
+            LazyIndex::Loaded(index) => index.block_for(key),
+            _ => None,
+        })
+    }
+
+    /// The newest version of every key of the block from `start` to `end`
+    /// (`None` for the end of the records), in key order
+    ///
+    /// `start` must be the start of a block (see `blocks_through`), which
+    /// is a restart point. A damaged record ends the block: the records
+    /// before it are returned.
+    pub(crate) fn read_block(
+        &mut self,
+        start: u64,
+        end: Option<u64>,
+    ) -> std::io::Result<Vec<Record>> {
+        self.reader.seek(SeekFrom::Start(start))?;
+        self.restart = start;
+        let mut entries: Vec<Record> = Vec::new();
+        loop {
+            if let Some(end) = end
+                && self.reader.stream_position()? >= end
+            {
+                break;
+            }
+            let Ok(Some(record)) = self.try_next() else {
+                break;
+            };
+            // Older versions follow the newest one
+            if entries
+                .last()
+                .is_none_or(|(newest, _, _)| *newest != record.0)
+            {
+                entries.push(record);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Reads the next record, failing on a truncated one
+    pub fn try_next(&mut self) -> std::io::Result<Option<Record>> {
+        let Some((seq, value_len)) = self.read_key()? else {
+            return Ok(None);
+        };
+        if value_len == TOMBSTONE_MARKER {
+            return Ok(Some((self.key.clone(), seq, None)));
+        }
+        let mut value = Vec::new();
+        read_field(&mut self.reader, value_len as usize, &mut value)?;
+        let value = self.values.decode(value)?;
+        Ok(Some((self.key.clone(), seq, Some(value))))
+    }
+
+    /// Reads the next key into `key`, stepping over its value
+    ///
+    /// Returns `false` at the end of the table. The value bytes are read
+    /// through rather than seeked past, so a truncated last value is still
+    /// reported as an error; nothing is allocated once `key` is big enough.
+    pub(crate) fn next_key_into(&mut self, key: &mut Vec<u8>) -> std::io::Result<bool> {
+        let Some((_, value_len)) = self.read_key()? else {
+            return Ok(false);
+        };
+        key.clear();
+        key.extend_from_slice(&self.key);
+        if value_len != TOMBSTONE_MARKER {
+            let skipped = std::io::copy(
+                &mut (&mut self.reader).take(value_len as u64),
+                &mut std::io::sink(),
+            )?;
+            if skipped < value_len as u64 {
+                return Err(truncated_record());
+            }
+        }
+        Ok(true)
+    }
+
+    /// Looks up the newest version of a key
+    ///
+    /// Returns `Some(Some(value))` if found, `Some(None)` for a tombstone,
+    /// and `None` if the table doesn't mention the key. The lookup starts
+    /// from the first record, or the block of the key when the footer's
+    /// index says where that is, and stops as soon as it passes the key,
+    /// since records are sorted.
+    ///
+    /// Keys are read into one reused buffer and values of other keys are
+    /// seeked past, so scanning a large table allocates nothing per record.
+    pub fn get(&mut self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        if self.reader.stream_position().ok()? != self.records_start {
+            self.rewind().ok()?;
+        }
+        self.get_at(key, u64::MAX)
+    }
+
+    /// Looks up the newest version of a key written at or before `seq`
+    ///
+    /// Like `get`, but versions newer than `seq` are skipped; `None` means
+    /// the table has no version of the key visible at `seq`. The scan
+    /// starts from the current position.
+    pub(crate) fn get_at(&mut self, key: &[u8], seq: u64) -> Option<Option<Vec<u8>>> {
+        self.get_version_at(key, seq).map(|(_, value)| value)
+    }
+
+    /// Like `get_at`, but also returns the sequence number of the version
+    /// found
+    pub(crate) fn get_version_at(
+        &mut self,
+        key: &[u8],
+        seq: u64,
+    ) -> Option<(u64, Option<Vec<u8>>)> {
+        self.skip_to_block(key).ok()?;
+        loop {
+            let (record_seq, value_len) = self.read_key().ok()??;
+            let ordering = match self.key.as_slice().cmp(key) {
+                // Too new to be visible: treat it like a smaller key
+                std::cmp::Ordering::Equal if record_seq > seq => std::cmp::Ordering::Less,
+                ordering => ordering,
+            };
+            match ordering {
+                std::cmp::Ordering::Less => {
+                    if value_len != TOMBSTONE_MARKER {
+                        self.reader.seek_relative(value_len as i64).ok()?;
+                    }
+                }
+                std::cmp::Ordering::Equal => {
+                    if value_len == TOMBSTONE_MARKER {
+                        return Some((record_seq, None));
+                    }
+                    let mut value = Vec::new();
+                    read_field(&mut self.reader, value_len as usize, &mut value).ok()?;
+                    return Some((record_seq, self.values.decode(value).ok()));
+                }
+                std::cmp::Ordering::Greater => return None,
+            }
+        }
+    }
+
+    /// Finds every record of `key` from the current position on, newest
+    /// first, checking each one as it goes
+    ///
+    /// Records of other keys are skipped without reading their values, and
+    /// the search stops at the first greater key. A record of `key` that
+    /// runs past the end of the file is reported as truncated, and nothing
+    /// after it can be read; one whose sequence isn't below the version
+    /// before it is reported as out of order, and one that doesn't match
+    /// its checksum as such.
+    pub(crate) fn locate(&mut self, key: &[u8]) -> std::io::Result<Vec<LocatedRecord>> {
+        self.skip_to_block(key)?;
+        let mut found: Vec<LocatedRecord> = Vec::new();
+        loop {
+            let offset = self.reader.stream_position()?;
+            let Ok(Some((seq, value_len))) = self.read_key() else {
+                // The end of the table, or a record too damaged to tell
+                // whose it is
+                return Ok(found);
+            };
+            match self.key.as_slice().cmp(key) {
+                std::cmp::Ordering::Less => {
+                    if value_len != TOMBSTONE_MARKER {
+                        self.reader.seek_relative(value_len as i64)?;
+                    }
+                    continue;
+                }
+                std::cmp::Ordering::Greater => return Ok(found),
+                std::cmp::Ordering::Equal => {}
+            }
+
+            let mut record = LocatedRecord {
+                offset,
+                seq,
+                value: None,
+                damage: None,
+            };
+            if found.last().is_some_and(|newer| seq >= newer.seq) {
+                record.damage = Some(Corruption::OutOfOrder { offset });
+            }
+            let mut value = Vec::new();
+            if value_len != TOMBSTONE_MARKER
+                && read_field(&mut self.reader, value_len as usize, &mut value).is_err()
+            {
+                record.damage = Some(Corruption::Truncated { offset });
+                found.push(record);
+                return Ok(found);
+            }
+            if !self.checksum_matches(seq, value_len, &value) {
+                record.damage = Some(Corruption::ChecksumMismatch { offset });
+            } else if value_len != TOMBSTONE_MARKER {
+                match self.values.decode(value) {
+                    Ok(value) => record.value = Some(value),
+                    Err(_) => record.damage = Some(Corruption::Undecodable { offset }),
+                }
+            }
+            found.push(record);
+        }
+    }
+}
+
+impl Iterator for SSTableReader {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().ok().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Writes a record the way builds before format version 2 did
+    #[test]
+    fn test_header_round_trip() {
+        let mut buf = Vec::new();
+        write_header(&mut buf).unwrap();
+        write_record(&mut buf, b"key", 7, Some(b"value")).unwrap();
+        write_record(&mut buf, b"gone", 8, None).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        let version = read_header(&mut cursor, Path::new("mem")).unwrap();
+        assert_eq!(version, SSTABLE_FORMAT_VERSION);
+        assert_eq!(
+            read_record(&mut cursor, version).unwrap(),
+            Some((b"key".to_vec(), 7, Some(b"value".to_vec())))
+        );
+        assert_eq!(
+            read_record(&mut cursor, version).unwrap(),
+            Some((b"gone".to_vec(), 8, None))
+        );
+        assert_eq!(read_record(&mut cursor, version).unwrap(), None);
+    }
+
+    #[test]
+    fn test_absurd_lengths_read_as_truncated() {
+        // Key length, then value length, claiming nearly 4 GiB
+        let mut key_record = 0xFFFF_FFF0u32.to_le_bytes().to_vec();
+        key_record.extend_from_slice(b"short");
+        let mut value_record = Vec::new();
+        write_record(&mut value_record, b"key", 1, Some(b"v")).unwrap();
+        let value_len_at = value_record.len() - 5;
+        value_record[value_len_at..value_len_at + 4].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+
+        for bytes in [key_record, value_record] {
+            let Err(e) = read_record(&mut Cursor::new(bytes), SSTABLE_FORMAT_VERSION) else {
+                panic!("absurd length was read");
+            };
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_legacy_file_without_header() {
+        let mut buf = Vec::new();
+        write_unsequenced_record(&mut buf, b"key", Some(b"value"));
+
+        let mut cursor = Cursor::new(buf);
+        let version = read_header(&mut cursor, Path::new("mem")).unwrap();
+        assert_eq!(version, LEGACY_FORMAT_VERSION);
+        assert_eq!(
+            read_record(&mut cursor, version).unwrap(),
+            Some((b"key".to_vec(), 0, Some(b"value".to_vec())))
+        );
+
+        // Empty and very short files are legacy too
+        let mut empty = Cursor::new(Vec::new());
+        assert_eq!(
+            read_header(&mut empty, Path::new("mem")).unwrap(),
+            LEGACY_FORMAT_VERSION
+        );
+    }
+
+    #[test]
+    fn test_future_version_is_rejected() {
+        let mut buf = SSTABLE_MAGIC.to_vec();
+        buf.extend_from_slice(&(NEWEST_FORMAT_VERSION + 1).to_le_bytes());
+
+        let err = read_header(&mut Cursor::new(buf), Path::new("future.db")).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("future.db"));
+    }
+
+    #[test]
+    fn test_writer_and_reader_round_trip() {
+        let dir = PathBuf::from("./test_sstable_writer");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sstable_0.db");
+
+        let mut writer = SSTableWriter::new(&path, SSTableWriterOptions::new(3)).unwrap();
+        writer.add(b"apple", 1, Some(b"red")).unwrap();
+        writer.add(b"banana", 2, None).unwrap();
+        writer.add(b"cherry", 3, Some(b"")).unwrap();
+
+        // Nothing is visible under the final name until finish
+        assert!(!path.exists());
+        let meta = writer.finish().unwrap();
+        assert!(path.exists());
+        assert!(!dir.join("sstable_0.db.tmp").exists());
+
+        assert_eq!(meta.num_items, 3);
+        assert_eq!((meta.tombstones, meta.shadowed), (1, 0));
+        assert_eq!(meta.first_key.as_deref(), Some(&b"apple"[..]));
+        assert_eq!(meta.last_key.as_deref(), Some(&b"cherry"[..]));
+        assert_eq!(meta.file_size, std::fs::metadata(&path).unwrap().len());
+        let bloom_filter = meta.bloom_filter.unwrap();
+        assert_eq!(bloom_filter.len(), 3);
+        assert!(bloom_filter.might_contain(b"banana"));
+
+        let reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.version(), SSTABLE_FORMAT_VERSION);
+        let records: Vec<_> = reader.collect();
+        assert_eq!(
+            records,
+            vec![
+                (b"apple".to_vec(), 1, Some(b"red".to_vec())),
+                (b"banana".to_vec(), 2, None),
+                (b"cherry".to_vec(), 3, Some(Vec::new())),
+            ]
+        );
+
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.get(b"banana"), Some(None));
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.get(b"blueberry"), None);
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.get(b"cherry"), Some(Some(Vec::new())));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_get_at_picks_the_version_visible_at_a_sequence() {
+        let dir = PathBuf::from("./test_sstable_versions");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sstable_0.db");
+
+        let mut writer = SSTableWriter::new(&path, SSTableWriterOptions::new(2)).unwrap();
+        writer.add(b"a", 1, Some(b"a1")).unwrap();
+        writer.add(b"key", 9, None).unwrap();
+        writer.add(b"key", 6, Some(b"v6")).unwrap();
+        writer.add(b"key", 3, Some(b"v3")).unwrap();
+        writer.add(b"z", 2, Some(b"z2")).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            None
+        );
+
+        let get_at = |key: &[u8], seq| SSTableReader::open(&path).unwrap().get_at(key, seq);
+        assert_eq!(get_at(b"key", 2), None);
+        assert_eq!(get_at(b"key", 3), Some(Some(b"v3".to_vec())));
+        assert_eq!(get_at(b"key", 8), Some(Some(b"v6".to_vec())));
+        assert_eq!(get_at(b"key", 9), Some(None));
+        assert_eq!(get_at(b"z", 5), Some(Some(b"z2".to_vec())));
+        assert_eq!(SSTableReader::open(&path).unwrap().get(b"key"), Some(None));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_write_times_section() {
+        let dir = PathBuf::from("./test_sstable_write_times");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sstable_0.db");
+
+        let written = std::time::UNIX_EPOCH + std::time::Duration::from_millis(42_000);
+        let mut options = SSTableWriterOptions::new(2);
+        options.write_times.record(1, 2, written);
+        let mut writer = SSTableWriter::new(&path, options).unwrap();
+        writer.add(b"a", 1, Some(b"one")).unwrap();
+        writer.add(b"b", 2, None).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            None
+        );
+
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.version(), TIMED_FORMAT_VERSION);
+        assert_eq!(reader.get_version_at(b"b", u64::MAX), Some((2, None)));
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.write_times().unwrap().time_of(1), Some(written));
+        // Reading the times doesn't move the reader off the records
+        assert_eq!(reader.get(b"a"), Some(Some(b"one".to_vec())));
+
+        // A range count running past the end of the file is corruption
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[13..17].copy_from_slice(&1000u32.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        let Err(e) = SSTableReader::open(&path) else {
+            panic!("damaged write times section was read");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_footer_summary_matches_a_full_scan() {
+        let dir = PathBuf::from("./test_sstable_footer");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sstable_0.db");
+
+        let written = UNIX_EPOCH + std::time::Duration::from_millis(42_000);
+        let mut options = SSTableWriterOptions::new(500);
+        options.tree_id = Some(TreeId::from_bytes([7; 16]));
+        options.clock = Arc::new(crate::testing::ManualClock::new(99_000));
+        options.write_times.record(1, 2000, written);
+        let mut writer = SSTableWriter::new(&path, options).unwrap();
+        // Enough records for several blocks, with tombstones and versions
+        for i in 0..500u64 {
+            let key = format!("key{:04}", i).into_bytes();
+            if i % 7 == 0 {
+                writer.add(&key, 1000 + i, None).unwrap();
+            } else {
+                writer.add(&key, 1000 + i, Some(&[b'v'; 40])).unwrap();
+            }
+            if i % 50 == 0 {
+                writer.add(&key, i + 1, Some(b"older")).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+        assert_eq!(format_version(&path).unwrap(), CHECKSUM_FORMAT_VERSION);
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            None
+        );
+
+        let summary = read_summary(&path).unwrap();
+        assert!(summary.from_footer);
+        assert!(summary.blocks > 1);
+        assert_eq!(
+            summary.created_at,
+            UNIX_EPOCH + std::time::Duration::from_secs(99)
+        );
+        assert_eq!((summary.entries, summary.tombstones), (510, 72));
+        assert_eq!(summary.shadowed, 10);
+        assert_eq!((summary.min_sequence, summary.max_sequence), (1, 1499));
+        assert_eq!(summary.write_span, Some((written, written)));
+        assert_eq!(
+            summary.expiry_span(std::time::Duration::from_secs(1)),
+            Some((
+                written + std::time::Duration::from_secs(1),
+                written + std::time::Duration::from_secs(1)
+            ))
+        );
+        // Lookups seek with the block index and still see every version
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.get(b"key0450"), Some(Some(vec![b'v'; 40])));
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(
+            reader.get_at(b"key0450", 500),
+            Some(Some(b"older".to_vec()))
+        );
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.locate(b"key0300").unwrap().len(), 2);
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.get(b"key0301a"), None);
+        assert_eq!(SSTableReader::open(&path).unwrap().count(), 510);
+
+        // A damaged trailer: the records still read, the summary comes
+        // from a scan, and the integrity check names the footer
+        let mut bytes = std::fs::read(&path).unwrap();
+        let magic_at = bytes.len() - 4;
+        bytes[magic_at] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+        let scanned = read_summary(&path).unwrap();
+        assert!(!scanned.from_footer);
+        assert_eq!(
+            TableSummary {
+                created_at: scanned.created_at,
+                blocks: 0,
+                from_footer: false,
+                ..summary
+            },
+            scanned
+        );
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.get(b"key0450"), Some(Some(vec![b'v'; 40])));
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            Some(Corruption::BadFooter)
+        );
+
+        // A changed stats block fails the checksum
+        bytes[magic_at] ^= 0xFF;
+        let stats_at = bytes.len() - 28;
+        let stats_offset = u64::from_le_bytes(bytes[stats_at..stats_at + 8].try_into().unwrap());
+        bytes[stats_offset as usize] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(!read_summary(&path).unwrap().from_footer);
+
+        // Tables without a footer are scanned
+        let plain = dir.join("sstable_1.db");
+        let mut writer = SSTableWriter::new(&plain, SSTableWriterOptions::new(1)).unwrap();
+        writer.add(b"a", 3, Some(b"one")).unwrap();
+        writer.finish().unwrap();
+        let summary = read_summary(&plain).unwrap();
+        assert!(!summary.from_footer);
+        assert_eq!(summary.entries, 1);
+        assert_eq!(summary.raw_value_bytes, 3);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_abandoned_writer_leaves_no_files() {
+        let dir = PathBuf::from("./test_sstable_writer_abandoned");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sstable_0.db");
+
+        let mut writer = SSTableWriter::new(&path, SSTableWriterOptions::new(1)).unwrap();
+        writer.add(b"key", 1, Some(b"value")).unwrap();
+        drop(writer);
+
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_check_integrity_detects_corruption() {
+        let dir = PathBuf::from("./test_sstable_integrity");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sstable_0.db");
+
+        let mut valid = Vec::new();
+        write_header(&mut valid).unwrap();
+        write_record(&mut valid, b"a", 1, Some(b"1")).unwrap();
+        write_record(&mut valid, b"b", 2, None).unwrap();
+        write_record(&mut valid, b"c", 3, Some(b"333")).unwrap();
+        std::fs::write(&path, &valid).unwrap();
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            None
+        );
+
+        std::fs::write(&path, []).unwrap();
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            Some(Corruption::Empty)
+        );
+
+        // Cut the last value short: the third record starts at 8 + 18 + 17
+        std::fs::write(&path, &valid[..valid.len() - 1]).unwrap();
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            Some(Corruption::Truncated { offset: 43 })
+        );
+
+        let mut unordered = Vec::new();
+        write_header(&mut unordered).unwrap();
+        write_record(&mut unordered, b"b", 1, Some(b"1")).unwrap();
+        write_record(&mut unordered, b"a", 2, Some(b"2")).unwrap();
+        std::fs::write(&path, &unordered).unwrap();
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            Some(Corruption::OutOfOrder { offset: 26 })
+        );
+
+        // Versions of one key must go from newest to oldest
+        let mut versions = Vec::new();
+        write_header(&mut versions).unwrap();
+        write_record(&mut versions, b"a", 5, Some(b"new")).unwrap();
+        write_record(&mut versions, b"a", 2, Some(b"old")).unwrap();
+        std::fs::write(&path, &versions).unwrap();
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            None
+        );
+        write_record(&mut versions, b"a", 2, None).unwrap();
+        std::fs::write(&path, &versions).unwrap();
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            Some(Corruption::OutOfOrder { offset: 48 })
+        );
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_record_checksums_catch_a_flipped_value_byte() {
+        let dir = PathBuf::from("./test_sstable_checksums");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sstable_0.db");
+
+        let mut options = SSTableWriterOptions::new(3);
+        options.tree_id = Some(TreeId::from_bytes([5; 16]));
+        let mut writer = SSTableWriter::new(&path, options).unwrap();
+        writer.add(b"apple", 9, Some(b"red")).unwrap();
+        writer.add(b"apple", 4, Some(b"green")).unwrap();
+        writer.add(b"banana", 7, None).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(format_version(&path).unwrap(), CHECKSUM_FORMAT_VERSION);
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            None
+        );
+        let mut reader = SSTableReader::open(&path).unwrap();
+        let records = reader.locate(b"apple").unwrap();
+        assert!(records.iter().all(|record| record.damage.is_none()));
+        let newest = records[0].offset;
+        let older = records[1].offset;
+
+        // "red" is the last field of the newest record: shared, suffix
+        // length, suffix, sequence, value length, checksum, value
+        let mut bytes = std::fs::read(&path).unwrap();
+        let value_at = (newest + 4 + 4 + 5 + 8 + 4 + 4) as usize;
+        assert_eq!(&bytes[value_at..value_at + 3], b"red");
+        bytes[value_at] ^= 0x20;
+        std::fs::write(&path, &bytes).unwrap();
+
+        assert_eq!(
+            check_integrity(&path, DEFAULT_READ_BUFFER_BYTES).unwrap(),
+            Some(Corruption::ChecksumMismatch { offset: newest })
+        );
+        // Only the damaged record is flagged; its older version is intact
+        let mut reader = SSTableReader::open(&path).unwrap();
+        let records = reader.locate(b"apple").unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| (record.offset, record.damage.clone(), record.value.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    newest,
+                    Some(Corruption::ChecksumMismatch { offset: newest }),
+                    None
+                ),
+                (older, None, Some(b"green".to_vec())),
+            ]
+        );
+        // Plain reads don't check checksums
+        assert_eq!(
+            SSTableReader::open(&path).unwrap().get(b"apple"),
+            Some(Some(b"Red".to_vec()))
+        );
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_cancelled_verify_fails_without_a_verdict() {
+        let dir = PathBuf::from("./test_sstable_cancelled_verify");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sstable_0.db");
+
+        let mut writer = SSTableWriter::new(&path, SSTableWriterOptions::new(50)).unwrap();
+        for i in 0..50u32 {
+            writer.add(&i.to_be_bytes(), 1, Some(b"value")).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = SSTableReader::open(&path).unwrap();
+        let token = CancellationToken::new();
+        assert_eq!(reader.verify_with(&token).unwrap(), None);
+        token.cancel();
+        let err = reader.verify_with(&token).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+        assert_eq!(crate::Cancelled::from_io(&err).unwrap().entries, 0);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_prefix_compressed_keys_round_trip() {
+        let dir = PathBuf::from("./test_sstable_prefix_keys");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A small LCG, so the random keys need no random number crate
+        let mut state = 7u64;
+        let mut next = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            state >> 11
+        };
+        let mut random: Vec<Vec<u8>> = (0..2000)
+            .map(|_| (0..next() % 24 + 1).map(|_| next() as u8).collect())
+            .collect();
+        random.sort();
+        random.dedup();
+        let shared: Vec<Vec<u8>> = (0..2000)
+            .map(|i| format!("tenant:acme/user:{:06}", i).into_bytes())
+            .collect();
+
+        for (name, keys) in [("shared", &shared), ("random", &random)] {
+            // The same records as version 8, and with whole keys as version 2
+            let prefixed = dir.join(format!("{}_8.db", name));
+            let mut options = SSTableWriterOptions::new(keys.len());
+            options.tree_id = Some(TreeId::from_bytes([3; 16]));
+            let mut writer = SSTableWriter::new(&prefixed, options).unwrap();
+            let whole = dir.join(format!("{}_2.db", name));
+            let mut plain =
+                SSTableWriter::new(&whole, SSTableWriterOptions::new(keys.len())).unwrap();
+            let mut records = Vec::new();
+            for (i, key) in keys.iter().enumerate() {
+                let seq = 10_000 + i as u64;
+                let value = (i % 9 != 0).then(|| format!("v{}", i).into_bytes());
+                records.push((key.clone(), seq, value.clone()));
+                // Every 25th key keeps an older version
+                if i % 25 == 0 {
+                    records.push((key.clone(), i as u64, Some(b"old".to_vec())));
+                }
+            }
+            for (key, seq, value) in &records {
+                writer.add(key, *seq, value.as_deref()).unwrap();
+                plain.add(key, *seq, value.as_deref()).unwrap();
+            }
+            writer.finish().unwrap();
+            plain.finish().unwrap();
+            assert_eq!(format_version(&prefixed).unwrap(), CHECKSUM_FORMAT_VERSION);
+            assert_eq!(check_integrity(&prefixed, 4096).unwrap(), None, "{}", name);
+            assert_eq!(
+                SSTableReader::open(&prefixed).unwrap().collect::<Vec<_>>(),
+                records
+            );
+            assert_eq!(
+                read_summary(&prefixed).unwrap().entries,
+                records.len() as u64
+            );
+
+            let (_, format) = open_reader(&prefixed, 4096).unwrap();
+            // Without the checksums, which version 2 records don't have
+            let prefixed_bytes =
+                format.records_end - format.records_start - CHECKSUM_LEN * records.len() as u64;
+            let whole_bytes = std::fs::metadata(&whole).unwrap().len() - HEADER_LEN;
+            if name == "shared" {
+                assert!(
+                    prefixed_bytes < whole_bytes * 7 / 10,
+                    "{} vs {}",
+                    prefixed_bytes,
+                    whole_bytes
+                );
+            } else {
+                // No more than the shared length per record
+                assert!(prefixed_bytes <= whole_bytes + 4 * records.len() as u64);
+            }
+
+            // Every key is found from a fresh reader, which starts at the
+            // restart point of its block, and so is every version of it
+            let summary = read_summary(&prefixed).unwrap();
+            assert!(summary.blocks > 1, "{}", name);
+            for (key, seq, value) in &records {
+                let mut reader = SSTableReader::open(&prefixed).unwrap();
+                assert_eq!(reader.get_at(key, *seq), Some(value.clone()));
+            }
+
+            // Blocks read last to first, each from its own restart point,
+            // hold the newest version of every key
+            let mut reader = SSTableReader::open(&prefixed).unwrap();
+            let (blocks, _) = reader.blocks_through(None).unwrap();
+            assert_eq!(blocks.len(), summary.blocks as usize);
+            let mut newest: Vec<Record> = Vec::new();
+            for (i, start) in blocks.iter().enumerate().rev() {
+                let block = reader.read_block(*start, blocks.get(i + 1).copied());
+                newest.splice(0..0, block.unwrap());
+            }
+            let mut expected = records.clone();
+            expected.dedup_by(|older, newer| older.0 == newer.0);
+            assert_eq!(newest, expected);
+        }
+
+        // A key claiming more shared bytes than the key before it has
+        let path = dir.join("shared_8.db");
+        let (_, format) = open_reader(&path, 4096).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        let first = format.records_start as usize;
+        bytes[first..first + 4].copy_from_slice(&5u32.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(
+            check_integrity(&path, 4096).unwrap(),
+            Some(Corruption::BadKeyPrefix {
+                offset: format.records_start
+            })
+        );
+        assert_eq!(SSTableReader::open(&path).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_ordered_records_settle_repeated_keys_by_policy() {
+        let pairs = |keys: &[&[u8]]| -> Vec<std::io::Result<(Vec<u8>, usize)>> {
+            keys.iter()
+                .enumerate()
+                .map(|(i, k)| Ok((k.to_vec(), i)))
+                .collect()
+        };
+        let ordered = |keys: &[&[u8]], policy| {
+            OrderedRecords::new(pairs(keys), policy).collect::<std::io::Result<Vec<_>>>()
+        };
+        let keys: [&[u8]; 5] = [b"a", b"b", b"b", b"b", b"c"];
+
+        let e = ordered(&keys, DedupPolicy::Error).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "duplicate key: 62 then 62");
+        let values = |records: Vec<(Vec<u8>, usize)>| -> Vec<usize> {
+            records.into_iter().map(|(_, i)| i).collect()
+        };
+        let last = ordered(&keys, DedupPolicy::LastWins).unwrap();
+        assert_eq!(values(last), [0, 3, 4]);
+        let first = ordered(&keys, DedupPolicy::FirstWins).unwrap();
+        assert_eq!(values(first), [0, 1, 4]);
+
+        // Out of order fails whatever the policy, with both keys in hex,
+        // long ones cut short
+        let long = [0xabu8; 40];
+        for policy in [DedupPolicy::LastWins, DedupPolicy::FirstWins] {
+            let e = ordered(&[b"a", &long, b"b"], policy).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!(
+                    "keys out of order: {}... (40 bytes) then 62",
+                    "ab".repeat(32)
+                )
+            );
+        }
+
+        // The order is bytewise: numbers sort as numbers only when encoded
+        // for it, so little-endian ones are turned away
+        let le: Vec<[u8; 8]> = [1u64, 256].iter().map(|n| n.to_le_bytes()).collect();
+        assert!(ordered(&[&le[0], &le[1]], DedupPolicy::Error).is_err());
+        let be: Vec<[u8; 8]> = [1u64, 256].map(crate::keys::u64_be::encode).to_vec();
+        assert!(ordered(&[&be[0], &be[1]], DedupPolicy::Error).is_ok());
+
+        // Errors from the source end the iteration too
+        let mut failing = pairs(&[b"a"]);
+        failing.push(Err(std::io::Error::other("source failed")));
+        failing.extend(pairs(&[b"b"]));
+        let mut records = OrderedRecords::new(failing, DedupPolicy::Error);
+        assert!(records.next().unwrap().is_err());
+        assert!(records.next().is_none());
+    }
+}
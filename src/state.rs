@@ -0,0 +1,259 @@
+/// Read snapshots of the tree's data (`TreeState`)
+///
+/// Reads through `&LSMTree` can't overlap a flush or compaction, since
+/// those take `&mut self`. Behind a lock (`SharedLSMTree`) that would mean
+/// holding the read lock for the whole of a long scan and stalling every
+/// writer, so such reads capture a `TreeState` instead and drop the lock
+/// right away. The state holds everything the read looks at:
+///
+/// - the memtable, shared: the tree copies it on its next write while a
+///   state still holds it, and starts a fresh one on flush
+/// - the SSTable list, newest first, and the Bloom filters that were in
+///   memory (tables whose filter was evicted are probed directly)
+///
+/// A compaction that replaces tables while states still read them must
+/// not delete the files under them. Live states count as users of their
+/// tables (`TableRefs`); a table the tree drops while it has users is only
+/// marked obsolete, and its files are deleted when the last state using it
+/// goes away. Should the process die first, the files stay behind, listed
+/// by no manifest.
+///
+/// Example:
+/// ```ignore
+/// let state = shared.read_state(); // the lock is only held here
+/// for (key, value) in state.range("user:".."user;") { /* ... */ }
+/// ```
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::bloom::BloomFilter;
+use crate::iter::{self, KeysIter, MergeIter, RangeIter};
+use crate::memtable::Memtable;
+use crate::sstable::SSTableReader;
+use crate::storage::Storage;
+
+/// Users of each SSTable, and the tables waiting for their last user
+#[derive(Debug, Default)]
+struct RefCounts {
+    users: HashMap<PathBuf, usize>,
+    obsolete: HashSet<PathBuf>,
+}
+
+/// Shared registry of the SSTables live `TreeState`s read from
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TableRefs {
+    inner: Arc<Mutex<RefCounts>>,
+}
+
+impl TableRefs {
+    fn lock(&self) -> std::sync::MutexGuard<'_, RefCounts> {
+        // The counts are updated in single steps, so a panic elsewhere
+        // can't leave them half changed
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn acquire(&self, paths: &[PathBuf]) {
+        let mut counts = self.lock();
+        for path in paths {
+            *counts.users.entry(path.clone()).or_default() += 1;
+        }
+    }
+
+    /// Drops one use of each table, deleting obsolete tables nobody uses
+    /// any more (errors are ignored; there is no caller to report them to)
+    fn release(&self, paths: &[PathBuf]) {
+        let mut unused = Vec::new();
+        {
+            let mut counts = self.lock();
+            for path in paths {
+                let Some(users) = counts.users.get_mut(path) else {
+                    continue;
+                };
+                *users -= 1;
+                if *users == 0 {
+                    counts.users.remove(path);
+                    if counts.obsolete.remove(path) {
+                        unused.push(path);
+                    }
+                }
+            }
+        }
+        for path in unused {
+            let _ = remove_table_files(path);
+        }
+    }
+
+    /// Deletes a table that left the tree, or defers it until no state
+    /// reads from it
+    pub(crate) fn remove_when_unused(&self, path: &Path) -> std::io::Result<()> {
+        {
+            let mut counts = self.lock();
+            if counts.users.contains_key(path) {
+                counts.obsolete.insert(path.to_path_buf());
+                return Ok(());
+            }
+        }
+        remove_table_files(path)
+    }
+
+    /// Whether a live state reads from the table
+    pub(crate) fn is_used(&self, path: &Path) -> bool {
+        self.lock().users.contains_key(path)
+    }
+
+    /// Number of dropped tables whose deletion waits for a state
+    pub(crate) fn deferred(&self) -> usize {
+        self.lock().obsolete.len()
+    }
+}
+
+/// Deletes an SSTable and its Bloom filter, which may not exist
+fn remove_table_files(path: &Path) -> std::io::Result<()> {
+    std::fs::remove_file(path)?;
+    let _ = std::fs::remove_file(path.with_extension("bloom"));
+    Ok(())
+}
+
+/// Immutable view of the tree's data as of one moment
+/// (see `LSMTree::read_state`)
+///
+/// Reads answer exactly as the tree did when the state was taken, whatever
+/// flushes and compactions happen since. Dropping the state releases its
+/// tables.
+pub struct TreeState {
+    memtable: Arc<Memtable>,
+
+    /// SSTable paths, newest first
+    tables: Vec<PathBuf>,
+
+    /// Filters of `tables` that were in memory
+    filters: Vec<Option<Arc<BloomFilter>>>,
+
+    storage: Arc<dyn Storage>,
+
+    /// `LSMConfig::read_buffer_bytes` of the tree
+    read_buffer_bytes: usize,
+
+    refs: TableRefs,
+}
+
+impl TreeState {
+    pub(crate) fn new(
+        memtable: Arc<Memtable>,
+        tables: Vec<PathBuf>,
+        filters: Vec<Option<Arc<BloomFilter>>>,
+        storage: Arc<dyn Storage>,
+        read_buffer_bytes: usize,
+        refs: TableRefs,
+    ) -> Self {
+        refs.acquire(&tables);
+        Self {
+            memtable,
+            tables,
+            filters,
+            storage,
+            read_buffer_bytes,
+            refs,
+        }
+    }
+
+    /// Looks up a key
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(value) = self.memtable.get(key) {
+            return value;
+        }
+        for (path, filter) in self.tables.iter().zip(&self.filters) {
+            if filter.as_ref().is_some_and(|bf| !bf.might_contain(key)) {
+                continue;
+            }
+            let found =
+                SSTableReader::open_with(path, self.storage.as_ref(), self.read_buffer_bytes)
+                    .ok()
+                    .and_then(|mut reader| reader.get(key));
+            if let Some(value) = found {
+                return value;
+            }
+        }
+        None
+    }
+
+    /// Looks up several keys, all against this same state
+    pub fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Option<Vec<u8>>> {
+        keys.iter().map(|key| self.get(key.as_ref())).collect()
+    }
+
+    /// Returns an iterator over live key-value pairs in `range`, in key
+    /// order (see `LSMTree::range`)
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> RangeIter<'_> {
+        RangeIter::new(MergeIter::new(
+            &self.memtable,
+            &self.tables,
+            iter::to_key_range(&range),
+            false,
+            self.read_buffer_bytes,
+        ))
+    }
+
+    /// Returns an iterator over live keys in `range`, in key order (see
+    /// `LSMTree::keys`)
+    pub fn keys<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> KeysIter<'_> {
+        KeysIter::new(MergeIter::new(
+            &self.memtable,
+            &self.tables,
+            iter::to_key_range(&range),
+            true,
+            self.read_buffer_bytes,
+        ))
+    }
+
+    /// Number of SSTables the state reads from
+    pub fn sstable_count(&self) -> usize {
+        self.tables.len()
+    }
+}
+
+impl Drop for TreeState {
+    fn drop(&mut self) {
+        self.refs.release(&self.tables);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_removal_waits_for_last_user() {
+        let dir = PathBuf::from("./test_state_table_refs");
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let table = dir.join("sstable_0.db");
+        fs::write(&table, b"table").unwrap();
+        fs::write(table.with_extension("bloom"), b"filter").unwrap();
+
+        let refs = TableRefs::default();
+        let paths = vec![table.clone()];
+        refs.acquire(&paths);
+        refs.acquire(&paths);
+        refs.remove_when_unused(&table).unwrap();
+        assert!(table.exists());
+        assert_eq!(refs.deferred(), 1);
+
+        refs.release(&paths);
+        assert!(table.exists());
+        refs.release(&paths);
+        assert!(!table.exists());
+        assert!(!table.with_extension("bloom").exists());
+        assert_eq!(refs.deferred(), 0);
+
+        // Without users the files go right away
+        fs::write(&table, b"table").unwrap();
+        refs.remove_when_unused(&table).unwrap();
+        assert!(!table.exists());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,140 @@
+/// Storage abstraction for durability calls
+///
+/// Writing bytes to a file only hands them to the operating system; they sit
+/// in the OS page cache until the OS decides to write them out. To survive a
+/// power failure, a database has to explicitly ask for the data to reach the
+/// disk (`fsync`). For a brand-new file that is not enough either: the
+/// directory entry pointing at the file must be synced too, or the file may
+/// vanish after a crash even though its contents were synced.
+///
+/// The LSM tree routes these calls through the `Storage` trait instead of
+/// calling the OS directly. The default `FsStorage` just forwards them, while
+/// `CountingStorage` records how many calls were made, which lets tests (and
+/// curious users) check that data is actually being made durable. Lookups
+/// open their SSTables through it too, so the same counters show how often
+/// a get has to go to disk, and the WAL appends its entries through it, so
+/// tests can make them fail (`testing::FaultyStorage`).
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Durability operations used by the tree
+pub trait Storage: Send + Sync + Debug {
+    /// Forces a file's contents and metadata to disk
+    fn sync_file(&self, file: &File) -> std::io::Result<()>;
+
+    /// Forces a directory's entries (created, renamed, deleted files) to disk
+    fn sync_dir(&self, dir: &Path) -> std::io::Result<()>;
+
+    /// Opens an SSTable for a point lookup
+    fn open_file(&self, path: &Path) -> std::io::Result<File> {
+        File::open(path)
+    }
+
+    /// Appends one encoded entry to the WAL file
+    ///
+    /// May fail after writing part of `buf`; the tree then cuts the log
+    /// back to where the write started.
+    fn append(&self, file: &File, buf: &[u8]) -> std::io::Result<()> {
+        let mut file = file;
+        file.write_all(buf)
+    }
+}
+
+/// Storage backed directly by the local filesystem
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FsStorage;
+
+impl Storage for FsStorage {
+    fn sync_file(&self, file: &File) -> std::io::Result<()> {
+        file.sync_all()
+    }
+
+    fn sync_dir(&self, dir: &Path) -> std::io::Result<()> {
+        // Directories can be opened and synced on Unix. Windows doesn't
+        // allow it and makes directory updates durable on its own.
+        #[cfg(unix)]
+        {
+            File::open(dir)?.sync_all()
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = dir;
+            Ok(())
+        }
+    }
+}
+
+/// Storage wrapper that counts durability calls before forwarding them
+///
+/// Clones share the same counters, so a test can keep one handle and give
+/// another to the tree.
+#[derive(Debug, Clone)]
+pub struct CountingStorage {
+    inner: Arc<dyn Storage>,
+    file_syncs: Arc<AtomicU64>,
+    dir_syncs: Arc<AtomicU64>,
+    file_opens: Arc<AtomicU64>,
+}
+
+impl CountingStorage {
+    /// Wraps the real filesystem
+    pub fn new() -> Self {
+        Self::wrap(Arc::new(FsStorage))
+    }
+
+    /// Wraps another storage implementation
+    pub fn wrap(inner: Arc<dyn Storage>) -> Self {
+        Self {
+            inner,
+            file_syncs: Arc::new(AtomicU64::new(0)),
+            dir_syncs: Arc::new(AtomicU64::new(0)),
+            file_opens: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Number of `sync_file` calls so far
+    pub fn file_syncs(&self) -> u64 {
+        self.file_syncs.load(Ordering::SeqCst)
+    }
+
+    /// Number of `sync_dir` calls so far
+    pub fn dir_syncs(&self) -> u64 {
+        self.dir_syncs.load(Ordering::SeqCst)
+    }
+
+    /// Number of `open_file` calls so far
+    pub fn file_opens(&self) -> u64 {
+        self.file_opens.load(Ordering::SeqCst)
+    }
+}
+
+impl Default for CountingStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Storage for CountingStorage {
+    fn sync_file(&self, file: &File) -> std::io::Result<()> {
+        self.file_syncs.fetch_add(1, Ordering::SeqCst);
+        self.inner.sync_file(file)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> std::io::Result<()> {
+        self.dir_syncs.fetch_add(1, Ordering::SeqCst);
+        self.inner.sync_dir(dir)
+    }
+
+    fn open_file(&self, path: &Path) -> std::io::Result<File> {
+        self.file_opens.fetch_add(1, Ordering::SeqCst);
+        self.inner.open_file(path)
+    }
+
+    fn append(&self, file: &File, buf: &[u8]) -> std::io::Result<()> {
+        self.inner.append(file, buf)
+    }
+}
@@ -0,0 +1,226 @@
+/// Prefix-scoped views of a tree
+///
+/// A `Subspace` prepends its prefix to every key it is given and strips it
+/// from every key it returns, so code working on one kind of record doesn't
+/// have to repeat the prefix or worry about seeing another kind's keys.
+/// Scans are limited to the keys starting with the prefix; the end of that
+/// range is the prefix with its last byte incremented, after dropping any
+/// trailing `0xFF` bytes (a prefix of only `0xFF` bytes has no end and
+/// runs to the end of the key space).
+///
+/// Subspaces nest: the prefixes are concatenated.
+///
+/// Example:
+/// ```ignore
+/// let mut users = lsm.subspace(b"user:");
+/// users.put(b"1", b"alice".to_vec())?; // writes "user:1"
+/// let mut admins = users.subspace(b"admin:");
+/// admins.put(b"7", b"bob".to_vec())?; // writes "user:admin:7"
+/// for (id, name) in users.iter() { /* ids without the "user:" prefix */ }
+/// ```
+use std::ops::{Bound, RangeBounds};
+
+use crate::LSMTree;
+use crate::iter::RangeIter;
+
+/// A view of the keys in a tree that start with a prefix
+pub struct Subspace<'a> {
+    tree: &'a mut LSMTree,
+    prefix: Vec<u8>,
+}
+
+impl<'a> Subspace<'a> {
+    pub(crate) fn new(tree: &'a mut LSMTree, prefix: Vec<u8>) -> Self {
+        Self { tree, prefix }
+    }
+
+    /// The full prefix of the subspace
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// Returns a subspace of this one, with `prefix` appended
+    pub fn subspace(&mut self, prefix: &[u8]) -> Subspace<'_> {
+        Subspace::new(self.tree, self.full_key(prefix))
+    }
+
+    /// Inserts or updates `key` under the prefix
+    pub fn put(&mut self, key: &[u8], value: Vec<u8>) -> std::io::Result<()> {
+        let key = self.full_key(key);
+        self.tree.put(key, value)
+    }
+
+    /// Retrieves the value of `key` under the prefix
+    pub fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let key = self.full_key(key);
+        self.tree.get(&key)
+    }
+
+    /// Deletes `key` under the prefix
+    pub fn delete(&mut self, key: &[u8]) -> std::io::Result<()> {
+        let key = self.full_key(key);
+        self.tree.delete(key)
+    }
+
+    /// Returns true if `key` under the prefix has a live value
+    ///
+    /// Values are never read from the SSTables.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        let key = self.full_key(key);
+        self.tree.any_in_range(key.as_slice()..=key.as_slice())
+    }
+
+    /// Returns the live entries of the subspace whose keys (without the
+    /// prefix) fall in `range`, in key order
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> SubspaceIter<'_> {
+        let start = match range.start_bound() {
+            Bound::Included(k) => Bound::Included(self.full_key(k.as_ref())),
+            Bound::Excluded(k) => Bound::Excluded(self.full_key(k.as_ref())),
+            Bound::Unbounded => Bound::Included(self.prefix.clone()),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(k) => Bound::Included(self.full_key(k.as_ref())),
+            Bound::Excluded(k) => Bound::Excluded(self.full_key(k.as_ref())),
+            Bound::Unbounded => match prefix_end(&self.prefix) {
+                Some(end) => Bound::Excluded(end),
+                None => Bound::Unbounded,
+            },
+        };
+        SubspaceIter {
+            inner: self.tree.range((start, end)),
+            prefix_len: self.prefix.len(),
+        }
+    }
+
+    /// Returns every live entry of the subspace, in key order
+    pub fn iter(&self) -> SubspaceIter<'_> {
+        self.range::<&[u8], _>(..)
+    }
+
+    fn full_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut full = Vec::with_capacity(self.prefix.len() + key.len());
+        full.extend_from_slice(&self.prefix);
+        full.extend_from_slice(key);
+        full
+    }
+}
+
+/// Returns the smallest key greater than every key starting with `prefix`,
+/// or `None` when no such key exists (an empty or all-`0xFF` prefix)
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&b| b != 0xFF)?;
+    let mut end = prefix[..=last].to_vec();
+    end[last] += 1;
+    Some(end)
+}
+
+/// Iterator over the entries of a subspace, with the prefix stripped
+pub struct SubspaceIter<'a> {
+    inner: RangeIter<'a>,
+    prefix_len: usize,
+}
+
+impl Iterator for SubspaceIter<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (mut key, value) = self.inner.next()?;
+        key.drain(..self.prefix_len);
+        Some((key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(b"user:"), Some(b"user;".to_vec()));
+        assert_eq!(prefix_end(&[0x61, 0xFF, 0xFF]), Some(vec![0x62]));
+        assert_eq!(prefix_end(&[0xFF, 0xFF]), None);
+        assert_eq!(prefix_end(b""), None);
+    }
+
+    #[test]
+    fn test_subspace_scopes_keys() {
+        let dir = PathBuf::from("./test_subspace_scopes");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"user".to_vec(), b"bare".to_vec()).unwrap();
+        lsm.put(b"user;".to_vec(), b"after".to_vec()).unwrap();
+        lsm.put(b"usea".to_vec(), b"before".to_vec()).unwrap();
+
+        let mut users = lsm.subspace(b"user:");
+        users.put(b"1", b"alice".to_vec()).unwrap();
+        users.put(b"2", b"bob".to_vec()).unwrap();
+        users.put(b"3", b"carol".to_vec()).unwrap();
+        users.delete(b"2").unwrap();
+        assert_eq!(users.get(b"1"), Some(b"alice".to_vec()));
+        assert_eq!(users.get(b"2"), None);
+        assert!(users.contains_key(b"3"));
+        assert!(!users.contains_key(b"2"));
+
+        // Neighbouring keys on both sides stay invisible
+        let all: Vec<_> = users.iter().collect();
+        assert_eq!(
+            all,
+            vec![
+                (b"1".to_vec(), b"alice".to_vec()),
+                (b"3".to_vec(), b"carol".to_vec()),
+            ]
+        );
+        let keys: Vec<_> = users.range(b"2".as_slice()..).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"3".to_vec()]);
+
+        // Nested subspaces compose their prefixes
+        let mut admins = users.subspace(b"admin:");
+        admins.put(b"7", b"dave".to_vec()).unwrap();
+        assert_eq!(admins.prefix(), b"user:admin:");
+        assert_eq!(admins.iter().count(), 1);
+        assert_eq!(users.get(b"admin:7"), Some(b"dave".to_vec()));
+        assert_eq!(lsm.get(b"user:admin:7"), Some(b"dave".to_vec()));
+        assert_eq!(lsm.get(b"user:1"), Some(b"alice".to_vec()));
+
+        // Still scoped once everything is in SSTables
+        lsm.flush().unwrap();
+        let users = lsm.subspace(b"user:");
+        assert_eq!(users.iter().count(), 3);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_subspace_with_ff_suffix() {
+        let dir = PathBuf::from("./test_subspace_ff");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(vec![b'a', 0xFE], b"below".to_vec()).unwrap();
+        lsm.put(vec![b'b'], b"above".to_vec()).unwrap();
+        lsm.put(vec![b'b', 0x00], b"above".to_vec()).unwrap();
+
+        let mut space = lsm.subspace(&[b'a', 0xFF]);
+        space.put(b"", b"empty".to_vec()).unwrap();
+        space.put(&[0xFF], b"ff".to_vec()).unwrap();
+        space.put(&[0xFF, 0xFF, 0x01], b"deep".to_vec()).unwrap();
+        let keys: Vec<_> = space.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![vec![], vec![0xFF], vec![0xFF, 0xFF, 0x01]]);
+
+        // An all-0xFF prefix runs to the end of the key space
+        lsm.put(vec![0xFE, 0xFF], b"below".to_vec()).unwrap();
+        let mut top = lsm.subspace(&[0xFF, 0xFF]);
+        top.put(b"x", b"1".to_vec()).unwrap();
+        top.put(&[0xFF; 4], b"2".to_vec()).unwrap();
+        let keys: Vec<_> = top.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"x".to_vec(), vec![0xFF; 4]]);
+        assert!(top.range(b"y".as_slice()..).all(|(k, _)| k == [0xFF; 4]));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
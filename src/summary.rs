@@ -0,0 +1,129 @@
+/// A one-call overview of a tree (see `LSMTree::tree_summary`)
+///
+/// Collects what a person looking at a tree wants first: where it lives,
+/// how full the memtable is, how much is on disk, how the Bloom filters
+/// are doing, when it last flushed and compacted, and the settings it runs
+/// with. `Display` prints it as a few lines of text, and `LSMTree`'s
+/// `Debug` prints the same instead of dumping the tree's contents.
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::bloom_summary::BloomFilterSummary;
+use crate::compression::Compression;
+use crate::config::FilterPolicy;
+use crate::memtable::MemtableBackend;
+
+/// State and settings of a tree at one moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeSummary {
+    pub data_dir: PathBuf,
+
+    /// Path of the WAL file
+    pub wal_path: PathBuf,
+
+    /// Keys in the memtable, tombstones included
+    pub memtable_entries: usize,
+
+    /// Bytes the memtable holds
+    pub memtable_bytes: usize,
+
+    /// `LSMConfig::memtable_size_threshold`
+    pub memtable_threshold: usize,
+
+    pub sstable_count: usize,
+
+    /// Size of the live SSTables
+    pub sstable_bytes: u64,
+
+    /// Size of the WAL file
+    pub wal_bytes: u64,
+
+    /// The Bloom filters in memory
+    pub bloom: BloomFilterSummary,
+
+    /// When the summary was taken, by `LSMConfig::clock`
+    pub now: SystemTime,
+
+    /// When the memtable was last flushed, or the tree opened if it hasn't
+    /// flushed since
+    pub last_flush: SystemTime,
+
+    /// When the SSTables were last compacted (`None` if they weren't since
+    /// the tree was opened)
+    pub last_compaction: Option<SystemTime>,
+
+    pub memtable_backend: MemtableBackend,
+    pub filter_policy: FilterPolicy,
+    pub bloom_filter_fpp: f64,
+    pub compression: Compression,
+    pub sync_writes: bool,
+    pub sync_on_flush: bool,
+    pub flush_after_idle: Option<Duration>,
+    pub ttl: Option<Duration>,
+    pub read_amp_warn_threshold: Option<f64>,
+    pub read_only: bool,
+}
+
+impl TreeSummary {
+    /// How long ago `time` was, as text
+    fn ago(&self, time: SystemTime) -> String {
+        // A clock that went backwards counts as no time passing
+        let elapsed = self.now.duration_since(time).unwrap_or_default();
+        format!("{:.1}s ago", elapsed.as_secs_f64())
+    }
+}
+
+impl fmt::Display for TreeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "off".to_string());
+
+        writeln!(
+            f,
+            "LSM tree at {}{}",
+            self.data_dir.display(),
+            if self.read_only { " (read-only)" } else { "" }
+        )?;
+        writeln!(
+            f,
+            "  memtable: {} entries, {} of {} bytes ({:.0}%)",
+            self.memtable_entries,
+            self.memtable_bytes,
+            self.memtable_threshold,
+            self.memtable_bytes as f64 * 100.0 / self.memtable_threshold.max(1) as f64
+        )?;
+        writeln!(
+            f,
+            "  sstables: {} tables, {} bytes",
+            self.sstable_count, self.sstable_bytes
+        )?;
+        writeln!(
+            f,
+            "  wal: {} bytes at {}",
+            self.wal_bytes,
+            self.wal_path.display()
+        )?;
+        writeln!(f, "  bloom: {}", self.bloom.one_line())?;
+        writeln!(
+            f,
+            "  last flush: {}; last compaction: {}",
+            self.ago(self.last_flush),
+            self.last_compaction
+                .map_or_else(|| "never".to_string(), |t| self.ago(t))
+        )?;
+        writeln!(
+            f,
+            "  options: backend {:?}, filter {:?} (fpp {}), compression {:?}, \
+             sync_writes {}, sync_on_flush {}, idle flush {}, ttl {}, read amp warning {}",
+            self.memtable_backend,
+            self.filter_policy,
+            self.bloom_filter_fpp,
+            self.compression,
+            self.sync_writes,
+            self.sync_on_flush,
+            optional(self.flush_after_idle.map(|d| format!("{:?}", d))),
+            optional(self.ttl.map(|d| format!("{:?}", d))),
+            optional(self.read_amp_warn_threshold.map(|t| t.to_string())),
+        )
+    }
+}
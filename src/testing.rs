@@ -0,0 +1,240 @@
+/// Helpers for testing code built on the LSM tree
+///
+/// Only compiled for this crate's own tests or with the `test-util`
+/// feature:
+///
+/// ```toml
+/// [dev-dependencies]
+/// lsm_tree = { version = "0.1", features = ["test-util"] }
+/// ```
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::events::{
+    EventListener, FlushPauseEvent, OptionChangeEvent, ReadAmpEvent, RecoveryEstimateEvent,
+};
+use crate::storage::{FsStorage, Storage};
+use crate::verify::CorruptRead;
+
+pub mod consistency_checker;
+mod layout;
+mod model;
+
+pub use consistency_checker::{ConsistencyChecker, ReadPath, Violation};
+pub use layout::{LayoutDigest, layout_digest};
+pub use model::{Divergence, Model, Op};
+
+/// Clock that only moves when told to
+///
+/// Clones share the same time, so a test can keep one handle and put
+/// another into `LSMConfig::clock`:
+///
+/// ```ignore
+/// let clock = ManualClock::new(0);
+/// let lsm = LSMTree::open(LSMConfig {
+///     clock: Arc::new(clock.clone()),
+///     ..LSMConfig::new(dir)
+/// })?;
+/// clock.advance(Duration::from_secs(60));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    millis: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Creates a clock showing `millis` milliseconds since the Unix epoch
+    pub fn new(millis: u64) -> Self {
+        Self {
+            millis: Arc::new(AtomicU64::new(millis)),
+        }
+    }
+
+    /// Moves the clock forward
+    pub fn advance(&self, by: Duration) {
+        self.millis
+            .fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// Sets the clock to an absolute time (it may move backwards)
+    pub fn set(&self, millis: u64) {
+        self.millis.store(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+}
+
+/// Event listener that keeps every event it receives
+///
+/// Clones share the same log, like `ManualClock`.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingListener {
+    read_amp_high: Arc<Mutex<Vec<ReadAmpEvent>>>,
+    option_changes: Arc<Mutex<Vec<OptionChangeEvent>>>,
+    pause_overrides: Arc<Mutex<Vec<FlushPauseEvent>>>,
+    corrupt_reads: Arc<Mutex<Vec<CorruptRead>>>,
+    recovery_estimates_high: Arc<Mutex<Vec<RecoveryEstimateEvent>>>,
+}
+
+impl RecordingListener {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `on_read_amp_high` events so far, oldest first
+    pub fn read_amp_high(&self) -> Vec<ReadAmpEvent> {
+        self.read_amp_high.lock().unwrap().clone()
+    }
+
+    /// The `on_option_changed` events so far, oldest first
+    pub fn option_changes(&self) -> Vec<OptionChangeEvent> {
+        self.option_changes.lock().unwrap().clone()
+    }
+
+    /// The `on_flush_pause_overridden` events so far, oldest first
+    pub fn pause_overrides(&self) -> Vec<FlushPauseEvent> {
+        self.pause_overrides.lock().unwrap().clone()
+    }
+
+    /// The `on_corrupt_read` events so far, oldest first
+    pub fn corrupt_reads(&self) -> Vec<CorruptRead> {
+        self.corrupt_reads.lock().unwrap().clone()
+    }
+
+    /// The `on_recovery_estimate_high` events so far, oldest first
+    pub fn recovery_estimates_high(&self) -> Vec<RecoveryEstimateEvent> {
+        self.recovery_estimates_high.lock().unwrap().clone()
+    }
+}
+
+impl EventListener for RecordingListener {
+    fn on_read_amp_high(&self, event: &ReadAmpEvent) {
+        self.read_amp_high.lock().unwrap().push(*event);
+    }
+
+    fn on_option_changed(&self, event: &OptionChangeEvent) {
+        self.option_changes.lock().unwrap().push(*event);
+    }
+
+    fn on_flush_pause_overridden(&self, event: &FlushPauseEvent) {
+        self.pause_overrides.lock().unwrap().push(*event);
+    }
+
+    fn on_corrupt_read(&self, event: &CorruptRead) {
+        self.corrupt_reads.lock().unwrap().push(event.clone());
+    }
+
+    fn on_recovery_estimate_high(&self, event: &RecoveryEstimateEvent) {
+        self.recovery_estimates_high.lock().unwrap().push(*event);
+    }
+}
+
+/// Storage whose WAL appends can be made to fail
+///
+/// A failing append writes the first half of the entry and then returns a
+/// `StorageFull` error, like a disk filling up mid-record. Clones share the
+/// same schedule, like `ManualClock`.
+#[derive(Debug, Clone, Default)]
+pub struct FaultyStorage {
+    /// Appends still to let through before failing
+    skip: Arc<AtomicU64>,
+
+    /// Appends still to fail once `skip` runs out
+    failing: Arc<AtomicU64>,
+
+    failed: Arc<AtomicU64>,
+}
+
+impl FaultyStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets the next `skip` appends through, then fails `count` of them
+    pub fn fail_appends(&self, skip: u64, count: u64) {
+        self.skip.store(skip, Ordering::SeqCst);
+        self.failing.store(count, Ordering::SeqCst);
+    }
+
+    /// Number of appends failed so far
+    pub fn failed_appends(&self) -> u64 {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    /// Whether the next append fails, counting it against the schedule
+    fn next_fails(&self) -> bool {
+        let take = |counter: &AtomicU64| {
+            counter
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+        };
+        !take(&self.skip) && take(&self.failing)
+    }
+}
+
+impl Storage for FaultyStorage {
+    fn sync_file(&self, file: &File) -> std::io::Result<()> {
+        FsStorage.sync_file(file)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> std::io::Result<()> {
+        FsStorage.sync_dir(dir)
+    }
+
+    fn append(&self, file: &File, buf: &[u8]) -> std::io::Result<()> {
+        if !self.next_fails() {
+            return FsStorage.append(file, buf);
+        }
+        self.failed.fetch_add(1, Ordering::SeqCst);
+        let mut file = file;
+        file.write_all(&buf[..buf.len() / 2])?;
+        Err(std::io::Error::new(
+            std::io::ErrorKind::StorageFull,
+            "injected append failure",
+        ))
+    }
+}
+
+/// Directory for one test, removed when dropped
+///
+/// The path is the system temp directory plus the name, the process id
+/// and a counter, so a test gets the same kind of path on every run while
+/// parallel tests, and fuzzers running several processes, never share one.
+/// Whatever is there from an earlier run is removed first.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "lsm_tree_{}_{}_{}",
+            name,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,457 @@
+/// Checks the read guarantees a tree gives each of its users
+///
+/// Two properties are checked for every handle (a logical user of the
+/// tree, one thread in `run_threads`):
+///
+/// - read-your-writes: after a handle's put or delete returns, its reads
+///   of that key see that write or a newer one
+/// - monotonic reads: once a handle has seen a version of a key, its later
+///   reads of the key never go back to an older one
+///
+/// Handles write values that name the write (a version number taken under
+/// the write lock, so versions follow the order writes were applied in),
+/// and every read goes through one of the tree's read paths in turn (see
+/// `ReadPath`), so a path that answers from stale data is caught even when
+/// the others are right. Flushes and compactions are injected between the
+/// handles' operations, and a small Bloom filter memory budget keeps
+/// filters being evicted and loaded back.
+///
+/// Any change to the write or read paths should keep this green:
+///
+/// ```ignore
+/// let checker = ConsistencyChecker::new("ryw")?;
+/// checker.run(42, 2_000)?;
+/// checker.run_threads(4, 500)?;
+/// ```
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::{ManualClock, TempDir};
+use crate::{LSMConfig, LSMTree, ReadOptions, SharedLSMTree};
+
+/// Memtable threshold of the checker's tree: a few dozen writes flush
+pub const CHECKER_MEMTABLE_THRESHOLD: usize = 512;
+
+/// Bloom filter memory budget of the checker's tree, a few filters' worth
+const CHECKER_FILTER_BUDGET: usize = 256;
+
+/// Distinct keys the handles write; few, so they keep overwriting each
+/// other
+const CHECKER_KEYS: u64 = 24;
+
+/// A way of reading one key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadPath {
+    /// `LSMTree::get`
+    Get,
+
+    /// `LSMTree::get_immut`, which only uses filters already in memory
+    GetImmut,
+
+    /// `LSMTree::get_opt` with `fill_cache: false`
+    GetUncached,
+
+    /// `TreeState::get` on a state taken for the read
+    ReadState,
+
+    /// `LSMTree::multi_get` with the key alone
+    MultiGet,
+}
+
+impl ReadPath {
+    pub const ALL: [ReadPath; 5] = [
+        ReadPath::Get,
+        ReadPath::GetImmut,
+        ReadPath::GetUncached,
+        ReadPath::ReadState,
+        ReadPath::MultiGet,
+    ];
+
+    fn read(self, tree: &SharedLSMTree, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+        match self {
+            ReadPath::Get => Ok(tree.write().get(key)),
+            ReadPath::GetImmut => Ok(tree.get(key)),
+            ReadPath::GetUncached => tree.write().get_opt(
+                key,
+                &ReadOptions {
+                    fill_cache: false,
+                    ..ReadOptions::default()
+                },
+            ),
+            ReadPath::ReadState => Ok(tree.read_state().get(key)),
+            ReadPath::MultiGet => Ok(tree.multi_get(&[key]).pop().flatten()),
+        }
+    }
+}
+
+/// A read that broke one of the guarantees
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// The handle that read
+    pub handle: usize,
+
+    /// Number of the handle's operation, counting from 1
+    pub step: usize,
+
+    /// How the handle read (`None` when a write failed)
+    pub path: Option<ReadPath>,
+
+    pub key: Vec<u8>,
+
+    /// What was wrong
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "handle {} step {} ({} of {}): {}",
+            self.handle,
+            self.step,
+            self.path
+                .map_or("write".to_string(), |path| format!("{:?}", path)),
+            self.key.escape_ascii(),
+            self.detail
+        )
+    }
+}
+
+impl std::error::Error for Violation {}
+
+/// Writes of one key, in the order they were applied: version and whether
+/// it was a delete
+type KeyWrites = Vec<(u64, bool)>;
+
+/// Every write made through the checker
+#[derive(Default)]
+struct History {
+    writes: Mutex<HashMap<Vec<u8>, KeyWrites>>,
+
+    /// Last version handed out
+    version: AtomicU64,
+}
+
+/// What one handle has written and seen
+#[derive(Default)]
+struct Handle {
+    id: usize,
+
+    /// Operations so far
+    steps: usize,
+
+    /// Per key, the newest version this handle wrote or read
+    floor: HashMap<Vec<u8>, u64>,
+
+    /// The read path to use next
+    next_path: usize,
+}
+
+/// A tree plus the bookkeeping to check its reads (see the module docs)
+pub struct ConsistencyChecker {
+    tree: SharedLSMTree,
+    history: History,
+
+    /// Dropped after the tree
+    _dir: Option<TempDir>,
+}
+
+impl ConsistencyChecker {
+    /// Opens a tree in a new temporary directory named after `name`, with a
+    /// `CHECKER_MEMTABLE_THRESHOLD` memtable, a small Bloom filter budget
+    /// and a clock that stands still
+    pub fn new(name: &str) -> std::io::Result<Self> {
+        let dir = TempDir::new(name);
+        let config = LSMConfig {
+            memtable_size_threshold: CHECKER_MEMTABLE_THRESHOLD,
+            bloom_filter_memory_budget: Some(CHECKER_FILTER_BUDGET),
+            sync_on_flush: false,
+            clock: Arc::new(ManualClock::new(1_000_000)),
+            ..LSMConfig::new(dir.path().to_path_buf())
+        };
+        let mut checker = Self::with_config(config)?;
+        checker._dir = Some(dir);
+        Ok(checker)
+    }
+
+    /// Opens a tree with a config of one's own; `data_dir` should be empty
+    /// and is left behind
+    pub fn with_config(config: LSMConfig) -> std::io::Result<Self> {
+        Ok(Self {
+            tree: SharedLSMTree::new(LSMTree::open(config)?),
+            history: History::default(),
+            _dir: None,
+        })
+    }
+
+    /// The tree under test
+    pub fn tree(&self) -> &SharedLSMTree {
+        &self.tree
+    }
+
+    /// Interleaves four handles on one thread for `steps` operations picked
+    /// from `seed`, with flushes and compactions among them
+    ///
+    /// The same seed runs the same operations, so a violation can be
+    /// replayed.
+    pub fn run(&self, seed: u64, steps: usize) -> Result<(), Violation> {
+        let mut rng = SplitMix(seed);
+        let mut handles: Vec<Handle> = (0..4)
+            .map(|id| Handle {
+                id,
+                ..Handle::default()
+            })
+            .collect();
+        for _ in 0..steps {
+            match rng.below(20) {
+                0 => self.inject(rng.below(4) == 0),
+                _ => {
+                    let index = rng.below(handles.len() as u64) as usize;
+                    self.step(&mut handles[index], &mut rng)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `threads` handles at once, `steps` operations each, while
+    /// another thread keeps flushing and compacting
+    pub fn run_threads(&self, threads: usize, steps: usize) -> Result<(), Violation> {
+        let done = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut rounds = 0u64;
+                while !done.load(Ordering::SeqCst) {
+                    self.inject(rounds % 4 == 3);
+                    rounds += 1;
+                    std::thread::yield_now();
+                }
+            });
+            let workers: Vec<_> = (0..threads)
+                .map(|id| {
+                    scope.spawn(move || {
+                        let mut rng = SplitMix(id as u64 + 1);
+                        let mut handle = Handle {
+                            id,
+                            ..Handle::default()
+                        };
+                        (0..steps).try_for_each(|_| self.step(&mut handle, &mut rng))
+                    })
+                })
+                .collect();
+            let results: Vec<_> = workers
+                .into_iter()
+                .map(|worker| worker.join().expect("checker handle panicked"))
+                .collect();
+            done.store(true, Ordering::SeqCst);
+            results.into_iter().collect()
+        })
+    }
+
+    /// Flushes the memtable, or compacts every SSTable
+    ///
+    /// Failures are left for the reads to notice: a flush that failed
+    /// must not lose writes either.
+    fn inject(&self, compact: bool) {
+        let mut tree = self.tree.write();
+        let _ = if compact {
+            tree.purge().map(|_| ())
+        } else {
+            tree.flush()
+        };
+    }
+
+    /// One operation of `handle`: a put, a delete or a read of a random key
+    fn step(&self, handle: &mut Handle, rng: &mut SplitMix) -> Result<(), Violation> {
+        handle.steps += 1;
+        let key = format!("key{:02}", rng.below(CHECKER_KEYS)).into_bytes();
+        match rng.below(10) {
+            0..=3 => self.write(handle, key, false),
+            4 => self.write(handle, key, true),
+            _ => {
+                let path = ReadPath::ALL[handle.next_path % ReadPath::ALL.len()];
+                handle.next_path += 1;
+                self.read(handle, key, path)
+            }
+        }
+    }
+
+    /// Writes a new version of `key` (a tombstone if `delete`)
+    fn write(&self, handle: &mut Handle, key: Vec<u8>, delete: bool) -> Result<(), Violation> {
+        let mut tree = self.tree.write();
+        // Taken under the write lock: versions follow the order writes apply
+        let version = self.history.version.fetch_add(1, Ordering::SeqCst) + 1;
+        let result = if delete {
+            tree.delete(key.clone())
+        } else {
+            tree.put(key.clone(), encode(version, handle.id))
+        };
+        self.history
+            .writes
+            .lock()
+            .expect("history lock poisoned")
+            .entry(key.clone())
+            .or_default()
+            .push((version, delete));
+        drop(tree);
+
+        if let Err(e) = result {
+            return Err(handle.violation(None, key, format!("write failed: {}", e)));
+        }
+        handle.floor.insert(key, version);
+        Ok(())
+    }
+
+    /// Reads `key` through `path` and checks the answer against what the
+    /// handle wrote and saw before
+    fn read(&self, handle: &mut Handle, key: Vec<u8>, path: ReadPath) -> Result<(), Violation> {
+        let value = path.read(&self.tree, &key).map_err(|e| {
+            handle.violation(Some(path), key.clone(), format!("read failed: {}", e))
+        })?;
+        let floor = handle.floor.get(&key).copied().unwrap_or(0);
+        let writes = self
+            .history
+            .writes
+            .lock()
+            .expect("history lock poisoned")
+            .get(&key)
+            .cloned()
+            .unwrap_or_default();
+
+        let seen = match value {
+            Some(value) => {
+                let Some(version) = decode(&value) else {
+                    return Err(handle.violation(
+                        Some(path),
+                        key,
+                        format!("returned {} nobody wrote", value.escape_ascii()),
+                    ));
+                };
+                if !writes.contains(&(version, false)) {
+                    return Err(handle.violation(
+                        Some(path),
+                        key,
+                        format!(
+                            "returned version {}, which isn't a put of this key",
+                            version
+                        ),
+                    ));
+                }
+                version
+            }
+            // Absent: the oldest delete at or after the floor explains it;
+            // with none, the newest write is still a put and was lost
+            None => match writes.iter().find(|&&(v, delete)| delete && v >= floor) {
+                Some(&(version, _)) => version,
+                None if floor == 0 => 0,
+                None => {
+                    return Err(handle.violation(
+                        Some(path),
+                        key,
+                        format!("returned nothing, but version {} is live", floor),
+                    ));
+                }
+            },
+        };
+        if seen < floor {
+            return Err(handle.violation(
+                Some(path),
+                key,
+                format!(
+                    "returned version {}, older than version {} this handle already wrote or saw",
+                    seen, floor
+                ),
+            ));
+        }
+        handle.floor.insert(key, seen);
+        Ok(())
+    }
+}
+
+impl Handle {
+    fn violation(&self, path: Option<ReadPath>, key: Vec<u8>, detail: String) -> Violation {
+        Violation {
+            handle: self.id,
+            step: self.steps,
+            path,
+            key,
+            detail,
+        }
+    }
+}
+
+/// Value naming a write: its version and the handle that made it
+fn encode(version: u64, handle: usize) -> Vec<u8> {
+    format!("v{:016x}h{}", version, handle).into_bytes()
+}
+
+fn decode(value: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(value).ok()?;
+    let hex = text.strip_prefix('v')?.get(..16)?;
+    u64::from_str_radix(hex, 16).ok()
+}
+
+/// Small deterministic generator (SplitMix64), so runs replay from a seed
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleaved_handles_read_their_writes() {
+        let checker = ConsistencyChecker::new("consistency_interleaved").unwrap();
+        for seed in 0..4 {
+            if let Err(violation) = checker.run(seed, 1_500) {
+                panic!("seed {}: {}", seed, violation);
+            }
+        }
+        assert!(checker.tree().read().sstable_count() > 0);
+    }
+
+    #[test]
+    fn test_threaded_handles_read_their_writes() {
+        let checker = ConsistencyChecker::new("consistency_threads").unwrap();
+        if let Err(violation) = checker.run_threads(4, 400) {
+            panic!("{}", violation);
+        }
+    }
+
+    #[test]
+    fn test_catches_a_lost_write() {
+        let checker = ConsistencyChecker::new("consistency_lost").unwrap();
+        let mut handle = Handle::default();
+        checker
+            .write(&mut handle, b"key00".to_vec(), false)
+            .unwrap();
+        // Behind the checker's back, as a write path that skipped the
+        // memtable would leave it
+        checker.tree().write().delete(b"key00".to_vec()).unwrap();
+
+        let violation = checker
+            .read(&mut handle, b"key00".to_vec(), ReadPath::GetImmut)
+            .unwrap_err();
+        assert_eq!(violation.path, Some(ReadPath::GetImmut));
+        assert!(
+            violation.detail.contains("version 1 is live"),
+            "{}",
+            violation
+        );
+    }
+}
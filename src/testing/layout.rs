@@ -0,0 +1,290 @@
+/// Structural fingerprints of a tree's directory, for golden tests of the
+/// on-disk layout
+///
+/// `layout_digest` reads every file the way the tree would and hashes what
+/// it holds rather than its raw bytes: the records of an SSTable (key,
+/// sequence, decoded value) with its format version, write times and
+/// summary (see `sstable::read_summary`), the
+/// entries of the WAL, the parameters of a Bloom filter, the lines of the
+/// manifest and the `FORMAT` file. What differs between two runs of the
+/// same workload is left out: the random tree id stamped into tables and
+/// `FORMAT`, the random seed and hasher of each Bloom filter (and so its
+/// bits), the `LOCK` file, and the counts estimated by asking those
+/// filters (a filter's overlapping items, a table's `obsolete` records). With a `ManualClock` in `LSMConfig::clock`, a
+/// scripted workload gives the same digest on every run and every machine
+/// until a format changes.
+///
+/// Example:
+/// ```ignore
+/// let digest = layout_digest(&dir)?;
+/// assert_eq!(digest.to_string(), include_str!("golden.txt"));
+/// ```
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::bloom::{BloomFilter, FNV_OFFSET_BASIS, fnv1a};
+use crate::config::SizeLimits;
+use crate::format::{DirectoryFormat, FORMAT_FILE};
+use crate::lock::LOCK_FILE;
+use crate::manifest::MANIFEST_FILE;
+use crate::sstable::{self, SSTableReader};
+use crate::wal::{WAL, WALOp};
+
+/// Digest of each file in a directory and of all of them together (see
+/// the module docs)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LayoutDigest {
+    /// Digest of each file's normalized contents, by file name
+    pub files: BTreeMap<String, u64>,
+
+    /// Digest of the file names and their digests, in name order
+    pub overall: u64,
+}
+
+/// Fingerprints the files in `data_dir` (not its subdirectories)
+///
+/// Fails if a table, filter or log can't be read.
+pub fn layout_digest(data_dir: &Path) -> std::io::Result<LayoutDigest> {
+    let mut digest = LayoutDigest::default();
+    for entry in std::fs::read_dir(data_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !entry.file_type()?.is_file() || name == LOCK_FILE {
+            continue;
+        }
+        let contents = normalized(data_dir, &name)?;
+        digest
+            .files
+            .insert(name, fnv1a(FNV_OFFSET_BASIS, &contents));
+    }
+
+    let mut all = Vec::new();
+    for (name, file) in &digest.files {
+        field(&mut all, name.as_bytes());
+        all.extend_from_slice(&file.to_le_bytes());
+    }
+    digest.overall = fnv1a(FNV_OFFSET_BASIS, &all);
+    Ok(digest)
+}
+
+/// What the digest of file `name` covers, as length-prefixed fields
+fn normalized(data_dir: &Path, name: &str) -> std::io::Result<Vec<u8>> {
+    let path = data_dir.join(name);
+    let mut out = Vec::new();
+    if name == FORMAT_FILE {
+        let Some(format) = DirectoryFormat::load(data_dir)? else {
+            return Ok(out);
+        };
+        let text = format!(
+            "{} {} {} {} {:?} {:?}",
+            format.version,
+            format.sstable_version,
+            format.endianness,
+            format.comparator,
+            format.features,
+            format.unknown_fields
+        );
+        field(&mut out, text.as_bytes());
+    } else if name.ends_with(".db") {
+        let mut reader = SSTableReader::open(&path)?;
+        out.extend_from_slice(&reader.version().to_le_bytes());
+        reader.write_times()?.write_to(&mut out)?;
+        // The table's creation time comes from the tree's clock
+        let summary = sstable::read_summary(&path)?;
+        let created_at = summary
+            .created_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        for n in [
+            summary.entries,
+            summary.tombstones,
+            summary.shadowed,
+            summary.min_sequence,
+            summary.max_sequence,
+            summary.raw_value_bytes,
+            summary.stored_value_bytes,
+            created_at.as_millis() as u64,
+            summary.blocks as u64,
+        ] {
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        out.push(summary.from_footer as u8);
+        while let Some((key, seq, value)) = reader.try_next()? {
+            field(&mut out, &key);
+            out.extend_from_slice(&seq.to_le_bytes());
+            optional_field(&mut out, value.as_deref());
+        }
+    } else if name.ends_with(".bloom") {
+        let filter = BloomFilter::read_from(&mut BufReader::new(File::open(&path)?))?;
+        for n in [filter.num_bits(), filter.num_hashes(), filter.len()] {
+            out.extend_from_slice(&(n as u64).to_le_bytes());
+        }
+    } else if name == MANIFEST_FILE {
+        for line in std::fs::read_to_string(&path)?.lines() {
+            let kept: Vec<&str> = line
+                .split(' ')
+                .filter(|word| !word.starts_with("obsolete="))
+                .collect();
+            field(&mut out, kept.join(" ").as_bytes());
+        }
+    } else if name.ends_with(".log") {
+        let recovery = WAL::replay(&path, SizeLimits::default())?;
+        for entry in &recovery.entries {
+            out.extend_from_slice(&entry.seq.to_le_bytes());
+            field(&mut out, &entry.key);
+            let value = (entry.op == WALOp::Put).then_some(&entry.value[..]);
+            optional_field(&mut out, value);
+        }
+        out.extend_from_slice(&recovery.dropped_bytes().to_le_bytes());
+    } else {
+        // Anything else as it is
+        out = std::fs::read(&path)?;
+    }
+    Ok(out)
+}
+
+fn field(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn optional_field(out: &mut Vec<u8>, bytes: Option<&[u8]>) {
+    match bytes {
+        Some(bytes) => {
+            out.push(1);
+            field(out, bytes);
+        }
+        None => out.push(0),
+    }
+}
+
+/// One `<digest> <name>` line per file, then `<digest> overall`
+impl fmt::Display for LayoutDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, digest) in &self.files {
+            writeln!(f, "{:016x} {}", digest, name)?;
+        }
+        writeln!(f, "{:016x} overall", self.overall)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ManualClock, TempDir};
+    use crate::{LSMConfig, LSMTree};
+    use std::sync::Arc;
+
+    /// The committed digest of `scripted_workload`
+    const GOLDEN_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/layout.txt");
+
+    /// A tree whose writes all happen at the same moment
+    fn open(dir: &Path, memtable_size_threshold: usize) -> LSMTree {
+        LSMTree::open(LSMConfig {
+            memtable_size_threshold,
+            clock: Arc::new(ManualClock::new(1_000_000)),
+            ..LSMConfig::new(dir.to_path_buf())
+        })
+        .unwrap()
+    }
+
+    /// A fixed mix of puts, overwrites and deletes: a few flushes, one
+    /// compaction, and writes left in the WAL
+    fn scripted_workload(dir: &Path) -> LayoutDigest {
+        let mut lsm = open(dir, 512);
+        // A small LCG, so the workload needs no random number crate
+        let mut state = 42u64;
+        let mut next = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as u32
+        };
+        for round in 0..3 {
+            for _ in 0..60 {
+                let key = format!("key{:03}", next() % 100).into_bytes();
+                if next() % 5 == 0 {
+                    lsm.delete(key).unwrap();
+                } else {
+                    lsm.put(key, format!("value{}-{}", round, next()).into_bytes())
+                        .unwrap();
+                }
+            }
+            if round == 1 {
+                lsm.purge().unwrap();
+            }
+        }
+        let digest = layout_digest(dir).unwrap();
+        lsm.simulate_crash();
+        digest
+    }
+
+    #[test]
+    fn test_layout_matches_golden_digest() {
+        let dir = TempDir::new("layout_golden");
+        let digest = scripted_workload(dir.path());
+        // Deterministic: a second run in another directory agrees
+        let again = TempDir::new("layout_golden");
+        assert_eq!(scripted_workload(again.path()), digest);
+
+        if std::env::var_os("LSM_UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(Path::new(GOLDEN_PATH).parent().unwrap()).unwrap();
+            std::fs::write(GOLDEN_PATH, digest.to_string()).unwrap();
+        }
+        let golden = std::fs::read_to_string(GOLDEN_PATH).unwrap_or_default();
+        assert!(
+            golden == digest.to_string(),
+            "The on-disk layout of the scripted workload changed.\n\
+             \n\
+             expected ({}):\n{}\nactual:\n{}\n\
+             If a format change is intended, bump the version of the format it \
+             touches, then regenerate the golden file with\n\
+             \n    LSM_UPDATE_GOLDEN=1 cargo test test_layout_matches_golden_digest\n\
+             \n\
+             and commit it with the change. Otherwise the change altered what \
+             the tree writes by accident.",
+            GOLDEN_PATH,
+            golden,
+            digest
+        );
+    }
+
+    #[test]
+    fn test_digest_ignores_tree_id_and_filter_seeds() {
+        let dir = TempDir::new("layout_ids");
+        let other = TempDir::new("layout_ids");
+        let digests: Vec<_> = [&dir, &other]
+            .iter()
+            .map(|dir| {
+                let mut lsm = open(dir.path(), 1024);
+                lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+                lsm.flush().unwrap();
+                lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+                assert!(lsm.tree_id().is_some());
+                let digest = layout_digest(dir.path()).unwrap();
+                drop(lsm);
+                digest
+            })
+            .collect();
+        // Different tree ids and seeds, same contents
+        assert_eq!(digests[0], digests[1]);
+        assert!(digests[0].files.contains_key("sstable_0.db"));
+        assert!(!digests[0].files.contains_key(LOCK_FILE));
+
+        // A changed value changes the table's digest and the overall one
+        let mut lsm = open(other.path(), 1024);
+        lsm.put(b"a".to_vec(), b"changed".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        let changed = layout_digest(other.path()).unwrap();
+        drop(lsm);
+        assert_ne!(changed.overall, digests[0].overall);
+        assert_eq!(
+            changed.files["sstable_0.db"],
+            digests[0].files["sstable_0.db"]
+        );
+    }
+}
@@ -0,0 +1,491 @@
+/// Differential testing of the tree against a `BTreeMap` (`Model`)
+///
+/// A `Model` runs a sequence of operations against a real tree in a
+/// temporary directory and against a `BTreeMap` holding what the tree
+/// should contain. After every step it compares the two: the result of the
+/// step (a get or a range), then every live entry of the tree. The first
+/// difference stops the run with a `Divergence` naming the step.
+///
+/// The tree has a small memtable, so a handful of puts flush on their own;
+/// `Flush` and `Compact` steps add explicit trips through the SSTable code,
+/// and `Reopen` abandons the tree like a crash and opens it again, so the
+/// memtable comes back from the WAL. The clock is a `ManualClock` that never
+/// moves, so runs are deterministic.
+///
+/// `Model::sharded` runs the same steps against a `ShardedLSMTree`, whose
+/// shards each flush and recover on their own; the property tests run in
+/// both modes.
+///
+/// The same model backs the property tests below and the fuzz target in
+/// `fuzz/`, which feeds it operations decoded from raw bytes
+/// (`Op::decode_all`):
+///
+/// ```ignore
+/// let mut model = Model::new("my_test")?;
+/// model.run(&[Op::Put(b"a".to_vec(), b"1".to_vec()), Op::Reopen, Op::Get(b"a".to_vec())])?;
+/// ```
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use super::{ManualClock, TempDir};
+use crate::{LSMConfig, LSMTree, ShardedLSMTree};
+
+/// Memtable threshold of the model's tree, small enough that a few puts
+/// flush
+pub const MODEL_MEMTABLE_THRESHOLD: usize = 256;
+
+/// Time the model's clock shows, in milliseconds since the Unix epoch
+const MODEL_CLOCK_MILLIS: u64 = 1_000_000;
+
+/// Distinct keys `Op::decode_all` picks from; few, so operations keep
+/// hitting the same keys
+const DECODED_KEYS: u8 = 32;
+
+/// Longest value `Op::decode_all` produces
+const DECODED_MAX_VALUE_LEN: u8 = 24;
+
+/// One step of a model run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Put(Vec<u8>, Vec<u8>),
+    Get(Vec<u8>),
+    Delete(Vec<u8>),
+
+    /// Writes the memtable into an SSTable
+    Flush,
+
+    /// Merges every SSTable (`LSMTree::purge`)
+    Compact,
+
+    /// Abandons the tree as a crash would and opens it again
+    Reopen,
+
+    /// Reads the live entries from the start key (inclusive) to the end
+    /// key (exclusive); `None` leaves that side unbounded
+    Range(Option<Vec<u8>>, Option<Vec<u8>>),
+}
+
+impl Op {
+    /// Decodes operations from arbitrary bytes, as a fuzzer hands them out
+    ///
+    /// Every byte string decodes to some sequence: a tag byte picks the
+    /// operation and the following bytes its key and value, missing bytes
+    /// reading as zero. Keys are `k` plus one byte out of `DECODED_KEYS`.
+    pub fn decode_all(data: &[u8]) -> Vec<Op> {
+        let mut bytes = data.iter().copied();
+        let mut ops = Vec::new();
+        while let Some(tag) = bytes.next() {
+            let op = match tag % 10 {
+                0..=3 => {
+                    let key = decode_key(bytes.next().unwrap_or(0));
+                    let len = bytes.next().unwrap_or(0) % (DECODED_MAX_VALUE_LEN + 1);
+                    Op::Put(key, bytes.by_ref().take(len as usize).collect())
+                }
+                4 => Op::Get(decode_key(bytes.next().unwrap_or(0))),
+                5 => Op::Delete(decode_key(bytes.next().unwrap_or(0))),
+                6 => {
+                    // The high bit leaves a side unbounded
+                    let bound = |b: u8| (b & 0x80 == 0).then(|| decode_key(b));
+                    let start = bound(bytes.next().unwrap_or(0x80));
+                    let end = bound(bytes.next().unwrap_or(0x80));
+                    Op::Range(start, end)
+                }
+                7 => Op::Flush,
+                8 => Op::Compact,
+                _ => Op::Reopen,
+            };
+            ops.push(op);
+        }
+        ops
+    }
+}
+
+fn decode_key(b: u8) -> Vec<u8> {
+    vec![b'k', b % DECODED_KEYS]
+}
+
+/// Where a model run found the tree and the reference apart
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Number of the step, counting from 1
+    pub step: usize,
+
+    pub op: Op,
+
+    /// What differed
+    pub detail: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {} ({:?}): {}", self.step, self.op, self.detail)
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// The tree a model runs against
+enum Target {
+    Plain(Box<LSMTree>),
+    Sharded(ShardedLSMTree),
+}
+
+impl Target {
+    /// Opens a sharded tree if `config.write_shards` asks for one
+    fn open(config: &LSMConfig) -> std::io::Result<Self> {
+        if config.write_shards > 1 {
+            ShardedLSMTree::open(config.clone()).map(Target::Sharded)
+        } else {
+            LSMTree::open(config.clone()).map(|tree| Target::Plain(Box::new(tree)))
+        }
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> std::io::Result<()> {
+        match self {
+            Target::Plain(tree) => tree.put(key, value),
+            Target::Sharded(tree) => tree.put(key, value),
+        }
+    }
+
+    fn delete(&mut self, key: Vec<u8>) -> std::io::Result<()> {
+        match self {
+            Target::Plain(tree) => tree.delete(key),
+            Target::Sharded(tree) => tree.delete(key),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Target::Plain(tree) => tree.flush(),
+            Target::Sharded(tree) => tree.flush(),
+        }
+    }
+
+    fn purge(&mut self) -> std::io::Result<()> {
+        match self {
+            Target::Plain(tree) => tree.purge().map(|_| ()),
+            Target::Sharded(tree) => tree.purge().map(|_| ()),
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Target::Plain(tree) => tree.get(key),
+            Target::Sharded(tree) => tree.get(key),
+        }
+    }
+
+    fn range(&self, bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Vec<Entry> {
+        match self {
+            Target::Plain(tree) => tree.range(bounds).collect(),
+            Target::Sharded(tree) => tree.range_collect(bounds),
+        }
+    }
+
+    fn simulate_crash(self) {
+        match self {
+            Target::Plain(tree) => tree.simulate_crash(),
+            Target::Sharded(tree) => tree.simulate_crash(),
+        }
+    }
+}
+
+/// A tree and the `BTreeMap` it should match
+pub struct Model {
+    tree: Option<Target>,
+
+    /// Reopens use the same config
+    config: LSMConfig,
+
+    expected: BTreeMap<Vec<u8>, Vec<u8>>,
+    steps: usize,
+
+    /// Dropped after the tree
+    _dir: Option<TempDir>,
+}
+
+impl Model {
+    /// Opens a tree in a new temporary directory named after `name`, with
+    /// a `MODEL_MEMTABLE_THRESHOLD` memtable and a clock that stands still
+    pub fn new(name: &str) -> std::io::Result<Self> {
+        let dir = TempDir::new(name);
+        let config = LSMConfig {
+            memtable_size_threshold: MODEL_MEMTABLE_THRESHOLD,
+            sync_on_flush: false,
+            clock: Arc::new(ManualClock::new(MODEL_CLOCK_MILLIS)),
+            ..LSMConfig::new(dir.path().to_path_buf())
+        };
+        let mut model = Self::with_config(config)?;
+        model._dir = Some(dir);
+        Ok(model)
+    }
+
+    /// Like `new`, on a `ShardedLSMTree` with `shards` shards
+    pub fn sharded(name: &str, shards: usize) -> std::io::Result<Self> {
+        let dir = TempDir::new(name);
+        let config = LSMConfig {
+            memtable_size_threshold: MODEL_MEMTABLE_THRESHOLD,
+            write_shards: shards,
+            sync_on_flush: false,
+            clock: Arc::new(ManualClock::new(MODEL_CLOCK_MILLIS)),
+            ..LSMConfig::new(dir.path().to_path_buf())
+        };
+        let mut model = Self::with_config(config)?;
+        model._dir = Some(dir);
+        Ok(model)
+    }
+
+    /// Opens a tree with a config of one's own, to run the model against
+    /// other settings (a sharded tree with `write_shards` over 1);
+    /// `data_dir` should be empty and is left behind
+    pub fn with_config(config: LSMConfig) -> std::io::Result<Self> {
+        Ok(Self {
+            tree: Some(Target::open(&config)?),
+            config,
+            expected: BTreeMap::new(),
+            steps: 0,
+            _dir: None,
+        })
+    }
+
+    /// Runs `ops` in order, stopping at the first divergence
+    pub fn run(&mut self, ops: &[Op]) -> Result<(), Divergence> {
+        ops.iter().try_for_each(|op| self.apply(op))
+    }
+
+    /// Runs the operations decoded from `data` in a new model and panics on
+    /// a divergence: the body of a fuzz target
+    pub fn fuzz(data: &[u8]) {
+        let ops = Op::decode_all(data);
+        let mut model = Model::new("fuzz").expect("failed to open the model's tree");
+        if let Err(divergence) = model.run(&ops) {
+            panic!("{}", divergence);
+        }
+    }
+
+    /// Applies one operation to the tree and the reference, then compares
+    /// them
+    pub fn apply(&mut self, op: &Op) -> Result<(), Divergence> {
+        self.steps += 1;
+        let tree = self.tree.as_mut().expect("the tree is open between steps");
+        let failed = |e: std::io::Error| format!("failed: {}", e);
+        let result = match op {
+            Op::Put(key, value) => tree.put(key.clone(), value.clone()).map_err(failed),
+            Op::Delete(key) => tree.delete(key.clone()).map_err(failed),
+            Op::Flush => tree.flush().map_err(failed),
+            Op::Compact => tree.purge().map_err(failed),
+            Op::Reopen => {
+                if let Some(tree) = self.tree.take() {
+                    tree.simulate_crash();
+                }
+                Target::open(&self.config)
+                    .map(|tree| self.tree = Some(tree))
+                    .map_err(|e| format!("reopen failed: {}", e))
+            }
+            Op::Get(key) => {
+                let actual = tree.get(key);
+                let expected = self.expected.get(key);
+                if actual.as_ref() == expected {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "get returned {:?}, expected {:?}",
+                        actual.as_deref().map(show),
+                        expected.map(|v| show(v))
+                    ))
+                }
+            }
+            Op::Range(start, end) => {
+                let bounds = (
+                    start.clone().map_or(Bound::Unbounded, Bound::Included),
+                    end.clone().map_or(Bound::Unbounded, Bound::Excluded),
+                );
+                let actual = tree.range(bounds.clone());
+                // BTreeMap panics on reversed bounds; the range is empty
+                let reversed = matches!((start, end), (Some(s), Some(e)) if s > e);
+                let expected: Vec<_> = if reversed {
+                    Vec::new()
+                } else {
+                    self.expected
+                        .range(bounds)
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect()
+                };
+                compare_entries(&actual, &expected).map_err(|d| format!("range {}", d))
+            }
+        };
+        match op {
+            Op::Put(key, value) => {
+                self.expected.insert(key.clone(), value.clone());
+            }
+            Op::Delete(key) => {
+                self.expected.remove(key);
+            }
+            _ => {}
+        }
+        result
+            .and_then(|()| self.check())
+            .map_err(|detail| Divergence {
+                step: self.steps,
+                op: op.clone(),
+                detail,
+            })
+    }
+
+    /// Compares every live entry of the tree with the reference
+    fn check(&self) -> Result<(), String> {
+        let Some(tree) = &self.tree else {
+            return Err("the tree is closed".to_string());
+        };
+        let actual = tree.range((Bound::Unbounded, Bound::Unbounded));
+        let expected: Vec<_> = self
+            .expected
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        compare_entries(&actual, &expected).map_err(|d| format!("after the step, {}", d))
+    }
+
+    /// The tree under test
+    ///
+    /// # Panics
+    /// For a sharded model (see `sharded_tree`).
+    pub fn tree(&self) -> &LSMTree {
+        match self.tree.as_ref().expect("the tree is open between steps") {
+            Target::Plain(tree) => tree,
+            Target::Sharded(_) => panic!("a sharded model has no single tree"),
+        }
+    }
+
+    /// The tree under test of a sharded model (`None` for a plain one)
+    pub fn sharded_tree(&self) -> Option<&ShardedLSMTree> {
+        match self.tree.as_ref().expect("the tree is open between steps") {
+            Target::Plain(_) => None,
+            Target::Sharded(tree) => Some(tree),
+        }
+    }
+
+    /// What the tree should contain
+    pub fn expected(&self) -> &BTreeMap<Vec<u8>, Vec<u8>> {
+        &self.expected
+    }
+
+    /// Steps applied so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+}
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// Describes the first difference between two entry lists
+fn compare_entries(actual: &[Entry], expected: &[Entry]) -> Result<(), String> {
+    if let Some((a, e)) = actual.iter().zip(expected).find(|(a, e)| a != e) {
+        return Err(format!(
+            "returned {} = {}, expected {} = {}",
+            show(&a.0),
+            show(&a.1),
+            show(&e.0),
+            show(&e.1)
+        ));
+    }
+    if actual.len() != expected.len() {
+        return Err(format!(
+            "returned {} entries, expected {}",
+            actual.len(),
+            expected.len()
+        ));
+    }
+    Ok(())
+}
+
+fn show(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn key() -> impl Strategy<Value = Vec<u8>> {
+        (0..DECODED_KEYS).prop_map(decode_key)
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            4 => (key(), prop::collection::vec(any::<u8>(), 0..40))
+                .prop_map(|(k, v)| Op::Put(k, v)),
+            2 => key().prop_map(Op::Get),
+            2 => key().prop_map(Op::Delete),
+            1 => (prop::option::of(key()), prop::option::of(key()))
+                .prop_map(|(s, e)| Op::Range(s, e)),
+            1 => Just(Op::Flush),
+            1 => Just(Op::Compact),
+            1 => Just(Op::Reopen),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(24))]
+
+        #[test]
+        fn prop_tree_matches_btreemap(ops in prop::collection::vec(op(), 1..80)) {
+            let mut model = Model::new("prop_model").unwrap();
+            prop_assert_eq!(model.run(&ops), Ok(()));
+        }
+
+        #[test]
+        fn prop_sharded_tree_matches_btreemap(ops in prop::collection::vec(op(), 1..80)) {
+            let mut model = Model::sharded("prop_model_sharded", 3).unwrap();
+            prop_assert_eq!(model.run(&ops), Ok(()));
+        }
+
+        #[test]
+        fn prop_decoded_bytes_match(data in prop::collection::vec(any::<u8>(), 0..200)) {
+            Model::fuzz(&data);
+        }
+    }
+
+    #[test]
+    fn test_model_covers_every_operation() {
+        let a = || b"a".to_vec();
+        let mut model = Model::new("model_every_op").unwrap();
+        let mut ops = vec![Op::Put(a(), b"1".to_vec()), Op::Flush];
+        for i in 0..40u8 {
+            ops.push(Op::Put(vec![b'k', i], vec![i; 16]));
+        }
+        ops.extend([
+            Op::Delete(a()),
+            Op::Get(a()),
+            Op::Put(b"b".to_vec(), Vec::new()),
+            Op::Reopen,
+            Op::Get(b"b".to_vec()),
+            Op::Compact,
+            Op::Range(Some(vec![b'k', 10]), Some(vec![b'k', 20])),
+            Op::Range(Some(b"z".to_vec()), Some(b"a".to_vec())),
+            Op::Range(None, None),
+        ]);
+        assert_eq!(model.run(&ops), Ok(()));
+        assert_eq!(model.steps(), ops.len());
+        assert!(model.tree().sstable_count() > 0);
+        assert_eq!(model.expected().len(), 41);
+
+        // A tree changed behind the model's back diverges
+        model.expected.insert(b"ghost".to_vec(), b"boo".to_vec());
+        let divergence = model.apply(&Op::Get(a())).unwrap_err();
+        assert_eq!(divergence.step, ops.len() + 1);
+        assert!(divergence.detail.contains("expected ghost = boo"));
+
+        assert_eq!(
+            Op::decode_all(&[0, 3, 2, b'x', b'y', 6, 1, 0x80, 9]),
+            vec![
+                Op::Put(vec![b'k', 3], b"xy".to_vec()),
+                Op::Range(Some(vec![b'k', 1]), None),
+                Op::Reopen,
+            ]
+        );
+    }
+}
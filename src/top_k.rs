@@ -0,0 +1,235 @@
+/// The K largest keys, smallest keys or most recently written keys in a
+/// range (see `LSMTree::top_k`)
+///
+/// Memory stays bounded by K, plus a block per SSTable, whatever the size
+/// of the range. Key order lets the scan stop early: the smallest keys are
+/// the first K of a forward scan, the largest the first K of a backward
+/// one, which reads each SSTable a block of its footer's index at a time
+/// from the end of the range down. Tables without a footer (older than
+/// format version 6) have no blocks to go by and are read whole. Write
+/// order doesn't: the newest writes can be anywhere in the range, so every
+/// live key is scanned, keys only, with a min-heap of the K newest
+/// sequence numbers seen so far, and only the values of the K winners are
+/// read at the end.
+///
+/// Sequence numbers grow with every write, so the newest write is the one
+/// with the highest sequence. Tables written before sequence numbers
+/// existed store 0 for all their records; among those the larger key wins.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use crate::iter::{self, KeyRange, MergeIter, RangeIter};
+use crate::memtable::Memtable;
+use crate::sstable::{Record, SSTableReader};
+use crate::storage::Storage;
+
+/// Which K entries `LSMTree::top_k` keeps, and the order it returns them in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopKOrder {
+    /// The largest keys, largest first
+    LargestKeys,
+
+    /// The smallest keys, smallest first
+    SmallestKeys,
+
+    /// The most recently written keys, newest first
+    NewestWrites,
+}
+
+/// Answer of `LSMTree::top_k`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopK {
+    /// Up to K live keys with their values, in the order asked for
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+
+    /// Whether every live key in the range was looked at: true when the
+    /// range holds no more than K keys, and always for `NewestWrites`.
+    /// False means the range goes on past the last entry.
+    pub exhaustive: bool,
+
+    /// Live keys the scan looked at
+    pub keys_scanned: u64,
+}
+
+/// The first K entries of a forward scan
+pub(crate) fn smallest(mut range: RangeIter<'_>, k: usize) -> TopK {
+    let mut top = TopK::default();
+    while top.entries.len() < k {
+        let Some(entry) = range.next() else {
+            top.exhaustive = true;
+            return top;
+        };
+        top.keys_scanned += 1;
+        top.entries.push(entry);
+    }
+    top.exhaustive = range.next().is_none();
+    top
+}
+
+/// A key and its newest value in one layer (`None` = tombstone)
+type Entry = (Vec<u8>, Option<Vec<u8>>);
+
+/// An SSTable read from the end of a range backwards, one block of its
+/// footer's index at a time
+struct BackwardTable {
+    reader: SSTableReader,
+
+    /// Starts of the blocks not read yet; the last one is read next
+    blocks: Vec<u64>,
+
+    /// Where the block read next ends (`None`: at the end of the records)
+    end: Option<u64>,
+
+    /// The rest of the block read last, largest key last
+    entries: Vec<Record>,
+}
+
+impl BackwardTable {
+    /// Opens a table, skipping the blocks past `last`
+    ///
+    /// A table that can't be opened is left out, as `get` treats it.
+    fn open(
+        path: &Path,
+        storage: &dyn Storage,
+        buffer_bytes: usize,
+        last: Option<&[u8]>,
+    ) -> Option<Self> {
+        let mut reader = SSTableReader::open_with(path, storage, buffer_bytes).ok()?;
+        let (blocks, end) = reader.blocks_through(last).ok()?;
+        Some(Self {
+            reader,
+            blocks,
+            end,
+            entries: Vec::new(),
+        })
+    }
+
+    /// The next key going backwards, with its newest value in the table
+    ///
+    /// A damaged record ends its block (see `read_block`), and a block that
+    /// can't be read ends the table.
+    fn prev(&mut self) -> Option<Entry> {
+        while self.entries.is_empty() {
+            let start = self.blocks.pop()?;
+            self.entries = self.reader.read_block(start, self.end).ok()?;
+            self.end = Some(start);
+        }
+        let (key, _, value) = self.entries.pop()?;
+        Some((key, value))
+    }
+}
+
+/// The first K entries walking back from the end of `range`
+///
+/// The memtable and the SSTables (`tables`, newest first) are merged on
+/// the largest key, the newest layer holding a key deciding its value.
+/// Tables are read a block at a time from the block of the range's end
+/// down, so no more than a block of each table is held besides the K
+/// entries; a table without a footer is a single block.
+pub(crate) fn largest(
+    memtable: &Memtable,
+    tables: &[PathBuf],
+    storage: &dyn Storage,
+    buffer_bytes: usize,
+    range: &KeyRange,
+    k: usize,
+) -> TopK {
+    let last = match &range.1 {
+        Bound::Included(end) | Bound::Excluded(end) => Some(end.as_slice()),
+        Bound::Unbounded => None,
+    };
+    let mut in_memory = memtable.range(range.clone()).rev();
+    let mut on_disk: Vec<BackwardTable> = tables
+        .iter()
+        .filter_map(|path| BackwardTable::open(path, storage, buffer_bytes, last))
+        .collect();
+    // Every layer's next key back, memtable first; keys past the end of
+    // the range are only in the first blocks read
+    let mut heads: Vec<Option<Entry>> = Vec::with_capacity(on_disk.len() + 1);
+    heads.push(in_memory.next());
+    for table in &mut on_disk {
+        let mut head = table.prev();
+        while head
+            .as_ref()
+            .is_some_and(|(key, _)| !iter::before_end(range, key))
+        {
+            head = table.prev();
+        }
+        heads.push(head);
+    }
+
+    let mut top = TopK::default();
+    loop {
+        // The largest key of any layer, valued by the first (newest) layer
+        // holding it
+        let Some(key) = heads.iter().flatten().map(|(key, _)| key).max().cloned() else {
+            top.exhaustive = true;
+            return top;
+        };
+        let value = heads
+            .iter()
+            .flatten()
+            .find(|(held, _)| *held == key)
+            .and_then(|(_, value)| value.clone());
+        if !iter::after_start(range, &key) {
+            top.exhaustive = true;
+            return top;
+        }
+        // Tombstones are stepped through: only a live key past the K
+        // makes the answer non-exhaustive
+        if let Some(value) = value {
+            if top.entries.len() == k {
+                top.exhaustive = false;
+                return top;
+            }
+            top.keys_scanned += 1;
+            top.entries.push((key.clone(), value));
+        }
+        // Step every layer holding the key past it
+        for (i, head) in heads.iter_mut().enumerate() {
+            if head.as_ref().is_some_and(|(held, _)| *held == key) {
+                *head = match i {
+                    0 => in_memory.next(),
+                    _ => on_disk[i - 1].prev(),
+                };
+            }
+        }
+    }
+}
+
+/// The K keys of a keys-only merge with the highest sequence numbers, with
+/// their values looked up through `get`
+pub(crate) fn newest(
+    mut keys: MergeIter<'_>,
+    k: usize,
+    get: impl Fn(&[u8]) -> Option<Vec<u8>>,
+) -> TopK {
+    let mut top = TopK {
+        exhaustive: true,
+        ..TopK::default()
+    };
+    // Min-heap: the oldest of the K newest writes is the one to drop
+    let mut heap = BinaryHeap::new();
+    while let Some((key, seq, value)) = keys.next_entry() {
+        if value.is_none() {
+            continue;
+        }
+        top.keys_scanned += 1;
+        heap.push(Reverse((seq, key)));
+        if heap.len() > k {
+            heap.pop();
+        }
+    }
+    // Ascending order of `Reverse` is newest first
+    top.entries = heap
+        .into_sorted_vec()
+        .into_iter()
+        .filter_map(|Reverse((_, key))| {
+            let value = get(&key)?;
+            Some((key, value))
+        })
+        .collect();
+    top
+}
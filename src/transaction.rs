@@ -0,0 +1,250 @@
+//! Multi-key read-modify-write with optimistic validation
+//!
+//! `LSMTree::begin` starts a transaction on a `TreeState` of the tree, so
+//! its reads all see the tree as of one moment, plus the transaction's own
+//! pending writes. Writes are buffered until `commit`, which checks that no
+//! key the transaction read has been written since it began (by sequence
+//! number) and then applies every buffered write as one `WriteBatch`. If a
+//! read key did change, nothing is applied and the commit fails with a
+//! `Conflict`; the caller starts over with a new transaction.
+//!
+//! Validation and the batch happen under the same `&mut LSMTree`, so with
+//! a `SharedLSMTree` the commit is atomic with respect to other writers:
+//! `tx.commit(&mut shared.write())`. Keys only written, never read, aren't
+//! validated: the last commit wins, as with plain puts.
+//!
+//! A transaction holds its state until it is dropped, which keeps the
+//! memtable it read from and the SSTables under it alive; keep them short.
+//!
+//! Example:
+//! ```ignore
+//! loop {
+//!     let mut tx = lsm.begin();
+//!     let count = tx.get(b"counter").map_or(0, decode);
+//!     tx.put(b"counter".to_vec(), encode(count + 1));
+//!     match tx.commit(&mut lsm) {
+//!         Err(e) if Conflict::from_io(&e).is_some() => continue,
+//!         result => break result?,
+//!     }
+//! }
+//! ```
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::{LSMTree, TreeState, WriteBatch};
+
+/// A transaction's reads and buffered writes (see the module docs)
+pub struct Transaction {
+    state: TreeState,
+
+    /// The tree's last sequence when the transaction began
+    read_sequence: u64,
+
+    /// Keys read from the state, and whether each had a value
+    reads: BTreeMap<Vec<u8>, bool>,
+
+    /// Pending writes, `None` for a delete
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl Transaction {
+    pub(crate) fn new(state: TreeState, read_sequence: u64) -> Self {
+        Self {
+            state,
+            read_sequence,
+            reads: BTreeMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Looks up a key: the transaction's own pending write if it has one,
+    /// otherwise the tree as of `begin`
+    ///
+    /// Keys read from the tree are validated on commit.
+    pub fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(pending) = self.writes.get(key) {
+            return pending.clone();
+        }
+        let value = self.state.get(key);
+        self.reads.insert(key.to_vec(), value.is_some());
+        value
+    }
+
+    /// Buffers a put
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.writes.insert(key, Some(value));
+    }
+
+    /// Buffers a delete
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.writes.insert(key, None);
+    }
+
+    /// Sequence number the transaction reads at
+    pub fn read_sequence(&self) -> u64 {
+        self.read_sequence
+    }
+
+    /// Number of buffered writes
+    pub fn pending_writes(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Validates the reads against `tree` and applies the writes
+    ///
+    /// Fails with a `Conflict` (kind `Other`) naming the first read key
+    /// that was written since the transaction began, including a value
+    /// that was deleted and compacted away; nothing is applied then. A
+    /// transaction without writes only validates.
+    pub fn commit(self, tree: &mut LSMTree) -> std::io::Result<()> {
+        for (key, &existed) in &self.reads {
+            let changed = match tree.newest_sequence(key) {
+                Some(sequence) => sequence > self.read_sequence,
+                // Gone altogether: deleted and purged since, if it was there
+                None => existed,
+            };
+            if changed {
+                return Err(Conflict { key: key.clone() }.into());
+            }
+        }
+
+        let mut batch = WriteBatch::new();
+        for (key, value) in self.writes {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        tree.write(batch)
+    }
+}
+
+impl fmt::Debug for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transaction")
+            .field("read_sequence", &self.read_sequence)
+            .field("reads", &self.reads.len())
+            .field("writes", &self.writes.len())
+            .finish()
+    }
+}
+
+/// A commit refused because a key the transaction read has changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The first changed key
+    pub key: Vec<u8>,
+}
+
+impl Conflict {
+    /// The `Conflict` inside an `io::Error` returned by `commit`, if any
+    pub fn from_io(error: &std::io::Error) -> Option<&Conflict> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "transaction conflict: {} was written after the transaction began",
+            self.key.escape_ascii()
+        )
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+impl From<Conflict> for std::io::Error {
+    fn from(conflict: Conflict) -> Self {
+        std::io::Error::other(conflict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LSMConfig;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn counter(tx: &mut Transaction, key: &[u8]) -> u64 {
+        tx.get(key)
+            .map_or(0, |v| u64::from_le_bytes(v.try_into().unwrap()))
+    }
+
+    #[test]
+    fn test_interleaved_increments_conflict() {
+        let dir = PathBuf::from("./test_tx_counter");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::open(LSMConfig::new(dir.clone())).unwrap();
+        lsm.put(b"counter".to_vec(), 5u64.to_le_bytes().to_vec())
+            .unwrap();
+
+        let mut first = lsm.begin();
+        let mut second = lsm.begin();
+        let a = counter(&mut first, b"counter");
+        let b = counter(&mut second, b"counter");
+        first.put(b"counter".to_vec(), (a + 1).to_le_bytes().to_vec());
+        second.put(b"counter".to_vec(), (b + 1).to_le_bytes().to_vec());
+        // A transaction reads its own pending write
+        assert_eq!(counter(&mut first, b"counter"), 6);
+
+        first.commit(&mut lsm).unwrap();
+        let err = second.commit(&mut lsm).unwrap_err();
+        assert_eq!(
+            Conflict::from_io(&err).map(|c| c.key.as_slice()),
+            Some(&b"counter"[..])
+        );
+        assert_eq!(lsm.get(b"counter"), Some(6u64.to_le_bytes().to_vec()));
+
+        // Retried on a fresh transaction, it goes through
+        let mut retry = lsm.begin();
+        let c = counter(&mut retry, b"counter");
+        retry.put(b"counter".to_vec(), (c + 1).to_le_bytes().to_vec());
+        retry.commit(&mut lsm).unwrap();
+        assert_eq!(lsm.get(b"counter"), Some(7u64.to_le_bytes().to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_non_overlapping_transactions_both_commit() {
+        let dir = PathBuf::from("./test_tx_disjoint");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::open(LSMConfig::new(dir.clone())).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        lsm.put(b"gone".to_vec(), b"x".to_vec()).unwrap();
+
+        let mut first = lsm.begin();
+        let mut second = lsm.begin();
+        let a = first.get(b"a").unwrap();
+        first.put(b"a2".to_vec(), a);
+        first.delete(b"a".to_vec());
+        let b = second.get(b"b").unwrap();
+        second.put(b"b2".to_vec(), b);
+
+        first.commit(&mut lsm).unwrap();
+        // Flushed in between: sequences survive into the SSTable
+        lsm.flush().unwrap();
+        second.commit(&mut lsm).unwrap();
+        assert_eq!(lsm.get(b"a"), None);
+        assert_eq!(lsm.get(b"a2"), Some(b"1".to_vec()));
+        assert_eq!(lsm.get(b"b2"), Some(b"2".to_vec()));
+
+        // A value deleted and compacted away since the read still conflicts
+        let mut stale = lsm.begin();
+        assert_eq!(stale.get(b"gone"), Some(b"x".to_vec()));
+        stale.put(b"copy".to_vec(), b"x".to_vec());
+        lsm.delete(b"gone".to_vec()).unwrap();
+        lsm.purge().unwrap();
+        let err = stale.commit(&mut lsm).unwrap_err();
+        assert!(Conflict::from_io(&err).is_some());
+        assert_eq!(lsm.get(b"copy"), None);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,10563 @@
+/// The tree itself: `LSMTree` and the reports its operations return
+///
+/// Everything else in the crate is a part the tree is built from (the
+/// WAL, memtables, SSTables and their Bloom filters, the manifest) or a
+/// view of it (read states, cursors, handles, summaries). The crate root
+/// re-exports the types here, and `prelude` the ones most programs need.
+use crate::advisor::ReadCounts;
+use crate::bloom::BloomFilter;
+use crate::config::DEFAULT_BLOOM_FILTER_FPP;
+use crate::error::Context;
+use crate::filter_cache::{FilterCache, SlotSource};
+use crate::flush_policy::{FlushPolicy, SizeThresholdPolicy};
+use crate::iter::{MergeIter, Versions};
+use crate::lock::{DirLock, LOCK_FILE};
+use crate::manifest::{MANIFEST_FILE, Manifest, TableEntry};
+use crate::memtable::Memtable;
+use crate::metrics::{IoRateTrackers, LatencyStats, ReadAmpTracker, TableReads};
+use crate::pinned_tables::PinnedTables;
+use crate::sstable::{
+    OrderedRecords, SSTABLE_FORMAT_VERSION, SSTableReader, SSTableWriter, SSTableWriterOptions,
+};
+use crate::state::TableRefs;
+use crate::storage::Storage;
+use crate::value_meta::WriteTimes;
+use crate::wal::{WAL, WALMark, WALOp};
+use crate::{
+    Advice, AdvisorInput, AmplificationStats, ApplyReport, BloomFilterStats, BloomFilterSummary,
+    CancellationToken, Cancelled, ColdPolicy, CompactionCandidate, Compression, CorruptRead,
+    Corruption, CorruptionPolicy, Cursor, DirectoryFormat, DiskUsage, ExpiryEstimate,
+    FilterDecision, FilterFloor, FilterPolicy, FilterResidency, FilterSizing, FlushContext,
+    FlushPauseGuard, ImportReport, IoRates, KeyCopy, KeyVerification, KeysIter, LSMConfig,
+    LatencyOp, LatencyPercentiles, LoadOptions, LoadReport, NumericRangeIter, OpenPhase,
+    OpenProgress, OpenReport, Operation, OptionChangeEvent, PinnedTableStats, Poisoned,
+    PrefixReport, RangeIter, ReadBudgetExceeded, ReadOptions, RecoveryEstimate, RepairOptions,
+    RepairReport, SSTableHandle, SSTableId, SizeLimits, SnapshotInfo, Subspace, TableReadStats,
+    TableStats, TopK, TopKOrder, Transaction, TreeDeleted, TreeId, TreeOption, TreeState,
+    TreeSummary, ValueMeta, ValueSource, VersionIter, WALStats, WorkloadStats, WriteBatch,
+    WriteOptions,
+};
+use crate::{
+    advisor, compression, disk_usage, events, expiry, filter_cache, garbage, handle, iter, keys,
+    legacy_import, load, metrics, prefix_stats, recovery, repair, sharded, snapshot, sstable,
+    subspace, top_k,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Extensions of the files kept next to an SSTable (`sstable_<n>.<ext>`)
+/// that are derived from it and worthless without it
+const SIDECAR_EXTENSIONS: &[&str] = &["bloom"];
+
+/// WAL entries replayed between two progress reports on open
+const WAL_PROGRESS_INTERVAL: u64 = 1024;
+
+/// WAL appends or syncs failing in a row that poison the tree (see
+/// `LSMTree::is_poisoned`)
+pub const WAL_FAILURES_BEFORE_POISON: u32 = 3;
+
+/// Log-Structured Merge Tree (LSM Tree) implementation
+///
+/// An LSM tree is a write-optimized data structure that provides efficient
+/// writes by batching them in memory (MemTable) and periodically flushing
+/// to disk as immutable sorted files (SSTables). Reads check memory first,
+/// then search through SSTables from newest to oldest.
+pub struct LSMTree {
+    /// In-memory write buffer, sorted by key, that also tracks its size
+    /// (a `None` value is a tombstone left by `delete`)
+    ///
+    /// Shared with the read states taken from it (see `read_state`); a
+    /// write while one is alive copies it first.
+    pub(crate) memtable: Arc<Memtable>,
+
+    /// Settings the tree was opened with (data directory, thresholds, ...)
+    config: LSMConfig,
+
+    /// Id stamped into every SSTable the tree writes (`None` for a
+    /// read-only tree whose directory has none)
+    tree_id: Option<TreeId>,
+
+    /// Live SSTables, kept newest first (mirrors the manifest)
+    ///
+    /// The position is only a convenience for display; which table holds
+    /// the newer version of a key is decided by `SSTableInfo::generation`.
+    pub(crate) sstables: Vec<SSTableInfo>,
+
+    /// Generation assigned to the next SSTable written by a flush
+    next_generation: u64,
+
+    /// Damaged SSTables moved out of the tree when it was opened
+    quarantined: Vec<QuarantinedSSTable>,
+
+    /// Unsequenced SSTables whose modification times contradict their
+    /// order (see `ordering_anomalies`)
+    ordering_anomalies: Vec<OrderingAnomaly>,
+
+    /// Sequence number of the most recent write
+    last_sequence: u64,
+
+    /// When the most recent write arrived (see `last_write_time`)
+    last_write: Option<SystemTime>,
+
+    /// When the memtable was last flushed (or the tree opened)
+    last_flush: SystemTime,
+
+    /// When the SSTables were last compacted, if they were since the tree
+    /// was opened
+    last_compaction: Option<SystemTime>,
+
+    /// When the writes in the memtable happened (writes replayed from the
+    /// WAL have no recorded time)
+    memtable_write_times: WriteTimes,
+
+    /// Counter for generating unique SSTable filenames
+    sstable_counter: usize,
+
+    /// Write-Ahead Log for crash recovery and durability (`None` when the
+    /// tree was opened read-only)
+    wal: Option<WAL>,
+
+    /// Entries the WAL held when the tree was opened (replayed or
+    /// skipped), until the first flush clears it
+    wal_entries_at_open: u64,
+
+    /// WAL replay rate measured on open, in bytes per second (see
+    /// `recovery`)
+    replay_rate: Option<f64>,
+
+    /// Whether `recovery_estimate` is above
+    /// `LSMConfig::recovery_warn_threshold`, so the event fires once per
+    /// crossing
+    recovery_warned: bool,
+
+    /// WAL appends or syncs that failed in a row
+    wal_failures: u32,
+
+    /// Set once WAL failures poisoned the tree (see `is_poisoned`)
+    poison: Option<Poison>,
+
+    /// Set once the directory turned out to be deleted or replaced under
+    /// the tree (see `stale`)
+    deleted: Option<TreeDeleted>,
+
+    /// Pauses of automatic flushes not yet resumed (see `flush_pause`)
+    flush_pauses: usize,
+
+    /// Damaged SSTables verified reads found (see `corruption_report`)
+    corrupt_reads: Vec<CorruptRead>,
+
+    /// Lock keeping other writers out of the data directory (`None` when
+    /// read-only); dropped after the final flush in `Drop`
+    _dir_lock: Option<DirLock>,
+
+    /// Bloom filters for each SSTable (indexed same as sstables vector),
+    /// kept in memory within `LSMConfig::bloom_filter_memory_budget`
+    pub(crate) bloom_filters: FilterCache,
+
+    /// Small SSTables held in memory (see
+    /// `LSMConfig::pin_small_sstables_bytes`)
+    pinned_tables: PinnedTables,
+
+    /// Sizes of the keys and values written, and the write count (see
+    /// `workload_stats`)
+    workload: WorkloadStats,
+
+    /// Reads and reads of absent keys, counted through `&self`
+    read_counts: ReadCounts,
+
+    /// Where the last `compact_for` stopped, if it didn't finish
+    compaction_resume: Option<CompactionResume>,
+
+    /// SSTables read states still use; tables dropped from the tree are
+    /// deleted once their last state goes
+    table_refs: TableRefs,
+
+    /// Statistics: number of Bloom filter checks that returned "definitely not"
+    bloom_filter_negatives: usize,
+
+    /// Statistics: number of Bloom filter checks that returned "maybe yes"
+    bloom_filter_positives: usize,
+
+    /// Latency histograms of gets, puts and flushes
+    latency: LatencyStats,
+
+    /// Moving average of SSTables probed per get
+    read_amp: ReadAmpTracker,
+
+    /// Key and value bytes written by users since the tree was opened
+    user_bytes_written: u64,
+
+    /// SSTable bytes written by flushes and purges since the tree was
+    /// opened
+    sstable_bytes_written: u64,
+
+    /// Puts skipped by `LSMConfig::skip_identical_writes` since the tree
+    /// was opened
+    skipped_writes: u64,
+
+    /// Recent WAL and SSTable throughput (`io_rates`)
+    io_rates: IoRateTrackers,
+
+    /// Lookups that gave up at `ReadOptions::max_tables_probed` since the
+    /// tree was opened
+    read_budget_exceeded: u64,
+
+    /// Sequences pinned by `pin_sequence`, with how often each is pinned
+    pinned_sequences: BTreeMap<u64, usize>,
+}
+
+impl LSMTree {
+    /// Creates a new LSM tree with specified configuration
+    pub fn new(data_dir: PathBuf, memtable_size_threshold: usize) -> std::io::Result<Self> {
+        Self::with_bloom_filter_fpp(data_dir, memtable_size_threshold, DEFAULT_BLOOM_FILTER_FPP)
+    }
+
+    /// Creates a new LSM tree with custom Bloom filter false positive probability
+    pub fn with_bloom_filter_fpp(
+        data_dir: PathBuf,
+        memtable_size_threshold: usize,
+        bloom_filter_fpp: f64,
+    ) -> std::io::Result<Self> {
+        Self::open(LSMConfig {
+            memtable_size_threshold,
+            bloom_filter_fpp,
+            ..LSMConfig::new(data_dir)
+        })
+    }
+
+    /// Opens (or creates) an LSM tree with the given configuration
+    pub fn open(config: LSMConfig) -> std::io::Result<Self> {
+        Self::open_with_progress(config, |_| {})
+    }
+
+    /// Opens (or creates) an LSM tree, reporting progress along the way
+    ///
+    /// `progress` is called synchronously on the opening thread, so the
+    /// open waits for it every time: it should only record or draw the
+    /// progress and return. It is called once per SSTable in the first two
+    /// phases and at most once per 1024 WAL entries while replaying (see
+    /// `OpenProgress` for the phases).
+    pub fn open_with_progress(
+        config: LSMConfig,
+        progress: impl FnMut(OpenProgress),
+    ) -> std::io::Result<Self> {
+        Self::open_with_progress_and_report(config, progress).map(|(tree, _)| tree)
+    }
+
+    /// Opens (or creates) an LSM tree and reports what recovery did: WAL
+    /// entries replayed and dropped, SSTables loaded and quarantined, Bloom
+    /// filters loaded and rebuilt, and temporary files removed
+    pub fn open_with_report(config: LSMConfig) -> std::io::Result<(Self, OpenReport)> {
+        Self::open_with_progress_and_report(config, |_| {})
+    }
+
+    /// `open_with_progress` and `open_with_report` in one
+    pub fn open_with_progress_and_report(
+        config: LSMConfig,
+        mut progress: impl FnMut(OpenProgress),
+    ) -> std::io::Result<(Self, OpenReport)> {
+        let started = std::time::Instant::now();
+        let mut report = OpenReport::default();
+        let data_dir = &config.data_dir;
+        config
+            .check_buffer_sizes()
+            .map_err(|reason| std::io::Error::new(std::io::ErrorKind::InvalidInput, reason))?;
+        if config.write_shards > 1 || data_dir.join(sharded::SHARDS_FILE).exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is (or is configured as) a sharded tree; open it with ShardedLSMTree::open",
+                    data_dir.display()
+                ),
+            ));
+        }
+        let dir_lock = if config.read_only {
+            // Nothing to create; the tree has to exist already
+            std::fs::metadata(data_dir).context(Operation::ReadDir, data_dir)?;
+            None
+        } else {
+            std::fs::create_dir_all(data_dir).context(Operation::CreateDir, data_dir)?;
+            let wal_dir = config.resolved_wal_dir();
+            std::fs::create_dir_all(wal_dir).context(Operation::CreateDir, wal_dir)?;
+            if let Some(cold_dir) = &config.cold_dir {
+                std::fs::create_dir_all(cold_dir).context(Operation::CreateDir, cold_dir)?;
+            }
+            let lock = DirLock::acquire(data_dir).context(Operation::LockDir, data_dir)?;
+            // Nothing else writes the directory while the lock is held
+            report.tmp_files_removed =
+                Self::remove_tmp_files(data_dir).context(Operation::ReadDir, data_dir)?;
+            report.orphan_sidecars_removed =
+                Self::remove_orphan_sidecars(data_dir, &TableRefs::default())
+                    .context(Operation::ReadDir, data_dir)?;
+            if let Some(cold_dir) = &config.cold_dir {
+                let removed =
+                    Self::remove_tmp_files(cold_dir).context(Operation::ReadDir, cold_dir)?;
+                report.tmp_files_removed.extend(removed);
+                let removed = Self::remove_orphan_sidecars(cold_dir, &TableRefs::default())
+                    .context(Operation::ReadDir, cold_dir)?;
+                report.orphan_sidecars_removed.extend(removed);
+            }
+            Some(lock)
+        };
+
+        // Nothing else is read before the directory is known to be readable
+        let tree_id = Self::check_format(&config)?;
+
+        // Load the manifest before touching the WAL, so a mismatched WAL
+        // directory is reported instead of creating a fresh, empty log
+        let loaded = Self::load_existing_sstables(&config, tree_id, &mut progress, &mut report)
+            .context(Operation::ReadDir, &config.data_dir)?;
+
+        let wal_path = config.resolved_wal_dir().join("wal.log");
+        let replay_started = Instant::now();
+        // Replay stops at a torn or damaged entry. A writable log is cut
+        // there, so new appends follow the last good entry instead of
+        // sitting behind bytes that would stop the next replay too.
+        let recovery =
+            WAL::replay_buffered(&wal_path, config.size_limits, config.read_buffer_bytes)
+                .context(Operation::OpenWal, &wal_path)?;
+        let wal = if config.read_only {
+            None
+        } else {
+            let mut wal = WAL::with_storage(
+                wal_path.clone(),
+                config.clock.clone(),
+                config.storage.clone(),
+            )
+            .context(Operation::OpenWal, &wal_path)?;
+            if recovery.dropped_bytes() > 0 {
+                wal.truncate_to(recovery.valid_len)
+                    .context(Operation::OpenWal, &wal_path)?;
+            }
+            Some(wal)
+        };
+        report.wal_bytes_replayed = recovery.valid_len;
+        report.wal_bytes_dropped = recovery.dropped_bytes();
+        report.wal_corruption = recovery.corruption;
+        let entries = recovery.entries;
+        let wal_entries_at_open = entries.len() as u64;
+
+        let bloom_filters =
+            FilterCache::new(loaded.bloom_filters, config.bloom_filter_memory_budget);
+        let mut memtable = Memtable::with_backend(config.memtable_backend);
+
+        // A crash between writing the manifest and clearing the WAL leaves
+        // entries in the log that are already in an SSTable. Replaying them
+        // again would resurrect them in the memtable, so skip anything at
+        // or below the highest flushed sequence. Entries from logs written
+        // before sequence numbers existed (seq 0) are always replayed.
+        let flushed_sequence = loaded
+            .tables
+            .iter()
+            .map(|t| t.max_sequence)
+            .max()
+            .unwrap_or(0);
+        let mut last_sequence = flushed_sequence;
+
+        let mut replay = OpenProgress {
+            phase: OpenPhase::ReplayingWal,
+            done: 0,
+            total: entries.len() as u64,
+            bytes_done: 0,
+            bytes_total: entries.iter().map(|e| e.encoded_len()).sum(),
+        };
+        progress(replay);
+
+        let mut replayed = None;
+        for entry in entries {
+            replay.done += 1;
+            replay.bytes_done += entry.encoded_len();
+            if replay.done.is_multiple_of(WAL_PROGRESS_INTERVAL) && replay.done < replay.total {
+                progress(replay);
+            }
+
+            if entry.seq != 0 && entry.seq <= flushed_sequence {
+                report.wal_entries_skipped += 1;
+                continue;
+            }
+            last_sequence = last_sequence.max(entry.seq);
+            report.wal_entries_replayed += 1;
+
+            let value = match entry.op {
+                WALOp::Put => Some(entry.value),
+                // Keep a tombstone so the delete still shadows older SSTables
+                WALOp::Delete => None,
+            };
+            memtable.insert_version(entry.key, value, entry.seq, None);
+            replayed =
+                Some(replayed.map_or((entry.seq, entry.seq), |(first, _)| (first, entry.seq)));
+        }
+        if replay.total > 0 {
+            progress(replay);
+        }
+        let replay_rate =
+            recovery::measured_rate(report.wal_bytes_replayed, replay_started.elapsed());
+        // When the replayed writes happened isn't known, only that they did
+        let mut memtable_write_times = WriteTimes::default();
+        if let Some((first, last)) = replayed {
+            memtable_write_times.record_unknown(first, last);
+        }
+
+        let ordering_anomalies = Self::find_ordering_anomalies(&loaded.tables);
+        // Replayed writes count as arriving now, so an idle flush waits a
+        // full window after a restart rather than firing straight away
+        let opened_at = config.clock.now();
+        let last_write = (!memtable.is_empty()).then_some(opened_at);
+
+        let pinned_tables = PinnedTables::new(
+            config.pin_small_sstables_bytes.unwrap_or(0),
+            config.small_sstable_max_bytes,
+        );
+        let mut tree = Self {
+            memtable: Arc::new(memtable),
+            config,
+            tree_id,
+            next_generation: loaded
+                .tables
+                .iter()
+                .map(|t| t.generation)
+                .max()
+                .unwrap_or(0)
+                + 1,
+            sstables: loaded.tables,
+            quarantined: loaded.quarantined,
+            ordering_anomalies,
+            last_sequence,
+            last_write,
+            last_flush: opened_at,
+            last_compaction: None,
+            memtable_write_times,
+            sstable_counter: loaded.next_file_number,
+            wal,
+            wal_entries_at_open,
+            replay_rate,
+            recovery_warned: false,
+            wal_failures: 0,
+            poison: None,
+            deleted: None,
+            flush_pauses: 0,
+            corrupt_reads: Vec::new(),
+            _dir_lock: dir_lock,
+            bloom_filters,
+            bloom_filter_negatives: 0,
+            bloom_filter_positives: 0,
+            latency: LatencyStats::default(),
+            read_amp: ReadAmpTracker::default(),
+            user_bytes_written: 0,
+            sstable_bytes_written: 0,
+            skipped_writes: 0,
+            io_rates: IoRateTrackers::default(),
+            read_budget_exceeded: 0,
+            pinned_sequences: BTreeMap::new(),
+            pinned_tables,
+            table_refs: TableRefs::default(),
+            workload: WorkloadStats::default(),
+            read_counts: ReadCounts::default(),
+            compaction_resume: None,
+        };
+        if !tree.config.read_only
+            && let Some(threshold) = tree.config.small_table_merge_threshold
+        {
+            let merged = tree.merge_small_tables(threshold);
+            (report.small_tables_merged, report.merged_tables_written) =
+                merged.context(Operation::Compact, &tree.config.data_dir)?;
+        }
+        tree.repin_small_tables();
+        report.duration = started.elapsed();
+        report.log();
+        Ok((tree, report))
+    }
+
+    /// Removes the temporary files of writes a crash interrupted
+    /// (`MANIFEST.tmp`, `FORMAT.tmp`, `sstable_<n>.db.tmp`, and the copies
+    /// `migrate_cold` makes)
+    ///
+    /// They are only ever renamed into place by the write that created
+    /// them, so once that write is gone they are garbage.
+    fn remove_tmp_files(data_dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+        for entry in std::fs::read_dir(data_dir)? {
+            let path = entry?.path();
+            if path.extension() == Some("tmp".as_ref()) && path.is_file() {
+                std::fs::remove_file(&path)?;
+                removed.push(path);
+            }
+        }
+        removed.sort();
+        Ok(removed)
+    }
+
+    /// Removes the sidecar files (see `SIDECAR_EXTENSIONS`) of SSTables
+    /// that no longer exist
+    ///
+    /// A crash between deleting a table and its filter, or a table deleted
+    /// by hand, leaves them behind. A sidecar stays as long as its `.db`
+    /// file does, listed in the manifest or not, and while a `TreeState`
+    /// in `refs` still reads the table.
+    fn remove_orphan_sidecars(dir: &Path, refs: &TableRefs) -> std::io::Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_sidecar = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| SIDECAR_EXTENSIONS.contains(&ext));
+            let table = path.with_extension("db");
+            if !is_sidecar || Self::sstable_number(&table).is_none() || !path.is_file() {
+                continue;
+            }
+            if !table.exists() && !refs.is_used(&table) {
+                std::fs::remove_file(&path)?;
+                removed.push(path);
+            }
+        }
+        removed.sort();
+        Ok(removed)
+    }
+
+    /// Removes orphaned sidecars from the data and cold directories
+    fn remove_orphans(&self) -> std::io::Result<usize> {
+        let mut removed = Self::remove_orphan_sidecars(&self.config.data_dir, &self.table_refs)?;
+        if let Some(cold_dir) = &self.config.cold_dir {
+            removed.extend(Self::remove_orphan_sidecars(cold_dir, &self.table_refs)?);
+        }
+        Ok(removed.len())
+    }
+
+    /// Rebuilds the manifest of a data directory from the SSTables in it
+    ///
+    /// For a directory whose manifest was lost or damaged: every table is
+    /// checked, damaged ones are quarantined, the rest are ordered by the
+    /// sequence numbers they hold (or their modification time, for tables
+    /// without any), missing Bloom filters are rebuilt and a new manifest
+    /// is written. See `repair` for the details. No file is deleted, and a
+    /// directory locked by an open tree is refused with `WouldBlock`.
+    pub fn repair(data_dir: &Path, options: &RepairOptions) -> std::io::Result<RepairReport> {
+        Self::repair_with(data_dir, options, &CancellationToken::new())
+    }
+
+    /// `repair`, stopping with a `Cancelled` error once `cancel` is
+    /// cancelled
+    ///
+    /// The token is checked before every record the repair reads. A repair
+    /// stopped part way writes no manifest: the directory keeps the one it
+    /// had, if any, and a rerun starts over. Tables it already found
+    /// damaged stay quarantined, and filters it already rebuilt stay.
+    pub fn repair_with(
+        data_dir: &Path,
+        options: &RepairOptions,
+        cancel: &CancellationToken,
+    ) -> std::io::Result<RepairReport> {
+        repair::repair(data_dir, options, cancel)
+    }
+
+    /// Deletes a tree's data directory and everything in it
+    ///
+    /// A directory locked by an open tree is refused with `WouldBlock`, and
+    /// one that holds neither a FORMAT file nor a manifest, so doesn't look
+    /// like a tree, with `InvalidInput`. A `wal_dir` or `cold_dir` outside
+    /// the data directory is left alone. Read-only trees don't lock the
+    /// directory; one still open on it fails with `TreeDeleted` from then
+    /// on.
+    pub fn destroy(data_dir: &Path) -> std::io::Result<()> {
+        let is_tree =
+            DirectoryFormat::path(data_dir).exists() || data_dir.join(MANIFEST_FILE).exists();
+        if !is_tree {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} doesn't hold a tree", data_dir.display()),
+            ));
+        }
+        let lock = DirLock::acquire(data_dir)?;
+        // Everything but the lock goes while it is held
+        for entry in std::fs::read_dir(data_dir)? {
+            let path = entry?.path();
+            if path.file_name().is_some_and(|name| name == LOCK_FILE) {
+                continue;
+            }
+            if path.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            } else {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        drop(lock);
+        std::fs::remove_dir_all(data_dir)
+    }
+
+    /// Converts a directory written by the old demo binary into a new tree
+    /// in `dst`
+    ///
+    /// The source is only read. Its tables are applied oldest first (by
+    /// modification time, as the old binary's restarting file counter
+    /// makes numbers meaningless), then its WAL, and the newest value of
+    /// every key is written to `dst` with `from_iter`. The report lists
+    /// every guess the order rested on. See `legacy_import` for the
+    /// details. A source with a manifest is refused with `InvalidInput`,
+    /// and a non-empty `dst` with `AlreadyExists`.
+    pub fn import_legacy_dir(src: &Path, dst: &Path) -> std::io::Result<ImportReport> {
+        legacy_import::import(src, dst)
+    }
+
+    /// Loads `key<TAB>value` lines from a reader, syncing the WAL once per
+    /// chunk of rows rather than once per row
+    ///
+    /// The report says how many rows are durable. A load that fails part
+    /// way returns an error holding a `LoadInterrupted` with the same
+    /// count, from which `LoadOptions::resume_from` or a
+    /// `LoadOptions::progress_key` picks the load up again without losing
+    /// or repeating rows. See `load` for the format and the durability
+    /// contract.
+    pub fn load_from_reader(
+        &mut self,
+        reader: impl std::io::BufRead,
+        options: &LoadOptions,
+    ) -> std::io::Result<LoadReport> {
+        load::load(self, reader, options)
+    }
+
+    /// Writes every live key-value pair to `writer` as a snapshot stream
+    ///
+    /// The stream holds the newest value of every key, memtable included,
+    /// in key order, with none of the tree's own file formats in it, so a
+    /// snapshot taken here can be imported by a build that writes its
+    /// SSTables differently (see `snapshot` for the layout). The live keys
+    /// are counted first, for the header, then written with their values;
+    /// nothing is held in memory beyond the current pair. The writer isn't
+    /// buffered here.
+    pub fn export_snapshot(&self, writer: impl std::io::Write) -> std::io::Result<SnapshotInfo> {
+        self.export_snapshot_with(writer, &CancellationToken::new())
+    }
+
+    /// `export_snapshot`, stopping with a `Cancelled` error once `cancel`
+    /// is cancelled
+    ///
+    /// The token is checked before every key counted and every pair
+    /// written; the error says how many pairs went out. The stream is left
+    /// without its trailer, so `import_snapshot` refuses it as cut short;
+    /// deleting it is up to the caller.
+    pub fn export_snapshot_with(
+        &self,
+        writer: impl std::io::Write,
+        cancel: &CancellationToken,
+    ) -> std::io::Result<SnapshotInfo> {
+        cancel.check(0)?;
+        let entries = self.keys::<&[u8], _>(..).with_cancellation(cancel).count() as u64;
+        cancel.check(0)?;
+        let mut snapshot =
+            snapshot::SnapshotWriter::new(writer, entries, self.config.clock.now_millis())?;
+        let mut written = 0;
+        for (key, value) in self.range::<&[u8], _>(..).with_cancellation(cancel) {
+            snapshot.add(&key, &value)?;
+            written += 1;
+        }
+        cancel.check(written)?;
+        snapshot.finish()
+    }
+
+    /// Loads a snapshot stream written by `export_snapshot` into the tree
+    ///
+    /// The memtable is flushed first, then the pairs are written straight
+    /// into new SSTables, never through the WAL, as `from_iter` does; they
+    /// become the newest data in the tree, so an imported value replaces
+    /// the tree's own value of the same key. Nothing joins the tree before
+    /// the stream's checksum has been checked: a snapshot that is cut
+    /// short, damaged or out of key order fails with `InvalidData`, and a
+    /// pair over `size_limits` with `InvalidInput`, leaving the tree as it
+    /// was. A key that comes twice fails it too, unless
+    /// `LSMConfig::ingest_dedup` says which value to keep.
+    pub fn import_snapshot(&mut self, reader: impl std::io::Read) -> std::io::Result<SnapshotInfo> {
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+        self.flush()?;
+        let mut snapshot = snapshot::SnapshotReader::new(reader)?;
+        let limits = self.config.size_limits;
+        let entries = snapshot.by_ref().map(|entry| {
+            let (key, value) = entry?;
+            limits.check(&key, Some(&value))?;
+            Ok((key, value))
+        });
+        let data_dir = self.config.data_dir.clone();
+        self.bulk_load(entries)
+            .context(Operation::Flush, &data_dir)?;
+        Ok(snapshot.info())
+    }
+
+    /// Returns the path of the SSTable with the given file number
+    pub(crate) fn sstable_path(data_dir: &Path, number: usize) -> PathBuf {
+        data_dir.join(format!("sstable_{}.db", number))
+    }
+
+    /// Returns the path of a table listed in the manifest, in the data
+    /// directory or the cold directory
+    fn table_path(config: &LSMConfig, table: &TableEntry) -> PathBuf {
+        let dir = match &config.cold_dir {
+            Some(cold_dir) if table.cold => cold_dir,
+            _ => &config.data_dir,
+        };
+        Self::sstable_path(dir, table.number)
+    }
+
+    /// Whether a table lives in the cold directory
+    fn is_cold(&self, path: &Path) -> bool {
+        self.config
+            .cold_dir
+            .as_deref()
+            .is_some_and(|cold_dir| path.parent() == Some(cold_dir))
+    }
+
+    /// Directory new tables go to: the cold directory for cold ones, if
+    /// there is one
+    fn table_dir(&self, cold: bool) -> &Path {
+        match &self.config.cold_dir {
+            Some(cold_dir) if cold => cold_dir,
+            _ => &self.config.data_dir,
+        }
+    }
+
+    /// Whether `LSMConfig::cold_policy` puts a table in the cold directory
+    /// (never without one)
+    fn belongs_cold(&self, compacted: bool, write_times: &WriteTimes) -> bool {
+        if self.config.cold_dir.is_none() {
+            return false;
+        }
+        match self.config.cold_policy {
+            ColdPolicy::Compacted => compacted,
+            ColdPolicy::OlderThan(age) => {
+                let now = self.config.clock.now();
+                write_times.span().is_some_and(|(_, newest)| {
+                    now.duration_since(newest)
+                        .is_ok_and(|elapsed| elapsed >= age)
+                })
+            }
+        }
+    }
+
+    /// Syncs the cold directory, if there is one, so new entries in it
+    /// survive a power failure
+    fn sync_cold_dir(&self) -> std::io::Result<()> {
+        match &self.config.cold_dir {
+            Some(cold_dir) => self.config.storage.sync_dir(cold_dir),
+            None => Ok(()),
+        }
+    }
+
+    /// Extracts the file number from an `sstable_<n>.db` path
+    pub(crate) fn sstable_number(path: &Path) -> Option<usize> {
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix("sstable_"))
+            .and_then(|n| n.strip_suffix(".db"))
+            .and_then(|n| n.parse().ok())
+    }
+
+    /// Checks the directory's FORMAT file and records what this tree needs
+    ///
+    /// A directory without one (new, or created before FORMAT files
+    /// existed) gets one, and one without a tree id gets a new id, unless
+    /// the tree is read-only. Returns the tree id.
+    fn check_format(config: &LSMConfig) -> std::io::Result<Option<TreeId>> {
+        let data_dir = config.data_dir.as_path();
+        let wanted = DirectoryFormat::for_compression(config.compression);
+        let stored = DirectoryFormat::load(data_dir)?;
+        if let Some(found) = &stored {
+            found.check(data_dir)?;
+        }
+        let mut merged = match &stored {
+            Some(found) => found.merged_with(&wanted),
+            None => wanted,
+        };
+        if !config.read_only {
+            merged.tree_id.get_or_insert_with(TreeId::generate);
+            if stored.as_ref() != Some(&merged) {
+                merged.store(data_dir)?;
+            }
+        }
+        Ok(merged.tree_id)
+    }
+
+    /// Finds the live SSTables, from the manifest when there is one
+    ///
+    /// Directories created before manifests existed are scanned for
+    /// `sstable_<n>.db` files (higher numbers are newer) and a manifest is
+    /// written for them, so later opens no longer depend on the scan.
+    fn load_existing_sstables(
+        config: &LSMConfig,
+        tree_id: Option<TreeId>,
+        progress: &mut dyn FnMut(OpenProgress),
+        report: &mut OpenReport,
+    ) -> std::io::Result<LoadedSSTables> {
+        let data_dir = config.data_dir.as_path();
+        let mut manifest = match Manifest::load(data_dir)? {
+            Some(mut manifest) => {
+                Self::check_wal_dir(config, &manifest)?;
+                Self::check_cold_dir(config, &manifest)?;
+                // Tables removed by hand can't be served; forget about them
+                manifest
+                    .sstables
+                    .retain(|t| Self::table_path(config, t).exists());
+                Self::assign_missing_generations(&mut manifest);
+                manifest
+            }
+            None => {
+                let mut manifest = Self::scan_sstable_files(data_dir)?;
+                Self::assign_missing_generations(&mut manifest);
+                // Before manifests existed the WAL always lived in the data
+                // directory; don't strand a log that still holds writes
+                let old_wal = data_dir.join("wal.log");
+                if std::fs::metadata(&old_wal).is_ok_and(|m| m.len() > 0) {
+                    Self::check_wal_dir(config, &manifest)?;
+                }
+                manifest.wal_dir = config.wal_dir.clone();
+                if !config.read_only {
+                    manifest.store(data_dir)?;
+                }
+                manifest
+            }
+        };
+
+        // Generations, not line order, define which table is newer
+        manifest
+            .sstables
+            .sort_by_key(|t| std::cmp::Reverse(t.generation));
+
+        let file_size = |path: &Path| std::fs::metadata(path).map_or(0, |m| m.len());
+
+        let table_sizes: Vec<u64> = manifest
+            .sstables
+            .iter()
+            .map(|t| file_size(&Self::table_path(config, t)))
+            .collect();
+        let mut scan = OpenProgress {
+            phase: OpenPhase::ScanningDirectory,
+            done: 0,
+            total: manifest.sstables.len() as u64,
+            bytes_done: 0,
+            bytes_total: table_sizes.iter().sum(),
+        };
+        progress(scan);
+
+        let mut tables = Vec::with_capacity(manifest.sstables.len());
+        let mut quarantined = Vec::new();
+
+        for (table, table_size) in manifest.sstables.iter().zip(table_sizes) {
+            let sstable_path = Self::table_path(config, table);
+            scan.done += 1;
+            scan.bytes_done += table_size;
+
+            if let Some(damaged) = Self::check_sstable(config, tree_id, &sstable_path)? {
+                quarantined.push(damaged);
+                progress(scan);
+                continue;
+            }
+
+            tables.push(SSTableInfo {
+                write_times: Self::read_write_times(&sstable_path),
+                path: sstable_path,
+                generation: table.generation,
+                max_sequence: table.max_sequence,
+                stats: table.stats,
+                compacted: table.compacted,
+                reads: Arc::default(),
+            });
+            progress(scan);
+        }
+
+        // Measured up front: rebuilt filters only get their file below
+        let filter_sizes: Vec<u64> = tables
+            .iter()
+            .map(|t| file_size(&t.path.with_extension("bloom")))
+            .collect();
+        let mut load = OpenProgress {
+            phase: OpenPhase::LoadingFilters,
+            done: 0,
+            total: tables.len() as u64,
+            bytes_done: 0,
+            bytes_total: filter_sizes.iter().sum(),
+        };
+        progress(load);
+
+        let mut bloom_filters = Vec::with_capacity(tables.len());
+        for (table, filter_size) in tables.iter().zip(filter_sizes) {
+            let filter = Self::load_filter(config, &table.path)?;
+            match filter {
+                LoadedFilter::Loaded(_) => report.filters_loaded += 1,
+                LoadedFilter::Rebuilt(_) => report.filters_rebuilt += 1,
+                LoadedFilter::Missing => report.filters_missing += 1,
+                LoadedFilter::Disabled => {}
+            }
+            bloom_filters.push(filter.into_filter());
+
+            load.done += 1;
+            load.bytes_done += filter_size;
+            progress(load);
+        }
+
+        if !quarantined.is_empty() && !config.read_only {
+            // Stop listing the quarantined tables so the next open doesn't
+            // look for them again
+            let numbers: Vec<usize> = tables
+                .iter()
+                .filter_map(|t: &SSTableInfo| Self::sstable_number(&t.path))
+                .collect();
+            manifest.sstables.retain(|t| numbers.contains(&t.number));
+            manifest.store(data_dir)?;
+        }
+        report.sstables_loaded = tables.len();
+        report.sstables_quarantined = quarantined.len();
+
+        Ok(LoadedSSTables {
+            tables,
+            quarantined,
+            bloom_filters,
+            next_file_number: manifest.next_file_number,
+        })
+    }
+
+    /// Checks a table before it joins the tree
+    ///
+    /// Returns the quarantined table if it is damaged (renamed aside, or
+    /// only reported when the tree is read-only), or fails with
+    /// `paranoid_checks`. A table whose only damage is its footer stays in
+    /// the tree without `paranoid_checks`, read without the footer's help.
+    /// The check also rejects files from a newer build
+    /// instead of misreading them, and tables stamped by another tree
+    /// (whatever `paranoid_checks` says).
+    pub(crate) fn check_sstable(
+        config: &LSMConfig,
+        tree_id: Option<TreeId>,
+        sstable_path: &Path,
+    ) -> std::io::Result<Option<QuarantinedSSTable>> {
+        Self::check_sstable_with(config, tree_id, sstable_path, &CancellationToken::new())
+    }
+
+    /// `check_sstable`, failing with `Cancelled` once `cancel` is
+    /// cancelled; a table whose check was cut short is left where it is
+    pub(crate) fn check_sstable_with(
+        config: &LSMConfig,
+        tree_id: Option<TreeId>,
+        sstable_path: &Path,
+        cancel: &CancellationToken,
+    ) -> std::io::Result<Option<QuarantinedSSTable>> {
+        // Not damage: the table may be fine, it just isn't this tree's
+        if let Some(expected) = tree_id
+            && let Ok(Some(found)) = sstable::tree_id(sstable_path)
+            && found != expected
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "SSTable {} belongs to tree {}, but {} is tree {}; adopt it with \
+                     LSMTree::ingest_sstable_file instead of copying it in",
+                    sstable_path.display(),
+                    found,
+                    config.data_dir.display(),
+                    expected
+                ),
+            ));
+        }
+        let Some(reason) =
+            sstable::check_integrity_with(sstable_path, config.read_buffer_bytes, cancel)?
+        else {
+            return Ok(None);
+        };
+        // The records are fine: without the footer, reads scan the table
+        if reason == Corruption::BadFooter && !config.paranoid_checks {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                path = %sstable_path.display(),
+                "SSTable footer is damaged; reading the table without it"
+            );
+            return Ok(None);
+        }
+        if config.paranoid_checks {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Corruption in SSTable {}: {}; open with paranoid_checks = false \
+                     to quarantine it and continue",
+                    sstable_path.display(),
+                    reason
+                ),
+            ));
+        }
+        Ok(Some(if config.read_only {
+            QuarantinedSSTable {
+                original_path: sstable_path.to_path_buf(),
+                quarantine_path: None,
+                reason,
+            }
+        } else {
+            Self::quarantine_sstable(sstable_path, reason)?
+        }))
+    }
+
+    /// Loads a table's Bloom filter, rebuilding it if the `.bloom` file is
+    /// missing or unreadable
+    ///
+    /// `None` leaves the table without a filter: always with
+    /// `FilterPolicy::None` (nothing is read) or when read-only (rebuilding
+    /// writes a new file), and when the rebuild fails without
+    /// `paranoid_checks`.
+    fn load_filter(config: &LSMConfig, sstable_path: &Path) -> std::io::Result<LoadedFilter> {
+        if config.filter_policy == FilterPolicy::None {
+            return Ok(LoadedFilter::Disabled);
+        }
+        Ok(match filter_cache::load(sstable_path) {
+            Some(bloom_filter) => LoadedFilter::Loaded(bloom_filter),
+            None if config.read_only => LoadedFilter::Missing,
+            None => match Self::rebuild_bloom_filter(
+                sstable_path,
+                config.bloom_filter_fpp,
+                config.bloom_filter_floor,
+                config.read_buffer_bytes,
+            ) {
+                Ok(bloom_filter) => LoadedFilter::Rebuilt(bloom_filter),
+                Err(e) if config.paranoid_checks => {
+                    return Err(std::io::Error::new(
+                        e.kind(),
+                        format!(
+                            "Failed to rebuild Bloom filter for {}: {}",
+                            sstable_path.display(),
+                            e
+                        ),
+                    ));
+                }
+                // A guessed filter could wrongly skip the table; without
+                // one the table is simply always probed
+                Err(_) => LoadedFilter::Missing,
+            },
+        })
+    }
+
+    /// Fails if the config points the WAL somewhere other than the manifest
+    ///
+    /// Opening with the wrong directory would start an empty log while the
+    /// real one, with all unflushed writes, sits unread elsewhere.
+    fn check_wal_dir(config: &LSMConfig, manifest: &Manifest) -> std::io::Result<()> {
+        let recorded = manifest.wal_dir.as_deref().unwrap_or(&config.data_dir);
+        let configured = config.resolved_wal_dir();
+
+        let canonical = |p: &Path| std::fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        if canonical(recorded) == canonical(configured) {
+            return Ok(());
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Tree in {} keeps its WAL in {}, but it was opened with WAL directory {}; \
+                 open it with the original wal_dir (or move wal.log and update the manifest)",
+                config.data_dir.display(),
+                recorded.display(),
+                configured.display()
+            ),
+        ))
+    }
+
+    /// Fails if the manifest lists cold tables in a directory other than
+    /// the configured `cold_dir`
+    ///
+    /// Those tables would be looked for in the wrong place and dropped
+    /// from the tree as missing.
+    fn check_cold_dir(config: &LSMConfig, manifest: &Manifest) -> std::io::Result<()> {
+        if !manifest.sstables.iter().any(|t| t.cold) {
+            return Ok(());
+        }
+        let canonical = |p: &Path| std::fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        let recorded = manifest.cold_dir.as_deref().map(canonical);
+        if recorded.is_some() && recorded == config.cold_dir.as_deref().map(canonical) {
+            return Ok(());
+        }
+
+        let display =
+            |dir: Option<&Path>| dir.map_or("none".to_string(), |d| d.display().to_string());
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Tree in {} keeps cold SSTables in {}, but it was opened with cold directory {}; \
+                 open it with the original cold_dir",
+                config.data_dir.display(),
+                display(manifest.cold_dir.as_deref()),
+                display(config.cold_dir.as_deref())
+            ),
+        ))
+    }
+
+    /// Moves a damaged SSTable aside as `<name>.db.corrupt`
+    ///
+    /// Its Bloom filter is derived data and is simply removed.
+    fn quarantine_sstable(path: &Path, reason: Corruption) -> std::io::Result<QuarantinedSSTable> {
+        let mut corrupt_name = path.as_os_str().to_owned();
+        corrupt_name.push(".corrupt");
+        let corrupt_path = PathBuf::from(corrupt_name);
+
+        std::fs::rename(path, &corrupt_path)?;
+        let _ = std::fs::remove_file(path.with_extension("bloom"));
+
+        Ok(QuarantinedSSTable {
+            original_path: path.to_path_buf(),
+            quarantine_path: Some(corrupt_path),
+            reason,
+        })
+    }
+
+    /// Finds unsequenced tables that were modified after a table ordered
+    /// as newer than them (`tables` is newest first)
+    ///
+    /// Tables written before sequence numbers existed were ordered by file
+    /// number alone, and builds of that era restarted numbering at 0 on
+    /// every start, overwriting the oldest files with new data. For those
+    /// tables the modification time is the better witness of recency.
+    /// Sequenced tables are left out: their order was recorded when they
+    /// were written, and rewrites like compaction legitimately give older
+    /// data a newer file.
+    fn find_ordering_anomalies(tables: &[SSTableInfo]) -> Vec<OrderingAnomaly> {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let unsequenced: Vec<_> = tables
+            .iter()
+            .filter(|t| t.max_sequence == 0)
+            .filter_map(|t| Some((&t.path, modified(&t.path)?)))
+            .collect();
+
+        // Any inversion implies one between neighbours, so this is enough
+        // to tell whether the order holds
+        unsequenced
+            .windows(2)
+            .filter(|pair| pair[0].1 < pair[1].1)
+            .map(|pair| OrderingAnomaly {
+                newer: pair[0].0.clone(),
+                older: pair[1].0.clone(),
+            })
+            .collect()
+    }
+
+    /// Gives every table a generation if the manifest predates them
+    ///
+    /// Older manifests (and directory scans) only know the newest-first
+    /// order, so generations are derived from it: the oldest table gets 1.
+    fn assign_missing_generations(manifest: &mut Manifest) {
+        if manifest.sstables.iter().all(|t| t.generation != 0) {
+            return;
+        }
+        let count = manifest.sstables.len() as u64;
+        for (i, table) in manifest.sstables.iter_mut().enumerate() {
+            table.generation = count - i as u64;
+        }
+    }
+
+    /// Builds a manifest from the `sstable_<n>.db` files found in a directory
+    fn scan_sstable_files(data_dir: &Path) -> std::io::Result<Manifest> {
+        let mut numbers = Vec::new();
+        for entry in std::fs::read_dir(data_dir)? {
+            if let Some(num) = Self::sstable_number(&entry?.path()) {
+                numbers.push(num);
+            }
+        }
+
+        numbers.sort_by_key(|&n| std::cmp::Reverse(n));
+
+        Ok(Manifest {
+            next_file_number: numbers.first().map_or(0, |n| n + 1),
+            wal_dir: None,
+            cold_dir: None,
+            sstables: numbers
+                .into_iter()
+                .map(|number| TableEntry {
+                    number,
+                    ..TableEntry::default()
+                })
+                .collect(),
+        })
+    }
+
+    /// Writes the current SSTable list to the manifest
+    fn write_manifest(&self) -> std::io::Result<()> {
+        self.manifest().store(&self.config.data_dir)
+    }
+
+    /// Reads the write times from an SSTable's header
+    ///
+    /// A table whose header can't be read is treated as having none, so it
+    /// never counts as expired.
+    fn read_write_times(path: &Path) -> WriteTimes {
+        SSTableReader::open(path)
+            .and_then(|mut reader| reader.write_times())
+            .unwrap_or_default()
+    }
+
+    /// Opens one of the tree's SSTables through `LSMConfig::storage`, with
+    /// the configured read buffer
+    fn open_sstable(&self, path: &Path) -> std::io::Result<SSTableReader> {
+        SSTableReader::open_with(
+            path,
+            self.config.storage.as_ref(),
+            self.config.read_buffer_bytes,
+        )
+    }
+
+    /// Describes the live SSTables the way the manifest stores them
+    fn manifest(&self) -> Manifest {
+        Manifest {
+            next_file_number: self.sstable_counter,
+            wal_dir: self.config.wal_dir.clone(),
+            cold_dir: self.config.cold_dir.clone(),
+            sstables: self
+                .read_order()
+                .into_iter()
+                .filter_map(|i| {
+                    let table = &self.sstables[i];
+                    Some(self.table_entry(Self::sstable_number(&table.path)?, table))
+                })
+                .collect(),
+        }
+    }
+
+    /// The manifest entry of a table
+    fn table_entry(&self, number: usize, table: &SSTableInfo) -> TableEntry {
+        TableEntry {
+            number,
+            generation: table.generation,
+            max_sequence: table.max_sequence,
+            stats: table.stats,
+            cold: self.is_cold(&table.path),
+            compacted: table.compacted,
+        }
+    }
+
+    /// Indices into `sstables`, newest generation first
+    ///
+    /// Lookups and merges walk tables in this order rather than trusting
+    /// their position in the vector.
+    fn read_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.sstables.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.sstables[i].generation));
+        order
+    }
+
+    /// SSTable paths, newest generation first (the source order for merges)
+    fn paths_newest_first(&self) -> Vec<PathBuf> {
+        self.read_order()
+            .into_iter()
+            .map(|i| self.sstables[i].path.clone())
+            .collect()
+    }
+
+    /// Rebuilds an SSTable's Bloom filter from its keys and saves it
+    ///
+    /// Fails if the table can't be opened or ends in a truncated record. A
+    /// readable table without records gets an empty filter, which correctly
+    /// answers "no" for every key.
+    ///
+    /// The table is read twice, once to count the keys (the filter has to
+    /// be sized up front) and once to insert them, so no key is ever held
+    /// in memory beyond a single reused buffer. Each pass reads through a
+    /// buffer of `buffer_bytes`.
+    pub(crate) fn rebuild_bloom_filter(
+        sstable_path: &Path,
+        fpp: f64,
+        floor: FilterFloor,
+        buffer_bytes: usize,
+    ) -> std::io::Result<BloomFilter> {
+        let mut key = Vec::new();
+
+        let mut reader = SSTableReader::open_buffered(sstable_path, buffer_bytes)?;
+        let mut count = 0;
+        while reader.next_key_into(&mut key)? {
+            count += 1;
+        }
+
+        let mut bf = BloomFilter::new_floored(count.max(1), fpp, floor)?;
+        let mut reader = SSTableReader::open_buffered(sstable_path, buffer_bytes)?;
+        while reader.next_key_into(&mut key)? {
+            bf.insert(&key);
+        }
+        bf.set_num_items(count);
+
+        // The filter is usable even if it can't be saved; the next open
+        // will simply rebuild it again
+        let _ = Self::write_bloom_filter(sstable_path, &bf, None);
+
+        Ok(bf)
+    }
+
+    /// Writes the `.bloom` file next to an SSTable, syncing it if `storage`
+    /// is given
+    fn write_bloom_filter(
+        sstable_path: &Path,
+        bloom_filter: &BloomFilter,
+        storage: Option<&dyn Storage>,
+    ) -> std::io::Result<()> {
+        let bloom_path = sstable_path.with_extension("bloom");
+        // The old file may be a hard link shared with a clone (see
+        // `clone_to`); replace the link instead of writing through it
+        let _ = std::fs::remove_file(&bloom_path);
+        let mut writer = BufWriter::new(File::create(&bloom_path)?);
+        bloom_filter.write_to(&mut writer)?;
+        writer.flush()?;
+        if let Some(storage) = storage {
+            storage.sync_file(writer.get_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Writer settings for a new SSTable of roughly `expected_items` keys
+    fn writer_options(&self, expected_items: usize, sync: bool) -> SSTableWriterOptions {
+        SSTableWriterOptions {
+            expected_items,
+            filter_policy: self.config.filter_policy,
+            filter_sizing: FilterSizing::Expected,
+            bloom_filter_fpp: self.config.bloom_filter_fpp,
+            filter_floor: self.config.bloom_filter_floor,
+            sync,
+            storage: self.config.storage.clone(),
+            compression: self.config.compression,
+            dictionary: None,
+            write_times: WriteTimes::default(),
+            tree_id: self.tree_id,
+            buffer_bytes: self.config.write_buffer_bytes,
+            clock: self.config.clock.clone(),
+        }
+    }
+
+    /// Trains a value dictionary for a new table of `count` values, if the
+    /// configured compression uses one
+    fn dictionary_for<V: AsRef<[u8]>>(
+        &self,
+        values: impl IntoIterator<Item = V>,
+        count: usize,
+    ) -> Option<Vec<u8>> {
+        if !self.config.compression.wants_dictionary() {
+            return None;
+        }
+        compression::train_dictionary(values, count)
+    }
+
+    /// Inserts or updates a key-value pair
+    ///
+    /// When this returns, the value is in the memtable: every read of the
+    /// tree that follows (`get`, `get_immut`, a `TreeState` taken
+    /// afterwards) sees it, or something newer. That holds too when the
+    /// write itself went through but the flush it set off failed. The same
+    /// goes for `delete` and `write`; `testing::consistency_checker` checks
+    /// it across flushes and compactions.
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> std::io::Result<()> {
+        self.put_opt(key, value, &WriteOptions::default())
+    }
+
+    /// Inserts or updates a key-value pair with per-write options
+    pub fn put_opt(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        options: &WriteOptions,
+    ) -> std::io::Result<()> {
+        self.apply_writes(vec![(key, Some(value))], options)
+    }
+
+    /// Deletes a key
+    ///
+    /// The key is not removed from older SSTables right away; instead a
+    /// tombstone is written that shadows every older version of the key.
+    pub fn delete(&mut self, key: Vec<u8>) -> std::io::Result<()> {
+        self.delete_opt(key, &WriteOptions::default())
+    }
+
+    /// Deletes a key with per-write options
+    pub fn delete_opt(&mut self, key: Vec<u8>, options: &WriteOptions) -> std::io::Result<()> {
+        self.apply_writes(vec![(key, None)], options)
+    }
+
+    /// Applies a batch of puts and deletes (see `WriteBatch`)
+    pub fn write(&mut self, batch: WriteBatch) -> std::io::Result<()> {
+        self.write_opt(batch, &WriteOptions::default())
+    }
+
+    /// Applies a batch of puts and deletes with per-write options
+    pub fn write_opt(&mut self, batch: WriteBatch, options: &WriteOptions) -> std::io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.apply_writes(batch.into_ops(), options)
+    }
+
+    /// Logs writes (`None` values are deletes), then applies them to the
+    /// memtable and flushes it if it is full
+    ///
+    /// Everything is logged before anything is applied, so a flush can't
+    /// clear the WAL while part of the writes are only in the log.
+    ///
+    /// Read-your-writes: the writes are in the memtable before this returns,
+    /// whether it returns `Ok` or the flush that follows fails. Logging
+    /// hands back a `LoggedWrites` that only `publish` consumes, so no path
+    /// through here can return with a write that is only in the log.
+    fn apply_writes(
+        &mut self,
+        mut ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+        options: &WriteOptions,
+    ) -> std::io::Result<()> {
+        let start = Instant::now();
+        // Checked up front: with the WAL disabled nothing else would notice
+        self.writable_wal()?;
+        self.check_not_deleted()?;
+        if let Some(poison) = &self.poison {
+            return Err(poison.error.clone().into());
+        }
+        for (key, value) in &ops {
+            self.config.size_limits.check(key, value.as_deref())?;
+        }
+        self.skip_identical_puts(&mut ops);
+        if ops.is_empty() {
+            self.latency.record(LatencyOp::Put, start.elapsed());
+            return Ok(());
+        }
+
+        let logged = self
+            .log_writes(ops, options)
+            .map_err(|error| self.stale(error))?;
+        self.publish(logged);
+        let result = if self.should_auto_flush() {
+            self.flush()
+        } else {
+            Ok(())
+        };
+        self.check_recovery_estimate();
+
+        self.latency.record(LatencyOp::Put, start.elapsed());
+        result
+    }
+
+    /// Assigns sequence numbers to writes and appends them to the WAL
+    /// (unless `options.disable_wal`), syncing it if asked to
+    ///
+    /// All or nothing: if an append or the sync fails, the log is cut back
+    /// to where the writes began (see `wal_failed`).
+    fn log_writes(
+        &mut self,
+        ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+        options: &WriteOptions,
+    ) -> std::io::Result<LoggedWrites> {
+        let seqs: Vec<u64> = ops.iter().map(|_| self.next_sequence()).collect();
+        if !options.disable_wal {
+            let sync = options.sync.unwrap_or(self.config.sync_writes);
+            let storage = self.config.storage.clone();
+            let wal = self.writable_wal()?;
+            let mark = wal.mark();
+            let logged = ops
+                .iter()
+                .zip(&seqs)
+                .try_for_each(|((key, value), &seq)| match value {
+                    Some(value) => wal.append_put(seq, key, value),
+                    None => wal.append_delete(seq, key),
+                })
+                .and_then(|()| match sync {
+                    true => wal.sync_to_disk(storage.as_ref()),
+                    false => Ok(()),
+                });
+            if let Err(error) = logged {
+                return Err(self.wal_failed(mark, error));
+            }
+            let bytes = wal.bytes_since(mark);
+            self.wal_failures = 0;
+            IoRateTrackers::record(
+                &self.io_rates.wal_write,
+                self.config.clock.now_millis(),
+                bytes,
+            );
+        }
+        Ok(LoggedWrites { ops, seqs })
+    }
+
+    /// Cuts the WAL back to `mark` after a failed append or sync, and
+    /// poisons the tree after `WAL_FAILURES_BEFORE_POISON` failures in a
+    /// row, or at once if the log can't be cut back
+    ///
+    /// Returns `error` for the caller: the writes are neither in the log
+    /// nor in the memtable.
+    fn wal_failed(&mut self, mark: WALMark, error: std::io::Error) -> std::io::Error {
+        self.wal_failures += 1;
+        let rolled_back = self
+            .writable_wal()
+            .and_then(|wal| wal.rollback(mark))
+            .is_ok();
+        if rolled_back && self.wal_failures < WAL_FAILURES_BEFORE_POISON {
+            return error;
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::error!(
+            failures = self.wal_failures,
+            rolled_back,
+            error = %error,
+            "WAL failures poisoned the tree; writes are refused until clear_poison"
+        );
+        self.poison = Some(Poison {
+            error: Poisoned {
+                failures: self.wal_failures,
+                cause: error.to_string(),
+            },
+            rollback: (!rolled_back).then_some(mark),
+        });
+        error
+    }
+
+    /// Puts logged writes into the memtable, where every read from here on
+    /// finds them
+    fn publish(&mut self, logged: LoggedWrites) {
+        let LoggedWrites { ops, seqs } = logged;
+        self.user_bytes_written += ops
+            .iter()
+            .map(|(key, value)| (key.len() + value.as_ref().map_or(0, Vec::len)) as u64)
+            .sum::<u64>();
+        let now = self.config.clock.now();
+        if let (Some(&first), Some(&last)) = (seqs.first(), seqs.last()) {
+            self.memtable_write_times.record(first, last, now);
+        }
+        for (key, value) in &ops {
+            self.workload.record_entry(key, value.as_deref());
+        }
+        self.workload.writes += ops.len() as u64;
+
+        #[cfg(debug_assertions)]
+        let last = ops
+            .last()
+            .map(|(key, _)| key.clone())
+            .zip(seqs.last().copied());
+        let newest_pin = self.pinned_sequences.keys().next_back().copied();
+        for ((key, value), seq) in ops.into_iter().zip(seqs) {
+            Arc::make_mut(&mut self.memtable).insert_version(key, value, seq, newest_pin);
+        }
+        #[cfg(debug_assertions)]
+        if let Some((key, seq)) = last {
+            debug_assert_eq!(
+                self.memtable.newest_sequence(&key),
+                Some(seq),
+                "a published write must be the newest version of its key"
+            );
+        }
+        self.last_write = Some(now);
+    }
+
+    /// Drops puts that would write the value the memtable already holds
+    /// (see `LSMConfig::skip_identical_writes`)
+    ///
+    /// A key written earlier in the same batch is never skipped: the
+    /// memtable doesn't hold the state that write leaves behind yet.
+    fn skip_identical_puts(&mut self, ops: &mut Vec<(Vec<u8>, Option<Vec<u8>>)>) {
+        let refreshes_age = self.config.ttl.is_some() && self.config.identical_writes_refresh_ttl;
+        if !self.config.skip_identical_writes || refreshes_age {
+            return;
+        }
+        let before = ops.len();
+        let mut touched = std::collections::HashSet::new();
+        ops.retain(|(key, value)| {
+            let identical = value.is_some()
+                && !touched.contains(key)
+                && self
+                    .memtable
+                    .get(key)
+                    .is_some_and(|current| current == *value);
+            touched.insert(key.clone());
+            !identical
+        });
+        self.skipped_writes += (before - ops.len()) as u64;
+    }
+
+    /// Returns how many puts `LSMConfig::skip_identical_writes` skipped
+    /// since the tree was opened
+    pub fn skipped_identical_writes(&self) -> u64 {
+        self.skipped_writes
+    }
+
+    /// Asks the flush policy whether the memtable should be flushed now
+    fn should_flush(&self) -> bool {
+        let context = FlushContext {
+            memtable_bytes: self.memtable.size(),
+            memtable_entries: self.memtable.len(),
+            wal_bytes: self.wal.as_ref().map_or(0, |wal| wal.stats().file_size),
+            since_last_flush: self
+                .config
+                .clock
+                .now()
+                .duration_since(self.last_flush)
+                .unwrap_or_default(),
+        };
+        match &self.config.flush_policy {
+            Some(policy) => policy.should_flush(&context),
+            None => {
+                SizeThresholdPolicy::new(self.config.memtable_size_threshold).should_flush(&context)
+            }
+        }
+    }
+
+    /// Whether a write should flush the memtable: `should_flush`, unless
+    /// automatic flushes are paused and the memtable is within
+    /// `max_paused_memtable_bytes`
+    fn should_auto_flush(&self) -> bool {
+        if self.flush_pauses == 0 {
+            return self.should_flush();
+        }
+        let memtable_bytes = self.memtable.size();
+        let cap = self.config.max_paused_memtable_bytes;
+        if memtable_bytes <= cap {
+            return false;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            memtable_bytes,
+            cap,
+            "memtable over max_paused_memtable_bytes; flushing despite the pause"
+        );
+        self.config
+            .event_listener
+            .on_flush_pause_overridden(&events::FlushPauseEvent {
+                memtable_bytes,
+                max_paused_memtable_bytes: cap,
+            });
+        true
+    }
+
+    /// Returns the WAL, or `PermissionDenied` if the tree is read-only
+    fn writable_wal(&mut self) -> std::io::Result<&mut WAL> {
+        self.wal.as_mut().ok_or_else(read_only_error)
+    }
+
+    /// Assigns the sequence number for a new write
+    fn next_sequence(&mut self) -> u64 {
+        self.last_sequence += 1;
+        self.last_sequence
+    }
+
+    /// Retrieves value for a given key
+    pub fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        // Default options never verify or limit, so there is no error to
+        // report
+        self.get_opt(key, &ReadOptions::default()).ok().flatten()
+    }
+
+    /// Retrieves the value for a key with per-read options
+    ///
+    /// Fails only when `options.verify_checksums` finds a damaged record,
+    /// or with `ReadBudgetExceeded` when the key isn't found within
+    /// `options.max_tables_probed` SSTables.
+    pub fn get_opt(
+        &mut self,
+        key: &[u8],
+        options: &ReadOptions,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        self.check_not_deleted()?;
+        let start = Instant::now();
+        let value = self.lookup(key, options);
+        self.latency.record(LatencyOp::Get, start.elapsed());
+        if let Ok(found) = &value {
+            self.read_counts.record(found.is_some());
+        }
+        value
+    }
+
+    /// Non-mutable version of get
+    pub fn get_immut(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let start = Instant::now();
+        let value = self.lookup_immut(key);
+        self.latency.record(LatencyOp::Get, start.elapsed());
+        self.read_counts.record(value.is_some());
+        value
+    }
+
+    /// Looks up a key, loading evicted Bloom filters and counting filter
+    /// hits and probed SSTables
+    fn lookup(&mut self, key: &[u8], options: &ReadOptions) -> std::io::Result<Option<Vec<u8>>> {
+        let mut probed = 0;
+        let value = self.lookup_probing(key, options, &mut probed);
+
+        let threshold = self.config.read_amp_warn_threshold;
+        if self.read_amp.record(probed, threshold) {
+            self.config
+                .event_listener
+                .on_read_amp_high(&events::ReadAmpEvent {
+                    average: self.read_amp.average(),
+                    threshold: threshold.unwrap_or_default(),
+                    sstable_count: self.sstables.len(),
+                });
+        }
+        value
+    }
+
+    /// The lookup itself; `probed` counts the SSTables it looked at
+    fn lookup_probing(
+        &mut self,
+        key: &[u8],
+        options: &ReadOptions,
+        probed: &mut usize,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        if let Some(value) = self.memtable.get(key) {
+            return Ok(value);
+        }
+
+        for i in self.read_order() {
+            if options.max_tables_probed.is_some_and(|max| *probed >= max) {
+                self.read_budget_exceeded += 1;
+                return Err(ReadBudgetExceeded { probed: *probed }.into());
+            }
+            *probed += 1;
+            let path = &self.sstables[i].path;
+            // Filters evicted under the memory budget are loaded back here,
+            // unless the caller asked to leave the cache alone
+            let might_contain = if options.fill_cache {
+                self.bloom_filters
+                    .get_or_load(i, path)
+                    .map(|bf| bf.might_contain(key))
+            } else {
+                self.bloom_filters
+                    .might_contain_without_filling(i, path, key)
+            };
+            if let Some(might_contain) = might_contain {
+                self.sstables[i].reads.record_bloom_check(!might_contain);
+            }
+            match might_contain {
+                Some(false) => {
+                    self.bloom_filter_negatives += 1;
+                    continue;
+                }
+                Some(true) => self.bloom_filter_positives += 1,
+                None => {}
+            }
+
+            let found = if options.verify_checksums {
+                self.read_verified(i, key)
+            } else {
+                self.read_from_sstable(&self.sstables[i], key)
+                    .map(|found| found.map(Ok))
+            };
+            match found {
+                Ok(Some(Ok(value))) => return Ok(value),
+                Ok(Some(Err(reason))) => match self.config.on_corruption {
+                    CorruptionPolicy::Error => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!(
+                                "Corruption in SSTable {}: {}",
+                                self.sstables[i].path.display(),
+                                reason
+                            ),
+                        ));
+                    }
+                    CorruptionPolicy::FallbackToOlder => {}
+                },
+                Ok(None) => {}
+                // A table that can't be opened is skipped, as if it didn't
+                // have the key, unless the whole tree has gone
+                Err(error) => {
+                    let error = self.stale(error);
+                    if TreeDeleted::from_io(&error).is_some() {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns how many lookups gave up at their
+    /// `ReadOptions::max_tables_probed` budget since the tree was opened
+    ///
+    /// A rising count means reads have to go through more SSTables than
+    /// callers are willing to wait for: compaction is overdue.
+    pub fn read_budget_exceeded(&self) -> u64 {
+        self.read_budget_exceeded
+    }
+
+    /// Looks up `key` in the `i`th SSTable for a verified lookup, checking
+    /// the newest record of the key against its checksum
+    ///
+    /// Returns what `read_from_sstable` would, or the damage of the record.
+    /// Only the records of `key` are read, from its block on, whatever the
+    /// size of the table. A damaged record not yet in the corruption report
+    /// is added and reported to the event listener.
+    fn read_verified(
+        &mut self,
+        i: usize,
+        key: &[u8],
+    ) -> std::io::Result<Option<Result<Option<Vec<u8>>, Corruption>>> {
+        let table = &self.sstables[i];
+        let mut reader = self.open_sstable(&table.path)?;
+        let newest = reader.locate(key)?.into_iter().next();
+        let bytes = reader.position().unwrap_or(0);
+        IoRateTrackers::record(
+            &self.io_rates.sstable_read,
+            self.config.clock.now_millis(),
+            bytes,
+        );
+        table.reads.record_probe(newest.is_some(), bytes);
+        let Some(newest) = newest else {
+            return Ok(None);
+        };
+        let Some(reason) = newest.damage else {
+            return Ok(Some(Ok(newest.value)));
+        };
+
+        let path = &table.path;
+        if self
+            .corrupt_reads
+            .iter()
+            .any(|known| known.path == *path && known.key == key)
+        {
+            return Ok(Some(Err(reason)));
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            path = %path.display(),
+            reason = %reason,
+            policy = ?self.config.on_corruption,
+            "verified read found a damaged record"
+        );
+        let report = CorruptRead {
+            path: path.clone(),
+            key: key.to_vec(),
+            reason: reason.clone(),
+        };
+        self.config.event_listener.on_corrupt_read(&report);
+        self.corrupt_reads.push(report);
+        Ok(Some(Err(reason)))
+    }
+
+    /// Damaged records that reads with `ReadOptions::verify_checksums`
+    /// found since the tree was opened, in the order they were found, one
+    /// entry per table and key
+    ///
+    /// The tables stay in the tree: plain reads still use them, and
+    /// verified reads of a listed key fail on them or skip them
+    /// (`LSMConfig::on_corruption`). `verify_key` tells which copies of a
+    /// key are damaged.
+    pub fn corruption_report(&self) -> &[CorruptRead] {
+        &self.corrupt_reads
+    }
+
+    /// Looks up a key using only the Bloom filters already in memory
+    fn lookup_immut(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(value) = self.memtable.get(key) {
+            return value;
+        }
+
+        for i in self.read_order() {
+            let table = &self.sstables[i];
+            // Can't load evicted filters without `&mut`; just probe those
+            if let Some(bloom_filter) = self.bloom_filters.resident(i) {
+                let might_contain = bloom_filter.might_contain(key);
+                table.reads.record_bloom_check(!might_contain);
+                if !might_contain {
+                    continue;
+                }
+            }
+            if let Ok(Some(value)) = self.read_from_sstable(table, key) {
+                return value;
+            }
+        }
+
+        None
+    }
+
+    /// Returns the value a key had right after the write with sequence
+    /// number `sequence`
+    ///
+    /// `None` means the key didn't exist yet or was deleted at that point.
+    /// Versions are kept for as long as something can ask for them: the
+    /// answer is exact for the current sequence and for any sequence held
+    /// with `pin_sequence` while the later writes happened. For other past
+    /// sequences, an overwrite that happened while nothing was pinned has
+    /// already replaced the older version, and the closest surviving older
+    /// version is returned instead.
+    ///
+    /// SSTables written by older builds have no per-write sequence numbers;
+    /// their entries count as written before every sequence.
+    pub fn get_at(&self, key: &[u8], sequence: u64) -> Option<Vec<u8>> {
+        if let Some(value) = self.memtable.get_at(key, sequence) {
+            return value;
+        }
+
+        for i in self.read_order() {
+            if let Some(bloom_filter) = self.bloom_filters.resident(i)
+                && !bloom_filter.might_contain(key)
+            {
+                continue;
+            }
+            let path = &self.sstables[i].path;
+            let found = match self.pinned_tables.get(path) {
+                Some(table) => table.get_at(key, sequence),
+                None => self
+                    .open_sstable(path)
+                    .ok()
+                    .and_then(|mut reader| reader.get_at(key, sequence)),
+            };
+            if let Some(value) = found {
+                return value;
+            }
+        }
+
+        None
+    }
+
+    /// Retrieves the value for a key together with where it came from and
+    /// when it was written (see `ValueMeta`)
+    ///
+    /// Like `get_immut`, only Bloom filters already in memory are used.
+    /// `written_at` is `None` for writes whose time wasn't recorded: writes
+    /// recovered from the WAL after a crash, and tables written by older
+    /// builds.
+    pub fn get_with_meta(&self, key: &[u8]) -> Option<(Vec<u8>, ValueMeta)> {
+        if let Some(newest) = self.memtable.versions(key).into_iter().next() {
+            let meta = ValueMeta {
+                sequence: newest.seq,
+                written_at: self.memtable_write_times.time_of(newest.seq),
+                source: ValueSource::Memtable,
+            };
+            return newest.value.map(|value| (value, meta));
+        }
+
+        for i in self.read_order() {
+            if let Some(bloom_filter) = self.bloom_filters.resident(i)
+                && !bloom_filter.might_contain(key)
+            {
+                continue;
+            }
+            let table = &self.sstables[i];
+            let Ok(mut reader) =
+                SSTableReader::open_buffered(&table.path, self.config.read_buffer_bytes)
+            else {
+                continue;
+            };
+            let Some((sequence, value)) = reader.get_version_at(key, u64::MAX) else {
+                continue;
+            };
+            let value = value?;
+            let written_at = reader
+                .write_times()
+                .ok()
+                .and_then(|times| times.time_of(sequence));
+            let id = SSTableId(Self::sstable_number(&table.path)?);
+            let meta = ValueMeta {
+                sequence,
+                written_at,
+                source: ValueSource::SSTable {
+                    id,
+                    generation: table.generation,
+                },
+            };
+            return Some((value, meta));
+        }
+
+        None
+    }
+
+    /// Finds and checks every copy of a key (see `KeyVerification`)
+    ///
+    /// Every SSTable is searched, whatever its Bloom filter says, and the
+    /// filter's answer is reported next to each copy, and each record of
+    /// the key is checked against its checksum. Tables that can't be
+    /// opened at all are left out; opening the tree reports those.
+    pub fn verify_key(&self, key: &[u8]) -> KeyVerification {
+        let mut copies: Vec<KeyCopy> = self
+            .memtable
+            .versions(key)
+            .into_iter()
+            .map(|version| KeyCopy {
+                source: ValueSource::Memtable,
+                offset: None,
+                sequence: version.seq,
+                value: version.value,
+                damage: None,
+                filter_may_contain: None,
+            })
+            .collect();
+
+        for i in self.read_order() {
+            let table = &self.sstables[i];
+            let Some(number) = Self::sstable_number(&table.path) else {
+                continue;
+            };
+            let Ok(records) = self
+                .open_sstable(&table.path)
+                .and_then(|mut reader| reader.locate(key))
+            else {
+                continue;
+            };
+            if records.is_empty() {
+                continue;
+            }
+            let filter_may_contain =
+                self.bloom_filters
+                    .might_contain_without_filling(i, &table.path, key);
+            let source = ValueSource::SSTable {
+                id: SSTableId(number),
+                generation: table.generation,
+            };
+            copies.extend(records.into_iter().map(|record| KeyCopy {
+                source,
+                offset: Some(record.offset),
+                sequence: record.seq,
+                value: record.value,
+                damage: record.damage,
+                filter_may_contain,
+            }));
+        }
+
+        let winner = copies.iter().position(|copy| copy.damage.is_none());
+        KeyVerification { copies, winner }
+    }
+
+    /// Pins the current sequence number so `get_at` stays exact for it
+    ///
+    /// While pinned, overwrites and deletes keep the version that was
+    /// current at this sequence instead of replacing it, in the memtable
+    /// and in the SSTables flushed from it. Every pin has to be released
+    /// with `release_sequence`; pins are not persisted across reopening.
+    pub fn pin_sequence(&mut self) -> u64 {
+        *self.pinned_sequences.entry(self.last_sequence).or_default() += 1;
+        self.last_sequence
+    }
+
+    /// Releases one pin taken by `pin_sequence`
+    ///
+    /// Returns false if `sequence` wasn't pinned.
+    pub fn release_sequence(&mut self, sequence: u64) -> bool {
+        match self.pinned_sequences.get_mut(&sequence) {
+            Some(count) if *count > 1 => *count -= 1,
+            Some(_) => {
+                self.pinned_sequences.remove(&sequence);
+            }
+            None => return false,
+        }
+        true
+    }
+
+    /// Returns the oldest pinned sequence, if any
+    pub fn oldest_pinned_sequence(&self) -> Option<u64> {
+        self.pinned_sequences.keys().next().copied()
+    }
+
+    /// Returns an iterator over live key-value pairs in `range`, in key order
+    ///
+    /// Newer versions shadow older ones and deleted keys are skipped.
+    ///
+    /// # Example
+    /// ```ignore
+    /// for (key, value) in lsm.range("user:".."user;") { /* ... */ }
+    /// ```
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> RangeIter<'_> {
+        RangeIter::new(MergeIter::new(
+            &self.memtable,
+            &self.paths_newest_first(),
+            iter::to_key_range(&range),
+            false,
+            self.config.read_buffer_bytes,
+        ))
+    }
+
+    /// Returns an iterator over live keys in `range`, in key order
+    ///
+    /// Like `range`, but values are never read from the SSTables.
+    pub fn keys<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> KeysIter<'_> {
+        KeysIter::new(MergeIter::new(
+            &self.memtable,
+            &self.paths_newest_first(),
+            iter::to_key_range(&range),
+            true,
+            self.config.read_buffer_bytes,
+        ))
+    }
+
+    /// Returns an iterator over every version the tree stores in `range`:
+    /// in key order, and the versions of each key newest first
+    ///
+    /// Unlike `range`, nothing is shadowed: overwritten values and
+    /// tombstones come out too, each with its sequence number and where it
+    /// is stored, which is what change data capture and debugging need.
+    /// Only what the tree still holds can be seen, though. The memtable
+    /// keeps a single version per key unless a sequence is pinned, and a
+    /// purge drops every version but the newest live one, tombstones
+    /// included; read versions out before compacting them away.
+    pub fn iter_versions<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> VersionIter<'_> {
+        let order = self.read_order();
+        let mut sources = Vec::with_capacity(order.len() + 1);
+        sources.push(ValueSource::Memtable);
+        sources.extend(order.iter().map(|&i| {
+            let table = &self.sstables[i];
+            ValueSource::SSTable {
+                id: SSTableId(Self::sstable_number(&table.path).unwrap_or_default()),
+                generation: table.generation,
+            }
+        }));
+        VersionIter::new(
+            MergeIter::all_versions(
+                &self.memtable,
+                &self.paths_newest_first(),
+                iter::to_key_range(&range),
+                false,
+                self.config.read_buffer_bytes,
+            ),
+            sources,
+        )
+    }
+
+    /// Returns a cursor over live keys that can seek and move both ways
+    ///
+    /// The cursor starts unpositioned; see `Cursor` for the moves. Opening
+    /// it only reads each SSTable's block index; moves read a block of a
+    /// table at a time.
+    pub fn cursor(&self) -> Cursor<'_> {
+        Cursor::new(
+            &self.memtable,
+            &self.paths_newest_first(),
+            self.config.storage.as_ref(),
+            self.config.read_buffer_bytes,
+        )
+    }
+
+    /// Captures the tree's data as of now for reads that outlive the borrow
+    ///
+    /// The state keeps answering as the tree did at this moment while
+    /// flushes and compactions go on; tables they drop are deleted only
+    /// once no state reads from them (see `TreeState`). Meant for reads
+    /// through a lock (`SharedLSMTree::read_state`), which can then run
+    /// without holding it. Reads on `&self` need none: nothing can change
+    /// the tree while they borrow it.
+    pub fn read_state(&self) -> TreeState {
+        let order = self.read_order();
+        TreeState::new(
+            self.memtable.clone(),
+            order
+                .iter()
+                .map(|&i| self.sstables[i].path.clone())
+                .collect(),
+            order
+                .iter()
+                .map(|&i| self.bloom_filters.resident_shared(i))
+                .collect(),
+            self.config.storage.clone(),
+            self.config.read_buffer_bytes,
+            self.table_refs.clone(),
+        )
+    }
+
+    /// Starts a transaction reading the tree as of now (see `Transaction`)
+    pub fn begin(&self) -> Transaction {
+        Transaction::new(self.read_state(), self.last_sequence)
+    }
+
+    /// Sequence number of the newest version of a key, tombstones
+    /// included (`None` if the tree holds no version of it)
+    pub(crate) fn newest_sequence(&self, key: &[u8]) -> Option<u64> {
+        if let Some(sequence) = self.memtable.newest_sequence(key) {
+            return Some(sequence);
+        }
+        for i in self.read_order() {
+            if let Some(bloom_filter) = self.bloom_filters.resident(i)
+                && !bloom_filter.might_contain(key)
+            {
+                continue;
+            }
+            let found = self
+                .open_sstable(&self.sstables[i].path)
+                .ok()
+                .and_then(|mut reader| reader.get_version_at(key, u64::MAX));
+            if let Some((sequence, _)) = found {
+                return Some(sequence);
+            }
+        }
+        None
+    }
+
+    /// Looks up several keys as of one moment
+    ///
+    /// Uses only the Bloom filters already in memory, like `get_immut`.
+    pub fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Option<Vec<u8>>> {
+        keys.iter()
+            .map(|key| {
+                let value = self.lookup_immut(key.as_ref());
+                self.read_counts.record(value.is_some());
+                value
+            })
+            .collect()
+    }
+
+    /// Number of SSTables that left the tree but are still read by a
+    /// `TreeState`, so their files aren't deleted yet
+    pub fn pending_table_deletions(&self) -> usize {
+        self.table_refs.deferred()
+    }
+
+    /// Returns the `k` largest keys, smallest keys or most recently
+    /// written keys in `range`, with their values (see `top_k`)
+    ///
+    /// Memory is bounded by `k`, plus a block per SSTable. Key orders stop
+    /// after `k` keys, the largest walking back from the end of the range
+    /// a block at a time; `NewestWrites` scans every live key in the
+    /// range, keys only, and reads the values of the `k` it keeps. `TopK::exhaustive` says
+    /// whether the range held more.
+    pub fn top_k<K: AsRef<[u8]>, R: RangeBounds<K>>(
+        &self,
+        range: R,
+        k: usize,
+        order: TopKOrder,
+    ) -> TopK {
+        let range = iter::to_key_range(&range);
+        match order {
+            TopKOrder::SmallestKeys => top_k::smallest(self.range(range), k),
+            TopKOrder::LargestKeys => top_k::largest(
+                &self.memtable,
+                &self.paths_newest_first(),
+                self.config.storage.as_ref(),
+                self.config.read_buffer_bytes,
+                &range,
+                k,
+            ),
+            TopKOrder::NewestWrites => {
+                let keys = MergeIter::new(
+                    &self.memtable,
+                    &self.paths_newest_first(),
+                    range,
+                    true,
+                    self.config.read_buffer_bytes,
+                );
+                top_k::newest(keys, k, |key| self.lookup_immut(key))
+            }
+        }
+    }
+
+    /// `top_k` over the keys starting with `prefix`
+    pub fn top_k_prefix(&self, prefix: &[u8], k: usize, order: TopKOrder) -> TopK {
+        let end = match subspace::prefix_end(prefix) {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        };
+        self.top_k((Bound::Included(prefix.to_vec()), end), k, order)
+    }
+
+    /// Counts the live keys in `range`
+    pub fn count_range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> u64 {
+        self.keys(range).count() as u64
+    }
+
+    /// Returns true if at least one live key exists in `range`
+    ///
+    /// Stops at the first live key, so it is cheap even for huge ranges.
+    pub fn any_in_range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> bool {
+        self.keys(range).next().is_some()
+    }
+
+    /// Returns a view of the keys starting with `prefix` (see `Subspace`)
+    ///
+    /// The view prepends `prefix` to the keys it is given and strips it
+    /// from the keys it returns.
+    pub fn subspace(&mut self, prefix: &[u8]) -> Subspace<'_> {
+        Subspace::new(self, prefix.to_vec())
+    }
+
+    /// Inserts or updates the value of a `u64` key (see `keys::u64_be`)
+    pub fn put_u64(&mut self, key: u64, value: Vec<u8>) -> std::io::Result<()> {
+        self.put(keys::u64_be::encode(key).to_vec(), value)
+    }
+
+    /// Retrieves the value of a `u64` key
+    pub fn get_u64(&mut self, key: u64) -> Option<Vec<u8>> {
+        self.get(&keys::u64_be::encode(key))
+    }
+
+    /// Deletes a `u64` key
+    pub fn delete_u64(&mut self, key: u64) -> std::io::Result<()> {
+        self.delete(keys::u64_be::encode(key).to_vec())
+    }
+
+    /// Returns the live entries with `u64` keys in `range`, in numeric
+    /// order
+    ///
+    /// Keys that aren't 8 bytes long are skipped.
+    pub fn range_u64<R: RangeBounds<u64>>(&self, range: R) -> NumericRangeIter<'_, u64> {
+        let range = keys::key_range(&range, keys::u64_be::encode);
+        NumericRangeIter::new(self.range(range), keys::u64_be::decode)
+    }
+
+    /// Inserts or updates the value of an `i64` key (see `keys::i64_be`)
+    pub fn put_i64(&mut self, key: i64, value: Vec<u8>) -> std::io::Result<()> {
+        self.put(keys::i64_be::encode(key).to_vec(), value)
+    }
+
+    /// Retrieves the value of an `i64` key
+    pub fn get_i64(&mut self, key: i64) -> Option<Vec<u8>> {
+        self.get(&keys::i64_be::encode(key))
+    }
+
+    /// Deletes an `i64` key
+    pub fn delete_i64(&mut self, key: i64) -> std::io::Result<()> {
+        self.delete(keys::i64_be::encode(key).to_vec())
+    }
+
+    /// Returns the live entries with `i64` keys in `range`, in numeric
+    /// order, negative keys first
+    ///
+    /// Keys that aren't 8 bytes long are skipped.
+    pub fn range_i64<R: RangeBounds<i64>>(&self, range: R) -> NumericRangeIter<'_, i64> {
+        let range = keys::key_range(&range, keys::i64_be::encode);
+        NumericRangeIter::new(self.range(range), keys::i64_be::decode)
+    }
+
+    /// Returns one page of live entries, for cursor-based pagination
+    ///
+    /// Yields up to `limit` entries with keys strictly greater than
+    /// `start_after` (or from the beginning when it is `None`), in key order.
+    /// Pass the returned `next_cursor` back as `start_after` to get the next
+    /// page; it is `None` once there is nothing left.
+    ///
+    /// Every page is a fresh read of the tree: writes and flushes that happen
+    /// between calls are visible in later pages, so a page never repeats a
+    /// key from an earlier page but may include keys inserted after it.
+    /// Each SSTable is entered at the block of its footer's index holding
+    /// the cursor, so a page reads about as much as its own entries take,
+    /// however far into the tree it starts.
+    ///
+    /// # Panics
+    /// If `limit` is 0: an empty page without a cursor would read as the
+    /// end of the data.
+    pub fn scan(&self, start_after: Option<&[u8]>, limit: usize) -> ScanPage {
+        assert!(limit > 0, "scan needs a limit of at least 1");
+        let start = match start_after {
+            Some(cursor) => Bound::Excluded(cursor.to_vec()),
+            None => Bound::Unbounded,
+        };
+        let mut iter = MergeIter::new(
+            &self.memtable,
+            &self.paths_newest_first(),
+            (start, Bound::Unbounded),
+            false,
+            self.config.read_buffer_bytes,
+        );
+
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = iter.by_ref().take(limit).collect();
+
+        // Only hand out a cursor if at least one more entry exists
+        let next_cursor = if entries.len() == limit && iter.next().is_some() {
+            entries.last().map(|(key, _)| key.clone())
+        } else {
+            None
+        };
+
+        ScanPage {
+            entries,
+            next_cursor,
+        }
+    }
+
+    /// Runs periodic upkeep and returns whether it did anything
+    ///
+    /// That is two things:
+    ///
+    /// - the idle flush: once the memtable holds writes and none has
+    ///   arrived for `LSMConfig::flush_after_idle`, it is flushed, so a
+    ///   quiet tree ends up with its data in SSTables and an empty WAL
+    /// - with `LSMConfig::ttl`, deleting the oldest SSTables whose entries
+    ///   have all expired (see `delete_expired_sstables`)
+    ///
+    /// Call it from a timer or background thread; when there is nothing to
+    /// do it only reads the clock and the tables' write times. A read-only
+    /// tree does nothing.
+    pub fn maintenance_tick(&mut self) -> std::io::Result<bool> {
+        if self.config.read_only {
+            return Ok(false);
+        }
+        let flushed = self.flush_if_idle()?;
+        let deleted = !self.delete_expired_sstables()?.is_empty();
+        Ok(flushed || deleted)
+    }
+
+    fn flush_if_idle(&mut self) -> std::io::Result<bool> {
+        let (Some(idle_window), Some(last_write)) = (self.config.flush_after_idle, self.last_write)
+        else {
+            return Ok(false);
+        };
+        if self.memtable.is_empty() || self.flush_pauses > 0 {
+            return Ok(false);
+        }
+        // A clock that went backwards counts as no time passing
+        let idle = self
+            .config
+            .clock
+            .now()
+            .duration_since(last_write)
+            .unwrap_or_default();
+        if idle < idle_window {
+            return Ok(false);
+        }
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Writes older than this have expired (`None` without a TTL)
+    fn expiry_cutoff(&self) -> Option<SystemTime> {
+        let ttl = self.config.ttl?;
+        self.config.clock.now().checked_sub(ttl)
+    }
+
+    /// Number of the oldest tables in read order whose entries have all
+    /// expired
+    fn fully_expired_count(&self, cutoff: SystemTime) -> usize {
+        self.read_order()
+            .into_iter()
+            .rev()
+            .take_while(|&index| {
+                self.sstables[index]
+                    .write_times
+                    .span()
+                    .is_some_and(|(_, latest)| latest <= cutoff)
+            })
+            .count()
+    }
+
+    /// Deletes the oldest SSTables whose entries have all expired under
+    /// `LSMConfig::ttl`, without reading them, and returns them oldest
+    /// first
+    ///
+    /// Tables go from the oldest one up and stop at the first table that
+    /// still holds a live write, so no deleted table ever uncovers an older
+    /// version of one of its keys. Does nothing without a TTL.
+    pub fn delete_expired_sstables(&mut self) -> std::io::Result<Vec<SSTableId>> {
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+        let Some(cutoff) = self.expiry_cutoff() else {
+            return Ok(Vec::new());
+        };
+        let count = self.fully_expired_count(cutoff);
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let expired: Vec<usize> = self.read_order().into_iter().rev().take(count).collect();
+
+        // The manifest goes first; if it can't be written, the tables are
+        // still part of the tree
+        let ids: Vec<SSTableId> = expired
+            .iter()
+            .filter_map(|&index| Self::sstable_number(&self.sstables[index].path))
+            .map(SSTableId)
+            .collect();
+        let mut manifest = self.manifest();
+        manifest
+            .sstables
+            .retain(|table| !ids.contains(&SSTableId(table.number)));
+        manifest.store(&self.config.data_dir)?;
+        self.config.storage.sync_dir(&self.config.data_dir)?;
+
+        let kept: Vec<usize> = (0..self.sstables.len())
+            .filter(|index| !expired.contains(index))
+            .collect();
+        let paths: Vec<PathBuf> = expired
+            .iter()
+            .map(|&index| self.sstables[index].path.clone())
+            .collect();
+        self.bloom_filters.relayout(
+            kept.iter()
+                .map(|&index| SlotSource::Existing(index))
+                .collect(),
+        );
+        self.sstables = kept
+            .iter()
+            .map(|&index| self.sstables[index].clone())
+            .collect();
+        self.ordering_anomalies = Self::find_ordering_anomalies(&self.sstables);
+        self.repin_small_tables();
+
+        for path in &paths {
+            self.table_refs.remove_when_unused(path)?;
+        }
+        Ok(ids)
+    }
+
+    /// How much of the tree has expired under `LSMConfig::ttl`, from the
+    /// write times of the SSTables (empty without a TTL)
+    ///
+    /// The memtable isn't counted: its writes are the newest, and nothing
+    /// deletes them before they are flushed.
+    pub fn expired_estimate(&self) -> ExpiryEstimate {
+        let mut estimate = ExpiryEstimate::default();
+        let Some(cutoff) = self.expiry_cutoff() else {
+            return estimate;
+        };
+        let fully_expired = self.fully_expired_count(cutoff);
+        for (position, index) in self.read_order().into_iter().rev().enumerate() {
+            let table = &self.sstables[index];
+            let (expired, total) = table.write_times.count_written_by(cutoff);
+            estimate.timed_writes += total;
+            estimate.expired_writes += expired;
+            let Some(id) = Self::sstable_number(&table.path).map(SSTableId) else {
+                continue;
+            };
+            if position < fully_expired {
+                estimate.fully_expired.push(id);
+            } else if total > 0 && expired as f64 >= total as f64 * expiry::MOSTLY_EXPIRED_FRACTION
+            {
+                estimate.mostly_expired.push(id);
+            }
+        }
+        estimate
+    }
+
+    /// Flushes memtable to disk as a new SSTable with Bloom filter
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.check_not_deleted()?;
+        self.flush_memtable()
+            .map(drop)
+            .map_err(|error| self.stale(error))
+    }
+
+    /// Defers flushes the flush policy or `maintenance_tick` would start,
+    /// until `resume_auto_flush` (see `flush_pause`)
+    ///
+    /// Explicit `flush` calls still flush. Pauses nest: each needs its own
+    /// resume.
+    pub fn pause_auto_flush(&mut self) {
+        self.flush_pauses += 1;
+    }
+
+    /// Ends one pause; once none is left, flushes the memtable if the
+    /// flush policy says it is due
+    ///
+    /// Without a pause to end it does nothing.
+    pub fn resume_auto_flush(&mut self) -> std::io::Result<()> {
+        if self.flush_pauses == 0 {
+            return Ok(());
+        }
+        self.flush_pauses -= 1;
+        if self.flush_pauses == 0 && !self.config.read_only && self.should_flush() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Whether automatic flushes are paused
+    pub fn is_auto_flush_paused(&self) -> bool {
+        self.flush_pauses > 0
+    }
+
+    /// Pauses automatic flushes until the returned guard is dropped or
+    /// resumed; the guard derefs to the tree
+    pub fn pause_flushes(&mut self) -> FlushPauseGuard<'_> {
+        FlushPauseGuard::new(self)
+    }
+
+    /// Flushes what is left in memory and closes the tree
+    ///
+    /// Dropping a tree flushes too, but has nowhere to report a failure;
+    /// `close` returns it, along with what the flush wrote. The tree keeps
+    /// a single memtable, whose writes are flushed synchronously when it
+    /// fills, so everything still in memory at shutdown goes into one
+    /// SSTable, newest version per key, and the WAL is cleared. Pins end
+    /// with the tree, so versions kept only for pinned sequences are left
+    /// out. A tree with nothing in memory, or a read-only one, writes
+    /// nothing.
+    pub fn close(mut self) -> std::io::Result<FlushResult> {
+        if self.config.read_only {
+            return Ok(FlushResult::default());
+        }
+        self.pinned_sequences.clear();
+        self.flush_memtable()
+    }
+
+    /// Abandons the tree the way a crash of the process would: nothing is
+    /// flushed, and the directory lock is released as the operating system
+    /// would. Reopening the directory then recovers from the WAL.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn simulate_crash(mut self) {
+        self._dir_lock = None;
+        std::mem::forget(self);
+    }
+
+    /// `flush`, reporting the SSTable written
+    fn flush_memtable(&mut self) -> std::io::Result<FlushResult> {
+        if self.memtable.is_empty() {
+            return Ok(FlushResult::default());
+        }
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+        let start = Instant::now();
+
+        let number = self.sstable_counter;
+        let sstable_path = Self::sstable_path(&self.config.data_dir, number);
+        self.sstable_counter += 1;
+
+        let result = self
+            .write_memtable(number, sstable_path.clone())
+            .context(Operation::Flush, &sstable_path)?;
+        self.latency.record(LatencyOp::Flush, start.elapsed());
+        Ok(result)
+    }
+
+    /// Writes the memtable into SSTable `number` and makes it part of the
+    /// tree (see `flush`)
+    fn write_memtable(
+        &mut self,
+        number: usize,
+        sstable_path: PathBuf,
+    ) -> std::io::Result<FlushResult> {
+        let mut options = self.writer_options(self.memtable.len(), self.config.sync_on_flush);
+        options.dictionary = self.dictionary_for(
+            self.memtable.iter().filter_map(|(_, value)| value),
+            self.memtable.len(),
+        );
+        options.write_times = self.memtable_write_times.clone();
+        let mut writer = SSTableWriter::new(&sstable_path, options)?;
+        for (key, versions) in self.memtable.iter_versions() {
+            for (i, version) in versions.iter().enumerate() {
+                // Older versions only matter while a pin can still see them
+                let visible_to_pin = i == 0
+                    || self
+                        .pinned_sequences
+                        .range(version.seq..versions[i - 1].seq)
+                        .next()
+                        .is_some();
+                if visible_to_pin {
+                    writer.add(&key, version.seq, version.value.as_deref())?;
+                }
+            }
+        }
+        let meta = writer.finish()?;
+        self.count_sstable_written(meta.file_size);
+        let result = FlushResult {
+            sstable: Some(SSTableId(number)),
+            entries_written: meta.num_items,
+            bytes_written: meta.file_size,
+        };
+        let mut bloom_filter = meta.bloom_filter;
+
+        let (overlapping, obsolete) = self.shadowed_by_memtable();
+
+        if let Some(bloom_filter) = bloom_filter.as_mut() {
+            // Remember how many of these keys shadow a copy in an older
+            // table, so statistics can estimate distinct keys without
+            // double counting
+            bloom_filter.set_overlapping_items(overlapping);
+
+            let storage = self
+                .config
+                .sync_on_flush
+                .then_some(self.config.storage.as_ref());
+            Self::write_bloom_filter(&sstable_path, bloom_filter, storage)?;
+        }
+
+        // Every write logged so far is in the memtable, so the newest
+        // sequence number is the highest one in this table
+        let table = SSTableInfo {
+            path: sstable_path,
+            generation: self.next_generation,
+            compacted: false,
+            reads: Arc::default(),
+            max_sequence: self.last_sequence,
+            stats: TableStats {
+                entries: meta.num_items as u64,
+                tombstones: meta.tombstones as u64,
+                shadowed: meta.shadowed as u64,
+                obsolete: 0,
+            },
+            write_times: self.memtable_write_times.clone(),
+        };
+        // Only an estimate, so a flush that fails below and is retried may
+        // overstate it until the tables are compacted
+        for (info, records) in self.sstables.iter_mut().zip(obsolete) {
+            info.stats.add_obsolete(records);
+        }
+
+        // The SSTable only becomes part of the tree once the manifest lists
+        // it. The manifest goes first, so a failure leaves the tree as it
+        // was: the memtable and WAL still hold every write.
+        let mut manifest = self.manifest();
+        manifest
+            .sstables
+            .insert(0, self.table_entry(number, &table));
+        manifest.store(&self.config.data_dir)?;
+
+        // Make the new directory entries durable before the WAL, which is
+        // the only other copy of this data, goes away
+        if self.config.sync_on_flush {
+            self.config.storage.sync_dir(&self.config.data_dir)?;
+        }
+
+        self.install_flushed_table(table, bloom_filter);
+
+        // Only once readers can find the data in the new table
+        self.writable_wal()?.clear()?;
+        self.wal_entries_at_open = 0;
+        // A torn entry a poisoned tree couldn't cut off went with the rest
+        if let Some(poison) = &mut self.poison {
+            poison.rollback = None;
+        }
+        self.last_flush = self.config.clock.now();
+        Ok(result)
+    }
+
+    /// Finds the memtable keys that shadow a version in an older table
+    ///
+    /// Returns how many keys an older table might hold, and per index into
+    /// `sstables` the keys charged to that table as now obsolete (see
+    /// `garbage`): each key goes to the newest older table whose filter
+    /// says it might hold the key. A table without a filter in memory might
+    /// hold any key, but isn't charged for it.
+    ///
+    /// The keys of a table no larger than the memtable are read to confirm
+    /// its filter, so false positives don't turn a small table into
+    /// garbage; in a larger one they hardly move the ratio.
+    fn shadowed_by_memtable(&self) -> (usize, Vec<u64>) {
+        let order = self.read_order();
+        let flushed = self.memtable.len() as u64;
+        let mut overlapping = 0;
+        let mut obsolete = vec![0; self.sstables.len()];
+        // Read the first time a filter points at the table; `None` if it
+        // can't be read, leaving the filter's answer
+        let mut exact: HashMap<usize, Option<HashSet<Vec<u8>>>> = HashMap::new();
+        for key in self.memtable.keys() {
+            for &i in &order {
+                let Some(bf) = self.bloom_filters.resident(i) else {
+                    overlapping += 1;
+                    break;
+                };
+                if !bf.might_contain(&key) {
+                    continue;
+                }
+                let stats = &self.sstables[i].stats;
+                if stats.entries > 0 && stats.entries <= flushed {
+                    let keys = exact
+                        .entry(i)
+                        .or_insert_with(|| self.sstable_keys(&self.sstables[i].path));
+                    if keys.as_ref().is_some_and(|keys| !keys.contains(&key)) {
+                        continue;
+                    }
+                }
+                overlapping += 1;
+                obsolete[i] += 1;
+                break;
+            }
+        }
+        (overlapping, obsolete)
+    }
+
+    /// Every key of an SSTable, or `None` if it can't be read
+    fn sstable_keys(&self, path: &Path) -> Option<HashSet<Vec<u8>>> {
+        let mut reader = self.open_sstable(path).ok()?;
+        let mut keys = HashSet::new();
+        let mut key = Vec::new();
+        while reader.next_key_into(&mut key).ok()? {
+            keys.insert(key.clone());
+        }
+        Some(keys)
+    }
+
+    /// Adopts an SSTable file from elsewhere, such as another tree's
+    /// directory, as the newest table of this tree
+    ///
+    /// Copying a table into the directory doesn't work: it carries the id
+    /// of the tree that wrote it, and opening refuses it (see `tree_id`).
+    /// Ingesting checks the file like a table on open, then rewrites it
+    /// into a new table stamped with this tree's id, compressed and
+    /// filtered the way the tree is configured, keeping only the newest
+    /// version of each key. The source file is left alone.
+    ///
+    /// The memtable is flushed first, and all the file's records get one
+    /// new sequence number: its keys, tombstones included, win over what
+    /// the tree held before, and read states and pins taken earlier don't
+    /// see them. A damaged file fails with `InvalidData` and changes
+    /// nothing.
+    pub fn ingest_sstable_file(&mut self, path: &Path) -> std::io::Result<SSTableId> {
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+        if let Some(reason) = sstable::check_integrity(path, self.config.read_buffer_bytes)? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Can't ingest SSTable {}: {}", path.display(), reason),
+            ));
+        }
+        self.flush()?;
+
+        let number = self.sstable_counter;
+        let sstable_path = Self::sstable_path(&self.config.data_dir, number);
+        self.sstable_counter += 1;
+        self.ingest_into(path, number, &sstable_path)
+            .context(Operation::Flush, &sstable_path)?;
+        Ok(SSTableId(number))
+    }
+
+    /// Replays a WAL file from elsewhere, such as the `wal.log` salvaged
+    /// from a dead instance, into this tree as normal writes
+    ///
+    /// The file is read the way opening reads a tree's own log: entries
+    /// are checked as they are parsed, against their checksum too in logs
+    /// that have them (see `WAL::replay`), and the first damaged one ends
+    /// the replay, since nothing after it can be trusted to start at an entry
+    /// boundary. Its bytes, and the reason, are reported. Well-formed
+    /// entries over this tree's `size_limits` are skipped. An entry with
+    /// a sequence number older than the version of its key this tree held
+    /// before the call is a conflict and is skipped too; entries of logs
+    /// without sequence numbers always apply, in log order.
+    ///
+    /// Applied entries get new sequence numbers of this tree. The file is
+    /// left alone. A failing write stops the call with what was applied
+    /// before it kept.
+    pub fn apply_wal_file(&mut self, path: &Path) -> std::io::Result<ApplyReport> {
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+        if !path.is_file() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No WAL file at {}", path.display()),
+            ));
+        }
+        // Lengths are still checked against the file's size before
+        // anything is allocated; the tree's own limits decide what applies
+        let structural = SizeLimits {
+            max_key_size: u32::MAX as usize,
+            max_value_size: u32::MAX as usize,
+        };
+        let recovery = WAL::replay(path, structural)?;
+        let mut report = ApplyReport {
+            dropped_bytes: recovery.dropped_bytes(),
+            corruption: recovery.corruption.clone(),
+            ..ApplyReport::default()
+        };
+
+        let before = self.last_sequence;
+        for entry in recovery.entries {
+            let value = (entry.op == WALOp::Put).then_some(entry.value);
+            if self
+                .config
+                .size_limits
+                .check(&entry.key, value.as_deref())
+                .is_err()
+            {
+                report.skipped += 1;
+                continue;
+            }
+            // Versions written by this call don't count: later entries of
+            // the log replace them
+            if entry.seq != 0
+                && self
+                    .newest_sequence(&entry.key)
+                    .is_some_and(|held| held <= before && held > entry.seq)
+            {
+                report.conflicted += 1;
+                continue;
+            }
+            match value {
+                Some(value) => self.put(entry.key, value)?,
+                None => self.delete(entry.key)?,
+            }
+            report.applied += 1;
+        }
+        Ok(report)
+    }
+
+    /// Rewrites `source` as SSTable `number` and makes it the newest table
+    /// (see `ingest_sstable_file`)
+    fn ingest_into(
+        &mut self,
+        source: &Path,
+        number: usize,
+        sstable_path: &Path,
+    ) -> std::io::Result<()> {
+        let mut keys = 0;
+        let mut key = Vec::new();
+        let mut previous = Vec::new();
+        let mut reader = SSTableReader::open_buffered(source, self.config.read_buffer_bytes)?;
+        while reader.next_key_into(&mut key)? {
+            if keys == 0 || key != previous {
+                keys += 1;
+                std::mem::swap(&mut key, &mut previous);
+            }
+        }
+
+        let seq = self.last_sequence + 1;
+        let mut options = self.writer_options(keys, true);
+        options.dictionary = self.dictionary_for(
+            SSTableReader::open_buffered(source, self.config.read_buffer_bytes)?
+                .filter_map(|(_, _, value)| value),
+            keys,
+        );
+        options
+            .write_times
+            .record(seq, seq, self.config.clock.now());
+        let write_times = options.write_times.clone();
+        let mut writer = SSTableWriter::new(sstable_path, options)?;
+        let mut newest: Option<Vec<u8>> = None;
+        for (key, _, value) in SSTableReader::open_buffered(source, self.config.read_buffer_bytes)?
+        {
+            // Older versions of a key follow its newest one
+            if newest.as_deref() == Some(key.as_slice()) {
+                continue;
+            }
+            writer.add(&key, seq, value.as_deref())?;
+            newest = Some(key);
+        }
+        let meta = writer.finish()?;
+        self.count_sstable_written(meta.file_size);
+        if let Some(bloom_filter) = &meta.bloom_filter {
+            Self::write_bloom_filter(
+                sstable_path,
+                bloom_filter,
+                Some(self.config.storage.as_ref()),
+            )?;
+        }
+
+        let table = SSTableInfo {
+            path: sstable_path.to_path_buf(),
+            generation: self.next_generation,
+            compacted: false,
+            reads: Arc::default(),
+            max_sequence: seq,
+            stats: TableStats {
+                entries: meta.num_items as u64,
+                tombstones: meta.tombstones as u64,
+                shadowed: 0,
+                obsolete: 0,
+            },
+            write_times,
+        };
+        let mut manifest = self.manifest();
+        manifest
+            .sstables
+            .insert(0, self.table_entry(number, &table));
+        manifest.store(&self.config.data_dir)?;
+        self.config.storage.sync_dir(&self.config.data_dir)?;
+
+        self.last_sequence = seq;
+        self.install_flushed_table(table, meta.bloom_filter);
+        Ok(())
+    }
+
+    /// Creates a tree in an empty directory and writes `data` straight
+    /// into its SSTables
+    ///
+    /// For tests and small tools that start from known data. The pairs may
+    /// come in any order; they are sorted, and a key given more than once
+    /// keeps its last value. They go into SSTables of about
+    /// `memtable_size_threshold` bytes each, like flushes of that much data
+    /// would, but never through the WAL. Every record gets sequence 1.
+    ///
+    /// Fails with `AlreadyExists` if the data directory (or the WAL
+    /// directory) already holds a tree or anything else, with
+    /// `InvalidInput` for a key or value over `size_limits` (before
+    /// anything is created), and for a read-only config.
+    pub fn from_iter<K, V>(
+        config: LSMConfig,
+        data: impl IntoIterator<Item = (K, V)>,
+    ) -> std::io::Result<Self>
+    where
+        K: Into<Vec<u8>>,
+        V: Into<Vec<u8>>,
+    {
+        if config.read_only {
+            return Err(read_only_error());
+        }
+        let mut sorted = BTreeMap::new();
+        for (key, value) in data {
+            let (key, value) = (key.into(), value.into());
+            config.size_limits.check(&key, Some(&value))?;
+            sorted.insert(key, value);
+        }
+
+        let is_empty_dir = |dir: &Path| match std::fs::read_dir(dir) {
+            Ok(mut entries) => entries.next().is_none(),
+            Err(e) => e.kind() == std::io::ErrorKind::NotFound,
+        };
+        let wal_path = config.resolved_wal_dir().join("wal.log");
+        if !is_empty_dir(&config.data_dir) || wal_path.exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} is not empty; from_iter only creates new trees",
+                    config.data_dir.display()
+                ),
+            ));
+        }
+
+        let mut tree = Self::open(config)?;
+        let data_dir = tree.config.data_dir.clone();
+        tree.bulk_load(sorted.into_iter().map(Ok))
+            .context(Operation::Flush, &data_dir)?;
+        Ok(tree)
+    }
+
+    /// Writes pairs in strictly ascending key order into new SSTables of
+    /// about a memtable's worth of bytes each (see `from_iter`), and
+    /// returns how many it wrote
+    ///
+    /// Repeated keys are settled by `LSMConfig::ingest_dedup` (see
+    /// `OrderedRecords`). Only one table's worth of pairs is held at a
+    /// time. Nothing joins the tree before the last pair is written: an
+    /// error from `entries`, or a key out of order, deletes the tables
+    /// written so far and leaves the tree as it was.
+    fn bulk_load(
+        &mut self,
+        entries: impl IntoIterator<Item = std::io::Result<(Vec<u8>, Vec<u8>)>>,
+    ) -> std::io::Result<u64> {
+        let seq = self.last_sequence + 1;
+        let mut written = Vec::new();
+        let entries = OrderedRecords::new(entries, self.config.ingest_dedup);
+        if let Err(error) = self.write_bulk_tables(entries, seq, &mut written) {
+            for (_, table, _) in &written {
+                self.table_refs.remove_when_unused(&table.path)?;
+            }
+            return Err(error);
+        }
+
+        let mut manifest = self.manifest();
+        for (number, table, _) in &written {
+            manifest
+                .sstables
+                .insert(0, self.table_entry(*number, table));
+        }
+        manifest.store(&self.config.data_dir)?;
+        self.config.storage.sync_dir(&self.config.data_dir)?;
+
+        if !written.is_empty() {
+            self.last_sequence = seq;
+        }
+        let mut loaded = 0;
+        for (_, table, bloom_filter) in written {
+            loaded += table.stats.entries;
+            self.install_flushed_table(table, bloom_filter);
+        }
+        Ok(loaded)
+    }
+
+    /// Writes the tables of `bulk_load` into `written`, one chunk at a time
+    fn write_bulk_tables(
+        &mut self,
+        entries: impl IntoIterator<Item = std::io::Result<(Vec<u8>, Vec<u8>)>>,
+        seq: u64,
+        written: &mut Vec<(usize, SSTableInfo, Option<BloomFilter>)>,
+    ) -> std::io::Result<()> {
+        let threshold = self.config.memtable_size_threshold.max(1);
+        let mut chunk: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let mut chunk_bytes = 0;
+        for entry in entries {
+            let (key, value) = entry?;
+            if chunk_bytes >= threshold {
+                // Tables cover disjoint key ranges, so their order only has
+                // to be consistent: later chunks become newer generations
+                let generation = self.next_generation + written.len() as u64;
+                written.push(self.write_bulk_table(&chunk, seq, generation)?);
+                chunk.clear();
+                chunk_bytes = 0;
+            }
+            chunk_bytes += Memtable::entry_size(&key, Some(&value));
+            chunk.push((key, value));
+        }
+        if !chunk.is_empty() {
+            let generation = self.next_generation + written.len() as u64;
+            written.push(self.write_bulk_table(&chunk, seq, generation)?);
+        }
+        Ok(())
+    }
+
+    /// Writes one table of `bulk_load`
+    fn write_bulk_table(
+        &mut self,
+        chunk: &[(Vec<u8>, Vec<u8>)],
+        seq: u64,
+        generation: u64,
+    ) -> std::io::Result<(usize, SSTableInfo, Option<BloomFilter>)> {
+        let number = self.sstable_counter;
+        let sstable_path = Self::sstable_path(&self.config.data_dir, number);
+        self.sstable_counter += 1;
+
+        let mut options = self.writer_options(chunk.len(), true);
+        options.dictionary = self.dictionary_for(chunk.iter().map(|(_, value)| value), chunk.len());
+        options
+            .write_times
+            .record(seq, seq, self.config.clock.now());
+        let write_times = options.write_times.clone();
+        let mut writer = SSTableWriter::new(&sstable_path, options)?;
+        for (key, value) in chunk {
+            writer.add(key, seq, Some(value))?;
+        }
+        let meta = writer.finish()?;
+        self.count_sstable_written(meta.file_size);
+        if let Some(bloom_filter) = &meta.bloom_filter {
+            Self::write_bloom_filter(
+                &sstable_path,
+                bloom_filter,
+                Some(self.config.storage.as_ref()),
+            )?;
+        }
+        let table = SSTableInfo {
+            path: sstable_path,
+            generation,
+            compacted: false,
+            reads: Arc::default(),
+            max_sequence: seq,
+            stats: TableStats {
+                entries: meta.num_items as u64,
+                ..TableStats::default()
+            },
+            write_times,
+        };
+        Ok((number, table, meta.bloom_filter))
+    }
+
+    /// Rewrites the whole tree into at most one SSTable holding only live
+    /// data
+    ///
+    /// The memtable is flushed first, then every SSTable is merged: the
+    /// newest version of each key is kept, and tombstones and the versions
+    /// they or newer writes shadow are dropped. Every entry kept is passed
+    /// through `LSMConfig::compaction_filter`, if there is one. If nothing
+    /// is live, no table remains at all. Old tables are deleted once the
+    /// manifest lists the result, so the directory shrinks to the size of
+    /// the live data.
+    ///
+    /// While sequences are pinned (`pin_sequence`), the versions they still
+    /// see are kept, as a flush keeps them, with the tombstones above them:
+    /// `get_at` stays exact for every pin, and the purge that follows the
+    /// last release drops them. With `LSMConfig::ttl`, entries written
+    /// longer ago than the TTL are dropped like deleted ones, pinned or
+    /// not. The read amplification average starts over, so
+    /// `needs_compaction` turns false.
+    ///
+    /// Returns what the merge read and wrote (the initial flush is not
+    /// included; it shows up in `amplification_stats` like any other), and
+    /// how many Bloom filter files of tables that no longer exist it
+    /// removed afterwards.
+    pub fn purge(&mut self) -> std::io::Result<CompactionResult> {
+        self.purge_with(&CancellationToken::new())
+    }
+
+    /// `purge`, stopping with a `Cancelled` error once `cancel` is
+    /// cancelled
+    ///
+    /// The token is checked before every entry the merge reads. A purge
+    /// stopped part way deletes the table it was writing and leaves the
+    /// SSTables and the manifest as they were; only the flush before the
+    /// merge stays done. Once the new manifest is being written the purge
+    /// runs to the end.
+    pub fn purge_with(&mut self, cancel: &CancellationToken) -> std::io::Result<CompactionResult> {
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+        cancel.check(0)?;
+        self.flush()?;
+        self.merge_tables(cancel)
+            .context(Operation::Compact, &self.config.data_dir)
+            .map_err(|error| self.stale(error))
+    }
+
+    /// Compacts like `purge`, in steps, until `budget` runs out; the next
+    /// call carries on where this one stopped
+    ///
+    /// For maintenance windows too short for a whole purge. The merge is
+    /// cut into output tables of about `memtable_size_threshold` bytes of
+    /// live entries each, in key order; each is finished and listed in the
+    /// manifest before the next one starts, so the tree is whole after
+    /// every step and a crash or a drop loses at most the table being
+    /// written. An output table once started is finished, so a call can
+    /// run over its budget by the time one takes, and every call writes
+    /// at least one (a zero budget does exactly one step).
+    ///
+    /// Outputs sit below every input table left, which still answer for
+    /// the keys they hold, and an input goes only once every key in it and
+    /// in every older input has been merged: tombstones and the versions
+    /// they shadow are dropped from the outputs, so an older input left
+    /// behind would bring them back. Input tables usually span the whole
+    /// key range, so most are only removed by the last step. Tables
+    /// written between calls stay on top and aren't merged. Each step
+    /// shifts the generations of the tables above the outputs up by one.
+    /// Versions a pinned sequence still sees are kept, as `purge` keeps
+    /// them.
+    ///
+    /// The first call of a compaction flushes the memtable. Where it
+    /// stopped is only kept in memory: after a restart, or when a table
+    /// it was merging has gone (a `purge`, say), the next call starts
+    /// over, merging the outputs so far like any other table.
+    ///
+    /// Returns what this call read and wrote: `input_tables` and
+    /// `bytes_read` count the inputs it removed, so the calls of one
+    /// compaction add up to what a `purge` of the same tables reports,
+    /// and `completed` tells whether the compaction is done. The output
+    /// tables' Bloom filters are sized for exactly the records they hold,
+    /// and their headers carry the write times of just those records.
+    pub fn compact_for(&mut self, budget: Duration) -> std::io::Result<CompactionResult> {
+        self.compact_for_with(budget, &CancellationToken::new())
+    }
+
+    /// `compact_for`, stopping with a `Cancelled` error once `cancel` is
+    /// cancelled
+    ///
+    /// The token is checked before every entry a step reads. A step
+    /// stopped part way deletes the table it was writing and leaves its
+    /// inputs and the manifest as they were; the steps before it stay
+    /// done, and the next call carries on after the last of them.
+    pub fn compact_for_with(
+        &mut self,
+        budget: Duration,
+        cancel: &CancellationToken,
+    ) -> std::io::Result<CompactionResult> {
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+        cancel.check(0)?;
+        let deadline = Instant::now() + budget;
+        let resume = match self.compaction_resume.take() {
+            Some(resume) if resume.is_current(&self.sstables) => resume,
+            _ => {
+                self.flush()?;
+                CompactionResume::start(&self.read_order_tables())?
+            }
+        };
+        self.compact_until(resume, deadline, cancel)
+            .context(Operation::Compact, &self.config.data_dir)
+            .map_err(|error| self.stale(error))
+    }
+
+    /// The steps of `compact_for`, until the merge is done or `deadline`
+    /// has passed
+    fn compact_until(
+        &mut self,
+        mut resume: CompactionResume,
+        deadline: Instant,
+        cancel: &CancellationToken,
+    ) -> std::io::Result<CompactionResult> {
+        let mut result = CompactionResult::default();
+        loop {
+            // Nothing is kept for the next call if the step fails: it may
+            // have removed tables the state still lists. A cancelled step
+            // stops before it changes anything.
+            let done = match self.compaction_step(&mut resume, &mut result, cancel) {
+                Ok(done) => done,
+                Err(error) => {
+                    if Cancelled::from_io(&error).is_some() {
+                        self.compaction_resume = Some(resume);
+                    }
+                    return Err(error);
+                }
+            };
+            if done {
+                self.read_amp = ReadAmpTracker::default();
+                self.last_compaction = Some(self.config.clock.now());
+                result.orphans_removed = self.remove_orphans()?;
+                result.completed = true;
+                return Ok(result);
+            }
+            if Instant::now() >= deadline {
+                self.compaction_resume = Some(resume);
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Writes the next output table of `compact_for` and removes the inputs
+    /// it was the last to need; true once every input is merged
+    fn compaction_step(
+        &mut self,
+        resume: &mut CompactionResume,
+        result: &mut CompactionResult,
+        cancel: &CancellationToken,
+    ) -> std::io::Result<bool> {
+        let inputs: Vec<SSTableInfo> = resume
+            .inputs
+            .iter()
+            .filter_map(|(path, _)| self.sstables.iter().find(|t| &t.path == path).cloned())
+            .collect();
+        let paths: Vec<PathBuf> = inputs.iter().map(|t| t.path.clone()).collect();
+        let write_times = WriteTimes::merge(
+            &inputs
+                .iter()
+                .map(|t| t.write_times.clone())
+                .collect::<Vec<_>>(),
+        );
+        let cutoff = self.expiry_cutoff();
+        let expired = |seq| {
+            cutoff.is_some_and(|cutoff| write_times.time_of(seq).is_some_and(|t| t <= cutoff))
+        };
+        let empty = Memtable::new();
+        let buffer_bytes = self.config.read_buffer_bytes;
+        let start = match &resume.merged_to {
+            Some(key) => Bound::Excluded(key.clone()),
+            None => Bound::Unbounded,
+        };
+
+        // A first pass finds where the output ends: after a memtable's
+        // worth of live entries, or with the input
+        let threshold = self.config.memtable_size_threshold.max(1);
+        let (mut live, mut bytes, mut last, mut end) = (0, 0, None, None);
+        let mut live_ranges = vec![false; write_times.range_count()];
+        let mut entries = MergeIter::all_versions(
+            &empty,
+            &paths,
+            (start.clone(), Bound::Unbounded),
+            false,
+            buffer_bytes,
+        );
+        entries.set_cancellation(cancel);
+        while let Some((key, versions)) = entries.next_versions() {
+            // Only cut where more keys follow, so no step is left empty
+            if bytes >= threshold {
+                end = last;
+                break;
+            }
+            for (seq, value) in self.compacted_versions(&key, versions, expired) {
+                live += 1;
+                bytes += Memtable::entry_size(&key, value.as_deref());
+                if let Some(range) = write_times.range_of(seq) {
+                    live_ranges[range] = true;
+                }
+            }
+            last = Some(key);
+        }
+        cancel.check(0)?;
+        let range = (start, end.clone().map_or(Bound::Unbounded, Bound::Included));
+
+        let mut output = None;
+        if live > 0 {
+            let number = self.sstable_counter;
+            self.sstable_counter += 1;
+
+            let mut options = self.writer_options(live, true);
+            options.dictionary = self.dictionary_for(
+                MergeIter::new(&empty, &paths, range.clone(), false, buffer_bytes)
+                    .filter_map(|(key, value)| self.compaction_filtered(&key, value)),
+                live,
+            );
+            let mut table_times = write_times.clone();
+            table_times.retain_ranges(&live_ranges);
+            options.write_times = table_times.clone();
+            let dir = self.table_dir(self.belongs_cold(true, &table_times));
+            let path = Self::sstable_path(dir, number);
+            let mut writer = SSTableWriter::new(&path, options)?;
+            let mut merged = MergeIter::all_versions(&empty, &paths, range, false, buffer_bytes);
+            merged.set_cancellation(cancel);
+            let mut written = 0;
+            while let Some((key, versions)) = merged.next_versions() {
+                for (seq, value) in self.compacted_versions(&key, versions, expired) {
+                    writer.add(&key, seq, value.as_deref())?;
+                    written += 1;
+                }
+            }
+            // Dropping the unfinished writer deletes what it wrote
+            cancel.check(written as u64)?;
+            // A compaction filter that changed its mind leaves nothing
+            if written > 0 {
+                let meta = writer.finish()?;
+                result.bytes_written += meta.file_size;
+                result.entries_written += meta.num_items;
+                self.count_sstable_written(meta.file_size);
+                if let Some(bloom_filter) = &meta.bloom_filter {
+                    Self::write_bloom_filter(
+                        &path,
+                        bloom_filter,
+                        Some(self.config.storage.as_ref()),
+                    )?;
+                }
+                let info = SSTableInfo {
+                    path,
+                    // Set below, under the inputs left
+                    generation: 0,
+                    compacted: true,
+                    reads: Arc::default(),
+                    stats: TableStats {
+                        entries: meta.num_items as u64,
+                        tombstones: meta.tombstones as u64,
+                        shadowed: meta.shadowed as u64,
+                        obsolete: 0,
+                    },
+                    write_times: table_times,
+                    max_sequence: inputs.iter().map(|t| t.max_sequence).max().unwrap_or(0),
+                };
+                output = Some((info, meta.bloom_filter));
+            }
+        }
+
+        // Inputs go oldest first, each once all its keys are merged
+        let merged = resume
+            .inputs
+            .iter()
+            .rev()
+            .take_while(|(_, last_key)| match (&end, last_key) {
+                (Some(end), Some(last_key)) => last_key <= end,
+                _ => true,
+            })
+            .count();
+        let removed: Vec<PathBuf> = resume
+            .inputs
+            .split_off(resume.inputs.len() - merged)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+
+        // The tables left, newest first: those above the outputs, the new
+        // output, then the earlier outputs. The new output takes the
+        // lowest generation above the earlier ones, the tables above it
+        // move up one.
+        let lowest = self
+            .sstables
+            .iter()
+            .filter(|t| !removed.contains(&t.path) && !resume.outputs.contains(&t.path))
+            .map(|t| t.generation)
+            .min()
+            .unwrap_or(self.next_generation);
+        let shift = output.is_some() as u64;
+        let mut tables = Vec::with_capacity(self.sstables.len() + 1);
+        let mut slots = Vec::with_capacity(self.sstables.len() + 1);
+        let mut earlier_outputs = Vec::new();
+        for (i, table) in self.sstables.iter().enumerate() {
+            if removed.contains(&table.path) {
+                continue;
+            }
+            if resume.outputs.contains(&table.path) {
+                earlier_outputs.push((table.clone(), SlotSource::Existing(i)));
+                continue;
+            }
+            let mut table = table.clone();
+            table.generation += shift;
+            tables.push(table);
+            slots.push(SlotSource::Existing(i));
+        }
+        if let Some((mut info, bloom_filter)) = output {
+            info.generation = lowest;
+            resume.outputs.push(info.path.clone());
+            tables.push(info);
+            slots.push(SlotSource::New(bloom_filter));
+        }
+        for (table, slot) in earlier_outputs {
+            tables.push(table);
+            slots.push(slot);
+        }
+
+        // The manifest goes first; if it can't be written, the tree is
+        // still what it was before the step
+        let mut manifest = self.manifest();
+        manifest.next_file_number = self.sstable_counter;
+        manifest.sstables = tables
+            .iter()
+            .filter_map(|table| Some(self.table_entry(Self::sstable_number(&table.path)?, table)))
+            .collect();
+        self.sync_cold_dir()?;
+        manifest.store(&self.config.data_dir)?;
+        self.config.storage.sync_dir(&self.config.data_dir)?;
+
+        self.next_generation += shift;
+        self.sstables = tables;
+        self.bloom_filters.relayout(slots);
+        // Not rescanned: outputs are written after the tables above them
+        self.ordering_anomalies.retain(|anomaly| {
+            !removed.contains(&anomaly.newer) && !removed.contains(&anomaly.older)
+        });
+        self.repin_small_tables();
+        let bytes_read: u64 = removed
+            .iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|meta| meta.len())
+            .sum();
+        IoRateTrackers::record(
+            &self.io_rates.sstable_read,
+            self.config.clock.now_millis(),
+            bytes_read,
+        );
+        result.input_tables += removed.len();
+        result.bytes_read += bytes_read;
+        for path in &removed {
+            self.table_refs.remove_when_unused(path)?;
+        }
+        resume.merged_to = end;
+        Ok(resume.merged_to.is_none())
+    }
+
+    /// The live SSTables in read order, newest generation first
+    fn read_order_tables(&self) -> Vec<&SSTableInfo> {
+        self.read_order()
+            .into_iter()
+            .map(|i| &self.sstables[i])
+            .collect()
+    }
+
+    /// Merges every SSTable into at most one (see `purge`)
+    fn merge_tables(&mut self, cancel: &CancellationToken) -> std::io::Result<CompactionResult> {
+        let mut result = CompactionResult {
+            completed: true,
+            ..CompactionResult::default()
+        };
+        // A `compact_for` in progress has nothing left to resume
+        self.compaction_resume = None;
+        if self.sstables.is_empty() {
+            return Ok(result);
+        }
+        result.input_tables = self.sstables.len();
+        result.bytes_read = self.sstable_bytes_on_disk();
+        IoRateTrackers::record(
+            &self.io_rates.sstable_read,
+            self.config.clock.now_millis(),
+            result.bytes_read,
+        );
+
+        let paths = self.paths_newest_first();
+        let empty = Memtable::new();
+        let full_range = (Bound::Unbounded, Bound::Unbounded);
+        let buffer_bytes = self.config.read_buffer_bytes;
+        let mut times = Vec::with_capacity(paths.len());
+        for path in &paths {
+            times.push(SSTableReader::open(path)?.write_times()?);
+        }
+        let write_times = WriteTimes::merge(&times);
+        // Under a TTL, expired entries go like deleted ones
+        let cutoff = self.expiry_cutoff();
+        let expired = |seq| {
+            cutoff.is_some_and(|cutoff| write_times.time_of(seq).is_some_and(|t| t <= cutoff))
+        };
+        // A keys-only pass first, so the header gets the write times of
+        // exactly the surviving versions. Only a flag per write-time range
+        // is kept, not the sequences: the merge streams every input, and
+        // memory stays bounded by the number of tables, not their size
+        let mut live = 0;
+        let mut live_ranges = vec![false; write_times.range_count()];
+        let mut keys =
+            MergeIter::all_versions(&empty, &paths, full_range.clone(), true, buffer_bytes);
+        keys.set_cancellation(cancel);
+        while let Some((_, versions)) = keys.next_versions() {
+            for (seq, _) in self.versions_to_keep(versions, expired) {
+                live += 1;
+                if let Some(range) = write_times.range_of(seq) {
+                    live_ranges[range] = true;
+                }
+            }
+        }
+        cancel.check(0)?;
+
+        let mut table = None;
+        if live > 0 {
+            let number = self.sstable_counter;
+            self.sstable_counter += 1;
+
+            let mut options = self.writer_options(live, true);
+            options.filter_sizing = self.config.compaction_filter_sizing;
+            if options.filter_sizing == FilterSizing::Expected {
+                // The input's entry count: known without merging, but every
+                // duplicate, tombstone and filtered-out entry is counted
+                let input: u64 = self.sstables.iter().map(|t| t.stats.entries).sum();
+                options.expected_items = (input as usize).max(live);
+            }
+            // Retrained on the merged values, not kept from any input table
+            options.dictionary = self.dictionary_for(
+                MergeIter::new(&empty, &paths, full_range.clone(), false, buffer_bytes)
+                    .filter_map(|(key, value)| self.compaction_filtered(&key, value)),
+                live,
+            );
+            let mut table_times = write_times.clone();
+            table_times.retain_ranges(&live_ranges);
+            options.write_times = table_times.clone();
+            let dir = self.table_dir(self.belongs_cold(true, &table_times));
+            let path = Self::sstable_path(dir, number);
+            let mut writer = SSTableWriter::new(&path, options)?;
+            let mut merged =
+                MergeIter::all_versions(&empty, &paths, full_range, false, buffer_bytes);
+            merged.set_cancellation(cancel);
+            let mut written = 0;
+            while let Some((key, versions)) = merged.next_versions() {
+                for (seq, value) in self.compacted_versions(&key, versions, expired) {
+                    writer.add(&key, seq, value.as_deref())?;
+                    written += 1;
+                }
+            }
+            // Dropping the unfinished writer deletes what it wrote
+            cancel.check(written as u64)?;
+            // The compaction filter may have removed everything; a writer
+            // that isn't finished leaves no file behind
+            if written > 0 {
+                let meta = writer.finish()?;
+                result.bytes_written = meta.file_size;
+                result.entries_written = meta.num_items;
+                self.count_sstable_written(meta.file_size);
+                let bloom_filter = meta.bloom_filter;
+                if let Some(bloom_filter) = &bloom_filter {
+                    Self::write_bloom_filter(
+                        &path,
+                        bloom_filter,
+                        Some(self.config.storage.as_ref()),
+                    )?;
+                }
+
+                let info = SSTableInfo {
+                    path,
+                    generation: self.next_generation,
+                    compacted: true,
+                    reads: Arc::default(),
+                    stats: TableStats {
+                        entries: meta.num_items as u64,
+                        tombstones: meta.tombstones as u64,
+                        shadowed: meta.shadowed as u64,
+                        obsolete: 0,
+                    },
+                    write_times: table_times,
+                    // Recovery skips WAL entries up to this sequence
+                    max_sequence: self
+                        .sstables
+                        .iter()
+                        .map(|t| t.max_sequence)
+                        .max()
+                        .unwrap_or(0),
+                };
+                table = Some((number, info, bloom_filter));
+            }
+        }
+
+        // The manifest goes first; if it can't be written, the old tables
+        // are still the tree
+        let mut manifest = self.manifest();
+        manifest.next_file_number = self.sstable_counter;
+        manifest.sstables = table
+            .iter()
+            .map(|(number, info, _)| self.table_entry(*number, info))
+            .collect();
+        self.sync_cold_dir()?;
+        manifest.store(&self.config.data_dir)?;
+        self.config.storage.sync_dir(&self.config.data_dir)?;
+
+        let old_tables = std::mem::take(&mut self.sstables);
+        let mut filters = Vec::new();
+        if let Some((_, info, bloom_filter)) = table {
+            self.next_generation += 1;
+            self.sstables.push(info);
+            filters.push(bloom_filter);
+        }
+        self.bloom_filters = FilterCache::new(filters, self.config.bloom_filter_memory_budget);
+        self.repin_small_tables();
+        // Reads past the old tables say nothing about the new layout
+        self.read_amp = ReadAmpTracker::default();
+        self.last_compaction = Some(self.config.clock.now());
+
+        for old in &old_tables {
+            self.table_refs.remove_when_unused(&old.path)?;
+        }
+        result.orphans_removed = self.remove_orphans()?;
+        Ok(result)
+    }
+
+    /// Merges every run of adjacent small SSTables into one table, once
+    /// more than `threshold` tables are small (see
+    /// `LSMConfig::small_table_merge_threshold`), and returns how many
+    /// tables were merged and how many they became
+    ///
+    /// A run stands for its tables in read order: the merged table takes
+    /// the newest generation of the run, so it stays newer than every
+    /// table before the run and older than every one after it. Only the
+    /// newest version of each key is kept. Tombstones are too, since they
+    /// may shadow older tables, unless the run ends with the oldest table
+    /// of the tree. Nothing else is dropped: expiry and the compaction
+    /// filter are left to `purge`. Tables in the cold directory are left
+    /// alone.
+    fn merge_small_tables(&mut self, threshold: usize) -> std::io::Result<(usize, usize)> {
+        let order = self.read_order();
+        let small: Vec<bool> = order
+            .iter()
+            .map(|&i| {
+                let path = &self.sstables[i].path;
+                !self.is_cold(path)
+                    && std::fs::metadata(path)
+                        .is_ok_and(|meta| meta.len() <= self.config.small_table_bytes)
+            })
+            .collect();
+        if small.iter().filter(|&&small| small).count() <= threshold {
+            return Ok((0, 0));
+        }
+        // Runs of at least two small tables, as ranges of `order`
+        let mut runs = Vec::new();
+        let mut start = 0;
+        while start < order.len() {
+            let end = start + small[start..].iter().take_while(|&&small| small).count();
+            if end - start >= 2 {
+                runs.push(start..end);
+            }
+            start = end + 1;
+        }
+
+        let empty = Memtable::new();
+        let full_range = (Bound::Unbounded, Bound::Unbounded);
+        let buffer_bytes = self.config.read_buffer_bytes;
+        let mut merged = Vec::with_capacity(runs.len());
+        for run in &runs {
+            let tables: Vec<SSTableInfo> = order[run.clone()]
+                .iter()
+                .map(|&i| self.sstables[i].clone())
+                .collect();
+            let paths: Vec<PathBuf> = tables.iter().map(|t| t.path.clone()).collect();
+            let keep_tombstones = run.end < order.len();
+            let input: u64 = tables.iter().map(|t| t.stats.entries).sum();
+            let number = self.sstable_counter;
+            self.sstable_counter += 1;
+
+            let mut options = self.writer_options(input as usize, true);
+            options.write_times = WriteTimes::merge(
+                &tables
+                    .iter()
+                    .map(|t| t.write_times.clone())
+                    .collect::<Vec<_>>(),
+            );
+            options.dictionary = self.dictionary_for(
+                MergeIter::new(&empty, &paths, full_range.clone(), false, buffer_bytes)
+                    .map(|(_, value)| value),
+                input as usize,
+            );
+            let path = Self::sstable_path(&self.config.data_dir, number);
+            let write_times = options.write_times.clone();
+            let mut writer = SSTableWriter::new(&path, options)?;
+            let mut entries =
+                MergeIter::new(&empty, &paths, full_range.clone(), false, buffer_bytes);
+            let mut written = 0;
+            while let Some((key, seq, value)) = entries.next_entry() {
+                if value.is_some() || keep_tombstones {
+                    writer.add(&key, seq, value.as_deref())?;
+                    written += 1;
+                }
+            }
+            // A run of nothing but tombstones at the bottom of the tree
+            // goes without a table in its place
+            if written == 0 {
+                merged.push(None);
+                continue;
+            }
+            let meta = writer.finish()?;
+            self.count_sstable_written(meta.file_size);
+            if let Some(bloom_filter) = &meta.bloom_filter {
+                Self::write_bloom_filter(&path, bloom_filter, Some(self.config.storage.as_ref()))?;
+            }
+            let info = SSTableInfo {
+                path,
+                generation: tables.iter().map(|t| t.generation).max().unwrap_or(0),
+                compacted: false,
+                reads: Arc::default(),
+                stats: TableStats {
+                    entries: meta.num_items as u64,
+                    tombstones: meta.tombstones as u64,
+                    shadowed: meta.shadowed as u64,
+                    obsolete: 0,
+                },
+                write_times,
+                max_sequence: tables.iter().map(|t| t.max_sequence).max().unwrap_or(0),
+            };
+            merged.push(Some((info, meta.bloom_filter)));
+        }
+
+        let merged_away = runs.iter().map(|run| run.len()).sum();
+        let merged_into = merged.iter().flatten().count();
+        // The new table list, newest first, with where each filter comes
+        // from
+        let mut tables = Vec::with_capacity(order.len());
+        let mut slots = Vec::with_capacity(order.len());
+        let mut runs_left = runs.iter().zip(merged).peekable();
+        let mut position = 0;
+        while position < order.len() {
+            if let Some((run, table)) = runs_left.next_if(|(run, _)| run.start == position) {
+                if let Some((info, bloom_filter)) = table {
+                    tables.push(info);
+                    slots.push(SlotSource::New(bloom_filter));
+                }
+                position = run.end;
+                continue;
+            }
+            tables.push(self.sstables[order[position]].clone());
+            slots.push(SlotSource::Existing(order[position]));
+            position += 1;
+        }
+
+        // The manifest goes first; if it can't be written, the small
+        // tables are still the tree
+        let mut manifest = self.manifest();
+        manifest.next_file_number = self.sstable_counter;
+        manifest.sstables = tables
+            .iter()
+            .filter_map(|table| Some(self.table_entry(Self::sstable_number(&table.path)?, table)))
+            .collect();
+        manifest.store(&self.config.data_dir)?;
+        self.config.storage.sync_dir(&self.config.data_dir)?;
+
+        let old_tables = std::mem::replace(&mut self.sstables, tables);
+        self.bloom_filters.relayout(slots);
+        self.ordering_anomalies = Self::find_ordering_anomalies(&self.sstables);
+        for run in &runs {
+            for &index in &order[run.clone()] {
+                self.table_refs
+                    .remove_when_unused(&old_tables[index].path)?;
+            }
+        }
+        Ok((merged_away, merged_into))
+    }
+
+    /// Runs the compaction filter on a live entry, returning the value to
+    /// write or `None` to drop it
+    fn compaction_filtered(&self, key: &[u8], value: Vec<u8>) -> Option<Vec<u8>> {
+        let Some(filter) = &self.config.compaction_filter else {
+            return Some(value);
+        };
+        match filter.filter(key, &value) {
+            FilterDecision::Keep => Some(value),
+            FilterDecision::Remove => None,
+            FilterDecision::Replace(value) => Some(value),
+        }
+    }
+
+    /// The versions of a key (newest first) a compaction keeps: the newest,
+    /// and every older one a pinned sequence still sees, like a flush
+    /// keeps them
+    ///
+    /// An expired version goes, with every version written before it.
+    /// Tombstones go once no older version is kept for them to hide: the
+    /// merge reads every table that could hold one.
+    fn versions_to_keep(&self, versions: Versions, expired: impl Fn(u64) -> bool) -> Versions {
+        let mut kept = Vec::with_capacity(1);
+        let mut newer = None;
+        for (seq, value) in versions {
+            let visible_to_pin =
+                newer.is_none_or(|newer| self.pinned_sequences.range(seq..newer).next().is_some());
+            newer = Some(seq);
+            if expired(seq) {
+                break;
+            }
+            if visible_to_pin {
+                kept.push((seq, value));
+            }
+        }
+        while kept.last().is_some_and(|(_, value)| value.is_none()) {
+            kept.pop();
+        }
+        kept
+    }
+
+    /// `versions_to_keep`, with the values run through the compaction
+    /// filter; a removed value stays as a tombstone while an older version
+    /// is kept under it
+    fn compacted_versions(
+        &self,
+        key: &[u8],
+        versions: Versions,
+        expired: impl Fn(u64) -> bool,
+    ) -> Versions {
+        let mut kept: Versions = self
+            .versions_to_keep(versions, expired)
+            .into_iter()
+            .map(|(seq, value)| {
+                (
+                    seq,
+                    value.and_then(|value| self.compaction_filtered(key, value)),
+                )
+            })
+            .collect();
+        while kept.last().is_some_and(|(_, value)| value.is_none()) {
+            kept.pop();
+        }
+        kept
+    }
+
+    /// Moves the SSTables that have become cold under
+    /// `LSMConfig::cold_policy` to `LSMConfig::cold_dir`, returning their
+    /// ids (none without a cold directory)
+    ///
+    /// Each table and its Bloom filter are copied and synced, the cold
+    /// directory is synced, the manifest is updated to list the copies, and
+    /// only then are the originals deleted (once no `TreeState` reads
+    /// them). A crash before the manifest update leaves the tree as it was,
+    /// with stray copies the next migration overwrites; one after it leaves
+    /// stray originals (see `disk_usage`). Reads go on as before: a table
+    /// keeps its id, generation and filter.
+    pub fn migrate_cold(&mut self) -> std::io::Result<Vec<SSTableId>> {
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+        let Some(cold_dir) = self.config.cold_dir.clone() else {
+            return Ok(Vec::new());
+        };
+        let storage = self.config.storage.clone();
+
+        let mut new_sstables = self.sstables.clone();
+        let mut moved = Vec::new();
+        for (table, new_table) in self.sstables.iter().zip(&mut new_sstables) {
+            if self.is_cold(&table.path) || !self.belongs_cold(table.compacted, &table.write_times)
+            {
+                continue;
+            }
+            let (Some(name), Some(number)) =
+                (table.path.file_name(), Self::sstable_number(&table.path))
+            else {
+                continue;
+            };
+            let cold_path = cold_dir.join(name);
+            copy_synced(storage.as_ref(), &table.path, &cold_path)?;
+            let filter = table.path.with_extension("bloom");
+            if filter.exists() {
+                copy_synced(
+                    storage.as_ref(),
+                    &filter,
+                    &cold_path.with_extension("bloom"),
+                )?;
+            }
+            new_table.path = cold_path;
+            moved.push((SSTableId(number), table.path.clone()));
+        }
+        if moved.is_empty() {
+            return Ok(Vec::new());
+        }
+        storage.sync_dir(&cold_dir)?;
+
+        self.sstables = new_sstables;
+        self.repin_small_tables();
+        self.write_manifest()?;
+        storage.sync_dir(&self.config.data_dir)?;
+
+        let mut ids = Vec::with_capacity(moved.len());
+        for (id, old_path) in moved {
+            self.table_refs.remove_when_unused(&old_path)?;
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+
+    /// Forks the tree into `target_dir` and opens the copy
+    ///
+    /// The memtable is flushed first, then every live SSTable and its Bloom
+    /// filter is hard-linked into the target (copied where linking fails,
+    /// across file systems for instance) under the same name, and a
+    /// manifest listing them is written there. The copy gets its own, empty
+    /// WAL in `target_dir` and the same settings otherwise, except that it
+    /// has no cold directory: cold tables are linked into `target_dir` too.
+    ///
+    /// The two trees are independent from then on. SSTables are never
+    /// changed once written: compactions write new files and only unlink
+    /// the old ones, so a file both trees link stays intact for the other.
+    ///
+    /// `target_dir` must not exist or be empty. Fails on a read-only tree.
+    pub fn clone_to(&mut self, target_dir: impl AsRef<Path>) -> std::io::Result<LSMTree> {
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+        let target_dir = target_dir.as_ref();
+        if std::fs::read_dir(target_dir).is_ok_and(|mut entries| entries.next().is_some()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Clone target {} is not empty", target_dir.display()),
+            ));
+        }
+        self.flush()?;
+        std::fs::create_dir_all(target_dir)?;
+
+        for table in &self.sstables {
+            let Some(name) = table.path.file_name() else {
+                continue;
+            };
+            let target = target_dir.join(name);
+            link_or_copy(&table.path, &target)?;
+            let bloom_path = table.path.with_extension("bloom");
+            if bloom_path.exists() {
+                link_or_copy(&bloom_path, &target.with_extension("bloom"))?;
+            }
+        }
+        if let Some(format) = DirectoryFormat::load(&self.config.data_dir)? {
+            format.store(target_dir)?;
+        }
+        let mut manifest = self.manifest();
+        manifest.wal_dir = None;
+        manifest.cold_dir = None;
+        for table in &mut manifest.sstables {
+            table.cold = false;
+        }
+        manifest.store(target_dir)?;
+        self.config.storage.sync_dir(target_dir)?;
+
+        LSMTree::open(LSMConfig {
+            data_dir: target_dir.to_path_buf(),
+            wal_dir: None,
+            cold_dir: None,
+            ..self.config.clone()
+        })
+    }
+
+    /// Picks up SSTables that another process added to or removed from the
+    /// manifest, without touching the memtable
+    ///
+    /// Meant for read-only replicas fed by an offline job that drops
+    /// finished tables into the data directory and then lists them in the
+    /// manifest. Tables the tree already has keep their filters and pins;
+    /// new ones are checked and their filters loaded as on open (a damaged
+    /// one is quarantined, or fails the refresh with `paranoid_checks`).
+    ///
+    /// Everything is prepared before anything changes, so a failed refresh
+    /// leaves the tree as it was, and the new table list is installed in
+    /// one step: behind `SharedLSMTree`, readers see either the old tables
+    /// or the new ones.
+    pub fn refresh(&mut self) -> std::io::Result<RefreshReport> {
+        let data_dir = self.config.data_dir.clone();
+        if let Some(format) = DirectoryFormat::load(&data_dir)? {
+            format.check(&data_dir)?;
+        }
+        let Some(mut manifest) = Manifest::load(&data_dir)? else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No manifest in {} to refresh from", data_dir.display()),
+            ));
+        };
+        Self::check_wal_dir(&self.config, &manifest)?;
+        Self::check_cold_dir(&self.config, &manifest)?;
+        manifest
+            .sstables
+            .retain(|t| Self::table_path(&self.config, t).exists());
+        Self::assign_missing_generations(&mut manifest);
+        manifest
+            .sstables
+            .sort_by_key(|t| std::cmp::Reverse(t.generation));
+
+        let mut report = RefreshReport::default();
+        let mut tables = Vec::with_capacity(manifest.sstables.len());
+        let mut sources = Vec::with_capacity(manifest.sstables.len());
+        for entry in &manifest.sstables {
+            let path = Self::table_path(&self.config, entry);
+            let known = self.sstables.iter().position(|t| {
+                (&t.path, t.generation, t.max_sequence)
+                    == (&path, entry.generation, entry.max_sequence)
+            });
+            if let Some(index) = known {
+                tables.push(self.sstables[index].clone());
+                sources.push(SlotSource::Existing(index));
+                continue;
+            }
+            let info = SSTableInfo {
+                write_times: Self::read_write_times(&path),
+                path,
+                generation: entry.generation,
+                max_sequence: entry.max_sequence,
+                stats: entry.stats,
+                compacted: entry.compacted,
+                reads: Arc::default(),
+            };
+            if let Some(damaged) = Self::check_sstable(&self.config, self.tree_id, &info.path)? {
+                report.quarantined.push(damaged);
+                continue;
+            }
+            sources.push(SlotSource::New(
+                Self::load_filter(&self.config, &info.path)?.into_filter(),
+            ));
+            report.added.push(SSTableId(entry.number));
+            tables.push(info);
+        }
+        report.removed = self
+            .sstables
+            .iter()
+            .filter(|t| !tables.contains(t))
+            .filter_map(|t| Self::sstable_number(&t.path).map(SSTableId))
+            .collect();
+
+        // Nothing has changed so far; install the new layout in one go
+        let newest_generation = tables.iter().map(|t| t.generation).max().unwrap_or(0);
+        let newest_sequence = tables.iter().map(|t| t.max_sequence).max().unwrap_or(0);
+        self.ordering_anomalies = Self::find_ordering_anomalies(&tables);
+        self.sstables = tables;
+        self.bloom_filters.relayout(sources);
+        self.repin_small_tables();
+        self.next_generation = self.next_generation.max(newest_generation + 1);
+        self.sstable_counter = self.sstable_counter.max(manifest.next_file_number);
+        // New writes must stay newer than anything in the added tables
+        self.last_sequence = self.last_sequence.max(newest_sequence);
+        self.quarantined.extend(report.quarantined.iter().cloned());
+        Ok(report)
+    }
+
+    /// Makes a flushed table visible to readers and empties the memtable
+    ///
+    /// This is the only step of a flush that readers can observe, and it
+    /// can't fail halfway: the table and its filter are registered and the
+    /// memtable is cleared together, so a key is never missing from both.
+    /// Behind a lock (see `SharedLSMTree`) it happens entirely while the
+    /// writer holds it; the WAL is cleared only afterwards.
+    fn install_flushed_table(&mut self, table: SSTableInfo, bloom_filter: Option<BloomFilter>) {
+        self.sstables.insert(0, table);
+        self.next_generation += 1;
+        self.bloom_filters.insert_front(bloom_filter);
+        // A fresh memtable rather than clearing the one read states may share
+        self.memtable = Arc::new(Memtable::with_backend(self.memtable.backend()));
+        self.memtable_write_times.clear();
+        self.repin_small_tables();
+    }
+
+    /// Chooses the small SSTables held in memory for the current tables
+    fn repin_small_tables(&mut self) {
+        let paths = self.paths_newest_first();
+        self.pinned_tables.refill(&paths);
+    }
+
+    /// Looks up a key in one SSTable
+    ///
+    /// Returns `Some(Some(value))` if found, `Some(None)` if the key has a
+    /// tombstone in this table, and `None` if the table doesn't mention it.
+    fn read_from_sstable(
+        &self,
+        table: &SSTableInfo,
+        key: &[u8],
+    ) -> std::io::Result<Option<Option<Vec<u8>>>> {
+        if let Some(pinned) = self.pinned_tables.get(&table.path) {
+            let found = pinned.get(key);
+            table.reads.record_probe(found.is_some(), 0);
+            return Ok(found);
+        }
+        let mut reader = self.open_sstable(&table.path)?;
+        let found = reader.get(key);
+        // Up to the record the lookup stopped at, header included
+        let bytes = reader.position().unwrap_or(0);
+        IoRateTrackers::record(
+            &self.io_rates.sstable_read,
+            self.config.clock.now_millis(),
+            bytes,
+        );
+        table.reads.record_probe(found.is_some(), bytes);
+        Ok(found)
+    }
+
+    /// Adds the bytes of a newly written SSTable to the write counters
+    fn count_sstable_written(&mut self, bytes: u64) {
+        self.sstable_bytes_written += bytes;
+        IoRateTrackers::record(
+            &self.io_rates.sstable_write,
+            self.config.clock.now_millis(),
+            bytes,
+        );
+    }
+
+    /// Bytes per second appended to the WAL, written to SSTables and read
+    /// from them, over the last `metrics::IO_RATE_WINDOW`
+    ///
+    /// Reads count what point lookups through the tree read from SSTables
+    /// (pinned tables aren't read from disk) and the whole input of each
+    /// compaction, counted as it starts; scans and read states aren't
+    /// tracked. Times come from `LSMConfig::clock`.
+    pub fn io_rates(&self) -> IoRates {
+        self.io_rates.rates(self.config.clock.now_millis())
+    }
+
+    /// Returns number of entries in memtable
+    pub fn len(&self) -> usize {
+        self.memtable.len()
+    }
+
+    /// Returns true if the tree holds no live keys
+    ///
+    /// Tombstones don't count: a tree whose keys were all deleted is empty,
+    /// even while the tombstones still take up disk space (see `purge`).
+    /// A live value in the memtable answers right away; otherwise the
+    /// layers are merged, without reading values, up to the first live key.
+    pub fn is_empty(&self) -> bool {
+        if self.memtable.iter().any(|(_, value)| value.is_some()) {
+            return false;
+        }
+        !self.any_in_range::<&[u8], _>(..)
+    }
+
+    /// Returns number of SSTables on disk
+    pub fn sstable_count(&self) -> usize {
+        self.sstables.len()
+    }
+
+    /// Returns the WAL's size and activity counters
+    pub fn wal_stats(&self) -> WALStats {
+        match &self.wal {
+            Some(wal) => wal.stats(),
+            None => WALStats {
+                file_size: std::fs::metadata(self.config.resolved_wal_dir().join("wal.log"))
+                    .map_or(0, |m| m.len()),
+                ..WALStats::default()
+            },
+        }
+    }
+
+    /// What replaying the WAL would cost if the process crashed now: the
+    /// log's size and entries, and a projected replay time (see
+    /// `recovery`)
+    pub fn recovery_estimate(&self) -> RecoveryEstimate {
+        let stats = self.wal_stats();
+        RecoveryEstimate::new(
+            stats.file_size,
+            self.wal_entries_at_open + stats.entries_since_clear,
+            self.replay_rate,
+        )
+    }
+
+    /// Calls `EventListener::on_recovery_estimate_high` when the estimate
+    /// crosses `LSMConfig::recovery_warn_threshold`
+    fn check_recovery_estimate(&mut self) {
+        let Some(threshold) = self.config.recovery_warn_threshold else {
+            return;
+        };
+        let estimate = self.recovery_estimate();
+        let high = estimate.replay_time > threshold;
+        if high && !self.recovery_warned {
+            self.config
+                .event_listener
+                .on_recovery_estimate_high(&events::RecoveryEstimateEvent {
+                    estimate,
+                    threshold,
+                });
+        }
+        self.recovery_warned = high;
+    }
+
+    /// Whether repeated WAL failures have poisoned the tree
+    ///
+    /// A WAL append or sync that fails is cut back out of the log, so the
+    /// write that failed left no trace and may simply be retried. After
+    /// `WAL_FAILURES_BEFORE_POISON` failures in a row, or one the log
+    /// couldn't be cut back from, the tree refuses writes with a
+    /// `Poisoned` error until `clear_poison` is called. Reads, flushes and
+    /// compactions go on: the memtable only holds writes that were logged
+    /// whole.
+    ///
+    /// A tree whose directory was deleted or replaced while it was open is
+    /// poisoned for good: writes, `get_opt`, flushes and purges all fail
+    /// with `TreeDeleted` (see `stale`), and `clear_poison` can't help.
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.is_some() || self.deleted.is_some()
+    }
+
+    /// Lets a poisoned tree take writes again, once whatever made the WAL
+    /// fail has been dealt with
+    ///
+    /// If the log still has to be cut back from the last failure, that is
+    /// tried first; should it fail again, the tree stays poisoned and the
+    /// error is returned.
+    pub fn clear_poison(&mut self) -> std::io::Result<()> {
+        self.check_not_deleted()?;
+        let Some(poison) = &self.poison else {
+            return Ok(());
+        };
+        if let Some(mark) = poison.rollback {
+            self.writable_wal()?.rollback(mark)?;
+        }
+        self.poison = None;
+        self.wal_failures = 0;
+        Ok(())
+    }
+
+    /// Fails with `TreeDeleted` once the tree's directory is known to be
+    /// gone
+    fn check_not_deleted(&self) -> std::io::Result<()> {
+        match &self.deleted {
+            Some(deleted) => Err(deleted.clone().into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the directory's FORMAT file no longer names this tree: the
+    /// directory was deleted, or replaced by another tree
+    ///
+    /// Only asked after an I/O error, so the happy path never reads it. A
+    /// FORMAT file that can't be read says nothing either way.
+    fn marker_gone(&self) -> bool {
+        match DirectoryFormat::load(&self.config.data_dir) {
+            Ok(Some(format)) => format.tree_id != self.tree_id,
+            // Without an id (a read-only tree of an old directory) only a
+            // missing directory tells
+            Ok(None) => self.tree_id.is_some() || !self.config.data_dir.is_dir(),
+            Err(_) => false,
+        }
+    }
+
+    /// Turns the I/O error an operation failed with into `TreeDeleted`,
+    /// and poisons the tree, if the directory has gone from under it
+    ///
+    /// Otherwise the error is returned as it is.
+    fn stale(&mut self, error: std::io::Error) -> std::io::Error {
+        if let Some(deleted) = &self.deleted {
+            return deleted.clone().into();
+        }
+        if !self.marker_gone() {
+            return error;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::error!(
+            data_dir = %self.config.data_dir.display(),
+            error = %error,
+            "tree directory deleted or replaced while open; every call fails from now on"
+        );
+        let deleted = TreeDeleted {
+            data_dir: self.config.data_dir.clone(),
+            cause: error.to_string(),
+        };
+        self.deleted = Some(deleted.clone());
+        deleted.into()
+    }
+
+    /// Returns when the most recent write arrived, by `LSMConfig::clock`
+    ///
+    /// `None` until the first write since the tree was opened, unless the
+    /// WAL replayed writes into the memtable (those count as arriving at
+    /// open).
+    pub fn last_write_time(&self) -> Option<SystemTime> {
+        self.last_write
+    }
+
+    /// Returns the sequence number of the most recent write
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    /// Returns current memtable size in bytes
+    pub fn memtable_size(&self) -> usize {
+        self.memtable.size()
+    }
+
+    /// Returns memtable size threshold
+    pub fn memtable_threshold(&self) -> usize {
+        self.config.memtable_size_threshold
+    }
+
+    /// Changes a setting of the open tree
+    ///
+    /// The new value applies to future operations only (see `TreeOption`);
+    /// an invalid one is rejected with `InvalidInput` and nothing changes.
+    /// Every change is reported to `EventListener::on_option_changed`.
+    pub fn set_option(&mut self, option: TreeOption) -> std::io::Result<()> {
+        option
+            .validate()
+            .map_err(|msg| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg))?;
+        let previous = option.apply(&mut self.config);
+        self.config
+            .event_listener
+            .on_option_changed(&OptionChangeEvent {
+                previous,
+                current: option,
+            });
+        Ok(())
+    }
+
+    /// Returns the configuration in effect, including changes made with
+    /// `set_option`
+    pub fn current_options(&self) -> &LSMConfig {
+        &self.config
+    }
+
+    /// Returns data directory path
+    pub fn data_dir(&self) -> &PathBuf {
+        &self.config.data_dir
+    }
+
+    /// Returns Bloom filter statistics for the filters currently in memory
+    pub fn bloom_filter_stats(&self) -> BloomFilterSummary {
+        let (sstable_ids, individual_stats): (Vec<usize>, Vec<BloomFilterStats>) = self
+            .bloom_filters
+            .resident_filters()
+            .filter_map(|(i, bf)| Some((Self::sstable_number(&self.sstables[i].path)?, bf.stats())))
+            .unzip();
+
+        let total_size_bytes: usize = individual_stats.iter().map(|s| s.size_bytes).sum();
+        let total_items: usize = individual_stats.iter().map(|s| s.num_items).sum();
+        let overlapping_items: usize = individual_stats.iter().map(|s| s.overlapping_items).sum();
+
+        BloomFilterSummary {
+            num_filters: individual_stats.len(),
+            total_size_bytes,
+            total_items,
+            distinct_estimate: total_items.saturating_sub(overlapping_items),
+            checks_negative: self.bloom_filter_negatives,
+            checks_positive: self.bloom_filter_positives,
+            individual_stats,
+            sstable_ids,
+        }
+    }
+
+    /// Returns number of reads skipped by Bloom filters
+    pub fn bloom_filter_skipped_reads(&self) -> usize {
+        self.bloom_filter_negatives
+    }
+
+    /// Reports the small SSTables held in memory (see
+    /// `LSMConfig::pin_small_sstables_bytes`) and how many lookups they
+    /// answered
+    pub fn pinned_sstable_stats(&self) -> PinnedTableStats {
+        self.pinned_tables.stats()
+    }
+
+    /// Moving average of SSTables probed per `get` (see
+    /// `LSMConfig::read_amp_warn_threshold`)
+    ///
+    /// Gets answered by the memtable count as zero. `get_immut` and range
+    /// scans don't update it.
+    pub fn read_amplification(&self) -> f64 {
+        self.read_amp.average()
+    }
+
+    /// Whether reads have become slow enough that compacting the SSTables
+    /// would help
+    ///
+    /// True while `read_amplification` is above
+    /// `LSMConfig::read_amp_warn_threshold`, while a table's estimated
+    /// garbage reaches `garbage::HIGH_GARBAGE_RATIO` (see
+    /// `compaction_candidates`), and, with `LSMConfig::ttl`, while a table
+    /// is mostly expired (see `expired_estimate`). Meant to be polled by
+    /// whatever schedules maintenance.
+    pub fn needs_compaction(&self) -> bool {
+        self.read_amp.is_high()
+            || self.high_garbage_tables() > 0
+            || !self.expired_estimate().mostly_expired.is_empty()
+    }
+
+    /// The live SSTables ranked for compaction, highest estimated garbage
+    /// ratio first (see `garbage`)
+    ///
+    /// Tables with the same ratio come in order of the lookups that
+    /// searched them (`SSTableInfo::read_stats`), most first, then oldest
+    /// first. Compaction merges the tables most worth rewriting when it
+    /// starts from the front.
+    pub fn compaction_candidates(&self) -> Vec<CompactionCandidate> {
+        let mut candidates: Vec<CompactionCandidate> = self
+            .read_order()
+            .into_iter()
+            .rev()
+            .filter_map(|i| {
+                let table = &self.sstables[i];
+                Some(CompactionCandidate {
+                    sstable: SSTableId(Self::sstable_number(&table.path)?),
+                    garbage_ratio: table.stats.garbage_ratio(),
+                    garbage: table.stats.garbage(),
+                    probes: table.reads.stats().probes,
+                })
+            })
+            .collect();
+        candidates.sort_by(|a, b| {
+            b.garbage_ratio
+                .total_cmp(&a.garbage_ratio)
+                .then(b.probes.cmp(&a.probes))
+        });
+        candidates
+    }
+
+    /// Number of tables at or past `garbage::HIGH_GARBAGE_RATIO`
+    fn high_garbage_tables(&self) -> usize {
+        self.sstables
+            .iter()
+            .filter(|t| t.stats.garbage_ratio() >= garbage::HIGH_GARBAGE_RATIO)
+            .count()
+    }
+
+    /// Resets the read statistics: the Bloom filter counters of
+    /// `bloom_filter_stats` and every table's `SSTableInfo::read_stats`
+    pub fn reset_read_stats(&mut self) {
+        self.bloom_filter_negatives = 0;
+        self.bloom_filter_positives = 0;
+        for table in &self.sstables {
+            table.reads.reset();
+        }
+    }
+
+    /// Resets Bloom filter statistics
+    #[deprecated(note = "use `reset_read_stats`, which also resets the per-table counters")]
+    pub fn reset_bloom_filter_stats(&mut self) {
+        self.reset_read_stats();
+    }
+
+    /// Returns latency percentiles of one kind of operation since the tree
+    /// was opened (or since `reset_latency_stats`)
+    pub fn latency_percentiles(&self, op: LatencyOp) -> LatencyPercentiles {
+        self.latency.histogram(op).percentiles()
+    }
+
+    /// Clears all latency histograms
+    pub fn reset_latency_stats(&self) {
+        self.latency.reset();
+    }
+
+    /// Returns what the tree has seen of its workload since it was opened:
+    /// key and value sizes, sampled value bytes, reads and writes
+    pub fn workload_stats(&self) -> WorkloadStats {
+        let (reads, absent_reads) = self.read_counts.get();
+        WorkloadStats {
+            reads,
+            absent_reads,
+            ..self.workload.clone()
+        }
+    }
+
+    /// Gathers what `advisor` bases its suggestions on
+    ///
+    /// Replace `workload` to get advice for other statistics, such as
+    /// sizes sampled from a scan of existing data.
+    pub fn advisor_input(&self) -> AdvisorInput {
+        AdvisorInput {
+            workload: self.workload_stats(),
+            memtable_size_threshold: self.config.memtable_size_threshold,
+            bloom_filter_fpp: self.config.bloom_filter_fpp,
+            compression_enabled: self.config.compression != Compression::None,
+            sstable_count: self.sstables.len(),
+            read_amplification: self.read_amp.average(),
+            read_amp_warn_threshold: self.config.read_amp_warn_threshold,
+            mostly_expired_tables: self.expired_estimate().mostly_expired.len(),
+            high_garbage_tables: self.high_garbage_tables(),
+        }
+    }
+
+    /// Suggests a memtable threshold, Bloom filter false positive rate and
+    /// whether to compress values, and says whether compaction is overdue,
+    /// each with the numbers behind it (see `advisor::advise`)
+    pub fn advisor(&self) -> Advice {
+        advisor::advise(&self.advisor_input())
+    }
+
+    /// Counts live keys and estimates bytes per key prefix, splitting keys
+    /// at `delimiter` and keeping `depth` components (see `prefix_stats`)
+    ///
+    /// Scans the whole tree, keys only.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let report = lsm.prefix_stats(b':', 1); // user:, order:, ...
+    /// for p in report.largest() { /* ... */ }
+    /// ```
+    pub fn prefix_stats(&self, delimiter: u8, depth: usize) -> PrefixReport {
+        prefix_stats::collect(
+            self.keys::<&[u8], _>(..),
+            delimiter,
+            depth,
+            1.0,
+            self.live_bytes_estimate(),
+        )
+    }
+
+    /// Like `prefix_stats`, but only counts a `sample_rate` share of the
+    /// keys (evenly spread over the key order) and scales the counts up
+    ///
+    /// Fails with `InvalidInput` unless `sample_rate` is in (0, 1].
+    pub fn prefix_stats_sampled(
+        &self,
+        delimiter: u8,
+        depth: usize,
+        sample_rate: f64,
+    ) -> std::io::Result<PrefixReport> {
+        if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("sample rate must be in (0, 1], got {}", sample_rate),
+            ));
+        }
+        Ok(prefix_stats::collect(
+            self.keys::<&[u8], _>(..),
+            delimiter,
+            depth,
+            sample_rate,
+            self.live_bytes_estimate(),
+        ))
+    }
+
+    /// Estimated size of the live data, in the SSTables and the memtable
+    fn live_bytes_estimate(&self) -> u64 {
+        self.amplification_stats().live_bytes_estimate + self.memtable.size() as u64
+    }
+
+    /// Returns bytes written by users and by the tree, with write and
+    /// space amplification (see `AmplificationStats`)
+    pub fn amplification_stats(&self) -> AmplificationStats {
+        let disk = self.sstable_bytes_on_disk();
+        let bloom = self.bloom_filter_stats();
+        // Tables without a resident filter are taken to be all live
+        let live = if bloom.total_items == 0 {
+            disk
+        } else {
+            (disk as f64 * bloom.distinct_estimate as f64 / bloom.total_items as f64) as u64
+        };
+        AmplificationStats {
+            user_bytes_written: self.user_bytes_written,
+            sstable_bytes_written: self.sstable_bytes_written,
+            sstable_bytes_on_disk: disk,
+            live_bytes_estimate: live,
+        }
+    }
+
+    /// Total size of the live SSTable files
+    fn sstable_bytes_on_disk(&self) -> u64 {
+        self.sstables
+            .iter()
+            .filter_map(|t| std::fs::metadata(&t.path).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    /// Renders the tree's metrics in the Prometheus text exposition format
+    pub fn prometheus_metrics(&self) -> String {
+        let mut out = String::new();
+        let bloom = self.bloom_filter_stats();
+        let pinned = self.pinned_sstable_stats();
+        let gauges = [
+            (
+                "lsm_sstables",
+                "Number of live SSTables",
+                self.sstable_count() as u64,
+            ),
+            (
+                "lsm_memtable_entries",
+                "Entries in the memtable",
+                self.len() as u64,
+            ),
+            (
+                "lsm_memtable_bytes",
+                "Approximate memtable size",
+                self.memtable_size() as u64,
+            ),
+            (
+                "lsm_wal_bytes",
+                "Size of the write-ahead log",
+                self.wal_stats().file_size,
+            ),
+            (
+                "lsm_bloom_filter_bytes",
+                "Memory used by resident Bloom filters",
+                bloom.total_size_bytes as u64,
+            ),
+            (
+                "lsm_pinned_sstables",
+                "Small SSTables held in memory",
+                pinned.tables as u64,
+            ),
+            (
+                "lsm_expired_writes",
+                "Writes in SSTables older than the TTL",
+                self.expired_estimate().expired_writes,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            metrics::write_metric(&mut out, name, "gauge", help, value);
+        }
+        metrics::write_metric(
+            &mut out,
+            "lsm_bloom_filter_skipped_reads_total",
+            "counter",
+            "SSTable reads avoided by Bloom filters",
+            bloom.checks_negative as u64,
+        );
+        metrics::write_metric(
+            &mut out,
+            "lsm_pinned_sstable_hits_total",
+            "counter",
+            "Lookups answered from SSTables held in memory",
+            pinned.hits,
+        );
+        metrics::write_metric(
+            &mut out,
+            "lsm_skipped_identical_writes_total",
+            "counter",
+            "Puts skipped because the memtable held the same value",
+            self.skipped_writes,
+        );
+        metrics::write_metric(
+            &mut out,
+            "lsm_read_budget_exceeded_total",
+            "counter",
+            "Lookups that gave up at their SSTable probe budget",
+            self.read_budget_exceeded,
+        );
+        metrics::write_metric(
+            &mut out,
+            "lsm_last_sequence",
+            "counter",
+            "Sequence number of the most recent write",
+            self.last_sequence,
+        );
+        let amplification = self.amplification_stats();
+        metrics::write_metric(
+            &mut out,
+            "lsm_user_bytes_written_total",
+            "counter",
+            "Key and value bytes written by users",
+            amplification.user_bytes_written,
+        );
+        metrics::write_metric(
+            &mut out,
+            "lsm_sstable_bytes_written_total",
+            "counter",
+            "SSTable bytes written by flushes and compactions",
+            amplification.sstable_bytes_written,
+        );
+        metrics::write_metric(
+            &mut out,
+            "lsm_write_amplification",
+            "gauge",
+            "SSTable bytes written per user byte written",
+            amplification.write_amplification(),
+        );
+        metrics::write_metric(
+            &mut out,
+            "lsm_space_amplification",
+            "gauge",
+            "SSTable bytes on disk per estimated live byte",
+            amplification.space_amplification(),
+        );
+        if let Ok(usage) = self.disk_usage() {
+            usage.write_prometheus(&mut out);
+        }
+        self.latency.write_prometheus(&mut out);
+        out
+    }
+
+    /// Bytes on disk by kind of file: SSTables, Bloom filters, WAL,
+    /// metadata, and files the tree doesn't use (see `disk_usage`)
+    ///
+    /// Stats every file in the data directory, and the cold tables.
+    pub fn disk_usage(&self) -> std::io::Result<DiskUsage> {
+        disk_usage::measure(
+            &self.config.data_dir,
+            &self.config.resolved_wal_dir().join("wal.log"),
+            &self.paths_newest_first(),
+        )
+    }
+
+    /// Returns an overview of the tree: memtable fill, bytes on disk, Bloom
+    /// filters, last flush and compaction, and settings (see `summary`)
+    ///
+    /// Stats the SSTable and WAL files; nothing is read.
+    pub fn tree_summary(&self) -> TreeSummary {
+        let config = &self.config;
+        TreeSummary {
+            data_dir: config.data_dir.clone(),
+            wal_path: config.resolved_wal_dir().join("wal.log"),
+            memtable_entries: self.memtable.len(),
+            memtable_bytes: self.memtable.size(),
+            memtable_threshold: config.memtable_size_threshold,
+            sstable_count: self.sstables.len(),
+            sstable_bytes: self.sstable_bytes_on_disk(),
+            wal_bytes: self.wal_stats().file_size,
+            bloom: self.bloom_filter_stats(),
+            now: config.clock.now(),
+            last_flush: self.last_flush,
+            last_compaction: self.last_compaction,
+            memtable_backend: config.memtable_backend,
+            filter_policy: config.filter_policy,
+            bloom_filter_fpp: config.bloom_filter_fpp,
+            compression: config.compression,
+            sync_writes: config.sync_writes,
+            sync_on_flush: config.sync_on_flush,
+            flush_after_idle: config.flush_after_idle,
+            ttl: config.ttl,
+            read_amp_warn_threshold: config.read_amp_warn_threshold,
+            read_only: config.read_only,
+        }
+    }
+
+    /// Returns all keys in memtable (for display purposes)
+    pub fn memtable_keys(&self) -> Vec<Vec<u8>> {
+        self.memtable.keys().collect()
+    }
+
+    /// Returns all live key-value pairs in memtable (tombstones are skipped)
+    ///
+    /// Copies every entry; to show a part of a large memtable, use
+    /// `memtable_page`.
+    pub fn memtable_entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.memtable
+            .iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .collect()
+    }
+
+    /// Returns up to `limit` live key-value pairs of the memtable, skipping
+    /// the first `offset` (tombstones are skipped and don't count)
+    ///
+    /// Only the entries returned are copied.
+    pub fn memtable_page(&self, offset: usize, limit: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.memtable.live_page(offset, limit)
+    }
+
+    /// Number of live keys in the memtable (`len` counts tombstones too)
+    pub fn memtable_live_len(&self) -> usize {
+        self.memtable.live_len()
+    }
+
+    /// Returns SSTable paths, newest first
+    #[deprecated(note = "use `sstables()`, whose handles carry stable ids")]
+    pub fn sstable_paths(&self) -> Vec<PathBuf> {
+        self.paths_newest_first()
+    }
+
+    /// Iterates over the live SSTables, newest generation first
+    ///
+    /// The order is only the read order at this moment; to refer to a
+    /// table later, keep its `SSTableHandle::id` and look it up again with
+    /// `sstable`.
+    pub fn sstables(&self) -> impl Iterator<Item = SSTableHandle<'_>> {
+        self.read_order().into_iter().filter_map(|i| self.handle(i))
+    }
+
+    /// Finds a live SSTable by id, or `None` if it is no longer part of the
+    /// tree (compacted, purged or quarantined)
+    pub fn sstable(&self, id: SSTableId) -> Option<SSTableHandle<'_>> {
+        (0..self.sstables.len())
+            .filter_map(|i| self.handle(i))
+            .find(|table| table.id() == id)
+    }
+
+    fn handle(&self, index: usize) -> Option<SSTableHandle<'_>> {
+        let number = Self::sstable_number(&self.sstables[index].path)?;
+        Some(SSTableHandle::new(self, index, SSTableId(number)))
+    }
+
+    /// Returns the damaged SSTables that were quarantined when the tree was
+    /// opened (always empty with `paranoid_checks`)
+    pub fn quarantined_sstables(&self) -> &[QuarantinedSSTable] {
+        &self.quarantined
+    }
+
+    /// Returns pairs of unsequenced SSTables whose modification times
+    /// contradict the order the tree reads them in
+    ///
+    /// Directories used with old builds of the demo, which restarted file
+    /// numbering after every restart, can end up serving stale values as
+    /// the newest ones. A non-empty list means reads may be wrong until
+    /// `repair_ordering` is run. Copying a directory without preserving
+    /// modification times can also trigger it.
+    pub fn ordering_anomalies(&self) -> &[OrderingAnomaly] {
+        &self.ordering_anomalies
+    }
+
+    /// Reorders unsequenced SSTables by modification time and renumbers
+    /// every table to match
+    ///
+    /// Does nothing and returns an empty list when `ordering_anomalies` is
+    /// empty. Otherwise the unsequenced tables swap generations so the most
+    /// recently modified one is newest (sequenced tables keep theirs), and
+    /// every table gets a fresh file number in generation order, so the
+    /// file names tell recency again. Returns what changed for each table,
+    /// newest first.
+    ///
+    /// New names are hard links made before the manifest is written and
+    /// old names are removed after, so a crash midway leaves the tree as
+    /// it was before the call. Data in a table that was overwritten by the
+    /// old numbering bug is gone; this only restores which of the surviving
+    /// versions wins.
+    pub fn repair_ordering(&mut self) -> std::io::Result<Vec<RenumberedSSTable>> {
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+        if self.ordering_anomalies.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified());
+        let mut unsequenced = Vec::new();
+        for (i, table) in self.sstables.iter().enumerate() {
+            if table.max_sequence == 0 {
+                unsequenced.push((modified(&table.path)?, table.generation, i));
+            }
+        }
+        // The generations they held, handed out again by modification time
+        let mut generations: Vec<u64> = unsequenced.iter().map(|&(_, g, _)| g).collect();
+        generations.sort_unstable();
+        unsequenced.sort();
+
+        let mut new_sstables = self.sstables.clone();
+        for (&(_, _, i), generation) in unsequenced.iter().zip(generations) {
+            new_sstables[i].generation = generation;
+        }
+
+        let mut order: Vec<usize> = (0..new_sstables.len()).collect();
+        order.sort_by_key(|&i| new_sstables[i].generation);
+        let mut changes = Vec::with_capacity(order.len());
+        for i in order {
+            let old_path = self.sstables[i].path.clone();
+            // Next to the old name, in the cold directory for a cold table
+            let dir = old_path.parent().unwrap_or(&self.config.data_dir);
+            let new_path = Self::sstable_path(dir, self.sstable_counter);
+            self.sstable_counter += 1;
+
+            std::fs::hard_link(&old_path, &new_path)?;
+            let old_filter = old_path.with_extension("bloom");
+            if old_filter.exists() {
+                std::fs::hard_link(&old_filter, new_path.with_extension("bloom"))?;
+            }
+
+            new_sstables[i].path = new_path.clone();
+            changes.push(RenumberedSSTable {
+                old_path,
+                new_path,
+                old_generation: self.sstables[i].generation,
+                new_generation: new_sstables[i].generation,
+            });
+        }
+
+        let old_sstables = std::mem::replace(&mut self.sstables, new_sstables);
+        self.repin_small_tables();
+        self.sync_cold_dir()?;
+        self.write_manifest()?;
+        self.config.storage.sync_dir(&self.config.data_dir)?;
+
+        for old in &old_sstables {
+            self.table_refs.remove_when_unused(&old.path)?;
+        }
+
+        self.ordering_anomalies = Self::find_ordering_anomalies(&self.sstable_infos());
+        changes.reverse();
+        Ok(changes)
+    }
+
+    /// Returns the SSTables that have no Bloom filter and are probed on
+    /// every lookup, newest first
+    pub fn filterless_sstables(&self) -> Vec<PathBuf> {
+        self.read_order()
+            .into_iter()
+            .filter(|&i| self.bloom_filters.is_missing(i))
+            .map(|i| self.sstables[i].path.clone())
+            .collect()
+    }
+
+    /// Loads an SSTable's Bloom filter and keeps it in memory
+    ///
+    /// `index` is the table's position in `sstable_paths`. A pinned filter
+    /// still counts towards `bloom_filter_memory_budget` but is never
+    /// evicted to meet it. Fails with `NotFound` for a table without a
+    /// usable filter.
+    pub fn pin_filter(&mut self, index: usize) -> std::io::Result<()> {
+        self.sstable_at(index)?;
+        self.bloom_filters.pin(index, &self.sstables[index].path)
+    }
+
+    /// Lets a pinned Bloom filter be evicted under the memory budget again
+    pub fn unpin_filter(&mut self, index: usize) -> std::io::Result<()> {
+        self.sstable_at(index)?;
+        self.bloom_filters.unpin(index);
+        Ok(())
+    }
+
+    /// Drops an SSTable's Bloom filter from memory, unpinning it
+    ///
+    /// The `.bloom` file is kept; the next lookup that reaches the table
+    /// loads the filter again.
+    pub fn evict_filter(&mut self, index: usize) -> std::io::Result<()> {
+        self.sstable_at(index)?;
+        self.bloom_filters.evict(index);
+        Ok(())
+    }
+
+    /// Reports where each SSTable's Bloom filter currently lives, in the
+    /// same order as `sstable_paths`
+    pub fn filter_residency(&self) -> Vec<(PathBuf, FilterResidency)> {
+        self.read_order()
+            .into_iter()
+            .map(|i| {
+                (
+                    self.sstables[i].path.clone(),
+                    self.bloom_filters.residency(i),
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the SSTable at `index`, or `InvalidInput` if there is none
+    fn sstable_at(&self, index: usize) -> std::io::Result<&SSTableInfo> {
+        self.sstables.get(index).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "No SSTable at index {} (the tree has {})",
+                    index,
+                    self.sstables.len()
+                ),
+            )
+        })
+    }
+
+    /// Id stamped into the tree's SSTables (`None` for a read-only tree
+    /// whose directory has none yet; see `tree_id`)
+    pub fn tree_id(&self) -> Option<TreeId> {
+        self.tree_id
+    }
+
+    /// Returns the live SSTables with their generations, newest first
+    ///
+    /// Each info's `read_stats` is a copy taken now, which later lookups
+    /// don't change.
+    pub fn sstable_infos(&self) -> Vec<SSTableInfo> {
+        self.read_order()
+            .into_iter()
+            .map(|i| SSTableInfo {
+                reads: Arc::new(self.sstables[i].reads.snapshot()),
+                ..self.sstables[i].clone()
+            })
+            .collect()
+    }
+
+    /// Reads all live entries from an SSTable (for display, tombstones are skipped)
+    pub fn read_sstable_entries(&self, index: usize) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+        handle::read_live_entries(&self.sstables.get(index)?.path)
+    }
+
+    /// Returns the number of SSTables still in an older on-disk format
+    pub fn legacy_sstable_count(&self) -> usize {
+        self.sstables
+            .iter()
+            .filter(|t| sstable::format_version(&t.path).is_ok_and(|v| v < SSTABLE_FORMAT_VERSION))
+            .count()
+    }
+
+    /// Rewrites every SSTable in an older format into the current format
+    ///
+    /// Each legacy table is copied record by record into a new file with a
+    /// fresh file number and a rebuilt Bloom filter, keeping its position in
+    /// the newest-to-oldest order. The manifest is swapped once, after all
+    /// new files are complete, and only then are the old files deleted, so
+    /// a crash midway leaves the tree as it was before the call.
+    ///
+    /// Returns the number of SSTables that were migrated.
+    pub fn migrate_format(&mut self) -> std::io::Result<usize> {
+        if self.config.read_only {
+            return Err(read_only_error());
+        }
+
+        let mut new_sstables = self.sstables.clone();
+        let mut new_filters = Vec::new();
+        let mut replaced = Vec::new();
+
+        for (i, new_table) in new_sstables.iter_mut().enumerate() {
+            let old_path = &self.sstables[i].path;
+            let mut reader = SSTableReader::open_buffered(old_path, self.config.read_buffer_bytes)?;
+            if reader.version() >= SSTABLE_FORMAT_VERSION {
+                continue;
+            }
+            let (old_items, old_overlapping) = self
+                .bloom_filters
+                .get_or_load(i, old_path)
+                .map_or((1, 0), |bf| (bf.len(), bf.overlapping_items()));
+
+            let dir = old_path.parent().unwrap_or(&self.config.data_dir);
+            let new_path = Self::sstable_path(dir, self.sstable_counter);
+            self.sstable_counter += 1;
+
+            let mut options = self.writer_options(old_items, true);
+            options.dictionary = self.dictionary_for(
+                SSTableReader::open_buffered(old_path, self.config.read_buffer_bytes)?
+                    .filter_map(|(_, _, value)| value),
+                old_items,
+            );
+            let mut writer = SSTableWriter::new(&new_path, options)?;
+            while let Some((key, seq, value)) = reader.try_next()? {
+                writer.add(&key, seq, value.as_deref())?;
+            }
+            let mut bloom_filter = writer.finish()?.bloom_filter;
+            // Keep the original modification time; for unsequenced tables
+            // it is what `repair_ordering` goes by
+            if let Ok(modified) = std::fs::metadata(old_path).and_then(|m| m.modified()) {
+                File::options()
+                    .write(true)
+                    .open(&new_path)?
+                    .set_modified(modified)?;
+            }
+
+            if let Some(bloom_filter) = bloom_filter.as_mut() {
+                bloom_filter.set_overlapping_items(old_overlapping);
+                Self::write_bloom_filter(
+                    &new_path,
+                    bloom_filter,
+                    Some(self.config.storage.as_ref()),
+                )?;
+            }
+
+            // The rewritten table keeps its generation, so it stays exactly
+            // as new as the table it replaces
+            *new_table = SSTableInfo {
+                path: new_path,
+                ..self.sstables[i].clone()
+            };
+            new_filters.push((i, bloom_filter));
+            replaced.push(old_path.clone());
+        }
+
+        if replaced.is_empty() {
+            return Ok(0);
+        }
+
+        self.sstables = new_sstables;
+        for (i, bloom_filter) in new_filters {
+            self.bloom_filters.replace(i, bloom_filter);
+        }
+        self.repin_small_tables();
+        self.sync_cold_dir()?;
+        self.write_manifest()?;
+        self.config.storage.sync_dir(&self.config.data_dir)?;
+
+        for old_path in &replaced {
+            self.table_refs.remove_when_unused(old_path)?;
+        }
+
+        Ok(replaced.len())
+    }
+}
+
+/// A live SSTable and its place in the tree's history
+#[derive(Debug, Clone, PartialEq)]
+pub struct SSTableInfo {
+    /// Path of the table file
+    pub path: PathBuf,
+
+    /// Generation of the table: when two tables hold the same key, the one
+    /// with the higher generation wins
+    pub generation: u64,
+
+    /// Highest write sequence number flushed into the table
+    pub max_sequence: u64,
+
+    /// Records, tombstones and estimated garbage in the table
+    pub stats: TableStats,
+
+    /// Whether a compaction wrote the table (rather than a flush or an
+    /// ingest)
+    pub compacted: bool,
+
+    /// When the table's records were written, from its header
+    pub(crate) write_times: WriteTimes,
+
+    /// Lookups the table took part in, shared by the tree's copies
+    pub(crate) reads: Arc<TableReads>,
+}
+
+impl SSTableInfo {
+    /// Earliest and latest write time of the table's records
+    ///
+    /// `None` for tables without write times, and for tables holding
+    /// writes whose time isn't known (recovered from the WAL). With
+    /// `LSMConfig::ttl`, the table's entries expire between the two times
+    /// plus the TTL.
+    pub fn write_time_range(&self) -> Option<(SystemTime, SystemTime)> {
+        self.write_times.span()
+    }
+
+    /// Point lookups (`get`, `get_opt`, `get_immut`) that consulted the
+    /// table's Bloom filter or searched the table, since it joined the
+    /// tree or the last `reset_read_stats`
+    ///
+    /// A table that takes most of the probes but rarely has the key is
+    /// a good one to compact away.
+    pub fn read_stats(&self) -> TableReadStats {
+        self.reads.stats()
+    }
+}
+
+/// A damaged SSTable that was moved out of the tree on open
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantinedSSTable {
+    /// Where the table used to live
+    pub original_path: PathBuf,
+
+    /// Where the file was moved (`<original>.corrupt`), or `None` if the
+    /// tree is read-only and the file was left in place
+    pub quarantine_path: Option<PathBuf>,
+
+    /// What the integrity check found
+    pub reason: Corruption,
+}
+
+/// Two unsequenced SSTables whose modification times contradict their order
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingAnomaly {
+    /// The table read first, as the newer one
+    pub newer: PathBuf,
+
+    /// The table read after it, although it was modified later
+    pub older: PathBuf,
+}
+
+/// How `repair_ordering` moved one SSTable
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenumberedSSTable {
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+    pub old_generation: u64,
+    pub new_generation: u64,
+}
+
+/// What a flush wrote (`LSMTree::close`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlushResult {
+    /// The SSTable written, if there was anything to flush
+    pub sstable: Option<SSTableId>,
+
+    /// Records written, including versions kept for pinned sequences
+    pub entries_written: usize,
+
+    /// Size of the SSTable written
+    pub bytes_written: u64,
+}
+
+/// What one compaction (`LSMTree::purge`), or one call of a time-boxed one
+/// (`LSMTree::compact_for`), read and wrote
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompactionResult {
+    /// Number of SSTables merged
+    pub input_tables: usize,
+
+    /// Total size of the merged SSTables
+    pub bytes_read: u64,
+
+    /// Size of the SSTable written (0 when nothing was live)
+    pub bytes_written: u64,
+
+    /// Live entries written
+    pub entries_written: usize,
+
+    /// Sidecar files (Bloom filters) of SSTables that no longer exist,
+    /// removed after the merge
+    pub orphans_removed: usize,
+
+    /// Whether the compaction is done; false when `compact_for` ran out of
+    /// time first
+    pub completed: bool,
+}
+
+/// Where a `compact_for` stopped (see `LSMTree::compact_for`)
+#[derive(Debug, Clone)]
+struct CompactionResume {
+    /// Input tables not removed yet, newest first, with their last keys
+    /// (`None` for an empty table)
+    inputs: Vec<(PathBuf, Option<Vec<u8>>)>,
+
+    /// Output tables written so far
+    outputs: Vec<PathBuf>,
+
+    /// Last key merged (`None` before the first step)
+    merged_to: Option<Vec<u8>>,
+}
+
+impl CompactionResume {
+    /// A compaction of `tables`, given newest first
+    fn start(tables: &[&SSTableInfo]) -> std::io::Result<Self> {
+        let mut inputs = Vec::with_capacity(tables.len());
+        for table in tables {
+            let summary = sstable::read_summary(&table.path)?;
+            inputs.push((table.path.clone(), summary.last_key));
+        }
+        Ok(Self {
+            inputs,
+            outputs: Vec::new(),
+            merged_to: None,
+        })
+    }
+
+    /// Whether every table the compaction works with is still in the tree
+    fn is_current(&self, tables: &[SSTableInfo]) -> bool {
+        let present = |path: &PathBuf| tables.iter().any(|t| &t.path == path);
+        self.inputs.iter().all(|(path, _)| present(path)) && self.outputs.iter().all(present)
+    }
+}
+
+/// What `LSMTree::refresh` changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RefreshReport {
+    /// Tables that joined the tree
+    pub added: Vec<SSTableId>,
+
+    /// Tables that left the tree
+    pub removed: Vec<SSTableId>,
+
+    /// New tables found damaged and left out (see `quarantined_sstables`)
+    pub quarantined: Vec<QuarantinedSSTable>,
+}
+
+impl RefreshReport {
+    /// Returns true if the table list didn't change
+    pub fn is_unchanged(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.quarantined.is_empty()
+    }
+}
+
+/// Where a table's Bloom filter came from when the table was loaded
+enum LoadedFilter {
+    /// Read from its `.bloom` file
+    Loaded(BloomFilter),
+
+    /// Rebuilt from the table, the file being missing or damaged
+    Rebuilt(BloomFilter),
+
+    /// Not available: the table is probed on every read
+    Missing,
+
+    /// `FilterPolicy::None`: no table has one
+    Disabled,
+}
+
+impl LoadedFilter {
+    fn into_filter(self) -> Option<BloomFilter> {
+        match self {
+            LoadedFilter::Loaded(filter) | LoadedFilter::Rebuilt(filter) => Some(filter),
+            LoadedFilter::Missing | LoadedFilter::Disabled => None,
+        }
+    }
+}
+
+/// Why a tree refuses writes (see `LSMTree::is_poisoned`)
+struct Poison {
+    error: Poisoned,
+
+    /// Where the WAL still has to be cut back to, if that failed too
+    rollback: Option<WALMark>,
+}
+
+/// Writes that are in the WAL but not yet in the memtable
+///
+/// Only `LSMTree::publish` takes one apart, so a write path that logged
+/// something can't drop it on the floor and return.
+#[must_use = "logged writes must be published before the write returns"]
+struct LoggedWrites {
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    seqs: Vec<u64>,
+}
+
+/// SSTables found when opening a tree, newest first
+struct LoadedSSTables {
+    tables: Vec<SSTableInfo>,
+    quarantined: Vec<QuarantinedSSTable>,
+    bloom_filters: Vec<Option<BloomFilter>>,
+    next_file_number: usize,
+}
+
+/// Error returned by operations that would modify a read-only tree
+fn read_only_error() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::PermissionDenied,
+        "LSM tree was opened read-only",
+    )
+}
+
+/// Copies `from` to `to` by way of a synced `<to>.tmp`, so `to` is either
+/// missing or complete
+fn copy_synced(storage: &dyn Storage, from: &Path, to: &Path) -> std::io::Result<()> {
+    let mut tmp = to.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::copy(from, &tmp)?;
+    storage.sync_file(&File::open(&tmp)?)?;
+    std::fs::rename(&tmp, to)
+}
+
+/// Hard-links `from` to `to`, or copies it where links aren't possible
+fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::hard_link(from, to).is_err() {
+        std::fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Prints `tree_summary` rather than the tree's contents
+impl std::fmt::Debug for LSMTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.tree_summary(), f)
+    }
+}
+
+impl Drop for LSMTree {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// One page of results from `LSMTree::scan`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanPage {
+    /// Live key-value pairs in ascending key order
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+
+    /// Continuation token for the next page (the last key returned), or
+    /// `None` when the scan reached the end of the tree
+    pub next_cursor: Option<Vec<u8>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, CompactionFilter, DedupPolicy, LsmError};
+    use crate::{bloom, config, flush_policy, storage, testing};
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_basic_put_get() {
+        let dir = PathBuf::from("./test_lib_basic");
+        let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+
+        lsm.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        assert_eq!(lsm.get(b"key1"), Some(b"value1".to_vec()));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_bloom_filter_integration() {
+        let dir = PathBuf::from("./test_lib_bloom");
+        let mut lsm = LSMTree::new(dir.clone(), 10).unwrap();
+
+        for i in 0..10 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            lsm.put(key.into_bytes(), value.into_bytes()).unwrap();
+        }
+
+        lsm.reset_bloom_filter_stats();
+
+        // Query non-existent keys
+        for i in 100..200 {
+            let key = format!("nonexistent{}", i);
+            let _ = lsm.get(key.as_bytes());
+        }
+
+        let stats = lsm.bloom_filter_stats();
+        assert!(stats.checks_negative > 0);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_bloom_filter_summary_names_tables_and_survives_json() {
+        let dir = PathBuf::from("./test_lib_bloom_summary");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 10).unwrap();
+        for i in 0..10 {
+            lsm.put(format!("key{i}").into_bytes(), b"value".to_vec())
+                .unwrap();
+        }
+        for i in 100..200 {
+            let _ = lsm.get(format!("nonexistent{i}").as_bytes());
+        }
+
+        // Every filter is labelled with its table, and survives a JSON trip
+        let stats = lsm.bloom_filter_stats();
+        let numbers: Vec<usize> = lsm.sstables().map(|t| t.id().0).collect();
+        assert!(numbers.len() > 1);
+        let mut ids = stats.sstable_ids.clone();
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        assert_eq!(ids, numbers);
+        assert_eq!(
+            BloomFilterSummary::from_json(&stats.to_json()).unwrap(),
+            stats
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_read_stats_are_counted_per_sstable() {
+        let dir = PathBuf::from("./test_lib_table_read_stats");
+        three_table_tree(&dir);
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        let table_of = |lsm: &LSMTree, number: usize| {
+            lsm.sstable_infos()
+                .into_iter()
+                .find(|t| LSMTree::sstable_number(&t.path) == Some(number))
+                .unwrap()
+                .read_stats()
+        };
+
+        // "b" is in sstable_1 only; the newer sstable_2 is checked first
+        for _ in 0..5 {
+            assert_eq!(lsm.get(b"b"), Some(b"value".to_vec()));
+        }
+        assert_eq!(lsm.get_immut(b"b"), Some(b"value".to_vec()));
+        let hit = table_of(&lsm, 1);
+        assert_eq!(hit.hits, 6);
+        assert_eq!(hit.probes, 6);
+        assert_eq!(hit.bloom_checks, 6);
+        assert_eq!(hit.bloom_negatives, 0);
+        assert!(hit.bytes_read > 0);
+        assert_eq!(table_of(&lsm, 2).hits, 0);
+        assert_eq!(table_of(&lsm, 2).bloom_checks, 6);
+        assert_eq!(table_of(&lsm, 0), TableReadStats::default());
+
+        // A copy from sstable_infos doesn't change; the tree's counters do
+        let before = lsm.sstable_infos();
+        lsm.get(b"b");
+        assert_eq!(before[1].read_stats().hits, 6);
+        assert_eq!(table_of(&lsm, 1).hits, 7);
+
+        lsm.reset_read_stats();
+        assert!(
+            lsm.sstable_infos()
+                .iter()
+                .all(|t| t.read_stats() == TableReadStats::default())
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_reset_read_stats_clears_bloom_and_table_counters() {
+        let dir = PathBuf::from("./test_lib_reset_read_stats");
+        three_table_tree(&dir);
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get(b"b"), Some(b"value".to_vec()));
+        for i in 0..20 {
+            let _ = lsm.get(format!("missing{i}").as_bytes());
+        }
+        let stats = lsm.bloom_filter_stats();
+        assert!(stats.checks_negative > 0);
+        assert!(stats.checks_positive > 0);
+
+        lsm.reset_read_stats();
+        let stats = lsm.bloom_filter_stats();
+        assert_eq!((stats.checks_negative, stats.checks_positive), (0, 0));
+        assert!(
+            lsm.sstable_infos()
+                .iter()
+                .all(|t| t.read_stats() == TableReadStats::default())
+        );
+
+        // Counting starts over from zero
+        assert_eq!(lsm.get(b"b"), Some(b"value".to_vec()));
+        assert!(lsm.sstable_infos().iter().any(|t| t.read_stats().hits == 1));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_delete_shadows_older_sstables() {
+        let dir = PathBuf::from("./test_lib_delete");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+
+        lsm.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.delete(b"key1".to_vec()).unwrap();
+        assert_eq!(lsm.get(b"key1"), None);
+
+        // The tombstone must keep shadowing the value after it is flushed too
+        lsm.flush().unwrap();
+        assert_eq!(lsm.get(b"key1"), None);
+        assert_eq!(lsm.get_immut(b"key1"), None);
+
+        drop(lsm);
+        let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+        assert_eq!(lsm.get(b"key1"), None);
+        drop(lsm);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_count_range_across_layers() {
+        let dir = PathBuf::from("./test_lib_count_range");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+
+        // SSTable-only keys
+        for i in 0..5 {
+            lsm.put(format!("a{}", i).into_bytes(), b"old".to_vec())
+                .unwrap();
+        }
+        lsm.flush().unwrap();
+
+        // Memtable-only keys, plus an overwrite of a flushed key
+        for i in 0..3 {
+            lsm.put(format!("b{}", i).into_bytes(), b"new".to_vec())
+                .unwrap();
+        }
+        lsm.put(b"a0".to_vec(), b"newer".to_vec()).unwrap();
+
+        assert_eq!(lsm.count_range("a".."b"), 5);
+        assert_eq!(lsm.count_range("b".."c"), 3);
+        assert_eq!(lsm.count_range("a0"..="b0"), 6);
+        assert_eq!(lsm.count_range::<&[u8], _>(..), 8);
+
+        // A key deleted in a newer layer must not be counted from an older one
+        lsm.delete(b"a1".to_vec()).unwrap();
+        lsm.delete(b"b1".to_vec()).unwrap();
+        assert_eq!(lsm.count_range("a".."b"), 4);
+        assert_eq!(lsm.count_range("b".."c"), 2);
+
+        lsm.flush().unwrap();
+        assert_eq!(lsm.count_range::<&[u8], _>(..), 6);
+        assert_eq!(lsm.count_range("z"..), 0);
+
+        drop(lsm);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_any_in_range() {
+        let dir = PathBuf::from("./test_lib_any_in_range");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+
+        assert!(!lsm.any_in_range::<&[u8], _>(..));
+
+        lsm.put(b"page:1".to_vec(), b"x".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(b"page:2".to_vec(), b"y".to_vec()).unwrap();
+
+        assert!(lsm.any_in_range("page:"..));
+        assert!(lsm.any_in_range("page:2"..));
+        assert!(!lsm.any_in_range("page:3"..));
+
+        // Only deleted keys remain in the range
+        lsm.delete(b"page:1".to_vec()).unwrap();
+        assert!(!lsm.any_in_range("page:0".."page:2"));
+        lsm.flush().unwrap();
+        lsm.delete(b"page:2".to_vec()).unwrap();
+        assert!(!lsm.any_in_range("page:"..));
+
+        // Inverted ranges are simply empty
+        assert!(!lsm.any_in_range("z".."a"));
+
+        drop(lsm);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_range_returns_newest_values_in_order() {
+        let dir = PathBuf::from("./test_lib_range");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+
+        lsm.put(b"c".to_vec(), b"1".to_vec()).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        lsm.put(b"c".to_vec(), b"2".to_vec()).unwrap();
+
+        let entries: Vec<_> = lsm.range::<&[u8], _>(..).collect();
+        assert_eq!(
+            entries,
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"2".to_vec()),
+                (b"c".to_vec(), b"2".to_vec()),
+            ]
+        );
+
+        drop(lsm);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_scan_paginates_without_gaps_or_duplicates() {
+        let dir = PathBuf::from("./test_lib_scan_pages");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 64 * 1024).unwrap();
+
+        let mut expected = Vec::new();
+        for i in 0..10_000 {
+            let key = format!("key:{:05}", i).into_bytes();
+            let value = format!("value{}", i).into_bytes();
+            lsm.put(key.clone(), value.clone()).unwrap();
+            expected.push((key, value));
+        }
+        assert!(lsm.sstable_count() > 1);
+
+        let mut seen = Vec::new();
+        let mut cursor: Option<Vec<u8>> = None;
+        loop {
+            let page = lsm.scan(cursor.as_deref(), 7);
+            assert!(page.entries.len() <= 7);
+            seen.extend(page.entries);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(seen, expected);
+
+        drop(lsm);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_scan_pages_start_at_the_cursor_block() {
+        let dir = PathBuf::from("./test_lib_scan_seek");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        let key = |i: u32| format!("key:{:04}", i).into_bytes();
+        for i in 0..300 {
+            lsm.put(key(i), vec![b'v'; 100]).unwrap();
+        }
+        lsm.flush().unwrap();
+        let path = lsm.sstable_paths().remove(0);
+        let (blocks, _) = SSTableReader::open(&path)
+            .unwrap()
+            .blocks_through(None)
+            .unwrap();
+        assert!(blocks.len() > 3);
+
+        // Damage the first record: a scan that reads from the start of the
+        // table stops there, one that seeks past the first block doesn't
+        let mut bytes = fs::read(&path).unwrap();
+        let first = blocks[0] as usize;
+        bytes[first..first + 4].copy_from_slice(&[0xFF; 4]);
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(lsm.scan(None, 5).entries, vec![]);
+
+        let page = lsm.scan(Some(&key(200)), 5);
+        assert_eq!(
+            page.entries,
+            (201..206)
+                .map(|i| (key(i), vec![b'v'; 100]))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(page.next_cursor, Some(key(205)));
+        drop(lsm);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_scan_rejects_a_zero_limit() {
+        let dir = PathBuf::from("./test_lib_scan_zero_limit");
+        fs::remove_dir_all(&dir).ok();
+        let lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+        let scanned = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| lsm.scan(None, 0)));
+        assert!(scanned.is_err());
+        drop(lsm);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_scan_across_flush_sees_newer_data() {
+        let dir = PathBuf::from("./test_lib_scan_flush");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+
+        for i in 0..20 {
+            lsm.put(format!("k{:02}", i).into_bytes(), b"v1".to_vec())
+                .unwrap();
+        }
+
+        let first = lsm.scan(None, 7);
+        assert_eq!(first.entries.len(), 7);
+        assert_eq!(first.next_cursor, Some(b"k06".to_vec()));
+
+        // Between pages: flush, overwrite a later key and add a new one
+        lsm.flush().unwrap();
+        lsm.put(b"k10".to_vec(), b"v2".to_vec()).unwrap();
+        lsm.put(b"k99".to_vec(), b"v2".to_vec()).unwrap();
+        // Keys before the cursor don't reappear
+        lsm.put(b"k00".to_vec(), b"v2".to_vec()).unwrap();
+
+        let mut rest = Vec::new();
+        let mut cursor = first.next_cursor;
+        while let Some(after) = cursor {
+            let page = lsm.scan(Some(&after), 7);
+            rest.extend(page.entries);
+            cursor = page.next_cursor;
+        }
+
+        let keys: Vec<Vec<u8>> = rest.iter().map(|(k, _)| k.clone()).collect();
+        let mut expected_keys: Vec<Vec<u8>> =
+            (7..20).map(|i| format!("k{:02}", i).into_bytes()).collect();
+        expected_keys.push(b"k99".to_vec());
+        assert_eq!(keys, expected_keys);
+        assert!(rest.contains(&(b"k10".to_vec(), b"v2".to_vec())));
+
+        // A page that ends exactly at the last key has no cursor
+        let page = lsm.scan(Some(b"k19"), 1);
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.next_cursor, None);
+
+        drop(lsm);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_bloom_distinct_estimate_with_overwrites() {
+        let dir = PathBuf::from("./test_lib_bloom_distinct");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+
+        // 100 live keys, each rewritten in 10 separate SSTables
+        for round in 0..10 {
+            for i in 0..100 {
+                let key = format!("key{}", i);
+                let value = format!("value{}", round);
+                lsm.put(key.into_bytes(), value.into_bytes()).unwrap();
+            }
+            lsm.flush().unwrap();
+        }
+
+        let stats = lsm.bloom_filter_stats();
+        assert_eq!(stats.total_items, 1000);
+        let estimate = stats.distinct_estimate as f64;
+        assert!(
+            (95.0..=105.0).contains(&estimate),
+            "distinct estimate {} should be close to 100",
+            estimate
+        );
+
+        // The estimate survives a reopen since overlap is stored with the filter
+        drop(lsm);
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(
+            lsm.bloom_filter_stats().distinct_estimate,
+            stats.distinct_estimate
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    /// Writes a headerless SSTable the way builds before format versioning did
+    fn write_legacy_sstable(path: &Path, records: &[(&[u8], &[u8])]) {
+        let mut buf = Vec::new();
+        for (key, value) in records {
+            sstable::write_unsequenced_record(&mut buf, key, Some(value));
+        }
+        fs::write(path, buf).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_legacy_sstables() {
+        let dir = PathBuf::from("./test_lib_migrate");
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+
+        write_legacy_sstable(
+            &dir.join("sstable_0.db"),
+            &[(b"a", b"old"), (b"b", b"only-in-0")],
+        );
+        write_legacy_sstable(&dir.join("sstable_1.db"), &[(b"a", b"new")]);
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+        assert_eq!(lsm.legacy_sstable_count(), 2);
+        assert_eq!(lsm.get(b"a"), Some(b"new".to_vec()));
+        assert_eq!(lsm.get(b"b"), Some(b"only-in-0".to_vec()));
+
+        assert_eq!(lsm.migrate_format().unwrap(), 2);
+        assert_eq!(lsm.legacy_sstable_count(), 0);
+        assert_eq!(lsm.migrate_format().unwrap(), 0);
+
+        // Rewritten tables are stamped with the tree id and get a footer
+        for path in lsm.sstables().map(|t| t.path()) {
+            assert_eq!(
+                sstable::format_version(path).unwrap(),
+                sstable::CHECKSUM_FORMAT_VERSION
+            );
+            assert!(path.with_extension("bloom").exists());
+        }
+        assert!(!dir.join("sstable_0.db").exists());
+        assert!(!dir.join("sstable_1.db").exists());
+        assert_eq!(lsm.get(b"a"), Some(b"new".to_vec()));
+        assert_eq!(lsm.get(b"b"), Some(b"only-in-0".to_vec()));
+
+        // The manifest keeps the migrated ordering across a reopen
+        drop(lsm);
+        let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+        assert_eq!(lsm.sstable_count(), 2);
+        assert_eq!(lsm.get(b"a"), Some(b"new".to_vec()));
+        assert_eq!(lsm.count_range::<&[u8], _>(..), 2);
+        drop(lsm);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_repair_ordering_after_counter_reset() {
+        let dir = PathBuf::from("./test_lib_repair_ordering");
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+
+        // A run of the old demo wrote tables 0..=2; after a restart the
+        // counter started over and overwrote table 0 with newer data
+        let set_modified = |number: usize, secs: u64| {
+            File::options()
+                .write(true)
+                .open(dir.join(format!("sstable_{}.db", number)))
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+                .unwrap();
+        };
+        write_legacy_sstable(&dir.join("sstable_1.db"), &[(b"a", b"stale"), (b"b", b"1")]);
+        write_legacy_sstable(&dir.join("sstable_2.db"), &[(b"a", b"stale-2")]);
+        write_legacy_sstable(&dir.join("sstable_0.db"), &[(b"a", b"fresh")]);
+        set_modified(1, 1_000);
+        set_modified(2, 2_000);
+        set_modified(0, 3_000);
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+        assert_eq!(lsm.get(b"a"), Some(b"stale-2".to_vec()));
+        assert_eq!(
+            lsm.ordering_anomalies(),
+            [OrderingAnomaly {
+                newer: dir.join("sstable_1.db"),
+                older: dir.join("sstable_0.db"),
+            }]
+        );
+
+        // A newer, sequenced table keeps its place above the legacy ones
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        lsm.flush().unwrap();
+
+        let changes = lsm.repair_ordering().unwrap();
+        let moves: Vec<_> = changes
+            .iter()
+            .map(|c| (c.old_path.clone(), c.new_path.clone(), c.new_generation))
+            .collect();
+        assert_eq!(
+            moves,
+            [
+                (dir.join("sstable_3.db"), dir.join("sstable_7.db"), 4),
+                (dir.join("sstable_0.db"), dir.join("sstable_6.db"), 3),
+                (dir.join("sstable_2.db"), dir.join("sstable_5.db"), 2),
+                (dir.join("sstable_1.db"), dir.join("sstable_4.db"), 1),
+            ]
+        );
+        assert!(lsm.ordering_anomalies().is_empty());
+        assert_eq!(lsm.get(b"a"), Some(b"fresh".to_vec()));
+        assert_eq!(lsm.get(b"b"), Some(b"2".to_vec()));
+        for number in 0..4 {
+            assert!(!dir.join(format!("sstable_{}.db", number)).exists());
+        }
+        assert!(lsm.repair_ordering().unwrap().is_empty());
+
+        // The repaired order survives a reopen, and file numbers agree with
+        // it even when the directory is scanned without a manifest
+        drop(lsm);
+        for rescan in [false, true] {
+            if rescan {
+                fs::remove_file(dir.join("MANIFEST")).unwrap();
+            }
+            let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+            assert!(lsm.ordering_anomalies().is_empty());
+            assert_eq!(lsm.get(b"a"), Some(b"fresh".to_vec()));
+            assert_eq!(lsm.range::<&[u8], _>(..).count(), 2);
+        }
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_open_rejects_future_sstable_version() {
+        let dir = PathBuf::from("./test_lib_future_version");
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut contents = sstable::SSTABLE_MAGIC.to_vec();
+        contents.extend_from_slice(&(sstable::NEWEST_FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(dir.join("sstable_0.db"), contents).unwrap();
+
+        let err = LSMTree::new(dir.clone(), 1024).expect_err("open must fail");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("format version"));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_flush_syncs_files_and_directory() {
+        let dir = PathBuf::from("./test_lib_flush_sync");
+        fs::remove_dir_all(&dir).ok();
+
+        let storage = storage::CountingStorage::new();
+        let mut lsm = LSMTree::open(LSMConfig {
+            storage: std::sync::Arc::new(storage.clone()),
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+
+        lsm.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        assert_eq!(storage.file_syncs(), 0);
+        lsm.flush().unwrap();
+
+        // SSTable + Bloom filter, then the directory holding them
+        assert_eq!(storage.file_syncs(), 2);
+        assert_eq!(storage.dir_syncs(), 1);
+
+        drop(lsm);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_flush_sync_can_be_disabled() {
+        let dir = PathBuf::from("./test_lib_flush_nosync");
+        fs::remove_dir_all(&dir).ok();
+
+        let storage = storage::CountingStorage::new();
+        let mut lsm = LSMTree::open(LSMConfig {
+            sync_on_flush: false,
+            storage: std::sync::Arc::new(storage.clone()),
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+
+        lsm.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        assert_eq!(storage.file_syncs(), 0);
+        assert_eq!(storage.dir_syncs(), 0);
+        assert_eq!(lsm.get(b"key"), Some(b"value".to_vec()));
+
+        drop(lsm);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_write_options_sync_and_disable_wal() {
+        let dir = PathBuf::from("./test_lib_write_options");
+        fs::remove_dir_all(&dir).ok();
+
+        let storage = storage::CountingStorage::new();
+        let mut lsm = LSMTree::open(LSMConfig {
+            storage: std::sync::Arc::new(storage.clone()),
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+
+        // Defaults keep the old behavior: no fsync per write
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        assert_eq!(storage.file_syncs(), 0);
+
+        let sync = WriteOptions {
+            sync: Some(true),
+            ..WriteOptions::default()
+        };
+        lsm.put_opt(b"b".to_vec(), b"2".to_vec(), &sync).unwrap();
+        lsm.delete_opt(b"a".to_vec(), &sync).unwrap();
+        assert_eq!(storage.file_syncs(), 2);
+        assert_eq!(lsm.wal_stats().fsyncs, 2);
+
+        // Skipping the WAL skips its sync too
+        let unlogged = WriteOptions {
+            sync: Some(true),
+            disable_wal: true,
+        };
+        let entries = lsm.wal_stats().entries_since_clear;
+        lsm.put_opt(b"c".to_vec(), b"3".to_vec(), &unlogged)
+            .unwrap();
+        assert_eq!(lsm.wal_stats().entries_since_clear, entries);
+        assert_eq!(storage.file_syncs(), 2);
+        assert_eq!(lsm.get(b"c"), Some(b"3".to_vec()));
+        lsm.simulate_crash();
+
+        // Unlogged writes are lost on a crash, logged ones are not
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get(b"a"), None);
+        assert_eq!(lsm.get(b"b"), Some(b"2".to_vec()));
+        assert_eq!(lsm.get(b"c"), None);
+        drop(lsm);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_write_batch_syncs_once() {
+        let dir = PathBuf::from("./test_lib_write_batch");
+        fs::remove_dir_all(&dir).ok();
+
+        let storage = storage::CountingStorage::new();
+        let mut lsm = LSMTree::open(LSMConfig {
+            sync_writes: true,
+            storage: std::sync::Arc::new(storage.clone()),
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+        lsm.put(b"old".to_vec(), b"1".to_vec()).unwrap();
+        assert_eq!(storage.file_syncs(), 1);
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"x".to_vec(), b"1".to_vec());
+        batch.put(b"y".to_vec(), b"2".to_vec());
+        batch.delete(b"old".to_vec());
+        batch.put(b"x".to_vec(), b"3".to_vec());
+        assert_eq!(batch.len(), 4);
+        lsm.write(batch).unwrap();
+        assert_eq!(storage.file_syncs(), 2);
+        assert_eq!(lsm.wal_stats().entries_since_clear, 5);
+
+        lsm.write(WriteBatch::new()).unwrap();
+        assert_eq!(storage.file_syncs(), 2);
+
+        // Later operations in a batch win, as if written one by one
+        assert_eq!(lsm.get(b"x"), Some(b"3".to_vec()));
+        assert_eq!(lsm.get(b"y"), Some(b"2".to_vec()));
+        assert_eq!(lsm.get(b"old"), None);
+        lsm.simulate_crash();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get(b"x"), Some(b"3".to_vec()));
+        assert_eq!(lsm.get(b"old"), None);
+        drop(lsm);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_recovery_skips_wal_entries_already_flushed() {
+        let dir = PathBuf::from("./test_lib_wal_skip");
+        fs::remove_dir_all(&dir).ok();
+
+        let wal_path = dir.join("wal.log");
+        let saved_wal = dir.join("wal.saved");
+        {
+            let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+            lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+            lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+            lsm.delete(b"a".to_vec()).unwrap();
+
+            // Keep the log as it was just before flush cleared it
+            fs::copy(&wal_path, &saved_wal).unwrap();
+            lsm.flush().unwrap();
+            assert_eq!(lsm.last_sequence(), 3);
+        }
+
+        // Simulate a crash after the manifest was written but before the
+        // WAL was cleared: the flushed entries are still in the log
+        fs::copy(&saved_wal, &wal_path).unwrap();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.len(), 0, "flushed entries must not be replayed");
+        assert_eq!(lsm.last_sequence(), 3);
+        assert_eq!(lsm.get(b"a"), None);
+        assert_eq!(lsm.get(b"b"), Some(b"2".to_vec()));
+
+        // New writes continue the sequence and are replayed normally
+        lsm.put(b"c".to_vec(), b"3".to_vec()).unwrap();
+        assert_eq!(lsm.last_sequence(), 4);
+        lsm.simulate_crash();
+
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.len(), 1);
+        assert_eq!(lsm.sstable_count(), 1);
+        assert_eq!(lsm.count_range::<&[u8], _>(..), 2);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_open_cuts_wal_at_absurd_length() {
+        let dir = PathBuf::from("./test_lib_wal_absurd");
+        fs::remove_dir_all(&dir).ok();
+        let config = LSMConfig {
+            size_limits: SizeLimits {
+                max_key_size: 16,
+                max_value_size: 16,
+            },
+            ..LSMConfig::new(dir.clone())
+        };
+
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        let Err(e) = lsm.put(vec![b'k'; 17], b"v".to_vec()) else {
+            panic!("oversized key was accepted");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(lsm.put(b"c".to_vec(), vec![0; 17]).is_err());
+        lsm.simulate_crash();
+
+        // Damage the second entry's key length to claim nearly 4 GiB
+        let wal_path = dir.join("wal.log");
+        let mut bytes = fs::read(&wal_path).unwrap();
+        let entry_len = bytes.len() / 2;
+        bytes[entry_len + 9..entry_len + 13].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        fs::write(&wal_path, &bytes).unwrap();
+
+        // Read-only replays the good prefix without touching the log
+        let mut read_only = LSMTree::open(LSMConfig {
+            read_only: true,
+            ..config.clone()
+        })
+        .unwrap();
+        assert_eq!(read_only.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(read_only.get(b"b"), None);
+        drop(read_only);
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), bytes.len() as u64);
+
+        // A writable open cuts the damage off, so new writes survive
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), entry_len as u64);
+        assert_eq!(lsm.get(b"a"), Some(b"1".to_vec()));
+        lsm.put(b"c".to_vec(), b"3".to_vec()).unwrap();
+        lsm.simulate_crash();
+
+        let mut lsm = LSMTree::open(config).unwrap();
+        assert_eq!(lsm.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(lsm.get(b"b"), None);
+        assert_eq!(lsm.get(b"c"), Some(b"3".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_open_report_counts_recovery() {
+        let dir = PathBuf::from("./test_lib_open_report");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        for i in 0..3u8 {
+            lsm.put(vec![i], vec![i]).unwrap();
+            lsm.flush().unwrap();
+        }
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        lsm.simulate_crash();
+
+        let (lsm, report) = LSMTree::open_with_report(LSMConfig::new(dir.clone())).unwrap();
+        assert!(!report.recovered(), "clean reopen reported {:?}", report);
+        assert_eq!(report.wal_entries_replayed, 2);
+        assert_eq!(report.sstables_loaded, 3);
+        assert_eq!(report.filters_loaded, 3);
+        lsm.simulate_crash();
+
+        // Tear the last WAL entry, lose a filter, leave a half-written file
+        let wal_path = dir.join("wal.log");
+        let wal_len = fs::metadata(&wal_path).unwrap().len();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&wal_path)
+            .unwrap()
+            .set_len(wal_len - 1)
+            .unwrap();
+        fs::remove_file(dir.join("sstable_1.bloom")).unwrap();
+        fs::write(dir.join("MANIFEST.tmp"), b"partial").unwrap();
+
+        let (mut lsm, report) = LSMTree::open_with_report(LSMConfig::new(dir.clone())).unwrap();
+        assert!(report.recovered());
+        assert_eq!(report.wal_entries_replayed, 1);
+        assert!(report.wal_bytes_replayed > 0);
+        assert_eq!(
+            report.wal_bytes_replayed + report.wal_bytes_dropped,
+            wal_len - 1
+        );
+        assert!(report.wal_corruption.is_some());
+        assert_eq!(report.sstables_loaded, 3);
+        assert_eq!(report.filters_loaded, 2);
+        assert_eq!(report.filters_rebuilt, 1);
+        assert_eq!(report.tmp_files_removed, vec![dir.join("MANIFEST.tmp")]);
+        assert!(!dir.join("MANIFEST.tmp").exists());
+        assert!(report.to_string().contains("1 rebuilt"));
+        assert_eq!(lsm.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(lsm.get(b"b"), None);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_separate_wal_dir_recovers_and_rejects_mismatch() {
+        let dir = PathBuf::from("./test_lib_wal_dir_data");
+        let wal_dir = PathBuf::from("./test_lib_wal_dir_wal");
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&wal_dir).ok();
+
+        let config = LSMConfig {
+            wal_dir: Some(wal_dir.clone()),
+            ..LSMConfig::new(dir.clone())
+        };
+
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        lsm.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        assert!(wal_dir.join("wal.log").exists());
+        assert!(!dir.join("wal.log").exists());
+        // Crash without flushing
+        lsm.simulate_crash();
+
+        // Forgetting the WAL directory must not hide the unflushed write
+        let Err(err) = LSMTree::new(dir.clone(), 1024 * 1024) else {
+            panic!("opening with the wrong WAL directory should fail");
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("test_lib_wal_dir_wal"));
+        assert!(!dir.join("wal.log").exists());
+
+        let mut lsm = LSMTree::open(config).unwrap();
+        assert_eq!(lsm.len(), 1);
+        assert_eq!(lsm.get(b"key"), Some(b"value".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&wal_dir).ok();
+    }
+
+    #[test]
+    fn test_generations_decide_newest_regardless_of_position() {
+        let dir = PathBuf::from("./test_lib_generations");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"key".to_vec(), b"old".to_vec()).unwrap();
+        lsm.put(b"other".to_vec(), b"x".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(b"key".to_vec(), b"new".to_vec()).unwrap();
+        lsm.delete(b"other".to_vec()).unwrap();
+        lsm.flush().unwrap();
+
+        let infos = lsm.sstable_infos();
+        assert_eq!(infos.len(), 2);
+        assert!(infos[0].generation > infos[1].generation);
+
+        // Scramble the in-memory order; reads must still follow generations
+        lsm.sstables.reverse();
+        lsm.bloom_filters.reverse();
+
+        assert_eq!(lsm.get(b"key"), Some(b"new".to_vec()));
+        assert_eq!(lsm.get_immut(b"other"), None);
+        let all: Vec<_> = lsm.range::<&[u8], _>(..).collect();
+        assert_eq!(all, vec![(b"key".to_vec(), b"new".to_vec())]);
+        assert_eq!(lsm.sstable_infos(), infos);
+
+        // The manifest is written in generation order too
+        lsm.put(b"third".to_vec(), b"3".to_vec()).unwrap();
+        drop(lsm);
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        let reopened = lsm.sstable_infos();
+        assert_eq!(reopened.len(), 3);
+        assert_eq!(&reopened[1..], &infos[..]);
+        assert_eq!(lsm.get_immut(b"key"), Some(b"new".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    /// Builds a tree with three SSTables (`a`, `b`, `c` in tables 0, 1, 2)
+    fn three_table_tree(dir: &Path) {
+        fs::remove_dir_all(dir).ok();
+        let mut lsm = LSMTree::new(dir.to_path_buf(), 1024 * 1024).unwrap();
+        for key in [b"a", b"b", b"c"] {
+            lsm.put(key.to_vec(), b"value".to_vec()).unwrap();
+            lsm.flush().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_orphaned_bloom_files_are_removed_unless_a_state_reads_the_table() {
+        let dir = PathBuf::from("./test_lib_orphan_bloom");
+        three_table_tree(&dir);
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+
+        // A state keeps the merged tables, and so their filters
+        let state = lsm.read_state();
+        lsm.put(b"d".to_vec(), b"value".to_vec()).unwrap();
+        assert_eq!(lsm.purge().unwrap().orphans_removed, 0);
+        fs::remove_file(dir.join("sstable_1.db")).unwrap();
+        lsm.put(b"e".to_vec(), b"value".to_vec()).unwrap();
+        assert_eq!(lsm.purge().unwrap().orphans_removed, 0);
+        assert!(dir.join("sstable_1.bloom").exists());
+
+        // Releasing the state deletes the other tables; the table deleted
+        // by hand leaves its filter behind
+        drop(state);
+        assert!(!dir.join("sstable_2.bloom").exists());
+        assert!(dir.join("sstable_1.bloom").exists());
+        drop(lsm);
+
+        let (mut lsm, report) = LSMTree::open_with_report(LSMConfig::new(dir.clone())).unwrap();
+        assert!(report.recovered());
+        assert_eq!(
+            report.orphan_sidecars_removed,
+            vec![dir.join("sstable_1.bloom")]
+        );
+        assert!(!dir.join("sstable_1.bloom").exists());
+        assert!(report.to_string().contains("removed orphan"));
+        assert_eq!(lsm.get(b"a"), Some(b"value".to_vec()));
+        assert_eq!(lsm.get(b"e"), Some(b"value".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_small_tables_are_merged_on_open() {
+        let dir = PathBuf::from("./test_lib_merge_small_tables");
+        fs::remove_dir_all(&dir).ok();
+
+        // One big table at the bottom, then 30 tiny flushes that overwrite
+        // and delete its keys, and each other's
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        let mut model = std::collections::BTreeMap::new();
+        for i in 0..200u32 {
+            let key = format!("key:{:04}", i).into_bytes();
+            lsm.put(key.clone(), vec![b'x'; 32]).unwrap();
+            model.insert(key, vec![b'x'; 32]);
+        }
+        lsm.flush().unwrap();
+        for round in 0..30u32 {
+            for i in 0..3u32 {
+                let key = format!("key:{:04}", (round * 3 + i) % 40).into_bytes();
+                if (round + i) % 4 == 0 {
+                    lsm.delete(key.clone()).unwrap();
+                    model.remove(&key);
+                } else {
+                    let value = format!("round {}", round).into_bytes();
+                    lsm.put(key.clone(), value.clone()).unwrap();
+                    model.insert(key, value);
+                }
+            }
+            lsm.flush().unwrap();
+        }
+        assert_eq!(lsm.sstable_count(), 31);
+        drop(lsm);
+
+        let config = || LSMConfig {
+            small_table_merge_threshold: Some(10),
+            small_table_bytes: 1024,
+            ..LSMConfig::new(dir.clone())
+        };
+        // Never in a read-only tree
+        let (lsm, report) = LSMTree::open_with_report(LSMConfig {
+            read_only: true,
+            ..config()
+        })
+        .unwrap();
+        assert_eq!(report.small_tables_merged, 0);
+        assert_eq!(lsm.sstable_count(), 31);
+        drop(lsm);
+
+        let (mut lsm, report) = LSMTree::open_with_report(config()).unwrap();
+        assert_eq!(report.small_tables_merged, 30);
+        assert_eq!(report.merged_tables_written, 1);
+        assert!(!report.recovered());
+        assert!(
+            report
+                .to_string()
+                .contains("merged 30 small sstables into 1")
+        );
+        assert_eq!(lsm.sstable_count(), 2);
+        // The deletes still shadow the big table
+        assert!(lsm.sstable_infos()[0].stats.tombstones > 0);
+        for i in 0..200u32 {
+            let key = format!("key:{:04}", i).into_bytes();
+            assert_eq!(lsm.get(&key), model.get(&key).cloned(), "key {}", i);
+        }
+        let tables = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("db".as_ref()))
+            .count();
+        assert_eq!(tables, 2);
+        drop(lsm);
+
+        // The merge is in the manifest, and nothing is left to merge
+        let (mut lsm, report) = LSMTree::open_with_report(config()).unwrap();
+        assert_eq!(report.small_tables_merged, 0);
+        assert_eq!(lsm.sstable_count(), 2);
+        assert_eq!(
+            lsm.range::<&[u8], _>(..).collect::<Vec<_>>(),
+            model.into_iter().collect::<Vec<_>>()
+        );
+        lsm.put(b"key:0000".to_vec(), b"newer".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        assert_eq!(lsm.get(b"key:0000"), Some(b"newer".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_repair_rebuilds_lost_manifest() {
+        let dir = PathBuf::from("./test_lib_repair");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        for round in 1..=3u8 {
+            lsm.put(b"key".to_vec(), vec![round]).unwrap();
+            lsm.put(vec![b'k', round], b"value".to_vec()).unwrap();
+            lsm.flush().unwrap();
+        }
+
+        // Refused while the tree holds the directory
+        let Err(e) = LSMTree::repair(&dir, &RepairOptions::default()) else {
+            panic!("repair ran on a locked directory");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+        drop(lsm);
+
+        // File numbers that contradict the write order don't fool it
+        fs::remove_file(dir.join("MANIFEST")).unwrap();
+        fs::remove_file(dir.join("sstable_1.bloom")).unwrap();
+        fs::rename(dir.join("sstable_0.db"), dir.join("sstable_5.db")).unwrap();
+        fs::rename(dir.join("sstable_0.bloom"), dir.join("sstable_5.bloom")).unwrap();
+        let table = fs::read(dir.join("sstable_2.db")).unwrap();
+        let records_end = sstable::records_end(&dir.join("sstable_2.db")) as usize;
+        fs::write(dir.join("sstable_3.db"), &table[..records_end - 1]).unwrap();
+        // A flipped value byte fails the record's checksum: the last value
+        // is that of b"key"
+        let mut flipped = table.clone();
+        flipped[records_end - 1] ^= 0x01;
+        fs::write(dir.join("sstable_4.db"), &flipped).unwrap();
+
+        let report = LSMTree::repair(&dir, &RepairOptions::default()).unwrap();
+        assert_eq!(
+            report.tables,
+            vec![SSTableId(2), SSTableId(1), SSTableId(5)]
+        );
+        assert_eq!(report.rebuilt_filters, vec![SSTableId(1)]);
+        assert!(report.ordered_by_mtime.is_empty());
+        assert_eq!(report.quarantined.len(), 2);
+        assert!(dir.join("sstable_3.db.corrupt").exists());
+        assert!(dir.join("sstable_4.db.corrupt").exists());
+        assert!(matches!(
+            report.quarantined[1].reason,
+            Corruption::ChecksumMismatch { .. }
+        ));
+        assert_eq!(report.previous_manifest, None);
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.sstable_count(), 3);
+        assert!(lsm.filterless_sstables().is_empty());
+        assert_eq!(lsm.get(b"key"), Some(vec![3]));
+        for round in 1..=3u8 {
+            assert_eq!(lsm.get(&[b'k', round]), Some(b"value".to_vec()));
+        }
+        // New tables don't reuse the quarantined number
+        lsm.put(b"key".to_vec(), vec![4]).unwrap();
+        lsm.flush().unwrap();
+        assert!(dir.join("sstable_6.db").exists());
+        drop(lsm);
+
+        // A second repair keeps the manifest it replaces
+        let report = LSMTree::repair(&dir, &RepairOptions::default()).unwrap();
+        assert_eq!(report.tables[0], SSTableId(6));
+        assert_eq!(report.previous_manifest, Some(dir.join("MANIFEST.bak")));
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get_immut(b"key"), Some(vec![4]));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_cancelled_repair_keeps_the_old_manifest() {
+        let dir = PathBuf::from("./test_lib_cancelled_repair");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        for round in 1..=3u8 {
+            lsm.put(vec![b'k', round], b"value".to_vec()).unwrap();
+            lsm.flush().unwrap();
+        }
+        drop(lsm);
+        let manifest = fs::read(dir.join("MANIFEST")).unwrap();
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let err = LSMTree::repair_with(&dir, &RepairOptions::default(), &token).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+        assert!(crate::Cancelled::from_io(&err).is_some());
+        assert_eq!(fs::read(dir.join("MANIFEST")).unwrap(), manifest);
+        assert!(!dir.join("MANIFEST.bak").exists());
+        assert!(fs::read_dir(&dir).unwrap().all(|entry| {
+            !entry
+                .unwrap()
+                .path()
+                .to_string_lossy()
+                .ends_with(".corrupt")
+        }));
+
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.sstable_count(), 3);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_open_reports_progress_per_phase() {
+        let dir = PathBuf::from("./test_lib_open_progress");
+        three_table_tree(&dir);
+        {
+            let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+            for i in 0..2500u32 {
+                lsm.put(i.to_be_bytes().to_vec(), b"v".to_vec()).unwrap();
+            }
+            // Keep the writes in the WAL
+            lsm.simulate_crash();
+        }
+        // A rebuilt filter still counts as loaded
+        fs::remove_file(dir.join("sstable_1.bloom")).unwrap();
+        let wal_size = fs::metadata(dir.join("wal.log")).unwrap().len();
+
+        let mut events = Vec::new();
+        let lsm =
+            LSMTree::open_with_progress(LSMConfig::new(dir.clone()), |p| events.push(p)).unwrap();
+        assert_eq!(lsm.len(), 2500);
+        drop(lsm);
+
+        let phases = [
+            OpenPhase::ScanningDirectory,
+            OpenPhase::LoadingFilters,
+            OpenPhase::ReplayingWal,
+        ];
+        let mut rest = events.as_slice();
+        for phase in phases {
+            let count = rest.iter().take_while(|p| p.phase == phase).count();
+            let (current, next) = rest.split_at(count);
+            rest = next;
+
+            let first = current.first().unwrap();
+            let last = current.last().unwrap();
+            assert_eq!((first.done, first.bytes_done), (0, 0), "{:?}", phase);
+            assert_eq!(last.done, last.total, "{:?}", phase);
+            assert_eq!(last.bytes_done, last.bytes_total, "{:?}", phase);
+            assert_eq!(last.ratio(), 1.0);
+            for pair in current.windows(2) {
+                assert!(pair[0].done <= pair[1].done && pair[0].bytes_done <= pair[1].bytes_done);
+                assert_eq!(pair[0].total, pair[1].total);
+            }
+
+            match phase {
+                OpenPhase::ReplayingWal => {
+                    assert_eq!(last.total, 2500);
+                    assert_eq!(last.bytes_total, wal_size);
+                    // Start, one report per 1024 entries, end
+                    assert_eq!(current.len(), 4);
+                }
+                _ => {
+                    assert_eq!(last.total, 3);
+                    assert_eq!(current.len(), 4);
+                }
+            }
+        }
+        assert!(rest.is_empty());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_deleted_everything_is_empty_and_purges_away() {
+        let dir = PathBuf::from("./test_lib_purge_empty");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert!(lsm.is_empty());
+        for i in 0..50u8 {
+            lsm.put(vec![i], vec![i; 100]).unwrap();
+        }
+        assert!(!lsm.is_empty());
+        lsm.flush().unwrap();
+        for i in 0..50u8 {
+            lsm.delete(vec![i]).unwrap();
+        }
+        // Only tombstones in the memtable, live values below them
+        assert!(lsm.is_empty());
+        lsm.flush().unwrap();
+        assert!(lsm.is_empty());
+        assert_eq!(lsm.sstable_count(), 2);
+
+        lsm.purge().unwrap();
+        assert!(lsm.is_empty());
+        assert_eq!(lsm.sstable_count(), 0);
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["FORMAT", "LOCK", "MANIFEST", "wal.log"]);
+
+        lsm.put(b"again".to_vec(), b"1".to_vec()).unwrap();
+        assert!(!lsm.is_empty());
+        drop(lsm);
+
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get_immut(b"again"), Some(b"1".to_vec()));
+        assert_eq!(lsm.get_immut(&[0]), None);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_sstable_ids_survive_flush_and_report_purged_tables() {
+        let dir = PathBuf::from("./test_lib_sstable_handles");
+        three_table_tree(&dir);
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+
+        let tables: Vec<_> = lsm.sstables().map(|t| (t.id(), t.generation())).collect();
+        assert_eq!(tables.len(), 3);
+        assert!(tables.windows(2).all(|w| w[0].1 > w[1].1), "newest first");
+        let (oldest, _) = tables[2];
+        let table = lsm.sstable(oldest).unwrap();
+        assert_eq!(table.path(), dir.join(format!("sstable_{}.db", oldest.0)));
+        assert_eq!(
+            table.live_entries().unwrap(),
+            vec![(b"a".to_vec(), b"value".to_vec())]
+        );
+        assert!(table.filter_stats().is_some());
+
+        // A flush moves every table down a place, but ids still resolve
+        lsm.put(b"d".to_vec(), b"value".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        let table = lsm.sstable(oldest).unwrap();
+        assert_eq!(table.live_entries().unwrap()[0].0, b"a");
+        assert_eq!(lsm.sstables().last().map(|t| t.id()), Some(oldest));
+
+        // Compaction replaces them all; the old ids are reported as gone
+        lsm.purge().unwrap();
+        for (id, _) in tables {
+            assert!(lsm.sstable(id).is_none());
+        }
+        let merged = lsm.sstables().next().unwrap();
+        assert_eq!(merged.live_entries().unwrap().len(), 4);
+        assert_eq!(
+            lsm.sstable(merged.id()).map(|t| t.path()),
+            Some(merged.path())
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_dictionary_shrinks_similar_values() {
+        let value = |i: u32| {
+            format!(
+                r#"{{"id":{},"name":"user-{}","email":"user{}@example.com","active":{},"plan":"standard","region":"eu-west-1"}}"#,
+                i,
+                i,
+                i,
+                i.is_multiple_of(2)
+            )
+            .into_bytes()
+        };
+        let table_bytes = |lsm: &LSMTree| -> u64 {
+            lsm.sstables()
+                .map(|t| fs::metadata(t.path()).unwrap().len())
+                .sum()
+        };
+
+        let mut sizes = Vec::new();
+        for (name, compression) in [
+            ("plain", Compression::None),
+            (
+                "zstd",
+                Compression::Zstd {
+                    level: 3,
+                    dictionary: false,
+                },
+            ),
+            (
+                "dict",
+                Compression::Zstd {
+                    level: 3,
+                    dictionary: true,
+                },
+            ),
+        ] {
+            let dir = PathBuf::from(format!("./test_lib_compression_{}", name));
+            fs::remove_dir_all(&dir).ok();
+            let config = LSMConfig {
+                compression,
+                memtable_size_threshold: 64 * 1024 * 1024,
+                ..LSMConfig::new(dir.clone())
+            };
+            let mut lsm = LSMTree::open(config.clone()).unwrap();
+            for i in 0..10_000u32 {
+                lsm.put(i.to_be_bytes().to_vec(), value(i)).unwrap();
+            }
+            lsm.flush().unwrap();
+            sizes.push(table_bytes(&lsm));
+
+            // A purge retrains on the merged data
+            for i in 0..100u32 {
+                lsm.put(i.to_be_bytes().to_vec(), value(i + 50_000))
+                    .unwrap();
+            }
+            lsm.flush().unwrap();
+            lsm.purge().unwrap();
+            drop(lsm);
+
+            let mut lsm = LSMTree::open(config).unwrap();
+            assert_eq!(lsm.sstable_count(), 1);
+            for i in (0..10_000u32).step_by(97) {
+                let expected = if i < 100 { value(i + 50_000) } else { value(i) };
+                assert_eq!(lsm.get(&i.to_be_bytes()), Some(expected), "{} {}", name, i);
+            }
+            let scanned: Vec<_> = lsm.range::<&[u8], _>(..).collect();
+            assert_eq!(scanned.len(), 10_000);
+            assert_eq!(scanned[9_999].1, value(9_999));
+            drop(lsm);
+            fs::remove_dir_all(&dir).ok();
+        }
+
+        let (plain, zstd, dict) = (sizes[0], sizes[1], sizes[2]);
+        assert!(dict * 2 < plain, "dictionary {} vs plain {}", dict, plain);
+        assert!(dict * 4 < zstd * 3, "dictionary {} vs zstd {}", dict, zstd);
+    }
+
+    #[test]
+    fn test_space_amplification_drops_after_purge() {
+        let dir = PathBuf::from("./test_lib_amplification");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.amplification_stats(), AmplificationStats::default());
+
+        // The same 200 keys overwritten in five tables
+        for round in 0..5u8 {
+            for i in 0..200u32 {
+                lsm.put(i.to_be_bytes().to_vec(), vec![round; 100]).unwrap();
+            }
+            lsm.flush().unwrap();
+        }
+        let before = lsm.amplification_stats();
+        assert_eq!(before.user_bytes_written, 5 * 200 * 104);
+        assert_eq!(before.sstable_bytes_written, before.sstable_bytes_on_disk);
+        assert!(before.write_amplification() > 1.0);
+        assert!(before.space_amplification() > 4.0);
+
+        let result = lsm.purge().unwrap();
+        assert_eq!(result.input_tables, 5);
+        assert_eq!(result.bytes_read, before.sstable_bytes_on_disk);
+        assert_eq!(result.entries_written, 200);
+        assert!(result.bytes_written < result.bytes_read / 4);
+
+        let after = lsm.amplification_stats();
+        assert_eq!(after.user_bytes_written, before.user_bytes_written);
+        assert_eq!(
+            after.sstable_bytes_written,
+            before.sstable_bytes_written + result.bytes_written
+        );
+        assert_eq!(after.sstable_bytes_on_disk, result.bytes_written);
+        assert_eq!(after.space_amplification(), 1.0);
+        assert!(after.write_amplification() > before.write_amplification());
+        assert!(
+            lsm.prometheus_metrics()
+                .contains("lsm_space_amplification 1\n")
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_cancelled_purge_leaves_the_tables_as_they_were() {
+        // Cancels the purge once it has seen `after` entries
+        #[derive(Debug)]
+        struct CancelAfter {
+            token: CancellationToken,
+            seen: std::sync::atomic::AtomicU64,
+            after: u64,
+        }
+
+        impl CompactionFilter for CancelAfter {
+            fn filter(&self, _key: &[u8], _value: &[u8]) -> FilterDecision {
+                let seen = self.seen.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+                if seen == self.after {
+                    self.token.cancel();
+                }
+                FilterDecision::Keep
+            }
+        }
+
+        let dir = PathBuf::from("./test_lib_cancelled_purge");
+        fs::remove_dir_all(&dir).ok();
+
+        let token = CancellationToken::new();
+        let config = LSMConfig {
+            compaction_filter: Some(Arc::new(CancelAfter {
+                token: token.clone(),
+                seen: Default::default(),
+                after: 10,
+            })),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config).unwrap();
+        for table in 0..3u32 {
+            for i in 0..20u32 {
+                lsm.put(
+                    format!("key{:02}", i).into_bytes(),
+                    table.to_be_bytes().to_vec(),
+                )
+                .unwrap();
+            }
+            lsm.flush().unwrap();
+        }
+        let before = lsm.sstable_paths();
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        files.sort();
+
+        let err = lsm.purge_with(&token).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+        assert_eq!(crate::Cancelled::from_io(&err).unwrap().entries, 10);
+
+        // No partial table is listed or left behind, and the inputs still
+        // answer reads
+        assert_eq!(lsm.sstable_paths(), before);
+        let mut after: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        after.sort();
+        assert_eq!(after, files);
+        assert_eq!(lsm.get(b"key05"), Some(2u32.to_be_bytes().to_vec()));
+        drop(lsm);
+
+        // The manifest on disk lists the same tables
+        let lsm = LSMTree::open(LSMConfig::new(dir.clone())).unwrap();
+        assert_eq!(lsm.sstable_paths(), before);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_cancelled_compact_for_keeps_the_steps_before() {
+        // Cancels once the filter has seen `after` more entries than when
+        // `arm` was called
+        #[derive(Debug, Default)]
+        struct CancelAfter {
+            token: CancellationToken,
+            seen: std::sync::atomic::AtomicU64,
+            after: std::sync::atomic::AtomicU64,
+        }
+
+        impl CancelAfter {
+            fn arm(&self, more: u64) {
+                let seen = self.seen.load(std::sync::atomic::Ordering::Relaxed);
+                self.after
+                    .store(seen + more, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+
+        impl CompactionFilter for CancelAfter {
+            fn filter(&self, _key: &[u8], _value: &[u8]) -> FilterDecision {
+                let seen = self.seen.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+                if seen == self.after.load(std::sync::atomic::Ordering::Relaxed) {
+                    self.token.cancel();
+                }
+                FilterDecision::Keep
+            }
+        }
+
+        let dir = PathBuf::from("./test_lib_cancelled_compact_for");
+        fs::remove_dir_all(&dir).ok();
+
+        let filter = Arc::new(CancelAfter::default());
+        let config = LSMConfig {
+            memtable_size_threshold: 512,
+            compaction_filter: Some(filter.clone()),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        let mut expected = BTreeMap::new();
+        for round in 0..3u32 {
+            for i in 0..100u32 {
+                let key = format!("key{i:04}").into_bytes();
+                let value = format!("value{i}-{round}").into_bytes();
+                lsm.put(key.clone(), value.clone()).unwrap();
+                expected.insert(key, value);
+            }
+        }
+        lsm.flush().unwrap();
+        let contents = |lsm: &LSMTree| lsm.range::<&[u8], _>(..).collect::<BTreeMap<_, _>>();
+
+        // A token cancelled up front stops the call before anything
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        let before = lsm.sstable_paths();
+        let err = lsm
+            .compact_for_with(Duration::ZERO, &cancelled)
+            .unwrap_err();
+        assert_eq!(crate::Cancelled::from_io(&err).unwrap().entries, 0);
+        assert_eq!(lsm.sstable_paths(), before);
+
+        // One step finishes, the next is stopped part way through
+        assert!(
+            !lsm.compact_for_with(Duration::ZERO, &filter.token)
+                .unwrap()
+                .completed
+        );
+        let after_step = lsm.sstable_paths();
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        files.sort();
+        // The filter sees a step's entries once to find where it ends,
+        // then again as it writes them
+        filter.arm(40);
+        let err = lsm
+            .compact_for_with(Duration::ZERO, &filter.token)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+        assert!(crate::Cancelled::from_io(&err).unwrap().entries > 0);
+
+        // The stopped step left no table behind, and the tree is whole
+        assert_eq!(lsm.sstable_paths(), after_step);
+        let mut after: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        after.sort();
+        assert_eq!(after, files);
+        assert_eq!(contents(&lsm), expected);
+
+        // The next call carries on and finishes
+        let result = lsm
+            .compact_for_with(Duration::from_secs(600), &CancellationToken::new())
+            .unwrap();
+        assert!(result.completed);
+        assert_eq!(contents(&lsm), expected);
+        assert!(
+            lsm.sstables
+                .iter()
+                .all(|t| t.compacted && t.stats.shadowed == 0)
+        );
+        drop(lsm);
+
+        let lsm = LSMTree::open(config).unwrap();
+        assert_eq!(contents(&lsm), expected);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_cancelled_iterators_stop_and_say_so() {
+        let dir = PathBuf::from("./test_lib_cancelled_scan");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 4096).unwrap();
+        for i in 0..50u32 {
+            lsm.put(i.to_be_bytes().to_vec(), b"value".to_vec())
+                .unwrap();
+        }
+        lsm.flush().unwrap();
+
+        let token = CancellationToken::new();
+        let mut range = lsm.range::<&[u8], _>(..).with_cancellation(&token);
+        assert_eq!(range.by_ref().take(5).count(), 5);
+        token.cancel();
+        assert_eq!(range.next(), None);
+        assert!(range.was_cancelled());
+
+        let mut keys = lsm
+            .keys::<&[u8], _>(..)
+            .with_cancellation(&CancellationToken::new());
+        assert_eq!(keys.by_ref().count(), 50);
+        assert!(!keys.was_cancelled());
+        drop((range, keys));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_compaction_filter_removes_and_rewrites_values() {
+        #[derive(Debug)]
+        struct Migrate;
+
+        impl CompactionFilter for Migrate {
+            fn filter(&self, _key: &[u8], value: &[u8]) -> FilterDecision {
+                if value.starts_with(b"expired:") {
+                    FilterDecision::Remove
+                } else if let Some(rest) = value.strip_prefix(b"v1:") {
+                    FilterDecision::Replace([b"v2:", rest].concat())
+                } else {
+                    FilterDecision::Keep
+                }
+            }
+        }
+
+        let dir = PathBuf::from("./test_lib_compaction_filter");
+        fs::remove_dir_all(&dir).ok();
+
+        let config = LSMConfig {
+            compaction_filter: Some(Arc::new(Migrate)),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config).unwrap();
+        lsm.put(b"a".to_vec(), b"v1:alpha".to_vec()).unwrap();
+        lsm.put(b"b".to_vec(), b"plain".to_vec()).unwrap();
+        lsm.put(b"c".to_vec(), b"old".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(b"c".to_vec(), b"expired:gone".to_vec()).unwrap();
+        lsm.put(b"d".to_vec(), b"v2:already".to_vec()).unwrap();
+
+        // Flushes don't run the filter
+        lsm.flush().unwrap();
+        assert_eq!(lsm.get(b"a"), Some(b"v1:alpha".to_vec()));
+        assert_eq!(lsm.get(b"c"), Some(b"expired:gone".to_vec()));
+
+        let result = lsm.purge().unwrap();
+        assert_eq!(result.entries_written, 3);
+        assert_eq!(lsm.get(b"a"), Some(b"v2:alpha".to_vec()));
+        assert_eq!(lsm.get(b"b"), Some(b"plain".to_vec()));
+        // Removed like a delete: the older version doesn't come back
+        assert_eq!(lsm.get(b"c"), None);
+        assert_eq!(lsm.get(b"d"), Some(b"v2:already".to_vec()));
+        let keys: Vec<_> = lsm.keys::<&[u8], _>(..).collect();
+        assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec(), b"d".to_vec()]);
+
+        // Removing everything leaves no table at all
+        lsm.put(b"a".to_vec(), b"expired:1".to_vec()).unwrap();
+        lsm.put(b"b".to_vec(), b"expired:2".to_vec()).unwrap();
+        lsm.put(b"d".to_vec(), b"expired:3".to_vec()).unwrap();
+        let result = lsm.purge().unwrap();
+        assert_eq!(result.bytes_written, 0);
+        assert_eq!(lsm.sstable_count(), 0);
+        assert!(lsm.is_empty());
+        drop(lsm);
+        let leftovers: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().starts_with("sstable_"))
+            .collect();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_purge_keeps_only_newest_live_values() {
+        let dir = PathBuf::from("./test_lib_purge_live");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        for key in [b"a", b"b", b"c"] {
+            lsm.put(key.to_vec(), b"old".to_vec()).unwrap();
+        }
+        lsm.flush().unwrap();
+        lsm.put(b"b".to_vec(), b"new".to_vec()).unwrap();
+        lsm.delete(b"c".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(b"d".to_vec(), b"unflushed".to_vec()).unwrap();
+
+        lsm.purge().unwrap();
+        assert_eq!(lsm.sstable_count(), 1);
+        assert_eq!(lsm.len(), 0);
+        let entries: Vec<_> = lsm.range::<&[u8], _>(..).collect();
+        assert_eq!(
+            entries,
+            [
+                (b"a".to_vec(), b"old".to_vec()),
+                (b"b".to_vec(), b"new".to_vec()),
+                (b"d".to_vec(), b"unflushed".to_vec()),
+            ]
+        );
+        let stats = lsm.bloom_filter_stats();
+        assert_eq!(stats.total_items, 3);
+        drop(lsm);
+
+        // The old tables are gone and the purged one survives a reopen
+        assert!(!dir.join("sstable_0.db").exists());
+        assert!(!dir.join("sstable_1.bloom").exists());
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get_immut(b"b"), Some(b"new".to_vec()));
+        assert_eq!(lsm.get_immut(b"c"), None);
+        assert_eq!(lsm.last_sequence(), 6);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_compact_for_resumes_until_it_matches_a_purge() {
+        let dirs = ["./test_lib_compact_for", "./test_lib_compact_for_purged"].map(PathBuf::from);
+        for dir in &dirs {
+            fs::remove_dir_all(dir).ok();
+        }
+        // The same overwrites and deletes, spread over a few dozen small
+        // tables, in each tree
+        let threshold = 512;
+        let fill = |dir: &PathBuf| {
+            let mut lsm = LSMTree::new(dir.clone(), threshold).unwrap();
+            let mut expected = BTreeMap::new();
+            for round in 0..3u32 {
+                for i in 0..200u32 {
+                    let key = format!("key{i:04}").into_bytes();
+                    if (i + round) % 7 == 0 {
+                        lsm.delete(key.clone()).unwrap();
+                        expected.remove(&key);
+                    } else {
+                        let value = format!("value{i}-{round}").into_bytes();
+                        lsm.put(key.clone(), value.clone()).unwrap();
+                        expected.insert(key, value);
+                    }
+                }
+            }
+            lsm.flush().unwrap();
+            (lsm, expected)
+        };
+        let contents = |lsm: &LSMTree| lsm.range::<&[u8], _>(..).collect::<BTreeMap<_, _>>();
+        let clean = |lsm: &LSMTree| {
+            lsm.sstables
+                .iter()
+                .all(|t| t.stats.tombstones == 0 && t.stats.shadowed == 0)
+        };
+
+        let (mut purged, expected) = fill(&dirs[1]);
+        let purge = purged.purge().unwrap();
+        assert!(purge.completed);
+        drop(purged);
+        fs::remove_dir_all(&dirs[1]).ok();
+
+        // A zero budget does one output table per call
+        let (mut lsm, _) = fill(&dirs[0]);
+        let mut totals = CompactionResult::default();
+        let mut steps = 0;
+        while !totals.completed {
+            let result = lsm.compact_for(Duration::ZERO).unwrap();
+            assert!(result.entries_written > 0);
+            totals.input_tables += result.input_tables;
+            totals.entries_written += result.entries_written;
+            totals.completed = result.completed;
+            steps += 1;
+            // Every step leaves a whole tree
+            assert_eq!(contents(&lsm), expected, "after step {steps}");
+        }
+        assert!(steps > 3);
+        assert_eq!(totals.input_tables, purge.input_tables);
+        assert_eq!(totals.entries_written, purge.entries_written);
+        assert_eq!(lsm.sstable_count(), steps);
+        assert!(clean(&lsm));
+        drop(lsm);
+        let lsm = LSMTree::new(dirs[0].clone(), threshold).unwrap();
+        assert_eq!(contents(&lsm), expected);
+        drop(lsm);
+        fs::remove_dir_all(&dirs[0]).ok();
+
+        // A budget long enough does it all in one call
+        let (mut lsm, _) = fill(&dirs[0]);
+        let result = lsm.compact_for(Duration::from_secs(600)).unwrap();
+        assert!(result.completed);
+        assert_eq!(result.input_tables, purge.input_tables);
+        assert_eq!(result.entries_written, purge.entries_written);
+        drop(lsm);
+        fs::remove_dir_all(&dirs[0]).ok();
+
+        // Writes between calls stay above the outputs, and a reopened tree
+        // starts over with the outputs as inputs
+        let (mut lsm, mut expected) = fill(&dirs[0]);
+        for steps in 1.. {
+            let result = lsm.compact_for(Duration::ZERO).unwrap();
+            assert_eq!(contents(&lsm), expected, "after step {steps}");
+            if result.completed {
+                break;
+            }
+            if steps == 2 {
+                lsm.put(b"key0003".to_vec(), b"later".to_vec()).unwrap();
+                lsm.delete(b"key0004".to_vec()).unwrap();
+                lsm.flush().unwrap();
+                expected.insert(b"key0003".to_vec(), b"later".to_vec());
+                expected.remove(&b"key0004"[..]);
+                assert_eq!(contents(&lsm), expected);
+            }
+            if steps == 4 {
+                drop(lsm);
+                lsm = LSMTree::new(dirs[0].clone(), threshold).unwrap();
+                assert_eq!(contents(&lsm), expected);
+            }
+        }
+        assert_eq!(contents(&lsm), expected);
+        assert!(clean(&lsm));
+        drop(lsm);
+        fs::remove_dir_all(&dirs[0]).ok();
+    }
+
+    #[test]
+    fn test_compact_for_keeps_the_versions_pins_see() {
+        let dir = PathBuf::from("./test_lib_compact_for_pinned");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 64).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        let pin = lsm.pin_sequence();
+        lsm.flush().unwrap();
+        lsm.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        // Enough other keys for several steps
+        for i in 0..50u32 {
+            lsm.put(format!("key{i:02}").into_bytes(), b"value".to_vec())
+                .unwrap();
+        }
+        lsm.flush().unwrap();
+        assert_eq!(lsm.get_at(b"a", pin), Some(b"1".to_vec()));
+
+        let mut steps = 0;
+        while !lsm.compact_for(Duration::ZERO).unwrap().completed {
+            steps += 1;
+            assert_eq!(lsm.get_at(b"a", pin), Some(b"1".to_vec()));
+        }
+        assert!(steps > 0);
+        assert_eq!(lsm.oldest_pinned_sequence(), Some(pin));
+        assert_eq!(lsm.get_at(b"a", pin), Some(b"1".to_vec()));
+        assert_eq!(lsm.get_immut(b"a"), Some(b"2".to_vec()));
+
+        // Released, the next compaction drops it
+        assert!(lsm.release_sequence(pin));
+        while !lsm.compact_for(Duration::ZERO).unwrap().completed {}
+        assert!(lsm.sstables.iter().all(|t| t.stats.shadowed == 0));
+        assert_eq!(lsm.get_immut(b"a"), Some(b"2".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_compacted_filter_fpp_stays_near_target_with_duplicate_keys() {
+        let fpp = 0.05;
+        let measure = |sizing: FilterSizing, dir: &str| {
+            let dir = PathBuf::from(dir);
+            fs::remove_dir_all(&dir).ok();
+            let mut lsm = LSMTree::open(LSMConfig {
+                bloom_filter_fpp: fpp,
+                // The default floor would size for about 1% instead
+                bloom_filter_floor: FilterFloor::NONE,
+                compaction_filter_sizing: sizing,
+                sync_on_flush: false,
+                ..LSMConfig::new(dir.clone())
+            })
+            .unwrap();
+            // Ten tables of the same 1000 keys: 90% of the input entries
+            // are older versions that the merge drops
+            for round in 0..10u32 {
+                for i in 0..1000u32 {
+                    lsm.put(
+                        format!("key{:05}", i).into_bytes(),
+                        round.to_le_bytes().to_vec(),
+                    )
+                    .unwrap();
+                }
+                lsm.flush().unwrap();
+            }
+            lsm.purge().unwrap();
+
+            let filter = lsm.bloom_filters.resident(0).unwrap().clone();
+            assert_eq!(filter.len(), 1000);
+            let probes = 20_000;
+            let false_positives = (0..probes)
+                .filter(|i| filter.might_contain(format!("absent{:05}", i).as_bytes()))
+                .count();
+            drop(lsm);
+            fs::remove_dir_all(&dir).ok();
+            (false_positives as f64 / probes as f64, filter.num_bits())
+        };
+
+        let (exact_fpp, exact_bits) =
+            measure(FilterSizing::Exact, "./test_lib_filter_sizing_exact");
+        assert!(
+            exact_fpp > fpp / 2.0 && exact_fpp < fpp * 2.0,
+            "measured {} against a target of {}",
+            exact_fpp,
+            fpp
+        );
+
+        // Sized from the 10000 input entries, the filter is ten times too big
+        let (_, expected_bits) =
+            measure(FilterSizing::Expected, "./test_lib_filter_sizing_expected");
+        assert!(expected_bits >= 9 * exact_bits);
+    }
+
+    #[test]
+    fn test_filter_floor_keeps_small_tables_at_target_fpp() {
+        let fpp = DEFAULT_BLOOM_FILTER_FPP;
+        let measure = |floor: FilterFloor, dir: &str| {
+            let dir = PathBuf::from(dir);
+            fs::remove_dir_all(&dir).ok();
+            let mut lsm = LSMTree::open(LSMConfig {
+                bloom_filter_floor: floor,
+                sync_on_flush: false,
+                ..LSMConfig::new(dir.clone())
+            })
+            .unwrap();
+            for table in 0..50u32 {
+                for i in 0..20u32 {
+                    lsm.put(
+                        format!("t{:02}k{:02}", table, i).into_bytes(),
+                        b"v".to_vec(),
+                    )
+                    .unwrap();
+                }
+                lsm.flush().unwrap();
+            }
+
+            let stats = lsm.bloom_filter_stats();
+            assert_eq!(stats.individual_stats.len(), 50);
+            let probes = 2_000;
+            let false_positives: usize = lsm
+                .bloom_filters
+                .resident_filters()
+                .map(|(_, filter)| {
+                    (0..probes)
+                        .filter(|i| filter.might_contain(format!("absent{:05}", i).as_bytes()))
+                        .count()
+                })
+                .sum();
+            drop(lsm);
+            fs::remove_dir_all(&dir).ok();
+            (
+                false_positives as f64 / (50 * probes) as f64,
+                stats.individual_stats,
+            )
+        };
+
+        let (floored_fpp, floored) = measure(FilterFloor::default(), "./test_lib_filter_floor");
+        assert!(
+            floored_fpp <= fpp,
+            "measured {} against a target of {}",
+            floored_fpp,
+            fpp
+        );
+        for stats in &floored {
+            assert_eq!(stats.num_items, 20);
+            assert!(stats.size_bytes >= bloom::DEFAULT_MIN_FILTER_BYTES);
+            assert!(stats.bits_per_key >= bloom::DEFAULT_MIN_BITS_PER_KEY as f64);
+        }
+
+        // Sized by the formula alone, 20-key filters overshoot the target
+        let (unfloored_fpp, unfloored) = measure(FilterFloor::NONE, "./test_lib_filter_no_floor");
+        assert!(unfloored_fpp > floored_fpp);
+        assert!(unfloored.iter().all(|stats| stats.bits_per_key < 10.0));
+    }
+
+    #[test]
+    fn test_paranoid_open_refuses_corrupt_sstables() {
+        let dir = PathBuf::from("./test_lib_paranoid");
+        three_table_tree(&dir);
+
+        // Zero bytes, as after a crash right after the file was created
+        fs::write(dir.join("sstable_1.db"), []).unwrap();
+
+        let Err(err) = LSMTree::new(dir.clone(), 1024 * 1024) else {
+            panic!("a corrupt SSTable must fail a paranoid open");
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("sstable_1.db"));
+        assert!(err.to_string().contains("empty"));
+        assert!(dir.join("sstable_1.db").exists());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_damaged_footer_fails_paranoid_open_and_falls_back_otherwise() {
+        let dir = PathBuf::from("./test_lib_bad_footer");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        lsm.delete(b"c".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        let table = lsm.sstables().next().unwrap();
+        let summary = table.summary().unwrap();
+        assert!(summary.from_footer);
+        assert_eq!((summary.entries, summary.tombstones), (3, 1));
+        assert_eq!(summary.first_key.as_deref(), Some(&b"a"[..]));
+        assert_eq!(summary.last_key.as_deref(), Some(&b"c"[..]));
+        assert_eq!((summary.min_sequence, summary.max_sequence), (1, 3));
+        let path = table.path().to_path_buf();
+        drop(lsm);
+
+        // Damage the trailer's checksum
+        let mut bytes = fs::read(&path).unwrap();
+        let checksum_at = bytes.len() - 12;
+        bytes[checksum_at] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+
+        let Err(err) = LSMTree::new(dir.clone(), 1024 * 1024) else {
+            panic!("a damaged footer must fail a paranoid open");
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("footer"));
+
+        // Without paranoid checks the table stays and is scanned instead
+        let mut lsm = LSMTree::open(LSMConfig {
+            paranoid_checks: false,
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+        assert!(lsm.quarantined_sstables().is_empty());
+        assert_eq!(lsm.get(b"b"), Some(b"2".to_vec()));
+        assert_eq!(lsm.get(b"c"), None);
+        let scanned = lsm.sstables().next().unwrap().summary().unwrap();
+        assert!(!scanned.from_footer);
+        assert_eq!((scanned.entries, scanned.tombstones), (3, 1));
+        assert_eq!(scanned.last_key, summary.last_key);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_corrupt_sstables_are_quarantined() {
+        let dir = PathBuf::from("./test_lib_quarantine");
+        three_table_tree(&dir);
+
+        // Truncate table 0 and write table 2 with keys out of order
+        let table0 = dir.join("sstable_0.db");
+        let bytes = fs::read(&table0).unwrap();
+        let records_end = sstable::records_end(&table0) as usize;
+        fs::write(&table0, &bytes[..records_end - 2]).unwrap();
+        let mut unordered = Vec::new();
+        sstable::write_header(&mut unordered).unwrap();
+        sstable::write_record(&mut unordered, b"z", 1, Some(b"1")).unwrap();
+        sstable::write_record(&mut unordered, b"c", 2, Some(b"2")).unwrap();
+        fs::write(dir.join("sstable_2.db"), unordered).unwrap();
+
+        let config = LSMConfig {
+            paranoid_checks: false,
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+
+        let quarantined = lsm.quarantined_sstables().to_vec();
+        assert_eq!(quarantined.len(), 2);
+        assert_eq!(quarantined[0].original_path, dir.join("sstable_2.db"));
+        assert_eq!(quarantined[0].reason, Corruption::OutOfOrder { offset: 26 });
+        assert_eq!(quarantined[1].original_path, dir.join("sstable_0.db"));
+        assert!(matches!(
+            quarantined[1].reason,
+            Corruption::Truncated { .. }
+        ));
+        assert!(dir.join("sstable_0.db.corrupt").exists());
+        assert!(dir.join("sstable_2.db.corrupt").exists());
+        assert!(!dir.join("sstable_0.bloom").exists());
+
+        assert_eq!(lsm.sstable_count(), 1);
+        assert_eq!(lsm.get(b"b"), Some(b"value".to_vec()));
+        assert_eq!(lsm.get(b"a"), None);
+        drop(lsm);
+
+        // The manifest no longer lists them, so a paranoid reopen succeeds
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert!(lsm.quarantined_sstables().is_empty());
+        assert_eq!(lsm.sstable_count(), 1);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_wal_stats_reset_after_flush() {
+        let dir = PathBuf::from("./test_lib_wal_stats");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        for i in 0..10u8 {
+            lsm.put(vec![b'k', i], vec![i; 6]).unwrap();
+        }
+        lsm.delete(vec![b'k', 0]).unwrap();
+
+        // 21 bytes of framing per entry, plus a 2-byte key and 6-byte value
+        let stats = lsm.wal_stats();
+        assert_eq!(stats.entries_since_clear, 11);
+        assert_eq!(stats.bytes_since_clear, 10 * (21 + 2 + 6) + (21 + 2));
+        assert_eq!(stats.file_size, stats.bytes_since_clear);
+        assert_eq!(stats.syncs, 11);
+
+        lsm.flush().unwrap();
+        let stats = lsm.wal_stats();
+        assert_eq!(stats.entries_since_clear, 0);
+        assert_eq!(stats.bytes_since_clear, 0);
+        assert_eq!(stats.file_size, 0);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_rebuild_bloom_filter_reports_unreadable_tables() {
+        let dir = PathBuf::from("./test_lib_rebuild_errors");
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sstable_0.db");
+
+        // Readable but empty: a valid filter that holds nothing
+        let mut header_only = Vec::new();
+        sstable::write_header(&mut header_only).unwrap();
+        fs::write(&path, &header_only).unwrap();
+        let bf = LSMTree::rebuild_bloom_filter(&path, 0.01, FilterFloor::default(), 1).unwrap();
+        assert_eq!(bf.len(), 0);
+        assert!(!bf.might_contain(b"anything"));
+
+        // Truncated: an error instead of a filter built from a prefix
+        let mut truncated = header_only.clone();
+        sstable::write_record(&mut truncated, b"key", 1, Some(b"value")).unwrap();
+        truncated.pop();
+        fs::write(&path, &truncated).unwrap();
+        let Err(err) = LSMTree::rebuild_bloom_filter(&path, 0.01, FilterFloor::default(), 1) else {
+            panic!("rebuilding from {} should fail", path.display());
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // Missing file
+        fs::remove_file(&path).unwrap();
+        let Err(err) = LSMTree::rebuild_bloom_filter(&path, 0.01, FilterFloor::default(), 1) else {
+            panic!("rebuilding from {} should fail", path.display());
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_filter_with_damaged_header_is_rebuilt() {
+        let dir = PathBuf::from("./test_lib_bloom_bad_header");
+        three_table_tree(&dir);
+
+        // Zero hash functions: every lookup would skip the table
+        let bloom_path = dir.join("sstable_1.bloom");
+        let mut bytes = fs::read(&bloom_path).unwrap();
+        bytes[4..8].copy_from_slice(&0u32.to_le_bytes());
+        fs::write(&bloom_path, &bytes).unwrap();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert!(lsm.filterless_sstables().is_empty());
+        assert_eq!(lsm.get(b"b"), Some(b"value".to_vec()));
+        let rebuilt = BloomFilter::from_bytes(&fs::read(&bloom_path).unwrap()).unwrap();
+        assert!(rebuilt.num_hashes() >= 1);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_truncated_sstable_without_filter_in_both_modes() {
+        let dir = PathBuf::from("./test_lib_rebuild_modes");
+        three_table_tree(&dir);
+
+        let table1 = dir.join("sstable_1.db");
+        let bytes = fs::read(&table1).unwrap();
+        let records_end = sstable::records_end(&table1) as usize;
+        fs::write(&table1, &bytes[..records_end - 1]).unwrap();
+        fs::remove_file(dir.join("sstable_1.bloom")).unwrap();
+
+        // Strict: the open fails and nothing is touched
+        assert!(LSMTree::new(dir.clone(), 1024 * 1024).is_err());
+        assert!(table1.exists());
+        assert!(!dir.join("sstable_1.bloom").exists());
+
+        // Lenient: the table is set aside instead of getting a guessed filter
+        let config = LSMConfig {
+            paranoid_checks: false,
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config).unwrap();
+        assert_eq!(lsm.quarantined_sstables().len(), 1);
+        assert!(lsm.filterless_sstables().is_empty());
+        assert_eq!(lsm.bloom_filter_stats().num_filters, 2);
+        assert!(!dir.join("sstable_1.bloom").exists());
+        assert_eq!(lsm.get(b"a"), Some(b"value".to_vec()));
+        assert_eq!(lsm.get(b"c"), Some(b"value".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_injected_clock_timestamps_wal_syncs() {
+        let dir = PathBuf::from("./test_lib_manual_clock");
+        fs::remove_dir_all(&dir).ok();
+
+        let clock = testing::ManualClock::new(1_000);
+        let config = LSMConfig {
+            clock: Arc::new(clock.clone()),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config).unwrap();
+        let at = |millis| Some(std::time::UNIX_EPOCH + std::time::Duration::from_millis(millis));
+
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        assert_eq!(lsm.wal_stats().last_sync, at(1_000));
+
+        // An hour passes instantly
+        clock.advance(std::time::Duration::from_secs(3600));
+        lsm.delete(b"a".to_vec()).unwrap();
+        assert_eq!(lsm.wal_stats().last_sync, at(3_601_000));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_maintenance_tick_flushes_idle_memtable() {
+        let dir = PathBuf::from("./test_lib_idle_flush");
+        fs::remove_dir_all(&dir).ok();
+
+        let clock = testing::ManualClock::new(1_000);
+        let config = LSMConfig {
+            flush_after_idle: Some(std::time::Duration::from_secs(30)),
+            clock: Arc::new(clock.clone()),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        assert_eq!(lsm.last_write_time(), None);
+        assert!(!lsm.maintenance_tick().unwrap());
+
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        clock.advance(std::time::Duration::from_secs(20));
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        let written_at = std::time::UNIX_EPOCH + std::time::Duration::from_millis(21_000);
+        assert_eq!(lsm.last_write_time(), Some(written_at));
+
+        // The window restarts with every write
+        clock.advance(std::time::Duration::from_secs(20));
+        assert!(!lsm.maintenance_tick().unwrap());
+        assert_eq!(lsm.sstable_count(), 0);
+
+        clock.advance(std::time::Duration::from_secs(10));
+        assert!(lsm.maintenance_tick().unwrap());
+        assert_eq!(lsm.sstable_count(), 1);
+        assert_eq!(lsm.len(), 0);
+        assert_eq!(lsm.wal_stats().file_size, 0);
+
+        // Nothing left to flush
+        clock.advance(std::time::Duration::from_secs(60));
+        assert!(!lsm.maintenance_tick().unwrap());
+        assert_eq!(lsm.sstable_count(), 1);
+        lsm.simulate_crash();
+
+        let mut lsm = LSMTree::open(config).unwrap();
+        assert_eq!(lsm.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(lsm.get(b"b"), Some(b"2".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_read_state_outlives_compaction() {
+        let dir = PathBuf::from("./test_lib_read_state");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(b"c".to_vec(), b"3".to_vec()).unwrap();
+
+        let state = lsm.read_state();
+        lsm.put(b"a".to_vec(), b"changed".to_vec()).unwrap();
+        lsm.delete(b"c".to_vec()).unwrap();
+        lsm.purge().unwrap();
+
+        // The purged tables stay on disk while the state reads them
+        assert_eq!(lsm.pending_table_deletions(), 2);
+        assert!(dir.join("sstable_0.db").exists());
+        assert_eq!(state.sstable_count(), 2);
+        assert_eq!(state.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(
+            state.multi_get(&[b"b", b"c"]),
+            vec![Some(b"2".to_vec()), Some(b"3".to_vec())]
+        );
+        let keys: Vec<_> = state.keys::<&[u8], _>(..).collect();
+        assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+
+        drop(state);
+        assert_eq!(lsm.pending_table_deletions(), 0);
+        assert!(!dir.join("sstable_0.db").exists());
+        assert!(!dir.join("sstable_1.bloom").exists());
+        assert_eq!(lsm.get(b"a"), Some(b"changed".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_workload_stats_feed_advisor() {
+        let dir = PathBuf::from("./test_lib_workload_stats");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+        for i in 0..10u32 {
+            lsm.put(i.to_be_bytes().to_vec(), vec![b'v'; 100]).unwrap();
+        }
+        lsm.delete(b"gone".to_vec()).unwrap();
+        assert!(lsm.get(&3u32.to_be_bytes()).is_some());
+        assert!(lsm.get_immut(b"absent").is_none());
+        assert_eq!(lsm.multi_get(&[b"gone"]), vec![None]);
+
+        let stats = lsm.workload_stats();
+        assert_eq!((stats.writes, stats.reads, stats.absent_reads), (11, 3, 2));
+        assert_eq!(stats.key_sizes.count(), 11);
+        assert_eq!(stats.value_sizes.mean(), 100.0);
+        assert_eq!(stats.value_bytes.total(), 640);
+
+        // A 1 KiB memtable holds about ten 104-byte entries per flush; 1024
+        // of them need 128 KiB
+        let advice = lsm.advisor();
+        assert_eq!(advice.memtable_size_threshold.current, 1024);
+        assert_eq!(advice.memtable_size_threshold.suggested, 128 * 1024);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_skip_identical_writes() {
+        let dir = PathBuf::from("./test_lib_skip_identical");
+        fs::remove_dir_all(&dir).ok();
+
+        let config = LSMConfig {
+            skip_identical_writes: true,
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        lsm.put(b"k".to_vec(), b"same".to_vec()).unwrap();
+        let wal_size = lsm.wal_stats().file_size;
+        let memtable_size = lsm.memtable_size();
+        for _ in 0..10 {
+            lsm.put(b"k".to_vec(), b"same".to_vec()).unwrap();
+        }
+        assert_eq!(lsm.skipped_identical_writes(), 10);
+        assert_eq!(lsm.wal_stats().file_size, wal_size);
+        assert_eq!(lsm.memtable_size(), memtable_size);
+        assert_eq!(lsm.last_sequence(), 1);
+
+        // A different value, a delete and a batch that touches the key
+        // first are all written
+        lsm.put(b"k".to_vec(), b"other".to_vec()).unwrap();
+        assert_eq!(lsm.get(b"k"), Some(b"other".to_vec()));
+        lsm.delete(b"k".to_vec()).unwrap();
+        lsm.delete(b"k".to_vec()).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"j".to_vec(), b"v".to_vec());
+        batch.delete(b"j".to_vec());
+        batch.put(b"j".to_vec(), b"v".to_vec());
+        lsm.write(batch).unwrap();
+        assert_eq!(lsm.last_sequence(), 7);
+        assert_eq!(lsm.skipped_identical_writes(), 10);
+
+        // Only the memtable counts: after a flush the value is written again
+        lsm.flush().unwrap();
+        lsm.put(b"j".to_vec(), b"v".to_vec()).unwrap();
+        assert_eq!(lsm.last_sequence(), 8);
+        assert!(
+            lsm.prometheus_metrics()
+                .contains("lsm_skipped_identical_writes_total 10")
+        );
+        drop(lsm);
+
+        // Under a TTL the second flag makes identical puts refresh the age
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::open(LSMConfig {
+            ttl: Some(std::time::Duration::from_secs(60)),
+            identical_writes_refresh_ttl: true,
+            ..config
+        })
+        .unwrap();
+        lsm.put(b"k".to_vec(), b"same".to_vec()).unwrap();
+        lsm.put(b"k".to_vec(), b"same".to_vec()).unwrap();
+        assert_eq!(lsm.skipped_identical_writes(), 0);
+        assert_eq!(lsm.last_sequence(), 2);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_top_k_matches_sorting_every_live_key() {
+        let dir = PathBuf::from("./test_lib_top_k");
+        fs::remove_dir_all(&dir).ok();
+
+        // Live keys with the order they were last written in
+        let mut model: BTreeMap<Vec<u8>, (Vec<u8>, u64)> = BTreeMap::new();
+        let mut writes = 0;
+        let mut lsm = LSMTree::new(dir.clone(), 1 << 20).unwrap();
+        for round in 0..4u64 {
+            for i in 0..60u64 {
+                let n = (i * 7 + round * 13) % 200;
+                let key = format!("k{:03}", n).into_bytes();
+                writes += 1;
+                if (i + round) % 9 == 0 {
+                    lsm.delete(key.clone()).unwrap();
+                    model.remove(&key);
+                } else {
+                    let value = format!("{}-{}", n, round).into_bytes();
+                    lsm.put(key.clone(), value.clone()).unwrap();
+                    model.insert(key, (value, writes));
+                }
+            }
+            // The last round stays in the memtable
+            if round < 3 {
+                lsm.flush().unwrap();
+            }
+        }
+
+        let ranges: [crate::iter::KeyRange; 3] = [
+            (Bound::Unbounded, Bound::Unbounded),
+            (
+                Bound::Included(b"k050".to_vec()),
+                Bound::Excluded(b"k150".to_vec()),
+            ),
+            (
+                Bound::Excluded(b"k020".to_vec()),
+                Bound::Included(b"k099".to_vec()),
+            ),
+        ];
+        for range in ranges {
+            let live: Vec<&Vec<u8>> = model.range(range.clone()).map(|(key, _)| key).collect();
+            let mut newest = live.clone();
+            newest.sort_by_key(|key| std::cmp::Reverse(model[*key].1));
+            for k in [0, 1, 5, 37, 500] {
+                let pairs = |keys: Vec<&Vec<u8>>| -> Vec<(Vec<u8>, Vec<u8>)> {
+                    keys.into_iter()
+                        .take(k)
+                        .map(|key| (key.clone(), model[key].0.clone()))
+                        .collect()
+                };
+                for (order, expected, exhaustive) in [
+                    (
+                        TopKOrder::SmallestKeys,
+                        pairs(live.clone()),
+                        live.len() <= k,
+                    ),
+                    (
+                        TopKOrder::LargestKeys,
+                        pairs(live.iter().rev().copied().collect()),
+                        live.len() <= k,
+                    ),
+                    (TopKOrder::NewestWrites, pairs(newest.clone()), true),
+                ] {
+                    let top = lsm.top_k(range.clone(), k, order);
+                    assert_eq!(top.entries, expected, "{:?} k={} {:?}", order, k, range);
+                    assert_eq!(
+                        top.exhaustive, exhaustive,
+                        "{:?} k={} {:?}",
+                        order, k, range
+                    );
+                }
+            }
+        }
+
+        // A prefix is the range of keys starting with it
+        let top = lsm.top_k_prefix(b"k1", 3, TopKOrder::LargestKeys);
+        let expected: Vec<_> = model
+            .range(b"k1".to_vec()..b"k2".to_vec())
+            .rev()
+            .take(3)
+            .map(|(key, (value, _))| (key.clone(), value.clone()))
+            .collect();
+        assert_eq!(top.entries, expected);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_top_k_steps_through_tombstones_past_the_last_entry() {
+        let dir = PathBuf::from("./test_lib_top_k_tombstones");
+        fs::remove_dir_all(&dir).ok();
+
+        // k00-k09 are deleted, some in SSTables and some in the memtable;
+        // only k10-k14 are live
+        let mut lsm = LSMTree::new(dir.clone(), 1 << 20).unwrap();
+        for i in 0..15u32 {
+            lsm.put(format!("k{i:02}").into_bytes(), b"value".to_vec())
+                .unwrap();
+        }
+        lsm.flush().unwrap();
+        for i in 0..10u32 {
+            lsm.delete(format!("k{i:02}").into_bytes()).unwrap();
+            if i == 4 {
+                lsm.flush().unwrap();
+            }
+        }
+
+        for order in [TopKOrder::LargestKeys, TopKOrder::SmallestKeys] {
+            let top = lsm.top_k::<&[u8], _>(.., 5, order);
+            assert_eq!(top.entries.len(), 5, "{:?}", order);
+            assert!(top.exhaustive, "{:?}", order);
+            let top = lsm.top_k::<&[u8], _>(.., 4, order);
+            assert!(!top.exhaustive, "{:?}", order);
+        }
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_largest_keys_walk_back_across_blocks() {
+        let dir = PathBuf::from("./test_lib_top_k_blocks");
+        fs::remove_dir_all(&dir).ok();
+
+        // Values big enough that every table spans several index blocks
+        let mut model: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        let mut lsm = LSMTree::new(dir.clone(), 1 << 20).unwrap();
+        for round in 0..3u64 {
+            for i in 0..150u64 {
+                let n = (i * 11 + round * 7) % 300;
+                let key = format!("k{:03}", n).into_bytes();
+                if (i + round) % 5 == 0 {
+                    lsm.delete(key.clone()).unwrap();
+                    model.remove(&key);
+                } else {
+                    let value = vec![b'a' + (round as u8); 200 + n as usize];
+                    lsm.put(key.clone(), value.clone()).unwrap();
+                    model.insert(key, value);
+                }
+            }
+            lsm.flush().unwrap();
+        }
+
+        let ranges: [crate::iter::KeyRange; 3] = [
+            (Bound::Unbounded, Bound::Unbounded),
+            (
+                Bound::Excluded(b"k010".to_vec()),
+                Bound::Excluded(b"k255".to_vec()),
+            ),
+            (
+                Bound::Included(b"k100".to_vec()),
+                Bound::Included(b"k101".to_vec()),
+            ),
+        ];
+        for range in ranges {
+            let live: Vec<_> = model.range(range.clone()).rev().collect();
+            for k in [1, 40, 1000] {
+                let top = lsm.top_k(range.clone(), k, TopKOrder::LargestKeys);
+                let expected: Vec<_> = live
+                    .iter()
+                    .take(k)
+                    .map(|(key, value)| ((*key).clone(), (*value).clone()))
+                    .collect();
+                assert_eq!(top.entries, expected, "k={} {:?}", k, range);
+                assert_eq!(top.exhaustive, live.len() <= k);
+            }
+        }
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_deleting_the_directory_poisons_the_tree() {
+        let dir = PathBuf::from("./test_lib_deleted_under_tree");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 4096).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        assert_eq!(
+            lsm.get_opt(b"a", &ReadOptions::default()).unwrap(),
+            Some(b"1".to_vec())
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+        let err = lsm.get_opt(b"a", &ReadOptions::default()).unwrap_err();
+        let deleted = crate::TreeDeleted::from_io(&err).unwrap();
+        assert_eq!(deleted.data_dir, dir);
+        assert!(lsm.is_poisoned());
+
+        // Every later call fails the same way, without touching the disk
+        for err in [
+            lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap_err(),
+            lsm.flush().unwrap_err(),
+            lsm.purge().unwrap_err(),
+            lsm.clear_poison().unwrap_err(),
+        ] {
+            assert_eq!(crate::TreeDeleted::from_io(&err), Some(deleted));
+        }
+        assert!(!dir.exists());
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_destroy_removes_a_tree_no_writer_has_open() {
+        let dir = PathBuf::from("./test_lib_destroy");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 4096).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        let err = LSMTree::destroy(&dir).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+        drop(lsm);
+
+        let mut reader = LSMTree::open(LSMConfig {
+            read_only: true,
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+        LSMTree::destroy(&dir).unwrap();
+        assert!(!dir.exists());
+
+        // A new tree in the same place isn't mistaken for the old one
+        let replacement = LSMTree::new(dir.clone(), 4096).unwrap();
+        let err = reader.get_opt(b"a", &ReadOptions::default()).unwrap_err();
+        assert!(crate::TreeDeleted::from_io(&err).is_some());
+        drop(replacement);
+        drop(reader);
+
+        // Nor is a directory that isn't a tree deleted
+        fs::remove_dir_all(&dir).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        let err = LSMTree::destroy(&dir).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(dir.exists());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_load_syncs_once_per_chunk_and_resumes_after_a_crash() {
+        let dir = PathBuf::from("./test_lib_load_from_reader");
+        fs::remove_dir_all(&dir).ok();
+
+        // A reader whose source fails after the rows it has
+        struct FailAfter<'a>(&'a [u8]);
+        impl std::io::Read for FailAfter<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(std::io::Error::other("source went away"));
+                }
+                std::io::Read::read(&mut self.0, buf)
+            }
+        }
+
+        let input: String = (0..10).map(|i| format!("key{}\tvalue{}\n", i, i)).collect();
+        let config = LSMConfig {
+            sync_writes: false,
+            ..LSMConfig::new(dir.clone())
+        };
+        let options = LoadOptions {
+            chunk_rows: 4,
+            resume_from: None,
+            progress_key: Some(b"!progress".to_vec()),
+        };
+
+        // The first 7 rows arrive: one full chunk is synced, then the read
+        // fails with 3 rows batched
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        let partial = &input.as_bytes()[..input.find("key7").unwrap()];
+        let err = lsm
+            .load_from_reader(std::io::BufReader::new(FailAfter(partial)), &options)
+            .unwrap_err();
+        let interrupted = crate::LoadInterrupted::from_io(&err).unwrap();
+        assert_eq!(interrupted.durable_rows, 4);
+        assert!(interrupted.cause.contains("source went away"));
+        assert_eq!(lsm.wal_stats().fsyncs, 1);
+
+        // After a crash the progress key says where to pick up
+        lsm.simulate_crash();
+        let mut lsm = LSMTree::open(config).unwrap();
+        assert_eq!(lsm.get(b"key3"), Some(b"value3".to_vec()));
+        assert_eq!(lsm.get(b"key4"), None);
+        let fsyncs = lsm.wal_stats().fsyncs;
+        let report = lsm.load_from_reader(input.as_bytes(), &options).unwrap();
+        assert_eq!(
+            report,
+            LoadReport {
+                rows_read: 10,
+                rows_skipped: 4,
+                rows_loaded: 6,
+                chunks: 2,
+                durable_rows: 10,
+            }
+        );
+        assert_eq!(lsm.wal_stats().fsyncs - fsyncs, 2);
+        for i in 0..10 {
+            let key = format!("key{}", i);
+            assert_eq!(
+                lsm.get(key.as_bytes()),
+                Some(format!("value{}", i).into_bytes())
+            );
+        }
+        assert_eq!(lsm.get(b"!progress"), Some(10u64.to_be_bytes().to_vec()));
+
+        // A row without a tab stops the load, with what came before durable
+        let err = lsm
+            .load_from_reader(
+                &b"x\t1\nno tab\n"[..],
+                &LoadOptions {
+                    chunk_rows: 1,
+                    ..LoadOptions::default()
+                },
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            crate::LoadInterrupted::from_io(&err).unwrap().durable_rows,
+            1
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_recovery_estimate_counts_the_wal_until_a_flush() {
+        let dir = PathBuf::from("./test_lib_recovery_estimate");
+        fs::remove_dir_all(&dir).ok();
+
+        let listener = testing::RecordingListener::new();
+        let config = LSMConfig {
+            event_listener: std::sync::Arc::new(listener.clone()),
+            recovery_warn_threshold: Some(std::time::Duration::from_nanos(1)),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        assert_eq!(lsm.recovery_estimate().wal_entries, 0);
+        for i in 0..20u32 {
+            lsm.put(i.to_be_bytes().to_vec(), b"value".to_vec())
+                .unwrap();
+        }
+        let estimate = lsm.recovery_estimate();
+        assert_eq!(estimate.wal_entries, 20);
+        assert_eq!(estimate.wal_bytes, lsm.wal_stats().file_size);
+        assert!(estimate.replay_time > std::time::Duration::ZERO);
+        assert!(!estimate.rate_measured);
+        // Once per crossing, not on every write above the threshold
+        assert_eq!(listener.recovery_estimates_high().len(), 1);
+
+        // Entries a restart replayed are still in the log until a flush
+        lsm.simulate_crash();
+        let mut lsm = LSMTree::open(config).unwrap();
+        lsm.put(b"next".to_vec(), b"value".to_vec()).unwrap();
+        assert_eq!(lsm.recovery_estimate().wal_entries, 21);
+        assert_eq!(listener.recovery_estimates_high().len(), 2);
+
+        lsm.flush().unwrap();
+        let estimate = lsm.recovery_estimate();
+        assert_eq!(estimate.wal_entries, 0);
+        assert_eq!(estimate.wal_bytes, 0);
+        assert_eq!(estimate.replay_time, std::time::Duration::ZERO);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_set_option_applies_to_later_writes() {
+        let dir = PathBuf::from("./test_lib_set_option");
+        fs::remove_dir_all(&dir).ok();
+
+        let listener = testing::RecordingListener::new();
+        let mut lsm = LSMTree::open(LSMConfig {
+            event_listener: std::sync::Arc::new(listener.clone()),
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+        for i in 0..10u32 {
+            lsm.put(i.to_be_bytes().to_vec(), b"value".to_vec())
+                .unwrap();
+        }
+        assert_eq!(lsm.sstable_count(), 0);
+
+        // Lowering the threshold below the memtable doesn't flush by itself
+        let threshold = lsm.memtable_size() / 2;
+        lsm.set_option(TreeOption::MemtableSizeThreshold(threshold))
+            .unwrap();
+        assert_eq!(lsm.sstable_count(), 0);
+        assert_eq!(lsm.current_options().memtable_size_threshold, threshold);
+        lsm.put(b"next".to_vec(), b"value".to_vec()).unwrap();
+        assert_eq!(lsm.sstable_count(), 1);
+
+        // Invalid values are rejected and leave the setting as it was
+        for fpp in [0.0, 1.0, 1.5, f64::NAN] {
+            let Err(e) = lsm.set_option(TreeOption::BloomFilterFpp(fpp)) else {
+                panic!("false positive rate {} was accepted", fpp);
+            };
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        assert!(
+            lsm.set_option(TreeOption::MemtableSizeThreshold(0))
+                .is_err()
+        );
+        assert_eq!(
+            lsm.current_options().bloom_filter_fpp,
+            config::DEFAULT_BLOOM_FILTER_FPP
+        );
+
+        let changes = listener.option_changes();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes[0].previous,
+            TreeOption::MemtableSizeThreshold(LSMConfig::new(dir.clone()).memtable_size_threshold)
+        );
+        assert_eq!(
+            changes[0].current,
+            TreeOption::MemtableSizeThreshold(threshold)
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_disk_usage_accounts_for_every_file() {
+        let dir = PathBuf::from("./test_lib_disk_usage");
+        fs::remove_dir_all(&dir).ok();
+
+        fn walk(dir: &Path) -> u64 {
+            fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    let meta = entry.metadata().unwrap();
+                    if meta.is_dir() {
+                        walk(&entry.path())
+                    } else {
+                        meta.len()
+                    }
+                })
+                .sum()
+        }
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+        for i in 0..200u32 {
+            lsm.put(i.to_be_bytes().to_vec(), vec![b'v'; 32]).unwrap();
+        }
+        lsm.delete(7u32.to_be_bytes().to_vec()).unwrap();
+        let usage = lsm.disk_usage().unwrap();
+        assert!(lsm.sstable_count() > 0);
+        assert!(usage.sstable_bytes > 0 && usage.bloom_bytes > 0);
+        assert!(usage.wal_bytes > 0 && usage.manifest_bytes > 0);
+        assert_eq!(usage.tmp_or_orphan_bytes, 0);
+        assert_eq!(usage.suggestion(), None);
+        assert_eq!(usage.total, walk(&dir));
+        assert_eq!(
+            usage.total,
+            usage.sstable_bytes
+                + usage.bloom_bytes
+                + usage.wal_bytes
+                + usage.manifest_bytes
+                + usage.tmp_or_orphan_bytes
+        );
+
+        // Leftovers count as orphans and ask for a cleanup
+        fs::write(dir.join("MANIFEST.tmp"), b"partial").unwrap();
+        fs::create_dir_all(dir.join("stray")).unwrap();
+        fs::write(dir.join("stray").join("notes.txt"), b"hello").unwrap();
+        let usage = lsm.disk_usage().unwrap();
+        assert_eq!(usage.tmp_or_orphan_bytes, 12);
+        assert_eq!(usage.orphan_files.len(), 2);
+        assert_eq!(usage.total, walk(&dir));
+        assert!(usage.suggestion().is_some());
+        assert!(usage.to_string().contains("suggestion:"));
+        assert!(
+            lsm.prometheus_metrics()
+                .contains("lsm_disk_bytes{kind=\"orphan\"} 12\n")
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_close_flushes_memory_into_one_sstable() {
+        let dir = PathBuf::from("./test_lib_close");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"a".to_vec(), b"flushed".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        // Pins keep three versions of "a" and two of "b" in memory
+        for round in 1..=3 {
+            lsm.pin_sequence();
+            lsm.put(b"a".to_vec(), format!("a{}", round).into_bytes())
+                .unwrap();
+            lsm.put(b"b".to_vec(), format!("b{}", round).into_bytes())
+                .unwrap();
+        }
+        lsm.delete(b"b".to_vec()).unwrap();
+        lsm.put(b"c".to_vec(), b"c1".to_vec()).unwrap();
+
+        let result = lsm.close().unwrap();
+        assert_eq!(result.sstable, Some(SSTableId(1)));
+        // Only the newest version of each key: a3, the tombstone of b, c1
+        assert_eq!(result.entries_written, 3);
+        assert!(result.bytes_written > 0);
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.sstable_count(), 2);
+        assert_eq!(lsm.wal_stats().file_size, 0);
+        assert_eq!(lsm.get(b"a"), Some(b"a3".to_vec()));
+        assert_eq!(lsm.get(b"b"), None);
+        assert_eq!(lsm.get(b"c"), Some(b"c1".to_vec()));
+        // Nothing left to write
+        assert_eq!(lsm.close().unwrap(), FlushResult::default());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_iter_versions_yields_every_stored_version() {
+        let dir = PathBuf::from("./test_lib_iter_versions");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"a".to_vec(), b"before".to_vec()).unwrap();
+        lsm.put(b"k".to_vec(), b"v1".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(b"k".to_vec(), b"v2".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(b"k".to_vec(), b"v3".to_vec()).unwrap();
+        // The pin keeps v3 in the memtable next to the tombstone
+        let pin = lsm.pin_sequence();
+        lsm.delete(b"k".to_vec()).unwrap();
+        lsm.put(b"z".to_vec(), b"after".to_vec()).unwrap();
+
+        let old = ValueSource::SSTable {
+            id: SSTableId(0),
+            generation: 1,
+        };
+        let new = ValueSource::SSTable {
+            id: SSTableId(1),
+            generation: 2,
+        };
+        let versions: Vec<_> = lsm.iter_versions("k".."l").collect();
+        let summary: Vec<_> = versions
+            .iter()
+            .map(|v| (v.sequence, v.source, v.is_tombstone(), v.value.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (5, ValueSource::Memtable, true, None),
+                (4, ValueSource::Memtable, false, Some(b"v3".to_vec())),
+                (3, new, false, Some(b"v2".to_vec())),
+                (2, old, false, Some(b"v1".to_vec())),
+            ]
+        );
+        assert!(versions.iter().all(|v| v.key == b"k"));
+
+        // Key order first, across every key
+        let keys: Vec<_> = lsm.iter_versions::<&[u8], _>(..).map(|v| v.key).collect();
+        assert_eq!(keys, [&b"a"[..], b"k", b"k", b"k", b"k", b"z"]);
+
+        // A purge leaves only the newest live versions
+        lsm.release_sequence(pin);
+        lsm.purge().unwrap();
+        let keys: Vec<_> = lsm.iter_versions::<&[u8], _>(..).map(|v| v.key).collect();
+        assert_eq!(keys, [&b"a"[..], b"z"]);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_open_failures_name_operation_and_path() {
+        let base = PathBuf::from("./test_lib_open_errors");
+        fs::remove_dir_all(&base).ok();
+        fs::create_dir_all(&base).unwrap();
+
+        // A data directory under a regular file can't be created
+        let file = base.join("not_a_dir");
+        fs::write(&file, b"file").unwrap();
+        let data_dir = file.join("data");
+        let Err(e) = LSMTree::new(data_dir.clone(), 1024) else {
+            panic!("opened a tree under a file");
+        };
+        let err = LsmError::from_io(&e).expect("error carries context");
+        assert_eq!(err.operation, Operation::CreateDir);
+        assert_eq!(err.path, data_dir);
+        #[cfg(unix)]
+        assert_eq!(e.kind(), std::io::ErrorKind::NotADirectory);
+        assert!(e.to_string().starts_with("create_dir "));
+
+        // A WAL directory that can't be written to
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let wal_dir = base.join("wal");
+            fs::create_dir_all(&wal_dir).unwrap();
+            fs::set_permissions(&wal_dir, fs::Permissions::from_mode(0o555)).unwrap();
+            // Permissions don't stop root; nothing to check then
+            let probe = wal_dir.join("probe");
+            if fs::write(&probe, b"").is_err() {
+                let config = LSMConfig {
+                    wal_dir: Some(wal_dir.clone()),
+                    ..LSMConfig::new(base.join("data"))
+                };
+                let Err(e) = LSMTree::open(config) else {
+                    panic!("opened a WAL in a read-only directory");
+                };
+                let err = LsmError::from_io(&e).expect("error carries context");
+                assert_eq!(err.operation, Operation::OpenWal);
+                assert_eq!(err.path, wal_dir.join("wal.log"));
+                assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+            }
+            fs::set_permissions(&wal_dir, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        // A directory locked by another writer
+        let data_dir = base.join("locked");
+        let lsm = LSMTree::new(data_dir.clone(), 1024).unwrap();
+        let Err(e) = LSMTree::new(data_dir.clone(), 1024) else {
+            panic!("opened a locked directory");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+        let err = LsmError::from_io(&e).expect("error carries context");
+        assert_eq!(err.operation, Operation::LockDir);
+        drop(lsm);
+
+        fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn test_prefix_stats_counts_keys_per_prefix() {
+        let dir = PathBuf::from("./test_lib_prefix_stats");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024).unwrap();
+        for i in 0..120 {
+            lsm.put(format!("user:{:03}", i).into_bytes(), vec![b'u'; 20])
+                .unwrap();
+        }
+        for i in 0..45 {
+            lsm.put(format!("order:{:03}:item", i).into_bytes(), vec![b'o'; 20])
+                .unwrap();
+        }
+        for i in 0..7 {
+            lsm.put(format!("session:{}", i).into_bytes(), vec![b's'; 20])
+                .unwrap();
+        }
+        // Deleted keys and overwrites don't count
+        lsm.delete(b"user:000".to_vec()).unwrap();
+        lsm.put(b"user:001".to_vec(), b"again".to_vec()).unwrap();
+        assert!(lsm.sstable_count() > 0);
+
+        let report = lsm.prefix_stats(b':', 1);
+        let counts: Vec<_> = report
+            .prefixes
+            .iter()
+            .map(|p| (p.prefix.as_slice(), p.keys))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                (b"order:".as_slice(), 45),
+                (b"session:".as_slice(), 7),
+                (b"user:".as_slice(), 119)
+            ]
+        );
+        assert_eq!(report.prefixes[2].key_bytes, 119 * 8);
+        assert!(report.prefixes[2].approx_bytes > report.prefixes[2].key_bytes);
+        assert_eq!(report.largest()[0].prefix, b"user:");
+        assert_eq!(lsm.prefix_stats(b':', 2).prefixes.len(), 45 + 2);
+
+        let sampled = lsm.prefix_stats_sampled(b':', 1, 0.1).unwrap();
+        assert_eq!(sampled.scanned_keys, 171);
+        assert_eq!(sampled.sampled_keys, 18);
+        assert!(lsm.prefix_stats_sampled(b':', 1, 0.0).is_err());
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_clone_to_forks_independent_tree() {
+        let dir = PathBuf::from("./test_lib_clone_source");
+        let fork_dir = PathBuf::from("./test_lib_clone_target");
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&fork_dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        // Still in the memtable; the clone flushes it first
+        lsm.put(b"c".to_vec(), b"3".to_vec()).unwrap();
+
+        let mut fork = lsm.clone_to(&fork_dir).unwrap();
+        assert_eq!(fork.sstable_count(), 3);
+        assert_eq!(fork.get(b"c"), Some(b"3".to_vec()));
+        assert_eq!(fork.wal_stats().file_size, 0);
+        let Err(e) = lsm.clone_to(&fork_dir) else {
+            panic!("cloned into a non-empty directory");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            for name in ["sstable_0.db", "sstable_0.bloom", "sstable_2.db"] {
+                let ino = |d: &Path| fs::metadata(d.join(name)).unwrap().ino();
+                assert_eq!(ino(&dir), ino(&fork_dir), "{} isn't shared", name);
+            }
+        }
+
+        // Diverge: writes, deletes and a purge on each side
+        lsm.put(b"a".to_vec(), b"source".to_vec()).unwrap();
+        lsm.delete(b"b".to_vec()).unwrap();
+        lsm.purge().unwrap();
+        fork.put(b"d".to_vec(), b"fork".to_vec()).unwrap();
+        fork.flush().unwrap();
+        fork.purge().unwrap();
+
+        assert_eq!(lsm.get(b"a"), Some(b"source".to_vec()));
+        assert_eq!(lsm.get(b"b"), None);
+        assert_eq!(lsm.get(b"d"), None);
+        assert_eq!(fork.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(fork.get(b"b"), Some(b"2".to_vec()));
+        assert_eq!(fork.get(b"d"), Some(b"fork".to_vec()));
+        drop(lsm);
+        drop(fork);
+
+        // Both reopen with their own data
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        let mut fork = LSMTree::new(fork_dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get(b"c"), Some(b"3".to_vec()));
+        assert_eq!(fork.get(b"c"), Some(b"3".to_vec()));
+        assert_eq!(fork.get(b"a"), Some(b"1".to_vec()));
+        drop(lsm);
+        drop(fork);
+
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&fork_dir).ok();
+    }
+
+    #[test]
+    fn test_ttl_deletes_fully_expired_tables() {
+        let dir = PathBuf::from("./test_lib_ttl_expiry");
+        fs::remove_dir_all(&dir).ok();
+
+        let clock = testing::ManualClock::new(1_000);
+        let config = LSMConfig {
+            ttl: Some(std::time::Duration::from_secs(60)),
+            clock: Arc::new(clock.clone()),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        clock.advance(std::time::Duration::from_secs(40));
+        lsm.put(b"c".to_vec(), b"3".to_vec()).unwrap();
+        clock.advance(std::time::Duration::from_secs(10));
+        lsm.put(b"d".to_vec(), b"4".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        assert_eq!(lsm.expired_estimate().expired_writes, 0);
+        assert!(!lsm.maintenance_tick().unwrap());
+
+        // Only the first table's writes are older than the TTL
+        clock.advance(std::time::Duration::from_secs(20));
+        let estimate = lsm.expired_estimate();
+        assert_eq!(estimate.fully_expired, vec![SSTableId(0)]);
+        assert!(estimate.mostly_expired.is_empty());
+        assert_eq!((estimate.expired_writes, estimate.timed_writes), (2, 4));
+        assert!(!lsm.needs_compaction());
+
+        assert!(lsm.maintenance_tick().unwrap());
+        assert!(!dir.join("sstable_0.db").exists());
+        assert!(!dir.join("sstable_0.bloom").exists());
+        assert_eq!(lsm.sstable_count(), 1);
+        assert_eq!(lsm.get(b"a"), None);
+        assert_eq!(lsm.get(b"b"), None);
+        assert_eq!(lsm.get(b"c"), Some(b"3".to_vec()));
+        assert!(!lsm.maintenance_tick().unwrap());
+
+        // Half of the remaining table expires: a compaction candidate, and
+        // compaction drops expired entries
+        lsm.put(b"c".to_vec(), b"5".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        clock.advance(std::time::Duration::from_secs(35));
+        assert_eq!(lsm.expired_estimate().mostly_expired, vec![SSTableId(1)]);
+        assert!(lsm.needs_compaction());
+        clock.advance(std::time::Duration::from_secs(10));
+        lsm.purge().unwrap();
+        assert_eq!(lsm.get(b"c"), Some(b"5".to_vec()));
+        assert_eq!(lsm.get(b"d"), None);
+        assert!(!lsm.needs_compaction());
+
+        // Writes recovered from the WAL have no known time, so their table
+        // never expires whole
+        lsm.put(b"e".to_vec(), b"6".to_vec()).unwrap();
+        lsm.simulate_crash();
+        let mut lsm = LSMTree::open(config).unwrap();
+        lsm.flush().unwrap();
+        clock.advance(std::time::Duration::from_secs(3_600));
+        assert!(lsm.expired_estimate().fully_expired.len() < lsm.sstable_count());
+        lsm.maintenance_tick().unwrap();
+        assert_eq!(lsm.get(b"e"), Some(b"6".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_deleted_keys_make_older_table_top_candidate() {
+        let dir = PathBuf::from("./test_lib_garbage_ratio");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1 << 20).unwrap();
+        for i in 0..100u32 {
+            lsm.put(i.to_be_bytes().to_vec(), b"value".to_vec())
+                .unwrap();
+        }
+        lsm.flush().unwrap();
+        lsm.put(b"other".to_vec(), b"value".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        assert!(!lsm.needs_compaction());
+
+        for i in (0..100u32).step_by(2) {
+            lsm.delete(i.to_be_bytes().to_vec()).unwrap();
+        }
+        lsm.flush().unwrap();
+
+        // The flush of `other` may have hit a false positive in the larger
+        // table's filter, which is trusted without reading the table
+        let infos = lsm.sstable_infos();
+        assert_eq!(infos[0].stats.tombstones, 50);
+        assert_eq!(infos[0].stats.garbage_ratio(), 0.0);
+        assert_eq!(infos[2].stats.entries, 100);
+        assert!((50..=51).contains(&infos[2].stats.garbage()));
+
+        let candidates = lsm.compaction_candidates();
+        assert_eq!(candidates[0].sstable, SSTableId(0));
+        assert_eq!(candidates[0].garbage, infos[2].stats.garbage());
+        assert!(lsm.needs_compaction());
+        assert_eq!(lsm.advisor_input().high_garbage_tables, 1);
+
+        // The estimate is kept in the manifest
+        drop(lsm);
+        let mut lsm = LSMTree::new(dir.clone(), 1 << 20).unwrap();
+        assert_eq!(
+            lsm.compaction_candidates()[0].garbage,
+            infos[2].stats.garbage()
+        );
+
+        lsm.purge().unwrap();
+        assert_eq!(lsm.sstable_infos()[0].stats.entries, 51);
+        assert!(!lsm.needs_compaction());
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_tree_summary_follows_workload() {
+        let dir = PathBuf::from("./test_lib_tree_summary");
+        fs::remove_dir_all(&dir).ok();
+
+        let clock = testing::ManualClock::new(1_000_000);
+        let mut lsm = LSMTree::open(LSMConfig {
+            memtable_size_threshold: 4096,
+            ttl: Some(std::time::Duration::from_secs(3_600)),
+            clock: Arc::new(clock.clone()),
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+        let opened = clock.now();
+        let summary = lsm.tree_summary();
+        assert_eq!((summary.memtable_entries, summary.sstable_count), (0, 0));
+        assert_eq!(summary.last_flush, opened);
+        assert_eq!(summary.last_compaction, None);
+        assert!(summary.to_string().contains("last compaction: never"));
+
+        for key in [b"a", b"b", b"c"] {
+            lsm.put(key.to_vec(), b"value".to_vec()).unwrap();
+        }
+        clock.advance(std::time::Duration::from_secs(10));
+        lsm.purge().unwrap();
+        let compacted = clock.now();
+        clock.advance(std::time::Duration::from_secs(5));
+        lsm.put(b"d".to_vec(), b"value".to_vec()).unwrap();
+        lsm.put(b"e".to_vec(), b"value".to_vec()).unwrap();
+        lsm.delete(b"a".to_vec()).unwrap();
+
+        let summary = lsm.tree_summary();
+        assert_eq!(summary.data_dir, dir);
+        assert_eq!(summary.memtable_entries, 3);
+        assert_eq!(summary.memtable_bytes, lsm.memtable_size());
+        assert_eq!(summary.memtable_threshold, 4096);
+        assert_eq!(summary.sstable_count, 1);
+        assert_eq!(
+            summary.sstable_bytes,
+            fs::metadata(dir.join("sstable_1.db")).unwrap().len()
+        );
+        assert_eq!(summary.wal_bytes, lsm.wal_stats().file_size);
+        assert!(summary.wal_bytes > 0);
+        assert_eq!(summary.bloom.num_filters, 1);
+        assert_eq!(summary.last_flush, compacted);
+        assert_eq!(summary.last_compaction, Some(compacted));
+        assert_eq!(summary.ttl, Some(std::time::Duration::from_secs(3_600)));
+
+        let text = format!("{:?}", lsm);
+        assert_eq!(text, summary.to_string());
+        for line in [
+            "memtable: 3 entries",
+            "sstables: 1 tables",
+            "bloom: 1 filters",
+            "last flush: 5.0s ago; last compaction: 5.0s ago",
+            "ttl 3600s",
+        ] {
+            assert!(text.contains(line), "{:?} missing from {}", line, text);
+        }
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_entry_count_policy_flushes_on_hundredth_put() {
+        let dir = PathBuf::from("./test_lib_entry_count_policy");
+        fs::remove_dir_all(&dir).ok();
+
+        let config = LSMConfig {
+            // Ignored once a policy is set
+            memtable_size_threshold: 1,
+            flush_policy: Some(Arc::new(flush_policy::EntryCountPolicy::new(100))),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config).unwrap();
+        for i in 0..99u32 {
+            lsm.put(i.to_be_bytes().to_vec(), vec![b'v'; 4096]).unwrap();
+        }
+        assert_eq!(lsm.sstable_count(), 0);
+        assert_eq!(lsm.len(), 99);
+
+        lsm.put(99u32.to_be_bytes().to_vec(), b"v".to_vec())
+            .unwrap();
+        assert_eq!(lsm.sstable_count(), 1);
+        assert_eq!(lsm.len(), 0);
+        assert_eq!(lsm.get(&0u32.to_be_bytes()), Some(vec![b'v'; 4096]));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_operations_populate_latency_histograms() {
+        let dir = PathBuf::from("./test_lib_latency");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        for i in 0..20u8 {
+            lsm.put(vec![i], vec![i; 10]).unwrap();
+        }
+        lsm.delete(vec![0]).unwrap();
+        lsm.flush().unwrap();
+        for i in 0..20u8 {
+            lsm.get(&[i]);
+        }
+        lsm.get_immut(b"missing");
+
+        let puts = lsm.latency_percentiles(LatencyOp::Put);
+        assert_eq!(puts.count, 21);
+        assert!(puts.max > std::time::Duration::ZERO);
+        assert!(puts.p50 <= puts.p99 && puts.p99 <= puts.max);
+        assert_eq!(lsm.latency_percentiles(LatencyOp::Get).count, 21);
+        assert_eq!(lsm.latency_percentiles(LatencyOp::Flush).count, 1);
+        assert_eq!(lsm.latency_percentiles(LatencyOp::Compaction).count, 0);
+
+        let metrics = lsm.prometheus_metrics();
+        assert!(metrics.contains("lsm_sstables 1\n"));
+        assert!(metrics.contains("lsm_operation_latency_seconds_count{op=\"get\"} 21\n"));
+        assert!(metrics.contains("lsm_operation_latency_seconds{op=\"put\",quantile=\"0.99\"}"));
+
+        lsm.reset_latency_stats();
+        assert_eq!(
+            lsm.latency_percentiles(LatencyOp::Put),
+            LatencyPercentiles::default()
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_io_rates_follow_the_clock() {
+        let dir = PathBuf::from("./test_lib_io_rates");
+        fs::remove_dir_all(&dir).ok();
+
+        let clock = testing::ManualClock::new(1_000_000);
+        let mut lsm = LSMTree::open(LSMConfig {
+            clock: Arc::new(clock.clone()),
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+        assert_eq!(lsm.io_rates(), IoRates::default());
+
+        // 32 bytes per entry: op, sequence, two lengths, key, value,
+        // checksum
+        for i in 0..20u8 {
+            lsm.put(vec![i], vec![i; 10]).unwrap();
+        }
+        let rates = lsm.io_rates();
+        assert_eq!(rates.wal_write, 20.0 * 32.0);
+        assert_eq!(rates.sstable_write, 0.0);
+
+        lsm.flush().unwrap();
+        let written = lsm.amplification_stats().sstable_bytes_written;
+        assert_eq!(lsm.io_rates().sstable_write, written as f64);
+        assert_eq!(lsm.get(&[19]), Some(vec![19; 10]));
+        let read = lsm.io_rates().sstable_read;
+        assert!(read > 0.0 && read <= written as f64, "{}", read);
+
+        // A second later the window is empty again
+        clock.advance(std::time::Duration::from_secs(1));
+        assert_eq!(lsm.io_rates(), IoRates::default());
+        lsm.purge().unwrap();
+        assert_eq!(lsm.io_rates().sstable_read, written as f64);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_many_sstables_trip_read_amp_warning() {
+        let dir = PathBuf::from("./test_lib_read_amp");
+        fs::remove_dir_all(&dir).ok();
+
+        let listener = testing::RecordingListener::new();
+        let mut lsm = LSMTree::open(LSMConfig {
+            read_amp_warn_threshold: Some(8.0),
+            event_listener: std::sync::Arc::new(listener.clone()),
+            sync_on_flush: false,
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+        for i in 0..20u32 {
+            lsm.put(i.to_be_bytes().to_vec(), b"value".to_vec())
+                .unwrap();
+            lsm.flush().unwrap();
+        }
+
+        // A few reads of recent keys don't move the average much
+        lsm.get(&19u32.to_be_bytes());
+        assert!(!lsm.needs_compaction());
+
+        // Old keys sit in the oldest tables, behind every newer one
+        for _ in 0..10 {
+            for i in 0..3u32 {
+                assert!(lsm.get(&i.to_be_bytes()).is_some());
+            }
+        }
+        assert!(lsm.read_amplification() > 8.0);
+        assert!(lsm.needs_compaction());
+        let events = listener.read_amp_high();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].threshold, 8.0);
+        assert_eq!(events[0].sstable_count, 20);
+
+        lsm.purge().unwrap();
+        assert!(!lsm.needs_compaction());
+        for i in 0..3u32 {
+            lsm.get(&i.to_be_bytes());
+        }
+        assert!(lsm.read_amplification() <= 1.0);
+        assert_eq!(listener.read_amp_high().len(), 1);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_small_sstables_are_served_from_memory() {
+        let dir = PathBuf::from("./test_lib_pinned_tables");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        for round in 0..4u8 {
+            for key in 0..10u8 {
+                if key % 4 == round {
+                    lsm.delete(vec![key]).unwrap();
+                } else {
+                    lsm.put(vec![key], vec![round, key]).unwrap();
+                }
+            }
+            lsm.flush().unwrap();
+        }
+        drop(lsm);
+
+        let open = |pin: Option<usize>| {
+            let storage = storage::CountingStorage::new();
+            let config = LSMConfig {
+                pin_small_sstables_bytes: pin,
+                storage: Arc::new(storage.clone()),
+                ..LSMConfig::new(dir.clone())
+            };
+            (LSMTree::open(config).unwrap(), storage)
+        };
+        let lookups = |lsm: &mut LSMTree| {
+            let mut results: Vec<_> = (0..12u8).map(|key| lsm.get(&[key])).collect();
+            results.extend((0..12u8).map(|key| lsm.get_at(&[key], 15)));
+            results
+        };
+
+        let (mut unpinned, storage) = open(None);
+        let expected = lookups(&mut unpinned);
+        assert!(storage.file_opens() > 0);
+        assert_eq!(unpinned.pinned_sstable_stats(), PinnedTableStats::default());
+        drop(unpinned);
+
+        let (mut pinned, storage) = open(Some(1024 * 1024));
+        assert_eq!(pinned.pinned_sstable_stats().tables, 4);
+        assert_eq!(lookups(&mut pinned), expected);
+        assert_eq!(storage.file_opens(), 0);
+        assert!(pinned.pinned_sstable_stats().hits > 0);
+
+        // Compaction replaces the pinned tables with its output
+        pinned.purge().unwrap();
+        let stats = pinned.pinned_sstable_stats();
+        assert_eq!(stats.tables, 1);
+        assert_eq!(stats.bytes, pinned.sstable_bytes_on_disk());
+        assert_eq!(pinned.get(&[1]), Some(vec![3, 1]));
+        assert_eq!(pinned.get(&[3]), None);
+        assert_eq!(storage.file_opens(), 0);
+        drop(pinned);
+
+        // Tables that don't fit the budget are read from disk
+        let (mut pinned, storage) = open(Some(1));
+        assert_eq!(pinned.pinned_sstable_stats().tables, 0);
+        assert_eq!(pinned.get(&[1]), Some(vec![3, 1]));
+        assert_eq!(storage.file_opens(), 1);
+        drop(pinned);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_filter_policy_none_writes_no_filters() {
+        let bloom_files = |dir: &Path| {
+            fs::read_dir(dir)
+                .unwrap()
+                .filter(|e| {
+                    e.as_ref().unwrap().path().extension() == Some(std::ffi::OsStr::new("bloom"))
+                })
+                .count()
+        };
+        let open = |dir: &Path, filter_policy| {
+            LSMTree::open(LSMConfig {
+                filter_policy,
+                memtable_size_threshold: 512,
+                ..LSMConfig::new(dir.to_path_buf())
+            })
+            .unwrap()
+        };
+        let answers = |lsm: &mut LSMTree| {
+            let gets: Vec<Option<Vec<u8>>> = (0..60)
+                .map(|i| lsm.get(format!("key{:02}", i).as_bytes()))
+                .collect();
+            let all: Vec<(Vec<u8>, Vec<u8>)> = lsm.range::<&[u8], _>(..).collect();
+            (
+                gets,
+                all,
+                lsm.get_immut(b"key07"),
+                lsm.count_range("key1".."key3"),
+            )
+        };
+
+        let filtered = PathBuf::from("./test_lib_filter_policy_bloom");
+        let unfiltered = PathBuf::from("./test_lib_filter_policy_none");
+        fs::remove_dir_all(&filtered).ok();
+        fs::remove_dir_all(&unfiltered).ok();
+
+        // The same writes, with automatic flushes, deletes and a purge
+        let mut trees = [
+            open(&filtered, FilterPolicy::Bloom),
+            open(&unfiltered, FilterPolicy::None),
+        ];
+        for lsm in &mut trees {
+            for i in 0..50 {
+                lsm.put(format!("key{:02}", i).into_bytes(), vec![b'v'; 20])
+                    .unwrap();
+            }
+            for i in (0..50).step_by(3) {
+                lsm.delete(format!("key{:02}", i).into_bytes()).unwrap();
+            }
+            lsm.flush().unwrap();
+        }
+        assert!(trees[1].sstable_count() > 1);
+        assert_eq!(bloom_files(&unfiltered), 0);
+        assert!(bloom_files(&filtered) > 0);
+        let expected = answers(&mut trees[0]);
+        assert_eq!(answers(&mut trees[1]), expected);
+
+        // Nothing to report, and nothing divided by zero
+        let stats = trees[1].bloom_filter_stats();
+        assert_eq!(stats.num_filters, 0);
+        assert_eq!(stats.skip_rate(), 0.0);
+        assert_eq!(stats.mean_estimated_fpp(), 0.0);
+        assert!(
+            trees[1]
+                .amplification_stats()
+                .space_amplification()
+                .is_finite()
+        );
+        assert_eq!(
+            trees[1].filterless_sstables().len(),
+            trees[1].sstable_count()
+        );
+
+        for lsm in &mut trees {
+            lsm.purge().unwrap();
+            lsm.put(b"key99".to_vec(), b"after".to_vec()).unwrap();
+            lsm.flush().unwrap();
+        }
+        assert_eq!(bloom_files(&unfiltered), 0);
+        let expected = answers(&mut trees[0]);
+        assert_eq!(answers(&mut trees[1]), expected);
+        let [a, b] = trees;
+        drop(a);
+        drop(b);
+
+        // Mixed directories: tables with filters opened without them, and
+        // filterless tables getting theirs on open with filters
+        let mut lsm = open(&filtered, FilterPolicy::None);
+        lsm.put(b"key00".to_vec(), b"new".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        assert_eq!(lsm.get(b"key00"), Some(b"new".to_vec()));
+        assert_eq!(lsm.get(b"key01"), Some(vec![b'v'; 20]));
+        assert_eq!(lsm.bloom_filter_stats().num_filters, 0);
+        drop(lsm);
+        let mut lsm = open(&filtered, FilterPolicy::Bloom);
+        assert!(lsm.filterless_sstables().is_empty());
+        assert_eq!(bloom_files(&filtered), lsm.sstable_count());
+        assert_eq!(lsm.get(b"key00"), Some(b"new".to_vec()));
+        assert_eq!(lsm.get(b"key03"), None);
+        drop(lsm);
+
+        fs::remove_dir_all(&filtered).ok();
+        fs::remove_dir_all(&unfiltered).ok();
+    }
+
+    #[test]
+    fn test_verify_key_reports_every_copy() {
+        let dir = PathBuf::from("./test_lib_verify_key");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        for round in 1..=3u8 {
+            lsm.put(b"key".to_vec(), vec![b'v', b'0' + round]).unwrap();
+            lsm.put(vec![b'z', round], b"other".to_vec()).unwrap();
+            lsm.flush().unwrap();
+        }
+
+        let report = lsm.verify_key(b"key");
+        assert!(report.is_healthy());
+        let sources: Vec<ValueSource> = report.copies.iter().map(|c| c.source).collect();
+        assert_eq!(
+            sources,
+            [(2, 3), (1, 2), (0, 1)].map(|(id, generation)| ValueSource::SSTable {
+                id: SSTableId(id),
+                generation,
+            })
+        );
+        assert_eq!(
+            report.copies.iter().map(|c| c.sequence).collect::<Vec<_>>(),
+            vec![5, 3, 1]
+        );
+        assert!(
+            report
+                .copies
+                .iter()
+                .all(|c| c.filter_may_contain == Some(true))
+        );
+        let winner = report.winning_copy().unwrap();
+        assert_eq!(report.winner, Some(0));
+        assert_eq!(winner.value, Some(b"v3".to_vec()));
+        assert!(lsm.verify_key(b"missing").copies.is_empty());
+
+        // Flip a byte of the middle copy's value, and give the oldest copy
+        // a value length running past the end of its table. Each key is
+        // the first of its table, so it is stored whole: shared and suffix
+        // lengths, key, sequence, value length, checksum, value
+        let middle = &report.copies[1];
+        let offset = middle.offset.unwrap() + 4 + 4 + 3 + 8 + 4 + 4;
+        let path = lsm.sstable(SSTableId(1)).unwrap().path().to_path_buf();
+        let mut bytes = fs::read(&path).unwrap();
+        assert_eq!(&bytes[offset as usize..offset as usize + 2], b"v2");
+        bytes[offset as usize + 1] ^= 0x01;
+        fs::write(&path, bytes).unwrap();
+        let oldest = &report.copies[2];
+        let offset = oldest.offset.unwrap() + 4 + 4 + 3 + 8;
+        let path = lsm.sstable(SSTableId(0)).unwrap().path().to_path_buf();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[offset as usize..offset as usize + 4].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        let report = lsm.verify_key(b"key");
+        assert!(!report.is_healthy());
+        let damaged: Vec<&KeyCopy> = report.problems().collect();
+        assert_eq!(
+            damaged.iter().map(|c| c.damage.clone()).collect::<Vec<_>>(),
+            vec![
+                Some(Corruption::ChecksumMismatch {
+                    offset: middle.offset.unwrap()
+                }),
+                Some(Corruption::Truncated {
+                    offset: oldest.offset.unwrap()
+                }),
+            ]
+        );
+        assert!(damaged.iter().all(|c| c.value.is_none()));
+        assert_eq!(report.winner, Some(0));
+        assert_eq!(lsm.get(b"key"), Some(b"v3".to_vec()));
+
+        drop(lsm);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_get_with_meta_reports_sequence_time_and_source() {
+        let dir = PathBuf::from("./test_lib_value_meta");
+        fs::remove_dir_all(&dir).ok();
+
+        let clock = testing::ManualClock::new(1_000);
+        let config = LSMConfig {
+            clock: Arc::new(clock.clone()),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config).unwrap();
+        let at = |millis| Some(std::time::UNIX_EPOCH + std::time::Duration::from_millis(millis));
+
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        clock.set(5_000);
+        let mut batch = WriteBatch::new();
+        batch.put(b"b".to_vec(), b"2".to_vec());
+        batch.put(b"c".to_vec(), b"3".to_vec());
+        lsm.write(batch).unwrap();
+
+        let (value, meta) = lsm.get_with_meta(b"c").unwrap();
+        assert_eq!(value, b"3".to_vec());
+        assert_eq!(
+            meta,
+            ValueMeta {
+                sequence: 3,
+                written_at: at(5_000),
+                source: ValueSource::Memtable,
+            }
+        );
+
+        lsm.flush().unwrap();
+        clock.set(9_000);
+        lsm.put(b"a".to_vec(), b"4".to_vec()).unwrap();
+        lsm.delete(b"c".to_vec()).unwrap();
+        lsm.flush().unwrap();
+
+        let first = ValueSource::SSTable {
+            id: SSTableId(0),
+            generation: 1,
+        };
+        let (_, meta) = lsm.get_with_meta(b"b").unwrap();
+        assert_eq!(
+            (meta.sequence, meta.written_at, meta.source),
+            (2, at(5_000), first)
+        );
+        let (value, meta) = lsm.get_with_meta(b"a").unwrap();
+        assert_eq!(value, b"4".to_vec());
+        assert_eq!(meta.sequence, 4);
+        assert_eq!(meta.written_at, at(9_000));
+        assert!(matches!(
+            meta.source,
+            ValueSource::SSTable { generation: 2, .. }
+        ));
+        assert_eq!(lsm.get_with_meta(b"c"), None);
+        assert_eq!(lsm.get_with_meta(b"missing"), None);
+
+        // Times survive reopening and purging
+        drop(lsm);
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get_with_meta(b"b").unwrap().1.written_at, at(5_000));
+        lsm.purge().unwrap();
+        let (_, meta) = lsm.get_with_meta(b"b").unwrap();
+        assert_eq!(meta.written_at, at(5_000));
+        assert!(matches!(
+            meta.source,
+            ValueSource::SSTable { generation: 3, .. }
+        ));
+        assert_eq!(lsm.get_with_meta(b"a").unwrap().1.written_at, at(9_000));
+
+        // Writes recovered from the WAL after a crash have no recorded time
+        lsm.put(b"d".to_vec(), b"5".to_vec()).unwrap();
+        lsm.simulate_crash();
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        let (_, meta) = lsm.get_with_meta(b"d").unwrap();
+        assert_eq!((meta.sequence, meta.written_at), (6, None));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_get_at_reads_historical_sequences() {
+        let dir = PathBuf::from("./test_lib_get_at");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"k".to_vec(), b"v1".to_vec()).unwrap();
+        let s1 = lsm.pin_sequence();
+        lsm.flush().unwrap();
+        lsm.put(b"k".to_vec(), b"v2".to_vec()).unwrap();
+        let s2 = lsm.pin_sequence();
+        lsm.delete(b"k".to_vec()).unwrap();
+        let s3 = lsm.last_sequence();
+
+        let check = |lsm: &LSMTree| {
+            assert_eq!(lsm.get_at(b"k", 0), None);
+            assert_eq!(lsm.get_at(b"k", s1), Some(b"v1".to_vec()));
+            assert_eq!(lsm.get_at(b"k", s2), Some(b"v2".to_vec()));
+            assert_eq!(lsm.get_at(b"k", s3), None);
+            assert_eq!(lsm.get_immut(b"k"), None);
+            assert_eq!(lsm.get_at(b"other", s3), None);
+        };
+        check(&lsm);
+
+        // The pinned version of v2 survives the flush next to the tombstone
+        lsm.flush().unwrap();
+        check(&lsm);
+        assert_eq!(lsm.range::<&[u8], _>(..).count(), 0);
+
+        assert_eq!(lsm.oldest_pinned_sequence(), Some(s1));
+        assert!(lsm.release_sequence(s1));
+        assert!(!lsm.release_sequence(s1));
+        assert!(lsm.release_sequence(s2));
+        assert_eq!(lsm.oldest_pinned_sequence(), None);
+        drop(lsm);
+
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        check(&lsm);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_purge_keeps_the_versions_pins_see() {
+        let dir = PathBuf::from("./test_lib_purge_pinned");
+        fs::remove_dir_all(&dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.put(b"b".to_vec(), b"old".to_vec()).unwrap();
+        lsm.put(b"b".to_vec(), b"1".to_vec()).unwrap();
+        let s1 = lsm.pin_sequence();
+        lsm.flush().unwrap();
+        lsm.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        let s2 = lsm.pin_sequence();
+        lsm.flush().unwrap();
+        lsm.delete(b"a".to_vec()).unwrap();
+        lsm.delete(b"b".to_vec()).unwrap();
+        lsm.flush().unwrap();
+
+        let check = |lsm: &LSMTree| {
+            assert_eq!(lsm.get_at(b"a", s1), Some(b"1".to_vec()));
+            assert_eq!(lsm.get_at(b"a", s2), Some(b"2".to_vec()));
+            assert_eq!(lsm.get_at(b"b", s2), Some(b"1".to_vec()));
+            assert_eq!(lsm.get_immut(b"a"), None);
+            assert_eq!(lsm.range::<&[u8], _>(..).count(), 0);
+        };
+        check(&lsm);
+        lsm.purge().unwrap();
+        check(&lsm);
+        assert_eq!(lsm.sstable_count(), 1);
+        // The overwrite no pin sees is gone, the tombstones above pinned
+        // versions stay
+        let stats = &lsm.sstables[0].stats;
+        assert_eq!((stats.entries, stats.tombstones), (5, 2));
+
+        // Once released, the next purge drops them
+        assert!(lsm.release_sequence(s1));
+        assert!(lsm.release_sequence(s2));
+        lsm.purge().unwrap();
+        assert_eq!(lsm.sstable_count(), 0);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_pinned_filter_survives_budget_eviction() {
+        let dir = PathBuf::from("./test_lib_pin_filter");
+        three_table_tree(&dir);
+        let lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        // Every table holds one key, so all filters have the same size
+        let filter_size = lsm.bloom_filter_stats().individual_stats[0].size_bytes;
+        drop(lsm);
+
+        let config = LSMConfig {
+            bloom_filter_memory_budget: Some(2 * filter_size),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config).unwrap();
+        let states = |lsm: &LSMTree| -> Vec<FilterResidency> {
+            lsm.filter_residency().into_iter().map(|(_, s)| s).collect()
+        };
+        assert_eq!(
+            states(&lsm),
+            [
+                FilterResidency::Resident,
+                FilterResidency::Resident,
+                FilterResidency::OnDisk
+            ]
+        );
+
+        // The oldest table holds `a`; pin its filter
+        lsm.pin_filter(2).unwrap();
+        assert_eq!(states(&lsm)[2], FilterResidency::Pinned);
+
+        // Every lookup loads filters back in and pushes others out
+        for _ in 0..5 {
+            for key in [b"a", b"b", b"c", b"z"] {
+                lsm.get(key);
+                let states = states(&lsm);
+                assert_eq!(states[2], FilterResidency::Pinned);
+                assert_eq!(
+                    states[..2]
+                        .iter()
+                        .filter(|&&s| s == FilterResidency::Resident)
+                        .count(),
+                    1
+                );
+            }
+        }
+        assert_eq!(lsm.get(b"a"), Some(b"value".to_vec()));
+        assert_eq!(lsm.get(b"z"), None);
+
+        // Unpinned, it is the least recently used filter and goes first
+        lsm.unpin_filter(2).unwrap();
+        lsm.get(b"c");
+        lsm.get(b"b");
+        assert_eq!(
+            states(&lsm),
+            [
+                FilterResidency::Resident,
+                FilterResidency::Resident,
+                FilterResidency::OnDisk
+            ]
+        );
+
+        lsm.evict_filter(0).unwrap();
+        assert_eq!(states(&lsm)[0], FilterResidency::OnDisk);
+        assert_eq!(lsm.bloom_filter_stats().num_filters, 1);
+        let err = lsm.pin_filter(3).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_read_options_skip_cache_fill_and_verify_tables() {
+        let dir = PathBuf::from("./test_lib_read_options");
+        three_table_tree(&dir);
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.evict_filter(2).unwrap();
+
+        let no_fill = ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            lsm.get_opt(b"a", &no_fill).unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(lsm.get_opt(b"z", &no_fill).unwrap(), None);
+        // The filter was still used, just not kept
+        assert_eq!(lsm.bloom_filter_stats().num_filters, 2);
+        assert_eq!(lsm.filter_residency()[2].1, FilterResidency::OnDisk);
+
+        // A default read loads it back
+        assert_eq!(lsm.get(b"a"), Some(b"value".to_vec()));
+        assert_eq!(lsm.filter_residency()[2].1, FilterResidency::Resident);
+
+        // Cut the oldest table in the middle of its only record
+        let path = dir.join("sstable_0.db");
+        let len = sstable::records_end(&path);
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 2)
+            .unwrap();
+
+        let verify = ReadOptions {
+            verify_checksums: true,
+            ..ReadOptions::default()
+        };
+        // Newer tables answer before the damaged one is reached
+        assert_eq!(lsm.get_opt(b"c", &verify).unwrap(), Some(b"value".to_vec()));
+        let err = lsm.get_opt(b"a", &verify).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_one_byte_buffers_read_and_write_the_same_tree() {
+        let dir = PathBuf::from("./test_lib_one_byte_buffers");
+        fs::remove_dir_all(&dir).ok();
+        let open = |read_buffer_bytes, write_buffer_bytes| {
+            LSMTree::open(LSMConfig {
+                memtable_size_threshold: 2048,
+                read_buffer_bytes,
+                write_buffer_bytes,
+                ..LSMConfig::new(dir.clone())
+            })
+        };
+        // Zero, or more than the cap, is refused before anything is created
+        for (read, write) in [(0, 1), (1, 0), (config::MAX_IO_BUFFER_BYTES + 1, 1)] {
+            let err = open(read, write).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        assert!(!dir.exists());
+
+        let mut lsm = open(1, 1).unwrap();
+        assert_eq!(lsm.current_options().read_buffer_bytes, 1);
+        assert_eq!(lsm.current_options().write_buffer_bytes, 1);
+        for i in 0..300u32 {
+            let key = format!("key{:04}", i % 200).into_bytes();
+            if i % 7 == 0 {
+                lsm.delete(key).unwrap();
+            } else {
+                lsm.put(key, vec![i as u8; (i % 50) as usize]).unwrap();
+            }
+        }
+        assert!(lsm.sstable_count() > 2);
+        let expected: Vec<_> = lsm.range::<&[u8], _>(..).collect();
+        let mut cursor = lsm.cursor();
+        cursor.seek(b"key0100");
+        assert_eq!(
+            cursor.key(),
+            expected
+                .iter()
+                .find(|(k, _)| k.as_slice() >= b"key0100".as_slice())
+                .map(|(k, _)| k.as_slice())
+        );
+        drop(cursor);
+        lsm.simulate_crash();
+
+        // WAL replay, filter rebuilds and integrity checks on open, a
+        // compaction, then the same data read back with default buffers
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "bloom") {
+                fs::remove_file(path).unwrap();
+            }
+        }
+        let mut lsm = open(1, 1).unwrap();
+        assert_eq!(lsm.range::<&[u8], _>(..).collect::<Vec<_>>(), expected);
+        for (key, value) in &expected {
+            assert_eq!(lsm.get(key).as_ref(), Some(value));
+        }
+        lsm.purge().unwrap();
+        assert_eq!(lsm.range::<&[u8], _>(..).collect::<Vec<_>>(), expected);
+        drop(lsm);
+        let lsm = LSMTree::open(LSMConfig::new(dir.clone())).unwrap();
+        assert_eq!(lsm.range::<&[u8], _>(..).collect::<Vec<_>>(), expected);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_read_budget_gives_up_on_deep_lookups() {
+        let dir = PathBuf::from("./test_lib_read_budget");
+        fs::remove_dir_all(&dir).ok();
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        // The key only in the oldest of ten tables
+        lsm.put(b"deep".to_vec(), b"value".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        for i in 0..9u32 {
+            lsm.put(format!("other{}", i).into_bytes(), b"x".to_vec())
+                .unwrap();
+            lsm.flush().unwrap();
+        }
+
+        let budget = |max| ReadOptions {
+            max_tables_probed: Some(max),
+            ..ReadOptions::default()
+        };
+        let err = lsm.get_opt(b"deep", &budget(3)).unwrap_err();
+        assert_eq!(
+            ReadBudgetExceeded::from_io(&err),
+            Some(&ReadBudgetExceeded { probed: 3 })
+        );
+        // Proving a key absent takes every table too
+        let err = lsm.get_opt(b"missing", &budget(9)).unwrap_err();
+        assert_eq!(ReadBudgetExceeded::from_io(&err).unwrap().probed, 9);
+        assert_eq!(lsm.read_budget_exceeded(), 2);
+        assert!(
+            lsm.prometheus_metrics()
+                .contains("lsm_read_budget_exceeded_total 2")
+        );
+
+        // Keys found within the budget, and budgets covering every table,
+        // answer as usual
+        assert_eq!(
+            lsm.get_opt(b"other8", &budget(1)).unwrap(),
+            Some(b"x".to_vec())
+        );
+        assert_eq!(
+            lsm.get_opt(b"deep", &budget(10)).unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(lsm.get_opt(b"missing", &budget(10)).unwrap(), None);
+        assert_eq!(
+            lsm.get_opt(b"deep", &ReadOptions::default()).unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(lsm.read_budget_exceeded(), 2);
+
+        // The memtable costs nothing
+        lsm.put(b"fresh".to_vec(), b"v".to_vec()).unwrap();
+        assert_eq!(
+            lsm.get_opt(b"fresh", &budget(0)).unwrap(),
+            Some(b"v".to_vec())
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_foreign_sstable_is_refused_unless_ingested() {
+        let dir = PathBuf::from("./test_lib_tree_id");
+        let other_dir = PathBuf::from("./test_lib_tree_id_other");
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&other_dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"a".to_vec(), b"mine".to_vec()).unwrap();
+        lsm.put(b"c".to_vec(), b"mine".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        let tree_id = lsm.tree_id().unwrap();
+        drop(lsm);
+
+        let mut other = LSMTree::new(other_dir.clone(), 1024 * 1024).unwrap();
+        other.put(b"a".to_vec(), b"theirs".to_vec()).unwrap();
+        other.put(b"b".to_vec(), b"theirs".to_vec()).unwrap();
+        other.delete(b"c".to_vec()).unwrap();
+        other.flush().unwrap();
+        let other_id = other.tree_id().unwrap();
+        drop(other);
+        assert_ne!(tree_id, other_id);
+        let foreign = other_dir.join("sstable_0.db");
+        assert_eq!(sstable::tree_id(&foreign).unwrap(), Some(other_id));
+
+        // Copied over a table of the same number, it is refused by name
+        let own = fs::read(dir.join("sstable_0.db")).unwrap();
+        fs::copy(&foreign, dir.join("sstable_0.db")).unwrap();
+        let Err(e) = LSMTree::open(LSMConfig {
+            paranoid_checks: false,
+            ..LSMConfig::new(dir.clone())
+        }) else {
+            panic!("a table of another tree was opened");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        let message = e.to_string();
+        assert!(message.contains(&tree_id.to_string()), "{}", message);
+        assert!(message.contains(&other_id.to_string()), "{}", message);
+
+        fs::write(dir.join("sstable_0.db"), own).unwrap();
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"d".to_vec(), b"mine".to_vec()).unwrap();
+        let id = lsm.ingest_sstable_file(&foreign).unwrap();
+        assert_eq!(
+            lsm.sstable_infos()[0].path,
+            dir.join(format!("sstable_{}.db", id))
+        );
+        assert_eq!(
+            sstable::tree_id(&lsm.sstable_infos()[0].path).unwrap(),
+            Some(tree_id)
+        );
+        assert_eq!(lsm.get(b"a"), Some(b"theirs".to_vec()));
+        assert_eq!(lsm.get(b"b"), Some(b"theirs".to_vec()));
+        assert_eq!(lsm.get(b"c"), None);
+        assert_eq!(lsm.get(b"d"), Some(b"mine".to_vec()));
+        drop(lsm);
+
+        // The adopted table opens like any other
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get(b"a"), Some(b"theirs".to_vec()));
+        assert_eq!(lsm.sstable_count(), 3);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&other_dir).ok();
+    }
+
+    #[test]
+    fn test_public_reader_matches_the_tree_for_every_kind_of_table() {
+        let dir = PathBuf::from("./test_lib_public_reader");
+        let other_dir = PathBuf::from("./test_lib_public_reader_other");
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&other_dir).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        let mut model = std::collections::BTreeMap::new();
+        for round in 0..2u32 {
+            for i in 0..300u32 {
+                let key = format!("user:{:06}", i * 7 % 300).into_bytes();
+                if i % 5 == round {
+                    lsm.delete(key.clone()).unwrap();
+                    model.remove(&key);
+                } else {
+                    let value = format!("v{}-{}", round, i).into_bytes();
+                    lsm.put(key.clone(), value.clone()).unwrap();
+                    model.insert(key, value);
+                }
+            }
+            lsm.flush().unwrap();
+        }
+        lsm.purge().unwrap();
+        assert_eq!(lsm.sstable_count(), 1);
+        let compacted = lsm.sstables[0].path.clone();
+        lsm.put(b"user:999999".to_vec(), b"late".to_vec()).unwrap();
+        lsm.delete(b"user:000001".to_vec()).unwrap();
+        lsm.flush().unwrap();
+
+        let mut other = LSMTree::new(other_dir.clone(), 1024 * 1024).unwrap();
+        other.put(b"zz:a".to_vec(), b"theirs".to_vec()).unwrap();
+        other.delete(b"zz:b".to_vec()).unwrap();
+        other.flush().unwrap();
+        drop(other);
+        lsm.ingest_sstable_file(&other_dir.join("sstable_0.db"))
+            .unwrap();
+        assert_eq!(lsm.sstable_count(), 3);
+
+        let mut reader = SSTableReader::open(&compacted).unwrap();
+        let entries = reader
+            .live_entries()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(entries, model.into_iter().collect::<Vec<_>>());
+
+        for (index, table) in lsm.sstables.iter().enumerate() {
+            let mut reader = SSTableReader::open(&table.path).unwrap();
+            let metadata = reader.metadata().unwrap();
+            assert_eq!(metadata.format_version, sstable::CHECKSUM_FORMAT_VERSION);
+            assert_eq!(metadata.tree_id, lsm.tree_id());
+            assert_eq!(
+                metadata.file_bytes,
+                fs::metadata(&table.path).unwrap().len()
+            );
+            let records = reader.iter().collect::<std::io::Result<Vec<_>>>().unwrap();
+            assert_eq!(records.len() as u64, metadata.summary.entries);
+
+            let entries = reader
+                .live_entries()
+                .collect::<std::io::Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(Some(&entries), lsm.read_sstable_entries(index).as_ref());
+            // Lookups start over whatever was read before
+            for (key, value) in entries.iter().rev() {
+                assert_eq!(reader.get(key), Some(Some(value.clone())));
+            }
+        }
+
+        // Verification on open catches a damaged copy that opens fine
+        // without it
+        let damaged = dir.join("damaged.db");
+        let bytes = fs::read(&compacted).unwrap();
+        fs::write(&damaged, &bytes[..bytes.len() / 2]).unwrap();
+        SSTableReader::open(&damaged).unwrap();
+        let options = sstable::SSTableReaderOptions {
+            verify_checksums: true,
+            ..sstable::SSTableReaderOptions::default()
+        };
+        SSTableReader::open_with_options(&compacted, &options).unwrap();
+        let Err(e) = SSTableReader::open_with_options(&damaged, &options) else {
+            panic!("a damaged table passed verification");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("Corruption in SSTable"), "{}", e);
+
+        drop(lsm);
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&other_dir).ok();
+    }
+
+    #[test]
+    fn test_apply_wal_file_from_a_dead_instance() {
+        let dir = PathBuf::from("./test_lib_apply_wal");
+        let dead_dir = PathBuf::from("./test_lib_apply_wal_dead");
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&dead_dir).ok();
+
+        // A tree that dies before flushing leaves only its log
+        let key = |i: u32| format!("k{:02}", i).into_bytes();
+        let mut dead = LSMTree::new(dead_dir.clone(), 1024 * 1024).unwrap();
+        for i in 0..50u32 {
+            dead.put(key(i), format!("v{}", i).into_bytes()).unwrap();
+        }
+        dead.put(key(0), b"newer".to_vec()).unwrap();
+        dead.delete(key(1)).unwrap();
+        dead.simulate_crash();
+        let wal = dead_dir.join("wal.log");
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        let report = lsm.apply_wal_file(&wal).unwrap();
+        assert_eq!(
+            report,
+            ApplyReport {
+                applied: 52,
+                ..ApplyReport::default()
+            }
+        );
+        lsm.simulate_crash();
+        // Applied as the tree's own writes: logged, so they outlive a crash
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get(&key(0)), Some(b"newer".to_vec()));
+        assert_eq!(lsm.get(&key(1)), None);
+        assert_eq!(lsm.get(&key(49)), Some(b"v49".to_vec()));
+
+        // Applied again, the first versions of k00 and k01 are older than
+        // the ones applied after them, and k02 was rewritten here since
+        lsm.put(key(2), b"mine".to_vec()).unwrap();
+        let report = lsm.apply_wal_file(&wal).unwrap();
+        assert_eq!((report.applied, report.conflicted), (49, 3));
+        assert_eq!(lsm.get(&key(2)), Some(b"mine".to_vec()));
+        drop(lsm);
+        fs::remove_dir_all(&dir).ok();
+
+        // Values over the tree's limit are skipped: v10 and up, and "newer"
+        let mut lsm = LSMTree::open(LSMConfig {
+            size_limits: SizeLimits {
+                max_key_size: 16,
+                max_value_size: 2,
+            },
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+        let report = lsm.apply_wal_file(&wal).unwrap();
+        assert_eq!((report.applied, report.skipped), (11, 41));
+        assert_eq!(lsm.get(&key(0)), Some(b"v0".to_vec()));
+        drop(lsm);
+        fs::remove_dir_all(&dir).ok();
+
+        // A flipped byte in the value of the 11th entry (the first ten take
+        // 26 bytes each) fails its checksum and ends the replay there
+        let mut bytes = fs::read(&wal).unwrap();
+        let file_len = bytes.len() as u64;
+        let value_at = 260 + 1 + 8 + 4 + 3 + 4;
+        assert_eq!(&bytes[value_at..value_at + 3], b"v10");
+        bytes[value_at + 2] ^= 0x01;
+        fs::write(&wal, bytes).unwrap();
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        let report = lsm.apply_wal_file(&wal).unwrap();
+        assert_eq!(report.applied, 10);
+        assert_eq!(report.dropped_bytes, file_len - 260);
+        let corruption = report.corruption.clone().unwrap();
+        assert!(corruption.contains("offset 260"), "{}", corruption);
+        assert!(corruption.contains("checksum"), "{}", corruption);
+        assert_eq!(lsm.get(&key(9)), Some(b"v9".to_vec()));
+        assert_eq!(lsm.get(&key(10)), None);
+        assert!(
+            lsm.apply_wal_file(&dead_dir.join("missing.log"))
+                .is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+        );
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&dead_dir).ok();
+    }
+
+    #[test]
+    fn test_from_iter_writes_sorted_tables_without_wal() {
+        let dir = PathBuf::from("./test_lib_from_iter");
+        fs::remove_dir_all(&dir).ok();
+
+        let key = |i: u32| format!("key{:05}", i).into_bytes();
+        // Every key once in a scrambled order, then the first 100 again
+        // with new values, which have to win
+        let pairs = (0..10_000u32)
+            .map(|i| (i * 7919) % 10_000)
+            .map(|i| (key(i), format!("old{}", i).into_bytes()))
+            .chain((0..100).map(|i| (key(i), format!("new{}", i).into_bytes())));
+        let config = LSMConfig {
+            memtable_size_threshold: 16 * 1024,
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::from_iter(config.clone(), pairs).unwrap();
+
+        assert!(lsm.sstable_count() > 1);
+        assert_eq!(lsm.len(), 0);
+        assert_eq!(fs::metadata(dir.join("wal.log")).unwrap().len(), 0);
+        // Oldest table first, each sorted and after the one before
+        let mut on_disk = Vec::new();
+        for info in lsm.sstable_infos().iter().rev() {
+            let keys: Vec<Vec<u8>> = SSTableReader::open(&info.path)
+                .unwrap()
+                .map(|(key, _, _)| key)
+                .collect();
+            assert_eq!(info.stats.entries, keys.len() as u64);
+            on_disk.extend(keys);
+        }
+        let expected: Vec<Vec<u8>> = (0..10_000).map(key).collect();
+        assert_eq!(on_disk, expected);
+
+        for i in (0..10_000).step_by(37) {
+            let value = if i < 100 { "new" } else { "old" };
+            assert_eq!(
+                lsm.get(&key(i)),
+                Some(format!("{}{}", value, i).into_bytes())
+            );
+        }
+        drop(lsm);
+
+        // The tree reopens as usual, and from_iter won't touch it again
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        assert_eq!(lsm.get(&key(5)), Some(b"new5".to_vec()));
+        assert_eq!(lsm.count_range::<&[u8], _>(..), 10_000);
+        drop(lsm);
+        let e = LSMTree::from_iter(config, [(b"k".to_vec(), b"v".to_vec())])
+            .expect_err("from_iter overwrote a tree");
+        assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_snapshot_round_trips_into_a_fresh_tree() {
+        let dir = PathBuf::from("./test_lib_snapshot");
+        let target = PathBuf::from("./test_lib_snapshot_target");
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&target).ok();
+
+        // Live data spread over tables, tombstones and the memtable
+        let mut lsm = LSMTree::new(dir.clone(), 4 * 1024).unwrap();
+        for i in 0..2_000u32 {
+            let key = format!("key{:05}", (i * 7919) % 2_000).into_bytes();
+            lsm.put(key, format!("value{}", i).into_bytes()).unwrap();
+        }
+        for i in (0..2_000u32).step_by(3) {
+            lsm.delete(format!("key{:05}", i).into_bytes()).unwrap();
+        }
+        lsm.put(b"key00003".to_vec(), b"back".to_vec()).unwrap();
+        assert!(lsm.sstable_count() > 1);
+        assert!(!lsm.memtable.is_empty());
+
+        let mut stream = Vec::new();
+        let exported = lsm.export_snapshot(&mut stream).unwrap();
+        assert_eq!(exported.bytes, stream.len() as u64);
+        assert_eq!(exported.entries, lsm.count_range::<&[u8], _>(..));
+
+        let config = LSMConfig {
+            memtable_size_threshold: 8 * 1024,
+            ..LSMConfig::new(target.clone())
+        };
+        let mut imported = LSMTree::open(config.clone()).unwrap();
+        imported
+            .put(b"key00003".to_vec(), b"mine".to_vec())
+            .unwrap();
+        imported.put(b"zzz".to_vec(), b"mine".to_vec()).unwrap();
+        assert_eq!(
+            imported.import_snapshot(stream.as_slice()).unwrap(),
+            exported
+        );
+        assert!(imported.sstable_count() > 1);
+        assert_eq!(fs::metadata(target.join("wal.log")).unwrap().len(), 0);
+        // The snapshot wins over the tree's own value of a key
+        assert_eq!(imported.get(b"key00003"), Some(b"back".to_vec()));
+        assert_eq!(imported.get(b"zzz"), Some(b"mine".to_vec()));
+        imported.delete(b"zzz".to_vec()).unwrap();
+        assert!(lsm.range::<&[u8], _>(..).eq(imported.range::<&[u8], _>(..)));
+        drop(imported);
+
+        // A stream cut short anywhere is turned away and nothing changes
+        let mut imported = LSMTree::open(config.clone()).unwrap();
+        let tables = imported.sstable_count();
+        let before: Vec<_> = imported.range::<&[u8], _>(..).collect();
+        for len in [0, 20, stream.len() / 2, stream.len() - 1] {
+            let e = imported.import_snapshot(&stream[..len]).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData, "cut at {}", len);
+            assert!(e.to_string().contains("snapshot"), "{}", e);
+            assert_eq!(imported.sstable_count(), tables);
+        }
+        assert_eq!(imported.range::<&[u8], _>(..).collect::<Vec<_>>(), before);
+        drop(imported);
+        let db_files = fs::read_dir(&target)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("db".as_ref()))
+            .count();
+        assert_eq!(db_files, tables);
+
+        drop(lsm);
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&target).ok();
+    }
+
+    #[test]
+    fn test_cancelled_export_leaves_a_stream_import_refuses() {
+        // Cancels the export on the `after`th write
+        struct CancelAfter {
+            bytes: Vec<u8>,
+            token: CancellationToken,
+            writes: usize,
+            after: usize,
+        }
+
+        impl std::io::Write for CancelAfter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.writes += 1;
+                if self.writes == self.after {
+                    self.token.cancel();
+                }
+                self.bytes.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let dirs = [
+            "./test_lib_cancelled_export",
+            "./test_lib_cancelled_export_to",
+        ]
+        .map(PathBuf::from);
+        for dir in &dirs {
+            fs::remove_dir_all(dir).ok();
+        }
+        let mut lsm = LSMTree::new(dirs[0].clone(), 1024).unwrap();
+        for i in 0..100u32 {
+            lsm.put(format!("key{i:04}").into_bytes(), b"value".to_vec())
+                .unwrap();
+        }
+
+        let mut writer = CancelAfter {
+            bytes: Vec::new(),
+            token: CancellationToken::new(),
+            writes: 0,
+            after: 20,
+        };
+        let token = writer.token.clone();
+        let err = lsm.export_snapshot_with(&mut writer, &token).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+        let entries = crate::Cancelled::from_io(&err).unwrap().entries;
+        assert!(entries > 0 && entries < 100, "{}", entries);
+
+        let mut imported = LSMTree::new(dirs[1].clone(), 1024).unwrap();
+        let err = imported
+            .import_snapshot(writer.bytes.as_slice())
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(imported.count_range::<&[u8], _>(..), 0);
+        drop((lsm, imported));
+
+        for dir in &dirs {
+            fs::remove_dir_all(dir).ok();
+        }
+    }
+
+    #[test]
+    fn test_snapshot_import_settles_repeated_keys_by_policy() {
+        let dir = PathBuf::from("./test_lib_ingest_dedup");
+        fs::remove_dir_all(&dir).ok();
+
+        // Checksummed like any snapshot, but from a writer that repeats a
+        // key, then one that goes backwards
+        let stream = |pairs: &[(&[u8], &[u8])]| {
+            let mut buf = Vec::new();
+            let mut writer =
+                snapshot::SnapshotWriter::new(&mut buf, pairs.len() as u64, 0).unwrap();
+            for (key, value) in pairs {
+                writer.add(key, value).unwrap();
+            }
+            writer.finish().unwrap();
+            buf
+        };
+        let repeated = stream(&[
+            (b"a", b"1"),
+            (b"b", b"first"),
+            (b"b", b"last"),
+            (b"c", b"3"),
+        ]);
+        let backwards = stream(&[(b"b", b"1"), (b"a", b"2")]);
+
+        for (policy, expected) in [
+            (DedupPolicy::Error, None),
+            (DedupPolicy::LastWins, Some(&b"last"[..])),
+            (DedupPolicy::FirstWins, Some(b"first")),
+        ] {
+            let config = LSMConfig {
+                ingest_dedup: policy,
+                ..LSMConfig::new(dir.clone())
+            };
+            let mut lsm = LSMTree::open(config).unwrap();
+            match expected {
+                None => {
+                    let e = lsm.import_snapshot(repeated.as_slice()).unwrap_err();
+                    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+                    assert!(e.to_string().contains("duplicate key: 62 then 62"), "{}", e);
+                    assert_eq!(lsm.sstable_count(), 0);
+                }
+                Some(value) => {
+                    lsm.import_snapshot(repeated.as_slice()).unwrap();
+                    assert_eq!(lsm.get(b"b"), Some(value.to_vec()));
+                    assert_eq!(lsm.count_range::<&[u8], _>(..), 3);
+                }
+            }
+            // Out of order is never settled
+            let e = lsm.import_snapshot(backwards.as_slice()).unwrap_err();
+            assert!(
+                e.to_string().contains("keys out of order: 62 then 61"),
+                "{}",
+                e
+            );
+            drop(lsm);
+            fs::remove_dir_all(&dir).ok();
+        }
+    }
+
+    #[test]
+    fn test_refresh_picks_up_tables_added_by_another_process() {
+        let dir = PathBuf::from("./test_lib_refresh");
+        let source = PathBuf::from("./test_lib_refresh_source");
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&source).ok();
+
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        drop(lsm);
+
+        // The job writes tables for this tree: it shares the tree's FORMAT
+        // file, and with it the id its tables are stamped with
+        fs::create_dir_all(&source).unwrap();
+        fs::copy(dir.join("FORMAT"), source.join("FORMAT")).unwrap();
+        let mut other = LSMTree::new(source.clone(), 1024 * 1024).unwrap();
+        other.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        other.put(b"b".to_vec(), b"3".to_vec()).unwrap();
+        other.flush().unwrap();
+        drop(other);
+
+        let config = LSMConfig {
+            read_only: true,
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut replica = LSMTree::open(config).unwrap();
+        assert!(replica.refresh().unwrap().is_unchanged());
+        assert_eq!(replica.get(b"b"), None);
+
+        // The offline job: copy the table in, then list it as the newest
+        fs::copy(source.join("sstable_0.db"), dir.join("sstable_1.db")).unwrap();
+        fs::copy(source.join("sstable_0.bloom"), dir.join("sstable_1.bloom")).unwrap();
+        let mut manifest = Manifest::load(&dir).unwrap().unwrap();
+        manifest.sstables.insert(
+            0,
+            TableEntry {
+                number: 1,
+                generation: 2,
+                max_sequence: 2,
+                ..TableEntry::default()
+            },
+        );
+        manifest.next_file_number = 2;
+        manifest.store(&dir).unwrap();
+
+        let report = replica.refresh().unwrap();
+        assert_eq!(report.added, vec![SSTableId(1)]);
+        assert!(report.removed.is_empty());
+        assert_eq!(replica.get(b"a"), Some(b"2".to_vec()));
+        assert_eq!(replica.get(b"b"), Some(b"3".to_vec()));
+        assert_eq!(replica.filter_residency()[0].1, FilterResidency::Resident);
+
+        // Dropping the old table from the manifest removes it
+        manifest.sstables.truncate(1);
+        manifest.store(&dir).unwrap();
+        let report = replica.refresh().unwrap();
+        assert_eq!(report.removed, vec![SSTableId(0)]);
+        assert_eq!(replica.sstable_count(), 1);
+        assert_eq!(replica.get(b"a"), Some(b"2".to_vec()));
+        drop(replica);
+
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&source).ok();
+    }
+
+    #[test]
+    fn test_compaction_outputs_land_in_cold_dir() {
+        let dir = PathBuf::from("./test_lib_cold_data");
+        let cold_dir = PathBuf::from("./test_lib_cold_tables");
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&cold_dir).ok();
+
+        let config = LSMConfig {
+            memtable_size_threshold: 256,
+            cold_dir: Some(cold_dir.clone()),
+            ..LSMConfig::new(dir.clone())
+        };
+        let table_files = |dir: &Path| {
+            fs::read_dir(dir)
+                .unwrap()
+                .filter(|e| LSMTree::sstable_number(&e.as_ref().unwrap().path()).is_some())
+                .count()
+        };
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        for i in 0..100u32 {
+            lsm.put(format!("key{:03}", i).into_bytes(), vec![b'v'; 16])
+                .unwrap();
+        }
+        // Flushes land hot
+        assert!(lsm.sstable_count() > 1);
+        assert_eq!(table_files(&dir), lsm.sstable_count());
+        assert_eq!(table_files(&cold_dir), 0);
+
+        // The compaction's output lands cold, filter and all
+        lsm.purge().unwrap();
+        assert_eq!(table_files(&dir), 0);
+        let cold_path = lsm.sstables().next().unwrap().path().to_path_buf();
+        assert!(cold_path.starts_with(&cold_dir));
+        assert!(cold_path.with_extension("bloom").exists());
+        let usage = lsm.disk_usage().unwrap();
+        assert_eq!(usage.sstable_bytes, fs::metadata(&cold_path).unwrap().len());
+        assert_eq!(usage.tmp_or_orphan_bytes, 0);
+
+        lsm.put(b"fresh".to_vec(), vec![b'f'; 300]).unwrap();
+        assert_eq!(table_files(&dir), 1);
+        drop(lsm);
+
+        let lsm = LSMTree::open(config.clone()).unwrap();
+        for i in 0..100u32 {
+            assert_eq!(
+                lsm.get_immut(format!("key{:03}", i).as_bytes()),
+                Some(vec![b'v'; 16])
+            );
+        }
+        assert_eq!(lsm.get_immut(b"fresh"), Some(vec![b'f'; 300]));
+        drop(lsm);
+
+        // The cold tables are only where the manifest says they are
+        for cold_dir in [None, Some(PathBuf::from("./test_lib_cold_elsewhere"))] {
+            let err = LSMTree::open(LSMConfig {
+                cold_dir,
+                ..config.clone()
+            })
+            .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&cold_dir).ok();
+        fs::remove_dir_all("./test_lib_cold_elsewhere").ok();
+    }
+
+    #[test]
+    fn test_migrate_cold_moves_old_tables() {
+        let dir = PathBuf::from("./test_lib_migrate_cold_data");
+        let cold_dir = PathBuf::from("./test_lib_migrate_cold_tables");
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&cold_dir).ok();
+
+        let clock = testing::ManualClock::new(1_000);
+        let config = LSMConfig {
+            cold_dir: Some(cold_dir.clone()),
+            cold_policy: ColdPolicy::OlderThan(std::time::Duration::from_secs(60)),
+            clock: Arc::new(clock.clone()),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config.clone()).unwrap();
+        lsm.put(b"old".to_vec(), b"1".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        clock.advance(std::time::Duration::from_secs(90));
+        lsm.put(b"new".to_vec(), b"2".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        // A state taken before the move keeps the hot original alive
+        let state = lsm.read_state();
+
+        assert_eq!(lsm.migrate_cold().unwrap(), vec![SSTableId(0)]);
+        assert!(cold_dir.join("sstable_0.db").exists());
+        assert!(cold_dir.join("sstable_0.bloom").exists());
+        assert!(dir.join("sstable_0.db").exists());
+        assert_eq!(state.get(b"old"), Some(b"1".to_vec()));
+        drop(state);
+        assert!(!dir.join("sstable_0.db").exists());
+        assert!(dir.join("sstable_1.db").exists());
+        assert_eq!(lsm.get(b"old"), Some(b"1".to_vec()));
+        // Nothing else qualifies yet
+        assert!(lsm.migrate_cold().unwrap().is_empty());
+        drop(lsm);
+
+        let mut lsm = LSMTree::open(config).unwrap();
+        assert_eq!(lsm.get(b"old"), Some(b"1".to_vec()));
+        assert_eq!(lsm.get(b"new"), Some(b"2".to_vec()));
+        clock.advance(std::time::Duration::from_secs(90));
+        assert_eq!(lsm.migrate_cold().unwrap(), vec![SSTableId(1)]);
+        assert_eq!(lsm.get(b"new"), Some(b"2".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&cold_dir).ok();
+    }
+
+    #[test]
+    fn test_read_only_open_does_not_touch_the_directory() {
+        let dir = PathBuf::from("./test_lib_read_only");
+        three_table_tree(&dir);
+        {
+            let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+            lsm.put(b"unflushed".to_vec(), b"1".to_vec()).unwrap();
+            lsm.simulate_crash();
+        }
+        fs::remove_file(dir.join("sstable_2.bloom")).unwrap();
+        let manifest_before = fs::read(dir.join("MANIFEST")).unwrap();
+        let wal_before = fs::read(dir.join("wal.log")).unwrap();
+
+        let config = LSMConfig {
+            read_only: true,
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config).unwrap();
+
+        // No filter was rebuilt; the table is probed on every lookup
+        assert_eq!(lsm.filterless_sstables(), vec![dir.join("sstable_2.db")]);
+        assert!(!dir.join("sstable_2.bloom").exists());
+        assert_eq!(lsm.get(b"c"), Some(b"value".to_vec()));
+        assert_eq!(lsm.get(b"unflushed"), Some(b"1".to_vec()));
+        assert_eq!(lsm.wal_stats().file_size, wal_before.len() as u64);
+
+        let err = lsm.put(b"x".to_vec(), b"y".to_vec()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(lsm.flush().is_err());
+        drop(lsm);
+
+        assert_eq!(fs::read(dir.join("MANIFEST")).unwrap(), manifest_before);
+        assert_eq!(fs::read(dir.join("wal.log")).unwrap(), wal_before);
+        // FORMAT, LOCK, MANIFEST, wal.log, three tables and the two
+        // remaining filters
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 9);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_failed_wal_append_is_rolled_back_and_retried() {
+        let dir = PathBuf::from("./test_lib_wal_retry");
+        fs::remove_dir_all(&dir).ok();
+        let storage = testing::FaultyStorage::new();
+        let config = LSMConfig {
+            storage: Arc::new(storage.clone()),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        let wal_size = lsm.wal_stats().file_size;
+
+        // Torn mid-record: nothing of it stays in the log or the memtable
+        storage.fail_appends(0, 1);
+        let err = lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+        assert_eq!(lsm.wal_stats().file_size, wal_size);
+        assert_eq!(fs::metadata(dir.join("wal.log")).unwrap().len(), wal_size);
+        assert_eq!(lsm.get(b"b"), None);
+        assert!(!lsm.is_poisoned());
+
+        // A batch failing on its second entry takes the first with it
+        storage.fail_appends(1, 1);
+        let mut batch = WriteBatch::new();
+        batch.put(b"c".to_vec(), b"3".to_vec());
+        batch.delete(b"a".to_vec());
+        assert!(lsm.write(batch.clone()).is_err());
+        assert_eq!(lsm.wal_stats().file_size, wal_size);
+        assert_eq!(lsm.get(b"c"), None);
+        assert_eq!(lsm.get(b"a"), Some(b"1".to_vec()));
+
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        lsm.write(batch).unwrap();
+        assert_eq!(storage.failed_appends(), 2);
+        lsm.simulate_crash();
+
+        // Replay finds every retried write and nothing torn in between
+        let mut lsm = LSMTree::new(dir.clone(), 1024 * 1024).unwrap();
+        assert_eq!(lsm.get(b"a"), None);
+        assert_eq!(lsm.get(b"b"), Some(b"2".to_vec()));
+        assert_eq!(lsm.get(b"c"), Some(b"3".to_vec()));
+        assert_eq!(lsm.wal_stats().entries_since_clear, 0);
+        assert_eq!(lsm.memtable_keys().len(), 3);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_repeated_wal_failures_poison_the_tree() {
+        let dir = PathBuf::from("./test_lib_wal_poison");
+        fs::remove_dir_all(&dir).ok();
+        let storage = testing::FaultyStorage::new();
+        let config = LSMConfig {
+            storage: Arc::new(storage.clone()),
+            ..LSMConfig::new(dir.clone())
+        };
+        let mut lsm = LSMTree::open(config).unwrap();
+        lsm.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+
+        storage.fail_appends(0, u64::MAX);
+        for _ in 0..WAL_FAILURES_BEFORE_POISON {
+            assert!(!lsm.is_poisoned());
+            let err = lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap_err();
+            assert!(Poisoned::from_io(&err).is_none());
+        }
+        assert!(lsm.is_poisoned());
+
+        // Refused before reaching the WAL, with an error of its own
+        let err = lsm.delete(b"a".to_vec()).unwrap_err();
+        let poisoned = Poisoned::from_io(&err).unwrap();
+        assert_eq!(poisoned.failures, WAL_FAILURES_BEFORE_POISON);
+        assert!(poisoned.cause.contains("injected append failure"));
+        assert_eq!(storage.failed_appends(), WAL_FAILURES_BEFORE_POISON as u64);
+
+        // Reads and flushes go on
+        assert_eq!(lsm.get(b"a"), Some(b"1".to_vec()));
+        lsm.flush().unwrap();
+        assert_eq!(lsm.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(lsm.get(b"b"), None);
+
+        storage.fail_appends(0, 0);
+        lsm.clear_poison().unwrap();
+        assert!(!lsm.is_poisoned());
+        lsm.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        assert_eq!(lsm.get(b"b"), Some(b"2".to_vec()));
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_paused_auto_flush_defers_until_resume() {
+        let dir = PathBuf::from("./test_lib_flush_pause");
+        fs::remove_dir_all(&dir).ok();
+        let threshold = 1024;
+        let clock = testing::ManualClock::new(1_000);
+        let mut lsm = LSMTree::open(LSMConfig {
+            memtable_size_threshold: threshold,
+            flush_after_idle: Some(std::time::Duration::from_secs(1)),
+            clock: Arc::new(clock.clone()),
+            sync_on_flush: false,
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+
+        let value = vec![b'v'; 64];
+        let mut paused = lsm.pause_flushes();
+        let mut i = 0u32;
+        while paused.memtable_size() < 3 * threshold {
+            paused
+                .put(format!("key{:04}", i).into_bytes(), value.clone())
+                .unwrap();
+            i += 1;
+        }
+        assert!(paused.is_auto_flush_paused());
+        // Idle flushes wait too
+        clock.advance(std::time::Duration::from_secs(10));
+        assert!(!paused.maintenance_tick().unwrap());
+        assert_eq!(paused.sstable_count(), 0);
+
+        paused.resume().unwrap();
+        assert!(!lsm.is_auto_flush_paused());
+        assert_eq!(lsm.sstable_count(), 1);
+        assert!(lsm.memtable.is_empty());
+        assert_eq!(lsm.get(b"key0000"), Some(value.clone()));
+
+        // Nested pauses flush once the outer one ends; an explicit flush
+        // goes through regardless
+        lsm.pause_auto_flush();
+        lsm.pause_auto_flush();
+        for j in 0..i {
+            lsm.put(format!("more{:04}", j).into_bytes(), value.clone())
+                .unwrap();
+        }
+        lsm.resume_auto_flush().unwrap();
+        assert_eq!(lsm.sstable_count(), 1);
+        lsm.resume_auto_flush().unwrap();
+        assert_eq!(lsm.sstable_count(), 2);
+        lsm.pause_auto_flush();
+        lsm.put(b"x".to_vec(), b"1".to_vec()).unwrap();
+        lsm.flush().unwrap();
+        assert_eq!(lsm.sstable_count(), 3);
+        // Under the threshold, resuming doesn't flush
+        lsm.put(b"y".to_vec(), b"2".to_vec()).unwrap();
+        lsm.resume_auto_flush().unwrap();
+        assert_eq!(lsm.sstable_count(), 3);
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_paused_memtable_over_cap_flushes_with_event() {
+        let dir = PathBuf::from("./test_lib_flush_pause_cap");
+        fs::remove_dir_all(&dir).ok();
+        let listener = testing::RecordingListener::new();
+        let mut lsm = LSMTree::open(LSMConfig {
+            memtable_size_threshold: 1024,
+            max_paused_memtable_bytes: 4096,
+            event_listener: Arc::new(listener.clone()),
+            sync_on_flush: false,
+            ..LSMConfig::new(dir.clone())
+        })
+        .unwrap();
+
+        let mut paused = lsm.pause_flushes();
+        for i in 0..100u32 {
+            paused
+                .put(format!("key{:04}", i).into_bytes(), vec![b'v'; 64])
+                .unwrap();
+        }
+        // Each table holds a cap's worth, not a threshold's
+        let overrides = listener.pause_overrides();
+        assert!(!overrides.is_empty());
+        assert_eq!(paused.sstable_count(), overrides.len());
+        assert!(overrides.iter().all(|event| event.memtable_bytes > 4096
+            && event.max_paused_memtable_bytes == 4096));
+        assert!(paused.is_auto_flush_paused());
+        drop(paused);
+        assert!(!lsm.is_auto_flush_paused());
+        for key in [&b"key0000"[..], b"key0050", b"key0099"] {
+            assert_eq!(lsm.get(key), Some(vec![b'v'; 64]));
+        }
+        drop(lsm);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_corrupt_newest_copy_errors_or_falls_back() {
+        let verify = ReadOptions {
+            verify_checksums: true,
+            ..ReadOptions::default()
+        };
+        for policy in [CorruptionPolicy::Error, CorruptionPolicy::FallbackToOlder] {
+            let dir = PathBuf::from(format!("./test_lib_corrupt_read_{:?}", policy));
+            fs::remove_dir_all(&dir).ok();
+            let listener = testing::RecordingListener::new();
+            let mut lsm = LSMTree::open(LSMConfig {
+                on_corruption: policy,
+                event_listener: Arc::new(listener.clone()),
+                ..LSMConfig::new(dir.clone())
+            })
+            .unwrap();
+            lsm.put(b"key".to_vec(), b"old".to_vec()).unwrap();
+            lsm.flush().unwrap();
+            lsm.put(b"key".to_vec(), b"new".to_vec()).unwrap();
+            lsm.flush().unwrap();
+
+            // Flip a byte of the newest copy's value, which ends the records
+            let newest = dir.join("sstable_1.db");
+            let len = sstable::records_end(&newest);
+            let mut bytes = fs::read(&newest).unwrap();
+            assert_eq!(&bytes[len as usize - 3..len as usize], b"new");
+            bytes[len as usize - 2] ^= 0x01;
+            fs::write(&newest, bytes).unwrap();
+
+            for _ in 0..2 {
+                let result = lsm.get_opt(b"key", &verify);
+                match policy {
+                    CorruptionPolicy::Error => {
+                        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData)
+                    }
+                    CorruptionPolicy::FallbackToOlder => {
+                        assert_eq!(result.unwrap(), Some(b"old".to_vec()))
+                    }
+                }
+            }
+
+            // Listed and reported once, however often it is read
+            let expected = CorruptRead {
+                path: newest.clone(),
+                key: b"key".to_vec(),
+                // The table's only record: shared and suffix lengths, key,
+                // sequence, value length, checksum and value
+                reason: Corruption::ChecksumMismatch {
+                    offset: len - (4 + 4 + 3 + 8 + 4 + 4 + 3),
+                },
+            };
+            assert_eq!(lsm.corruption_report(), std::slice::from_ref(&expected));
+            assert_eq!(listener.corrupt_reads(), vec![expected]);
+            drop(lsm);
+
+            fs::remove_dir_all(&dir).ok();
+        }
+    }
+}
@@ -0,0 +1,110 @@
+/// The identity of a tree, stamped into every SSTable it writes
+///
+/// File numbers and the manifest keep one directory consistent, but
+/// nothing stopped a `sstable_3.db` copied over from another tree from
+/// being read as if it belonged here. A tree gets a random UUID when its
+/// directory is created (kept in the FORMAT file), and every SSTable it
+/// writes carries that UUID in its header. Opening the tree, refreshing it
+/// and repairing it refuse a table stamped by another tree;
+/// `LSMTree::ingest_sstable_file` is the way to adopt one.
+///
+/// Tables written before tables were stamped, and directories created
+/// before they had an id, are accepted as they are. A checkpoint copies the
+/// FORMAT file, so it keeps the id of the tree it was taken from.
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// A random (version 4) UUID naming one tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TreeId([u8; 16]);
+
+impl TreeId {
+    /// Size of the id in an SSTable header
+    pub const LEN: usize = 16;
+
+    /// Generates a new random id
+    ///
+    /// The randomness comes from the keys of `RandomState`, which the
+    /// standard library seeds from the operating system, mixed with the
+    /// time, the process id and a counter.
+    pub fn generate() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+
+        let mut bytes = [0u8; Self::LEN];
+        for half in bytes.chunks_mut(8) {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(nanos);
+            hasher.write_u32(std::process::id());
+            hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+            half.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+        // Version 4, variant 1 (RFC 4122)
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Self(bytes)
+    }
+
+    pub fn from_bytes(bytes: [u8; Self::LEN]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; Self::LEN] {
+        &self.0
+    }
+
+    /// Parses the hyphenated form written by `Display`
+    /// (`xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`)
+    pub fn parse(text: &str) -> Option<Self> {
+        let groups: Vec<&str> = text.split('-').collect();
+        let lengths: Vec<usize> = groups.iter().map(|g| g.len()).collect();
+        if lengths != [8, 4, 4, 4, 12] {
+            return None;
+        }
+        let digits: String = groups.concat();
+        if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut bytes = [0u8; Self::LEN];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).ok()?;
+        }
+        Some(Self(bytes))
+    }
+}
+
+impl fmt::Display for TreeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_ids_differ_and_round_trip() {
+        let a = TreeId::generate();
+        let b = TreeId::generate();
+        assert_ne!(a, b);
+
+        let text = a.to_string();
+        assert_eq!(text.len(), 36);
+        assert_eq!(&text[14..15], "4");
+        assert_eq!(TreeId::parse(&text), Some(a));
+        assert_eq!(TreeId::parse("not-a-uuid"), None);
+        assert_eq!(TreeId::parse(&text.replace('-', "")), None);
+    }
+}
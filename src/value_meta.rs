@@ -0,0 +1,262 @@
+/// Where a value came from and when it was written
+///
+/// `LSMTree::get_with_meta` returns a `ValueMeta` next to the value, so a
+/// cache in front of the tree can tell how old an answer is.
+///
+/// Write times are not stored per record. Every write of a batch gets the
+/// same time and sequence numbers grow with time, so the times of a run of
+/// writes are kept as sequence ranges (`WriteTimes`): the tree keeps one
+/// for the memtable, and each SSTable stores the ranges of its records in
+/// its header (format version 4). A table with thousands of writes from
+/// the same millisecond needs one range for all of them.
+///
+/// Writes recovered from the WAL after a crash, and records of tables
+/// written before write times existed, have no recorded time. Recovered
+/// writes are still kept as a range, marked as written at an unknown time,
+/// so a table holding them is never taken to be entirely older than some
+/// time (see `LSMConfig::ttl`).
+use std::io::{Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::handle::SSTableId;
+
+/// Provenance of a value returned by `LSMTree::get_with_meta`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueMeta {
+    /// Sequence number of the write that stored the value (0 for tables
+    /// written before sequence numbers existed)
+    pub sequence: u64,
+
+    /// When the write happened, by `LSMConfig::clock`, if it was recorded
+    pub written_at: Option<SystemTime>,
+
+    /// Where the value was found
+    pub source: ValueSource,
+}
+
+/// Where a lookup found its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueSource {
+    /// In the memtable, not yet flushed
+    Memtable,
+
+    /// In an SSTable
+    SSTable { id: SSTableId, generation: u64 },
+}
+
+/// Size of one encoded range: first and last sequence, milliseconds
+const RANGE_LEN: usize = 24;
+
+/// Milliseconds stored for writes whose time isn't known
+const UNKNOWN_TIME: u64 = u64::MAX;
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Write times of runs of sequence numbers
+///
+/// Ranges are ordered by sequence and never overlap; adjacent runs written
+/// in the same millisecond are kept as one range.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct WriteTimes {
+    /// `(first_seq, last_seq, unix_millis)`, inclusive
+    ranges: Vec<(u64, u64, u64)>,
+}
+
+impl WriteTimes {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.ranges.clear();
+    }
+
+    /// Records that sequences `first..=last` were written at `time`
+    ///
+    /// Sequences must be recorded in increasing order.
+    pub(crate) fn record(&mut self, first: u64, last: u64, time: SystemTime) {
+        self.record_millis(first, last, to_millis(time));
+    }
+
+    /// Records that sequences `first..=last` were written at a time that
+    /// isn't known (writes recovered from the WAL)
+    pub(crate) fn record_unknown(&mut self, first: u64, last: u64) {
+        self.record_millis(first, last, UNKNOWN_TIME);
+    }
+
+    fn record_millis(&mut self, first: u64, last: u64, millis: u64) {
+        match self.ranges.last_mut() {
+            Some(range) if range.2 == millis && range.1 + 1 == first => range.1 = last,
+            _ => self.ranges.push((first, last, millis)),
+        }
+    }
+
+    /// When the write with sequence `seq` happened, if it was recorded
+    pub(crate) fn time_of(&self, seq: u64) -> Option<SystemTime> {
+        let after = self.ranges.partition_point(|r| r.0 <= seq);
+        let &(_, last, millis) = self.ranges.get(after.checked_sub(1)?)?;
+        (seq <= last && millis != UNKNOWN_TIME).then(|| UNIX_EPOCH + Duration::from_millis(millis))
+    }
+
+    /// Earliest and latest write time, or `None` if nothing is recorded or
+    /// some write's time is unknown
+    pub(crate) fn span(&self) -> Option<(SystemTime, SystemTime)> {
+        if self.ranges.iter().any(|r| r.2 == UNKNOWN_TIME) {
+            return None;
+        }
+        let earliest = self.ranges.iter().map(|r| r.2).min()?;
+        let latest = self.ranges.iter().map(|r| r.2).max()?;
+        let time = |millis| UNIX_EPOCH + Duration::from_millis(millis);
+        Some((time(earliest), time(latest)))
+    }
+
+    /// Counts the recorded sequence numbers, and those among them written
+    /// at or before `cutoff`
+    pub(crate) fn count_written_by(&self, cutoff: SystemTime) -> (u64, u64) {
+        let cutoff = to_millis(cutoff);
+        let (mut by, mut total) = (0, 0);
+        for &(first, last, millis) in &self.ranges {
+            let count = last - first + 1;
+            total += count;
+            if millis != UNKNOWN_TIME && millis <= cutoff {
+                by += count;
+            }
+        }
+        (by, total)
+    }
+
+    /// Combines the times of several tables
+    pub(crate) fn merge<'a>(all: impl IntoIterator<Item = &'a WriteTimes>) -> Self {
+        let mut ranges: Vec<_> = all.into_iter().flat_map(|t| t.ranges.clone()).collect();
+        ranges.sort_unstable();
+        let mut merged = Self::default();
+        for (first, last, millis) in ranges {
+            match merged.ranges.last_mut() {
+                Some(range) if range.2 == millis && range.1 + 1 == first => range.1 = last,
+                _ => merged.ranges.push((first, last, millis)),
+            }
+        }
+        merged
+    }
+
+    /// Number of ranges; `range_of` indexes below it
+    pub(crate) fn range_count(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Index of the range holding `seq`, if one does
+    pub(crate) fn range_of(&self, seq: u64) -> Option<usize> {
+        let i = self.ranges.partition_point(|r| r.0 <= seq).checked_sub(1)?;
+        (seq <= self.ranges[i].1).then_some(i)
+    }
+
+    /// Keeps the ranges whose flag in `keep` (by `range_of` index) is set,
+    /// so a rewritten table doesn't carry the times of versions it dropped
+    pub(crate) fn retain_ranges(&mut self, keep: &[bool]) {
+        let mut keep = keep.iter();
+        self.ranges
+            .retain(|_| keep.next().copied().unwrap_or(false));
+    }
+
+    /// Writes `[count: u32]` followed by every range
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&(self.ranges.len() as u32).to_le_bytes())?;
+        for (first, last, millis) in &self.ranges {
+            writer.write_all(&first.to_le_bytes())?;
+            writer.write_all(&last.to_le_bytes())?;
+            writer.write_all(&millis.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reads what `write_to` wrote
+    pub(crate) fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut count_buf = [0u8; 4];
+        reader.read_exact(&mut count_buf)?;
+        let count = u32::from_le_bytes(count_buf) as usize;
+        let mut bytes = Vec::new();
+        crate::sstable::read_field(reader, count * RANGE_LEN, &mut bytes)?;
+        let field = |chunk: &[u8], i: usize| {
+            u64::from_le_bytes(chunk[i * 8..i * 8 + 8].try_into().expect("8 bytes"))
+        };
+        let ranges = bytes
+            .chunks_exact(RANGE_LEN)
+            .map(|chunk| (field(chunk, 0), field(chunk, 1), field(chunk, 2)))
+            .collect();
+        Ok(Self { ranges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(millis: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_write_times_ranges() {
+        let mut times = WriteTimes::default();
+        times.record(3, 3, at(100));
+        times.record(4, 6, at(100));
+        times.record(7, 7, at(250));
+        // A gap (writes whose time is unknown) keeps ranges apart
+        times.record(10, 10, at(250));
+        assert_eq!(times.ranges.len(), 3);
+
+        assert_eq!(times.time_of(2), None);
+        assert_eq!(times.time_of(3), Some(at(100)));
+        assert_eq!(times.time_of(6), Some(at(100)));
+        assert_eq!(times.time_of(7), Some(at(250)));
+        assert_eq!(times.time_of(8), None);
+        assert_eq!(times.time_of(10), Some(at(250)));
+        assert_eq!(times.time_of(11), None);
+
+        let mut encoded = Vec::new();
+        times.write_to(&mut encoded).unwrap();
+        assert_eq!(encoded.len(), 4 + 3 * RANGE_LEN);
+        let decoded = WriteTimes::read_from(&mut encoded.as_slice()).unwrap();
+        assert_eq!(decoded, times);
+        assert!(WriteTimes::read_from(&mut &encoded[..encoded.len() - 1]).is_err());
+
+        let mut other = WriteTimes::default();
+        other.record(8, 9, at(250));
+        let mut merged = WriteTimes::merge([&times, &other]);
+        // 7..=10 all at 250 now form one range
+        assert_eq!(merged.ranges.len(), 2);
+        assert_eq!(merged.time_of(9), Some(at(250)));
+
+        assert_eq!(merged.range_of(8), Some(1));
+        assert_eq!(merged.range_of(11), None);
+        let mut keep = vec![false; merged.range_count()];
+        keep[merged.range_of(8).unwrap()] = true;
+        merged.retain_ranges(&keep);
+        assert_eq!(merged.ranges, vec![(7, 10, 250)]);
+    }
+
+    #[test]
+    fn test_write_times_span_and_counts() {
+        let mut times = WriteTimes::default();
+        assert_eq!(times.span(), None);
+        times.record(1, 4, at(100));
+        times.record(5, 5, at(300));
+        assert_eq!(times.span(), Some((at(100), at(300))));
+        assert_eq!(times.count_written_by(at(99)), (0, 5));
+        assert_eq!(times.count_written_by(at(100)), (4, 5));
+        assert_eq!(times.count_written_by(at(300)), (5, 5));
+
+        // Writes of unknown time count, but never as written by a cutoff,
+        // and leave the span unknown
+        let mut recovered = WriteTimes::default();
+        recovered.record_unknown(6, 7);
+        let merged = WriteTimes::merge([&times, &recovered]);
+        assert_eq!(merged.time_of(6), None);
+        assert_eq!(merged.span(), None);
+        assert_eq!(merged.count_written_by(at(1_000)), (5, 7));
+    }
+}
@@ -0,0 +1,95 @@
+/// Targeted integrity check of a single key (see `LSMTree::verify_key`)
+///
+/// When one value looks wrong, checking the whole tree is slow and says
+/// little about that value. `verify_key` instead finds every copy of the
+/// key the tree still holds, the versions kept in the memtable and every
+/// record in every SSTable, and checks each of them:
+///
+/// - the record matches its checksum, and can be read to its end and its
+///   value decoded (tables older than `sstable::CHECKSUM_FORMAT_VERSION`
+///   have no checksums, so only the last two are checked in them)
+/// - versions of the key within a table are in order
+/// - the Bloom filter of every table holding the key answers "maybe"; a
+///   "no" means the filter is broken, and lookups skip that table
+///
+/// It also names the copy reads return, so a report shows at a glance
+/// whether a damaged copy is served or only an older, shadowed one.
+use std::path::PathBuf;
+
+use crate::sstable::Corruption;
+use crate::value_meta::ValueSource;
+
+/// One copy of a key held by the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyCopy {
+    /// Where the copy lives
+    pub source: ValueSource,
+
+    /// Offset of the record in the SSTable file (`None` in the memtable)
+    pub offset: Option<u64>,
+
+    /// Sequence number of the write
+    pub sequence: u64,
+
+    /// The value (`None` for a tombstone, or when the copy is damaged)
+    pub value: Option<Vec<u8>>,
+
+    /// What is wrong with the record, if anything
+    pub damage: Option<Corruption>,
+
+    /// The answer of the table's Bloom filter for the key
+    ///
+    /// `None` in the memtable and for tables without a usable filter;
+    /// `Some(false)` means the filter is broken.
+    pub filter_may_contain: Option<bool>,
+}
+
+impl KeyCopy {
+    /// Returns true if the copy is undamaged and its table's filter finds it
+    pub fn is_healthy(&self) -> bool {
+        self.damage.is_none() && self.filter_may_contain != Some(false)
+    }
+}
+
+/// Every copy of a key and the result of checking it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyVerification {
+    /// The copies, newest first: memtable versions, then the records of
+    /// each SSTable in read order
+    pub copies: Vec<KeyCopy>,
+
+    /// Position in `copies` of the version reads return: the newest copy
+    /// that isn't damaged (`None` if there is none)
+    pub winner: Option<usize>,
+}
+
+impl KeyVerification {
+    /// The copy reads return
+    pub fn winning_copy(&self) -> Option<&KeyCopy> {
+        self.copies.get(self.winner?)
+    }
+
+    /// Copies that failed a check
+    pub fn problems(&self) -> impl Iterator<Item = &KeyCopy> {
+        self.copies.iter().filter(|copy| !copy.is_healthy())
+    }
+
+    /// Returns true if every copy passed its checks
+    pub fn is_healthy(&self) -> bool {
+        self.problems().next().is_none()
+    }
+}
+
+/// A damaged record a read with `ReadOptions::verify_checksums` came
+/// across (see `LSMTree::corruption_report`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptRead {
+    /// The table holding the record
+    pub path: PathBuf,
+
+    /// The key being read
+    pub key: Vec<u8>,
+
+    /// What the check found
+    pub reason: Corruption,
+}
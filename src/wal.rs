@@ -8,9 +8,35 @@
 /// Think of it like this:
 /// - Without WAL: Write to memory → crash → data lost forever
 /// - With WAL: Write to journal → write to memory → crash → replay journal → data recovered!
+use std::fmt;
 use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::bloom::{FNV_OFFSET_BASIS, fnv1a, mix64};
+use crate::clock::{Clock, SystemClock};
+use crate::config::{DEFAULT_READ_BUFFER_BYTES, SizeLimits};
+use crate::storage::{FsStorage, Storage};
+
+/// Flag set in the op byte when the entry carries a sequence number
+///
+/// Logs written before sequence numbers existed store only the bare op
+/// byte (1 or 2). New entries set the high bit and are followed by an
+/// 8-byte sequence number, so both kinds can be told apart when replaying.
+const SEQUENCE_FLAG: u8 = 0x80;
+
+/// Flag set in the op byte when the entry ends with a checksum
+///
+/// The checksum covers every byte of the entry before it, op byte
+/// included. Logs written before checksums existed don't set the flag, and
+/// their entries are replayed unchecked.
+const CHECKSUM_FLAG: u8 = 0x40;
+
+/// Size of an entry's fixed fields: op byte, sequence, key and value
+/// lengths, and checksum
+const ENTRY_HEADER_LEN: u64 = 1 + 8 + 4 + 4 + 4;
 
 /// Types of operations we can log
 ///
@@ -23,7 +49,7 @@ pub enum WALOp {
     /// Stored in log as byte value: 1
     Put = 1,
 
-    /// Delete a key (for future use)
+    /// Delete a key (replayed as a tombstone)
     /// Stored in log as byte value: 2
     Delete = 2,
 }
@@ -31,12 +57,18 @@ pub enum WALOp {
 /// A single entry in the Write-Ahead Log
 ///
 /// This represents one operation that was (or will be) performed.
-/// We store the operation type, the key, and the value.
+/// We store the operation type, its sequence number, the key, and the value.
 #[derive(Debug, Clone, PartialEq)]
 pub struct WALEntry {
     /// What kind of operation is this? (Put or Delete)
     pub op: WALOp,
 
+    /// Position of this write in the tree's history (1, 2, 3, ...)
+    ///
+    /// 0 means the entry came from a log written before sequence numbers
+    /// existed.
+    pub seq: u64,
+
     /// The key being operated on (stored as bytes for flexibility)
     pub key: Vec<u8>,
 
@@ -44,6 +76,76 @@ pub struct WALEntry {
     pub value: Vec<u8>,
 }
 
+impl WALEntry {
+    /// Number of bytes the entry takes up in the log
+    pub fn encoded_len(&self) -> u64 {
+        // Entries without a sequence number come from logs older than the
+        // sequence field and the checksum
+        let missing_len = if self.seq == 0 { 8 + 4 } else { 0 };
+        ENTRY_HEADER_LEN - missing_len + self.key.len() as u64 + self.value.len() as u64
+    }
+}
+
+/// What replaying a log found (see `WAL::replay`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WALRecovery {
+    /// The well-formed entries, in the order they were written
+    pub entries: Vec<WALEntry>,
+
+    /// Bytes taken up by those entries at the start of the file
+    pub valid_len: u64,
+
+    /// Size of the whole file
+    pub file_len: u64,
+
+    /// Where and why replay stopped early, if it did
+    pub corruption: Option<String>,
+}
+
+impl WALRecovery {
+    /// Bytes after the last good entry that were not replayed
+    pub fn dropped_bytes(&self) -> u64 {
+        self.file_len - self.valid_len
+    }
+}
+
+/// What applying another tree's log did (see `LSMTree::apply_wal_file`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApplyReport {
+    /// Entries written to the tree
+    pub applied: usize,
+
+    /// Well-formed entries the tree's `SizeLimits` refused
+    pub skipped: usize,
+
+    /// Entries older than the version of their key the tree already held
+    pub conflicted: usize,
+
+    /// Bytes after the last good entry that were not read
+    pub dropped_bytes: u64,
+
+    /// Where and why reading stopped early, if it did
+    pub corruption: Option<String>,
+}
+
+impl fmt::Display for ApplyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Applied {} WAL entries ({} skipped, {} conflicted)",
+            self.applied, self.skipped, self.conflicted
+        )?;
+        if let Some(corruption) = &self.corruption {
+            writeln!(
+                f,
+                "  dropped {} unreadable bytes: {}",
+                self.dropped_bytes, corruption
+            )?;
+        }
+        Ok(())
+    }
+}
+
 /// Write-Ahead Log implementation
 ///
 /// The WAL is a simple append-only file on disk. Every time you write data,
@@ -51,21 +153,70 @@ pub struct WALEntry {
 /// guarantees that even if the power goes out, the operation is saved.
 ///
 /// File format for each entry:
-/// `[operation_type: 1 byte][key_length: 4 bytes][key_bytes][value_length: 4 bytes][value_bytes]`
+/// `[operation_type: 1 byte][sequence: 8 bytes][key_length: 4 bytes][key_bytes][value_length: 4 bytes][value_bytes][checksum: 4 bytes]`
 ///
 /// This format is self-describing - we can parse it even if we don't know
 /// how many entries are in the file. Just keep reading until EOF.
+#[deprecated(note = "internal to `LSMTree`; will become crate-private in the next release")]
 pub struct WAL {
     /// Path to the WAL file on disk
     /// Typically something like "./lsm_data/wal.log"
     path: PathBuf,
 
-    /// Buffered writer for efficient sequential writes
+    /// The open log file
     ///
-    /// We use buffering because WAL writes are always sequential (append-only).
-    /// Sequential writes are the fastest kind of disk I/O, and buffering makes
-    /// them even faster by batching multiple small writes together.
-    writer: BufWriter<File>,
+    /// Each entry is encoded whole into `record` and handed to `storage`
+    /// in one call, so after a failed append the log ends either at the
+    /// entry's start or partway into it, never later.
+    file: File,
+
+    /// Scratch buffer the entry being appended is encoded into
+    record: Vec<u8>,
+
+    /// Where appends are written
+    storage: Arc<dyn Storage>,
+
+    /// Counters reported by `stats()`
+    stats: WALStats,
+
+    /// Source of the `last_sync` timestamps
+    clock: Arc<dyn Clock>,
+}
+
+/// A position in the log to roll back to (see `WAL::mark`)
+#[deprecated(note = "internal to `LSMTree`; will become crate-private in the next release")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WALMark {
+    len: u64,
+    entries: u64,
+    bytes: u64,
+}
+
+/// Activity counters for a WAL
+///
+/// Useful for reasoning about recovery time (how much would be replayed)
+/// and about how often the log is pushed to the operating system.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WALStats {
+    /// Current size of the log file in bytes
+    pub file_size: u64,
+
+    /// Entries appended since the log was last cleared (or opened)
+    pub entries_since_clear: u64,
+
+    /// Bytes appended since the log was last cleared (or opened)
+    pub bytes_since_clear: u64,
+
+    /// Number of times buffered entries were pushed to the operating system
+    /// since the log was opened (one per append)
+    pub syncs: u64,
+
+    /// Number of times the log was forced to disk (fsync) since it was
+    /// opened
+    pub fsyncs: u64,
+
+    /// When the last sync happened
+    pub last_sync: Option<SystemTime>,
 }
 
 impl WAL {
@@ -87,40 +238,60 @@ impl WAL {
     /// let wal = WAL::new(PathBuf::from("./data/wal.log"))?;
     /// ```
     pub fn new(path: PathBuf) -> std::io::Result<Self> {
+        Self::with_clock(path, Arc::new(SystemClock))
+    }
+
+    /// Creates or opens a WAL that timestamps syncs with `clock`
+    pub fn with_clock(path: PathBuf, clock: Arc<dyn Clock>) -> std::io::Result<Self> {
+        Self::with_storage(path, clock, Arc::new(FsStorage))
+    }
+
+    /// Creates or opens a WAL that writes its entries through `storage`
+    pub fn with_storage(
+        path: PathBuf,
+        clock: Arc<dyn Clock>,
+        storage: Arc<dyn Storage>,
+    ) -> std::io::Result<Self> {
         // Open in append mode - this preserves existing data
         // create(true) means "create the file if it doesn't exist"
         // append(true) means "all writes go to the end of the file"
         let file = OpenOptions::new().create(true).append(true).open(&path)?;
 
-        // Wrap in a buffered writer for better performance
-        // BufWriter accumulates small writes in memory before
-        // actually writing to disk in larger chunks
-        let writer = BufWriter::new(file);
-
-        Ok(Self { path, writer })
+        let stats = WALStats {
+            file_size: file.metadata()?.len(),
+            ..WALStats::default()
+        };
+
+        Ok(Self {
+            path,
+            file,
+            record: Vec::new(),
+            storage,
+            stats,
+            clock,
+        })
     }
 
     /// Appends a PUT operation to the WAL
     ///
-    /// This is the critical durability step: we write the operation to disk
-    /// BEFORE applying it to the in-memory memtable. The flush() call at the
-    /// end forces the OS to actually write the data to the physical disk
-    /// (not just cache it in memory).
+    /// This is the critical durability step: we write the operation to the
+    /// log BEFORE applying it to the in-memory memtable.
     ///
     /// Order of operations when you call lsm.put():
     /// 1. Call this function (write to WAL)
-    /// 2. flush() forces data to disk
-    /// 3. Now it's safe to update memtable
+    /// 2. Now it's safe to update memtable
     ///
     /// # Arguments
+    /// * `seq` - Sequence number assigned to this write by the tree
     /// * `key` - The key being inserted/updated
     /// * `value` - The new value for this key
     ///
     /// # Returns
-    /// * `Ok(())` - Successfully logged and flushed to disk
-    /// * `Err(io::Error)` - Disk write failed (out of space, I/O error, etc.)
-    pub fn append_put(&mut self, key: &[u8], value: &[u8]) -> std::io::Result<()> {
-        self.append_entry(WALOp::Put, key, value)
+    /// * `Ok(())` - Successfully handed to the operating system
+    /// * `Err(io::Error)` - Disk write failed (out of space, I/O error,
+    ///   etc.); part of the entry may be in the log (see `rollback`)
+    pub fn append_put(&mut self, seq: u64, key: &[u8], value: &[u8]) -> std::io::Result<()> {
+        self.append_entry(WALOp::Put, seq, key, value)
     }
 
     /// Appends a DELETE operation to the WAL
@@ -130,15 +301,15 @@ impl WAL {
     /// to keep the format consistent.
     ///
     /// # Arguments
+    /// * `seq` - Sequence number assigned to this write by the tree
     /// * `key` - The key being deleted
     ///
     /// # Returns
     /// * `Ok(())` - Successfully logged to disk
     /// * `Err(io::Error)` - Disk write failed
-    #[allow(dead_code)]
-    pub fn append_delete(&mut self, key: &[u8]) -> std::io::Result<()> {
+    pub fn append_delete(&mut self, seq: u64, key: &[u8]) -> std::io::Result<()> {
         // Value is empty for deletes, but we still write the length field
-        self.append_entry(WALOp::Delete, key, &[])
+        self.append_entry(WALOp::Delete, seq, key, &[])
     }
 
     /// Internal helper that writes any operation type to the log
@@ -146,7 +317,9 @@ impl WAL {
     /// Binary format (all numbers in little-endian):
     ///
     /// +------------------+
-    /// | op_type (1 byte) |  ← WALOp::Put = 1, WALOp::Delete = 2
+    /// | op_type (1 byte) |  ← WALOp::Put = 1, WALOp::Delete = 2, plus SEQUENCE_FLAG and CHECKSUM_FLAG
+    /// +------------------+
+    /// | seq (8 bytes)    |  ← Sequence number of the write (u64)
     /// +------------------+
     /// | key_len (4 bytes)|  ← Length of the key in bytes (u32)
     /// +------------------+
@@ -156,44 +329,116 @@ impl WAL {
     /// +------------------+
     /// | value bytes      |  ← Actual value data
     /// +------------------+
+    /// | checksum (4 b)   |  ← Checksum of everything above (u32)
+    /// +------------------+
     ///
     /// This format is easy to parse because:
     /// - Fixed-size fields tell us what comes next
     /// - Variable-length fields have their size stored before them
     /// - No delimiters needed (length-prefixed data)
     ///
+    /// The log's length is only advanced once the whole entry is written,
+    /// so after a failure `stats().file_size` is still the offset the entry
+    /// started at; a `mark` taken before it cuts off whatever did land.
+    ///
     /// # Arguments
     /// * `op` - Type of operation (Put or Delete)
+    /// * `seq` - Sequence number of the write
     /// * `key` - Key bytes
     /// * `value` - Value bytes
-    fn append_entry(&mut self, op: WALOp, key: &[u8], value: &[u8]) -> std::io::Result<()> {
-        // Step 1: Write operation type (1 byte)
-        // Convert enum to its u8 representation (Put = 1, Delete = 2)
-        self.writer.write_all(&[op as u8])?;
-
-        // Step 2: Write key length (4 bytes, little-endian)
+    fn append_entry(
+        &mut self,
+        op: WALOp,
+        seq: u64,
+        key: &[u8],
+        value: &[u8],
+    ) -> std::io::Result<()> {
+        let record = &mut self.record;
+        record.clear();
+
+        // Step 1: Operation type (1 byte)
+        // Convert enum to its u8 representation (Put = 1, Delete = 2) and
+        // mark that a sequence number follows and a checksum ends the entry
+        record.push(op as u8 | SEQUENCE_FLAG | CHECKSUM_FLAG);
+        record.extend_from_slice(&seq.to_le_bytes());
+
+        // Step 2: Key length (4 bytes, little-endian)
         // We cast to u32 because that's plenty for key lengths
         // Little-endian is the standard for most modern CPUs
-        self.writer.write_all(&(key.len() as u32).to_le_bytes())?;
+        record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+
+        // Step 3: The actual key bytes
+        record.extend_from_slice(key);
 
-        // Step 3: Write the actual key bytes
-        self.writer.write_all(key)?;
+        // Step 4: Value length (4 bytes, little-endian)
+        record.extend_from_slice(&(value.len() as u32).to_le_bytes());
 
-        // Step 4: Write value length (4 bytes, little-endian)
-        self.writer.write_all(&(value.len() as u32).to_le_bytes())?;
+        // Step 5: The actual value bytes
+        record.extend_from_slice(value);
 
-        // Step 5: Write the actual value bytes
-        self.writer.write_all(value)?;
+        // Step 6: Checksum of the entry so far, so replay can tell a
+        // damaged entry from a good one
+        let checksum = mix64(fnv1a(FNV_OFFSET_BASIS, record)) as u32;
+        record.extend_from_slice(&checksum.to_le_bytes());
 
-        // Step 6: CRITICAL - Force everything to disk
-        // flush() ensures the OS writes buffered data to the physical disk.
-        // Without this, the data might sit in OS cache and be lost on crash.
-        // This is why WAL writes are "durable" - they survive power loss.
-        self.writer.flush()?;
+        // Step 7: Hand the whole entry to the operating system in one go.
+        // It survives a crash of the process from here on; surviving a
+        // power failure takes `sync_to_disk`.
+        self.storage.append(&self.file, &self.record)?;
+
+        let entry_len = ENTRY_HEADER_LEN + key.len() as u64 + value.len() as u64;
+        self.stats.file_size += entry_len;
+        self.stats.entries_since_clear += 1;
+        self.stats.bytes_since_clear += entry_len;
+        self.stats.syncs += 1;
+        self.stats.last_sync = Some(self.clock.now());
 
         Ok(())
     }
 
+    /// Forces everything appended so far to disk
+    ///
+    /// Appends only hand the entries to the operating system, which
+    /// survives a crash of the process but not a power failure. This makes
+    /// them durable through `storage`.
+    pub fn sync_to_disk(&mut self, storage: &dyn Storage) -> std::io::Result<()> {
+        storage.sync_file(&self.file)?;
+        self.stats.fsyncs += 1;
+        Ok(())
+    }
+
+    /// Bytes appended since `mark` was taken
+    pub fn bytes_since(&self, mark: WALMark) -> u64 {
+        self.stats.file_size.saturating_sub(mark.len)
+    }
+
+    /// Returns the log's activity counters
+    pub fn stats(&self) -> WALStats {
+        self.stats.clone()
+    }
+
+    /// The current end of the log, to `rollback` to if the appends that
+    /// follow fail
+    pub fn mark(&self) -> WALMark {
+        WALMark {
+            len: self.stats.file_size,
+            entries: self.stats.entries_since_clear,
+            bytes: self.stats.bytes_since_clear,
+        }
+    }
+
+    /// Cuts off everything appended since `mark` was taken, including a
+    /// partly written entry
+    ///
+    /// The mark must be from after the last `clear`. Entries up to the
+    /// mark are untouched, and the next append follows them directly.
+    pub fn rollback(&mut self, mark: WALMark) -> std::io::Result<()> {
+        self.truncate_to(mark.len)?;
+        self.stats.entries_since_clear = mark.entries;
+        self.stats.bytes_since_clear = mark.bytes;
+        Ok(())
+    }
+
     /// Recovers all entries from the WAL
     ///
     /// This is called when the LSM tree starts up. We read the entire WAL
@@ -210,9 +455,12 @@ impl WAL {
     ///    - Add to results vector
     /// 3. Return all entries in chronological order
     ///
+    /// A damaged or torn entry ends recovery early instead of failing it;
+    /// see `replay` for the details and for how much was left unread.
+    ///
     /// # Returns
-    /// * `Ok(Vec<WALEntry>)` - All operations from the log, in order
-    /// * `Err(io::Error)` - File read error or corrupted data
+    /// * `Ok(Vec<WALEntry>)` - All intact operations from the log, in order
+    /// * `Err(io::Error)` - File read error
     ///
     /// # Example
     /// ```ignore
@@ -225,65 +473,176 @@ impl WAL {
     /// }
     /// ```
     pub fn recover(&self) -> std::io::Result<Vec<WALEntry>> {
-        // Open file for reading (different from our writer instance)
-        let file = File::open(&self.path)?;
-        let mut reader = BufReader::new(file);
+        Self::recover_from(&self.path)
+    }
+
+    /// Reads all entries from a WAL file without opening it for writing
+    ///
+    /// Used when a tree is opened read-only. A missing file has no entries.
+    /// Like `replay` with default limits, it stops at the first damaged
+    /// entry.
+    pub fn recover_from(path: &Path) -> std::io::Result<Vec<WALEntry>> {
+        Ok(Self::replay(path, SizeLimits::default())?.entries)
+    }
+
+    /// Reads the well-formed entries at the start of a WAL file
+    ///
+    /// A crash in the middle of an append leaves a partial entry at the
+    /// end of the log, and a damaged length field can claim gigabytes.
+    /// Replay stops at the first entry that runs past the end of the file,
+    /// has an unknown operation type, has a key or value longer than
+    /// `limits`, or doesn't match its checksum; it never allocates for a length it hasn't checked. The
+    /// entries before that point are returned, and `valid_len` tells where
+    /// a writable log should be cut (see `truncate_to`) so that new appends
+    /// don't land behind the damage. A missing file has no entries.
+    pub fn replay(path: &Path, limits: SizeLimits) -> std::io::Result<WALRecovery> {
+        Self::replay_buffered(path, limits, DEFAULT_READ_BUFFER_BYTES)
+    }
+
+    /// Like `replay`, reading the log through a buffer of `buffer_bytes`
+    pub fn replay_buffered(
+        path: &Path,
+        limits: SizeLimits,
+        buffer_bytes: usize,
+    ) -> std::io::Result<WALRecovery> {
+        // Open file for reading (different from any writer instance)
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(WALRecovery::default());
+            }
+            Err(e) => return Err(e),
+        };
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::with_capacity(buffer_bytes, file);
         let mut entries = Vec::new();
+        let mut valid_len = 0;
 
-        // Read entries until we hit end of file
-        loop {
-            // Try to read operation type (1 byte)
-            let mut op_buf = [0u8; 1];
-            match reader.read_exact(&mut op_buf) {
-                Ok(_) => {
-                    // Successfully read a byte, continue parsing
-                }
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                    // Hit end of file - this is normal, we're done
-                    break;
+        // Read entries until we hit end of file or a damaged entry
+        let corruption = loop {
+            if valid_len == file_len {
+                break None;
+            }
+            match Self::read_entry(&mut reader, file_len - valid_len, limits) {
+                Ok((entry, len)) => {
+                    entries.push(entry);
+                    valid_len += len;
                 }
-                Err(e) => {
-                    // Some other error - propagate it
-                    return Err(e);
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                    break Some(format!("entry at offset {}: {}", valid_len, e));
                 }
+                Err(e) => return Err(e),
             }
+        };
+
+        Ok(WALRecovery {
+            entries,
+            valid_len,
+            file_len,
+            corruption,
+        })
+    }
 
-            // Parse operation type from byte value
-            let op = match op_buf[0] {
-                1 => WALOp::Put,
-                2 => WALOp::Delete,
-                invalid => {
-                    // If we see an unexpected byte value, the file is corrupted
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        format!("Invalid WAL operation type: {}", invalid),
-                    ));
-                }
-            };
-
-            // Read key length (4 bytes)
-            let mut key_len_buf = [0u8; 4];
-            reader.read_exact(&mut key_len_buf)?;
-            let key_len = u32::from_le_bytes(key_len_buf) as usize;
-
-            // Read key bytes (variable length)
-            let mut key = vec![0u8; key_len];
-            reader.read_exact(&mut key)?;
-
-            // Read value length (4 bytes)
-            let mut value_len_buf = [0u8; 4];
-            reader.read_exact(&mut value_len_buf)?;
-            let value_len = u32::from_le_bytes(value_len_buf) as usize;
-
-            // Read value bytes (variable length)
-            let mut value = vec![0u8; value_len];
-            reader.read_exact(&mut value)?;
-
-            // Add this entry to our results
-            entries.push(WALEntry { op, key, value });
+    /// Reads one entry with at most `left` bytes remaining in the file and
+    /// returns it with its encoded length
+    ///
+    /// Damage of any kind is reported as `InvalidData`.
+    fn read_entry<R: Read>(
+        reader: &mut R,
+        mut left: u64,
+        limits: SizeLimits,
+    ) -> std::io::Result<(WALEntry, u64)> {
+        let corrupt = |what: String| std::io::Error::new(std::io::ErrorKind::InvalidData, what);
+        let total = left;
+        // Claims a field's bytes before they are read or allocated
+        let mut take = |len: u64| {
+            if len > left {
+                return Err(corrupt("runs past the end of the log".to_string()));
+            }
+            left -= len;
+            Ok(())
+        };
+
+        // Operation type (1 byte)
+        take(1)?;
+        let mut op_buf = [0u8; 1];
+        reader.read_exact(&mut op_buf)?;
+        // Hash of the entry's bytes so far, for its checksum
+        let mut hash = fnv1a(FNV_OFFSET_BASIS, &op_buf);
+        let op = match op_buf[0] & !(SEQUENCE_FLAG | CHECKSUM_FLAG) {
+            1 => WALOp::Put,
+            2 => WALOp::Delete,
+            other => return Err(corrupt(format!("invalid operation type {}", other))),
+        };
+
+        // Sequence number (8 bytes), if this entry has one
+        let seq = if op_buf[0] & SEQUENCE_FLAG != 0 {
+            take(8)?;
+            let mut seq_buf = [0u8; 8];
+            reader.read_exact(&mut seq_buf)?;
+            hash = fnv1a(hash, &seq_buf);
+            u64::from_le_bytes(seq_buf)
+        } else {
+            0
+        };
+
+        // Key length (4 bytes), then the key
+        take(4)?;
+        let mut len_buf = [0u8; 4];
+        reader.read_exact(&mut len_buf)?;
+        hash = fnv1a(hash, &len_buf);
+        let key_len = u32::from_le_bytes(len_buf) as usize;
+        if key_len > limits.max_key_size {
+            return Err(corrupt(format!("key length {} exceeds the limit", key_len)));
         }
+        take(key_len as u64)?;
+        let mut key = vec![0u8; key_len];
+        reader.read_exact(&mut key)?;
+        hash = fnv1a(hash, &key);
+
+        // Value length (4 bytes), then the value
+        take(4)?;
+        reader.read_exact(&mut len_buf)?;
+        hash = fnv1a(hash, &len_buf);
+        let value_len = u32::from_le_bytes(len_buf) as usize;
+        if value_len > limits.max_value_size {
+            return Err(corrupt(format!(
+                "value length {} exceeds the limit",
+                value_len
+            )));
+        }
+        take(value_len as u64)?;
+        let mut value = vec![0u8; value_len];
+        reader.read_exact(&mut value)?;
+        hash = fnv1a(hash, &value);
+
+        // Checksum (4 bytes), if this entry has one
+        if op_buf[0] & CHECKSUM_FLAG != 0 {
+            take(4)?;
+            reader.read_exact(&mut len_buf)?;
+            if u32::from_le_bytes(len_buf) != mix64(hash) as u32 {
+                return Err(corrupt("fails its checksum".to_string()));
+            }
+        }
+
+        let entry = WALEntry {
+            op,
+            seq,
+            key,
+            value,
+        };
+        Ok((entry, total - left))
+    }
 
-        Ok(entries)
+    /// Cuts the log off after its first `len` bytes
+    ///
+    /// Used on open to drop a damaged tail found by `replay`, so entries
+    /// appended afterwards follow the last good one.
+    pub fn truncate_to(&mut self, len: u64) -> std::io::Result<()> {
+        self.file.set_len(len)?;
+        self.file.seek(SeekFrom::Start(len))?;
+        self.stats.file_size = len;
+        Ok(())
     }
 
     /// Clears the WAL after successful memtable flush
@@ -298,7 +657,6 @@ impl WAL {
     /// 3. New writes will create new WAL entries
     ///
     /// # How it works
-    /// - Flush any buffered data first
     /// - Truncate file to 0 bytes (delete all content)
     /// - Seek back to beginning for next write
     ///
@@ -306,9 +664,6 @@ impl WAL {
     /// * `Ok(())` - WAL successfully cleared
     /// * `Err(io::Error)` - File operation failed
     pub fn clear(&mut self) -> std::io::Result<()> {
-        // Make sure any buffered writes are on disk first
-        self.writer.flush()?;
-
         // On Windows, we can't truncate a file while it's open with a write handle.
         // The safest cross-platform approach is to close and recreate the file.
         // We do this by creating a new file with truncate mode, which replaces
@@ -319,8 +674,12 @@ impl WAL {
             .truncate(true)
             .open(&self.path)?;
 
-        // Replace the old writer with a new one
-        self.writer = BufWriter::new(file);
+        // Replace the old handle with the new one
+        self.file = file;
+
+        self.stats.file_size = 0;
+        self.stats.entries_since_clear = 0;
+        self.stats.bytes_since_clear = 0;
 
         Ok(())
     }
@@ -340,6 +699,7 @@ impl WAL {
 mod tests {
     use super::*;
     use std::fs;
+    use std::io::Write;
 
     /// Test basic write and recovery flow
     ///
@@ -357,13 +717,13 @@ mod tests {
             let mut wal = WAL::new(path.clone()).unwrap();
 
             // Write a PUT operation
-            wal.append_put(b"key1", b"value1").unwrap();
+            wal.append_put(1, b"key1", b"value1").unwrap();
 
             // Write another PUT operation
-            wal.append_put(b"key2", b"value2").unwrap();
+            wal.append_put(2, b"key2", b"value2").unwrap();
 
             // Write a DELETE operation
-            wal.append_delete(b"key1").unwrap();
+            wal.append_delete(3, b"key1").unwrap();
 
             // WAL is dropped here, file is closed
         }
@@ -388,6 +748,7 @@ mod tests {
         // Verify third entry (DELETE key1)
         assert_eq!(entries[2].op, WALOp::Delete);
         assert_eq!(entries[2].key, b"key1");
+        assert_eq!(entries[2].seq, 3);
         // Delete operations have empty values
         assert_eq!(entries[2].value, b"");
 
@@ -407,8 +768,8 @@ mod tests {
         let mut wal = WAL::new(path.clone()).unwrap();
 
         // Write some data
-        wal.append_put(b"key1", b"value1").unwrap();
-        wal.append_put(b"key2", b"value2").unwrap();
+        wal.append_put(1, b"key1", b"value1").unwrap();
+        wal.append_put(2, b"key2", b"value2").unwrap();
 
         // Clear the WAL
         wal.clear().unwrap();
@@ -455,7 +816,8 @@ mod tests {
             for i in 0..10 {
                 let key = format!("key{}", i);
                 let value = format!("value{}", i);
-                wal.append_put(key.as_bytes(), value.as_bytes()).unwrap();
+                wal.append_put(i as u64 + 1, key.as_bytes(), value.as_bytes())
+                    .unwrap();
             }
         }
 
@@ -471,6 +833,7 @@ mod tests {
             let expected_value = format!("value{}", i);
             assert_eq!(entry.key, expected_key.as_bytes());
             assert_eq!(entry.value, expected_value.as_bytes());
+            assert_eq!(entry.seq, i as u64 + 1);
         }
 
         fs::remove_file(path).ok();
@@ -487,9 +850,9 @@ mod tests {
         let mut wal = WAL::new(path.clone()).unwrap();
 
         // Write, clear, write again
-        wal.append_put(b"old_key", b"old_value").unwrap();
+        wal.append_put(1, b"old_key", b"old_value").unwrap();
         wal.clear().unwrap();
-        wal.append_put(b"new_key", b"new_value").unwrap();
+        wal.append_put(2, b"new_key", b"new_value").unwrap();
 
         // Should only recover the new entry
         let entries = wal.recover().unwrap();
@@ -499,4 +862,209 @@ mod tests {
 
         fs::remove_file(path).ok();
     }
+
+    /// Test replaying a log written before sequence numbers existed
+    ///
+    /// Old entries have no sequence field; they must still parse and come
+    /// back with sequence 0.
+    #[test]
+    fn test_wal_recovers_legacy_entries() {
+        let path = PathBuf::from("./test_wal_legacy.log");
+
+        // Old format: [op][key_len][key][value_len][value]
+        let mut legacy = vec![WALOp::Put as u8];
+        legacy.extend_from_slice(&3u32.to_le_bytes());
+        legacy.extend_from_slice(b"old");
+        legacy.extend_from_slice(&1u32.to_le_bytes());
+        legacy.extend_from_slice(b"v");
+        fs::write(&path, legacy).unwrap();
+
+        let mut wal = WAL::new(path.clone()).unwrap();
+        wal.append_put(9, b"new", b"v").unwrap();
+
+        let entries = wal.recover().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key, b"old");
+        assert_eq!(entries[0].seq, 0);
+        assert_eq!(entries[1].key, b"new");
+        assert_eq!(entries[1].seq, 9);
+
+        fs::remove_file(path).ok();
+    }
+
+    /// Damaged length fields must end replay at the last good entry
+    ///
+    /// A length over the limit, or longer than the rest of the file, is
+    /// corruption; neither may be trusted with an allocation.
+    #[test]
+    fn test_wal_replay_stops_at_absurd_lengths() {
+        let path = PathBuf::from("./test_wal_absurd.log");
+        fs::remove_file(&path).ok();
+        let limits = SizeLimits {
+            max_key_size: 64,
+            max_value_size: 64,
+        };
+
+        let mut wal = WAL::new(path.clone()).unwrap();
+        wal.append_put(1, b"good", b"value").unwrap();
+        drop(wal);
+        let good_len = fs::metadata(&path).unwrap().len();
+
+        // Over the configured limit, and a key length of nearly 4 GiB
+        for (key_len, value_len) in [(65u32, 1u32), (0xFFFF_FFF0, 1), (3, 0xFFFF_FFF0)] {
+            let mut bytes = fs::read(&path).unwrap();
+            bytes.truncate(good_len as usize);
+            bytes.push(WALOp::Put as u8 | SEQUENCE_FLAG);
+            bytes.extend_from_slice(&2u64.to_le_bytes());
+            bytes.extend_from_slice(&key_len.to_le_bytes());
+            bytes.extend_from_slice(b"bad");
+            bytes.extend_from_slice(&value_len.to_le_bytes());
+            bytes.extend_from_slice(b"v");
+            fs::write(&path, &bytes).unwrap();
+
+            let recovery = WAL::replay(&path, limits).unwrap();
+            assert_eq!(recovery.entries.len(), 1);
+            assert_eq!(recovery.entries[0].key, b"good");
+            assert_eq!(recovery.valid_len, good_len);
+            assert_eq!(recovery.file_len, bytes.len() as u64);
+            let corruption = recovery.corruption.unwrap();
+            assert!(corruption.contains(&format!("offset {}", good_len)));
+        }
+
+        // A torn tail and an unknown operation stop replay the same way
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.truncate(good_len as usize + 5);
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(WAL::replay(&path, limits).unwrap().valid_len, good_len);
+        bytes[good_len as usize] = 7;
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(WAL::recover_from(&path).unwrap().len(), 1);
+
+        // Cutting the tail lets later appends be replayed
+        let mut wal = WAL::new(path.clone()).unwrap();
+        wal.truncate_to(good_len).unwrap();
+        wal.append_put(3, b"after", b"v").unwrap();
+        let recovery = WAL::replay(&path, limits).unwrap();
+        assert_eq!(recovery.entries.len(), 2);
+        assert_eq!(recovery.corruption, None);
+        assert_eq!(recovery.dropped_bytes(), 0);
+
+        fs::remove_file(path).ok();
+    }
+
+    /// A damaged byte anywhere in an entry fails its checksum, while
+    /// entries from before checksums existed still replay
+    #[test]
+    fn test_wal_replay_stops_at_checksum_mismatch() {
+        let path = PathBuf::from("./test_wal_checksum.log");
+        fs::remove_file(&path).ok();
+
+        // Previous format: a sequence number but no checksum
+        let mut bytes = vec![WALOp::Put as u8 | SEQUENCE_FLAG];
+        bytes.extend_from_slice(&1u64.to_le_bytes());
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(b"old");
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(b"v");
+        fs::write(&path, &bytes).unwrap();
+        let old_len = bytes.len() as u64;
+
+        let mut wal = WAL::new(path.clone()).unwrap();
+        wal.append_put(2, b"new", b"value").unwrap();
+        wal.append_put(3, b"last", b"value").unwrap();
+        drop(wal);
+        let recovery = WAL::replay(&path, SizeLimits::default()).unwrap();
+        assert_eq!(recovery.entries.len(), 3);
+        assert_eq!(recovery.corruption, None);
+
+        // Flip the last byte of the second entry's value
+        let mut bytes = fs::read(&path).unwrap();
+        let value_end = old_len + 21 + 3 + 5 - 4;
+        bytes[value_end as usize - 1] ^= 0x01;
+        fs::write(&path, &bytes).unwrap();
+        let recovery = WAL::replay(&path, SizeLimits::default()).unwrap();
+        assert_eq!(recovery.entries.len(), 1);
+        assert_eq!(recovery.entries[0].key, b"old");
+        assert_eq!(recovery.valid_len, old_len);
+        let corruption = recovery.corruption.unwrap();
+        assert!(
+            corruption.contains(&format!("offset {}", old_len)) && corruption.contains("checksum"),
+            "{}",
+            corruption
+        );
+
+        fs::remove_file(path).ok();
+    }
+
+    /// Test that the byte counters follow the entry framing
+    #[test]
+    fn test_wal_stats_track_appends_and_clear() {
+        let path = PathBuf::from("./test_wal_stats.log");
+        fs::remove_file(&path).ok();
+
+        let mut wal = WAL::new(path.clone()).unwrap();
+        assert_eq!(wal.stats(), WALStats::default());
+
+        // Each entry is 21 bytes of framing plus the key and value
+        wal.append_put(1, b"key", b"value").unwrap();
+        wal.append_put(2, b"k", b"").unwrap();
+        wal.append_delete(3, b"gone").unwrap();
+
+        let stats = wal.stats();
+        let expected = (21 + 3 + 5) + (21 + 1) + (21 + 4);
+        assert_eq!(stats.entries_since_clear, 3);
+        assert_eq!(stats.bytes_since_clear, expected);
+        assert_eq!(stats.file_size, expected);
+        assert_eq!(fs::metadata(&path).unwrap().len(), expected);
+        assert_eq!(stats.syncs, 3);
+        assert!(stats.last_sync.is_some());
+
+        wal.clear().unwrap();
+        let stats = wal.stats();
+        assert_eq!(stats.entries_since_clear, 0);
+        assert_eq!(stats.bytes_since_clear, 0);
+        assert_eq!(stats.file_size, 0);
+        assert_eq!(stats.syncs, 3);
+        drop(wal);
+
+        // Reopening picks up the size of what is already in the file
+        let mut wal = WAL::new(path.clone()).unwrap();
+        wal.append_put(4, b"a", b"b").unwrap();
+        drop(wal);
+        let wal = WAL::new(path.clone()).unwrap();
+        assert_eq!(wal.stats().file_size, 21 + 2);
+        assert_eq!(wal.stats().entries_since_clear, 0);
+
+        fs::remove_file(path).ok();
+    }
+
+    /// Test that a rollback cuts off a partly written entry
+    #[test]
+    fn test_wal_rollback_cuts_partial_entry() {
+        let path = PathBuf::from("./test_wal_rollback.log");
+        fs::remove_file(&path).ok();
+
+        let mut wal = WAL::new(path.clone()).unwrap();
+        wal.append_put(1, b"key1", b"value1").unwrap();
+        let mark = wal.mark();
+        wal.append_put(2, b"key2", b"value2").unwrap();
+        // Half an entry, as a failed write would leave it
+        let mut file = &wal.file;
+        file.write_all(&[WALOp::Put as u8 | SEQUENCE_FLAG, 3, 0])
+            .unwrap();
+
+        wal.rollback(mark).unwrap();
+        assert_eq!(wal.stats().entries_since_clear, 1);
+        assert_eq!(wal.stats().file_size, 21 + 4 + 6);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 21 + 4 + 6);
+
+        // The next entry follows the last good one directly
+        wal.append_put(4, b"key4", b"value4").unwrap();
+        let recovery = WAL::replay(&path, SizeLimits::default()).unwrap();
+        assert_eq!(recovery.corruption, None);
+        let seqs: Vec<u64> = recovery.entries.iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, vec![1, 4]);
+
+        fs::remove_file(path).ok();
+    }
 }
@@ -0,0 +1,78 @@
+//! Allocation counts of SSTable point lookups
+//!
+//! A lookup that has to scan past every record of a table should not
+//! allocate per record: keys are read into one reused buffer and the values
+//! of other keys are skipped. A counting global allocator checks that the
+//! number of allocations doesn't grow with the size of the table.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs;
+use std::path::PathBuf;
+
+use lsm_tree::LSMTree;
+
+struct CountingAllocator;
+
+thread_local! {
+    // Per thread, so tests running in parallel don't see each other's allocations
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    std::hint::black_box(f());
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+/// Allocations made by a lookup of the last key in a one-table tree
+fn last_key_lookup_allocations(dir: &str, entries: usize) -> usize {
+    let dir = PathBuf::from(dir);
+    fs::remove_dir_all(&dir).ok();
+
+    let mut lsm = LSMTree::new(dir.clone(), usize::MAX).unwrap();
+    for i in 0..entries {
+        lsm.put(
+            format!("key_{:06}", i).into_bytes(),
+            format!("value_{:06}", i).into_bytes(),
+        )
+        .unwrap();
+    }
+    lsm.flush().unwrap();
+
+    let last_key = format!("key_{:06}", entries - 1).into_bytes();
+    // Warm up once so lazily initialized state doesn't count
+    assert!(lsm.get(&last_key).is_some());
+    let count = allocations(|| lsm.get(&last_key));
+
+    drop(lsm);
+    fs::remove_dir_all(&dir).ok();
+    count
+}
+
+#[test]
+fn test_lookup_allocations_do_not_grow_with_table_size() {
+    let small = last_key_lookup_allocations("./test_alloc_small", 10);
+    let large = last_key_lookup_allocations("./test_alloc_large", 10_000);
+
+    assert_eq!(
+        small, large,
+        "scanning 10k records allocated {} times vs {} for 10",
+        large, small
+    );
+    assert!(large < 16, "lookup allocated {} times", large);
+}
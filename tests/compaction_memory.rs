@@ -0,0 +1,124 @@
+//! Peak memory of compaction
+//!
+//! A compaction streams a k-way merge of its input tables straight into
+//! the new table, so what it holds at once is one entry per input plus
+//! the reader and writer buffers, however many entries the inputs have. A
+//! global allocator tracking the bytes allocated by the test's thread
+//! checks that the peak doesn't grow with the input.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use lsm_tree::{Clock, FilterPolicy, LSMConfig, LSMTree};
+
+struct PeakAllocator;
+
+thread_local! {
+    // Per thread, so tests running in parallel don't see each other's allocations
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+    static PEAK: Cell<isize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // try_with: thread-local storage may already be gone while a
+        // thread shuts down
+        let _ = LIVE.try_with(|live| {
+            live.set(live.get() + layout.size() as isize);
+            let _ = PEAK.try_with(|peak| peak.set(peak.get().max(live.get())));
+        });
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = LIVE.try_with(|live| live.set(live.get() - layout.size() as isize));
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAllocator = PeakAllocator;
+
+/// Bytes allocated at the peak of `f`, beyond what was live before it
+fn peak_bytes<T>(f: impl FnOnce() -> T) -> usize {
+    let before = LIVE.with(Cell::get);
+    PEAK.with(|peak| peak.set(before));
+    std::hint::black_box(f());
+    (PEAK.with(Cell::get) - before) as usize
+}
+
+/// A clock that never moves, so each table records one write-time range
+/// however long writing it took
+#[derive(Debug)]
+struct FixedClock;
+
+impl Clock for FixedClock {
+    fn now_millis(&self) -> u64 {
+        1_000
+    }
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:07}", i).into_bytes()
+}
+
+/// Peak memory of compacting four tables of `entries` keys each (every
+/// key written four times, every tenth deleted at the end)
+fn compaction_peak(dir: &str, entries: usize) -> usize {
+    let dir = PathBuf::from(dir);
+    fs::remove_dir_all(&dir).ok();
+
+    // No filters: the new table's filter is sized by its keys, which is
+    // output, not merge state. Write times are kept per millisecond, so a
+    // moving clock would give the slower large input more of them
+    let mut lsm = LSMTree::open(LSMConfig {
+        memtable_size_threshold: usize::MAX,
+        filter_policy: FilterPolicy::None,
+        sync_on_flush: false,
+        clock: Arc::new(FixedClock),
+        ..LSMConfig::new(dir.clone())
+    })
+    .unwrap();
+    for round in 0..4 {
+        for i in 0..entries {
+            if round == 3 && i % 10 == 0 {
+                lsm.delete(key(i)).unwrap();
+            } else {
+                lsm.put(key(i), format!("value_{}_{:07}", round, i).into_bytes())
+                    .unwrap();
+            }
+        }
+        lsm.flush().unwrap();
+    }
+
+    let mut result = None;
+    let peak = peak_bytes(|| result = Some(lsm.purge().unwrap()));
+    let result = result.unwrap();
+    assert_eq!(result.input_tables, 4);
+    assert_eq!(result.entries_written, entries - entries / 10);
+    for i in [0, 1, entries / 2 + 1, entries - 1] {
+        let expected = (i % 10 != 0).then(|| format!("value_3_{:07}", i).into_bytes());
+        assert_eq!(lsm.get(&key(i)), expected, "key {}", i);
+    }
+
+    drop(lsm);
+    fs::remove_dir_all(&dir).ok();
+    peak
+}
+
+#[test]
+fn test_compaction_peak_memory_does_not_grow_with_input() {
+    let small = compaction_peak("./test_compaction_memory_small", 1_000);
+    let large = compaction_peak("./test_compaction_memory_large", 50_000);
+
+    // The large input holds 200k records, about 7 MB
+    assert!(
+        large < small + 64 * 1024,
+        "compacting 200k records peaked at {} bytes vs {} for 4k",
+        large,
+        small
+    );
+}